@@ -0,0 +1,21 @@
+// Runs one NostrCraft miner off the page's main thread, started by src/web_mining.rs.
+// It loads the same wasm build as the page and hands it every message the page posts.
+importScripts("../nostr_craft.js");
+
+const pending = [];
+let ready = false;
+
+self.onmessage = (event) => {
+  if (ready) {
+    wasm_bindgen.mining_worker_message(event.data);
+  } else {
+    pending.push(event.data);
+  }
+};
+
+wasm_bindgen("../nostr_craft_bg.wasm").then(() => {
+  ready = true;
+  for (const message of pending.splice(0)) {
+    wasm_bindgen.mining_worker_message(message);
+  }
+});
@@ -0,0 +1,177 @@
+use bevy::prelude::*;
+
+use crate::{
+    cyberspace::CyberspaceCoordinates, menu::AppState, origin::CyberspacePosition,
+    settings::Settings, waypoints::Waypoints,
+};
+
+const BEAM_WIDTH: f32 = 0.6;
+// Taller than the home base beacons, home should be the first thing to find
+const BEAM_HEIGHT: f32 = 256.0;
+const HOME_GLOW: Color = Color::rgb_linear(3.0, 3.0, 3.0);
+const WAYPOINT_GLOW: Color = Color::rgb_linear(0.4, 1.6, 2.0);
+// Lies under the block, wide enough to see from above around what is built there
+const RING_RADIUS: f32 = 4.0;
+const RING_THICKNESS: f32 = 0.08;
+// The ring only hints at the spot, the beam does the pointing
+const RING_DIMMING: f32 = 0.25;
+
+pub fn beacons_plugin(app: &mut App) {
+    app.add_systems(Startup, setup_beacon_assets).add_systems(
+        Update,
+        update_waypoint_beacons.run_if(in_state(AppState::InGame)),
+    );
+}
+
+#[derive(Resource)]
+struct BeaconAssets {
+    beam: Handle<Mesh>,
+    ring: Handle<Mesh>,
+    home_beam: Handle<StandardMaterial>,
+    home_ring: Handle<StandardMaterial>,
+    waypoint_beam: Handle<StandardMaterial>,
+    waypoint_ring: Handle<StandardMaterial>,
+}
+
+// A beam and ring over our home or one of the other waypoints
+#[derive(Component)]
+struct WaypointBeacon;
+
+fn glowing(glow: Color) -> StandardMaterial {
+    StandardMaterial {
+        emissive: glow,
+        alpha_mode: AlphaMode::Add,
+        ..Default::default()
+    }
+}
+
+fn setup_beacon_assets(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+    // Standing on the block, and the ring flat around its bottom
+    let beam = Mesh::from(Cuboid::new(BEAM_WIDTH, BEAM_HEIGHT, BEAM_WIDTH))
+        .translated_by(Vec3::Y * BEAM_HEIGHT / 2.0);
+    let ring = Mesh::from(Torus::new(
+        RING_RADIUS - RING_THICKNESS,
+        RING_RADIUS + RING_THICKNESS,
+    ))
+    .translated_by(Vec3::NEG_Y * 0.5);
+    commands.insert_resource(BeaconAssets {
+        beam: meshes.add(beam),
+        ring: meshes.add(ring),
+        home_beam: materials.add(glowing(HOME_GLOW)),
+        home_ring: materials.add(glowing(HOME_GLOW * RING_DIMMING)),
+        waypoint_beam: materials.add(glowing(WAYPOINT_GLOW)),
+        waypoint_ring: materials.add(glowing(WAYPOINT_GLOW * RING_DIMMING)),
+    });
+}
+
+// Where beacons stand, true for home. Waypoints sharing home's spot only get its beacon
+fn beacon_positions(
+    waypoints: &Waypoints,
+    settings: &Settings,
+) -> Vec<(CyberspaceCoordinates, bool)> {
+    let home = waypoints.home();
+    let mut positions: Vec<(CyberspaceCoordinates, bool)> = home
+        .filter(|_| settings.home_beacon)
+        .map(|home| (home, true))
+        .into_iter()
+        .collect();
+    if settings.waypoint_beacons {
+        for position in waypoints
+            .iter()
+            .skip(1)
+            .filter_map(|waypoint| waypoint.position())
+        {
+            if Some(position) != home && !positions.iter().any(|(other, _)| *other == position) {
+                positions.push((position, false));
+            }
+        }
+    }
+    positions
+}
+
+// Respawns every beacon when the waypoints or the beacon settings change
+fn update_waypoint_beacons(
+    mut commands: Commands,
+    waypoints: Res<Waypoints>,
+    settings: Res<Settings>,
+    assets: Res<BeaconAssets>,
+    beacon_query: Query<Entity, With<WaypointBeacon>>,
+    mut shown: Local<Vec<(CyberspaceCoordinates, bool)>>,
+) {
+    if !waypoints.is_changed() && !settings.is_changed() {
+        return;
+    }
+    let positions = beacon_positions(&waypoints, &settings);
+    if positions == *shown {
+        return;
+    }
+    for beacon in beacon_query.iter() {
+        commands.entity(beacon).despawn_recursive();
+    }
+    for (coordinates, home) in positions.iter().copied() {
+        let (beam, ring) = if home {
+            (&assets.home_beam, &assets.home_ring)
+        } else {
+            (&assets.waypoint_beam, &assets.waypoint_ring)
+        };
+        commands
+            .spawn((
+                SpatialBundle::default(),
+                CyberspacePosition(coordinates),
+                WaypointBeacon,
+            ))
+            .with_children(|beacon| {
+                beacon.spawn(PbrBundle {
+                    mesh: assets.beam.clone_weak(),
+                    material: beam.clone_weak(),
+                    ..Default::default()
+                });
+                beacon.spawn(PbrBundle {
+                    mesh: assets.ring.clone_weak(),
+                    material: ring.clone_weak(),
+                    ..Default::default()
+                });
+            });
+    }
+    *shown = positions;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{cyberspace::Plane, waypoints::Waypoint};
+
+    fn waypoint(x: i128) -> Waypoint {
+        Waypoint {
+            name: format!("Waypoint {}", x),
+            coordinates: CyberspaceCoordinates::new(x, 0, 0).to_hex(Plane::ISpace),
+        }
+    }
+
+    #[test]
+    fn beacons_follow_the_settings() {
+        let waypoints = Waypoints(vec![waypoint(1), waypoint(2), waypoint(1), waypoint(2)]);
+        let mut settings = Settings::default();
+        assert_eq!(
+            beacon_positions(&waypoints, &settings),
+            vec![(CyberspaceCoordinates::new(1, 0, 0), true)]
+        );
+        settings.waypoint_beacons = true;
+        assert_eq!(
+            beacon_positions(&waypoints, &settings),
+            vec![
+                (CyberspaceCoordinates::new(1, 0, 0), true),
+                (CyberspaceCoordinates::new(2, 0, 0), false)
+            ]
+        );
+        settings.home_beacon = false;
+        assert_eq!(
+            beacon_positions(&waypoints, &settings),
+            vec![(CyberspaceCoordinates::new(2, 0, 0), false)]
+        );
+    }
+}
@@ -0,0 +1,225 @@
+use bevy::{
+    prelude::*,
+    render::{
+        mesh::VertexAttributeValues,
+        render_asset::RenderAssetUsages,
+        render_resource::{Extent3d, TextureDimension, TextureFormat},
+        texture::ImageSampler,
+    },
+};
+use image::imageops::FilterType;
+
+use crate::resources::{tier_material, MeshesAndMaterials, TOP_MATERIAL_TIER};
+
+// Every tier texture is scaled to this before being stacked, the sources range up to 4000 pixels
+const TILE_SIZE: u32 = 256;
+// Tiers 0 and 1 share the clay tile
+const TILES: usize = TOP_MATERIAL_TIER;
+// The brightest tiers glow 10 times brighter than white, emissive tiles hold their color divided
+// by this so it fits in a byte
+const EMISSIVE_SCALE: f32 = 10.0;
+
+pub fn block_atlas_plugin(app: &mut App) {
+    app.add_systems(
+        Update,
+        build_block_atlas.run_if(not(resource_exists::<BlockAtlas>)),
+    );
+}
+
+// The block textures stacked top to bottom in tier order, so a sector batch needs one material
+// for the solid tiers and one for the see-through ones instead of one per tier. Each block picks
+// its tile through its UVs, emissive, metallic and roughness come from one pixel per tile
+#[derive(Resource)]
+pub struct BlockAtlas {
+    solid_material: Handle<StandardMaterial>,
+    see_through_material: Handle<StandardMaterial>,
+    // Whether the tier material behind each tile is blended
+    see_through: Vec<bool>,
+}
+
+impl BlockAtlas {
+    pub fn material(&self, tile: usize) -> Handle<StandardMaterial> {
+        if self.see_through.get(tile).copied().unwrap_or(false) {
+            self.see_through_material.clone_weak()
+        } else {
+            self.solid_material.clone_weak()
+        }
+    }
+}
+
+pub fn tier_tile(tier: usize) -> usize {
+    tier.clamp(1, TOP_MATERIAL_TIER) - 1
+}
+
+// Squeezes a block's UVs into its tile, half a texel in so filtering doesn't pick up the next one
+pub fn atlas_uv([u, v]: [f32; 2], tile: usize) -> [f32; 2] {
+    let inset = 0.5 / TILE_SIZE as f32;
+    let v = inset + v.clamp(0.0, 1.0) * (1.0 - 2.0 * inset);
+    [u, (tile.min(TILES - 1) as f32 + v) / TILES as f32]
+}
+
+// Points each block copy of a batch mesh at its tile, `tiles` holds one entry per copy
+pub fn place_in_tiles(mesh: &mut Mesh, tiles: &[usize]) {
+    let Some(VertexAttributeValues::Float32x2(uvs)) = mesh.attribute_mut(Mesh::ATTRIBUTE_UV_0)
+    else {
+        return;
+    };
+    if tiles.is_empty() || uvs.len() % tiles.len() != 0 {
+        return;
+    }
+    let block_vertices = uvs.len() / tiles.len();
+    for (block_uvs, tile) in uvs.chunks_mut(block_vertices).zip(tiles) {
+        for uv in block_uvs.iter_mut() {
+            *uv = atlas_uv(*uv, *tile);
+        }
+    }
+}
+
+fn unit_byte(value: f32) -> u8 {
+    (value.clamp(0.0, 1.0) * 255.0).round() as u8
+}
+
+fn stacked_image(width: u32, data: Vec<u8>, format: TextureFormat) -> Image {
+    let height = data.len() as u32 / (width * 4);
+    Image::new(
+        Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        },
+        TextureDimension::D2,
+        data,
+        format,
+        RenderAssetUsages::RENDER_WORLD,
+    )
+}
+
+// Waits for the loading screen's textures, then builds the atlas once
+fn build_block_atlas(
+    mut commands: Commands,
+    stuff: Res<MeshesAndMaterials>,
+    asset_server: Res<AssetServer>,
+    mut images: ResMut<Assets<Image>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+    if stuff.loaded_textures(&asset_server) < stuff.block_textures.len() {
+        return;
+    }
+    let tier_materials: Vec<StandardMaterial> = (1..=TOP_MATERIAL_TIER)
+        .map(|tier| {
+            materials
+                .get(&tier_material(&stuff, tier))
+                .cloned()
+                .unwrap_or_default()
+        })
+        .collect();
+
+    let mut colors = Vec::new();
+    let mut emissive = Vec::new();
+    let mut metallic_roughness = Vec::new();
+    for (tile, material) in tier_materials.iter().enumerate() {
+        // A texture that failed to load leaves its tile white, like the untextured block it was
+        let texture = stuff
+            .block_textures
+            .get(tile)
+            .and_then(|texture| images.get(texture).cloned())
+            .and_then(|image| image.try_into_dynamic().ok())
+            .map(|image| {
+                image
+                    .resize_exact(TILE_SIZE, TILE_SIZE, FilterType::Triangle)
+                    .to_rgba8()
+                    .into_raw()
+            })
+            .unwrap_or_else(|| vec![255; (TILE_SIZE * TILE_SIZE * 4) as usize]);
+        colors.extend(texture);
+        let [red, green, blue, _] = material.emissive.as_linear_rgba_f32();
+        emissive.extend([red, green, blue].map(|channel| unit_byte(channel / EMISSIVE_SCALE)));
+        emissive.push(255);
+        // Roughness is read from green and metallic from blue
+        metallic_roughness.extend([
+            0,
+            unit_byte(material.perceptual_roughness),
+            unit_byte(material.metallic),
+            255,
+        ]);
+    }
+
+    let color_atlas = images.add(stacked_image(
+        TILE_SIZE,
+        colors,
+        TextureFormat::Rgba8UnormSrgb,
+    ));
+    // One pixel per tile, sampled without blending into the neighbouring tiles
+    let mut emissive_image = stacked_image(1, emissive, TextureFormat::Rgba8Unorm);
+    emissive_image.sampler = ImageSampler::nearest();
+    let emissive_atlas = images.add(emissive_image);
+    let mut metallic_roughness_image =
+        stacked_image(1, metallic_roughness, TextureFormat::Rgba8Unorm);
+    metallic_roughness_image.sampler = ImageSampler::nearest();
+    let metallic_roughness_atlas = images.add(metallic_roughness_image);
+
+    let see_through: Vec<bool> = tier_materials
+        .iter()
+        .map(|material| material.alpha_mode == AlphaMode::Blend)
+        .collect();
+    // What the textures can't carry is averaged over the tiers sharing the material
+    let group_material = |blended: bool| {
+        let group: Vec<&StandardMaterial> = tier_materials
+            .iter()
+            .zip(&see_through)
+            .filter(|(_, see_through)| **see_through == blended)
+            .map(|(material, _)| material)
+            .collect();
+        let mean = |value: fn(&StandardMaterial) -> f32| {
+            group.iter().map(|material| value(material)).sum::<f32>() / group.len().max(1) as f32
+        };
+        StandardMaterial {
+            base_color_texture: Some(color_atlas.clone()),
+            emissive: Color::rgb_linear(EMISSIVE_SCALE, EMISSIVE_SCALE, EMISSIVE_SCALE),
+            emissive_texture: Some(emissive_atlas.clone()),
+            metallic: 1.0,
+            perceptual_roughness: 1.0,
+            metallic_roughness_texture: Some(metallic_roughness_atlas.clone()),
+            reflectance: mean(|material| material.reflectance),
+            ior: mean(|material| material.ior),
+            specular_transmission: mean(|material| material.specular_transmission),
+            alpha_mode: if blended {
+                AlphaMode::Blend
+            } else {
+                AlphaMode::Opaque
+            },
+            ..Default::default()
+        }
+    };
+    let solid_material = materials.add(group_material(false));
+    let see_through_material = materials.add(group_material(true));
+
+    commands.insert_resource(BlockAtlas {
+        solid_material,
+        see_through_material,
+        see_through,
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tiers_map_to_their_texture_tile() {
+        assert_eq!(tier_tile(0), 0);
+        assert_eq!(tier_tile(1), 0);
+        assert_eq!(tier_tile(5), 4);
+        assert_eq!(tier_tile(TOP_MATERIAL_TIER + 3), TILES - 1);
+    }
+
+    #[test]
+    fn block_uvs_stay_inside_their_tile() {
+        for tile in 0..TILES {
+            let [_, top] = atlas_uv([0.0, 0.0], tile);
+            let [_, bottom] = atlas_uv([1.0, 1.0], tile);
+            assert!(top > tile as f32 / TILES as f32);
+            assert!(bottom < (tile + 1) as f32 / TILES as f32);
+        }
+    }
+}
@@ -0,0 +1,221 @@
+use std::time::Duration;
+
+use bevy::prelude::*;
+
+use crate::{
+    chunks::{rebuild_block_batches, BlockSlice, OwnershipHeatmap},
+    cyberspace::CyberspaceCoordinates,
+    origin::{CyberspacePosition, FloatingOrigin},
+    resources::{block_tier, tier_material, MeshesAndMaterials, POWBlock},
+};
+
+const SPAWN_ANIMATION: Duration = Duration::from_millis(400);
+// More blocks than this appearing at once just pop in, a sector loading would otherwise draw
+// every one of its blocks on its own for the length of the animation
+const MAX_SPAWN_ANIMATIONS: usize = 48;
+// Added to the block's own glow at the start of the animation, fading out by its end
+const SPAWN_FLASH: Color = Color::rgb_linear(4.0, 4.0, 4.0);
+const BURST_PARTICLES: usize = 16;
+const BURST_LIFETIME: Duration = Duration::from_millis(800);
+// Blocks per second the particles start out at, slowed by the drag
+const BURST_SPEED: f32 = 6.0;
+const BURST_DRAG: f32 = 3.0;
+const PARTICLE_SCALE: f32 = 0.15;
+// Further upgrades while this many bursts are flying are left out
+const MAX_BURSTS: usize = 8;
+
+pub fn block_effects_plugin(app: &mut App) {
+    app.add_event::<BlockReplaced>().add_systems(
+        Update,
+        (
+            (start_spawn_animations, animate_spawning_blocks)
+                .chain()
+                .before(rebuild_block_batches),
+            spawn_particle_bursts,
+            animate_particles,
+        ),
+    );
+}
+
+// Added by `spawn_mined_block`. The block is drawn on its own while it grows in, then joins its
+// sector's batch
+#[derive(Component, Default)]
+pub struct SpawnAnimation {
+    elapsed: Duration,
+    // Its own copy of the tier material, for the flash
+    material: Option<Handle<StandardMaterial>>,
+    emissive: Color,
+}
+
+// Sent by the websocket middleware when a block is upgraded or outbid by one with more POW
+#[derive(Event)]
+pub struct BlockReplaced {
+    pub coordinates: CyberspaceCoordinates,
+    pub tier: usize,
+}
+
+#[derive(Component)]
+struct BurstParticle {
+    velocity: Vec3,
+    elapsed: Duration,
+}
+
+// Overshoots a little before settling at full size
+fn ease_out_back(t: f32) -> f32 {
+    let t = t.clamp(0.0, 1.0) - 1.0;
+    1.0 + t * t * (2.7 * t + 1.7)
+}
+
+// Evenly spread directions on a sphere, so every burst looks the same without randomness
+fn burst_directions(count: usize) -> Vec<Vec3> {
+    let golden_angle = std::f32::consts::PI * (3.0 - 5.0_f32.sqrt());
+    (0..count)
+        .map(|index| {
+            let y = 1.0 - 2.0 * (index as f32 + 0.5) / count as f32;
+            let radius = (1.0 - y * y).sqrt();
+            let angle = golden_angle * index as f32;
+            Vec3::new(radius * angle.cos(), y, radius * angle.sin())
+        })
+        .collect()
+}
+
+fn start_spawn_animations(
+    mut commands: Commands,
+    stuff: Res<MeshesAndMaterials>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    heatmap: Res<OwnershipHeatmap>,
+    block_slice: Res<BlockSlice>,
+    mut new_blocks: Query<
+        (Entity, &CyberspacePosition, &POWBlock, &mut SpawnAnimation),
+        Added<SpawnAnimation>,
+    >,
+    running_query: Query<(), (With<SpawnAnimation>, With<Handle<Mesh>>)>,
+) {
+    let mut running = running_query.iter().count();
+    for (entity, position, block, mut animation) in new_blocks.iter_mut() {
+        // Heatmap colors and sliced away blocks only come from the batches
+        if running >= MAX_SPAWN_ANIMATIONS || **heatmap || !block_slice.shows(**position) {
+            commands.entity(entity).remove::<SpawnAnimation>();
+            continue;
+        }
+        running += 1;
+        let tier = block_tier(block.pow_amount, block.material);
+        let material = materials
+            .get(&tier_material(&stuff, tier))
+            .cloned()
+            .unwrap_or_default();
+        animation.emissive = material.emissive;
+        let material = materials.add(material);
+        animation.material = Some(material.clone());
+        commands.entity(entity).insert((
+            stuff.cube_mesh.clone_weak(),
+            material,
+            VisibilityBundle::default(),
+        ));
+    }
+}
+
+fn animate_spawning_blocks(
+    mut commands: Commands,
+    time: Res<Time>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    mut block_query: Query<(Entity, &mut Transform, &mut SpawnAnimation), With<Handle<Mesh>>>,
+) {
+    for (entity, mut transform, mut animation) in block_query.iter_mut() {
+        animation.elapsed += time.delta();
+        let t = animation.elapsed.as_secs_f32() / SPAWN_ANIMATION.as_secs_f32();
+        if t >= 1.0 {
+            transform.scale = Vec3::ONE;
+            commands.entity(entity).remove::<(
+                SpawnAnimation,
+                Handle<Mesh>,
+                Handle<StandardMaterial>,
+                VisibilityBundle,
+            )>();
+            continue;
+        }
+        transform.scale = Vec3::splat(ease_out_back(t));
+        let flash = (1.0 - t) * (1.0 - t);
+        if let Some(material) = animation
+            .material
+            .as_ref()
+            .and_then(|material| materials.get_mut(material))
+        {
+            material.emissive = animation.emissive + SPAWN_FLASH * flash;
+        }
+    }
+}
+
+fn spawn_particle_bursts(
+    mut commands: Commands,
+    stuff: Res<MeshesAndMaterials>,
+    origin: Res<FloatingOrigin>,
+    mut replaced_blocks: EventReader<BlockReplaced>,
+    particle_query: Query<(), With<BurstParticle>>,
+) {
+    let mut bursts = particle_query.iter().count() / BURST_PARTICLES;
+    for replaced in replaced_blocks.read() {
+        if bursts >= MAX_BURSTS {
+            continue;
+        }
+        bursts += 1;
+        // Not on a `CyberspacePosition`, the origin shifts them like any free transform
+        let center = origin.position(replaced.coordinates);
+        for direction in burst_directions(BURST_PARTICLES) {
+            commands.spawn((
+                PbrBundle {
+                    mesh: stuff.cube_mesh.clone_weak(),
+                    material: tier_material(&stuff, replaced.tier),
+                    transform: Transform::from_translation(center)
+                        .with_scale(Vec3::splat(PARTICLE_SCALE)),
+                    ..Default::default()
+                },
+                BurstParticle {
+                    velocity: direction * BURST_SPEED,
+                    elapsed: Duration::ZERO,
+                },
+            ));
+        }
+    }
+}
+
+fn animate_particles(
+    mut commands: Commands,
+    time: Res<Time>,
+    mut particle_query: Query<(Entity, &mut Transform, &mut BurstParticle)>,
+) {
+    let delta = time.delta_seconds();
+    for (entity, mut transform, mut particle) in particle_query.iter_mut() {
+        particle.elapsed += time.delta();
+        let t = particle.elapsed.as_secs_f32() / BURST_LIFETIME.as_secs_f32();
+        if t >= 1.0 {
+            commands.entity(entity).despawn();
+            continue;
+        }
+        transform.translation += particle.velocity * delta;
+        particle.velocity *= (1.0 - BURST_DRAG * delta).max(0.0);
+        transform.scale = Vec3::splat(PARTICLE_SCALE * (1.0 - t));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn spawn_animation_overshoots_then_settles() {
+        assert!(ease_out_back(0.0).abs() < 1e-5);
+        assert!((ease_out_back(1.0) - 1.0).abs() < 1e-5);
+        assert!(ease_out_back(0.8) > 1.0);
+    }
+
+    #[test]
+    fn burst_directions_are_unit_length_and_balanced() {
+        let directions = burst_directions(BURST_PARTICLES);
+        assert_eq!(directions.len(), BURST_PARTICLES);
+        assert!(directions
+            .iter()
+            .all(|direction| (direction.length() - 1.0).abs() < 1e-5));
+        assert!(directions.iter().sum::<Vec3>().y.abs() < 1e-4);
+    }
+}
@@ -0,0 +1,254 @@
+use std::collections::BTreeMap;
+
+use bevy::prelude::*;
+
+use crate::{
+    cameras::BlockIndicator,
+    chunks::WorldChunks,
+    cyberspace::CyberspaceCoordinates,
+    keybindings::{Action, KeyBindings},
+    logging::WORLD_LOG,
+    mining::{
+        queue_block, ChosenMaterial, MiningAction, MiningQueue, MiningState, UnminedBlockMap,
+    },
+    notifications::{Notification, Severity},
+    origin::FloatingOrigin,
+    resources::MeshesAndMaterials,
+    settings::Settings,
+};
+
+const BLUEPRINTS_FILE_PATH: &str = "./blueprints.json";
+const BLUEPRINT_PREVIEW_COLOR: Color = Color::CYAN;
+
+pub fn blueprints_plugin(app: &mut App) {
+    app.insert_resource(Blueprints::load())
+        .init_resource::<SelectedBlueprint>()
+        .add_systems(
+            Update,
+            (
+                save_blueprint,
+                select_blueprint,
+                preview_blueprint,
+                stamp_blueprint,
+            ),
+        );
+}
+
+// Named block patterns, offsets are relative to the indicator when saved
+#[derive(Resource, Default, Deref, DerefMut)]
+pub struct Blueprints(pub BTreeMap<String, Vec<[i32; 3]>>);
+
+impl Blueprints {
+    fn load() -> Self {
+        let Ok(blueprints) = std::fs::read_to_string(BLUEPRINTS_FILE_PATH) else {
+            return Blueprints::default();
+        };
+        match serde_json::from_str(&blueprints) {
+            Ok(blueprints) => Blueprints(blueprints),
+            Err(error) => {
+                warn!(target: WORLD_LOG, "Could not read {}: {}", BLUEPRINTS_FILE_PATH, error);
+                Blueprints::default()
+            }
+        }
+    }
+
+    fn save(&self) {
+        let blueprints = match serde_json::to_string_pretty(&self.0) {
+            Ok(blueprints) => blueprints,
+            Err(error) => {
+                warn!(target: WORLD_LOG, "Could not serialize blueprints: {}", error);
+                return;
+            }
+        };
+        if let Err(error) = std::fs::write(BLUEPRINTS_FILE_PATH, blueprints) {
+            warn!(target: WORLD_LOG, "Could not write {}: {}", BLUEPRINTS_FILE_PATH, error);
+        }
+    }
+
+    // Names can be changed by editing the file, new ones are just numbered
+    fn next_name(&self) -> String {
+        (1..)
+            .map(|number| format!("Blueprint {}", number))
+            .find(|name| !self.contains_key(name))
+            .unwrap_or_default()
+    }
+}
+
+// The blueprint shown at the indicator and stamped with V
+#[derive(Resource, Default, Deref, DerefMut)]
+pub struct SelectedBlueprint(pub Option<String>);
+
+// Offsets of the coordinates from the anchor, sorted so equal patterns save equally
+fn blueprint_offsets<'a>(
+    coordinates: impl Iterator<Item = &'a String>,
+    anchor: CyberspaceCoordinates,
+) -> Vec<[i32; 3]> {
+    let mut offsets: Vec<[i32; 3]> = coordinates
+        .filter_map(|coordinates| CyberspaceCoordinates::from_hex(coordinates).ok())
+        .map(|coordinates| coordinates.relative_to(anchor).as_ivec3().to_array())
+        .collect();
+    offsets.sort();
+    offsets
+}
+
+// K saves the placed blocks that aren't queued yet as a new blueprint
+fn save_blueprint(
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    key_bindings: Res<KeyBindings>,
+    block_indicator: Query<&Transform, With<BlockIndicator>>,
+    origin: Res<FloatingOrigin>,
+    unmined_block_map: Res<UnminedBlockMap>,
+    mut blueprints: ResMut<Blueprints>,
+    mut selected_blueprint: ResMut<SelectedBlueprint>,
+    mut notifications: EventWriter<Notification>,
+) {
+    if !keyboard_input.just_pressed(key_bindings.key(Action::SaveBlueprint))
+        || unmined_block_map.is_empty()
+    {
+        return;
+    }
+    let Ok(indicator_transform) = block_indicator.get_single() else {
+        return;
+    };
+    let anchor = origin.coordinates(indicator_transform.translation);
+    let offsets = blueprint_offsets(unmined_block_map.keys(), anchor);
+    let name = blueprints.next_name();
+    notifications.send(Notification {
+        message: format!("Saved {} blocks as {}", offsets.len(), name),
+        severity: Severity::Success,
+        play_sound: false,
+    });
+    blueprints.insert(name.clone(), offsets);
+    blueprints.save();
+    **selected_blueprint = Some(name);
+}
+
+// U cycles through the blueprints and back to none
+fn select_blueprint(
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    key_bindings: Res<KeyBindings>,
+    blueprints: Res<Blueprints>,
+    mut selected_blueprint: ResMut<SelectedBlueprint>,
+    mut notifications: EventWriter<Notification>,
+) {
+    if !keyboard_input.just_pressed(key_bindings.key(Action::SelectBlueprint)) {
+        return;
+    }
+    let next = match selected_blueprint.as_ref() {
+        None => blueprints.keys().next(),
+        Some(selected) => blueprints
+            .range::<String, _>((
+                std::ops::Bound::Excluded(selected),
+                std::ops::Bound::Unbounded,
+            ))
+            .next()
+            .map(|(name, _)| name),
+    };
+    **selected_blueprint = next.cloned();
+    if let Some(name) = next {
+        notifications.send(Notification {
+            message: format!("{} selected, V to stamp it", name),
+            severity: Severity::Info,
+            play_sound: false,
+        });
+    }
+}
+
+fn preview_blueprint(
+    blueprints: Res<Blueprints>,
+    selected_blueprint: Res<SelectedBlueprint>,
+    block_indicator: Query<&Transform, With<BlockIndicator>>,
+    mut gizmos: Gizmos,
+) {
+    let Some(offsets) = selected_blueprint
+        .as_ref()
+        .and_then(|name| blueprints.get(name))
+    else {
+        return;
+    };
+    let Ok(indicator_transform) = block_indicator.get_single() else {
+        return;
+    };
+    let anchor = indicator_transform.translation.round();
+    for offset in offsets {
+        gizmos.cuboid(
+            Transform::from_translation(anchor + IVec3::from_array(*offset).as_vec3()),
+            BLUEPRINT_PREVIEW_COLOR,
+        );
+    }
+}
+
+// V queues every block of the selected blueprint around the indicator
+fn stamp_blueprint(
+    mut commands: Commands,
+    stuff: Res<MeshesAndMaterials>,
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    key_bindings: Res<KeyBindings>,
+    blueprints: Res<Blueprints>,
+    selected_blueprint: Res<SelectedBlueprint>,
+    chosen_material: Res<ChosenMaterial>,
+    settings: Res<Settings>,
+    block_indicator: Query<&Transform, With<BlockIndicator>>,
+    origin: Res<FloatingOrigin>,
+    world_chunks: Res<WorldChunks>,
+    mut mining_queue: ResMut<MiningQueue>,
+    mut state: ResMut<NextState<MiningState>>,
+) {
+    // Ctrl+V is paste
+    if !keyboard_input.just_pressed(key_bindings.key(Action::StampBlueprint))
+        || keyboard_input.any_pressed([KeyCode::ControlLeft, KeyCode::ControlRight])
+    {
+        return;
+    }
+    let Some(offsets) = selected_blueprint
+        .as_ref()
+        .and_then(|name| blueprints.get(name))
+    else {
+        return;
+    };
+    let Ok(indicator_transform) = block_indicator.get_single() else {
+        return;
+    };
+    let anchor = origin.coordinates(indicator_transform.translation);
+    let action = MiningAction::Place {
+        material: **chosen_material,
+        label: settings.block_label(),
+    };
+    for offset in offsets {
+        queue_block(
+            &mut commands,
+            &stuff,
+            &mut mining_queue,
+            anchor.offset(IVec3::from_array(*offset)),
+            world_chunks.plane(),
+            action.clone(),
+        );
+    }
+    state.set(MiningState::Mining);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cyberspace::Plane;
+
+    #[test]
+    fn offsets_are_relative_and_sorted() {
+        let anchor = CyberspaceCoordinates::new(1 << 84, 5, 3);
+        let coordinates = [
+            anchor.offset(IVec3::new(2, 0, 0)).to_hex(Plane::ISpace),
+            anchor.to_hex(Plane::ISpace),
+        ];
+        assert_eq!(
+            blueprint_offsets(coordinates.iter(), anchor),
+            vec![[0, 0, 0], [2, 0, 0]]
+        );
+    }
+
+    #[test]
+    fn new_blueprints_get_free_names() {
+        let mut blueprints = Blueprints::default();
+        blueprints.insert("Blueprint 1".to_string(), Vec::new());
+        assert_eq!(blueprints.next_name(), "Blueprint 2");
+    }
+}
@@ -1,41 +1,99 @@
 use crate::{
-    cyberspace::{extract_coordinates, scale_coordinates_to_world},
+    chunks::WorldChunks,
+    console::{ConsoleCommand, ConsoleOutput, RegisterConsoleCommand},
+    cyberspace::CyberspaceCoordinates,
+    destination_prompt::parse_destination,
+    gamepad::GamepadSticks,
+    keybindings::{Action, KeyBindings},
+    logging::WORLD_LOG,
+    menu::AppState,
+    origin::FloatingOrigin,
     resources::MeshesAndMaterials,
-    ui_camera::{AvatarListDetails, UiElement},
+    settings::{Settings, DEFAULT_BLOOM_INTENSITY},
+    touch::TouchGestures,
+    ui_camera::{AvatarListDetails, AvatarRoster, UiElement},
+    waypoints::Waypoints,
     UserNostrKeys,
 };
 
 use bevy::{
     core_pipeline::{bloom::BloomSettings, tonemapping::Tonemapping},
-    input::mouse::MouseMotion,
+    input::mouse::{MouseMotion, MouseScrollUnit, MouseWheel},
     prelude::*,
     render::camera::RenderTarget,
-    window::WindowRef,
+    window::{CursorGrabMode, PrimaryWindow, WindowFocused, WindowMode, WindowRef},
 };
 
 pub fn camera_plugin(app: &mut App) {
-    app.add_systems(PostStartup, setup_voxel_camera)
+    app.init_resource::<NoClip>()
+        .init_resource::<CameraMode>()
+        .init_resource::<OrbitDistance>()
+        .add_event::<IndicatorMoved>()
+        .register_console_command(
+            "tp",
+            "/tp <x y z | npub | coordinate>",
+            "Flies the indicator there",
+        )
+        .add_systems(PostStartup, setup_voxel_camera)
+        .add_systems(OnEnter(AppState::Loading), move_indicator_home)
         .add_systems(
             Update,
             (
+                toggle_camera_mode,
+                pointer_capture,
+                toggle_fullscreen,
                 camera_look_system,
+                zoom_camera,
+                toggle_no_clip,
                 move_block_indicator,
+                announce_indicator_moves,
                 return_home,
                 teleporting_to_avatar,
+                teleport_command,
+                fly_teleports,
+                apply_bloom_intensity,
             ),
         );
 }
 
+// Every teleport flight takes this long, however far it goes
+const TELEPORT_SECONDS: f32 = 2.0;
+// The flight arcs up by this fraction of its length, up to a limit
+const TELEPORT_ARC: f32 = 0.2;
+const MAX_TELEPORT_ARC_HEIGHT: f32 = 64.0;
+// Flights to anywhere further move the origin there and come in from this far out
+const MAX_FLIGHT_DISTANCE: f32 = 2048.0;
+// Bevy's default field of view, and how much wider it gets halfway through a flight
+const DEFAULT_FOV: f32 = std::f32::consts::FRAC_PI_4;
+const TELEPORT_FOV_KICK: f32 = 0.35;
 const CAMERA_ORBIT_LOCATION: Vec3 = Vec3::new(4.0, 21.0, 21.0);
 const CAMERA_ORBIT_LOOK_AT: Vec3 = Vec3::ZERO;
+const MOUSE_SENSITIVITY: f32 = 0.01;
+// Just short of straight up or down, past it the view flips over
+const MAX_PITCH: f32 = 1.5;
+// Mouse pixels per second a fully tilted right stick is worth
+const STICK_LOOK_SPEED: f32 = 300.0;
+// Closest and furthest the orbit camera gets from the indicator
+const MIN_ORBIT_DISTANCE: f32 = 4.0;
+const MAX_ORBIT_DISTANCE: f32 = 256.0;
+// Each notch of the wheel moves this fraction of the distance, so zooming feels the same near and far
+const ZOOM_STEP: f32 = 0.15;
+// Pixel scrolling from touchpads counts a notch every this many pixels
+const PIXELS_PER_NOTCH: f32 = 40.0;
+// How quickly the camera catches up with the zoom, higher is snappier
+const ZOOM_SMOOTHING: f32 = 12.0;
+const ZOOM_SETTLE_DISTANCE: f32 = 0.01;
+// Blocks per second while holding WASDQE, and while holding the arrows and page keys
+const WALK_SPEED: f32 = 8.0;
+const RUN_SPEED: f32 = 64.0;
+// Positions this close to a whole coordinate count as on it when snapping
+const SNAP_TOLERANCE: f32 = 0.001;
 
 #[derive(Component)]
-struct ExplorerCamera;
+pub struct ExplorerCamera;
 
 #[derive(Component)]
-pub struct BlockIndicator {
-    pub teleport_progress: f32,
-}
+pub struct BlockIndicator;
 
 #[derive(Bundle)]
 pub struct ExplorerCameraBundle(Camera3dBundle, ExplorerCamera, BloomSettings);
@@ -58,29 +116,23 @@ impl ExplorerCameraBundle {
             0: camera_entity,
             1: ExplorerCamera,
             2: BloomSettings {
-                intensity: 0.21,
+                intensity: DEFAULT_BLOOM_INTENSITY,
                 ..Default::default()
             },
         }
     }
 }
 
-fn setup_voxel_camera(
-    mut commands: Commands,
-    nostr_signer: Res<UserNostrKeys>,
-    assets: Res<MeshesAndMaterials>,
-) {
+// Moved home once the menu picks a key, see `move_indicator_home`
+fn setup_voxel_camera(mut commands: Commands, assets: Res<MeshesAndMaterials>) {
     commands
         .spawn((
             PbrBundle {
                 mesh: assets.cube_mesh.clone_weak(),
                 material: assets.clear_material.clone_weak(),
-                transform: Transform::from_translation(nostr_signer.get_home_coordinates()),
                 ..Default::default()
             },
-            BlockIndicator {
-                teleport_progress: 0.0,
-            },
+            BlockIndicator,
         ))
         .with_children(|builder| {
             builder.spawn(ExplorerCameraBundle::new_default(
@@ -90,164 +142,713 @@ fn setup_voxel_camera(
         });
 }
 
+// The camera exists behind the menu, so it starts at the home of the key picked there.
+// Nothing is placed in the world yet, so the origin jumps home without moving anything
+pub fn move_indicator_home(
+    nostr_signer: Res<UserNostrKeys>,
+    mut origin: ResMut<FloatingOrigin>,
+    mut block_indicator: Query<&mut Transform, With<BlockIndicator>>,
+) {
+    origin.reset(nostr_signer.get_home_coordinates());
+    for mut transform in block_indicator.iter_mut() {
+        transform.translation = origin.position(nostr_signer.get_home_coordinates());
+    }
+}
+
+// Tab switches between orbiting the indicator and looking out from inside it
+#[derive(Resource, Default)]
+pub struct CameraMode {
+    pub first_person: bool,
+    yaw: f32,
+    pitch: f32,
+    // Where the orbit camera was, to go back to it
+    orbit: Transform,
+    // Mouse look only follows the mouse while it's locked to the window
+    pointer_captured: bool,
+}
+
+impl CameraMode {
+    // The orbit camera's transform, remembered while looking out in first person
+    pub fn orbit_transform(&self, camera_transform: Transform) -> Transform {
+        if self.first_person {
+            self.orbit
+        } else {
+            camera_transform
+        }
+    }
+}
+
+fn toggle_camera_mode(
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    key_bindings: Res<KeyBindings>,
+    mut camera_mode: ResMut<CameraMode>,
+    mut camera_query: Query<&mut Transform, With<ExplorerCamera>>,
+    mut window_query: Query<&mut Window, With<PrimaryWindow>>,
+) {
+    if !keyboard_input.just_pressed(key_bindings.key(Action::FirstPerson)) {
+        return;
+    }
+    let Ok(mut camera_transform) = camera_query.get_single_mut() else {
+        return;
+    };
+    camera_mode.first_person = !camera_mode.first_person;
+    if camera_mode.first_person {
+        // Keep facing the way the orbit camera was looking
+        let forward = *camera_transform.forward();
+        camera_mode.yaw = (-forward.x).atan2(-forward.z);
+        camera_mode.pitch = 0.0;
+        camera_mode.orbit = *camera_transform;
+        *camera_transform = Transform::from_rotation(first_person_rotation(&camera_mode));
+    } else {
+        *camera_transform = camera_mode.orbit;
+    }
+    for mut window in window_query.iter_mut() {
+        capture_pointer(&mut window, camera_mode.first_person);
+    }
+    camera_mode.pointer_captured = camera_mode.first_person;
+}
+
+fn capture_pointer(window: &mut Window, captured: bool) {
+    window.cursor.grab_mode = if captured {
+        CursorGrabMode::Locked
+    } else {
+        CursorGrabMode::None
+    };
+    window.cursor.visible = !captured;
+}
+
+// Losing focus, like alt tabbing or a browser dropping the pointer lock, hands the pointer back.
+// Clicking into the window takes it again, browsers only allow the lock on a click anyway
+fn pointer_capture(
+    mouse_input: Res<ButtonInput<MouseButton>>,
+    mut focus_events: EventReader<WindowFocused>,
+    mut camera_mode: ResMut<CameraMode>,
+    mut window_query: Query<&mut Window, With<PrimaryWindow>>,
+) {
+    let focus_lost = focus_events.read().any(|focus| !focus.focused);
+    let wanted = if focus_lost {
+        false
+    } else if camera_mode.first_person && mouse_input.just_pressed(MouseButton::Left) {
+        true
+    } else {
+        return;
+    };
+    if camera_mode.pointer_captured == wanted {
+        return;
+    }
+    camera_mode.pointer_captured = wanted;
+    for mut window in window_query.iter_mut() {
+        capture_pointer(&mut window, wanted);
+    }
+}
+
+fn toggle_fullscreen(
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    key_bindings: Res<KeyBindings>,
+    mut window_query: Query<&mut Window, With<PrimaryWindow>>,
+) {
+    if !keyboard_input.just_pressed(key_bindings.key(Action::ToggleFullscreen)) {
+        return;
+    }
+    for mut window in window_query.iter_mut() {
+        window.mode = match window.mode {
+            WindowMode::Windowed => WindowMode::BorderlessFullscreen,
+            _ => WindowMode::Windowed,
+        };
+    }
+}
+
+fn apply_bloom_intensity(
+    settings: Res<Settings>,
+    mut bloom_query: Query<&mut BloomSettings, With<ExplorerCamera>>,
+) {
+    if !settings.is_changed() {
+        return;
+    }
+    for mut bloom in bloom_query.iter_mut() {
+        bloom.intensity = settings.bloom_intensity;
+    }
+}
+
+fn first_person_rotation(camera_mode: &CameraMode) -> Quat {
+    Quat::from_euler(EulerRot::YXZ, camera_mode.yaw, camera_mode.pitch, 0.0)
+}
+
+// Turns movement keys to where the first person camera faces, in quarter turns
+// so the indicator stays on the grid
+fn facing_movement(movement: IVec3, yaw: f32) -> IVec3 {
+    let quarter_turns = ((yaw / std::f32::consts::FRAC_PI_2).round() as i32).rem_euclid(4);
+    (0..quarter_turns).fold(movement, |movement, _| {
+        IVec3::new(movement.z, movement.y, -movement.x)
+    })
+}
+
+// Set when the indicator may move through mined blocks
+#[derive(Resource, Default, Deref, DerefMut)]
+pub struct NoClip(pub bool);
+
+fn toggle_no_clip(
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    key_bindings: Res<KeyBindings>,
+    mut no_clip: ResMut<NoClip>,
+) {
+    if keyboard_input.just_pressed(key_bindings.key(Action::NoClip)) {
+        **no_clip = !**no_clip;
+        info!(target: WORLD_LOG, "No-clip {}", if **no_clip { "on" } else { "off" });
+    }
+}
+
+// Sent when the indicator reaches other coordinates, so panels about where it is only update then
+#[derive(Event)]
+pub struct IndicatorMoved(pub CyberspaceCoordinates);
+
+fn announce_indicator_moves(
+    block_indicator: Query<&Transform, With<BlockIndicator>>,
+    origin: Res<FloatingOrigin>,
+    mut moves: EventWriter<IndicatorMoved>,
+    mut last_coordinates: Local<Option<CyberspaceCoordinates>>,
+) {
+    let Ok(indicator_transform) = block_indicator.get_single() else {
+        return;
+    };
+    let coordinates = origin.coordinates(indicator_transform.translation);
+    if *last_coordinates != Some(coordinates) {
+        *last_coordinates = Some(coordinates);
+        moves.send(IndicatorMoved(coordinates));
+    }
+}
+
 fn move_block_indicator(
+    time: Res<Time>,
     keyboard_input: Res<ButtonInput<KeyCode>>,
-    mut query: Query<(&mut Transform, &BlockIndicator)>,
+    key_bindings: Res<KeyBindings>,
+    world_chunks: Res<WorldChunks>,
+    origin: Res<FloatingOrigin>,
+    no_clip: Res<NoClip>,
+    camera_mode: Res<CameraMode>,
+    gamepad_sticks: Res<GamepadSticks>,
+    // The flight moves the indicator until it lands
+    mut query: Query<(&mut Transform, &BlockIndicator), Without<Teleporting>>,
+    mut travel_direction: Local<Vec3>,
 ) {
-    for (mut transform, _block_indicator) in query.iter_mut() {
-        if keyboard_input.just_pressed(KeyCode::KeyW) {
-            transform.translation.z -= 1.0;
-        }
-        if keyboard_input.just_pressed(KeyCode::KeyS) {
-            transform.translation.z += 1.0;
-        }
-        if keyboard_input.just_pressed(KeyCode::KeyA) {
-            transform.translation.x -= 1.0;
-        }
-        if keyboard_input.just_pressed(KeyCode::KeyD) {
-            transform.translation.x += 1.0;
-        }
-        if keyboard_input.just_pressed(KeyCode::KeyQ) {
-            transform.translation.y += 1.0;
-        }
-        if keyboard_input.just_pressed(KeyCode::KeyE) {
-            transform.translation.y -= 1.0;
+    let held_direction = |actions: [(Action, IVec3); 6]| {
+        let direction = actions
+            .into_iter()
+            .filter(|(action, _)| keyboard_input.pressed(key_bindings.key(*action)))
+            .fold(IVec3::ZERO, |direction, (_, key_direction)| {
+                direction + key_direction
+            });
+        if camera_mode.first_person {
+            facing_movement(direction, camera_mode.yaw)
+        } else {
+            direction
         }
+    };
+    let walk = held_direction([
+        (Action::MoveForward, IVec3::NEG_Z),
+        (Action::MoveBack, IVec3::Z),
+        (Action::MoveLeft, IVec3::NEG_X),
+        (Action::MoveRight, IVec3::X),
+        (Action::MoveUp, IVec3::Y),
+        (Action::MoveDown, IVec3::NEG_Y),
+    ]);
+    let run = held_direction([
+        (Action::FastForward, IVec3::NEG_Z),
+        (Action::FastBack, IVec3::Z),
+        (Action::FastLeft, IVec3::NEG_X),
+        (Action::FastRight, IVec3::X),
+        (Action::FastUp, IVec3::Y),
+        (Action::FastDown, IVec3::NEG_Y),
+    ]);
+    // The stick is analog so it follows the exact facing, releasing it still snaps to the grid
+    let stick = if camera_mode.first_person {
+        Quat::from_rotation_y(camera_mode.yaw) * gamepad_sticks.movement
+    } else {
+        gamepad_sticks.movement
+    };
+    let velocity = walk.as_vec3() * WALK_SPEED + run.as_vec3() * RUN_SPEED + stick * WALK_SPEED;
 
-        if keyboard_input.pressed(KeyCode::ArrowUp) {
-            transform.translation.z -= 1.0;
+    // Only blocks of the plane we're in are solid
+    let occupied = |voxel: IVec3| {
+        !**no_clip
+            && world_chunks
+                .block_at(origin.coordinates(voxel.as_vec3()))
+                .is_some()
+    };
+    for (mut transform, _block_indicator) in query.iter_mut() {
+        if velocity == Vec3::ZERO {
+            // Blocks are placed on whole coordinates, so settle on one once the keys are released
+            if *travel_direction != Vec3::ZERO {
+                let snapped = snap_to_grid(transform.translation, *travel_direction);
+                transform.translation = if occupied(snapped) {
+                    transform.translation.round()
+                } else {
+                    snapped.as_vec3()
+                };
+            }
+            continue;
         }
+        transform.translation = glide(
+            transform.translation,
+            velocity * time.delta_seconds(),
+            occupied,
+        );
+    }
 
-        if keyboard_input.pressed(KeyCode::ArrowDown) {
-            transform.translation.z += 1.0;
-        }
+    // Axes keep the last direction they moved in until the keys are released
+    if velocity == Vec3::ZERO {
+        *travel_direction = Vec3::ZERO;
+    } else {
+        *travel_direction = Vec3::select(
+            velocity.cmpeq(Vec3::ZERO),
+            *travel_direction,
+            velocity.signum(),
+        );
+    }
+}
 
-        if keyboard_input.pressed(KeyCode::ArrowLeft) {
-            transform.translation.x -= 1.0;
+// Rounds towards the direction of travel, so even a short tap moves a whole block
+fn snap_to_grid(position: Vec3, direction: Vec3) -> IVec3 {
+    let snap_axis = |position: f32, direction: f32| {
+        if direction > 0.0 {
+            (position - SNAP_TOLERANCE).ceil()
+        } else if direction < 0.0 {
+            (position + SNAP_TOLERANCE).floor()
+        } else {
+            position.round()
         }
+    };
+    Vec3::new(
+        snap_axis(position.x, direction.x),
+        snap_axis(position.y, direction.y),
+        snap_axis(position.z, direction.z),
+    )
+    .as_ivec3()
+}
 
-        if keyboard_input.pressed(KeyCode::ArrowRight) {
-            transform.translation.x += 1.0;
+// Moves freely within a block, crossing into the next one goes through the voxel collision
+fn glide(position: Vec3, movement: Vec3, occupied: impl Fn(IVec3) -> bool) -> Vec3 {
+    let mut position = position;
+    for axis in [Vec3::X, Vec3::Y, Vec3::Z] {
+        let next = position + movement * axis;
+        let cell = position.round().as_ivec3();
+        let next_cell = next.round().as_ivec3();
+        if next_cell == cell {
+            position = next;
+            continue;
         }
-
-        if keyboard_input.pressed(KeyCode::PageUp) {
-            transform.translation.y += 1.0;
+        let reached = collide_movement(cell, next_cell - cell, &occupied);
+        if reached == next_cell {
+            position = next;
+        } else if reached == next_cell + IVec3::Y {
+            // Stepped on top of a block
+            position = next;
+            position.y = reached.y as f32;
+        } else {
+            // Blocked part of the way, a long step still covers the free cells before the block
+            position += (reached - cell).as_vec3();
         }
+    }
+    position
+}
 
-        if keyboard_input.pressed(KeyCode::PageDown) {
-            transform.translation.y -= 1.0;
+// Moves a unit at a time along each axis. Walking into a single block steps on top of it,
+// anything else occupied stops the movement along that axis.
+fn collide_movement(position: IVec3, movement: IVec3, occupied: impl Fn(IVec3) -> bool) -> IVec3 {
+    let mut position = position;
+    for axis in [IVec3::X, IVec3::Y, IVec3::Z] {
+        let distance = (movement * axis).dot(IVec3::ONE);
+        let step = axis * distance.signum();
+        for _ in 0..distance.abs() {
+            let next = position + step;
+            if !occupied(next) {
+                position = next;
+            } else if axis != IVec3::Y
+                && !occupied(next + IVec3::Y)
+                && !occupied(position + IVec3::Y)
+            {
+                position = next + IVec3::Y;
+            } else {
+                break;
+            }
         }
     }
+    position
 }
 
 fn camera_look_system(
+    time: Res<Time>,
     mouse_input: Res<ButtonInput<MouseButton>>,
+    gamepad_sticks: Res<GamepadSticks>,
+    touch_gestures: Res<TouchGestures>,
     mut mouse_motion_events: EventReader<MouseMotion>,
+    mut camera_mode: ResMut<CameraMode>,
     mut camera_state: Query<&mut Transform, With<ExplorerCamera>>,
 ) {
+    let mouse_delta: Vec2 = mouse_motion_events
+        .read()
+        .fold(Vec2::ZERO, |acc, motion| acc + motion.delta);
+    // Tilting the stick up looks up, like moving the mouse up
+    let stick_delta =
+        gamepad_sticks.look * Vec2::new(1.0, -1.0) * STICK_LOOK_SPEED * time.delta_seconds();
+    if camera_mode.first_person {
+        // While the pointer is captured every mouse movement looks around
+        let mouse_delta = if camera_mode.pointer_captured {
+            mouse_delta
+        } else {
+            Vec2::ZERO
+        };
+        let delta = mouse_delta + stick_delta + touch_gestures.orbit;
+        if delta == Vec2::ZERO {
+            return;
+        }
+        camera_mode.yaw -= delta.x * MOUSE_SENSITIVITY;
+        camera_mode.pitch =
+            (camera_mode.pitch - delta.y * MOUSE_SENSITIVITY).clamp(-MAX_PITCH, MAX_PITCH);
+        for mut camera_transform in camera_state.iter_mut() {
+            camera_transform.rotation = first_person_rotation(&camera_mode);
+        }
+        return;
+    }
     if let Ok(mut camera_transform) = camera_state.get_single_mut() {
-        let vec_forward = camera_transform.rotation.mul_vec3(Vec3::Z);
-
-        if mouse_input.pressed(MouseButton::Right) {
-            let delta: Vec2 = mouse_motion_events
-                .read()
-                .fold(Vec2::ZERO, |acc, motion| acc + motion.delta);
+        let orbit_delta = if mouse_input.pressed(MouseButton::Right) {
+            mouse_delta + stick_delta
+        } else {
+            stick_delta
+        } + touch_gestures.orbit;
+        if orbit_delta != Vec2::ZERO {
             // Calculate the pitch adjustment relative to the camera's current orientation
             let right_dir = camera_transform.local_x();
-            let pitch_quat = Quat::from_axis_angle(*right_dir, -delta.y * 0.01);
+            let pitch_quat = Quat::from_axis_angle(*right_dir, -orbit_delta.y * 0.01);
             camera_transform.rotate_around(Vec3::ZERO, pitch_quat);
 
             // Move the yaw with delta.x
-            camera_transform.rotate_around(Vec3::ZERO, Quat::from_rotation_y(delta.x * 0.01));
+            camera_transform.rotate_around(Vec3::ZERO, Quat::from_rotation_y(orbit_delta.x * 0.01));
         }
+    }
+}
+
+// How far the orbit camera wants to be from the indicator, the wheel changes it
+#[derive(Resource, Deref, DerefMut)]
+pub struct OrbitDistance(pub f32);
+
+impl Default for OrbitDistance {
+    fn default() -> Self {
+        OrbitDistance(CAMERA_ORBIT_LOCATION.distance(CAMERA_ORBIT_LOOK_AT))
+    }
+}
 
-        if mouse_input.pressed(MouseButton::Middle) {
-            let delta: Vec2 = mouse_motion_events
-                .read()
-                .fold(Vec2::ZERO, |acc, motion| acc + motion.delta);
-            camera_transform.translation += vec_forward * delta.y * 0.1;
+fn zoom_target(distance: f32, notches: f32) -> f32 {
+    (distance * (1.0 - ZOOM_STEP).powf(notches)).clamp(MIN_ORBIT_DISTANCE, MAX_ORBIT_DISTANCE)
+}
+
+// Eases towards the target at the same pace whatever the frame rate
+fn smooth_distance(current: f32, target: f32, delta_seconds: f32) -> f32 {
+    target + (current - target) * (-ZOOM_SMOOTHING * delta_seconds).exp()
+}
+
+// The orbit camera always looks at the indicator, so zooming slides it along its own position
+fn zoom_camera(
+    time: Res<Time>,
+    camera_mode: Res<CameraMode>,
+    touch_gestures: Res<TouchGestures>,
+    mut mouse_wheel_events: EventReader<MouseWheel>,
+    roster_query: Query<&Interaction, With<AvatarRoster>>,
+    mut orbit_distance: ResMut<OrbitDistance>,
+    mut camera_query: Query<&mut Transform, With<ExplorerCamera>>,
+) {
+    // The roster scrolls instead while the cursor is over it
+    let over_roster = roster_query
+        .iter()
+        .any(|interaction| *interaction != Interaction::None);
+    let notches: f32 = mouse_wheel_events
+        .read()
+        .filter(|_| !over_roster)
+        .map(|wheel| match wheel.unit {
+            MouseScrollUnit::Line => wheel.y,
+            MouseScrollUnit::Pixel => wheel.y / PIXELS_PER_NOTCH,
+        })
+        .sum::<f32>()
+        + touch_gestures.zoom;
+    if camera_mode.first_person {
+        return;
+    }
+    if notches != 0.0 {
+        **orbit_distance = zoom_target(**orbit_distance, notches);
+    }
+    for mut camera_transform in camera_query.iter_mut() {
+        let current = camera_transform.translation.length();
+        if current == **orbit_distance {
+            continue;
+        }
+        let mut distance = smooth_distance(current, **orbit_distance, time.delta_seconds());
+        // Close enough, stop easing forever towards it
+        if (distance - **orbit_distance).abs() < ZOOM_SETTLE_DISTANCE {
+            distance = **orbit_distance;
+        }
+        camera_transform.translation = camera_transform.translation.normalize_or_zero() * distance;
+    }
+}
+
+// A flight of the indicator to somewhere far, started by the teleport keys
+#[derive(Component, Debug, Clone, PartialEq)]
+pub struct Teleporting {
+    from: CyberspaceCoordinates,
+    to: CyberspaceCoordinates,
+    elapsed: f32,
+    // Shown next to the progress
+    label: &'static str,
+}
+
+impl Teleporting {
+    pub fn new(
+        from: CyberspaceCoordinates,
+        to: CyberspaceCoordinates,
+        label: &'static str,
+    ) -> Self {
+        Teleporting {
+            from,
+            to,
+            elapsed: 0.0,
+            label,
         }
     }
+
+    fn progress(&self) -> f32 {
+        (self.elapsed / TELEPORT_SECONDS).min(1.0)
+    }
+}
+
+// Slow to leave, fast in the middle and slow to land
+fn ease_in_out(t: f32) -> f32 {
+    if t < 0.5 {
+        4.0 * t * t * t
+    } else {
+        1.0 - (-2.0 * t + 2.0).powi(3) / 2.0
+    }
+}
+
+// Arcs up over the way so the flight clears the blocks in between
+fn flight_position(from: Vec3, to: Vec3, progress: f32) -> Vec3 {
+    let arc = (from.distance(to) * TELEPORT_ARC).min(MAX_TELEPORT_ARC_HEIGHT);
+    from.lerp(to, ease_in_out(progress)) + Vec3::Y * arc * (progress * std::f32::consts::PI).sin()
 }
 
 fn return_home(
+    mut commands: Commands,
     keyboard_input: Res<ButtonInput<KeyCode>>,
-    mut block_indicator: Query<(&mut Transform, &mut BlockIndicator)>,
+    key_bindings: Res<KeyBindings>,
+    block_indicator: Query<(Entity, &Transform), (With<BlockIndicator>, Without<Teleporting>)>,
     nostr_signer: Res<UserNostrKeys>,
-    mut text_query: Query<(&mut Text, &UiElement)>,
+    waypoints: Res<Waypoints>,
+    origin: Res<FloatingOrigin>,
 ) {
-    let (mut block_transform, mut block_details) = block_indicator.single_mut();
-
-    if keyboard_input.pressed(KeyCode::Home) {
-        while block_details.teleport_progress < 100.0 {
-            block_details.teleport_progress += 1.0;
-            for (mut text, ui_entity) in text_query.iter_mut() {
-                if let UiElement::TeleportingNotice(_) = ui_entity {
-                    text.sections[0].value =
-                        format!("Going Home: {:.2}%", block_details.teleport_progress);
-                }
-            }
-            return;
-        }
-        block_details.teleport_progress = 0.0;
-        for (mut text, ui_entity) in text_query.iter_mut() {
-            if let UiElement::TeleportingNotice(_) = ui_entity {
-                text.sections[0].value = String::new();
-            }
-        }
-        let pubkey = nostr_signer.get_public_key();
-        let home_coordinates = extract_coordinates(&pubkey).unwrap();
-        let scale_coordinates =
-            scale_coordinates_to_world(home_coordinates.0, home_coordinates.1, home_coordinates.2);
-        let home_vec = Vec3::new(
-            scale_coordinates.0,
-            scale_coordinates.1,
-            scale_coordinates.2,
-        );
+    if !keyboard_input.just_pressed(key_bindings.key(Action::GoHome)) {
+        return;
+    }
+    let Ok((indicator, block_transform)) = block_indicator.get_single() else {
+        return;
+    };
+    // Home is the first waypoint, unless they were all removed
+    let home_vec = waypoints
+        .home()
+        .unwrap_or_else(|| nostr_signer.get_home_coordinates());
+    commands.entity(indicator).insert(Teleporting::new(
+        origin.coordinates(block_transform.translation),
+        home_vec,
+        "Going Home",
+    ));
+}
 
-        block_transform.translation = home_vec;
+fn teleporting_to_avatar(
+    mut commands: Commands,
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    key_bindings: Res<KeyBindings>,
+    avatar_list: Res<AvatarListDetails>,
+    origin: Res<FloatingOrigin>,
+    block_indicator: Query<(Entity, &Transform), (With<BlockIndicator>, Without<Teleporting>)>,
+) {
+    if !keyboard_input.just_pressed(key_bindings.key(Action::TeleportToAvatar)) {
+        return;
     }
+    let (Ok((indicator, block_transform)), Some(avatar_coordinates)) =
+        (block_indicator.get_single(), avatar_list.get_coordinates())
+    else {
+        return;
+    };
+    commands.entity(indicator).insert(Teleporting::new(
+        origin.coordinates(block_transform.translation),
+        avatar_coordinates,
+        "Teleporting",
+    ));
+}
 
-    if keyboard_input.just_released(KeyCode::Home) {
-        for (mut text, ui_entity) in text_query.iter_mut() {
-            if let UiElement::TeleportingNotice(_) = ui_entity {
-                text.sections[0].value = String::new();
-                block_details.teleport_progress = 0.0;
-            }
+fn teleport_command(
+    mut commands: Commands,
+    mut console_commands: EventReader<ConsoleCommand>,
+    mut console_output: EventWriter<ConsoleOutput>,
+    origin: Res<FloatingOrigin>,
+    block_indicator: Query<(Entity, &Transform), (With<BlockIndicator>, Without<Teleporting>)>,
+) {
+    for command in console_commands.read() {
+        if command.name != "tp" {
+            continue;
         }
+        let Some(destination) = parse_destination(&command.args.join(" ")) else {
+            console_output.send(ConsoleOutput(
+                "Usage: /tp <x y z | npub | coordinate>".to_string(),
+            ));
+            continue;
+        };
+        let Ok((indicator, block_transform)) = block_indicator.get_single() else {
+            console_output.send(ConsoleOutput("Already teleporting".to_string()));
+            continue;
+        };
+        commands.entity(indicator).insert(Teleporting::new(
+            origin.coordinates(block_transform.translation),
+            destination,
+            "Teleporting",
+        ));
+        console_output.send(ConsoleOutput(format!(
+            "Teleporting to X: {} Y: {} Z: {}",
+            destination.x, destination.y, destination.z
+        )));
     }
 }
 
-fn teleporting_to_avatar(
-    keyboard_input: Res<ButtonInput<KeyCode>>,
-    avatar_list: ResMut<AvatarListDetails>,
-    mut block_indicator: Query<(&mut BlockIndicator, &mut Transform)>,
+fn fly_teleports(
+    mut commands: Commands,
+    time: Res<Time>,
+    mut origin: ResMut<FloatingOrigin>,
+    mut block_indicator: Query<(Entity, &mut Transform, &mut Teleporting)>,
+    mut camera_query: Query<&mut Projection, With<ExplorerCamera>>,
     mut text_query: Query<(&mut Text, &UiElement)>,
 ) {
-    let (mut block_details, mut block_transform) = block_indicator.single_mut();
-    if keyboard_input.pressed(KeyCode::End) {
-        for (mut text, ui_entity) in text_query.iter_mut() {
-            if let UiElement::TeleportingNotice(_) = ui_entity {
-                text.sections[0].value =
-                    format!("Teleporting... {:.2}%", block_details.teleport_progress);
-                if block_details.teleport_progress < 100.0 {
-                    block_details.teleport_progress += 1.0;
-                } else {
-                    block_details.teleport_progress = 0.0;
-                    text.sections[0].value = String::new();
+    for (indicator, mut block_transform, mut teleporting) in block_indicator.iter_mut() {
+        teleporting.elapsed += time.delta_seconds();
+        let progress = teleporting.progress();
+        // Too far for an f32, the world is moved over to the destination first
+        if origin.position(teleporting.to).length() > MAX_FLIGHT_DISTANCE {
+            origin.recenter(teleporting.to);
+        }
+        let to = origin.position(teleporting.to);
+        let from =
+            to + (origin.position(teleporting.from) - to).clamp_length_max(MAX_FLIGHT_DISTANCE);
+        block_transform.translation = flight_position(from, to, progress);
+        let landed = progress >= 1.0;
+        if landed {
+            block_transform.translation = to;
+            commands.entity(indicator).remove::<Teleporting>();
+        }
 
-                    block_transform.translation = avatar_list.get_coordinates();
-                }
+        // The view widens while flying fast and settles back when landing
+        for mut projection in camera_query.iter_mut() {
+            if let Projection::Perspective(perspective) = projection.as_mut() {
+                perspective.fov = DEFAULT_FOV
+                    + TELEPORT_FOV_KICK * (progress * std::f32::consts::PI).sin().max(0.0);
             }
         }
-    }
-
-    if keyboard_input.just_released(KeyCode::End) {
+        let notice = if landed {
+            String::new()
+        } else {
+            format!("{}: {:.0}%", teleporting.label, progress * 100.0)
+        };
         for (mut text, ui_entity) in text_query.iter_mut() {
             if let UiElement::TeleportingNotice(_) = ui_entity {
-                text.sections[0].value = String::new();
-                block_details.teleport_progress = 0.0;
+                text.sections[0].value = notice.clone();
             }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn teleport_flights_ease_and_land_on_target() {
+        let from = Vec3::new(0.0, 0.0, 0.0);
+        let to = Vec3::new(100.0, 0.0, 0.0);
+        assert_eq!(flight_position(from, to, 0.0), from);
+        assert!((flight_position(from, to, 1.0) - to).length() < 1e-3);
+        // Halfway there, up at the top of the arc
+        let middle = flight_position(from, to, 0.5);
+        assert!((middle.x - 50.0).abs() < 1e-3);
+        assert!((middle.y - 20.0).abs() < 1e-3);
+        assert!(ease_in_out(0.1) < 0.1 && ease_in_out(0.9) > 0.9);
+    }
+
+    #[test]
+    fn zoom_is_clamped_and_smoothed() {
+        assert!(zoom_target(30.0, 1.0) < 30.0);
+        assert!(zoom_target(30.0, -1.0) > 30.0);
+        assert_eq!(zoom_target(30.0, 100.0), MIN_ORBIT_DISTANCE);
+        assert_eq!(zoom_target(30.0, -100.0), MAX_ORBIT_DISTANCE);
+        let eased = smooth_distance(30.0, 10.0, 1.0 / 60.0);
+        assert!(eased < 30.0 && eased > 10.0);
+        // Two half frames land where one whole frame does
+        let halves = smooth_distance(smooth_distance(30.0, 10.0, 0.01), 10.0, 0.01);
+        assert!((halves - smooth_distance(30.0, 10.0, 0.02)).abs() < 1e-4);
+    }
+
+    #[test]
+    fn released_movement_snaps_ahead() {
+        let position = Vec3::new(3.2, 1.0, -4.1);
+        assert_eq!(
+            snap_to_grid(position, Vec3::new(1.0, 0.0, -1.0)),
+            IVec3::new(4, 1, -5)
+        );
+        assert_eq!(snap_to_grid(Vec3::splat(2.0), Vec3::ONE), IVec3::splat(2));
+    }
+
+    #[test]
+    fn gliding_stops_at_blocks() {
+        let wall = |voxel: IVec3| voxel.x == 2;
+        let moved = glide(Vec3::new(1.3, 0.0, 0.0), Vec3::new(0.5, 0.0, 0.25), wall);
+        assert_eq!(moved, Vec3::new(1.3, 0.0, 0.25));
+        let free = glide(Vec3::ZERO, Vec3::new(0.75, 0.0, 0.0), |_| false);
+        assert_eq!(free, Vec3::new(0.75, 0.0, 0.0));
+        // A slow frame's long step ends in front of the wall, not where it started
+        let long_step = glide(Vec3::new(-1.0, 0.0, 0.0), Vec3::new(3.5, 0.0, 0.25), wall);
+        assert_eq!(long_step, Vec3::new(1.0, 0.0, 0.25));
+    }
+
+    #[test]
+    fn first_person_movement_turns_with_the_camera() {
+        let forward = IVec3::NEG_Z;
+        assert_eq!(facing_movement(forward, 0.0), forward);
+        // Looking left, towards -X
+        assert_eq!(
+            facing_movement(forward, std::f32::consts::FRAC_PI_2),
+            IVec3::NEG_X
+        );
+        assert_eq!(facing_movement(forward, std::f32::consts::PI), IVec3::Z);
+        assert_eq!(facing_movement(IVec3::Y, 1.0), IVec3::Y);
+    }
+
+    #[test]
+    fn indicator_steps_onto_blocks_and_stops_at_walls() {
+        let block = IVec3::new(1, 0, 0);
+        let single = |voxel: IVec3| voxel == block;
+        assert_eq!(
+            collide_movement(IVec3::ZERO, IVec3::X, single),
+            IVec3::new(1, 1, 0)
+        );
+        assert_eq!(
+            collide_movement(IVec3::ZERO, IVec3::NEG_X, single),
+            IVec3::NEG_X
+        );
+        let wall = |voxel: IVec3| voxel.x == 1;
+        assert_eq!(collide_movement(IVec3::ZERO, IVec3::X, wall), IVec3::ZERO);
+        // Blocked along one axis, still free along the other
+        assert_eq!(
+            collide_movement(IVec3::ZERO, IVec3::new(1, 0, -1), wall),
+            IVec3::NEG_Z
+        );
+        let floor = |voxel: IVec3| voxel.y == -1;
+        assert_eq!(
+            collide_movement(IVec3::ZERO, IVec3::NEG_Y, floor),
+            IVec3::ZERO
+        );
+    }
+}
@@ -0,0 +1,121 @@
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use crossbeam_channel::{bounded, Receiver, Sender, TrySendError};
+
+// Every channel between the relay tasks, the miners and the ECS is bounded, so a flooding relay
+// or a fast miner waits for the frame loop instead of growing memory
+pub const INCOMING_NOTES_CAPACITY: usize = 4096;
+// Notes from the relays waiting for a verifier thread
+pub const UNVERIFIED_NOTES_CAPACITY: usize = 4096;
+pub const OUTGOING_NOTES_CAPACITY: usize = 256;
+pub const MINED_NOTES_CAPACITY: usize = 256;
+pub const RELAY_REPLIES_CAPACITY: usize = 1024;
+// Only shown in the traffic panel, which keeps fewer than this anyway
+pub const TRAFFIC_CAPACITY: usize = 512;
+// How long a task waits before trying a full channel again
+const FULL_CHANNEL_RETRY: Duration = Duration::from_millis(5);
+
+// Sends from a task without holding up the runtime's thread. A relay session waiting here stops
+// reading its socket, which slows the relay down rather than queueing its notes.
+// Gives the value back once the receiving end is gone
+pub async fn send_when_ready<T>(sender: &Sender<T>, mut value: T) -> Result<(), T> {
+    loop {
+        match sender.try_send(value) {
+            Ok(()) => return Ok(()),
+            Err(TrySendError::Disconnected(value)) => return Err(value),
+            Err(TrySendError::Full(returned)) => {
+                value = returned;
+                tokio::time::sleep(FULL_CHANNEL_RETRY).await;
+            }
+        }
+    }
+}
+
+// For updates that are only displayed: a full channel loses its oldest entry, never the newest
+pub struct DropOldestSender<T> {
+    sender: Sender<T>,
+    // Our own handle on the receiving end, to take the oldest entry out
+    oldest: Receiver<T>,
+    dropped: Arc<AtomicU64>,
+}
+
+// Derived Clone would need `T: Clone`
+impl<T> Clone for DropOldestSender<T> {
+    fn clone(&self) -> Self {
+        DropOldestSender {
+            sender: self.sender.clone(),
+            oldest: self.oldest.clone(),
+            dropped: self.dropped.clone(),
+        }
+    }
+}
+
+impl<T> DropOldestSender<T> {
+    pub fn send(&self, mut value: T) {
+        loop {
+            match self.sender.try_send(value) {
+                Ok(()) | Err(TrySendError::Disconnected(_)) => return,
+                Err(TrySendError::Full(returned)) => {
+                    if self.oldest.try_recv().is_ok() {
+                        self.dropped.fetch_add(1, Ordering::Relaxed);
+                    }
+                    value = returned;
+                }
+            }
+        }
+    }
+}
+
+// The receiving end of a drop-oldest channel, with the count of entries it lost
+pub struct DropOldestReceiver<T> {
+    pub receiver: Receiver<T>,
+    pub dropped: Arc<AtomicU64>,
+}
+
+pub fn drop_oldest<T>(capacity: usize) -> (DropOldestSender<T>, DropOldestReceiver<T>) {
+    let (sender, receiver) = bounded(capacity);
+    let dropped = Arc::new(AtomicU64::new(0));
+    (
+        DropOldestSender {
+            sender,
+            oldest: receiver.clone(),
+            dropped: dropped.clone(),
+        },
+        DropOldestReceiver { receiver, dropped },
+    )
+}
+
+// "12/256", for the diagnostics overlay
+pub fn queue_depth(len: usize, capacity: Option<usize>) -> String {
+    match capacity {
+        Some(capacity) => format!("{}/{}", len, capacity),
+        None => len.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn full_display_channels_drop_their_oldest_entry() {
+        let (sender, receiver) = drop_oldest(2);
+        for entry in 1..=5 {
+            sender.send(entry);
+        }
+        assert_eq!(receiver.receiver.try_iter().collect::<Vec<_>>(), vec![4, 5]);
+        assert_eq!(receiver.dropped.load(Ordering::Relaxed), 3);
+    }
+
+    #[test]
+    fn depths_show_the_capacity_of_bounded_queues() {
+        assert_eq!(queue_depth(12, Some(256)), "12/256");
+        assert_eq!(queue_depth(3, None), "3");
+    }
+}
@@ -0,0 +1,320 @@
+use std::collections::VecDeque;
+
+use bevy::{
+    input::{
+        keyboard::{Key, KeyboardInput},
+        ButtonState,
+    },
+    prelude::*,
+};
+use nostro2::notes::Note;
+
+use crate::{
+    cameras::BlockIndicator,
+    cyberspace::Sector,
+    keybindings::{key_name, Action, KeyBindings, Typing},
+    nostr::OutgoingNotes,
+    origin::FloatingOrigin,
+    protocol::{sector_chat_tag, CHAT_KIND},
+    resources::Profiles,
+    ui_camera::{
+        edit_text_field, text_bundle_builder, TextInput, BORDER_WIDTH, FLEX_GAP, LIGHT_GRAY,
+        MARGIN_UI, NORMAL_FONT, PADDING_UI,
+    },
+    UserNostrKeys,
+};
+
+// Kept per sector, older messages are dropped
+const MAX_CHAT_MESSAGES: usize = 50;
+// Only the latest few fit in the panel
+const SHOWN_CHAT_MESSAGES: usize = 8;
+const MAX_CHAT_CHARS: usize = 280;
+
+pub fn chat_plugin(app: &mut App) {
+    app.init_resource::<ChatSector>()
+        .init_resource::<ChatLog>()
+        .init_resource::<ChatInput>()
+        .add_event::<ChatMessageReceived>()
+        .add_systems(PostStartup, setup_chat_panel)
+        .add_systems(PreUpdate, type_chat_message.in_set(TextInput))
+        .add_systems(
+            Update,
+            (
+                follow_indicator_sector,
+                receive_chat_messages,
+                update_chat_panel,
+            )
+                .chain(),
+        );
+}
+
+// Sector the indicator is in, chat is only sent to and read from this one
+#[derive(Resource, Default, Deref, DerefMut)]
+pub struct ChatSector(pub Option<Sector>);
+
+// A sector chat note read from a relay, relays send our own back too
+#[derive(Event, Debug, Clone, PartialEq)]
+pub struct ChatMessageReceived {
+    pub id: String,
+    pub author: String,
+    pub sector: Sector,
+    pub content: String,
+    pub created_at: u64,
+}
+
+// Messages of the current sector, oldest first
+#[derive(Resource, Default, Deref, DerefMut)]
+struct ChatLog(VecDeque<ChatMessageReceived>);
+
+impl ChatLog {
+    // Several relays and the history request deliver the same message, and not in order
+    fn add(&mut self, message: ChatMessageReceived) {
+        if self.iter().any(|logged| logged.id == message.id) {
+            return;
+        }
+        let index = self.partition_point(|logged| logged.created_at <= message.created_at);
+        self.insert(index, message);
+        if self.len() > MAX_CHAT_MESSAGES {
+            self.pop_front();
+        }
+    }
+}
+
+// What has been typed so far, none while not typing
+#[derive(Resource, Default, Deref, DerefMut)]
+struct ChatInput(Option<String>);
+
+#[derive(Component)]
+struct ChatTitle;
+
+#[derive(Component)]
+struct ChatMessages;
+
+#[derive(Component)]
+struct ChatInputText;
+
+fn follow_indicator_sector(
+    block_indicator: Query<&Transform, With<BlockIndicator>>,
+    origin: Res<FloatingOrigin>,
+    mut chat_sector: ResMut<ChatSector>,
+) {
+    let Ok(indicator_transform) = block_indicator.get_single() else {
+        return;
+    };
+    let sector = Some(origin.coordinates(indicator_transform.translation).sector());
+    if **chat_sector != sector {
+        **chat_sector = sector;
+    }
+}
+
+fn type_chat_message(
+    mut keyboard_events: EventReader<KeyboardInput>,
+    key_bindings: Res<KeyBindings>,
+    mut keyboard_input: ResMut<ButtonInput<KeyCode>>,
+    mut chat_input: ResMut<ChatInput>,
+    mut typing: ResMut<Typing>,
+    chat_sector: Res<ChatSector>,
+    user_keys: Res<UserNostrKeys>,
+    outgoing_notes: Res<OutgoingNotes>,
+    mut chat_messages: EventWriter<ChatMessageReceived>,
+) {
+    let Some(typed) = chat_input.as_mut() else {
+        keyboard_events.clear();
+        if !**typing && keyboard_input.just_pressed(key_bindings.key(Action::Chat)) {
+            **chat_input = Some(String::new());
+            **typing = true;
+            keyboard_input.reset_all();
+        }
+        return;
+    };
+    let mut submitted = None;
+    for event in keyboard_events.read() {
+        if event.state != ButtonState::Pressed {
+            continue;
+        }
+        match &event.logical_key {
+            Key::Enter => submitted = Some(true),
+            Key::Escape => submitted = Some(false),
+            key => {
+                edit_text_field(typed, key, MAX_CHAT_CHARS, &keyboard_input);
+            }
+        }
+    }
+    // Keys typed into the chat don't reach the rest of the game
+    keyboard_input.reset_all();
+    let Some(submitted) = submitted else {
+        return;
+    };
+    let content = typed.trim();
+    if let (true, false, Some(sector)) = (submitted, content.is_empty(), **chat_sector) {
+        let mut note = Note::new(user_keys.get_public_key(), CHAT_KIND, content);
+        note.tags
+            .push(vec!["t".to_string(), sector_chat_tag(sector)]);
+        let note = user_keys.get_keypair().sign_nostr_event(note);
+        // Shown right away, the copy relays send back is a duplicate
+        chat_messages.send(ChatMessageReceived {
+            id: note.get_id().to_string(),
+            author: note.get_pubkey().to_string(),
+            sector,
+            content: content.to_string(),
+            created_at: note.get_created_at(),
+        });
+        let _sent = outgoing_notes.send(note);
+    }
+    **chat_input = None;
+    **typing = false;
+}
+
+fn receive_chat_messages(
+    chat_sector: Res<ChatSector>,
+    mut chat_messages: EventReader<ChatMessageReceived>,
+    mut chat_log: ResMut<ChatLog>,
+) {
+    // The new sector's history arrives with its subscription
+    if chat_sector.is_changed() && !chat_log.is_empty() {
+        chat_log.clear();
+    }
+    for message in chat_messages.read() {
+        // Sectors we left are still subscribed to
+        if Some(message.sector) == **chat_sector {
+            chat_log.add(message.clone());
+        }
+    }
+}
+
+fn setup_chat_panel(mut commands: Commands) {
+    let chat_ui = NodeBundle {
+        style: Style {
+            position_type: PositionType::Absolute,
+            bottom: Val::Percent(30.0),
+            left: Val::Px(0.0),
+            max_width: Val::Percent(30.0),
+            margin: MARGIN_UI,
+            padding: PADDING_UI,
+            row_gap: FLEX_GAP,
+            flex_direction: FlexDirection::Column,
+            border: BORDER_WIDTH,
+            ..Default::default()
+        },
+        border_color: BorderColor(LIGHT_GRAY),
+        background_color: BackgroundColor(Color::rgba(0.0, 0.0, 0.0, 0.5)),
+        ..Default::default()
+    };
+    commands.spawn(chat_ui).with_children(|chat_ui| {
+        chat_ui.spawn((text_bundle_builder(String::new(), NORMAL_FONT), ChatTitle));
+        chat_ui.spawn((
+            text_bundle_builder(String::new(), NORMAL_FONT),
+            ChatMessages,
+        ));
+        chat_ui.spawn((
+            text_bundle_builder(String::new(), NORMAL_FONT),
+            ChatInputText,
+        ));
+    });
+}
+
+fn update_chat_panel(
+    chat_sector: Res<ChatSector>,
+    chat_log: Res<ChatLog>,
+    chat_input: Res<ChatInput>,
+    key_bindings: Res<KeyBindings>,
+    profiles: Res<Profiles>,
+    mut title_query: Query<&mut Text, (With<ChatTitle>, Without<ChatMessages>)>,
+    mut messages_query: Query<&mut Text, (With<ChatMessages>, Without<ChatInputText>)>,
+    mut input_query: Query<&mut Text, (With<ChatInputText>, Without<ChatTitle>)>,
+) {
+    if chat_sector.is_changed() || key_bindings.is_changed() {
+        let title = match **chat_sector {
+            Some(sector) => format!(
+                "Sector {} {} {} chat, {} to talk",
+                sector.x,
+                sector.y,
+                sector.z,
+                key_name(key_bindings.key(Action::Chat))
+            ),
+            None => String::new(),
+        };
+        for mut text in title_query.iter_mut() {
+            text.sections[0].value = title.clone();
+        }
+    }
+    if chat_log.is_changed() || profiles.is_changed() {
+        let messages = chat_log
+            .iter()
+            .skip(chat_log.len().saturating_sub(SHOWN_CHAT_MESSAGES))
+            .map(|message| {
+                format!(
+                    "{}: {}",
+                    profiles.display_name(&message.author),
+                    message.content
+                )
+            })
+            .collect::<Vec<_>>()
+            .join("\n");
+        for mut text in messages_query.iter_mut() {
+            text.sections[0].value = messages.clone();
+        }
+    }
+    if chat_input.is_changed() {
+        let input = match chat_input.as_ref() {
+            Some(typed) => format!("> {}_", typed),
+            None => String::new(),
+        };
+        for mut text in input_query.iter_mut() {
+            text.sections[0].value = input.clone();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::chat_sector;
+
+    fn message(id: &str, created_at: u64) -> ChatMessageReceived {
+        ChatMessageReceived {
+            id: id.to_string(),
+            author: String::new(),
+            sector: Sector::default(),
+            content: id.to_string(),
+            created_at,
+        }
+    }
+
+    #[test]
+    fn chat_tags_round_trip_the_sector() {
+        let sector = Sector {
+            x: 3,
+            y: 1 << 70,
+            z: 12,
+        };
+        let tags = vec![
+            vec!["p".to_string(), "someone".to_string()],
+            vec!["t".to_string(), sector_chat_tag(sector)],
+        ];
+        assert_eq!(chat_sector(&tags), Some(sector));
+        assert_eq!(
+            chat_sector(&[vec!["t".to_string(), "nostr".to_string()]]),
+            None
+        );
+        assert_eq!(
+            chat_sector(&[vec!["t".to_string(), "nostrcraft-sector-1_2".to_string()]]),
+            None
+        );
+    }
+
+    #[test]
+    fn chat_log_orders_and_deduplicates() {
+        let mut chat_log = ChatLog::default();
+        chat_log.add(message("second", 20));
+        chat_log.add(message("first", 10));
+        chat_log.add(message("second", 20));
+        let ids: Vec<_> = chat_log.iter().map(|message| message.id.as_str()).collect();
+        assert_eq!(ids, ["first", "second"]);
+        for index in 0..MAX_CHAT_MESSAGES {
+            chat_log.add(message(&index.to_string(), 30 + index as u64));
+        }
+        assert_eq!(chat_log.len(), MAX_CHAT_MESSAGES);
+        assert_eq!(chat_log[0].id, "0");
+    }
+}
@@ -0,0 +1,899 @@
+use bevy::{
+    prelude::*,
+    render::{
+        mesh::{Indices, VertexAttributeValues},
+        render_asset::RenderAssetUsages,
+        render_resource::PrimitiveTopology,
+    },
+    utils::{HashMap, HashSet},
+};
+
+use crate::{
+    block_atlas::{place_in_tiles, tier_tile, BlockAtlas},
+    block_effects::SpawnAnimation,
+    cameras::{BlockIndicator, ExplorerCamera},
+    cyberspace::{
+        extract_plane, sector_reach, CoordinateBounds, CyberspaceCoordinates, Plane, Sector,
+        WORLD_SECTOR_SIZE,
+    },
+    keybindings::{Action, KeyBindings},
+    origin::{CyberspacePosition, FloatingOrigin},
+    resources::{
+        block_tier, owner_heat_color, spawn_mined_block, tier_material, CoordinatesMap, FollowList,
+        MeshesAndMaterials, POWBlock, FRIEND_COLOR,
+    },
+    settings::Settings,
+};
+
+// Sectors out to this many sectors from the indicator that aren't loaded are drawn as a glow
+const SECTOR_GLOW_RADIUS: i128 = 16;
+// A sector holding this many bits of POW in total glows at full brightness
+const GLOW_SATURATION_POW: f32 = 4096.0;
+const GLOW_COLOR: Color = Color::rgb_linear(0.4, 0.6, 1.0);
+const GLOW_MAX_EMISSIVE: f32 = 8.0;
+const GLOW_RADIUS: f32 = WORLD_SECTOR_SIZE / 4.0;
+// Faint enough not to get in the way of the blocks
+const SECTOR_GRID_COLOR: Color = Color::rgba(0.4, 0.6, 1.0, 0.12);
+// Layers a slice can be, stepped through in order
+const SLICE_THICKNESSES: [i32; 4] = [1, 2, 4, 8];
+// How far followed miners' blocks are pulled towards the friend color, textures stay readable
+const FRIEND_BLOCK_TINT: f32 = 0.5;
+
+pub fn chunks_plugin(app: &mut App) {
+    app.init_resource::<WorldChunks>()
+        .init_resource::<BlockBatches>()
+        .init_resource::<SectorSummaries>()
+        .init_resource::<SectorGlows>()
+        .init_resource::<OwnershipHeatmap>()
+        .init_resource::<BlockSlice>()
+        .add_systems(
+            Update,
+            (
+                toggle_ownership_heatmap,
+                control_block_slice,
+                switch_plane,
+                update_loaded_chunks,
+                update_sector_glows,
+                rebuild_block_batches,
+                cull_block_batches,
+                draw_sector_grid,
+            )
+                .chain(),
+        );
+}
+
+// Mined blocks grouped by sector. Only sectors near the block indicator have entities,
+// the rest stay cached in the `CoordinatesMap` until we come back.
+// Only blocks in the viewed plane of cyberspace are spawned.
+// Doubles as the spatial index of the map: blocks are bucketed by sector with their decoded
+// coordinates, so lookups by position and radius only touch the sectors they reach
+#[derive(Resource, Default)]
+pub struct WorldChunks {
+    sectors: HashMap<Sector, SectorBlocks>,
+    loaded: HashSet<Sector>,
+    center: Option<Sector>,
+    radius: i32,
+    plane: Plane,
+}
+
+// Hex coordinates of a sector's blocks by plane and position
+type SectorBlocks = HashMap<(Plane, CyberspaceCoordinates), String>;
+
+// Position of a block in the index, none for a malformed coordinate
+fn block_key(coordinates: &str) -> Option<(Plane, CyberspaceCoordinates)> {
+    Some((
+        extract_plane(coordinates).ok()?,
+        CyberspaceCoordinates::from_hex(coordinates).ok()?,
+    ))
+}
+
+impl WorldChunks {
+    pub fn index(&mut self, sector: Sector, coordinates: &str) {
+        let Some(key) = block_key(coordinates) else {
+            return;
+        };
+        self.sectors
+            .entry(sector)
+            .or_default()
+            .insert(key, coordinates.to_string());
+        if self.is_loaded(sector) {
+            self.loaded.insert(sector);
+        }
+    }
+
+    pub fn remove(&mut self, sector: Sector, coordinates: &str) {
+        let Some(key) = block_key(coordinates) else {
+            return;
+        };
+        if let Some(sector_blocks) = self.sectors.get_mut(&sector) {
+            sector_blocks.remove(&key);
+            if sector_blocks.is_empty() {
+                self.sectors.remove(&sector);
+            }
+        }
+    }
+
+    pub fn is_loaded(&self, sector: Sector) -> bool {
+        self.center
+            .is_some_and(|center| within_radius(sector, center, self.radius as i128))
+    }
+
+    pub fn plane(&self) -> Plane {
+        self.plane
+    }
+
+    // Whether a block at these coordinates gets an entity right now
+    pub fn shows(&self, sector: Sector, coordinates: &str) -> bool {
+        self.is_loaded(sector) && self.in_plane(coordinates)
+    }
+
+    fn in_plane(&self, coordinates: &str) -> bool {
+        extract_plane(coordinates).is_ok_and(|plane| plane == self.plane)
+    }
+
+    pub fn loaded_sectors(&self) -> usize {
+        self.loaded.len()
+    }
+
+    // Hex coordinates of the known block at these coordinates in the viewed plane
+    pub fn block_at(&self, coordinates: CyberspaceCoordinates) -> Option<&String> {
+        self.sectors
+            .get(&coordinates.sector())?
+            .get(&(self.plane, coordinates))
+    }
+
+    // Known sectors out to `radius` sectors from `center`. Small cubes are walked sector by
+    // sector, once the cube holds more sectors than we know the known ones are checked instead
+    fn sectors_within(&self, center: Sector, radius: i128) -> Vec<Sector> {
+        let cube_sectors = radius
+            .checked_mul(2)
+            .and_then(|side| side.checked_add(1))
+            .and_then(|side| side.checked_pow(3));
+        match (cube_sectors, i32::try_from(radius)) {
+            (Some(cube_sectors), Ok(radius)) if cube_sectors <= self.sectors.len() as i128 => {
+                (-radius..=radius)
+                    .flat_map(|x| {
+                        (-radius..=radius)
+                            .flat_map(move |y| (-radius..=radius).map(move |z| IVec3::new(x, y, z)))
+                    })
+                    .map(|offset| center.offset(offset))
+                    .filter(|sector| self.sectors.contains_key(sector))
+                    .collect()
+            }
+            _ => self
+                .sectors
+                .keys()
+                .filter(|sector| within_radius(**sector, center, radius))
+                .copied()
+                .collect(),
+        }
+    }
+
+    // Known blocks of the viewed plane within `radius` of `center`, loaded or not
+    pub fn blocks_within(
+        &self,
+        center: CyberspaceCoordinates,
+        radius: i128,
+    ) -> impl Iterator<Item = (CyberspaceCoordinates, &String)> {
+        let bounds = CoordinateBounds::around(center, radius);
+        self.sectors_within(center.sector(), sector_reach(radius))
+            .into_iter()
+            .filter(move |sector| bounds.intersects(sector.bounds()))
+            .filter_map(|sector| self.sectors.get(&sector))
+            .flat_map(|sector_blocks| sector_blocks.iter())
+            .filter(|((plane, _), _)| *plane == self.plane)
+            .filter(move |((_, coordinates), _)| {
+                bounds.contains(*coordinates) && coordinates.within_distance(center, radius)
+            })
+            .map(|((_, coordinates), hex)| (*coordinates, hex))
+    }
+
+    // Coordinates of every known block shown in the loaded sectors
+    pub fn loaded_coordinates(&self) -> impl Iterator<Item = &String> {
+        self.loaded
+            .iter()
+            .filter_map(|sector| self.sectors.get(sector))
+            .flat_map(|sector_blocks| self.plane_blocks(sector_blocks))
+    }
+
+    fn plane_blocks<'a>(
+        &self,
+        sector_blocks: &'a SectorBlocks,
+    ) -> impl Iterator<Item = &'a String> {
+        let plane = self.plane;
+        sector_blocks
+            .iter()
+            .filter(move |((block_plane, _), _)| *block_plane == plane)
+            .map(|(_, coordinates)| coordinates)
+    }
+}
+
+#[derive(Clone, Copy, Default, Debug, PartialEq)]
+pub struct SectorSummary {
+    pub blocks: usize,
+    pub total_pow: usize,
+}
+
+// Block count and POW of every sector we know blocks in, loaded or not
+#[derive(Resource, Deref, Default)]
+pub struct SectorSummaries(HashMap<Sector, SectorSummary>);
+
+impl SectorSummaries {
+    pub fn add_block(&mut self, sector: Sector, pow_amount: usize) {
+        let summary = self.0.entry(sector).or_default();
+        summary.blocks += 1;
+        summary.total_pow += pow_amount;
+    }
+
+    pub fn remove_block(&mut self, sector: Sector, pow_amount: usize) {
+        let Some(summary) = self.0.get_mut(&sector) else {
+            return;
+        };
+        summary.blocks = summary.blocks.saturating_sub(1);
+        summary.total_pow = summary.total_pow.saturating_sub(pow_amount);
+        if summary.blocks == 0 {
+            self.0.remove(&sector);
+        }
+    }
+}
+
+// Grows quickly for the first blocks so sparse sectors are still visible
+fn glow_brightness(summary: &SectorSummary) -> f32 {
+    (summary.total_pow as f32 / GLOW_SATURATION_POW)
+        .sqrt()
+        .min(1.0)
+}
+
+fn glow_emissive(summary: &SectorSummary) -> Color {
+    GLOW_COLOR * (glow_brightness(summary) * GLOW_MAX_EMISSIVE)
+}
+
+// Glow entity and its own material, its brightness changes with the sector
+#[derive(Resource, Default, Deref, DerefMut)]
+struct SectorGlows(HashMap<Sector, (Entity, Handle<StandardMaterial>)>);
+
+fn update_sector_glows(
+    mut commands: Commands,
+    stuff: Res<MeshesAndMaterials>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    world_chunks: Res<WorldChunks>,
+    sector_summaries: Res<SectorSummaries>,
+    mut sector_glows: ResMut<SectorGlows>,
+) {
+    if !world_chunks.is_changed() && !sector_summaries.is_changed() {
+        return;
+    }
+    let Some(center) = world_chunks.center else {
+        return;
+    };
+    let wants_glow = |sector: Sector| {
+        !world_chunks.is_loaded(sector) && within_radius(sector, center, SECTOR_GLOW_RADIUS)
+    };
+
+    // Loaded sectors show their blocks instead
+    sector_glows.retain(|sector, (entity, _)| {
+        let keep = sector_summaries.contains_key(sector) && wants_glow(*sector);
+        if !keep {
+            commands.entity(*entity).despawn();
+        }
+        keep
+    });
+
+    for (sector, summary) in sector_summaries.iter() {
+        if !wants_glow(*sector) {
+            continue;
+        }
+        let emissive = glow_emissive(summary);
+        if let Some((_, material)) = sector_glows.get(sector) {
+            // Only touch the material when it changes, every write re-uploads it
+            if materials
+                .get(material)
+                .is_some_and(|material| material.emissive != emissive)
+            {
+                if let Some(material) = materials.get_mut(material) {
+                    material.emissive = emissive;
+                }
+            }
+            continue;
+        }
+
+        let material = materials.add(StandardMaterial {
+            base_color: Color::BLACK,
+            emissive,
+            alpha_mode: AlphaMode::Add,
+            ..Default::default()
+        });
+        let sector_center = sector.bounds().center();
+        let entity = commands
+            .spawn((
+                PbrBundle {
+                    mesh: stuff.pubkey_mesh.clone_weak(),
+                    material: material.clone(),
+                    transform: Transform::from_scale(Vec3::splat(GLOW_RADIUS)),
+                    ..Default::default()
+                },
+                CyberspacePosition(sector_center),
+            ))
+            .id();
+        sector_glows.insert(*sector, (entity, material));
+    }
+}
+
+// Sectors form a cube around the center, `radius` sectors out on every axis
+fn within_radius(sector: Sector, center: Sector, radius: i128) -> bool {
+    sector.distance(center) <= radius
+}
+
+fn update_loaded_chunks(
+    mut commands: Commands,
+    settings: Res<Settings>,
+    block_indicator: Query<&Transform, With<BlockIndicator>>,
+    origin: Res<FloatingOrigin>,
+    mut world_chunks: ResMut<WorldChunks>,
+    mut coordinates_map: ResMut<CoordinatesMap>,
+) {
+    let Ok(indicator_transform) = block_indicator.get_single() else {
+        return;
+    };
+    let center = origin.coordinates(indicator_transform.translation).sector();
+    let radius = settings.sector_load_radius;
+    if world_chunks.center == Some(center) && world_chunks.radius == radius {
+        return;
+    }
+    world_chunks.center = Some(center);
+    world_chunks.radius = radius;
+    let world_chunks = world_chunks.as_mut();
+
+    // Despawn the chunks we moved away from, keeping their blocks in the map
+    let unloaded: Vec<Sector> = world_chunks
+        .loaded
+        .iter()
+        .filter(|sector| !within_radius(**sector, center, radius as i128))
+        .copied()
+        .collect();
+    for sector in unloaded {
+        world_chunks.loaded.remove(&sector);
+        for coordinates in world_chunks
+            .sectors
+            .get(&sector)
+            .into_iter()
+            .flat_map(|blocks| blocks.values())
+        {
+            if let Some((entity, _)) = coordinates_map.get_mut(coordinates) {
+                if let Some(entity) = entity.take() {
+                    commands.entity(entity).despawn();
+                }
+            }
+        }
+    }
+
+    // Spawn the cached blocks of the chunks we came close to
+    for sector in world_chunks.sectors_within(center, radius as i128) {
+        if !world_chunks.loaded.insert(sector) {
+            continue;
+        }
+        let sector_blocks = world_chunks.sectors.get(&sector).into_iter();
+        for coordinates in sector_blocks.flat_map(|blocks| world_chunks.plane_blocks(blocks)) {
+            if let Some((entity, block_details)) = coordinates_map.get_mut(coordinates) {
+                if entity.is_none() {
+                    *entity = Some(spawn_mined_block(&mut commands, block_details));
+                }
+            }
+        }
+    }
+}
+
+// Despawns every block of the plane we leave, the next update spawns the other plane
+fn switch_plane(
+    mut commands: Commands,
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    key_bindings: Res<KeyBindings>,
+    mut world_chunks: ResMut<WorldChunks>,
+    mut coordinates_map: ResMut<CoordinatesMap>,
+) {
+    if !keyboard_input.just_pressed(key_bindings.key(Action::SwitchPlane)) {
+        return;
+    }
+    let world_chunks = world_chunks.as_mut();
+    for sector in world_chunks.loaded.drain() {
+        for coordinates in world_chunks
+            .sectors
+            .get(&sector)
+            .into_iter()
+            .flat_map(|blocks| blocks.values())
+        {
+            if let Some((entity, _)) = coordinates_map.get_mut(coordinates) {
+                if let Some(entity) = entity.take() {
+                    commands.entity(entity).despawn();
+                }
+            }
+        }
+    }
+    world_chunks.center = None;
+    world_chunks.plane = world_chunks.plane.other();
+}
+
+// While slicing only blocks from the bottom layer up to the thickness are drawn and picked,
+// to look inside dense builds
+#[derive(Resource, Default)]
+pub struct BlockSlice {
+    bottom: Option<CyberspaceCoordinates>,
+    thickness_index: usize,
+}
+
+impl BlockSlice {
+    pub fn shows(&self, coordinates: CyberspaceCoordinates) -> bool {
+        let Some(bottom) = self.bottom else {
+            return true;
+        };
+        let [_, layer, _] = coordinates.difference(bottom);
+        (0..self.thickness() as i128).contains(&layer)
+    }
+
+    // Layers shown, for the coordinates panel
+    pub fn describe(&self) -> Option<String> {
+        let bottom = self.bottom?;
+        let top = bottom.offset(IVec3::Y * (self.thickness() - 1));
+        Some(format!("Slice: Y {} to {}", bottom.y, top.y))
+    }
+
+    fn thickness(&self) -> i32 {
+        SLICE_THICKNESSES[self.thickness_index]
+    }
+}
+
+// The slice starts at the indicator's layer, the keys then move it and change its thickness
+fn control_block_slice(
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    key_bindings: Res<KeyBindings>,
+    origin: Res<FloatingOrigin>,
+    block_indicator: Query<&Transform, With<BlockIndicator>>,
+    mut block_slice: ResMut<BlockSlice>,
+) {
+    if keyboard_input.just_pressed(key_bindings.key(Action::ToggleSlice)) {
+        block_slice.bottom = match block_slice.bottom {
+            Some(_) => None,
+            None => block_indicator
+                .get_single()
+                .ok()
+                .map(|indicator_transform| origin.coordinates(indicator_transform.translation)),
+        };
+    }
+    let Some(bottom) = block_slice.bottom else {
+        return;
+    };
+    if keyboard_input.just_pressed(key_bindings.key(Action::SliceUp)) {
+        block_slice.bottom = Some(bottom.offset(IVec3::Y));
+    }
+    if keyboard_input.just_pressed(key_bindings.key(Action::SliceDown)) {
+        block_slice.bottom = Some(bottom.offset(IVec3::NEG_Y));
+    }
+    if keyboard_input.just_pressed(key_bindings.key(Action::CycleSliceThickness)) {
+        block_slice.thickness_index = (block_slice.thickness_index + 1) % SLICE_THICKNESSES.len();
+    }
+}
+
+// Block entities only carry their data, each sector is drawn as one merged mesh per material
+// so the renderer sees a handful of draw calls instead of one per block. Once the block atlas is
+// built, every tier shares one of its two materials
+#[derive(Component)]
+pub struct BlockBatch {
+    sector: Sector,
+}
+
+#[derive(Resource, Default)]
+struct BlockBatches {
+    // Sector of every block entity, so we still know it once the entity is despawned
+    block_sectors: HashMap<Entity, Sector>,
+    batches: HashMap<Sector, Vec<Entity>>,
+}
+
+// Colors blocks by miner instead of material, to see who holds what at a glance
+#[derive(Resource, Default, Deref, DerefMut)]
+pub struct OwnershipHeatmap(pub bool);
+
+fn toggle_ownership_heatmap(
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    key_bindings: Res<KeyBindings>,
+    mut heatmap: ResMut<OwnershipHeatmap>,
+) {
+    if keyboard_input.just_pressed(key_bindings.key(Action::ToggleHeatmap)) {
+        **heatmap = !**heatmap;
+    }
+}
+
+pub fn rebuild_block_batches(
+    mut commands: Commands,
+    stuff: Res<MeshesAndMaterials>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    added_blocks: Query<(Entity, &CyberspacePosition), Added<POWBlock>>,
+    mut removed_blocks: RemovedComponents<POWBlock>,
+    // Blocks growing in are drawn on their own until their animation ends
+    mut finished_animations: RemovedComponents<SpawnAnimation>,
+    block_query: Query<(&CyberspacePosition, &POWBlock), Without<SpawnAnimation>>,
+    heatmap: Res<OwnershipHeatmap>,
+    block_slice: Res<BlockSlice>,
+    follow_list: Res<FollowList>,
+    mut heatmap_materials: Local<HashMap<String, Handle<StandardMaterial>>>,
+    mut friend_materials: Local<HashMap<AssetId<StandardMaterial>, Handle<StandardMaterial>>>,
+    mut block_batches: ResMut<BlockBatches>,
+    block_atlas: Option<Res<BlockAtlas>>,
+) {
+    let mut dirty_sectors = HashSet::new();
+    // Switching modes or following someone recolors or slices every loaded sector, as does the
+    // atlas replacing the tier materials
+    let atlas_built = block_atlas.as_ref().is_some_and(|atlas| atlas.is_added());
+    if heatmap.is_changed() || block_slice.is_changed() || follow_list.is_changed() || atlas_built {
+        dirty_sectors.extend(block_batches.block_sectors.values().copied());
+    }
+    for (entity, position) in added_blocks.iter() {
+        let sector = position.sector();
+        block_batches.block_sectors.insert(entity, sector);
+        dirty_sectors.insert(sector);
+    }
+    for entity in removed_blocks.read() {
+        if let Some(sector) = block_batches.block_sectors.remove(&entity) {
+            dirty_sectors.insert(sector);
+        }
+    }
+    for entity in finished_animations.read() {
+        if let Some(sector) = block_batches.block_sectors.get(&entity) {
+            dirty_sectors.insert(*sector);
+        }
+    }
+    if dirty_sectors.is_empty() {
+        return;
+    }
+    let Some(block_mesh) = meshes.get(&stuff.cube_mesh).cloned() else {
+        return;
+    };
+
+    // Group the blocks of the changed sectors by material, relative to the sector origin, with
+    // each block's atlas tile when the material is an atlas one
+    let mut batch_blocks: HashMap<
+        (Sector, AssetId<StandardMaterial>),
+        (Handle<StandardMaterial>, Vec<Vec3>, Vec<usize>),
+    > = HashMap::new();
+    for (position, block) in block_query.iter() {
+        let sector = position.sector();
+        if !dirty_sectors.contains(&sector) || !block_slice.shows(**position) {
+            continue;
+        }
+        let tier = block_tier(block.pow_amount, block.material);
+        let (base_material, tile) = match block_atlas.as_deref() {
+            Some(atlas) => (atlas.material(tier_tile(tier)), Some(tier_tile(tier))),
+            None => (tier_material(&stuff, tier), None),
+        };
+        let (material, tile) = if **heatmap {
+            let material = heatmap_materials
+                .entry(block.owner_pubkey.clone())
+                .or_insert_with(|| {
+                    let color = owner_heat_color(&block.owner_pubkey);
+                    materials.add(StandardMaterial {
+                        base_color: color,
+                        emissive: color,
+                        ..Default::default()
+                    })
+                })
+                .clone();
+            (material, None)
+        } else if follow_list.contains(&block.owner_pubkey) {
+            let material = friend_materials
+                .entry(base_material.id())
+                .or_insert_with(|| {
+                    let mut material = materials.get(&base_material).cloned().unwrap_or_default();
+                    material.base_color = friend_tint(material.base_color);
+                    materials.add(material)
+                })
+                .clone();
+            (material, tile)
+        } else {
+            (base_material, tile)
+        };
+        let (_, offsets, tiles) = batch_blocks
+            .entry((sector, material.id()))
+            .or_insert_with(|| (material, Vec::new(), Vec::new()));
+        offsets.push(position.relative_to(sector.corner()));
+        tiles.extend(tile);
+    }
+
+    for sector in dirty_sectors.iter() {
+        for batch in block_batches.batches.remove(sector).into_iter().flatten() {
+            commands.entity(batch).despawn();
+        }
+    }
+    for ((sector, _), (material, offsets, tiles)) in batch_blocks {
+        let Some(mut mesh) = batch_mesh(&block_mesh, &offsets) else {
+            continue;
+        };
+        if !tiles.is_empty() {
+            place_in_tiles(&mut mesh, &tiles);
+        }
+        let batch = commands
+            .spawn((
+                PbrBundle {
+                    mesh: meshes.add(mesh),
+                    material,
+                    ..Default::default()
+                },
+                CyberspacePosition(sector.corner()),
+                BlockBatch { sector },
+            ))
+            .id();
+        block_batches.batches.entry(sector).or_default().push(batch);
+    }
+}
+
+// Multiplies with the tier's texture, alpha is kept so see-through tiers stay see-through
+fn friend_tint(base_color: Color) -> Color {
+    let tint = Color::WHITE * (1.0 - FRIEND_BLOCK_TINT) + FRIEND_COLOR * FRIEND_BLOCK_TINT;
+    Color::rgba(
+        base_color.r() * tint.r(),
+        base_color.g() * tint.g(),
+        base_color.b() * tint.b(),
+        base_color.a(),
+    )
+}
+
+// Copies the block mesh once per offset into a single mesh
+pub fn batch_mesh(block_mesh: &Mesh, offsets: &[Vec3]) -> Option<Mesh> {
+    let positions = block_mesh
+        .attribute(Mesh::ATTRIBUTE_POSITION)?
+        .as_float3()?;
+    let normals = block_mesh.attribute(Mesh::ATTRIBUTE_NORMAL)?.as_float3()?;
+    let VertexAttributeValues::Float32x2(uvs) = block_mesh.attribute(Mesh::ATTRIBUTE_UV_0)? else {
+        return None;
+    };
+    let indices: Vec<u32> = block_mesh
+        .indices()?
+        .iter()
+        .map(|index| index as u32)
+        .collect();
+
+    let vertex_count = positions.len() * offsets.len();
+    let mut batch_positions = Vec::with_capacity(vertex_count);
+    let mut batch_normals = Vec::with_capacity(vertex_count);
+    let mut batch_uvs = Vec::with_capacity(vertex_count);
+    let mut batch_indices = Vec::with_capacity(indices.len() * offsets.len());
+    for (copy, offset) in offsets.iter().enumerate() {
+        let first_vertex = (copy * positions.len()) as u32;
+        batch_positions.extend(
+            positions
+                .iter()
+                .map(|position| (Vec3::from_array(*position) + *offset).to_array()),
+        );
+        batch_normals.extend_from_slice(normals);
+        batch_uvs.extend_from_slice(uvs);
+        batch_indices.extend(indices.iter().map(|index| index + first_vertex));
+    }
+
+    // Kept in the main world too, bounds for frustum culling are computed from it
+    let mesh = Mesh::new(
+        PrimitiveTopology::TriangleList,
+        RenderAssetUsages::default(),
+    )
+    .with_inserted_attribute(Mesh::ATTRIBUTE_POSITION, batch_positions)
+    .with_inserted_attribute(Mesh::ATTRIBUTE_NORMAL, batch_normals)
+    .with_inserted_attribute(Mesh::ATTRIBUTE_UV_0, batch_uvs)
+    .with_inserted_indices(Indices::U32(batch_indices));
+    Some(mesh)
+}
+
+// Hides sector batches that are too far from the camera, Bevy's own frustum culling
+// takes care of the ones out of view
+fn cull_block_batches(
+    settings: Res<Settings>,
+    origin: Res<FloatingOrigin>,
+    camera_query: Query<&GlobalTransform, With<ExplorerCamera>>,
+    mut batch_query: Query<(&BlockBatch, &mut Visibility)>,
+) {
+    let Ok(camera_transform) = camera_query.get_single() else {
+        return;
+    };
+    let camera_position = camera_transform.translation();
+
+    for (batch, mut visibility) in batch_query.iter_mut() {
+        // Distance to the closest point of the sector
+        let (sector_min, sector_max) = origin.sector_bounds(batch.sector);
+        let closest = camera_position.clamp(sector_min, sector_max);
+        let wanted = if block_in_view(closest, camera_position, settings.block_view_distance) {
+            Visibility::Inherited
+        } else {
+            Visibility::Hidden
+        };
+        // Only write on change so the renderer isn't told everything moved every frame
+        if *visibility != wanted {
+            *visibility = wanted;
+        }
+    }
+}
+
+// Outlines the indicator's sector and the ones around it, so sector edges can be told apart
+fn draw_sector_grid(
+    world_chunks: Res<WorldChunks>,
+    origin: Res<FloatingOrigin>,
+    mut gizmos: Gizmos,
+) {
+    let Some(center) = world_chunks.center else {
+        return;
+    };
+    for sector in std::iter::once(center).chain(center.neighbors()) {
+        let (min, max) = origin.sector_bounds(sector);
+        gizmos.cuboid(
+            Transform::from_translation((min + max) / 2.0).with_scale(max - min),
+            SECTOR_GRID_COLOR,
+        );
+    }
+}
+
+fn block_in_view(block: Vec3, camera: Vec3, view_distance: f32) -> bool {
+    block.distance_squared(camera) <= view_distance * view_distance
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cyberspace::SECTOR_SIZE;
+
+    #[test]
+    fn sectors_load_within_a_cube_radius() {
+        let center = Sector { x: 5, y: 5, z: 5 };
+        assert!(within_radius(Sector { x: 7, y: 3, z: 7 }, center, 2));
+        assert!(!within_radius(Sector { x: 8, y: 5, z: 5 }, center, 2));
+    }
+
+    #[test]
+    fn blocks_past_the_view_distance_are_hidden() {
+        assert!(block_in_view(Vec3::new(3.0, 4.0, 0.0), Vec3::ZERO, 5.0));
+        assert!(!block_in_view(Vec3::new(3.0, 4.1, 0.0), Vec3::ZERO, 5.0));
+    }
+
+    #[test]
+    fn batch_mesh_copies_the_block_at_each_offset() {
+        let block_mesh = Mesh::from(Cuboid::default());
+        let block_vertices = block_mesh.count_vertices();
+        let block_indices = block_mesh.indices().unwrap().len();
+
+        let offsets = [Vec3::ZERO, Vec3::new(2.0, 0.0, 0.0)];
+        let batch = batch_mesh(&block_mesh, &offsets).unwrap();
+        assert_eq!(batch.count_vertices(), block_vertices * 2);
+        assert_eq!(batch.indices().unwrap().len(), block_indices * 2);
+
+        // The second copy is shifted and indexes its own vertices
+        let positions = batch
+            .attribute(Mesh::ATTRIBUTE_POSITION)
+            .unwrap()
+            .as_float3()
+            .unwrap();
+        assert_eq!(
+            Vec3::from_array(positions[block_vertices]),
+            Vec3::from_array(positions[0]) + offsets[1]
+        );
+        assert!(batch
+            .indices()
+            .unwrap()
+            .iter()
+            .skip(block_indices)
+            .all(|index| index >= block_vertices));
+    }
+
+    #[test]
+    fn sector_summaries_forget_empty_sectors() {
+        let mut sector_summaries = SectorSummaries::default();
+        sector_summaries.add_block(Sector { x: 1, y: 1, z: 1 }, 12);
+        sector_summaries.add_block(Sector { x: 1, y: 1, z: 1 }, 20);
+        assert_eq!(
+            sector_summaries.get(&Sector { x: 1, y: 1, z: 1 }),
+            Some(&SectorSummary {
+                blocks: 2,
+                total_pow: 32
+            })
+        );
+        sector_summaries.remove_block(Sector { x: 1, y: 1, z: 1 }, 12);
+        sector_summaries.remove_block(Sector { x: 1, y: 1, z: 1 }, 20);
+        assert!(sector_summaries.is_empty());
+    }
+
+    #[test]
+    fn glow_brightens_with_pow_until_saturated() {
+        let sparse = SectorSummary {
+            blocks: 1,
+            total_pow: 16,
+        };
+        let dense = SectorSummary {
+            blocks: 100,
+            total_pow: 1600,
+        };
+        assert!(glow_brightness(&sparse) < glow_brightness(&dense));
+        let saturated = SectorSummary {
+            blocks: 1000,
+            total_pow: 100_000,
+        };
+        assert_eq!(glow_brightness(&saturated), 1.0);
+    }
+
+    #[test]
+    fn nothing_is_loaded_before_the_first_update() {
+        let mut world_chunks = WorldChunks::default();
+        world_chunks.index(Sector::default(), "00");
+        assert!(!world_chunks.is_loaded(Sector::default()));
+        assert_eq!(world_chunks.loaded_sectors(), 0);
+    }
+
+    #[test]
+    fn only_the_viewed_plane_is_shown() {
+        let mut world_chunks = WorldChunks {
+            center: Some(Sector::default()),
+            radius: 1,
+            ..Default::default()
+        };
+        let i_space = crate::cyberspace::encode_plane_coordinates(1, 2, 3, Plane::ISpace);
+        let d_space = crate::cyberspace::encode_plane_coordinates(1, 2, 3, Plane::DSpace);
+        assert!(world_chunks.shows(Sector::default(), &i_space));
+        assert!(!world_chunks.shows(Sector::default(), &d_space));
+        world_chunks.plane = Plane::DSpace;
+        assert!(world_chunks.shows(Sector::default(), &d_space));
+    }
+
+    #[test]
+    fn blocks_within_a_radius_include_unloaded_sectors() {
+        let mut world_chunks = WorldChunks::default();
+        let center = CyberspaceCoordinates::new(0, 0, 0);
+        for offset in [
+            IVec3::new(-3, 0, 4),
+            IVec3::new(40, 0, 0),
+            IVec3::new(4, 4, 4),
+        ] {
+            let coordinates = center.offset(offset);
+            world_chunks.index(coordinates.sector(), &coordinates.to_hex(Plane::ISpace));
+        }
+        let d_space = center.offset(IVec3::X);
+        world_chunks.index(d_space.sector(), &d_space.to_hex(Plane::DSpace));
+        let nearby: Vec<_> = world_chunks
+            .blocks_within(center, 5)
+            .map(|(c, _)| c)
+            .collect();
+        assert_eq!(nearby, vec![center.offset(IVec3::new(-3, 0, 4))]);
+        assert_eq!(world_chunks.blocks_within(center, 40).count(), 3);
+    }
+
+    #[test]
+    fn blocks_are_found_by_position_in_the_viewed_plane() {
+        let mut world_chunks = WorldChunks::default();
+        let center = CyberspaceCoordinates::new(0, 0, 0);
+        // More sectors than the cube around the center, so only that cube is walked
+        for sector in 0..30 {
+            let coordinates = center.offset(IVec3::new(sector * SECTOR_SIZE as i32, 2, 0));
+            world_chunks.index(coordinates.sector(), &coordinates.to_hex(Plane::ISpace));
+        }
+        let below = center.offset(IVec3::NEG_Y);
+        world_chunks.index(below.sector(), &below.to_hex(Plane::DSpace));
+        assert!(world_chunks.block_at(center.offset(IVec3::Y * 2)).is_some());
+        assert!(world_chunks.block_at(below).is_none());
+        assert_eq!(world_chunks.blocks_within(center, 5).count(), 1);
+        assert_eq!(
+            world_chunks.blocks_within(center, SECTOR_SIZE + 1).count(),
+            2
+        );
+
+        world_chunks.remove(below.sector(), &below.to_hex(Plane::DSpace));
+        world_chunks.plane = Plane::DSpace;
+        assert!(world_chunks.block_at(below).is_none());
+    }
+
+    #[test]
+    fn slices_show_their_layers_only() {
+        let mut block_slice = BlockSlice::default();
+        let bottom = CyberspaceCoordinates::new(5, 0, 5);
+        let above = bottom.offset(IVec3::new(3, 1, -2));
+        assert!(block_slice.shows(above));
+        block_slice.bottom = Some(bottom);
+        assert!(block_slice.shows(bottom.offset(IVec3::X)));
+        assert!(!block_slice.shows(above));
+        block_slice.thickness_index = 1;
+        assert!(block_slice.shows(above));
+        // Across the edge of cyberspace
+        assert!(!block_slice.shows(bottom.offset(IVec3::NEG_Y)));
+    }
+}
@@ -0,0 +1,88 @@
+use std::time::{Duration, SystemTime};
+
+use bevy::prelude::*;
+
+// Every tunable lives in this file, the settings at the top and the key bindings in their
+// own table. Edits made while the game runs are picked up without a restart
+pub const CONFIG_FILE_PATH: &str = "./nostrcraft.toml";
+// How often the file's modification time is checked
+const CONFIG_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+pub fn config_plugin(app: &mut App) {
+    app.init_resource::<ConfigWatcher>()
+        .add_event::<ConfigReloaded>()
+        .add_systems(Update, watch_config_file);
+}
+
+// The whole file, sent every time it changes on disk. Our own saves come back as well,
+// readers only apply what differs from what they have
+#[derive(Event, Debug, Clone)]
+pub struct ConfigReloaded(pub toml::Table);
+
+#[derive(Resource)]
+struct ConfigWatcher {
+    timer: Timer,
+    modified: Option<SystemTime>,
+}
+
+impl Default for ConfigWatcher {
+    fn default() -> Self {
+        ConfigWatcher {
+            timer: Timer::new(CONFIG_POLL_INTERVAL, TimerMode::Repeating),
+            modified: config_modified(),
+        }
+    }
+}
+
+fn config_modified() -> Option<SystemTime> {
+    std::fs::metadata(CONFIG_FILE_PATH)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+}
+
+// None when there is no file or it doesn't parse, the game keeps what it has then
+pub fn read_config() -> Option<toml::Table> {
+    let config = std::fs::read_to_string(CONFIG_FILE_PATH).ok()?;
+    match config.parse::<toml::Table>() {
+        Ok(config) => Some(config),
+        Err(error) => {
+            warn!("Could not read {}: {}", CONFIG_FILE_PATH, error);
+            None
+        }
+    }
+}
+
+// Changes some keys and writes the file back, keys owned by other resources are kept
+pub fn write_config(update: impl FnOnce(&mut toml::Table)) {
+    let mut config = read_config().unwrap_or_default();
+    update(&mut config);
+    let config = match toml::to_string_pretty(&config) {
+        Ok(config) => config,
+        Err(error) => {
+            warn!("Could not serialize {}: {}", CONFIG_FILE_PATH, error);
+            return;
+        }
+    };
+    if let Err(error) = std::fs::write(CONFIG_FILE_PATH, config) {
+        warn!("Could not write {}: {}", CONFIG_FILE_PATH, error);
+    }
+}
+
+fn watch_config_file(
+    time: Res<Time>,
+    mut watcher: ResMut<ConfigWatcher>,
+    mut reloaded: EventWriter<ConfigReloaded>,
+) {
+    if !watcher.timer.tick(time.delta()).just_finished() {
+        return;
+    }
+    let modified = config_modified();
+    if modified == watcher.modified {
+        return;
+    }
+    watcher.modified = modified;
+    if let Some(config) = read_config() {
+        info!("Reloading {}", CONFIG_FILE_PATH);
+        reloaded.send(ConfigReloaded(config));
+    }
+}
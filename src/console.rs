@@ -0,0 +1,330 @@
+use std::collections::{BTreeMap, VecDeque};
+
+use bevy::{
+    input::{
+        keyboard::{Key, KeyboardInput},
+        ButtonState,
+    },
+    prelude::*,
+};
+
+use crate::{
+    keybindings::{Action, KeyBindings, Typing},
+    ui_camera::{
+        edit_text_field, text_bundle_builder, TextInput, BORDER_WIDTH, FLEX_GAP, LIGHT_GRAY,
+        NORMAL_FONT, PADDING_UI,
+    },
+};
+
+// Older lines are dropped
+const MAX_CONSOLE_LINES: usize = 100;
+// Only the latest few fit in the console
+const SHOWN_CONSOLE_LINES: usize = 16;
+const MAX_CONSOLE_CHARS: usize = 160;
+
+pub fn console_plugin(app: &mut App) {
+    app.init_resource::<ConsoleLog>()
+        .init_resource::<ConsoleInput>()
+        .add_event::<ConsoleOutput>()
+        .register_console_command("help", "/help", "Lists the commands")
+        .register_console_command("clear", "/clear", "Empties the console")
+        .add_systems(PostStartup, setup_console)
+        .add_systems(PreUpdate, type_console_command.in_set(TextInput))
+        .add_systems(
+            Update,
+            (run_builtin_commands, collect_console_output, update_console).chain(),
+        );
+}
+
+// A command typed into the console, handled by the plugin that registered its name
+#[derive(Event, Debug, Clone, PartialEq)]
+pub struct ConsoleCommand {
+    pub name: String,
+    pub args: Vec<String>,
+}
+
+// A line printed to the console, how command handlers reply
+#[derive(Event, Debug, Clone, PartialEq)]
+pub struct ConsoleOutput(pub String);
+
+pub struct CommandInfo {
+    usage: &'static str,
+    description: &'static str,
+}
+
+// Every known command by name, filled by the plugins through `register_console_command`
+#[derive(Resource, Default, Deref, DerefMut)]
+pub struct ConsoleCommands(BTreeMap<&'static str, CommandInfo>);
+
+pub trait RegisterConsoleCommand {
+    fn register_console_command(
+        &mut self,
+        name: &'static str,
+        usage: &'static str,
+        description: &'static str,
+    ) -> &mut Self;
+}
+
+impl RegisterConsoleCommand for App {
+    // Works whether or not the console plugin was added yet
+    fn register_console_command(
+        &mut self,
+        name: &'static str,
+        usage: &'static str,
+        description: &'static str,
+    ) -> &mut Self {
+        self.add_event::<ConsoleCommand>();
+        self.world
+            .get_resource_or_insert_with(ConsoleCommands::default)
+            .insert(name, CommandInfo { usage, description });
+        self
+    }
+}
+
+// Name and arguments of a typed line, the leading slash is optional
+fn parse_command(line: &str) -> Option<ConsoleCommand> {
+    let mut words = line.trim().trim_start_matches('/').split_whitespace();
+    let name = words.next()?.to_lowercase();
+    Some(ConsoleCommand {
+        name,
+        args: words.map(|word| word.to_string()).collect(),
+    })
+}
+
+// Printed lines, oldest first
+#[derive(Resource, Default, Deref, DerefMut)]
+struct ConsoleLog(VecDeque<String>);
+
+impl ConsoleLog {
+    fn print(&mut self, line: String) {
+        self.push_back(line);
+        if self.len() > MAX_CONSOLE_LINES {
+            self.pop_front();
+        }
+    }
+}
+
+// What has been typed so far, none while the console is closed
+#[derive(Resource, Default)]
+struct ConsoleInput {
+    typed: Option<String>,
+    // Submitted lines, Up and Down walk back through them
+    history: Vec<String>,
+    recalled: Option<usize>,
+}
+
+#[derive(Component)]
+struct ConsolePanel;
+
+#[derive(Component)]
+struct ConsoleLines;
+
+#[derive(Component)]
+struct ConsoleInputText;
+
+fn type_console_command(
+    mut keyboard_events: EventReader<KeyboardInput>,
+    key_bindings: Res<KeyBindings>,
+    mut keyboard_input: ResMut<ButtonInput<KeyCode>>,
+    mut typing: ResMut<Typing>,
+    registry: Res<ConsoleCommands>,
+    mut console_input: ResMut<ConsoleInput>,
+    mut console_log: ResMut<ConsoleLog>,
+    mut console_commands: EventWriter<ConsoleCommand>,
+) {
+    let toggle_key = key_bindings.key(Action::ToggleConsole);
+    if console_input.typed.is_none() {
+        keyboard_events.clear();
+        if !**typing && keyboard_input.just_pressed(toggle_key) {
+            console_input.typed = Some(String::new());
+            **typing = true;
+            keyboard_input.reset_all();
+        }
+        return;
+    }
+    let ConsoleInput {
+        typed: Some(typed),
+        history,
+        recalled,
+    } = console_input.as_mut()
+    else {
+        return;
+    };
+    let mut closed = false;
+    for event in keyboard_events.read() {
+        if event.state != ButtonState::Pressed {
+            continue;
+        }
+        if event.key_code == toggle_key {
+            closed = true;
+            continue;
+        }
+        match &event.logical_key {
+            Key::Enter => {
+                let line = typed.trim().to_string();
+                typed.clear();
+                *recalled = None;
+                let Some(command) = parse_command(&line) else {
+                    continue;
+                };
+                console_log.print(format!("> {}", line));
+                history.push(line);
+                if registry.contains_key(command.name.as_str()) {
+                    console_commands.send(command);
+                } else {
+                    console_log.print(format!(
+                        "Unknown command {}, /help lists them",
+                        command.name
+                    ));
+                }
+            }
+            Key::ArrowUp | Key::ArrowDown if !history.is_empty() => {
+                let index = match (&event.logical_key, *recalled) {
+                    (Key::ArrowUp, None) => history.len() - 1,
+                    (Key::ArrowUp, Some(index)) => index.saturating_sub(1),
+                    (_, Some(index)) if index + 1 < history.len() => index + 1,
+                    _ => continue,
+                };
+                *recalled = Some(index);
+                *typed = history[index].clone();
+            }
+            Key::Escape => closed = true,
+            key => {
+                edit_text_field(typed, key, MAX_CONSOLE_CHARS, &keyboard_input);
+            }
+        }
+    }
+    // Keys typed into the console don't reach the rest of the game
+    keyboard_input.reset_all();
+    if closed {
+        console_input.typed = None;
+        console_input.recalled = None;
+        **typing = false;
+    }
+}
+
+fn run_builtin_commands(
+    registry: Res<ConsoleCommands>,
+    mut console_commands: EventReader<ConsoleCommand>,
+    mut console_log: ResMut<ConsoleLog>,
+) {
+    for command in console_commands.read() {
+        match command.name.as_str() {
+            "help" => {
+                for info in registry.values() {
+                    console_log.print(format!("{}  {}", info.usage, info.description));
+                }
+            }
+            "clear" => console_log.clear(),
+            _ => {}
+        }
+    }
+}
+
+fn collect_console_output(
+    mut console_output: EventReader<ConsoleOutput>,
+    mut console_log: ResMut<ConsoleLog>,
+) {
+    for output in console_output.read() {
+        console_log.print(output.0.clone());
+    }
+}
+
+fn setup_console(mut commands: Commands) {
+    let console_ui = NodeBundle {
+        style: Style {
+            position_type: PositionType::Absolute,
+            top: Val::Px(0.0),
+            left: Val::Px(0.0),
+            width: Val::Percent(100.0),
+            min_height: Val::Percent(40.0),
+            padding: PADDING_UI,
+            row_gap: FLEX_GAP,
+            flex_direction: FlexDirection::Column,
+            justify_content: JustifyContent::FlexEnd,
+            border: BORDER_WIDTH,
+            ..Default::default()
+        },
+        border_color: BorderColor(LIGHT_GRAY),
+        background_color: BackgroundColor(Color::rgba(0.0, 0.0, 0.0, 0.85)),
+        visibility: Visibility::Hidden,
+        // Drawn over the other panels
+        z_index: ZIndex::Global(10),
+        ..Default::default()
+    };
+    commands
+        .spawn((console_ui, ConsolePanel))
+        .with_children(|console_ui| {
+            console_ui.spawn((
+                text_bundle_builder(String::new(), NORMAL_FONT),
+                ConsoleLines,
+            ));
+            console_ui.spawn((
+                text_bundle_builder(String::new(), NORMAL_FONT),
+                ConsoleInputText,
+            ));
+        });
+}
+
+fn update_console(
+    console_input: Res<ConsoleInput>,
+    console_log: Res<ConsoleLog>,
+    mut panel_query: Query<&mut Visibility, With<ConsolePanel>>,
+    mut lines_query: Query<&mut Text, (With<ConsoleLines>, Without<ConsoleInputText>)>,
+    mut input_query: Query<&mut Text, (With<ConsoleInputText>, Without<ConsoleLines>)>,
+) {
+    if console_input.is_changed() {
+        for mut visibility in panel_query.iter_mut() {
+            *visibility = match console_input.typed {
+                Some(_) => Visibility::Visible,
+                None => Visibility::Hidden,
+            };
+        }
+        let input = format!("> {}_", console_input.typed.as_deref().unwrap_or_default());
+        for mut text in input_query.iter_mut() {
+            text.sections[0].value = input.clone();
+        }
+    }
+    if console_log.is_changed() {
+        let lines = console_log
+            .iter()
+            .skip(console_log.len().saturating_sub(SHOWN_CONSOLE_LINES))
+            .cloned()
+            .collect::<Vec<_>>()
+            .join("\n");
+        for mut text in lines_query.iter_mut() {
+            text.sections[0].value = lines.clone();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn commands_split_into_name_and_arguments() {
+        assert_eq!(
+            parse_command("  /TP 12 -3 40 "),
+            Some(ConsoleCommand {
+                name: "tp".to_string(),
+                args: vec!["12".to_string(), "-3".to_string(), "40".to_string()],
+            })
+        );
+        assert_eq!(
+            parse_command("help").map(|command| command.name),
+            Some("help".to_string())
+        );
+        assert_eq!(parse_command(" / "), None);
+    }
+
+    #[test]
+    fn log_drops_the_oldest_lines() {
+        let mut console_log = ConsoleLog::default();
+        for index in 0..MAX_CONSOLE_LINES + 1 {
+            console_log.print(index.to_string());
+        }
+        assert_eq!(console_log.len(), MAX_CONSOLE_LINES);
+        assert_eq!(console_log[0], "1");
+    }
+}
@@ -0,0 +1,229 @@
+use bevy::{prelude::*, utils::HashMap};
+
+use crate::{
+    chunks::WorldChunks, cyberspace::CyberspaceCoordinates, origin::CyberspacePosition,
+    protocol::ConstructShape,
+};
+
+// A construct with this much POW or more is drawn at the largest size, shapes are capped there too
+const MAX_CONSTRUCT_SIDE: f32 = 32.0;
+// Shapes smaller than this would be lost between the blocks
+const MIN_CONSTRUCT_SIDE: f32 = 0.25;
+const CONSTRUCT_COLOR: Color = Color::rgba(0.8, 0.3, 1.0, 0.3);
+// Constructs kept once this many are known, the ones with the least POW make room
+const MAX_CONSTRUCTS: usize = 4096;
+
+pub fn constructs_plugin(app: &mut App) {
+    app.init_resource::<Constructs>()
+        .add_event::<ConstructReceived>()
+        .add_systems(Startup, setup_construct_assets)
+        .add_systems(Update, (receive_constructs, show_constructs).chain());
+}
+
+// A kind 331 note from another cyberspace client, placed by its `C` tag
+#[derive(Event, Debug, Clone, PartialEq)]
+pub struct ConstructReceived {
+    pub id: String,
+    pub coordinates: String,
+    pub pow_amount: usize,
+    pub shape: Option<ConstructShape>,
+}
+
+struct Construct {
+    details: ConstructReceived,
+    entity: Option<Entity>,
+}
+
+// Every construct read from a relay by note id, only the ones in loaded sectors have entities
+#[derive(Resource, Default, Deref, DerefMut)]
+struct Constructs(HashMap<String, Construct>);
+
+#[derive(Resource)]
+struct ConstructAssets {
+    material: Handle<StandardMaterial>,
+    // Unit sized, scaled to each construct
+    cube: Handle<Mesh>,
+    sphere: Handle<Mesh>,
+}
+
+fn setup_construct_assets(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+    let material = materials.add(StandardMaterial {
+        base_color: CONSTRUCT_COLOR,
+        alpha_mode: AlphaMode::Blend,
+        unlit: true,
+        ..Default::default()
+    });
+    commands.insert_resource(ConstructAssets {
+        material,
+        cube: meshes.add(Mesh::from(Cuboid::new(1.0, 1.0, 1.0))),
+        sphere: meshes.add(Mesh::from(Sphere::new(0.5))),
+    });
+}
+
+// Constructs without a shape are cubes that grow with their POW
+fn construct_side(pow_amount: usize) -> f32 {
+    (pow_amount as f32).clamp(1.0, MAX_CONSTRUCT_SIDE)
+}
+
+fn construct_scale(details: &ConstructReceived) -> Vec3 {
+    let scale = match details.shape {
+        Some(ConstructShape::Box(size)) => Vec3::from(size),
+        Some(ConstructShape::Sphere(diameter)) => Vec3::splat(diameter),
+        None => Vec3::splat(construct_side(details.pow_amount)),
+    };
+    scale.clamp(
+        Vec3::splat(MIN_CONSTRUCT_SIDE),
+        Vec3::splat(MAX_CONSTRUCT_SIDE),
+    )
+}
+
+// Makes room for a construct with this much POW, false if every kept one has more
+fn make_room(constructs: &mut Constructs, commands: &mut Commands, pow_amount: usize) -> bool {
+    if constructs.len() < MAX_CONSTRUCTS {
+        return true;
+    }
+    let Some((weakest_id, weakest_pow)) = constructs
+        .iter()
+        .map(|(id, construct)| (id.clone(), construct.details.pow_amount))
+        .min_by_key(|(_, pow_amount)| *pow_amount)
+    else {
+        return true;
+    };
+    if weakest_pow >= pow_amount {
+        return false;
+    }
+    if let Some(entity) = constructs
+        .remove(&weakest_id)
+        .and_then(|weakest| weakest.entity)
+    {
+        commands.entity(entity).despawn();
+    }
+    true
+}
+
+fn receive_constructs(
+    mut commands: Commands,
+    mut construct_events: EventReader<ConstructReceived>,
+    mut constructs: ResMut<Constructs>,
+) {
+    for received in construct_events.read() {
+        // Several relays deliver the same note
+        if constructs.contains_key(&received.id)
+            || !make_room(&mut constructs, &mut commands, received.pow_amount)
+        {
+            continue;
+        }
+        constructs.insert(
+            received.id.clone(),
+            Construct {
+                details: received.clone(),
+                entity: None,
+            },
+        );
+    }
+}
+
+// Spawns the constructs of the loaded sectors in the viewed plane, like the mined blocks
+fn show_constructs(
+    mut commands: Commands,
+    construct_assets: Res<ConstructAssets>,
+    world_chunks: Res<WorldChunks>,
+    mut constructs: ResMut<Constructs>,
+) {
+    if !world_chunks.is_changed() && !constructs.is_changed() {
+        return;
+    }
+    for construct in constructs.values_mut() {
+        let Ok(coordinates) = CyberspaceCoordinates::from_hex(&construct.details.coordinates)
+        else {
+            continue;
+        };
+        let shown = world_chunks.shows(coordinates.sector(), &construct.details.coordinates);
+        match (shown, construct.entity) {
+            (true, None) => {
+                let mesh = match construct.details.shape {
+                    Some(ConstructShape::Sphere(_)) => &construct_assets.sphere,
+                    Some(ConstructShape::Box(_)) | None => &construct_assets.cube,
+                };
+                let entity = commands
+                    .spawn((
+                        PbrBundle {
+                            mesh: mesh.clone_weak(),
+                            material: construct_assets.material.clone_weak(),
+                            transform: Transform::from_scale(construct_scale(&construct.details)),
+                            ..Default::default()
+                        },
+                        CyberspacePosition(coordinates),
+                    ))
+                    .id();
+                construct.entity = Some(entity);
+            }
+            (false, Some(entity)) => {
+                commands.entity(entity).despawn();
+                construct.entity = None;
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        cyberspace::{encode_coordinates, Plane},
+        protocol::{construct_coordinates, construct_shape},
+    };
+
+    #[test]
+    fn constructs_are_placed_by_their_c_tag() {
+        let coordinates = CyberspaceCoordinates::new(1 << 84, 3, 9).to_hex(Plane::DSpace);
+        let tags = vec![
+            vec!["nonce".to_string(), "12".to_string(), "20".to_string()],
+            vec!["C".to_string(), coordinates.to_uppercase()],
+        ];
+        assert_eq!(construct_coordinates(&tags), Some(coordinates));
+        assert_eq!(
+            construct_coordinates(&[vec!["C".to_string(), "1234".to_string()]]),
+            None
+        );
+        assert_eq!(
+            construct_coordinates(&[vec!["c".to_string(), encode_coordinates(1, 2, 3)]]),
+            None
+        );
+    }
+
+    #[test]
+    fn constructs_grow_with_pow_up_to_a_limit() {
+        assert_eq!(construct_side(0), 1.0);
+        assert!(construct_side(8) < construct_side(16));
+        assert_eq!(construct_side(200), MAX_CONSTRUCT_SIDE);
+    }
+
+    #[test]
+    fn shape_tags_size_the_construct() {
+        let shape =
+            |tag: &[&str]| construct_shape(&[tag.iter().map(|field| field.to_string()).collect()]);
+        let construct = |shape| ConstructReceived {
+            id: "id".to_string(),
+            coordinates: encode_coordinates(1, 2, 3),
+            pow_amount: 8,
+            shape,
+        };
+        let wall = shape(&["shape", "box", "12", "4", "0.5"]);
+        assert_eq!(wall, Some(ConstructShape::Box([12.0, 4.0, 0.5])));
+        assert_eq!(construct_scale(&construct(wall)), Vec3::new(12.0, 4.0, 0.5));
+        let dome = shape(&["shape", "sphere", "100"]);
+        assert_eq!(
+            construct_scale(&construct(dome)),
+            Vec3::splat(MAX_CONSTRUCT_SIDE)
+        );
+        assert_eq!(shape(&["shape", "box", "1", "-2", "3"]), None);
+        assert_eq!(shape(&["shape", "torus", "1"]), None);
+        assert_eq!(construct_scale(&construct(None)), Vec3::splat(8.0));
+    }
+}
@@ -0,0 +1,233 @@
+use bevy::prelude::*;
+use nostro2::notes::Note;
+
+use crate::{
+    keybindings::{Action, KeyBindings},
+    nostr::{ContactListAnswers, OutgoingNotes},
+    notifications::{Notification, Severity},
+    protocol::{tagged_pubkeys, CONTACTS_KIND},
+    resources::{FollowList, Profiles, UniqueKeys},
+    ui_camera::AvatarListDetails,
+    UserNostrKeys,
+};
+
+pub fn contacts_plugin(app: &mut App) {
+    app.init_resource::<ContactList>()
+        .add_event::<ContactListReceived>()
+        .add_systems(
+            Update,
+            (
+                count_contact_list_answers,
+                apply_contact_list,
+                toggle_follow,
+            )
+                .chain(),
+        );
+}
+
+// Our own kind 3 note, read from a relay
+#[derive(Event, Debug, Clone, PartialEq)]
+pub struct ContactListReceived {
+    pub created_at: u64,
+    pub tags: Vec<Vec<String>>,
+    pub content: String,
+}
+
+// The newest contact list we know of. It's republished whole, so petnames, relay hints
+// and content written by other clients survive following someone from here
+#[derive(Resource, Default)]
+struct ContactList {
+    newest: Option<ContactListReceived>,
+    // Relays that finished sending what they store, with and without our list
+    relays_with_list: usize,
+    relays_without_list: usize,
+    // Following again after being warned that no relay answered yet starts a new list
+    confirmed_new_list: bool,
+}
+
+impl ContactList {
+    // Following before our list arrived would replace it with a list of one, so a new list is only
+    // started once relays answered without one, or when asked to twice
+    fn may_start_new_list(&self) -> bool {
+        self.relays_with_list == 0 && (self.relays_without_list > 0 || self.confirmed_new_list)
+    }
+}
+
+fn count_contact_list_answers(
+    answers: Res<ContactListAnswers>,
+    mut contact_list: ResMut<ContactList>,
+) {
+    for has_list in answers.try_iter() {
+        if has_list {
+            contact_list.relays_with_list += 1;
+        } else {
+            contact_list.relays_without_list += 1;
+        }
+    }
+}
+
+// Every relay sends the list, only a newer one replaces what we have
+fn apply_contact_list(
+    mut received: EventReader<ContactListReceived>,
+    mut contact_list: ResMut<ContactList>,
+    mut follow_list: ResMut<FollowList>,
+    mut unique_keys: ResMut<UniqueKeys>,
+) {
+    for contacts in received.read() {
+        if contact_list
+            .newest
+            .as_ref()
+            .is_some_and(|known| known.created_at >= contacts.created_at)
+        {
+            continue;
+        }
+        **follow_list = tagged_pubkeys(&contacts.tags).into_iter().collect();
+        // Followed keys are in the roster even before they publish anything
+        unique_keys.extend(follow_list.iter().cloned());
+        contact_list.newest = Some(contacts.clone());
+    }
+}
+
+fn is_contact(tag: &[String], pubkey: &str) -> bool {
+    matches!(tag, [name, contact, ..] if name == "p" && contact == pubkey)
+}
+
+// Adds the key's `p` tag or drops it, every other tag is kept as it was
+fn toggle_contact(tags: &[Vec<String>], pubkey: &str) -> (Vec<Vec<String>>, bool) {
+    if tags.iter().any(|tag| is_contact(tag, pubkey)) {
+        let tags = tags
+            .iter()
+            .filter(|tag| !is_contact(tag, pubkey))
+            .cloned()
+            .collect();
+        return (tags, false);
+    }
+    let mut tags = tags.to_vec();
+    tags.push(vec!["p".to_string(), pubkey.to_string()]);
+    (tags, true)
+}
+
+fn toggle_follow(
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    key_bindings: Res<KeyBindings>,
+    avatar_list: Res<AvatarListDetails>,
+    user_keys: Res<UserNostrKeys>,
+    outgoing_notes: Res<OutgoingNotes>,
+    profiles: Res<Profiles>,
+    mut contact_list: ResMut<ContactList>,
+    mut follow_list: ResMut<FollowList>,
+    mut notifications: EventWriter<Notification>,
+) {
+    if !keyboard_input.just_pressed(key_bindings.key(Action::ToggleFollow)) {
+        return;
+    }
+    let our_pubkey = user_keys.get_public_key();
+    let Some(pubkey) = avatar_list
+        .selected_pubkey()
+        .filter(|pubkey| *pubkey != our_pubkey)
+    else {
+        return;
+    };
+    if contact_list.newest.is_none() && !contact_list.may_start_new_list() {
+        // A relay that has the list is still sending it, there's nothing to confirm
+        let message = if contact_list.relays_with_list > 0 {
+            "Your contact list is still loading, try again in a moment"
+        } else {
+            contact_list.confirmed_new_list = true;
+            "No relay has answered with your contact list yet, follow again to start a new one"
+        };
+        notifications.send(Notification {
+            message: message.to_string(),
+            severity: Severity::Warning,
+            play_sound: false,
+        });
+        return;
+    }
+
+    let (tags, content) = contact_list
+        .newest
+        .as_ref()
+        .map(|contacts| (contacts.tags.clone(), contacts.content.clone()))
+        .unwrap_or_default();
+    let (tags, followed) = toggle_contact(&tags, pubkey);
+    let mut note = Note::new(our_pubkey, CONTACTS_KIND, &content);
+    note.tags = tags.clone();
+    let signed_note = user_keys.get_keypair().sign_nostr_event(note);
+    let created_at = signed_note.get_created_at();
+    let _sent = outgoing_notes.send(signed_note);
+
+    // Relays echo it back, but the tint shouldn't wait for them
+    if followed {
+        follow_list.insert(pubkey.to_string());
+    } else {
+        follow_list.remove(pubkey);
+    }
+    contact_list.newest = Some(ContactListReceived {
+        created_at,
+        tags,
+        content,
+    });
+    notifications.send(Notification {
+        message: format!(
+            "{} {}",
+            if followed { "Following" } else { "Unfollowed" },
+            profiles.display_name(pubkey)
+        ),
+        severity: Severity::Success,
+        play_sound: false,
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn following_adds_a_p_tag_and_keeps_the_rest() {
+        let tags = vec![
+            vec![
+                "p".to_string(),
+                "aa".to_string(),
+                "wss://relay".to_string(),
+                "al".to_string(),
+            ],
+            vec!["t".to_string(), "nostr".to_string()],
+        ];
+        let (followed_tags, followed) = toggle_contact(&tags, "bb");
+        assert!(followed);
+        assert_eq!(followed_tags[..2], tags[..]);
+        assert_eq!(followed_tags[2], vec!["p".to_string(), "bb".to_string()]);
+    }
+
+    #[test]
+    fn unfollowing_drops_only_that_key() {
+        let tags = vec![
+            vec![
+                "p".to_string(),
+                "aa".to_string(),
+                "wss://relay".to_string(),
+                "al".to_string(),
+            ],
+            vec!["p".to_string(), "bb".to_string()],
+        ];
+        let (unfollowed_tags, followed) = toggle_contact(&tags, "aa");
+        assert!(!followed);
+        assert_eq!(
+            unfollowed_tags,
+            vec![vec!["p".to_string(), "bb".to_string()]]
+        );
+        assert_eq!(tagged_pubkeys(&unfollowed_tags), vec!["bb".to_string()]);
+    }
+
+    #[test]
+    fn new_lists_wait_for_relays_without_one() {
+        let mut contact_list = ContactList::default();
+        assert!(!contact_list.may_start_new_list());
+        contact_list.relays_without_list = 1;
+        assert!(contact_list.may_start_new_list());
+        // Another relay has the list, it only has to arrive
+        contact_list.relays_with_list = 1;
+        contact_list.confirmed_new_list = true;
+        assert!(!contact_list.may_start_new_list());
+    }
+}
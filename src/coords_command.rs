@@ -0,0 +1,63 @@
+use crate::{
+    cyberspace::{extract_plane, Plane},
+    destination_prompt::parse_destination,
+    resources::{npub, parse_pubkey},
+};
+
+pub const COORDS_USAGE: &str = "Usage: nostrcraft coords <x y z | npub | 64 character coordinate>";
+
+// `nostrcraft coords`, prints every form of a coordinate without opening a window.
+// Takes the same input as the destination prompt
+pub fn coords_command(args: &[String]) -> Result<String, String> {
+    let input = args.join(" ");
+    let coordinates = parse_destination(&input).ok_or_else(|| COORDS_USAGE.to_string())?;
+    let sector = coordinates.sector();
+    let mut lines = vec![
+        format!(
+            "X: {} Y: {} Z: {}",
+            coordinates.x, coordinates.y, coordinates.z
+        ),
+        format!("Sector: {} {} {}", sector.x, sector.y, sector.z),
+    ];
+    if let Some(plane) = parse_pubkey(&input).and_then(|hex| extract_plane(&hex).ok()) {
+        lines.push(format!("Given in {}", plane.name()));
+    }
+    // A key's home is the coordinate its hex spells out
+    for plane in [Plane::ISpace, Plane::DSpace] {
+        let hex = coordinates.to_hex(plane);
+        lines.push(format!("{}: {}", plane.name(), hex));
+        if let Some(npub) = npub(&hex) {
+            lines.push(format!("  home of {}", npub));
+        }
+    }
+    Ok(lines.join("\n"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cyberspace::CyberspaceCoordinates;
+
+    fn args(input: &str) -> Vec<String> {
+        input.split_whitespace().map(str::to_string).collect()
+    }
+
+    #[test]
+    fn every_form_converts_to_the_same_coordinate() {
+        let hex = CyberspaceCoordinates::new(69, 420, 50).to_hex(Plane::DSpace);
+        let from_numbers = coords_command(&args("69 420 50")).unwrap();
+        let from_hex = coords_command(&args(&hex)).unwrap();
+        let from_npub = coords_command(&args(&npub(&hex).unwrap())).unwrap();
+        assert!(from_numbers.starts_with("X: 69 Y: 420 Z: 50\nSector: 2 13 1\ni-space: "));
+        assert!(from_numbers.contains(&format!("d-space: {}\n  home of npub1", hex)));
+        assert_eq!(from_hex, from_npub);
+        assert!(from_hex.contains("Given in d-space"));
+        assert_eq!(from_hex.replace("Given in d-space\n", ""), from_numbers);
+    }
+
+    #[test]
+    fn bad_input_prints_the_usage() {
+        assert_eq!(coords_command(&[]), Err(COORDS_USAGE.to_string()));
+        assert_eq!(coords_command(&args("1 2")), Err(COORDS_USAGE.to_string()));
+    }
+}
@@ -2,6 +2,50 @@
 // These methods are used to generate the cyberspace coordinates for the notes and avatars
 // based on their content and public key respectively
 
+use bevy::math::{IVec3, Vec3};
+use cryptoxide::{digest::Digest, sha2::Sha256};
+
+// Each axis of a coordinate is 85 bits
+const AXIS_BITS: u32 = 85;
+const AXIS_SIZE: i128 = 1 << AXIS_BITS;
+
+// The last bit of a coordinate picks one of the two planes of cyberspace
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum Plane {
+    // Dataspace, bit 0
+    DSpace,
+    // Ideaspace, bit 1, where blocks have always been placed
+    #[default]
+    ISpace,
+}
+
+impl Plane {
+    pub fn other(self) -> Plane {
+        match self {
+            Plane::DSpace => Plane::ISpace,
+            Plane::ISpace => Plane::DSpace,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Plane::DSpace => "d-space",
+            Plane::ISpace => "i-space",
+        }
+    }
+}
+
+// Plane of a 64 character coordinate or key, from its last bit
+pub fn extract_plane(hex_str: &str) -> Result<Plane, hex::FromHexError> {
+    let hex_bytes = hex::decode(hex_str)?;
+    let last_byte = hex_bytes.last().copied().unwrap_or_default();
+    Ok(if last_byte & 0x80 == 0 {
+        Plane::DSpace
+    } else {
+        Plane::ISpace
+    })
+}
+
 pub fn extract_coordinates(hex_str: &str) -> Result<(i128, i128, i128), hex::FromHexError> {
     // Decode the hexadecimal string into bytes
     let hex_bytes = hex::decode(hex_str)?;
@@ -29,7 +73,7 @@ pub fn extract_coordinates(hex_str: &str) -> Result<(i128, i128, i128), hex::Fro
         }
     }
 
-    // Last bit is for i-space o d-space, we are using i-space here so always 1
+    // Last bit is for i-space or d-space, see `extract_plane`
 
     // Convert the bit vectors into i128 values
     let x = vec_bool_to_i128(x_bit_vector).unwrap();
@@ -40,6 +84,10 @@ pub fn extract_coordinates(hex_str: &str) -> Result<(i128, i128, i128), hex::Fro
 }
 
 pub fn encode_coordinates(x: i128, y: i128, z: i128) -> String {
+    encode_plane_coordinates(x, y, z, Plane::ISpace)
+}
+
+pub fn encode_plane_coordinates(x: i128, y: i128, z: i128, plane: Plane) -> String {
     // Convert the coordinates into a vector of bits
     let x_bits = i128_to_vec_bool(x);
     let y_bits = i128_to_vec_bool(y);
@@ -53,7 +101,7 @@ pub fn encode_coordinates(x: i128, y: i128, z: i128) -> String {
         combined_bits.push(z_bits[i]);
     }
 
-    combined_bits.push(true); // Always 1 for i-space
+    combined_bits.push(plane == Plane::ISpace);
 
     // Convert the bits into bytes
     let mut bytes = Vec::new();
@@ -102,19 +150,337 @@ fn i128_to_vec_bool(num: i128) -> Vec<bool> {
     result
 }
 
-// This scale doesnt lose precision between the i128 and f32
-const CYBERSPACE_SECTOR_SCALE: i128 = 2_i128.pow(71);
+// 256 bit simhash of a text's words, read as a coordinate. Texts sharing most of their words
+// share most of the bits, so similar notes land in similar places
+pub fn simhash_coordinates(text: &str) -> String {
+    let mut weights = [0i32; 256];
+    let words = text
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty());
+    for word in words {
+        let mut hasher = Sha256::new();
+        hasher.input_str(&word.to_lowercase());
+        let mut hash = [0u8; 32];
+        hasher.result(&mut hash);
+        for (bit, weight) in weights.iter_mut().enumerate() {
+            if hash[bit / 8] & (0x80 >> (bit % 8)) != 0 {
+                *weight += 1;
+            } else {
+                *weight -= 1;
+            }
+        }
+    }
+    let mut simhash = [0u8; 32];
+    for (bit, weight) in weights.iter().enumerate() {
+        if *weight > 0 {
+            simhash[bit / 8] |= 0x80 >> (bit % 8);
+        }
+    }
+    hex::encode(simhash)
+}
+
+// Full precision position in cyberspace, every axis wraps around at 2^85
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct CyberspaceCoordinates {
+    pub x: i128,
+    pub y: i128,
+    pub z: i128,
+}
+
+impl CyberspaceCoordinates {
+    pub fn new(x: i128, y: i128, z: i128) -> Self {
+        CyberspaceCoordinates {
+            x: x.rem_euclid(AXIS_SIZE),
+            y: y.rem_euclid(AXIS_SIZE),
+            z: z.rem_euclid(AXIS_SIZE),
+        }
+    }
+
+    pub fn from_hex(hex_str: &str) -> Result<Self, hex::FromHexError> {
+        let (x, y, z) = extract_coordinates(hex_str)?;
+        Ok(CyberspaceCoordinates::new(x, y, z))
+    }
+
+    pub fn to_hex(self, plane: Plane) -> String {
+        encode_plane_coordinates(self.x, self.y, self.z, plane)
+    }
+
+    pub fn offset(self, offset: IVec3) -> Self {
+        CyberspaceCoordinates::new(
+            self.x + offset.x as i128,
+            self.y + offset.y as i128,
+            self.z + offset.z as i128,
+        )
+    }
+
+    // Where this coordinate is drawn with `origin` at the world origin, the short way round.
+    // Exact as long as it's within 2^24 of the origin, further out is too far to see anyway
+    pub fn relative_to(self, origin: CyberspaceCoordinates) -> Vec3 {
+        Vec3::new(
+            wrapped_difference(self.x, origin.x) as f32,
+            wrapped_difference(self.y, origin.y) as f32,
+            wrapped_difference(self.z, origin.z) as f32,
+        )
+    }
+
+    pub fn sector(self) -> Sector {
+        Sector {
+            x: self.x.div_euclid(SECTOR_SIZE),
+            y: self.y.div_euclid(SECTOR_SIZE),
+            z: self.z.div_euclid(SECTOR_SIZE),
+        }
+    }
+
+    // Rounds every axis down to a multiple of `step`
+    pub fn aligned(self, step: i128) -> Self {
+        CyberspaceCoordinates {
+            x: self.x - self.x.rem_euclid(step),
+            y: self.y - self.y.rem_euclid(step),
+            z: self.z - self.z.rem_euclid(step),
+        }
+    }
+
+    // Steps from `other` to this coordinate on each axis, the short way round
+    pub fn difference(self, other: CyberspaceCoordinates) -> [i128; 3] {
+        [
+            wrapped_difference(self.x, other.x),
+            wrapped_difference(self.y, other.y),
+            wrapped_difference(self.z, other.z),
+        ]
+    }
+
+    // Straight line distance, in floats since the squares don't fit an i128 across cyberspace
+    pub fn distance(self, other: CyberspaceCoordinates) -> f64 {
+        self.difference(other)
+            .into_iter()
+            .map(|axis| (axis as f64).powi(2))
+            .sum::<f64>()
+            .sqrt()
+    }
+
+    pub fn manhattan_distance(self, other: CyberspaceCoordinates) -> i128 {
+        self.difference(other).into_iter().map(i128::abs).sum()
+    }
+
+    // Distance along the axis where the two are furthest apart
+    pub fn chebyshev_distance(self, other: CyberspaceCoordinates) -> i128 {
+        self.difference(other)
+            .into_iter()
+            .map(i128::abs)
+            .max()
+            .unwrap_or_default()
+    }
+
+    // Exact check of the straight line distance while the squares fit an i128,
+    // radii that large are only ever compared in floats
+    pub fn within_distance(self, other: CyberspaceCoordinates, radius: i128) -> bool {
+        let squared = self
+            .difference(other)
+            .into_iter()
+            .try_fold(0i128, |sum, axis| sum.checked_add(axis.checked_mul(axis)?));
+        match (squared, radius.checked_mul(radius)) {
+            (Some(squared), Some(limit)) => squared <= limit,
+            _ => self.distance(other) <= radius as f64,
+        }
+    }
+
+    // Halfway between the two the short way round, rounded towards this coordinate
+    pub fn midpoint(self, other: CyberspaceCoordinates) -> CyberspaceCoordinates {
+        let [x, y, z] = other.difference(self);
+        CyberspaceCoordinates::new(self.x + x / 2, self.y + y / 2, self.z + z / 2)
+    }
+}
+
+// Box of coordinates from `min` to `max` inclusive, which may wrap around the edges of cyberspace
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CoordinateBounds {
+    pub min: CyberspaceCoordinates,
+    pub max: CyberspaceCoordinates,
+}
 
-pub fn scale_coordinates_to_world(x: i128, y: i128, z: i128) -> (f32, f32, f32) {
-    let x_scaled = x / CYBERSPACE_SECTOR_SCALE;
-    let y_scaled = y / CYBERSPACE_SECTOR_SCALE;
-    let z_scaled = z / CYBERSPACE_SECTOR_SCALE;
+impl CoordinateBounds {
+    // The cube reaching `radius` out from `center` on every axis
+    pub fn around(center: CyberspaceCoordinates, radius: i128) -> Self {
+        CoordinateBounds {
+            min: CyberspaceCoordinates::new(
+                center.x - radius,
+                center.y - radius,
+                center.z - radius,
+            ),
+            max: CyberspaceCoordinates::new(
+                center.x + radius,
+                center.y + radius,
+                center.z + radius,
+            ),
+        }
+    }
 
-    let x_scaled = x_scaled as f32;
-    let y_scaled = y_scaled as f32;
-    let z_scaled = z_scaled as f32;
+    pub fn contains(self, coordinates: CyberspaceCoordinates) -> bool {
+        [
+            (coordinates.x, self.min.x, self.max.x),
+            (coordinates.y, self.min.y, self.max.y),
+            (coordinates.z, self.min.z, self.max.z),
+        ]
+        .into_iter()
+        .all(|(axis, min, max)| {
+            (axis - min).rem_euclid(AXIS_SIZE) <= (max - min).rem_euclid(AXIS_SIZE)
+        })
+    }
+
+    pub fn intersects(self, other: CoordinateBounds) -> bool {
+        [
+            (self.min.x, self.max.x, other.min.x, other.max.x),
+            (self.min.y, self.max.y, other.min.y, other.max.y),
+            (self.min.z, self.max.z, other.min.z, other.max.z),
+        ]
+        .into_iter()
+        .all(|(min, max, other_min, other_max)| {
+            (other_min - min).rem_euclid(AXIS_SIZE) <= (max - min).rem_euclid(AXIS_SIZE)
+                || (min - other_min).rem_euclid(AXIS_SIZE)
+                    <= (other_max - other_min).rem_euclid(AXIS_SIZE)
+        })
+    }
+
+    pub fn center(self) -> CyberspaceCoordinates {
+        self.min.midpoint(self.max)
+    }
+}
 
-    (x_scaled.round(), y_scaled.round(), z_scaled.round())
+// Difference of two axis values, between -2^84 and 2^84
+fn wrapped_difference(a: i128, b: i128) -> i128 {
+    (a - b + AXIS_SIZE / 2).rem_euclid(AXIS_SIZE) - AXIS_SIZE / 2
+}
+
+// Blocks are grouped into cubic sectors of this many coordinates per side
+pub const SECTOR_SIZE: i128 = 32;
+pub const WORLD_SECTOR_SIZE: f32 = SECTOR_SIZE as f32;
+
+// A cube of `SECTOR_SIZE` coordinates, numbered by its coordinates divided by the size
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct Sector {
+    pub x: i128,
+    pub y: i128,
+    pub z: i128,
+}
+
+impl Sector {
+    // Coordinate with the lowest x, y and z in the sector
+    pub fn corner(self) -> CyberspaceCoordinates {
+        CyberspaceCoordinates::new(
+            self.x * SECTOR_SIZE,
+            self.y * SECTOR_SIZE,
+            self.z * SECTOR_SIZE,
+        )
+    }
+
+    pub fn contains(self, coordinates: CyberspaceCoordinates) -> bool {
+        coordinates.sector() == self
+    }
+
+    pub fn bounds(self) -> CoordinateBounds {
+        CoordinateBounds {
+            min: self.corner(),
+            max: self.corner().offset(IVec3::splat(SECTOR_SIZE as i32 - 1)),
+        }
+    }
+
+    // Sector this many sectors away on each axis, wrapping around the edges of cyberspace
+    pub fn offset(self, offset: IVec3) -> Sector {
+        self.corner().offset(offset * SECTOR_SIZE as i32).sector()
+    }
+
+    // The 26 sectors sharing a face, edge or corner with this one
+    pub fn neighbors(self) -> impl Iterator<Item = Sector> {
+        (-1..=1)
+            .flat_map(|x| (-1..=1).flat_map(move |y| (-1..=1).map(move |z| IVec3::new(x, y, z))))
+            .filter(|offset| *offset != IVec3::ZERO)
+            .map(move |offset| self.offset(offset))
+    }
+
+    // World space box around the sector's blocks, blocks are unit cubes centered on coordinates
+    pub fn world_bounds(self, origin: CyberspaceCoordinates) -> (Vec3, Vec3) {
+        let min = self.corner().relative_to(origin) - Vec3::splat(0.5);
+        (min, min + Vec3::splat(WORLD_SECTOR_SIZE))
+    }
+
+    // Sectors between the two along the axis where they're furthest apart
+    pub fn distance(self, other: Sector) -> i128 {
+        self.corner().chebyshev_distance(other.corner()) / SECTOR_SIZE
+    }
+}
+
+// Sectors are ordered along a Morton curve, which interleaves the bits of their x, y and z.
+// A cell of 2^level sectors per side is one unbroken run of the curve and named by its code,
+// so relays can be asked for the blocks of a few cells instead of every sector one by one
+pub const MORTON_LEVELS: [u32; 3] = [2, 4, 6];
+const SECTOR_AXIS_BITS: u32 = AXIS_BITS - SECTOR_SIZE.trailing_zeros();
+
+// The level's code of the cell holding this sector, hex digits in curve order
+pub fn morton_cell(sector: Sector, level: u32) -> String {
+    let bits = (level..SECTOR_AXIS_BITS)
+        .rev()
+        .flat_map(|bit| [sector.x, sector.y, sector.z].map(|axis| (axis >> bit) & 1));
+    let mut digits = String::new();
+    let mut nibble = 0;
+    let mut nibble_bits = 0;
+    for bit in bits {
+        nibble = nibble << 1 | bit;
+        nibble_bits += 1;
+        if nibble_bits == 4 {
+            digits.push_str(&format!("{:x}", nibble));
+            nibble = 0;
+            nibble_bits = 0;
+        }
+    }
+    if nibble_bits > 0 {
+        digits.push_str(&format!("{:x}", nibble << (4 - nibble_bits)));
+    }
+    format!("{}:{}", level, digits)
+}
+
+// A coordinate within `radius` of another is at most this many sectors from the other's sector
+pub fn sector_reach(radius: i128) -> i128 {
+    radius / SECTOR_SIZE + 1
+}
+
+// Cells holding every block within `radius` of `center`, what relays are asked for around us
+pub fn morton_cover_within(center: CyberspaceCoordinates, radius: i128) -> Vec<String> {
+    morton_cover(center.sector(), sector_reach(radius))
+}
+
+// Cells covering the sectors out to `radius` around `center`, sorted along the curve.
+// The smallest level the cube fits in takes at most two cells per axis
+pub fn morton_cover(center: Sector, radius: i128) -> Vec<String> {
+    let level = MORTON_LEVELS
+        .into_iter()
+        .find(|level| 1 << level > 2 * radius)
+        .unwrap_or(MORTON_LEVELS[MORTON_LEVELS.len() - 1]);
+    let cell_size: usize = 1 << level;
+    // Every cell the cube reaches holds one of these offsets on each axis
+    let offsets: Vec<i128> = (-radius..radius)
+        .step_by(cell_size)
+        .chain(std::iter::once(radius))
+        .collect();
+    let mut cells: Vec<String> = offsets
+        .iter()
+        .flat_map(|x| {
+            offsets
+                .iter()
+                .flat_map(move |y| offsets.iter().map(move |z| (*x, *y, *z)))
+        })
+        .map(|(x, y, z)| {
+            let sector = CyberspaceCoordinates::new(
+                (center.x + x) * SECTOR_SIZE,
+                (center.y + y) * SECTOR_SIZE,
+                (center.z + z) * SECTOR_SIZE,
+            )
+            .sector();
+            morton_cell(sector, level)
+        })
+        .collect();
+    cells.sort();
+    cells.dedup();
+    cells
 }
 
 #[cfg(test)]
@@ -182,7 +548,7 @@ mod tests {
         let expected = "b722c93ee3be55e782a2d14378dd2b47e3a7faf08f5e5d79e34911fcf9b8409b";
         assert_eq!(result, expected);
     }
-    
+
     #[test]
     fn encode_coordinates_and_back() {
         let x = 69;
@@ -193,4 +559,199 @@ mod tests {
         let result = extract_coordinates(&encoded).unwrap();
         assert_eq!(result, (x, y, z));
     }
+
+    #[test]
+    fn both_planes_round_trip() {
+        for plane in [Plane::ISpace, Plane::DSpace] {
+            let encoded = encode_plane_coordinates(69, 420, 50, plane);
+            assert_eq!(extract_coordinates(&encoded).unwrap(), (69, 420, 50));
+            assert_eq!(extract_plane(&encoded).unwrap(), plane);
+        }
+        assert_ne!(
+            encode_plane_coordinates(69, 420, 50, Plane::ISpace),
+            encode_plane_coordinates(69, 420, 50, Plane::DSpace)
+        );
+        let hex_str = "b722c93ee3be55e782a2d14378dd2b47e3a7faf08f5e5d79e34911fcf9b8409b";
+        assert_eq!(extract_plane(hex_str).unwrap(), Plane::ISpace);
+        let hex_str = "b722c93ee3be55e782a2d14378dd2b47e3a7faf08f5e5d79e34911fcf9b8401b";
+        assert_eq!(extract_plane(hex_str).unwrap(), Plane::DSpace);
+    }
+
+    #[test]
+    fn far_coordinates_stay_apart() {
+        let home = CyberspaceCoordinates::from_hex(
+            "b722c93ee3be55e782a2d14378dd2b47e3a7faf08f5e5d79e34911fcf9b8409b",
+        )
+        .unwrap();
+        let next_door = home.offset(IVec3::new(1, 0, -2));
+        assert_ne!(home.to_hex(Plane::ISpace), next_door.to_hex(Plane::ISpace));
+        assert_eq!(next_door.relative_to(home), Vec3::new(1.0, 0.0, -2.0));
+        assert_eq!(
+            CyberspaceCoordinates::from_hex(&next_door.to_hex(Plane::ISpace)).unwrap(),
+            next_door
+        );
+    }
+
+    #[test]
+    fn coordinates_wrap_around_the_edges() {
+        let edge = CyberspaceCoordinates::new(-1, 0, AXIS_SIZE);
+        assert_eq!(edge, CyberspaceCoordinates::new(AXIS_SIZE - 1, 0, 0));
+        let origin = CyberspaceCoordinates::default();
+        assert_eq!(edge.relative_to(origin), Vec3::new(-1.0, 0.0, 0.0));
+        assert_eq!(edge.offset(IVec3::X), origin);
+    }
+
+    #[test]
+    fn sectors_floor_and_wrap() {
+        let coordinates = CyberspaceCoordinates::new(31, 32, -1);
+        let sector = coordinates.sector();
+        assert_eq!(
+            sector,
+            Sector {
+                x: 0,
+                y: 1,
+                z: AXIS_SIZE / SECTOR_SIZE - 1
+            }
+        );
+        assert_eq!(sector.corner(), CyberspaceCoordinates::new(0, 32, -32));
+        assert_eq!(sector.distance(Sector::default()), 1);
+    }
+
+    #[test]
+    fn similar_texts_get_similar_simhashes() {
+        let differing_bits = |a: &str, b: &str| {
+            let (a, b) = (hex::decode(a).unwrap(), hex::decode(b).unwrap());
+            a.iter()
+                .zip(b.iter())
+                .map(|(a, b)| (a ^ b).count_ones())
+                .sum::<u32>()
+        };
+        let note = simhash_coordinates("gm nostr, building a castle in cyberspace today");
+        assert_eq!(note.len(), 64);
+        assert!(extract_coordinates(&note).is_ok());
+        assert_eq!(
+            note,
+            simhash_coordinates("GM nostr! Building a castle in cyberspace today")
+        );
+        let similar = simhash_coordinates("gm nostr, building a tower in cyberspace today");
+        let unrelated = simhash_coordinates("bitcoin fees are low, time to consolidate utxos");
+        assert!(differing_bits(&note, &similar) < differing_bits(&note, &unrelated));
+    }
+
+    #[test]
+    fn neighbors_surround_the_sector_across_the_edges() {
+        let sector = Sector::default();
+        let neighbors: Vec<Sector> = sector.neighbors().collect();
+        assert_eq!(neighbors.len(), 26);
+        assert!(neighbors
+            .iter()
+            .all(|neighbor| neighbor.distance(sector) == 1 && *neighbor != sector));
+        assert!(neighbors.contains(&CyberspaceCoordinates::new(-1, 0, 0).sector()));
+        assert!(sector.contains(CyberspaceCoordinates::new(31, 0, 31)));
+        assert!(!sector.contains(CyberspaceCoordinates::new(32, 0, 0)));
+    }
+
+    #[test]
+    fn sector_bounds_enclose_its_blocks() {
+        let origin = CyberspaceCoordinates::new(1 << 84, 0, 0);
+        let (min, max) = origin.sector().offset(IVec3::X).world_bounds(origin);
+        assert_eq!(min, Vec3::new(31.5, -0.5, -0.5));
+        assert_eq!(max, Vec3::new(63.5, 31.5, 31.5));
+    }
+
+    #[test]
+    fn distances_go_the_short_way_round() {
+        let home = CyberspaceCoordinates::new(AXIS_SIZE - 3, 0, 10);
+        let other = CyberspaceCoordinates::new(1, 4, 10);
+        assert_eq!(other.difference(home), [4, 4, 0]);
+        assert_eq!(other.manhattan_distance(home), 8);
+        assert_eq!(other.chebyshev_distance(home), 4);
+        assert!((other.distance(home) - 32f64.sqrt()).abs() < 1e-9);
+        assert!(other.within_distance(home, 6));
+        assert!(!other.within_distance(home, 5));
+        // Opposite corners of cyberspace don't overflow
+        let far = CyberspaceCoordinates::new(home.x + (1 << 84), 1 << 84, (1 << 84) + 10);
+        assert!(!far.within_distance(home, 1 << 84));
+        assert!(far.within_distance(home, 1 << 85));
+        assert_eq!(
+            home.midpoint(other),
+            CyberspaceCoordinates::new(AXIS_SIZE - 1, 2, 10)
+        );
+    }
+
+    #[test]
+    fn bounds_contain_coordinates_across_the_edges() {
+        let bounds = CoordinateBounds::around(CyberspaceCoordinates::new(0, 5, 1 << 84), 2);
+        assert!(bounds.contains(CyberspaceCoordinates::new(AXIS_SIZE - 2, 7, 1 << 84)));
+        assert!(bounds.contains(CyberspaceCoordinates::new(2, 3, (1 << 84) + 1)));
+        assert!(!bounds.contains(CyberspaceCoordinates::new(3, 5, 1 << 84)));
+        assert!(!bounds.contains(CyberspaceCoordinates::new(0, 8, 1 << 84)));
+        let sector = Sector {
+            x: 0,
+            y: 0,
+            z: 1 << 79,
+        };
+        assert!(bounds.intersects(sector.bounds()));
+        assert!(sector.offset(IVec3::NEG_X).bounds().intersects(bounds));
+        assert!(!sector.offset(IVec3::Y).bounds().intersects(bounds));
+        assert_eq!(
+            sector.bounds().center(),
+            sector.corner().offset(IVec3::splat(15))
+        );
+    }
+
+    #[test]
+    fn morton_cells_follow_the_curve() {
+        let sector = Sector { x: 5, y: 0, z: 3 };
+        // Bits 2, 1 and 0 of x, y and z interleaved are 100 001 101, the rest are zero
+        assert!(morton_cell(sector, 0).starts_with("0:000"));
+        assert!(morton_cell(sector, 0).ends_with("0010d"));
+        assert_eq!(
+            morton_cell(sector, 2),
+            morton_cell(Sector { x: 4, y: 1, z: 0 }, 2)
+        );
+        assert_ne!(
+            morton_cell(sector, 2),
+            morton_cell(Sector { x: 3, ..sector }, 2)
+        );
+        // Cells of a level sort in curve order
+        let cells: Vec<String> = (0..8)
+            .map(|z| morton_cell(Sector { x: 0, y: 0, z }, 0))
+            .collect();
+        assert!(cells.windows(2).all(|pair| pair[0] < pair[1]));
+    }
+
+    #[test]
+    fn morton_cover_takes_few_cells() {
+        let center = Sector { x: 31, y: 20, z: 8 };
+        let cells = morton_cover(center, 4);
+        assert!(cells.iter().all(|cell| cell.starts_with("4:")));
+        // The cube crosses a cell boundary along x only
+        assert_eq!(cells.len(), 2);
+        assert!(center
+            .neighbors()
+            .chain([
+                center.offset(IVec3::splat(4)),
+                center.offset(IVec3::splat(-4))
+            ])
+            .all(|sector| cells.contains(&morton_cell(sector, 4))));
+        assert_eq!(morton_cover(center, 0), vec![morton_cell(center, 2)]);
+        assert!(morton_cover(center, 40).len() <= 27);
+    }
+
+    #[test]
+    fn radius_covers_reach_the_blocks_at_the_edge() {
+        let center = CyberspaceCoordinates::new(1000, -40, 77);
+        let radius = 3 * SECTOR_SIZE;
+        let cells = morton_cover_within(center, radius);
+        let level = cells[0].split(':').next().unwrap().parse::<u32>().unwrap();
+        for edge in [
+            IVec3::new(radius as i32, 0, 0),
+            IVec3::new(0, -radius as i32, 0),
+            IVec3::new(0, 0, radius as i32),
+        ] {
+            let sector = center.offset(edge).sector();
+            assert!(cells.contains(&morton_cell(sector, level)));
+        }
+    }
 }
@@ -0,0 +1,186 @@
+use bevy::{
+    input::{
+        keyboard::{Key, KeyboardInput},
+        ButtonState,
+    },
+    prelude::*,
+};
+
+use crate::{
+    cyberspace::CyberspaceCoordinates,
+    keybindings::{Action, KeyBindings, Typing},
+    minimap::TeleportDestination,
+    notifications::{Notification, Severity},
+    resources::parse_pubkey,
+    ui_camera::{
+        edit_text_field, text_bundle_builder, TextInput, BORDER_WIDTH, LIGHT_GRAY, MARGIN_UI,
+        PADDING_UI, TITLE_FONT,
+    },
+};
+
+// Long enough for an npub
+const MAX_PROMPT_CHARS: usize = 72;
+
+pub fn destination_prompt_plugin(app: &mut App) {
+    app.init_resource::<DestinationPrompt>()
+        .add_systems(PostStartup, setup_destination_prompt)
+        .add_systems(PreUpdate, type_destination.in_set(TextInput))
+        .add_systems(Update, update_destination_prompt);
+}
+
+// What has been typed so far, none while the prompt is closed
+#[derive(Resource, Default, Deref, DerefMut)]
+struct DestinationPrompt(Option<String>);
+
+#[derive(Component)]
+struct DestinationPromptPanel;
+
+#[derive(Component)]
+struct DestinationPromptText;
+
+// Three integers are cyberspace coordinates, a key or 64 character cyberspace coordinate
+// lands where that coordinate's home would be
+pub fn parse_destination(input: &str) -> Option<CyberspaceCoordinates> {
+    if let Some(coordinates) = parse_pubkey(input) {
+        return CyberspaceCoordinates::from_hex(&coordinates).ok();
+    }
+    // Also takes the "X:1, Y: 2, Z: 3" format the coordinates panel shows
+    let numbers: Vec<i128> = input
+        .split(|c: char| c == ',' || c.is_whitespace())
+        .map(|part| part.trim_start_matches(|c: char| "xyzXYZ:".contains(c)))
+        .filter(|part| !part.is_empty())
+        .map(|part| part.parse())
+        .collect::<Result<_, _>>()
+        .ok()?;
+    match numbers[..] {
+        [x, y, z] => Some(CyberspaceCoordinates::new(x, y, z)),
+        _ => None,
+    }
+}
+
+fn type_destination(
+    mut keyboard_events: EventReader<KeyboardInput>,
+    key_bindings: Res<KeyBindings>,
+    mut keyboard_input: ResMut<ButtonInput<KeyCode>>,
+    mut prompt: ResMut<DestinationPrompt>,
+    mut typing: ResMut<Typing>,
+    mut teleport_destination: ResMut<TeleportDestination>,
+    mut notifications: EventWriter<Notification>,
+) {
+    let Some(typed) = prompt.as_mut() else {
+        keyboard_events.clear();
+        if !**typing && keyboard_input.just_pressed(key_bindings.key(Action::TypeDestination)) {
+            **prompt = Some(String::new());
+            **typing = true;
+            keyboard_input.reset_all();
+        }
+        return;
+    };
+    let mut submitted = None;
+    for event in keyboard_events.read() {
+        if event.state != ButtonState::Pressed {
+            continue;
+        }
+        match &event.logical_key {
+            Key::Enter => submitted = Some(true),
+            Key::Escape => submitted = Some(false),
+            key => {
+                edit_text_field(typed, key, MAX_PROMPT_CHARS, &keyboard_input);
+            }
+        }
+    }
+    // Keys typed into the prompt don't reach the rest of the game
+    keyboard_input.reset_all();
+    let Some(submitted) = submitted else {
+        return;
+    };
+    if submitted {
+        let (message, severity) = match parse_destination(typed) {
+            Some(destination) => {
+                **teleport_destination = Some(destination);
+                let message = format!(
+                    "Destination X: {} Y: {} Z: {}, press Enter to teleport",
+                    destination.x, destination.y, destination.z
+                );
+                (message, Severity::Info)
+            }
+            None => (format!("Not a destination: {}", typed), Severity::Warning),
+        };
+        notifications.send(Notification {
+            message,
+            severity,
+            play_sound: false,
+        });
+    }
+    **prompt = None;
+    **typing = false;
+}
+
+fn setup_destination_prompt(mut commands: Commands) {
+    let prompt_ui = NodeBundle {
+        style: Style {
+            position_type: PositionType::Absolute,
+            top: Val::Percent(45.0),
+            left: Val::Percent(30.0),
+            margin: MARGIN_UI,
+            padding: PADDING_UI,
+            border: BORDER_WIDTH,
+            ..Default::default()
+        },
+        border_color: BorderColor(LIGHT_GRAY),
+        background_color: BackgroundColor(Color::rgba(0.0, 0.0, 0.0, 0.7)),
+        visibility: Visibility::Hidden,
+        ..Default::default()
+    };
+    commands
+        .spawn((prompt_ui, DestinationPromptPanel))
+        .with_children(|prompt_ui| {
+            prompt_ui.spawn((
+                text_bundle_builder(String::new(), TITLE_FONT),
+                DestinationPromptText,
+            ));
+        });
+}
+
+fn update_destination_prompt(
+    prompt: Res<DestinationPrompt>,
+    mut panel_query: Query<&mut Visibility, With<DestinationPromptPanel>>,
+    mut text_query: Query<&mut Text, With<DestinationPromptText>>,
+) {
+    if !prompt.is_changed() {
+        return;
+    }
+    for mut visibility in panel_query.iter_mut() {
+        *visibility = match prompt.as_ref() {
+            Some(_) => Visibility::Visible,
+            None => Visibility::Hidden,
+        };
+    }
+    let Some(typed) = prompt.as_ref() else {
+        return;
+    };
+    for mut text in text_query.iter_mut() {
+        text.sections[0].value = format!("Teleport to: {}_", typed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn destinations_are_world_or_cyberspace_coordinates() {
+        let expected = Some(CyberspaceCoordinates::new(12, -3, 40));
+        assert_eq!(parse_destination("12 -3 40"), expected);
+        assert_eq!(parse_destination("X:12, Y: -3, Z: 40"), expected);
+        let far = Some(CyberspaceCoordinates::new(1 << 84, 0, 7));
+        assert_eq!(parse_destination(&format!("{} 0 7", 1i128 << 84)), far);
+        assert_eq!(parse_destination("12, 40"), None);
+        assert_eq!(parse_destination("home"), None);
+        let center = "8".repeat(64);
+        assert_eq!(
+            parse_destination(&center),
+            CyberspaceCoordinates::from_hex(&center).ok()
+        );
+    }
+}
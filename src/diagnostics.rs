@@ -0,0 +1,452 @@
+use std::{collections::VecDeque, sync::atomic::Ordering, time::Duration};
+
+use bevy::{
+    diagnostic::{
+        Diagnostic, DiagnosticPath, Diagnostics, DiagnosticsStore, EntityCountDiagnosticsPlugin,
+        FrameTimeDiagnosticsPlugin, RegisterDiagnostic,
+    },
+    prelude::*,
+};
+
+use crate::{
+    channels::queue_depth,
+    chunks::{BlockBatch, WorldChunks},
+    keybindings::{Action, KeyBindings},
+    mining::{Hashrate, POWNotes},
+    nostr::{IncomingNotes, NoteCounters, OutgoingNotes, RelayAcks, TrafficUpdates},
+    resources::{CoordinatesMap, POWBlock},
+    session::SessionPanel,
+    supervisor::{TaskStatus, TaskSupervisor},
+    ui_camera::{
+        display_hashrate, text_bundle_builder, BORDER_WIDTH, FLEX_GAP, LIGHT_GRAY, MARGIN_UI,
+        NORMAL_FONT, PADDING_UI, TITLE_FONT,
+    },
+    verification::VerificationQueue,
+};
+
+// How many samples each hop keeps for its percentiles
+const LATENCY_SAMPLES: usize = 512;
+// Note and block counters are turned into rates this often
+const THROUGHPUT_INTERVAL: Duration = Duration::from_secs(1);
+
+pub const INCOMING_NOTES: DiagnosticPath = DiagnosticPath::const_new("nostrcraft/incoming_notes");
+pub const OUTGOING_NOTES: DiagnosticPath = DiagnosticPath::const_new("nostrcraft/outgoing_notes");
+pub const SPAWNED_BLOCKS: DiagnosticPath = DiagnosticPath::const_new("nostrcraft/spawned_blocks");
+pub const HASHRATE: DiagnosticPath = DiagnosticPath::const_new("nostrcraft/hashrate");
+
+pub fn diagnostics_plugin(app: &mut App) {
+    app.add_plugins((FrameTimeDiagnosticsPlugin, EntityCountDiagnosticsPlugin))
+        .register_diagnostic(Diagnostic::new(INCOMING_NOTES).with_suffix(" notes/s"))
+        .register_diagnostic(Diagnostic::new(OUTGOING_NOTES).with_suffix(" notes/s"))
+        .register_diagnostic(Diagnostic::new(SPAWNED_BLOCKS).with_suffix(" blocks/s"))
+        .register_diagnostic(Diagnostic::new(HASHRATE).with_suffix(" H/s"))
+        .init_resource::<PipelineLatency>()
+        .add_systems(PostStartup, setup_diagnostics_overlay)
+        .add_systems(
+            Update,
+            (
+                measure_throughput,
+                toggle_diagnostics_overlay,
+                update_performance_status,
+                update_task_status,
+                update_latency_status,
+                update_world_status,
+                update_queue_status,
+            ),
+        );
+}
+
+// Stages an incoming note goes through, each measured from the end of the previous one
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PipelineHop {
+    Received,
+    Verified,
+    Parsed,
+    PowCounted,
+    Spawned,
+}
+
+impl PipelineHop {
+    const ALL: [PipelineHop; 5] = [
+        PipelineHop::Received,
+        PipelineHop::Verified,
+        PipelineHop::Parsed,
+        PipelineHop::PowCounted,
+        PipelineHop::Spawned,
+    ];
+
+    fn label(&self) -> &'static str {
+        match self {
+            PipelineHop::Received => "relay -> ECS",
+            PipelineHop::Verified => "signature checked",
+            PipelineHop::Parsed => "parsed",
+            PipelineHop::PowCounted => "POW counted",
+            PipelineHop::Spawned => "spawned",
+        }
+    }
+}
+
+#[derive(Resource, Default)]
+pub struct PipelineLatency([VecDeque<Duration>; 5]);
+
+impl PipelineLatency {
+    pub fn record(&mut self, hop: PipelineHop, latency: Duration) {
+        let samples = &mut self.0[hop as usize];
+        if samples.len() == LATENCY_SAMPLES {
+            samples.pop_front();
+        }
+        samples.push_back(latency);
+    }
+
+    pub fn percentile(&self, hop: PipelineHop, percentile: f32) -> Option<Duration> {
+        let mut samples: Vec<Duration> = self.0[hop as usize].iter().copied().collect();
+        if samples.is_empty() {
+            return None;
+        }
+        samples.sort();
+        let index = ((samples.len() - 1) as f32 * percentile).round() as usize;
+        Some(samples[index])
+    }
+}
+
+// Per second rate of a counter that went from previous to current over elapsed
+fn rate(previous: u64, current: u64, elapsed: Duration) -> f64 {
+    if elapsed.is_zero() {
+        return 0.0;
+    }
+    current.saturating_sub(previous) as f64 / elapsed.as_secs_f64()
+}
+
+#[derive(Default)]
+struct ThroughputSample {
+    elapsed: Duration,
+    incoming: u64,
+    outgoing: u64,
+    spawned: u64,
+}
+
+// Measured even while the overlay is closed so the smoothed values are ready when opened
+fn measure_throughput(
+    time: Res<Time>,
+    note_counters: Res<NoteCounters>,
+    hashrate: Res<Hashrate>,
+    spawned_blocks: Query<(), Added<POWBlock>>,
+    mut diagnostics: Diagnostics,
+    mut last_sample: Local<ThroughputSample>,
+    mut spawned: Local<u64>,
+) {
+    // Every spawn counts, replacing a block or loading a sector included
+    *spawned += spawned_blocks.iter().count() as u64;
+    last_sample.elapsed += time.delta();
+    if last_sample.elapsed < THROUGHPUT_INTERVAL {
+        return;
+    }
+    let incoming = note_counters.incoming.load(Ordering::Relaxed);
+    let outgoing = note_counters.outgoing.load(Ordering::Relaxed);
+    let spawned = *spawned;
+    let elapsed = last_sample.elapsed;
+    diagnostics.add_measurement(&INCOMING_NOTES, || {
+        rate(last_sample.incoming, incoming, elapsed)
+    });
+    diagnostics.add_measurement(&OUTGOING_NOTES, || {
+        rate(last_sample.outgoing, outgoing, elapsed)
+    });
+    diagnostics.add_measurement(&SPAWNED_BLOCKS, || {
+        rate(last_sample.spawned, spawned, elapsed)
+    });
+    diagnostics.add_measurement(&HASHRATE, || hashrate.hashes_per_second);
+    *last_sample = ThroughputSample {
+        elapsed: Duration::ZERO,
+        incoming,
+        outgoing,
+        spawned,
+    };
+}
+
+#[derive(Component)]
+struct DiagnosticsOverlay;
+
+#[derive(Component)]
+struct PerformanceText;
+
+#[derive(Component)]
+struct TaskStatusText;
+
+#[derive(Component)]
+struct LatencyText;
+
+#[derive(Component)]
+struct WorldText;
+
+#[derive(Component)]
+struct QueueText;
+
+fn setup_diagnostics_overlay(mut commands: Commands) {
+    let overlay = NodeBundle {
+        style: Style {
+            position_type: PositionType::Absolute,
+            top: Val::Percent(30.0),
+            left: Val::Px(0.0),
+            margin: MARGIN_UI,
+            padding: PADDING_UI,
+            row_gap: FLEX_GAP,
+            column_gap: FLEX_GAP,
+            flex_direction: FlexDirection::Column,
+            border: BORDER_WIDTH,
+            ..Default::default()
+        },
+        border_color: BorderColor(LIGHT_GRAY),
+        background_color: BackgroundColor(Color::rgba(0.0, 0.0, 0.0, 0.7)),
+        visibility: Visibility::Hidden,
+        ..Default::default()
+    };
+
+    commands
+        .spawn((overlay, DiagnosticsOverlay, SessionPanel("diagnostics")))
+        .with_children(|overlay| {
+            let title = text_bundle_builder("Diagnostics".to_string(), TITLE_FONT);
+            overlay.spawn(title);
+
+            let performance = text_bundle_builder(String::new(), NORMAL_FONT);
+            overlay.spawn((performance, PerformanceText));
+
+            let task_status = text_bundle_builder(String::new(), NORMAL_FONT);
+            overlay.spawn((task_status, TaskStatusText));
+
+            let latency = text_bundle_builder(String::new(), NORMAL_FONT);
+            overlay.spawn((latency, LatencyText));
+
+            let world = text_bundle_builder(String::new(), NORMAL_FONT);
+            overlay.spawn((world, WorldText));
+
+            let queues = text_bundle_builder(String::new(), NORMAL_FONT);
+            overlay.spawn((queues, QueueText));
+        });
+}
+
+fn toggle_diagnostics_overlay(
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    key_bindings: Res<KeyBindings>,
+    mut overlay_query: Query<&mut Visibility, With<DiagnosticsOverlay>>,
+) {
+    if keyboard_input.just_pressed(key_bindings.key(Action::ToggleDiagnostics)) {
+        for mut visibility in overlay_query.iter_mut() {
+            *visibility = match *visibility {
+                Visibility::Hidden => Visibility::Visible,
+                _ => Visibility::Hidden,
+            };
+        }
+    }
+}
+
+fn update_performance_status(
+    diagnostics: Res<DiagnosticsStore>,
+    overlay_query: Query<&Visibility, With<DiagnosticsOverlay>>,
+    mut text_query: Query<&mut Text, With<PerformanceText>>,
+) {
+    if !overlay_query
+        .iter()
+        .any(|visibility| *visibility == Visibility::Visible)
+    {
+        return;
+    }
+
+    let smoothed = |path: &DiagnosticPath| {
+        diagnostics
+            .get(path)
+            .and_then(|diagnostic| diagnostic.smoothed())
+            .unwrap_or_default()
+    };
+    let performance_status = format!(
+        "Performance\n  {:.0} fps ({:.2}ms)\n  {:.0} entities\n  {:.1} notes/s in, {:.1} notes/s out\n  {:.1} blocks/s spawned\n  mining at {}\n",
+        smoothed(&FrameTimeDiagnosticsPlugin::FPS),
+        smoothed(&FrameTimeDiagnosticsPlugin::FRAME_TIME),
+        smoothed(&EntityCountDiagnosticsPlugin::ENTITY_COUNT),
+        smoothed(&INCOMING_NOTES),
+        smoothed(&OUTGOING_NOTES),
+        smoothed(&SPAWNED_BLOCKS),
+        display_hashrate(smoothed(&HASHRATE))
+    );
+
+    for mut text in text_query.iter_mut() {
+        text.sections[0].value = performance_status.clone();
+    }
+}
+
+fn update_task_status(
+    supervisor: Res<TaskSupervisor>,
+    overlay_query: Query<&Visibility, With<DiagnosticsOverlay>>,
+    mut text_query: Query<&mut Text, With<TaskStatusText>>,
+) {
+    // Skip the work while the overlay is closed
+    if !overlay_query
+        .iter()
+        .any(|visibility| *visibility == Visibility::Visible)
+    {
+        return;
+    }
+
+    let mut task_status = String::from("Background tasks\n");
+    for (name, health) in supervisor.snapshot() {
+        let status = match health.status {
+            TaskStatus::Running => "running",
+            TaskStatus::Restarting => "restarting",
+            TaskStatus::Failed => "failed",
+        };
+        task_status.push_str(&format!(
+            "  {}: {} ({} restarts)\n",
+            name, status, health.restarts
+        ));
+        if let Some(error) = health.last_error {
+            task_status.push_str(&format!("    last error: {}\n", error));
+        }
+    }
+
+    for mut text in text_query.iter_mut() {
+        text.sections[0].value = task_status.clone();
+    }
+}
+
+fn update_latency_status(
+    pipeline_latency: Res<PipelineLatency>,
+    overlay_query: Query<&Visibility, With<DiagnosticsOverlay>>,
+    mut text_query: Query<&mut Text, With<LatencyText>>,
+) {
+    if !overlay_query
+        .iter()
+        .any(|visibility| *visibility == Visibility::Visible)
+    {
+        return;
+    }
+
+    let mut latency_status = String::from("Pipeline latency (p50 / p95)\n");
+    for hop in PipelineHop::ALL {
+        let p50 = pipeline_latency.percentile(hop, 0.5);
+        let p95 = pipeline_latency.percentile(hop, 0.95);
+        match (p50, p95) {
+            (Some(p50), Some(p95)) => latency_status.push_str(&format!(
+                "  {}: {:.2}ms / {:.2}ms\n",
+                hop.label(),
+                p50.as_secs_f64() * 1000.0,
+                p95.as_secs_f64() * 1000.0
+            )),
+            _ => latency_status.push_str(&format!("  {}: no samples\n", hop.label())),
+        }
+    }
+
+    for mut text in text_query.iter_mut() {
+        text.sections[0].value = latency_status.clone();
+    }
+}
+
+fn update_world_status(
+    coordinates_map: Res<CoordinatesMap>,
+    world_chunks: Res<WorldChunks>,
+    batch_query: Query<(), With<BlockBatch>>,
+    overlay_query: Query<&Visibility, With<DiagnosticsOverlay>>,
+    mut text_query: Query<&mut Text, With<WorldText>>,
+) {
+    if !overlay_query
+        .iter()
+        .any(|visibility| *visibility == Visibility::Visible)
+    {
+        return;
+    }
+
+    let spawned_blocks = coordinates_map
+        .values()
+        .filter(|(entity, _)| entity.is_some())
+        .count();
+    let world_status = format!(
+        "World\n  {} blocks known, {} spawned\n  {} sectors loaded, {} block batches\n",
+        coordinates_map.len(),
+        spawned_blocks,
+        world_chunks.loaded_sectors(),
+        batch_query.iter().count()
+    );
+
+    for mut text in text_query.iter_mut() {
+        text.sections[0].value = world_status.clone();
+    }
+}
+
+// Entries waiting in the channels between the relays, the miners and the ECS.
+// A queue that stays full means its producer is being held back
+fn update_queue_status(
+    verification_queue: Res<VerificationQueue>,
+    note_counters: Res<NoteCounters>,
+    incoming_notes: Res<IncomingNotes>,
+    outgoing_notes: Res<OutgoingNotes>,
+    pow_notes: Res<POWNotes>,
+    relay_acks: Res<RelayAcks>,
+    traffic_updates: Res<TrafficUpdates>,
+    overlay_query: Query<&Visibility, With<DiagnosticsOverlay>>,
+    mut text_query: Query<&mut Text, With<QueueText>>,
+) {
+    if !overlay_query
+        .iter()
+        .any(|visibility| *visibility == Visibility::Visible)
+    {
+        return;
+    }
+
+    let traffic = &traffic_updates.0;
+    let queue_status = format!(
+        "Queues\n  verifying: {} ({} rejected)\n  incoming notes: {}\n  outgoing notes: {} ({} missed by slow relays)\n  mined notes: {}\n  relay replies: {}\n  traffic: {} ({} dropped)\n",
+        queue_depth(verification_queue.len(), verification_queue.capacity()),
+        note_counters.rejected.load(Ordering::Relaxed),
+        queue_depth(incoming_notes.len(), incoming_notes.capacity()),
+        queue_depth(outgoing_notes.len(), outgoing_notes.capacity()),
+        note_counters.missed_outgoing.load(Ordering::Relaxed),
+        queue_depth(pow_notes.len(), pow_notes.capacity()),
+        queue_depth(relay_acks.len(), relay_acks.capacity()),
+        queue_depth(traffic.receiver.len(), traffic.receiver.capacity()),
+        traffic.dropped.load(Ordering::Relaxed)
+    );
+
+    for mut text in text_query.iter_mut() {
+        text.sections[0].value = queue_status.clone();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn latency_percentiles_use_recorded_samples() {
+        let mut latency = PipelineLatency::default();
+        assert_eq!(latency.percentile(PipelineHop::Parsed, 0.5), None);
+        for millis in 1..=100 {
+            latency.record(PipelineHop::Parsed, Duration::from_millis(millis));
+        }
+        assert_eq!(
+            latency.percentile(PipelineHop::Parsed, 0.5),
+            Some(Duration::from_millis(51))
+        );
+        assert_eq!(
+            latency.percentile(PipelineHop::Parsed, 0.95),
+            Some(Duration::from_millis(95))
+        );
+    }
+
+    #[test]
+    fn counters_become_per_second_rates() {
+        assert_eq!(rate(10, 40, Duration::from_secs(2)), 15.0);
+        assert_eq!(rate(10, 40, Duration::ZERO), 0.0);
+        // Unspawned blocks can make the count go down
+        assert_eq!(rate(40, 10, Duration::from_secs(1)), 0.0);
+    }
+
+    #[test]
+    fn latency_samples_are_bounded() {
+        let mut latency = PipelineLatency::default();
+        for _ in 0..LATENCY_SAMPLES * 2 {
+            latency.record(PipelineHop::Spawned, Duration::from_millis(1));
+        }
+        assert_eq!(
+            latency.0[PipelineHop::Spawned as usize].len(),
+            LATENCY_SAMPLES
+        );
+    }
+}
@@ -0,0 +1,355 @@
+use bevy::{
+    input::{
+        keyboard::{Key, KeyboardInput},
+        ButtonState,
+    },
+    prelude::*,
+    utils::HashMap,
+};
+use nostro2::notes::Note;
+
+use crate::{
+    keybindings::{Action, KeyBindings, Typing},
+    logging::NOSTR_LOG,
+    nostr::OutgoingNotes,
+    notifications::{Notification, Severity},
+    protocol::DIRECT_MESSAGE_KIND,
+    resources::{unix_now, Profiles},
+    ui_camera::{
+        edit_text_field, text_bundle_builder, AvatarListDetails, TextInput, BORDER_WIDTH, FLEX_GAP,
+        LIGHT_GRAY, MARGIN_UI, NORMAL_FONT, PADDING_UI, TITLE_FONT,
+    },
+    UserNostrKeys,
+};
+
+// Only the latest few fit in the window
+const SHOWN_DIRECT_MESSAGES: usize = 10;
+const MAX_DIRECT_MESSAGE_CHARS: usize = 500;
+
+pub fn direct_messages_plugin(app: &mut App) {
+    app.init_resource::<Conversations>()
+        .init_resource::<ConversationWindow>()
+        .add_event::<DirectMessageReceived>()
+        .add_systems(PostStartup, setup_conversation_window)
+        .add_systems(PreUpdate, type_direct_message.in_set(TextInput))
+        .add_systems(
+            Update,
+            (receive_direct_messages, update_conversation_window).chain(),
+        );
+}
+
+// A kind 4 note to or from us, the content is still encrypted
+#[derive(Event, Debug, Clone, PartialEq)]
+pub struct DirectMessageReceived {
+    pub id: String,
+    pub author: String,
+    pub recipient: String,
+    pub content: String,
+    pub created_at: u64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct DirectMessage {
+    id: String,
+    from_us: bool,
+    text: String,
+    created_at: u64,
+}
+
+// Decrypted messages by the public key of the other side, oldest first
+#[derive(Resource, Default, Deref, DerefMut)]
+struct Conversations(HashMap<String, Vec<DirectMessage>>);
+
+impl Conversations {
+    // Returns false for a message we already have, every relay sends its own copy
+    fn add(&mut self, peer: &str, message: DirectMessage) -> bool {
+        let conversation = self.entry(peer.to_string()).or_default();
+        if conversation.iter().any(|logged| logged.id == message.id) {
+            return false;
+        }
+        let index = conversation.partition_point(|logged| logged.created_at <= message.created_at);
+        conversation.insert(index, message);
+        true
+    }
+}
+
+struct OpenConversation {
+    peer: String,
+    typed: String,
+}
+
+// The conversation being shown, none while the window is closed
+#[derive(Resource, Default, Deref, DerefMut)]
+struct ConversationWindow(Option<OpenConversation>);
+
+#[derive(Component)]
+struct ConversationPanel;
+
+#[derive(Component)]
+struct ConversationTitle;
+
+#[derive(Component)]
+struct ConversationMessages;
+
+#[derive(Component)]
+struct ConversationInput;
+
+fn type_direct_message(
+    mut keyboard_events: EventReader<KeyboardInput>,
+    key_bindings: Res<KeyBindings>,
+    mut keyboard_input: ResMut<ButtonInput<KeyCode>>,
+    mut typing: ResMut<Typing>,
+    avatar_list: Res<AvatarListDetails>,
+    user_keys: Res<UserNostrKeys>,
+    outgoing_notes: Res<OutgoingNotes>,
+    mut window: ResMut<ConversationWindow>,
+    mut direct_messages: EventWriter<DirectMessageReceived>,
+) {
+    let Some(open) = window.as_mut() else {
+        keyboard_events.clear();
+        if **typing || !keyboard_input.just_pressed(key_bindings.key(Action::DirectMessage)) {
+            return;
+        }
+        let our_pubkey = user_keys.get_public_key();
+        if let Some(peer) = avatar_list
+            .selected_pubkey()
+            .filter(|peer| *peer != our_pubkey)
+        {
+            **window = Some(OpenConversation {
+                peer: peer.to_string(),
+                typed: String::new(),
+            });
+            **typing = true;
+            keyboard_input.reset_all();
+        }
+        return;
+    };
+    let mut closed = false;
+    for event in keyboard_events.read() {
+        if event.state != ButtonState::Pressed {
+            continue;
+        }
+        match &event.logical_key {
+            Key::Enter => {
+                let text = open.typed.trim();
+                if text.is_empty() {
+                    continue;
+                }
+                let content = match user_keys.encrypt_for(&open.peer, text) {
+                    Ok(content) => content,
+                    Err(error) => {
+                        warn!(target: NOSTR_LOG, "Could not encrypt direct message: {}", error);
+                        continue;
+                    }
+                };
+                let mut note = Note::new(user_keys.get_public_key(), DIRECT_MESSAGE_KIND, &content);
+                note.tags.push(vec!["p".to_string(), open.peer.clone()]);
+                let note = user_keys.get_keypair().sign_nostr_event(note);
+                // Shown right away, the copies relays send back are duplicates
+                direct_messages.send(DirectMessageReceived {
+                    id: note.get_id().to_string(),
+                    author: note.get_pubkey().to_string(),
+                    recipient: open.peer.clone(),
+                    content,
+                    created_at: note.get_created_at(),
+                });
+                let _sent = outgoing_notes.send(note);
+                open.typed.clear();
+            }
+            Key::Escape => closed = true,
+            key => {
+                edit_text_field(
+                    &mut open.typed,
+                    key,
+                    MAX_DIRECT_MESSAGE_CHARS,
+                    &keyboard_input,
+                );
+            }
+        }
+    }
+    // Keys typed into the window don't reach the rest of the game
+    keyboard_input.reset_all();
+    if closed {
+        **window = None;
+        **typing = false;
+    }
+}
+
+fn receive_direct_messages(
+    user_keys: Res<UserNostrKeys>,
+    profiles: Res<Profiles>,
+    window: Res<ConversationWindow>,
+    mut received: EventReader<DirectMessageReceived>,
+    mut conversations: ResMut<Conversations>,
+    mut notifications: EventWriter<Notification>,
+    mut started_at: Local<u64>,
+) {
+    // Messages from before we started are history, not news
+    if *started_at == 0 {
+        *started_at = unix_now();
+    }
+    let our_pubkey = user_keys.get_public_key();
+    for message in received.read() {
+        let from_us = message.author == our_pubkey;
+        let peer = if from_us {
+            &message.recipient
+        } else {
+            &message.author
+        };
+        // Both sides derive the same key, so ours decrypt with the recipient's public key
+        let text = match user_keys.decrypt_with(peer, &message.content) {
+            Ok(text) => text,
+            Err(error) => {
+                warn!(
+                    target: NOSTR_LOG,
+                    "Could not decrypt direct message {}: {}",
+                    message.id,
+                    error
+                );
+                continue;
+            }
+        };
+        let added = conversations.add(
+            peer,
+            DirectMessage {
+                id: message.id.clone(),
+                from_us,
+                text,
+                created_at: message.created_at,
+            },
+        );
+        let showing = window.as_ref().is_some_and(|open| open.peer == *peer);
+        if added && !from_us && !showing && message.created_at >= *started_at {
+            notifications.send(Notification {
+                message: format!("Message from {}", profiles.display_name(peer)),
+                severity: Severity::Info,
+                play_sound: true,
+            });
+        }
+    }
+}
+
+fn setup_conversation_window(mut commands: Commands) {
+    let conversation_ui = NodeBundle {
+        style: Style {
+            position_type: PositionType::Absolute,
+            bottom: Val::Percent(30.0),
+            left: Val::Percent(35.0),
+            max_width: Val::Percent(30.0),
+            margin: MARGIN_UI,
+            padding: PADDING_UI,
+            row_gap: FLEX_GAP,
+            flex_direction: FlexDirection::Column,
+            border: BORDER_WIDTH,
+            ..Default::default()
+        },
+        border_color: BorderColor(LIGHT_GRAY),
+        background_color: BackgroundColor(Color::rgba(0.0, 0.0, 0.0, 0.7)),
+        visibility: Visibility::Hidden,
+        ..Default::default()
+    };
+    commands
+        .spawn((conversation_ui, ConversationPanel))
+        .with_children(|conversation_ui| {
+            conversation_ui.spawn((
+                text_bundle_builder(String::new(), TITLE_FONT),
+                ConversationTitle,
+            ));
+            conversation_ui.spawn((
+                text_bundle_builder(String::new(), NORMAL_FONT),
+                ConversationMessages,
+            ));
+            conversation_ui.spawn((
+                text_bundle_builder(String::new(), NORMAL_FONT),
+                ConversationInput,
+            ));
+            conversation_ui.spawn(text_bundle_builder(
+                "Encrypted with NIP-04, Enter to send, Esc to close".to_string(),
+                NORMAL_FONT,
+            ));
+        });
+}
+
+fn conversation_lines(messages: &[DirectMessage], peer_name: &str) -> String {
+    messages
+        .iter()
+        .skip(messages.len().saturating_sub(SHOWN_DIRECT_MESSAGES))
+        .map(|message| {
+            let author = if message.from_us { "You" } else { peer_name };
+            format!("{}: {}", author, message.text)
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn update_conversation_window(
+    window: Res<ConversationWindow>,
+    conversations: Res<Conversations>,
+    profiles: Res<Profiles>,
+    mut panel_query: Query<&mut Visibility, With<ConversationPanel>>,
+    mut text_query: Query<
+        (
+            &mut Text,
+            Has<ConversationTitle>,
+            Has<ConversationMessages>,
+            Has<ConversationInput>,
+        ),
+        Or<(
+            With<ConversationTitle>,
+            With<ConversationMessages>,
+            With<ConversationInput>,
+        )>,
+    >,
+) {
+    if !window.is_changed() && !conversations.is_changed() && !profiles.is_changed() {
+        return;
+    }
+    for mut visibility in panel_query.iter_mut() {
+        *visibility = match window.as_ref() {
+            Some(_) => Visibility::Visible,
+            None => Visibility::Hidden,
+        };
+    }
+    let Some(open) = window.as_ref() else {
+        return;
+    };
+    let peer_name = profiles.display_name(&open.peer);
+    for (mut text, title, messages, input) in text_query.iter_mut() {
+        if title {
+            text.sections[0].value = format!("Messages with {}", peer_name);
+        } else if messages {
+            text.sections[0].value = match conversations.get(&open.peer) {
+                Some(conversation) => conversation_lines(conversation, &peer_name),
+                None => "No messages yet".to_string(),
+            };
+        } else if input {
+            text.sections[0].value = format!("> {}_", open.typed);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(id: &str, from_us: bool, created_at: u64) -> DirectMessage {
+        DirectMessage {
+            id: id.to_string(),
+            from_us,
+            text: id.to_string(),
+            created_at,
+        }
+    }
+
+    #[test]
+    fn conversations_order_and_deduplicate() {
+        let mut conversations = Conversations::default();
+        assert!(conversations.add("peer", message("reply", false, 20)));
+        assert!(conversations.add("peer", message("hello", true, 10)));
+        assert!(!conversations.add("peer", message("reply", false, 20)));
+        assert_eq!(
+            conversation_lines(&conversations["peer"], "Satoshi"),
+            "You: hello\nSatoshi: reply"
+        );
+    }
+}
@@ -0,0 +1,219 @@
+use bevy::{prelude::*, utils::HashSet};
+
+use crate::{
+    cameras::BlockIndicator,
+    chunks::WorldChunks,
+    cyberspace::CyberspaceCoordinates,
+    keybindings::{Action, KeyBindings},
+    logging::WORLD_LOG,
+    notifications::{Notification, Severity},
+    origin::FloatingOrigin,
+    resources::{
+        block_tier, tier_name, CoordinatesMap, ADAMANT, BRONZE, GOLD, IRON, MITHRIL, RUNE, STEEL,
+    },
+};
+
+const EXPORT_OBJ_PATH: &str = "./nostrcraft_export.obj";
+const EXPORT_MTL_FILE: &str = "nostrcraft_export.mtl";
+const EXPORT_MTL_PATH: &str = "./nostrcraft_export.mtl";
+const MUD: Color = Color::rgb_linear(0.35, 0.25, 0.15);
+// Corners of a block around its center, same size as the cube mesh
+const CUBE_CORNERS: [Vec3; 8] = [
+    Vec3::new(-0.5, -0.5, -0.5),
+    Vec3::new(0.5, -0.5, -0.5),
+    Vec3::new(0.5, 0.5, -0.5),
+    Vec3::new(-0.5, 0.5, -0.5),
+    Vec3::new(-0.5, -0.5, 0.5),
+    Vec3::new(0.5, -0.5, 0.5),
+    Vec3::new(0.5, 0.5, 0.5),
+    Vec3::new(-0.5, 0.5, 0.5),
+];
+// Counter clockwise from outside, indices into `CUBE_CORNERS`
+const CUBE_FACES: [[usize; 4]; 6] = [
+    [0, 3, 2, 1],
+    [4, 5, 6, 7],
+    [0, 1, 5, 4],
+    [2, 3, 7, 6],
+    [1, 2, 6, 5],
+    [0, 4, 7, 3],
+];
+
+pub fn export_plugin(app: &mut App) {
+    app.add_systems(Update, export_controls);
+}
+
+// A mined block as written to the export
+struct ExportedBlock<'a> {
+    position: Vec3,
+    tier: usize,
+    owner: &'a str,
+    pow_amount: usize,
+}
+
+fn export_controls(
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    key_bindings: Res<KeyBindings>,
+    block_indicator: Query<&Transform, With<BlockIndicator>>,
+    origin: Res<FloatingOrigin>,
+    world_chunks: Res<WorldChunks>,
+    coordinates_map: Res<CoordinatesMap>,
+    mut notifications: EventWriter<Notification>,
+) {
+    if !keyboard_input.just_pressed(key_bindings.key(Action::Export)) {
+        return;
+    }
+    let Ok(indicator_transform) = block_indicator.get_single() else {
+        return;
+    };
+    // Blender handles small coordinates better, so the export is centered on the indicator
+    let center = origin.coordinates(indicator_transform.translation);
+
+    let blocks: Vec<ExportedBlock> = world_chunks
+        .loaded_coordinates()
+        .filter_map(|coordinates| coordinates_map.get(coordinates))
+        .map(|(_, block_details)| ExportedBlock {
+            position: block_details.coordinates().relative_to(center),
+            tier: block_tier(block_details.pow_amount, block_details.material),
+            owner: block_details.owner(),
+            pow_amount: block_details.pow_amount,
+        })
+        .collect();
+
+    let written = std::fs::write(EXPORT_OBJ_PATH, blocks_to_obj(&blocks, center))
+        .and_then(|_| std::fs::write(EXPORT_MTL_PATH, blocks_to_mtl(&blocks)));
+    let (message, severity) = match written {
+        Ok(_) => (
+            format!("Exported {} blocks to {}", blocks.len(), EXPORT_OBJ_PATH),
+            Severity::Success,
+        ),
+        Err(error) => {
+            warn!(target: WORLD_LOG, "Could not write {}: {}", EXPORT_OBJ_PATH, error);
+            (
+                format!("Could not export blocks: {}", error),
+                Severity::Error,
+            )
+        }
+    };
+    notifications.send(Notification {
+        message,
+        severity,
+        play_sound: false,
+    });
+}
+
+// One object per block, named after its position and owner so they can be picked apart in Blender
+fn blocks_to_obj(blocks: &[ExportedBlock], center: CyberspaceCoordinates) -> String {
+    let mut obj = format!(
+        "# NostrCraft export centered on X: {} Y: {} Z: {}\nmtllib {}\n",
+        center.x, center.y, center.z, EXPORT_MTL_FILE
+    );
+    for (index, block) in blocks.iter().enumerate() {
+        // Owners can come from transfer notes, anything but a key's characters could break a line
+        let owner: String = block
+            .owner
+            .chars()
+            .filter(char::is_ascii_alphanumeric)
+            .collect();
+        obj.push_str(&format!(
+            "o block_{}_{}_{}_{}\n# owner {} pow {}\n",
+            block.position.x,
+            block.position.y,
+            block.position.z,
+            owner.chars().take(8).collect::<String>(),
+            owner,
+            block.pow_amount
+        ));
+        for corner in CUBE_CORNERS {
+            let vertex = block.position + corner;
+            obj.push_str(&format!("v {} {} {}\n", vertex.x, vertex.y, vertex.z));
+        }
+        obj.push_str(&format!("usemtl {}\n", tier_name(block.tier)));
+        // OBJ indices are 1 based and count every vertex in the file
+        let first_vertex = index * CUBE_CORNERS.len() + 1;
+        for face in CUBE_FACES {
+            let [a, b, c, d] = face.map(|corner| first_vertex + corner);
+            obj.push_str(&format!("f {} {} {} {}\n", a, b, c, d));
+        }
+    }
+    obj
+}
+
+fn blocks_to_mtl(blocks: &[ExportedBlock]) -> String {
+    let mut mtl = String::new();
+    let mut written = HashSet::new();
+    for block in blocks {
+        let name = tier_name(block.tier);
+        if !written.insert(name) {
+            continue;
+        }
+        let [red, green, blue] = tier_color(block.tier);
+        mtl.push_str(&format!(
+            "newmtl {}\nKd {:.3} {:.3} {:.3}\n",
+            name, red, green, blue
+        ));
+    }
+    mtl
+}
+
+// The in game colors are HDR for the glowing tiers, OBJ wants 0..1
+fn tier_color(tier: usize) -> [f32; 3] {
+    let color = match tier {
+        0 | 1 => MUD,
+        2 => BRONZE,
+        3 => IRON,
+        4 => STEEL,
+        5 => MITHRIL,
+        6 => ADAMANT,
+        7 => RUNE,
+        _ => GOLD,
+    };
+    let [red, green, blue, _] = color.as_linear_rgba_f32();
+    let brightest = red.max(green).max(blue).max(1.0);
+    [red / brightest, green / brightest, blue / brightest]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::resources::TOP_MATERIAL_TIER;
+
+    #[test]
+    fn obj_has_a_cube_per_block_and_a_material_per_tier() {
+        let blocks = [
+            ExportedBlock {
+                position: Vec3::ZERO,
+                tier: 2,
+                owner: "abcdef0123456789",
+                pow_amount: 8,
+            },
+            ExportedBlock {
+                position: Vec3::X,
+                tier: 2,
+                owner: "abcdef0123456789",
+                pow_amount: 9,
+            },
+        ];
+        let obj = blocks_to_obj(&blocks, CyberspaceCoordinates::default());
+        assert_eq!(
+            obj.lines().filter(|line| line.starts_with("v ")).count(),
+            16
+        );
+        assert_eq!(
+            obj.lines().filter(|line| line.starts_with("f ")).count(),
+            12
+        );
+        assert!(obj.contains("f 9 12 11 10"));
+        assert!(obj.contains("# owner abcdef0123456789 pow 9"));
+        assert_eq!(blocks_to_mtl(&blocks).matches("newmtl").count(), 1);
+        let injected = ExportedBlock {
+            owner: "é\nv 1 1 1",
+            ..blocks[0]
+        };
+        let obj = blocks_to_obj(&[injected], CyberspaceCoordinates::default());
+        assert!(obj.contains("# owner v111 pow 8"));
+        assert_eq!(obj.lines().filter(|line| line.starts_with("v ")).count(), 8);
+        assert!(tier_color(TOP_MATERIAL_TIER)
+            .iter()
+            .all(|channel| *channel <= 1.0));
+    }
+}
@@ -0,0 +1,285 @@
+use bevy::{
+    input::{keyboard::KeyboardInput, mouse::MouseButtonInput, InputSystem},
+    prelude::*,
+};
+
+use crate::{
+    cameras::BlockIndicator,
+    chunks::WorldChunks,
+    keybindings::{key_name, Action, KeyBindings},
+    mining::{toggle_unmined_block, UnminedBlockMap},
+    origin::FloatingOrigin,
+    resources::MeshesAndMaterials,
+    ui_camera::{text_bundle_builder, MARGIN_UI, NORMAL_FONT},
+};
+
+// Stick and trigger values below this are treated as resting
+const STICK_DEAD_ZONE: f32 = 0.15;
+const PLACE_BUTTON: GamepadButtonType = GamepadButtonType::South;
+// Buttons press the key bound to their action, so every keyboard control works from the pad
+// and follows the key bindings
+const GAMEPAD_ACTIONS: [(GamepadButtonType, Action); 12] = [
+    (GamepadButtonType::East, Action::Mine),
+    (GamepadButtonType::West, Action::StopMining),
+    (GamepadButtonType::North, Action::CycleMaterial),
+    (GamepadButtonType::LeftTrigger, Action::PreviousQueued),
+    (GamepadButtonType::RightTrigger, Action::NextQueued),
+    (GamepadButtonType::Select, Action::FirstPerson),
+    (GamepadButtonType::Start, Action::ToggleSettings),
+    (GamepadButtonType::RightThumb, Action::NoClip),
+    (GamepadButtonType::DPadUp, Action::GoHome),
+    (GamepadButtonType::DPadDown, Action::TeleportToAvatar),
+    (GamepadButtonType::DPadLeft, Action::PreviousAvatar),
+    (GamepadButtonType::DPadRight, Action::NextAvatar),
+];
+const GAMEPAD_HINTS: &str =
+    "Left stick move, Triggers up and down, (A) place, (B) mine, (X) stop, \
+    (Y) material, Right stick orbit, D-pad Up home";
+
+pub fn gamepad_plugin(app: &mut App) {
+    app.init_resource::<GamepadSticks>()
+        .init_resource::<InputDevice>()
+        .add_systems(PostStartup, setup_control_hints)
+        .add_systems(
+            PreUpdate,
+            (read_gamepad_sticks, press_gamepad_actions).after(InputSystem),
+        )
+        .add_systems(
+            Update,
+            (
+                place_with_gamepad,
+                (detect_input_device, update_control_hints).chain(),
+            ),
+        );
+}
+
+// Analog input of the first connected gamepad, already past the dead zone
+#[derive(Resource, Default, Debug, Clone, Copy, PartialEq)]
+pub struct GamepadSticks {
+    // Blocks per second as a fraction of walking speed, -Z is forward like W
+    pub movement: Vec3,
+    // Right stick, +Y is up
+    pub look: Vec2,
+}
+
+// Whichever was used last picks the hints shown
+#[derive(Resource, Default, Debug, Clone, Copy, PartialEq)]
+enum InputDevice {
+    #[default]
+    Keyboard,
+    Gamepad,
+}
+
+#[derive(Component)]
+struct ControlHints;
+
+fn dead_zone(value: f32) -> f32 {
+    if value.abs() < STICK_DEAD_ZONE {
+        0.0
+    } else {
+        value
+    }
+}
+
+// The left stick moves across the ground, the triggers raise and lower
+fn stick_movement(left_stick: Vec2, raise: f32, lower: f32) -> Vec3 {
+    Vec3::new(
+        dead_zone(left_stick.x),
+        dead_zone(raise) - dead_zone(lower),
+        -dead_zone(left_stick.y),
+    )
+}
+
+fn read_gamepad_sticks(
+    gamepads: Res<Gamepads>,
+    axes: Res<Axis<GamepadAxis>>,
+    button_axes: Res<Axis<GamepadButton>>,
+    mut gamepad_sticks: ResMut<GamepadSticks>,
+) {
+    let sticks = gamepads
+        .iter()
+        .next()
+        .map(|gamepad| {
+            let axis = |axis_type| {
+                axes.get(GamepadAxis::new(gamepad, axis_type))
+                    .unwrap_or_default()
+            };
+            let trigger = |button_type| {
+                button_axes
+                    .get(GamepadButton::new(gamepad, button_type))
+                    .unwrap_or_default()
+            };
+            GamepadSticks {
+                movement: stick_movement(
+                    Vec2::new(
+                        axis(GamepadAxisType::LeftStickX),
+                        axis(GamepadAxisType::LeftStickY),
+                    ),
+                    trigger(GamepadButtonType::RightTrigger2),
+                    trigger(GamepadButtonType::LeftTrigger2),
+                ),
+                look: Vec2::new(
+                    dead_zone(axis(GamepadAxisType::RightStickX)),
+                    dead_zone(axis(GamepadAxisType::RightStickY)),
+                ),
+            }
+        })
+        .unwrap_or_default();
+    if *gamepad_sticks != sticks {
+        *gamepad_sticks = sticks;
+    }
+}
+
+fn press_gamepad_actions(
+    gamepads: Res<Gamepads>,
+    buttons: Res<ButtonInput<GamepadButton>>,
+    key_bindings: Res<KeyBindings>,
+    mut keyboard_input: ResMut<ButtonInput<KeyCode>>,
+) {
+    for gamepad in gamepads.iter() {
+        for (button_type, action) in GAMEPAD_ACTIONS {
+            let button = GamepadButton::new(gamepad, button_type);
+            if buttons.just_pressed(button) {
+                keyboard_input.press(key_bindings.key(action));
+            }
+            if buttons.just_released(button) {
+                keyboard_input.release(key_bindings.key(action));
+            }
+        }
+    }
+}
+
+// Same as a left click, places or removes an unmined block at the indicator
+fn place_with_gamepad(
+    mut commands: Commands,
+    stuff: Res<MeshesAndMaterials>,
+    gamepads: Res<Gamepads>,
+    buttons: Res<ButtonInput<GamepadButton>>,
+    block_indicator: Query<&Transform, With<BlockIndicator>>,
+    origin: Res<FloatingOrigin>,
+    world_chunks: Res<WorldChunks>,
+    mut unmined_block_map: ResMut<UnminedBlockMap>,
+) {
+    let placing = gamepads
+        .iter()
+        .any(|gamepad| buttons.just_pressed(GamepadButton::new(gamepad, PLACE_BUTTON)));
+    if !placing {
+        return;
+    }
+    let Ok(indicator_transform) = block_indicator.get_single() else {
+        return;
+    };
+    toggle_unmined_block(
+        &mut commands,
+        &stuff,
+        &mut unmined_block_map,
+        origin.coordinates(indicator_transform.translation),
+        world_chunks.plane(),
+    );
+}
+
+fn setup_control_hints(mut commands: Commands) {
+    let hints_ui = NodeBundle {
+        style: Style {
+            position_type: PositionType::Absolute,
+            bottom: Val::Px(0.0),
+            left: Val::Percent(20.0),
+            margin: MARGIN_UI,
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    commands.spawn(hints_ui).with_children(|hints_ui| {
+        hints_ui.spawn((
+            text_bundle_builder(String::new(), NORMAL_FONT),
+            ControlHints,
+        ));
+    });
+}
+
+// Synthetic presses from the pad don't send keyboard events, so they don't count as the keyboard
+fn detect_input_device(
+    mut keyboard_events: EventReader<KeyboardInput>,
+    mut mouse_events: EventReader<MouseButtonInput>,
+    buttons: Res<ButtonInput<GamepadButton>>,
+    gamepad_sticks: Res<GamepadSticks>,
+    mut input_device: ResMut<InputDevice>,
+) {
+    let used = if buttons.get_just_pressed().next().is_some()
+        || *gamepad_sticks != GamepadSticks::default()
+    {
+        InputDevice::Gamepad
+    } else if keyboard_events.read().next().is_some() || mouse_events.read().next().is_some() {
+        InputDevice::Keyboard
+    } else {
+        return;
+    };
+    keyboard_events.clear();
+    mouse_events.clear();
+    if *input_device != used {
+        *input_device = used;
+    }
+}
+
+fn control_hints(input_device: InputDevice, key_bindings: &KeyBindings) -> String {
+    match input_device {
+        InputDevice::Gamepad => GAMEPAD_HINTS.to_string(),
+        InputDevice::Keyboard => {
+            let key = |action| key_name(key_bindings.key(action));
+            format!(
+                "{}{}{}{} move, {} {} up and down, Click place, {} mine, {} stop, \
+                {} material, Right drag orbit, {} home",
+                key(Action::MoveForward),
+                key(Action::MoveLeft),
+                key(Action::MoveBack),
+                key(Action::MoveRight),
+                key(Action::MoveUp),
+                key(Action::MoveDown),
+                key(Action::Mine),
+                key(Action::StopMining),
+                key(Action::CycleMaterial),
+                key(Action::GoHome),
+            )
+        }
+    }
+}
+
+fn update_control_hints(
+    input_device: Res<InputDevice>,
+    key_bindings: Res<KeyBindings>,
+    mut text_query: Query<&mut Text, With<ControlHints>>,
+) {
+    if !input_device.is_changed() && !key_bindings.is_changed() {
+        return;
+    }
+    let hints = control_hints(*input_device, &key_bindings);
+    for mut text in text_query.iter_mut() {
+        text.sections[0].value = hints.clone();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sticks_rest_inside_the_dead_zone() {
+        assert_eq!(stick_movement(Vec2::new(0.1, -0.1), 0.05, 0.0), Vec3::ZERO);
+        assert_eq!(
+            stick_movement(Vec2::new(0.5, 1.0), 0.0, 0.75),
+            Vec3::new(0.5, -0.75, -1.0)
+        );
+    }
+
+    #[test]
+    fn keyboard_hints_follow_the_bindings() {
+        let key_bindings = KeyBindings::default();
+        let hints = control_hints(InputDevice::Keyboard, &key_bindings);
+        assert!(hints.starts_with("WASD move, Q E up and down"));
+        assert!(hints.contains("M mine"));
+        assert_eq!(
+            control_hints(InputDevice::Gamepad, &key_bindings),
+            GAMEPAD_HINTS
+        );
+    }
+}
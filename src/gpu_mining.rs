@@ -0,0 +1,302 @@
+use std::{
+    borrow::Cow,
+    sync::{
+        atomic::{AtomicU64, AtomicU8, AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Instant,
+};
+
+use bevy::prelude::*;
+use crossbeam_channel::Sender;
+use cryptoxide::digest::Digest;
+use cryptoxide::sha2::Sha256;
+use nostro2::{
+    notes::{Note, SignedNote},
+    userkeys::UserKeys,
+};
+use tokio::sync::OnceCell;
+use tokio_util::sync::CancellationToken;
+use wgpu::util::DeviceExt;
+
+use crate::{
+    channels::send_when_ready,
+    logging::MINING_LOG,
+    mining::{count_leading_zero_bits, nonce_tag, throttle_delay, MiningAction},
+};
+
+const SHADER: &str = include_str!("gpu_mining.wgsl");
+// 16384 workgroups of 64 invocations, well within the 9 digits the shader writes
+const WORKGROUPS_PER_DISPATCH: u32 = 16_384;
+const HASHES_PER_DISPATCH: u64 = WORKGROUPS_PER_DISPATCH as u64 * 64;
+// The shader fills in the last 9 digits of the nonce, the CPU picks the rest per dispatch
+const GPU_NONCE_DIGITS: usize = 9;
+
+// Shared by every GPU miner, `None` when no adapter could be found
+static GPU_MINER: OnceCell<Option<GpuMiner>> = OnceCell::const_new();
+
+struct GpuMiner {
+    device: wgpu::Device,
+    queue: wgpu::Queue,
+    pipeline: wgpu::ComputePipeline,
+}
+
+impl GpuMiner {
+    async fn new() -> Option<Self> {
+        let instance = wgpu::Instance::new(wgpu::InstanceDescriptor::default());
+        let adapter = instance
+            .request_adapter(&wgpu::RequestAdapterOptions {
+                power_preference: wgpu::PowerPreference::HighPerformance,
+                compatible_surface: None,
+                force_fallback_adapter: false,
+            })
+            .await?;
+        let (device, queue) = adapter
+            .request_device(&wgpu::DeviceDescriptor::default(), None)
+            .await
+            .ok()?;
+
+        let module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("sha256_miner"),
+            source: wgpu::ShaderSource::Wgsl(Cow::Borrowed(SHADER)),
+        });
+        let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("sha256_miner"),
+            layout: None,
+            module: &module,
+            entry_point: "main",
+        });
+
+        info!(target: MINING_LOG, "GPU mining on {}", adapter.get_info().name);
+        Some(GpuMiner {
+            device,
+            queue,
+            pipeline,
+        })
+    }
+
+    // Runs one dispatch over the template and returns the winning nonce digits, if any
+    fn grind(&self, message: &[u8], nonce_offset: usize, target: usize) -> Option<u32> {
+        let message_words = pack_message(message);
+        let params: Vec<u8> = [
+            message_words.len() as u32,
+            nonce_offset as u32,
+            target as u32,
+            0,
+        ]
+        .iter()
+        .flat_map(|value| value.to_le_bytes())
+        .collect();
+        let message_bytes: Vec<u8> = message_words
+            .iter()
+            .flat_map(|word| word.to_le_bytes())
+            .collect();
+
+        let params_buffer = self
+            .device
+            .create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some("miner_params"),
+                contents: &params,
+                usage: wgpu::BufferUsages::UNIFORM,
+            });
+        let message_buffer = self
+            .device
+            .create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some("miner_message"),
+                contents: &message_bytes,
+                usage: wgpu::BufferUsages::STORAGE,
+            });
+        let result_buffer = self
+            .device
+            .create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some("miner_result"),
+                contents: &[0; 8],
+                usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+            });
+        let staging_buffer = self.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("miner_staging"),
+            size: 8,
+            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let bind_group = self.device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("miner_bind_group"),
+            layout: &self.pipeline.get_bind_group_layout(0),
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: params_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: message_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: result_buffer.as_entire_binding(),
+                },
+            ],
+        });
+
+        let mut encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor::default());
+        {
+            let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                label: Some("miner_pass"),
+                timestamp_writes: None,
+            });
+            compute_pass.set_pipeline(&self.pipeline);
+            compute_pass.set_bind_group(0, &bind_group, &[]);
+            compute_pass.dispatch_workgroups(WORKGROUPS_PER_DISPATCH, 1, 1);
+        }
+        encoder.copy_buffer_to_buffer(&result_buffer, 0, &staging_buffer, 0, 8);
+        self.queue.submit(Some(encoder.finish()));
+
+        let result_slice = staging_buffer.slice(..);
+        result_slice.map_async(wgpu::MapMode::Read, |_| {});
+        self.device.poll(wgpu::Maintain::Wait);
+        let result = result_slice.get_mapped_range();
+        let found = u32::from_le_bytes([result[0], result[1], result[2], result[3]]);
+        let nonce = u32::from_le_bytes([result[4], result[5], result[6], result[7]]);
+        drop(result);
+        staging_buffer.unmap();
+
+        (found == 1).then_some(nonce)
+    }
+}
+
+// Returns false when there is no usable GPU, so the caller can use the CPU instead
+pub async fn mine_pow_event_gpu(
+    coordinate: &str,
+    action: &MiningAction,
+    best_pow: &Arc<AtomicUsize>,
+    writer_arc_clone: &Arc<Sender<SignedNote>>,
+    cancel_token: &CancellationToken,
+    key_ref: &Arc<UserKeys>,
+    hash_counter: &Arc<AtomicU64>,
+    worker_hashes: &Arc<AtomicU64>,
+    intensity: &Arc<AtomicU8>,
+) -> bool {
+    let Some(gpu_miner) = GPU_MINER.get_or_init(GpuMiner::new).await else {
+        return false;
+    };
+
+    let mut nonce_prefix: u64 = 0;
+    info!(target: MINING_LOG, "Starting GPU POW Miner");
+
+    while !cancel_token.is_cancelled() {
+        let target = best_pow.load(Ordering::Relaxed) + 1;
+        let mut template_note = action.note(key_ref.get_public_key(), coordinate, target);
+
+        // The nonce is the prefix followed by 9 placeholder digits the GPU fills in
+        let nonce_placeholder = format!("{}{}", nonce_prefix, "0".repeat(GPU_NONCE_DIGITS));
+        template_note
+            .tags
+            .push(nonce_tag(&nonce_placeholder, target));
+        let template = template_note.serialize_for_nostr();
+        let nonce_tag_start = format!("\"nonce\",\"{}", nonce_placeholder);
+        let Some(tag_offset) = template.find(&nonce_tag_start) else {
+            warn!(target: MINING_LOG, "Could not find the nonce in the note template");
+            return false;
+        };
+        let nonce_offset = tag_offset + nonce_tag_start.len() - GPU_NONCE_DIGITS;
+        nonce_prefix += 1;
+
+        let dispatch_started = Instant::now();
+        // Waiting on the GPU blocks, so keep it off the runtime's workers
+        let dispatch = tokio::task::spawn_blocking(move || {
+            gpu_miner.grind(template.as_bytes(), nonce_offset, target)
+        });
+        let Ok(nonce_digits) = dispatch.await else {
+            warn!(target: MINING_LOG, "GPU dispatch panicked, mining on the CPU instead");
+            return false;
+        };
+        hash_counter.fetch_add(HASHES_PER_DISPATCH, Ordering::Relaxed);
+        worker_hashes.fetch_add(HASHES_PER_DISPATCH, Ordering::Relaxed);
+        let delay = throttle_delay(
+            dispatch_started.elapsed(),
+            intensity.load(Ordering::Relaxed),
+        );
+        if !delay.is_zero() {
+            tokio::time::sleep(delay).await;
+        }
+        let Some(nonce_digits) = nonce_digits else {
+            continue;
+        };
+
+        // Rebuild the winning note and double check its id on the CPU before signing
+        let nonce = format!("{}{:09}", nonce_prefix - 1, nonce_digits);
+        let pow_note = with_nonce(&template_note, &nonce, target);
+
+        let mut hasher = Sha256::new();
+        hasher.input_str(&pow_note.serialize_for_nostr());
+        let mut result = [0u8; 32];
+        hasher.result(&mut result);
+
+        let leading_zeroes_in_id = count_leading_zero_bits(&result);
+        if leading_zeroes_in_id < target {
+            warn!(target: MINING_LOG, "GPU nonce {} did not verify on the CPU", nonce);
+        } else if best_pow.fetch_max(leading_zeroes_in_id, Ordering::Relaxed) < leading_zeroes_in_id
+        {
+            let signed_note = key_ref.sign_nostr_event(pow_note);
+            let _sent = send_when_ready(writer_arc_clone, signed_note).await;
+        }
+    }
+    info!(target: MINING_LOG, "Stopping GPU POW Miner");
+    true
+}
+
+// The template with its trailing placeholder nonce tag swapped for the found nonce,
+// the sector cell tags before it stay
+fn with_nonce(template_note: &Note, nonce: &str, target: usize) -> Note {
+    let mut pow_note = template_note.clone();
+    pow_note.tags.pop();
+    pow_note.tags.push(nonce_tag(nonce, target));
+    pow_note
+}
+
+// SHA-256 padding, packed into big endian words
+fn pack_message(message: &[u8]) -> Vec<u32> {
+    let mut padded = message.to_vec();
+    padded.push(0x80);
+    while padded.len() % 64 != 56 {
+        padded.push(0);
+    }
+    padded.extend_from_slice(&((message.len() as u64) * 8).to_be_bytes());
+    padded
+        .chunks(4)
+        .map(|chunk| u32::from_be_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pack_message_pads_to_whole_blocks() {
+        let packed = pack_message(b"abc");
+        assert_eq!(packed.len(), 16);
+        assert_eq!(packed[0], 0x61626380);
+        assert_eq!(packed[15], 24);
+    }
+
+    #[test]
+    fn found_nonces_keep_the_cell_tags() {
+        let action = MiningAction::Place {
+            material: None,
+            label: None,
+        };
+        let coordinates = crate::cyberspace::encode_coordinates(1, 2, 3);
+        let mut template_note = action.note("ab".repeat(32), &coordinates, 12);
+        let cell_tags = template_note.tags.clone();
+        assert!(!cell_tags.is_empty());
+        template_note.tags.push(nonce_tag("0000000000", 12));
+        let pow_note = with_nonce(&template_note, "4123456789", 12);
+        assert_eq!(pow_note.tags[..cell_tags.len()], cell_tags[..]);
+        assert_eq!(pow_note.tags.last(), Some(&nonce_tag("4123456789", 12)));
+        assert_eq!(pow_note.tags.len(), cell_tags.len() + 1);
+    }
+}
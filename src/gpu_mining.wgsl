@@ -0,0 +1,131 @@
+// SHA-256 nonce grinder for NIP-13 proof of work.
+// The CPU uploads the padded, serialized note with a placeholder nonce. Every invocation
+// writes its own index as the last 9 decimal digits of the nonce, hashes the note and
+// reports back if the id meets the target difficulty.
+
+struct Params {
+    message_words: u32,
+    nonce_offset: u32,
+    target: u32,
+    padding: u32,
+}
+
+struct MiningResult {
+    found: atomic<u32>,
+    nonce: atomic<u32>,
+}
+
+@group(0) @binding(0) var<uniform> params: Params;
+@group(0) @binding(1) var<storage, read> message: array<u32>;
+@group(0) @binding(2) var<storage, read_write> result: MiningResult;
+
+const NONCE_DIGITS: u32 = 9u;
+
+var<private> K: array<u32, 64> = array<u32, 64>(
+    0x428a2f98u, 0x71374491u, 0xb5c0fbcfu, 0xe9b5dba5u, 0x3956c25bu, 0x59f111f1u, 0x923f82a4u, 0xab1c5ed5u,
+    0xd807aa98u, 0x12835b01u, 0x243185beu, 0x550c7dc3u, 0x72be5d74u, 0x80deb1feu, 0x9bdc06a7u, 0xc19bf174u,
+    0xe49b69c1u, 0xefbe4786u, 0x0fc19dc6u, 0x240ca1ccu, 0x2de92c6fu, 0x4a7484aau, 0x5cb0a9dcu, 0x76f988dau,
+    0x983e5152u, 0xa831c66du, 0xb00327c8u, 0xbf597fc7u, 0xc6e00bf3u, 0xd5a79147u, 0x06ca6351u, 0x14292967u,
+    0x27b70a85u, 0x2e1b2138u, 0x4d2c6dfcu, 0x53380d13u, 0x650a7354u, 0x766a0abbu, 0x81c2c92eu, 0x92722c85u,
+    0xa2bfe8a1u, 0xa81a664bu, 0xc24b8b70u, 0xc76c51a3u, 0xd192e819u, 0xd6990624u, 0xf40e3585u, 0x106aa070u,
+    0x19a4c116u, 0x1e376c08u, 0x2748774cu, 0x34b0bcb5u, 0x391c0cb3u, 0x4ed8aa4au, 0x5b9cca4fu, 0x682e6ff3u,
+    0x748f82eeu, 0x78a5636fu, 0x84c87814u, 0x8cc70208u, 0x90befffau, 0xa4506cebu, 0xbef9a3f7u, 0xc67178f2u,
+);
+
+fn rotr(x: u32, n: u32) -> u32 {
+    return (x >> n) | (x << (32u - n));
+}
+
+// ASCII digit of the nonce, index 0 being the most significant
+fn nonce_digit(nonce: u32, index: u32) -> u32 {
+    var value = nonce;
+    for (var i = index + 1u; i < NONCE_DIGITS; i++) {
+        value = value / 10u;
+    }
+    return 48u + value % 10u;
+}
+
+fn message_byte(word_index: u32, byte_in_word: u32, nonce: u32) -> u32 {
+    let byte_index = word_index * 4u + byte_in_word;
+    if (byte_index >= params.nonce_offset && byte_index < params.nonce_offset + NONCE_DIGITS) {
+        return nonce_digit(nonce, byte_index - params.nonce_offset);
+    }
+    return (message[word_index] >> (24u - byte_in_word * 8u)) & 0xffu;
+}
+
+fn message_word(word_index: u32, nonce: u32) -> u32 {
+    return (message_byte(word_index, 0u, nonce) << 24u)
+        | (message_byte(word_index, 1u, nonce) << 16u)
+        | (message_byte(word_index, 2u, nonce) << 8u)
+        | message_byte(word_index, 3u, nonce);
+}
+
+@compute @workgroup_size(64)
+fn main(@builtin(global_invocation_id) id: vec3<u32>) {
+    let nonce = id.x;
+    var h = array<u32, 8>(
+        0x6a09e667u, 0xbb67ae85u, 0x3c6ef372u, 0xa54ff53au,
+        0x510e527fu, 0x9b05688cu, 0x1f83d9abu, 0x5be0cd19u,
+    );
+    var w: array<u32, 64>;
+
+    let blocks = params.message_words / 16u;
+    for (var block = 0u; block < blocks; block++) {
+        for (var t = 0u; t < 16u; t++) {
+            w[t] = message_word(block * 16u + t, nonce);
+        }
+        for (var t = 16u; t < 64u; t++) {
+            let s0 = rotr(w[t - 15u], 7u) ^ rotr(w[t - 15u], 18u) ^ (w[t - 15u] >> 3u);
+            let s1 = rotr(w[t - 2u], 17u) ^ rotr(w[t - 2u], 19u) ^ (w[t - 2u] >> 10u);
+            w[t] = w[t - 16u] + s0 + w[t - 7u] + s1;
+        }
+
+        var a = h[0];
+        var b = h[1];
+        var c = h[2];
+        var d = h[3];
+        var e = h[4];
+        var f = h[5];
+        var g = h[6];
+        var hh = h[7];
+        for (var t = 0u; t < 64u; t++) {
+            let big_s1 = rotr(e, 6u) ^ rotr(e, 11u) ^ rotr(e, 25u);
+            let ch = (e & f) ^ (~e & g);
+            let temp1 = hh + big_s1 + ch + K[t] + w[t];
+            let big_s0 = rotr(a, 2u) ^ rotr(a, 13u) ^ rotr(a, 22u);
+            let maj = (a & b) ^ (a & c) ^ (b & c);
+            let temp2 = big_s0 + maj;
+            hh = g;
+            g = f;
+            f = e;
+            e = d + temp1;
+            d = c;
+            c = b;
+            b = a;
+            a = temp1 + temp2;
+        }
+        h[0] += a;
+        h[1] += b;
+        h[2] += c;
+        h[3] += d;
+        h[4] += e;
+        h[5] += f;
+        h[6] += g;
+        h[7] += hh;
+    }
+
+    var zeros = 0u;
+    for (var i = 0u; i < 8u; i++) {
+        if (h[i] == 0u) {
+            zeros += 32u;
+        } else {
+            zeros += countLeadingZeros(h[i]);
+            break;
+        }
+    }
+
+    if (zeros >= params.target) {
+        atomicStore(&result.nonce, nonce);
+        atomicStore(&result.found, 1u);
+    }
+}
@@ -0,0 +1,505 @@
+use std::{
+    io::{BufRead, BufReader, Write},
+    net::{TcpListener, TcpStream},
+    sync::{atomic::Ordering, Arc},
+    time::{Duration, Instant},
+};
+
+use bevy::{
+    app::{AppExit, ScheduleRunnerPlugin},
+    prelude::*,
+    utils::HashMap,
+};
+use bevy_tokio_tasks::TokioTasksPlugin;
+use crossbeam_channel::{bounded, unbounded, Receiver, Sender};
+
+use crate::{
+    channels::MINED_NOTES_CAPACITY,
+    cyberspace::extract_plane,
+    destination_prompt::parse_destination,
+    logging::{log_plugin, MINING_LOG, NOSTR_LOG},
+    mining::{
+        apply_mining_intensity, apply_mining_threads, measure_hashrate, measure_worker_hashrates,
+        note_id_difficulty, schedule_miners, HashCounter, Hashrate, MiningAction, MiningIntensity,
+        MiningQueue, POWNotes, POWNotesWriter, QueuedBlockStatus,
+    },
+    nostr::{
+        add_configured_relays, connect_pending_relays, record_traffic, update_relay_pool,
+        websocket_thread, IncomingNotes, NoteCounters, OutgoingNotes, RelayAcks, RelayPool,
+        TrafficLog,
+    },
+    protocol::{NoteContent, POWBlockDetails, POW_BLOCK_KIND},
+    resources::{npub, parse_pubkey},
+    settings::{Settings, MAX_MINING_INTENSITY, MIN_MINING_INTENSITY},
+    supervisor::TaskSupervisor,
+    UserNostrKeys,
+};
+
+// Miners get the rest of the time, the daemon only answers commands and forwards notes
+const DAEMON_TICK: Duration = Duration::from_millis(50);
+// Relays that never answer a published note stop being waited for after this long
+const PUBLISHED_NOTE_TIMEOUT: Duration = Duration::from_secs(60);
+
+const DAEMON_HELP: &str = "Commands:
+  mine <x y z | npub | 64 character coordinate>  queues a block and starts mining it
+  stop <x y z | npub | 64 character coordinate>  takes a block out of the queue
+  status                                        lists the queue, the hashrate and the relays
+  intensity <10-100>                            sets the share of the time miners hash
+  quit                                          stops mining and exits";
+
+// `nostrcraft --headless [--listen 127.0.0.1:7777]`, mines without a window. Commands are read
+// from stdin, and from every TCP connection when listening
+pub fn run_headless(listen: Option<String>) {
+    let (command_sender, command_receiver) = unbounded::<DaemonCommand>();
+    spawn_stdin_reader(command_sender.clone());
+    if let Some(address) = listen {
+        spawn_tcp_listener(address, command_sender);
+    }
+
+    let (pow_notes_writer, pow_notes_reader) = bounded(MINED_NOTES_CAPACITY);
+    App::new()
+        .add_plugins((
+            MinimalPlugins.set(ScheduleRunnerPlugin::run_loop(DAEMON_TICK)),
+            log_plugin(),
+            TokioTasksPlugin::default(),
+        ))
+        .insert_resource(Settings::load())
+        .init_resource::<UserNostrKeys>()
+        .init_resource::<TaskSupervisor>()
+        .init_resource::<RelayPool>()
+        .init_resource::<NoteCounters>()
+        .init_resource::<TrafficLog>()
+        .init_resource::<MiningQueue>()
+        .init_resource::<HashCounter>()
+        .init_resource::<Hashrate>()
+        .init_resource::<MiningIntensity>()
+        .insert_resource(POWNotes(pow_notes_reader))
+        .insert_resource(POWNotesWriter(Arc::new(pow_notes_writer)))
+        .init_resource::<PublishedNotes>()
+        .insert_resource(DaemonCommands(command_receiver))
+        .add_systems(Startup, (websocket_thread, announce_daemon))
+        .add_systems(
+            Update,
+            (
+                add_configured_relays,
+                connect_pending_relays,
+                update_relay_pool,
+                record_traffic,
+                watch_incoming_blocks,
+                apply_mining_intensity,
+                apply_mining_threads,
+                run_daemon_commands,
+                schedule_miners,
+                publish_mined_notes,
+                report_relay_acks,
+                measure_hashrate,
+                measure_worker_hashrates.after(measure_hashrate),
+            ),
+        )
+        .run();
+}
+
+// A line from stdin or a TCP client, answered on `reply` with one message
+struct DaemonCommand {
+    line: String,
+    reply: Sender<String>,
+}
+
+#[derive(Resource, Deref)]
+struct DaemonCommands(Receiver<DaemonCommand>);
+
+#[derive(Debug, Clone, PartialEq)]
+enum DaemonRequest {
+    Mine(String),
+    Stop(String),
+    Status,
+    Intensity(u8),
+    Quit,
+    Help,
+}
+
+// Takes the same coordinates as the destination prompt, hex coordinates keep their plane
+fn parse_coordinates(input: &str) -> Option<String> {
+    let coordinates = parse_destination(input)?;
+    let plane = parse_pubkey(input)
+        .and_then(|hex| extract_plane(&hex).ok())
+        .unwrap_or_default();
+    Some(coordinates.to_hex(plane))
+}
+
+fn parse_daemon_command(line: &str) -> Result<DaemonRequest, String> {
+    let line = line.trim();
+    let (name, args) = line.split_once(' ').unwrap_or((line, ""));
+    let args = args.trim();
+    match name {
+        "mine" | "stop" => {
+            let coordinates = parse_coordinates(args).ok_or_else(|| {
+                format!("Usage: {} <x y z | npub | 64 character coordinate>", name)
+            })?;
+            Ok(if name == "mine" {
+                DaemonRequest::Mine(coordinates)
+            } else {
+                DaemonRequest::Stop(coordinates)
+            })
+        }
+        "status" => Ok(DaemonRequest::Status),
+        "intensity" => args
+            .parse::<u8>()
+            .ok()
+            .filter(|intensity| (MIN_MINING_INTENSITY..=MAX_MINING_INTENSITY).contains(intensity))
+            .map(DaemonRequest::Intensity)
+            .ok_or_else(|| {
+                format!(
+                    "Usage: intensity <{}-{}>",
+                    MIN_MINING_INTENSITY, MAX_MINING_INTENSITY
+                )
+            }),
+        "quit" | "exit" => Ok(DaemonRequest::Quit),
+        "help" | "" => Ok(DaemonRequest::Help),
+        _ => Err(format!("Unknown command {}, try help", name)),
+    }
+}
+
+fn spawn_stdin_reader(commands: Sender<DaemonCommand>) {
+    std::thread::spawn(move || {
+        let (reply, replies) = unbounded();
+        for line in std::io::stdin().lock().lines().map_while(Result::ok) {
+            let command = DaemonCommand {
+                line,
+                reply: reply.clone(),
+            };
+            if commands.send(command).is_err() {
+                break;
+            }
+            if let Ok(reply) = replies.recv() {
+                println!("{}", reply);
+            }
+        }
+    });
+}
+
+fn spawn_tcp_listener(address: String, commands: Sender<DaemonCommand>) {
+    let listener = match TcpListener::bind(&address) {
+        Ok(listener) => listener,
+        Err(error) => {
+            eprintln!("Could not listen on {}: {}", address, error);
+            return;
+        }
+    };
+    std::thread::spawn(move || {
+        for stream in listener.incoming().map_while(Result::ok) {
+            let commands = commands.clone();
+            std::thread::spawn(move || serve_tcp_client(stream, commands));
+        }
+    });
+}
+
+// One line in, one reply out, until the client hangs up
+fn serve_tcp_client(stream: TcpStream, commands: Sender<DaemonCommand>) {
+    let Ok(mut writer) = stream.try_clone() else {
+        return;
+    };
+    let (reply, replies) = unbounded();
+    for line in BufReader::new(stream).lines().map_while(Result::ok) {
+        let command = DaemonCommand {
+            line,
+            reply: reply.clone(),
+        };
+        if commands.send(command).is_err() {
+            break;
+        }
+        let Ok(reply) = replies.recv() else {
+            break;
+        };
+        if writeln!(writer, "{}", reply).is_err() {
+            break;
+        }
+    }
+}
+
+fn announce_daemon(user_keys: Res<UserNostrKeys>, settings: Res<Settings>) {
+    let pubkey = user_keys.get_public_key();
+    info!(
+        target: MINING_LOG,
+        "Mining headless as {} with the key in {}, type help for commands",
+        npub(&pubkey).unwrap_or(pubkey),
+        settings.key_path
+    );
+}
+
+fn run_daemon_commands(
+    daemon_commands: Res<DaemonCommands>,
+    settings: Res<Settings>,
+    relay_pool: Res<RelayPool>,
+    hashrate: Res<Hashrate>,
+    mining_intensity: Res<MiningIntensity>,
+    mut mining_queue: ResMut<MiningQueue>,
+    mut app_exit: EventWriter<AppExit>,
+) {
+    for command in daemon_commands.try_iter() {
+        let reply = match parse_daemon_command(&command.line) {
+            Ok(DaemonRequest::Mine(coordinates)) => {
+                if mining_queue.enqueue(coordinates.clone(), Entity::PLACEHOLDER) {
+                    if let Some(block) = mining_queue.blocks.last_mut() {
+                        block.action = MiningAction::Place {
+                            material: None,
+                            label: settings.block_label(),
+                        };
+                    }
+                    format!("Mining {}", coordinates)
+                } else {
+                    format!("{} is already queued", coordinates)
+                }
+            }
+            Ok(DaemonRequest::Stop(coordinates)) => match mining_queue.cancel(&coordinates) {
+                Some(block) => format!("Stopped {} at {} bits", coordinates, block.best_pow),
+                None => format!("{} is not queued", coordinates),
+            },
+            Ok(DaemonRequest::Status) => {
+                daemon_status(&mining_queue, &relay_pool, hashrate.hashes_per_second)
+            }
+            Ok(DaemonRequest::Intensity(intensity)) => {
+                mining_intensity.store(intensity, Ordering::Relaxed);
+                format!("Mining {}% of the time", intensity)
+            }
+            Ok(DaemonRequest::Quit) => {
+                app_exit.send(AppExit);
+                "Stopping".to_string()
+            }
+            Ok(DaemonRequest::Help) => DAEMON_HELP.to_string(),
+            Err(usage) => usage,
+        };
+        let _ = command.reply.send(reply);
+    }
+}
+
+fn daemon_status(
+    mining_queue: &MiningQueue,
+    relay_pool: &RelayPool,
+    hashes_per_second: f64,
+) -> String {
+    let mut lines = vec![format!(
+        "{:.0} hashes/s, {} relays: {}",
+        hashes_per_second,
+        relay_pool.len(),
+        relay_pool
+            .iter()
+            .map(|relay| relay.url.as_str())
+            .collect::<Vec<_>>()
+            .join(" ")
+    )];
+    if mining_queue.blocks.is_empty() {
+        lines.push("Nothing queued".to_string());
+    }
+    for block in mining_queue.blocks.iter() {
+        let status = match block.status {
+            QueuedBlockStatus::Mining => "mining",
+            QueuedBlockStatus::Queued => "queued",
+            QueuedBlockStatus::Paused => "paused",
+        };
+        let block_rate: f64 = block
+            .workers
+            .iter()
+            .map(|worker| worker.hashes_per_second)
+            .sum();
+        lines.push(format!(
+            "{} {} {} bits, {} workers at {:.0} hashes/s",
+            block.coordinates,
+            status,
+            block.best_pow,
+            block.workers.len(),
+            block_rate
+        ));
+    }
+    lines.join("\n")
+}
+
+// Mined notes go straight to the relays, their note ids are kept to match the relays' replies
+fn publish_mined_notes(
+    pow_notes: Res<POWNotes>,
+    outgoing_notes: Res<OutgoingNotes>,
+    mut mining_queue: ResMut<MiningQueue>,
+    mut published: ResMut<PublishedNotes>,
+) {
+    for note in pow_notes.try_iter() {
+        let pow_amount = note_id_difficulty(note.get_id());
+        if let Ok(block_details) = serde_json::from_str::<POWBlockDetails>(note.get_content()) {
+            if let Some(block) = mining_queue
+                .blocks
+                .iter_mut()
+                .find(|block| block.coordinates == block_details.coordinates)
+            {
+                block.best_pow = block.best_pow.max(pow_amount);
+            }
+            info!(
+                target: MINING_LOG,
+                "Mined {} bits at {}", pow_amount, block_details.coordinates
+            );
+            published.insert(
+                note.get_id().to_string(),
+                PublishedNote {
+                    coordinates: block_details.coordinates,
+                    pow_amount,
+                    published_at: Instant::now(),
+                    answers: 0,
+                },
+            );
+        }
+        let _sent = outgoing_notes.send(note);
+    }
+}
+
+struct PublishedNote {
+    coordinates: String,
+    pow_amount: usize,
+    published_at: Instant,
+    // Relays that replied so far
+    answers: usize,
+}
+
+#[derive(Resource, Default, Deref, DerefMut)]
+struct PublishedNotes(HashMap<String, PublishedNote>);
+
+impl PublishedNotes {
+    // Forgets the note once every relay in the pool replied
+    fn answered(&mut self, note_id: &str, relays: usize) {
+        let Some(published) = self.get_mut(note_id) else {
+            return;
+        };
+        published.answers += 1;
+        if published.answers >= relays {
+            self.remove(note_id);
+        }
+    }
+
+    fn expire(&mut self, now: Instant) {
+        self.retain(|_, published| {
+            now.duration_since(published.published_at) < PUBLISHED_NOTE_TIMEOUT
+        });
+    }
+}
+
+fn report_relay_acks(
+    relay_acks: Res<RelayAcks>,
+    relay_pool: Res<RelayPool>,
+    mut published: ResMut<PublishedNotes>,
+) {
+    published.expire(Instant::now());
+    for ack in relay_acks.try_iter() {
+        let Some(PublishedNote {
+            coordinates,
+            pow_amount,
+            ..
+        }) = published.get(&ack.note_id)
+        else {
+            continue;
+        };
+        if ack.accepted {
+            info!(
+                target: NOSTR_LOG,
+                "{} accepted {} bits at {}", ack.relay_url, pow_amount, coordinates
+            );
+        } else {
+            warn!(
+                target: NOSTR_LOG,
+                "{} rejected {} bits at {}: {}", ack.relay_url, pow_amount, coordinates, ack.message
+            );
+        }
+        published.answered(&ack.note_id, relay_pool.len());
+    }
+}
+
+// Nothing is drawn, incoming notes are only checked for blocks beating the ones we mine
+fn watch_incoming_blocks(
+    incoming_notes: Res<IncomingNotes>,
+    user_keys: Res<UserNostrKeys>,
+    mining_queue: Res<MiningQueue>,
+) {
+    let our_pubkey = user_keys.get_public_key();
+    for received in incoming_notes.try_iter() {
+        let note = received.note;
+        if note.get_kind() != POW_BLOCK_KIND || note.get_pubkey() == our_pubkey {
+            continue;
+        }
+        let NoteContent::Block(block_details) = received.content else {
+            continue;
+        };
+        // Blocks naming a miner other than their signer don't count in the game either
+        if block_details.miner_pubkey != note.get_pubkey() {
+            continue;
+        }
+        let pow_amount = note_id_difficulty(note.get_id());
+        if let Some(block) = mining_queue
+            .blocks
+            .iter()
+            .find(|block| block.coordinates == block_details.coordinates)
+        {
+            if pow_amount > block.best_pow {
+                warn!(
+                    target: MINING_LOG,
+                    "{} has {} bits at {}, ours has {}",
+                    note.get_pubkey(),
+                    pow_amount,
+                    block.coordinates,
+                    block.best_pow
+                );
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cyberspace::{CyberspaceCoordinates, Plane};
+
+    #[test]
+    fn daemon_commands_take_every_form_of_coordinate() {
+        let hex = CyberspaceCoordinates::new(1, 2, 3).to_hex(Plane::DSpace);
+        assert_eq!(
+            parse_daemon_command(&format!("mine {}", hex)),
+            Ok(DaemonRequest::Mine(hex.clone()))
+        );
+        assert_eq!(
+            parse_daemon_command("stop 1 2 3"),
+            Ok(DaemonRequest::Stop(
+                CyberspaceCoordinates::new(1, 2, 3).to_hex(Plane::ISpace)
+            ))
+        );
+        assert!(parse_daemon_command("mine here").is_err());
+    }
+
+    #[test]
+    fn published_notes_are_forgotten_once_answered_or_expired() {
+        let mut published = PublishedNotes::default();
+        let published_at = Instant::now();
+        for note_id in ["answered", "unanswered"] {
+            published.insert(
+                note_id.to_string(),
+                PublishedNote {
+                    coordinates: "coordinates".to_string(),
+                    pow_amount: 20,
+                    published_at,
+                    answers: 0,
+                },
+            );
+        }
+        published.answered("answered", 2);
+        assert!(published.contains_key("answered"));
+        published.answered("answered", 2);
+        assert!(!published.contains_key("answered"));
+        published.expire(published_at + PUBLISHED_NOTE_TIMEOUT);
+        assert!(published.is_empty());
+    }
+
+    #[test]
+    fn intensity_stays_in_range() {
+        assert_eq!(
+            parse_daemon_command("intensity 50"),
+            Ok(DaemonRequest::Intensity(50))
+        );
+        assert!(parse_daemon_command("intensity 5").is_err());
+        assert_eq!(parse_daemon_command("  status "), Ok(DaemonRequest::Status));
+        assert_eq!(parse_daemon_command(""), Ok(DaemonRequest::Help));
+    }
+}
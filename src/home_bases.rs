@@ -0,0 +1,379 @@
+use bevy::{prelude::*, utils::HashMap};
+use nostro2::notes::Note;
+use serde_json::json;
+
+use crate::{
+    cameras::{BlockIndicator, Teleporting},
+    chunks::WorldChunks,
+    console::{ConsoleCommand, ConsoleOutput, RegisterConsoleCommand},
+    cyberspace::{CyberspaceCoordinates, Sector},
+    keybindings::{key_name, Action, KeyBindings},
+    nostr::{HomeBaseSubscription, OutgoingNotes},
+    origin::{CyberspacePosition, FloatingOrigin},
+    protocol::{sector_cell_tags, trim_label, HomeBaseDetails, HOME_BASE_KIND},
+    resources::{owner_palette_index, FollowList, OWNER_PALETTE},
+    settings::Settings,
+    ui_camera::AvatarListDetails,
+    waypoints::Waypoints,
+    UserNostrKeys,
+};
+
+const BEACON_WIDTH: f32 = 0.5;
+// Tall enough to be spotted over a sector of blocks
+const BEACON_HEIGHT: f32 = 64.0;
+const BEACON_GLOW: f32 = 4.0;
+
+pub fn home_bases_plugin(app: &mut App) {
+    app.init_resource::<HomeBases>()
+        .init_resource::<HomeBeacons>()
+        .add_event::<HomeBaseReceived>()
+        .register_console_command(
+            "homebase",
+            "/homebase [description]",
+            "Publishes your home, the first waypoint, as a base other players can visit",
+        )
+        .add_systems(Startup, setup_beacon_assets)
+        .add_systems(
+            Update,
+            (
+                (record_home_bases, publish_home_base, spawn_home_beacons).chain(),
+                follow_home_base_authors,
+                visit_home_base,
+                update_roster_home_base,
+            ),
+        );
+}
+
+// A kind 10333 note, read from a relay
+#[derive(Event, Debug, Clone, PartialEq)]
+pub struct HomeBaseReceived {
+    pub author: String,
+    pub created_at: u64,
+    pub details: HomeBaseDetails,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct HomeBase {
+    pub coordinates: CyberspaceCoordinates,
+    pub description: Option<String>,
+    pub created_at: u64,
+}
+
+// Newest home base of every key, relays may still send the ones it replaced
+#[derive(Resource, Default, Deref, DerefMut)]
+pub struct HomeBases(HashMap<String, HomeBase>);
+
+impl HomeBases {
+    // False for a base that is older than the one we have, or that isn't anywhere
+    pub fn apply(&mut self, pubkey: &str, created_at: u64, details: &HomeBaseDetails) -> bool {
+        let Some(coordinates) = details.coordinates() else {
+            return false;
+        };
+        if self
+            .get(pubkey)
+            .is_some_and(|known| known.created_at >= created_at)
+        {
+            return false;
+        }
+        self.insert(
+            pubkey.to_string(),
+            HomeBase {
+                coordinates,
+                description: details.description.as_deref().and_then(trim_label),
+                created_at,
+            },
+        );
+        true
+    }
+}
+
+// Beacon entity of every other player's home base
+#[derive(Resource, Default, Deref, DerefMut)]
+struct HomeBeacons(HashMap<String, Entity>);
+
+// Beacons of the same palette color share their material
+#[derive(Resource)]
+struct BeaconAssets {
+    mesh: Handle<Mesh>,
+    materials: Vec<Handle<StandardMaterial>>,
+}
+
+#[derive(Component)]
+pub struct HomeBeacon(pub String);
+
+// The selected avatar's home base, under the roster
+#[derive(Component)]
+pub struct RosterHomeBase;
+
+fn setup_beacon_assets(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+    // Standing on the base's block rather than through it
+    let mesh = Mesh::from(Cuboid::new(BEACON_WIDTH, BEACON_HEIGHT, BEACON_WIDTH))
+        .translated_by(Vec3::Y * BEACON_HEIGHT / 2.0);
+    let materials = OWNER_PALETTE
+        .iter()
+        .map(|color| {
+            materials.add(StandardMaterial {
+                emissive: *color * BEACON_GLOW,
+                alpha_mode: AlphaMode::Add,
+                ..Default::default()
+            })
+        })
+        .collect();
+    commands.insert_resource(BeaconAssets {
+        mesh: meshes.add(mesh),
+        materials,
+    });
+}
+
+// Bases far away are asked for only for the keys we follow and the one selected in the roster
+fn follow_home_base_authors(
+    follow_list: Res<FollowList>,
+    avatar_list: Res<AvatarListDetails>,
+    home_base_subscription: Res<HomeBaseSubscription>,
+) {
+    if !follow_list.is_changed() && !avatar_list.is_changed() {
+        return;
+    }
+    let mut authors: Vec<String> = follow_list.iter().cloned().collect();
+    if let Some(selected) = avatar_list.selected_pubkey() {
+        if !follow_list.contains(selected) {
+            authors.push(selected.to_string());
+        }
+    }
+    authors.sort();
+    home_base_subscription.send_if_modified(|current| {
+        if *current == authors {
+            return false;
+        }
+        *current = authors;
+        true
+    });
+}
+
+fn record_home_bases(
+    mut received: EventReader<HomeBaseReceived>,
+    mut home_bases: ResMut<HomeBases>,
+) {
+    for home_base in received.read() {
+        home_bases.apply(&home_base.author, home_base.created_at, &home_base.details);
+    }
+}
+
+fn publish_home_base(
+    user_keys: Res<UserNostrKeys>,
+    waypoints: Res<Waypoints>,
+    world_chunks: Res<WorldChunks>,
+    outgoing_notes: Res<OutgoingNotes>,
+    mut home_bases: ResMut<HomeBases>,
+    mut console_commands: EventReader<ConsoleCommand>,
+    mut console_output: EventWriter<ConsoleOutput>,
+) {
+    for command in console_commands.read() {
+        if command.name != "homebase" {
+            continue;
+        }
+        let coordinates = waypoints
+            .home()
+            .unwrap_or_else(|| user_keys.get_home_coordinates());
+        let details = HomeBaseDetails {
+            home: coordinates.to_hex(world_chunks.plane()),
+            description: trim_label(&command.args.join(" ")),
+        };
+        let our_pubkey = user_keys.get_public_key();
+        let mut note = Note::new(
+            our_pubkey.clone(),
+            HOME_BASE_KIND,
+            &json!(details).to_string(),
+        );
+        // Players nearby find it with the blocks around them
+        note.tags = sector_cell_tags(&details.home);
+        let signed_note = user_keys.get_keypair().sign_nostr_event(note);
+        home_bases.apply(&our_pubkey, signed_note.get_created_at(), &details);
+        let _sent = outgoing_notes.send(signed_note);
+        console_output.send(ConsoleOutput(format!(
+            "Published your home base at X: {}, Y: {}, Z: {}",
+            coordinates.x, coordinates.y, coordinates.z
+        )));
+    }
+}
+
+fn beacon_in_range(home_base: &HomeBase, indicator: Sector, load_radius: i32) -> bool {
+    home_base.coordinates.sector().distance(indicator) <= load_radius as i128
+}
+
+// Beacons stand on the bases within the sector load radius of the indicator, our own base is
+// already marked on the minimap
+fn spawn_home_beacons(
+    mut commands: Commands,
+    home_bases: Res<HomeBases>,
+    beacon_assets: Res<BeaconAssets>,
+    user_keys: Res<UserNostrKeys>,
+    settings: Res<Settings>,
+    origin: Res<FloatingOrigin>,
+    block_indicator: Query<&Transform, With<BlockIndicator>>,
+    mut home_beacons: ResMut<HomeBeacons>,
+    mut beacon_query: Query<&mut CyberspacePosition, With<HomeBeacon>>,
+    mut indicator_sector: Local<Option<Sector>>,
+) {
+    let Ok(indicator_transform) = block_indicator.get_single() else {
+        return;
+    };
+    let sector = origin.coordinates(indicator_transform.translation).sector();
+    let moved = *indicator_sector != Some(sector);
+    if !moved && !home_bases.is_changed() && !settings.is_changed() {
+        return;
+    }
+    *indicator_sector = Some(sector);
+
+    let our_pubkey = user_keys.get_public_key();
+    home_beacons.retain(|pubkey, beacon| {
+        let keep = home_bases.get(pubkey).is_some_and(|home_base| {
+            beacon_in_range(home_base, sector, settings.sector_load_radius)
+        });
+        if !keep {
+            commands.entity(*beacon).despawn();
+        }
+        keep
+    });
+    for (pubkey, home_base) in home_bases.iter() {
+        if *pubkey == our_pubkey || !beacon_in_range(home_base, sector, settings.sector_load_radius)
+        {
+            continue;
+        }
+        if let Some(mut position) = home_beacons
+            .get(pubkey)
+            .and_then(|beacon| beacon_query.get_mut(*beacon).ok())
+        {
+            if **position != home_base.coordinates {
+                **position = home_base.coordinates;
+            }
+            continue;
+        }
+        let beacon = commands
+            .spawn((
+                PbrBundle {
+                    mesh: beacon_assets.mesh.clone_weak(),
+                    material: beacon_assets.materials[owner_palette_index(pubkey)].clone_weak(),
+                    ..Default::default()
+                },
+                CyberspacePosition(home_base.coordinates),
+                HomeBeacon(pubkey.clone()),
+            ))
+            .id();
+        home_beacons.insert(pubkey.clone(), beacon);
+    }
+}
+
+fn visit_home_base(
+    mut commands: Commands,
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    key_bindings: Res<KeyBindings>,
+    avatar_list: Res<AvatarListDetails>,
+    home_bases: Res<HomeBases>,
+    origin: Res<FloatingOrigin>,
+    block_indicator: Query<(Entity, &Transform), (With<BlockIndicator>, Without<Teleporting>)>,
+) {
+    if !keyboard_input.just_pressed(key_bindings.key(Action::VisitHomeBase)) {
+        return;
+    }
+    let Some(home_base) = avatar_list
+        .selected_pubkey()
+        .and_then(|pubkey| home_bases.get(pubkey))
+    else {
+        return;
+    };
+    let Ok((indicator, block_transform)) = block_indicator.get_single() else {
+        return;
+    };
+    commands.entity(indicator).insert(Teleporting::new(
+        origin.coordinates(block_transform.translation),
+        home_base.coordinates,
+        "Visiting home base",
+    ));
+}
+
+fn home_base_line(home_base: Option<&HomeBase>, visit_key: &str) -> String {
+    let Some(home_base) = home_base else {
+        return "No home base published".to_string();
+    };
+    let coordinates = home_base.coordinates;
+    format!(
+        "Home base: {}\nX: {}, Y: {}, Z: {}, {} visits",
+        home_base.description.as_deref().unwrap_or("no description"),
+        coordinates.x,
+        coordinates.y,
+        coordinates.z,
+        visit_key
+    )
+}
+
+fn update_roster_home_base(
+    avatar_list: Res<AvatarListDetails>,
+    home_bases: Res<HomeBases>,
+    key_bindings: Res<KeyBindings>,
+    mut text_query: Query<&mut Text, With<RosterHomeBase>>,
+) {
+    if !avatar_list.is_changed() && !home_bases.is_changed() && !key_bindings.is_changed() {
+        return;
+    }
+    let line = avatar_list
+        .selected_pubkey()
+        .map(|pubkey| {
+            home_base_line(
+                home_bases.get(pubkey),
+                &key_name(key_bindings.key(Action::VisitHomeBase)),
+            )
+        })
+        .unwrap_or_default();
+    for mut text in text_query.iter_mut() {
+        text.sections[0].value = line.clone();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cyberspace::{Plane, SECTOR_SIZE};
+
+    fn details(x: i128, description: &str) -> HomeBaseDetails {
+        HomeBaseDetails {
+            home: CyberspaceCoordinates::new(x, 0, 0).to_hex(Plane::DSpace),
+            description: Some(description.to_string()),
+        }
+    }
+
+    #[test]
+    fn only_newer_home_bases_replace_older_ones() {
+        let mut home_bases = HomeBases::default();
+        assert!(home_bases.apply("aa", 10, &details(1, "first")));
+        assert!(!home_bases.apply("aa", 5, &details(2, "stale")));
+        assert!(home_bases.apply("aa", 20, &details(3, "  moved  ")));
+        let home_base = &home_bases["aa"];
+        assert_eq!(home_base.coordinates, CyberspaceCoordinates::new(3, 0, 0));
+        assert_eq!(home_base.description.as_deref(), Some("moved"));
+    }
+
+    #[test]
+    fn home_bases_need_a_coordinate() {
+        let mut home_bases = HomeBases::default();
+        let nowhere = HomeBaseDetails {
+            home: "not hex".to_string(),
+            description: None,
+        };
+        assert!(!home_bases.apply("aa", 10, &nowhere));
+        assert!(home_bases.is_empty());
+    }
+
+    #[test]
+    fn beacons_stand_within_the_load_radius() {
+        let mut home_bases = HomeBases::default();
+        home_bases.apply("aa", 10, &details(0, "here"));
+        let indicator = CyberspaceCoordinates::new(3 * SECTOR_SIZE, 0, 0).sector();
+        assert!(beacon_in_range(&home_bases["aa"], indicator, 3));
+        assert!(!beacon_in_range(&home_bases["aa"], indicator, 2));
+    }
+}
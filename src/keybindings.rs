@@ -0,0 +1,862 @@
+use std::collections::BTreeMap;
+
+use bevy::{input::InputSystem, prelude::*};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::{read_config, write_config, ConfigReloaded, CONFIG_FILE_PATH},
+    session::SessionPanel,
+    ui_camera::{
+        text_bundle_builder, BORDER_WIDTH, FLEX_GAP, LIGHT_GRAY, MARGIN_UI, NORMAL_FONT,
+        PADDING_UI, TITLE_FONT,
+    },
+};
+
+// Table of the config file holding the bindings
+const KEY_BINDINGS_TABLE: &str = "keybindings";
+// Where bindings were kept before they moved to the config file, still read once
+const LEGACY_KEY_BINDINGS_FILE_PATH: &str = "./keybindings.toml";
+const BINDING_HOVER_COLOR: Color = Color::rgba(1.0, 1.0, 1.0, 0.15);
+const REBINDING_COLOR: Color = Color::YELLOW;
+// Cancels a rebind instead of being bound
+const CANCEL_REBIND_KEY: KeyCode = KeyCode::Escape;
+
+pub fn keybindings_plugin(app: &mut App) {
+    app.insert_resource(KeyBindings::load())
+        .init_resource::<Rebinding>()
+        .init_resource::<Typing>()
+        .add_systems(PostStartup, (setup_key_bindings_panel, setup_help_overlay))
+        // Before the Update systems so the captured key doesn't also trigger its old action
+        .add_systems(PreUpdate, capture_rebind_key.after(InputSystem))
+        .add_systems(
+            Update,
+            (
+                toggle_key_bindings_panel,
+                pick_binding,
+                update_key_bindings_panel,
+                reload_key_bindings,
+            )
+                .chain(),
+        )
+        .add_systems(Update, (toggle_help_overlay, update_help_overlay));
+}
+
+// Everything that can be triggered from the keyboard, in the order the panel lists them
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum Action {
+    MoveForward,
+    MoveBack,
+    MoveLeft,
+    MoveRight,
+    MoveUp,
+    MoveDown,
+    FastForward,
+    FastBack,
+    FastLeft,
+    FastRight,
+    FastUp,
+    FastDown,
+    FirstPerson,
+    ToggleFullscreen,
+    NoClip,
+    GoHome,
+    TeleportToAvatar,
+    NextAvatar,
+    PreviousAvatar,
+    SortAvatars,
+    FilterAvatars,
+    TeleportToDestination,
+    TypeDestination,
+    AddWaypoint,
+    Mine,
+    StopMining,
+    NextQueued,
+    PreviousQueued,
+    PauseQueued,
+    CancelQueued,
+    RaiseQueued,
+    LowerQueued,
+    CycleMaterial,
+    Derezz,
+    ToggleDefense,
+    SelectArea,
+    CancelArea,
+    HostPool,
+    JoinPool,
+    LeavePool,
+    ProbeRelays,
+    AddSuggestedRelays,
+    Chat,
+    ToggleConsole,
+    DirectMessage,
+    SaveBlueprint,
+    SelectBlueprint,
+    StampBlueprint,
+    ToggleAmbience,
+    ToggleSettings,
+    ToggleDiagnostics,
+    ToggleStats,
+    Export,
+    ToggleHeatmap,
+    ToggleSearch,
+    ToggleWaypoints,
+    ToggleKeyBindings,
+    ToggleHelp,
+    ToggleTraffic,
+    SwitchPlane,
+    ToggleNoteGlyphs,
+    ToggleSlice,
+    SliceUp,
+    SliceDown,
+    CycleSliceThickness,
+    EditProfile,
+    ToggleFollow,
+    LikeBlock,
+    Screenshot,
+    VisitHomeBase,
+}
+
+// Headings of the help overlay
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ActionCategory {
+    Movement,
+    Camera,
+    Mining,
+    Ui,
+}
+
+impl ActionCategory {
+    const ALL: [ActionCategory; 4] = [
+        ActionCategory::Movement,
+        ActionCategory::Camera,
+        ActionCategory::Mining,
+        ActionCategory::Ui,
+    ];
+
+    fn label(self) -> &'static str {
+        match self {
+            ActionCategory::Movement => "Movement",
+            ActionCategory::Camera => "Camera",
+            ActionCategory::Mining => "Mining",
+            ActionCategory::Ui => "Interface",
+        }
+    }
+}
+
+impl Action {
+    const ALL: [Action; 70] = [
+        Action::MoveForward,
+        Action::MoveBack,
+        Action::MoveLeft,
+        Action::MoveRight,
+        Action::MoveUp,
+        Action::MoveDown,
+        Action::FastForward,
+        Action::FastBack,
+        Action::FastLeft,
+        Action::FastRight,
+        Action::FastUp,
+        Action::FastDown,
+        Action::FirstPerson,
+        Action::ToggleFullscreen,
+        Action::NoClip,
+        Action::GoHome,
+        Action::TeleportToAvatar,
+        Action::NextAvatar,
+        Action::PreviousAvatar,
+        Action::SortAvatars,
+        Action::FilterAvatars,
+        Action::TeleportToDestination,
+        Action::TypeDestination,
+        Action::AddWaypoint,
+        Action::Mine,
+        Action::StopMining,
+        Action::NextQueued,
+        Action::PreviousQueued,
+        Action::PauseQueued,
+        Action::CancelQueued,
+        Action::RaiseQueued,
+        Action::LowerQueued,
+        Action::CycleMaterial,
+        Action::Derezz,
+        Action::ToggleDefense,
+        Action::SelectArea,
+        Action::CancelArea,
+        Action::HostPool,
+        Action::JoinPool,
+        Action::LeavePool,
+        Action::ProbeRelays,
+        Action::AddSuggestedRelays,
+        Action::Chat,
+        Action::ToggleConsole,
+        Action::DirectMessage,
+        Action::SaveBlueprint,
+        Action::SelectBlueprint,
+        Action::StampBlueprint,
+        Action::ToggleAmbience,
+        Action::ToggleSettings,
+        Action::ToggleDiagnostics,
+        Action::ToggleStats,
+        Action::Export,
+        Action::ToggleHeatmap,
+        Action::ToggleSearch,
+        Action::ToggleWaypoints,
+        Action::ToggleKeyBindings,
+        Action::ToggleHelp,
+        Action::ToggleTraffic,
+        Action::SwitchPlane,
+        Action::ToggleNoteGlyphs,
+        Action::ToggleSlice,
+        Action::SliceUp,
+        Action::SliceDown,
+        Action::CycleSliceThickness,
+        Action::EditProfile,
+        Action::ToggleFollow,
+        Action::LikeBlock,
+        Action::Screenshot,
+        Action::VisitHomeBase,
+    ];
+
+    fn default_key(self) -> KeyCode {
+        match self {
+            Action::MoveForward => KeyCode::KeyW,
+            Action::MoveBack => KeyCode::KeyS,
+            Action::MoveLeft => KeyCode::KeyA,
+            Action::MoveRight => KeyCode::KeyD,
+            Action::MoveUp => KeyCode::KeyQ,
+            Action::MoveDown => KeyCode::KeyE,
+            Action::FastForward => KeyCode::ArrowUp,
+            Action::FastBack => KeyCode::ArrowDown,
+            Action::FastLeft => KeyCode::ArrowLeft,
+            Action::FastRight => KeyCode::ArrowRight,
+            Action::FastUp => KeyCode::PageUp,
+            Action::FastDown => KeyCode::PageDown,
+            Action::FirstPerson => KeyCode::Tab,
+            Action::ToggleFullscreen => KeyCode::F11,
+            Action::NoClip => KeyCode::KeyO,
+            Action::GoHome => KeyCode::Home,
+            Action::TeleportToAvatar => KeyCode::End,
+            Action::NextAvatar => KeyCode::Delete,
+            Action::PreviousAvatar => KeyCode::Insert,
+            Action::SortAvatars => KeyCode::Comma,
+            Action::FilterAvatars => KeyCode::Period,
+            Action::TeleportToDestination => KeyCode::Enter,
+            Action::TypeDestination => KeyCode::Slash,
+            Action::AddWaypoint => KeyCode::KeyF,
+            Action::Mine => KeyCode::KeyM,
+            Action::StopMining => KeyCode::KeyN,
+            Action::NextQueued => KeyCode::BracketRight,
+            Action::PreviousQueued => KeyCode::BracketLeft,
+            Action::PauseQueued => KeyCode::KeyP,
+            Action::CancelQueued => KeyCode::Backspace,
+            Action::RaiseQueued => KeyCode::Equal,
+            Action::LowerQueued => KeyCode::Minus,
+            Action::CycleMaterial => KeyCode::KeyC,
+            Action::Derezz => KeyCode::KeyX,
+            Action::ToggleDefense => KeyCode::KeyG,
+            Action::SelectArea => KeyCode::KeyB,
+            Action::CancelArea => KeyCode::Escape,
+            Action::HostPool => KeyCode::KeyH,
+            Action::JoinPool => KeyCode::KeyJ,
+            Action::LeavePool => KeyCode::KeyL,
+            Action::ProbeRelays => KeyCode::KeyR,
+            Action::AddSuggestedRelays => KeyCode::KeyY,
+            Action::Chat => KeyCode::Quote,
+            Action::ToggleConsole => KeyCode::Backquote,
+            Action::DirectMessage => KeyCode::KeyI,
+            Action::SaveBlueprint => KeyCode::KeyK,
+            Action::SelectBlueprint => KeyCode::KeyU,
+            Action::StampBlueprint => KeyCode::KeyV,
+            Action::ToggleAmbience => KeyCode::KeyT,
+            Action::ToggleSettings => KeyCode::F2,
+            Action::ToggleDiagnostics => KeyCode::F3,
+            Action::ToggleStats => KeyCode::F4,
+            Action::Export => KeyCode::F5,
+            Action::ToggleHeatmap => KeyCode::F6,
+            Action::ToggleSearch => KeyCode::F7,
+            Action::ToggleWaypoints => KeyCode::F9,
+            Action::ToggleKeyBindings => KeyCode::F8,
+            Action::ToggleHelp => KeyCode::F1,
+            Action::ToggleTraffic => KeyCode::F10,
+            Action::SwitchPlane => KeyCode::KeyZ,
+            Action::ToggleNoteGlyphs => KeyCode::F12,
+            Action::ToggleSlice => KeyCode::Backslash,
+            Action::SliceUp => KeyCode::Digit0,
+            Action::SliceDown => KeyCode::Digit9,
+            Action::CycleSliceThickness => KeyCode::Semicolon,
+            Action::EditProfile => KeyCode::Digit1,
+            Action::ToggleFollow => KeyCode::Digit2,
+            Action::LikeBlock => KeyCode::Digit3,
+            Action::Screenshot => KeyCode::Digit4,
+            Action::VisitHomeBase => KeyCode::Digit5,
+        }
+    }
+
+    fn label(self) -> &'static str {
+        match self {
+            Action::MoveForward => "Move forward",
+            Action::MoveBack => "Move back",
+            Action::MoveLeft => "Move left",
+            Action::MoveRight => "Move right",
+            Action::MoveUp => "Move up",
+            Action::MoveDown => "Move down",
+            Action::FastForward => "Fast forward",
+            Action::FastBack => "Fast back",
+            Action::FastLeft => "Fast left",
+            Action::FastRight => "Fast right",
+            Action::FastUp => "Fast up",
+            Action::FastDown => "Fast down",
+            Action::FirstPerson => "First person camera",
+            Action::ToggleFullscreen => "Fullscreen",
+            Action::NoClip => "No-clip",
+            Action::GoHome => "Go home",
+            Action::TeleportToAvatar => "Teleport to avatar",
+            Action::NextAvatar => "Next avatar",
+            Action::PreviousAvatar => "Previous avatar",
+            Action::SortAvatars => "Sort avatars",
+            Action::FilterAvatars => "Filter avatars",
+            Action::TeleportToDestination => "Teleport to destination",
+            Action::TypeDestination => "Type destination",
+            Action::AddWaypoint => "Bookmark waypoint",
+            Action::Mine => "Mine placed blocks",
+            Action::StopMining => "Stop mining",
+            Action::NextQueued => "Next queued block",
+            Action::PreviousQueued => "Previous queued block",
+            Action::PauseQueued => "Pause queued block",
+            Action::CancelQueued => "Cancel queued block",
+            Action::RaiseQueued => "Raise queued block",
+            Action::LowerQueued => "Lower queued block",
+            Action::CycleMaterial => "Cycle material",
+            Action::Derezz => "Derezz block",
+            Action::ToggleDefense => "Toggle defense",
+            Action::SelectArea => "Select area corner",
+            Action::CancelArea => "Cancel area",
+            Action::HostPool => "Host pool",
+            Action::JoinPool => "Join pool",
+            Action::LeavePool => "Leave pool",
+            Action::ProbeRelays => "Probe relays",
+            Action::AddSuggestedRelays => "Add suggested relays",
+            Action::Chat => "Sector chat",
+            Action::ToggleConsole => "Console",
+            Action::DirectMessage => "Message selected avatar",
+            Action::SaveBlueprint => "Save blueprint",
+            Action::SelectBlueprint => "Select blueprint",
+            Action::StampBlueprint => "Stamp blueprint",
+            Action::ToggleAmbience => "Toggle ambience",
+            Action::ToggleSettings => "Settings",
+            Action::ToggleDiagnostics => "Diagnostics",
+            Action::ToggleStats => "Stats",
+            Action::Export => "Export blocks",
+            Action::ToggleHeatmap => "Ownership heatmap",
+            Action::ToggleSearch => "Search by owner",
+            Action::ToggleWaypoints => "Waypoints",
+            Action::ToggleKeyBindings => "Key bindings",
+            Action::ToggleHelp => "Help",
+            Action::ToggleTraffic => "Relay traffic",
+            Action::SwitchPlane => "Switch i-space/d-space",
+            Action::ToggleNoteGlyphs => "Text note glyphs",
+            Action::ToggleSlice => "Slice view",
+            Action::SliceUp => "Move the slice up",
+            Action::SliceDown => "Move the slice down",
+            Action::CycleSliceThickness => "Slice thickness",
+            Action::EditProfile => "Edit profile",
+            Action::ToggleFollow => "Follow selected avatar",
+            Action::LikeBlock => "Like block",
+            Action::Screenshot => "Share screenshot",
+            Action::VisitHomeBase => "Visit selected home base",
+        }
+    }
+
+    fn category(self) -> ActionCategory {
+        match self {
+            Action::MoveForward
+            | Action::MoveBack
+            | Action::MoveLeft
+            | Action::MoveRight
+            | Action::MoveUp
+            | Action::MoveDown
+            | Action::FastForward
+            | Action::FastBack
+            | Action::FastLeft
+            | Action::FastRight
+            | Action::FastUp
+            | Action::FastDown
+            | Action::GoHome
+            | Action::TeleportToAvatar
+            | Action::VisitHomeBase
+            | Action::TeleportToDestination
+            | Action::TypeDestination
+            | Action::AddWaypoint => ActionCategory::Movement,
+            Action::FirstPerson
+            | Action::ToggleFullscreen
+            | Action::NoClip
+            | Action::SwitchPlane
+            | Action::ToggleSlice
+            | Action::SliceUp
+            | Action::SliceDown
+            | Action::CycleSliceThickness => ActionCategory::Camera,
+            Action::Mine
+            | Action::StopMining
+            | Action::NextQueued
+            | Action::PreviousQueued
+            | Action::PauseQueued
+            | Action::CancelQueued
+            | Action::RaiseQueued
+            | Action::LowerQueued
+            | Action::CycleMaterial
+            | Action::Derezz
+            | Action::ToggleDefense
+            | Action::SelectArea
+            | Action::CancelArea
+            | Action::HostPool
+            | Action::JoinPool
+            | Action::LeavePool
+            | Action::SaveBlueprint
+            | Action::SelectBlueprint
+            | Action::StampBlueprint => ActionCategory::Mining,
+            Action::NextAvatar
+            | Action::PreviousAvatar
+            | Action::SortAvatars
+            | Action::FilterAvatars
+            | Action::ProbeRelays
+            | Action::AddSuggestedRelays
+            | Action::Chat
+            | Action::ToggleConsole
+            | Action::DirectMessage
+            | Action::EditProfile
+            | Action::ToggleFollow
+            | Action::LikeBlock
+            | Action::Screenshot
+            | Action::ToggleAmbience
+            | Action::ToggleSettings
+            | Action::ToggleDiagnostics
+            | Action::ToggleStats
+            | Action::Export
+            | Action::ToggleHeatmap
+            | Action::ToggleSearch
+            | Action::ToggleWaypoints
+            | Action::ToggleKeyBindings
+            | Action::ToggleHelp
+            | Action::ToggleTraffic
+            | Action::ToggleNoteGlyphs => ActionCategory::Ui,
+        }
+    }
+}
+
+// The key bound to every action, stored in the `[keybindings]` table of `nostrcraft.toml`
+#[derive(Resource, Debug, Clone, PartialEq)]
+pub struct KeyBindings(BTreeMap<Action, KeyCode>);
+
+impl Default for KeyBindings {
+    fn default() -> Self {
+        KeyBindings(
+            Action::ALL
+                .into_iter()
+                .map(|action| (action, action.default_key()))
+                .collect(),
+        )
+    }
+}
+
+impl KeyBindings {
+    fn load() -> Self {
+        if let Some(key_bindings) = read_config().and_then(KeyBindings::from_config) {
+            return key_bindings;
+        }
+        let Ok(bindings) = std::fs::read_to_string(LEGACY_KEY_BINDINGS_FILE_PATH) else {
+            return KeyBindings::default();
+        };
+        match toml::from_str::<BTreeMap<Action, KeyCode>>(&bindings) {
+            Ok(bindings) => KeyBindings::default().merged(bindings),
+            Err(error) => {
+                warn!(
+                    "Could not read {}: {}",
+                    LEGACY_KEY_BINDINGS_FILE_PATH, error
+                );
+                KeyBindings::default()
+            }
+        }
+    }
+
+    // None without a bindings table, or with one that doesn't parse
+    fn from_config(mut config: toml::Table) -> Option<Self> {
+        let bindings = config.remove(KEY_BINDINGS_TABLE)?;
+        match bindings.try_into::<BTreeMap<Action, KeyCode>>() {
+            Ok(bindings) => Some(KeyBindings::default().merged(bindings)),
+            Err(error) => {
+                warn!("Could not read {}: {}", CONFIG_FILE_PATH, error);
+                None
+            }
+        }
+    }
+
+    fn save(&self) {
+        let bindings = match toml::Value::try_from(&self.0) {
+            Ok(bindings) => bindings,
+            Err(error) => {
+                warn!("Could not serialize key bindings: {}", error);
+                return;
+            }
+        };
+        write_config(|config| {
+            config.insert(KEY_BINDINGS_TABLE.to_string(), bindings);
+        });
+    }
+
+    // Actions missing from the file keep their default key
+    fn merged(mut self, bindings: BTreeMap<Action, KeyCode>) -> Self {
+        for (action, key) in bindings {
+            self.rebind(action, key);
+        }
+        self
+    }
+
+    pub fn key(&self, action: Action) -> KeyCode {
+        self.0
+            .get(&action)
+            .copied()
+            .unwrap_or_else(|| action.default_key())
+    }
+
+    // A key triggers a single action, the one it's taken from gets the old key instead
+    fn rebind(&mut self, action: Action, key: KeyCode) {
+        let previous = self.key(action);
+        if let Some(other) = self
+            .0
+            .iter()
+            .find(|(other, bound)| **other != action && **bound == key)
+            .map(|(other, _)| *other)
+        {
+            self.0.insert(other, previous);
+        }
+        self.0.insert(action, key);
+    }
+}
+
+fn reload_key_bindings(
+    mut reloaded: EventReader<ConfigReloaded>,
+    mut key_bindings: ResMut<KeyBindings>,
+) {
+    for config in reloaded.read() {
+        let Some(loaded) = KeyBindings::from_config(config.0.clone()) else {
+            continue;
+        };
+        if *key_bindings != loaded {
+            *key_bindings = loaded;
+        }
+    }
+}
+
+// Set while a text prompt has the keyboard, so other prompts don't open on the keys typed into it
+#[derive(Resource, Default, Deref, DerefMut)]
+pub struct Typing(pub bool);
+
+// Short names for the panel, `KeyM` reads as `M`
+pub fn key_name(key: KeyCode) -> String {
+    let name = format!("{:?}", key);
+    ["Key", "Digit"]
+        .iter()
+        .find_map(|prefix| name.strip_prefix(prefix))
+        .map(str::to_string)
+        .unwrap_or(name)
+}
+
+// The action waiting for its new key
+#[derive(Resource, Default, Deref, DerefMut)]
+struct Rebinding(Option<Action>);
+
+#[derive(Component)]
+struct KeyBindingsPanel;
+
+#[derive(Component)]
+struct BindingRow(Action);
+
+fn setup_key_bindings_panel(mut commands: Commands) {
+    let panel = NodeBundle {
+        style: Style {
+            position_type: PositionType::Absolute,
+            top: Val::Percent(10.0),
+            left: Val::Percent(20.0),
+            margin: MARGIN_UI,
+            padding: PADDING_UI,
+            row_gap: FLEX_GAP,
+            flex_direction: FlexDirection::Column,
+            border: BORDER_WIDTH,
+            ..Default::default()
+        },
+        border_color: BorderColor(LIGHT_GRAY),
+        background_color: BackgroundColor(Color::rgba(0.0, 0.0, 0.0, 0.85)),
+        visibility: Visibility::Hidden,
+        ..Default::default()
+    };
+    // Too many actions for one column, they wrap into more
+    let rows = NodeBundle {
+        style: Style {
+            max_height: Val::Vh(65.0),
+            row_gap: Val::Px(2.0),
+            column_gap: FLEX_GAP,
+            flex_direction: FlexDirection::Column,
+            flex_wrap: FlexWrap::Wrap,
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    commands
+        .spawn((panel, KeyBindingsPanel, SessionPanel("key_bindings")))
+        .with_children(|panel| {
+            panel.spawn(text_bundle_builder("Key Bindings".to_string(), TITLE_FONT));
+            panel.spawn(text_bundle_builder(
+                "Click an action, then press its new key. Esc cancels".to_string(),
+                NORMAL_FONT,
+            ));
+            panel.spawn(rows).with_children(|rows| {
+                for action in Action::ALL {
+                    rows.spawn((
+                        text_bundle_builder(String::new(), NORMAL_FONT),
+                        Interaction::default(),
+                        BindingRow(action),
+                    ));
+                }
+            });
+        });
+}
+
+fn toggle_key_bindings_panel(
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    key_bindings: Res<KeyBindings>,
+    mut rebinding: ResMut<Rebinding>,
+    mut panel_query: Query<&mut Visibility, With<KeyBindingsPanel>>,
+) {
+    if !keyboard_input.just_pressed(key_bindings.key(Action::ToggleKeyBindings)) {
+        return;
+    }
+    for mut visibility in panel_query.iter_mut() {
+        *visibility = match *visibility {
+            Visibility::Hidden => Visibility::Visible,
+            _ => Visibility::Hidden,
+        };
+    }
+    if rebinding.is_some() {
+        **rebinding = None;
+    }
+}
+
+fn pick_binding(
+    mut row_query: Query<(&Interaction, &BindingRow, &mut BackgroundColor), Changed<Interaction>>,
+    mut rebinding: ResMut<Rebinding>,
+) {
+    for (interaction, row, mut background) in row_query.iter_mut() {
+        *background = match interaction {
+            Interaction::Pressed => {
+                **rebinding = Some(row.0);
+                BINDING_HOVER_COLOR.into()
+            }
+            Interaction::Hovered => BINDING_HOVER_COLOR.into(),
+            Interaction::None => Color::NONE.into(),
+        };
+    }
+}
+
+fn capture_rebind_key(
+    mut keyboard_input: ResMut<ButtonInput<KeyCode>>,
+    mut key_bindings: ResMut<KeyBindings>,
+    mut rebinding: ResMut<Rebinding>,
+) {
+    let Some(action) = **rebinding else {
+        return;
+    };
+    let Some(key) = keyboard_input.get_just_pressed().next().copied() else {
+        return;
+    };
+    keyboard_input.clear_just_pressed(key);
+    **rebinding = None;
+    if key == CANCEL_REBIND_KEY {
+        return;
+    }
+    key_bindings.rebind(action, key);
+    key_bindings.save();
+}
+
+fn update_key_bindings_panel(
+    key_bindings: Res<KeyBindings>,
+    rebinding: Res<Rebinding>,
+    mut row_query: Query<(&mut Text, &BindingRow)>,
+) {
+    if !key_bindings.is_changed() && !rebinding.is_changed() {
+        return;
+    }
+    for (mut text, row) in row_query.iter_mut() {
+        let (key, color) = if **rebinding == Some(row.0) {
+            ("press a key".to_string(), REBINDING_COLOR)
+        } else {
+            (key_name(key_bindings.key(row.0)), Color::WHITE)
+        };
+        text.sections[0].value = format!("{}: {}", row.0.label(), key);
+        text.sections[0].style.color = color;
+    }
+}
+
+#[derive(Component)]
+struct HelpOverlay;
+
+#[derive(Component)]
+struct HelpColumn(ActionCategory);
+
+// One line per action of the category, with the key it's bound to right now
+fn help_lines(key_bindings: &KeyBindings, category: ActionCategory) -> String {
+    Action::ALL
+        .into_iter()
+        .filter(|action| action.category() == category)
+        .map(|action| format!("{}  {}", key_name(key_bindings.key(action)), action.label()))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn setup_help_overlay(mut commands: Commands) {
+    let overlay = NodeBundle {
+        style: Style {
+            position_type: PositionType::Absolute,
+            top: Val::Percent(10.0),
+            left: Val::Percent(10.0),
+            margin: MARGIN_UI,
+            padding: PADDING_UI,
+            row_gap: FLEX_GAP,
+            flex_direction: FlexDirection::Column,
+            border: BORDER_WIDTH,
+            ..Default::default()
+        },
+        border_color: BorderColor(LIGHT_GRAY),
+        background_color: BackgroundColor(Color::rgba(0.0, 0.0, 0.0, 0.85)),
+        visibility: Visibility::Hidden,
+        z_index: ZIndex::Global(5),
+        ..Default::default()
+    };
+    let columns = NodeBundle {
+        style: Style {
+            column_gap: FLEX_GAP,
+            align_items: AlignItems::FlexStart,
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    commands
+        .spawn((overlay, HelpOverlay, SessionPanel("help")))
+        .with_children(|overlay| {
+            overlay.spawn(text_bundle_builder("Controls".to_string(), TITLE_FONT));
+            overlay.spawn(columns).with_children(|columns| {
+                for category in ActionCategory::ALL {
+                    columns
+                        .spawn(NodeBundle {
+                            style: Style {
+                                flex_direction: FlexDirection::Column,
+                                ..Default::default()
+                            },
+                            ..Default::default()
+                        })
+                        .with_children(|column| {
+                            column.spawn(text_bundle_builder(
+                                category.label().to_string(),
+                                TITLE_FONT,
+                            ));
+                            column.spawn((
+                                text_bundle_builder(String::new(), NORMAL_FONT),
+                                HelpColumn(category),
+                            ));
+                        });
+                }
+            });
+        });
+}
+
+fn toggle_help_overlay(
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    key_bindings: Res<KeyBindings>,
+    mut overlay_query: Query<&mut Visibility, With<HelpOverlay>>,
+) {
+    if !keyboard_input.just_pressed(key_bindings.key(Action::ToggleHelp)) {
+        return;
+    }
+    for mut visibility in overlay_query.iter_mut() {
+        *visibility = match *visibility {
+            Visibility::Hidden => Visibility::Visible,
+            _ => Visibility::Hidden,
+        };
+    }
+}
+
+// Rewritten on every rebind, so it never shows a stale key
+fn update_help_overlay(
+    key_bindings: Res<KeyBindings>,
+    mut column_query: Query<(&mut Text, &HelpColumn)>,
+) {
+    if !key_bindings.is_changed() {
+        return;
+    }
+    for (mut text, column) in column_query.iter_mut() {
+        text.sections[0].value = help_lines(&key_bindings, column.0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rebinding_a_taken_key_swaps_it() {
+        let mut key_bindings = KeyBindings::default();
+        key_bindings.rebind(Action::Mine, KeyCode::KeyN);
+        assert_eq!(key_bindings.key(Action::Mine), KeyCode::KeyN);
+        assert_eq!(key_bindings.key(Action::StopMining), KeyCode::KeyM);
+    }
+
+    #[test]
+    fn config_overrides_only_listed_actions() {
+        let bindings: BTreeMap<Action, KeyCode> =
+            toml::from_str("go_home = \"F10\"\nteleport_to_avatar = \"F11\"").unwrap();
+        let key_bindings = KeyBindings::default().merged(bindings);
+        assert_eq!(key_bindings.key(Action::GoHome), KeyCode::F10);
+        assert_eq!(key_bindings.key(Action::TeleportToAvatar), KeyCode::F11);
+        assert_eq!(key_bindings.key(Action::Mine), KeyCode::KeyM);
+    }
+
+    #[test]
+    fn config_keys_taken_from_other_actions_are_swapped() {
+        let bindings: BTreeMap<Action, KeyCode> =
+            toml::from_str("teleport_to_avatar = \"F11\"").unwrap();
+        let key_bindings = KeyBindings::default().merged(bindings);
+        assert_eq!(key_bindings.key(Action::ToggleFullscreen), KeyCode::End);
+    }
+
+    #[test]
+    fn bindings_are_read_from_their_config_table() {
+        let config: toml::Table = "ui_scale = 1.5\n[keybindings]\nmine = \"KeyZ\""
+            .parse()
+            .unwrap();
+        let key_bindings = KeyBindings::from_config(config).unwrap();
+        assert_eq!(key_bindings.key(Action::Mine), KeyCode::KeyZ);
+        assert_eq!(key_bindings.key(Action::StopMining), KeyCode::KeyN);
+        assert_eq!(KeyBindings::from_config(toml::Table::new()), None);
+    }
+
+    #[test]
+    fn every_action_has_its_own_default_key() {
+        let key_bindings = KeyBindings::default();
+        assert_eq!(key_bindings.0.len(), Action::ALL.len());
+        let mut keys: Vec<String> = key_bindings.0.values().map(|key| key_name(*key)).collect();
+        keys.sort();
+        keys.dedup();
+        assert_eq!(keys.len(), Action::ALL.len());
+        assert_eq!(key_name(KeyCode::KeyM), "M");
+        assert_eq!(key_name(KeyCode::PageUp), "PageUp");
+    }
+
+    #[test]
+    fn help_follows_rebinds() {
+        let mut key_bindings = KeyBindings::default();
+        key_bindings.rebind(Action::Mine, KeyCode::KeyZ);
+        let mining_help = help_lines(&key_bindings, ActionCategory::Mining);
+        assert!(mining_help.contains("Z  Mine placed blocks"));
+        assert!(!help_lines(&key_bindings, ActionCategory::Movement).contains("Mine placed"));
+    }
+}
@@ -0,0 +1,311 @@
+use std::{
+    fs::{File, OpenOptions},
+    io::Write,
+    str::FromStr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex,
+    },
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use bevy::{
+    log::{
+        tracing_subscriber::{
+            layer::{Context, SubscriberExt},
+            Layer,
+        },
+        BoxedSubscriber, Level, LogPlugin,
+    },
+    prelude::*,
+    utils::tracing::{
+        self,
+        field::{Field, Visit},
+        Subscriber,
+    },
+};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+
+use crate::{
+    config::{read_config, ConfigReloaded, CONFIG_FILE_PATH},
+    console::{ConsoleCommand, ConsoleOutput, RegisterConsoleCommand},
+};
+
+// Log targets, each with its own level in the `[log]` table
+pub const NOSTR_LOG: &str = "nostr";
+pub const MINING_LOG: &str = "mining";
+pub const WORLD_LOG: &str = "world";
+// Every message read from or sent to a relay, only logged while verbose relay logging is on
+pub const RELAY_LOG: &str = "relays";
+
+const LOG_TABLE: &str = "log";
+// Bevy's own default, the renderer is very chatty otherwise
+const RENDERER_FILTER: &str = "wgpu=error,naga=warn";
+
+// Read by the relay tasks, which run outside the ECS
+static VERBOSE_RELAYS: AtomicBool = AtomicBool::new(false);
+
+pub fn verbose_relays() -> bool {
+    VERBOSE_RELAYS.load(Ordering::Relaxed)
+}
+
+fn set_verbose_relays(verbose: bool) {
+    VERBOSE_RELAYS.store(verbose, Ordering::Relaxed);
+}
+
+pub fn logging_plugin(app: &mut App) {
+    app.insert_resource(AppliedLogSettings(LogSettings::load()))
+        .register_console_command(
+            "log",
+            "/log relays [on|off]",
+            "Turns logging of every relay message on or off",
+        )
+        .add_systems(Update, (log_command, reload_log_settings));
+}
+
+// The `[log]` table of `nostrcraft.toml`. Levels are applied on launch, RUST_LOG still
+// overrides all of them
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct LogSettings {
+    // Level of everything without a level of its own
+    pub level: String,
+    pub nostr: Option<String>,
+    pub mining: Option<String>,
+    pub world: Option<String>,
+    // Every log line is also appended to this file as JSON, empty for none
+    pub file: String,
+    // Can also be turned on and off with /log relays while playing
+    pub verbose_relays: bool,
+}
+
+impl Default for LogSettings {
+    fn default() -> Self {
+        LogSettings {
+            level: "info".to_string(),
+            nostr: None,
+            mining: None,
+            world: None,
+            file: String::new(),
+            verbose_relays: false,
+        }
+    }
+}
+
+impl LogSettings {
+    // Runs before the log plugin exists, a broken table is reported once logging is up
+    pub fn load() -> Self {
+        read_config()
+            .and_then(LogSettings::from_config)
+            .unwrap_or_default()
+    }
+
+    fn from_config(mut config: toml::Table) -> Option<Self> {
+        let Some(table) = config.remove(LOG_TABLE) else {
+            return Some(LogSettings::default());
+        };
+        match table.try_into::<LogSettings>() {
+            Ok(log_settings) => Some(log_settings),
+            Err(error) => {
+                warn!(
+                    "Could not read [{}] in {}: {}",
+                    LOG_TABLE, CONFIG_FILE_PATH, error
+                );
+                None
+            }
+        }
+    }
+
+    fn level(&self) -> Level {
+        Level::from_str(&self.level).unwrap_or(Level::INFO)
+    }
+
+    // Directives for the targets given a level, relay logging is gated by the toggle instead
+    fn filter(&self) -> String {
+        let mut filter = vec![RENDERER_FILTER.to_string(), format!("{}=debug", RELAY_LOG)];
+        for (target, level) in [
+            (NOSTR_LOG, &self.nostr),
+            (MINING_LOG, &self.mining),
+            (WORLD_LOG, &self.world),
+        ] {
+            if let Some(level) = level
+                .as_deref()
+                .and_then(|level| Level::from_str(level).ok())
+            {
+                filter.push(format!("{}={}", target, level));
+            }
+        }
+        filter.join(",")
+    }
+
+    fn file(&self) -> Option<&str> {
+        let file = self.file.trim();
+        (!file.is_empty()).then_some(file)
+    }
+}
+
+// The table as last read, so a reload only changes what was edited and a toggle from the
+// console isn't undone by edits to other keys
+#[derive(Resource, Deref, DerefMut)]
+struct AppliedLogSettings(LogSettings);
+
+// Replaces the default log plugin, levels and the JSON file come from the config file
+pub fn log_plugin() -> LogPlugin {
+    let log_settings = LogSettings::load();
+    set_verbose_relays(log_settings.verbose_relays);
+    LogPlugin {
+        filter: log_settings.filter(),
+        level: log_settings.level(),
+        update_subscriber: Some(add_json_file),
+    }
+}
+
+// A plain function pointer, so the file is looked up again rather than captured
+fn add_json_file(subscriber: BoxedSubscriber) -> BoxedSubscriber {
+    let log_settings = LogSettings::load();
+    let Some(path) = log_settings.file() else {
+        return subscriber;
+    };
+    match OpenOptions::new().create(true).append(true).open(path) {
+        Ok(file) => Box::new(subscriber.with(JsonFileLayer(Mutex::new(file)))),
+        Err(error) => {
+            eprintln!("Could not open log file {}: {}", path, error);
+            subscriber
+        }
+    }
+}
+
+// One JSON object per line, written after the filter so the file holds what the console does
+struct JsonFileLayer(Mutex<File>);
+
+impl<S: Subscriber> Layer<S> for JsonFileLayer {
+    fn on_event(&self, event: &tracing::Event<'_>, _ctx: Context<'_, S>) {
+        let mut fields = JsonFields::default();
+        event.record(&mut fields);
+        let metadata = event.metadata();
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|time| time.as_secs_f64())
+            .unwrap_or_default();
+        let line = json!({
+            "timestamp": timestamp,
+            "level": metadata.level().as_str(),
+            "target": metadata.target(),
+            "fields": fields.0,
+        });
+        if let Ok(mut file) = self.0.lock() {
+            let _ = writeln!(file, "{}", line);
+        }
+    }
+}
+
+#[derive(Default)]
+struct JsonFields(Map<String, Value>);
+
+impl Visit for JsonFields {
+    fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+        self.0
+            .insert(field.name().to_string(), json!(format!("{:?}", value)));
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.0.insert(field.name().to_string(), json!(value));
+    }
+
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.0.insert(field.name().to_string(), json!(value));
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.0.insert(field.name().to_string(), json!(value));
+    }
+
+    fn record_f64(&mut self, field: &Field, value: f64) {
+        self.0.insert(field.name().to_string(), json!(value));
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.0.insert(field.name().to_string(), json!(value));
+    }
+}
+
+fn log_command(
+    mut console_commands: EventReader<ConsoleCommand>,
+    mut console_output: EventWriter<ConsoleOutput>,
+) {
+    for command in console_commands.read() {
+        if command.name != "log" {
+            continue;
+        }
+        let verbose = match command.args.as_slice() {
+            [target] if target == "relays" => !verbose_relays(),
+            [target, toggle] if target == "relays" && toggle == "on" => true,
+            [target, toggle] if target == "relays" && toggle == "off" => false,
+            _ => {
+                console_output.send(ConsoleOutput("Usage: /log relays [on|off]".to_string()));
+                continue;
+            }
+        };
+        set_verbose_relays(verbose);
+        console_output.send(ConsoleOutput(format!(
+            "Relay logging {}",
+            if verbose { "on" } else { "off" }
+        )));
+    }
+}
+
+// Only the relay toggle follows the file while playing, levels need a restart
+fn reload_log_settings(
+    mut reloaded: EventReader<ConfigReloaded>,
+    mut applied: ResMut<AppliedLogSettings>,
+) {
+    for config in reloaded.read() {
+        let Some(loaded) = LogSettings::from_config(config.0.clone()) else {
+            continue;
+        };
+        if loaded.verbose_relays != applied.verbose_relays {
+            set_verbose_relays(loaded.verbose_relays);
+        }
+        if loaded.filter() != applied.filter() || loaded.level() != applied.level() {
+            info!(
+                "Log levels in {} apply on the next launch",
+                CONFIG_FILE_PATH
+            );
+        }
+        **applied = loaded;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn log_table_sets_target_levels() {
+        let config: toml::Table = r#"
+            bloom_intensity = 0.5
+
+            [log]
+            level = "warn"
+            nostr = "debug"
+            world = "loud"
+        "#
+        .parse()
+        .unwrap();
+        let log_settings = LogSettings::from_config(config).unwrap();
+        assert_eq!(log_settings.level(), Level::WARN);
+        assert_eq!(
+            log_settings.filter(),
+            "wgpu=error,naga=warn,relays=debug,nostr=DEBUG"
+        );
+        assert_eq!(log_settings.file(), None);
+    }
+
+    #[test]
+    fn missing_log_table_uses_defaults() {
+        let log_settings = LogSettings::from_config(toml::Table::new()).unwrap();
+        assert_eq!(log_settings, LogSettings::default());
+        assert_eq!(log_settings.level(), Level::INFO);
+    }
+}
@@ -8,8 +8,12 @@ use cameras::camera_plugin;
 
 mod ui_camera;
 
+#[cfg(feature = "gpu-mining")]
+mod gpu_mining;
 mod mining;
-use cyberspace::{extract_coordinates, scale_coordinates_to_world};
+#[cfg(target_arch = "wasm32")]
+mod web_mining;
+use cyberspace::CyberspaceCoordinates;
 use mining::mining_plugin;
 
 mod resources;
@@ -17,55 +21,286 @@ use nostro2::userkeys::UserKeys;
 use resources::world_plugin;
 
 mod nostr;
-use nostr::{websocket_middleware, websocket_thread};
+use nostr::nostr_plugin;
+
+mod protocol;
+use protocol::dump_protocol;
+
+mod channels;
+
+mod verification;
+
+mod supervisor;
+use supervisor::TaskSupervisor;
+
+mod diagnostics;
+use diagnostics::diagnostics_plugin;
+
+mod settings;
+use settings::{settings_plugin, Settings};
+
+mod stats;
+use stats::stats_plugin;
+
+mod pool;
+use pool::pool_plugin;
+
+mod notifications;
+use notifications::notifications_plugin;
+
+mod chunks;
+use chunks::chunks_plugin;
+
+mod minimap;
+use minimap::minimap_plugin;
+
+mod starfield;
+use starfield::starfield_plugin;
+
+mod export;
+use export::export_plugin;
+
+mod blueprints;
+use blueprints::blueprints_plugin;
+
+mod picking;
+use picking::picking_plugin;
+
+mod search;
+use search::search_plugin;
+
+mod keybindings;
+use keybindings::keybindings_plugin;
+
+mod gamepad;
+use gamepad::gamepad_plugin;
+
+mod destination_prompt;
+use destination_prompt::destination_prompt_plugin;
+
+mod waypoints;
+use waypoints::waypoints_plugin;
+
+mod touch;
+use touch::touch_plugin;
+
+mod chat;
+use chat::chat_plugin;
+
+mod nip04;
+
+mod direct_messages;
+use direct_messages::direct_messages_plugin;
+
+mod console;
+use console::console_plugin;
+
+mod menu;
+use menu::{menu_plugin, AppState};
+
+mod traffic;
+use traffic::traffic_plugin;
+
+mod placement;
+use placement::placement_plugin;
+
+mod origin;
+use origin::{origin_plugin, CyberspacePosition};
+
+mod constructs;
+use constructs::constructs_plugin;
+
+mod note_glyphs;
+use note_glyphs::note_glyphs_plugin;
+
+mod coords_command;
+use coords_command::coords_command;
+
+mod nip05;
+use nip05::nip05_plugin;
+
+mod zaps;
+use zaps::zaps_plugin;
+
+mod profile_editor;
+use profile_editor::profile_editor_plugin;
+
+mod contacts;
+use contacts::contacts_plugin;
+
+mod reactions;
+use reactions::reactions_plugin;
+
+mod screenshots;
+use screenshots::screenshots_plugin;
+
+mod transfers;
+use transfers::transfers_plugin;
+
+mod home_bases;
+use home_bases::home_bases_plugin;
+
+mod config;
+use config::config_plugin;
+
+mod session;
+use session::session_plugin;
+
+mod logging;
+use logging::{log_plugin, logging_plugin};
+
+mod headless;
+use headless::run_headless;
+
+mod replay;
+use replay::{replay_plugin, ReplayMode};
+
+mod sandbox;
+use sandbox::{sandbox_plugin, SandboxMode};
+
+mod block_atlas;
+use block_atlas::block_atlas_plugin;
+
+mod sounds;
+use sounds::sounds_plugin;
+
+mod music;
+use music::music_plugin;
+
+mod block_effects;
+use block_effects::block_effects_plugin;
+
+mod beacons;
+use beacons::beacons_plugin;
 
 use openssl::ec::EcKey;
 use std::sync::Arc;
 use ui_camera::ui_camera_plugin;
 
 fn main() {
+    // Mining Web Workers load this same module, see `web_mining`
+    #[cfg(target_arch = "wasm32")]
+    if web_mining::is_worker() {
+        return;
+    }
+
+    if std::env::args().any(|arg| arg == "--dump-protocol") {
+        println!("{}", dump_protocol());
+        return;
+    }
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.first().is_some_and(|arg| arg == "coords") {
+        match coords_command(&args[1..]) {
+            Ok(output) => println!("{}", output),
+            Err(usage) => {
+                eprintln!("{}", usage);
+                std::process::exit(2);
+            }
+        }
+        return;
+    }
+    if args.iter().any(|arg| arg == "--headless") {
+        let listen = args
+            .iter()
+            .position(|arg| arg == "--listen")
+            .and_then(|index| args.get(index + 1))
+            .cloned();
+        run_headless(listen);
+        return;
+    }
+
     App::new()
-        .add_plugins((
-            DefaultPlugins.set(WindowPlugin {
-                primary_window: Some(Window {
-                    title: "NostrCraft".into(),
-                    prevent_default_event_handling: true,
-                    focused: true,
-                    resizable: true,
-                    decorations: false,
-                    transparent: true,
+        .add_plugins(
+            DefaultPlugins
+                .set(WindowPlugin {
+                    primary_window: Some(Window {
+                        title: "NostrCraft".into(),
+                        prevent_default_event_handling: true,
+                        focused: true,
+                        resizable: true,
+                        decorations: false,
+                        transparent: true,
+                        ..default()
+                    }),
                     ..default()
-                }),
-                ..default()
-            }),
-            // Adds frame time diagnostics
-            // FrameTimeDiagnosticsPlugin,
-            // Adds a system that prints diagnostics to the console
-            // LogDiagnosticsPlugin::default(),
-            // Any plugin can register diagnostics. Uncomment this to add an entity count diagnostics:
-            // bevy::diagnostic::EntityCountDiagnosticsPlugin::default(),
-            // Uncomment this to add an asset count diagnostics:
-            // bevy::asset::diagnostic::AssetCountDiagnosticsPlugin::<Texture>::default(),
-            // Uncomment this to add system info diagnostics:
-            // bevy::diagnostic::SystemInformationDiagnosticsPlugin::default(),
-        ))
+                })
+                .set(log_plugin()),
+        )
+        .insert_resource(ReplayMode::from_args(&args))
+        .insert_resource(SandboxMode::from_args(&args))
         .init_resource::<UserNostrKeys>()
-        .add_systems(Startup, websocket_thread)
-        .add_systems(PostStartup, add_sample_blocks)
-        .add_systems(Update, websocket_middleware)
-        .add_plugins((camera_plugin, world_plugin, mining_plugin, ui_camera_plugin))
+        .init_resource::<TaskSupervisor>()
+        .add_systems(OnEnter(AppState::InGame), add_sample_blocks)
+        .add_plugins((
+            camera_plugin,
+            world_plugin,
+            mining_plugin,
+            ui_camera_plugin,
+            nostr_plugin,
+            diagnostics_plugin,
+            settings_plugin,
+            stats_plugin,
+            pool_plugin,
+            notifications_plugin,
+            chunks_plugin,
+            minimap_plugin,
+            starfield_plugin,
+            origin_plugin,
+            constructs_plugin,
+        ))
+        .add_plugins((
+            export_plugin,
+            blueprints_plugin,
+            picking_plugin,
+            search_plugin,
+            keybindings_plugin,
+            gamepad_plugin,
+            destination_prompt_plugin,
+            waypoints_plugin,
+            touch_plugin,
+            chat_plugin,
+            direct_messages_plugin,
+            console_plugin,
+            menu_plugin,
+            traffic_plugin,
+            placement_plugin,
+        ))
+        .add_plugins((
+            note_glyphs_plugin,
+            nip05_plugin,
+            zaps_plugin,
+            profile_editor_plugin,
+            contacts_plugin,
+            reactions_plugin,
+            screenshots_plugin,
+            transfers_plugin,
+            home_bases_plugin,
+            config_plugin,
+            session_plugin,
+            logging_plugin,
+            replay_plugin,
+            sandbox_plugin,
+            block_atlas_plugin,
+        ))
+        .add_plugins((
+            sounds_plugin,
+            music_plugin,
+            block_effects_plugin,
+            beacons_plugin,
+        ))
         .add_plugins(TokioTasksPlugin::default())
         .run();
 }
 
-const PEM_FILE_PATH: &str = "./nostr.pem";
 const DEFULT_KEYPAIR: &str = "55BE2A31916E238A5D21F44DEAF7FA2579D11EEEB98D022842A15A2C7AF2F106";
 
 #[derive(Resource)]
 struct UserNostrKeys {
     keypair: Arc<UserKeys>,
-    home_coordinates: Vec3,
+    home_coordinates: CyberspaceCoordinates,
     public_key: String,
+    // Hex, only used to encrypt and decrypt direct messages
+    secret_key: String,
 }
 
 impl UserNostrKeys {
@@ -73,13 +308,24 @@ impl UserNostrKeys {
         self.keypair.clone()
     }
 
-    fn get_home_coordinates(&self) -> Vec3 {
+    fn get_home_coordinates(&self) -> CyberspaceCoordinates {
         self.home_coordinates
     }
 
     fn get_public_key(&self) -> String {
         self.public_key.clone()
     }
+
+    // NIP-04 encrypted content of a direct message to `public_key`
+    fn encrypt_for(&self, public_key: &str, plaintext: &str) -> anyhow::Result<String> {
+        nip04::encrypt(&self.secret_key, public_key, plaintext)
+    }
+
+    // Works for messages from `public_key` and for ours to them
+    fn decrypt_with(&self, public_key: &str, content: &str) -> anyhow::Result<String> {
+        nip04::decrypt(&self.secret_key, public_key, content)
+    }
+
     fn get_display_key(&self) -> String {
         format!(
             "Your Key: {}...{}",
@@ -89,60 +335,36 @@ impl UserNostrKeys {
     }
 }
 
-impl Default for UserNostrKeys {
-    fn default() -> Self {
-        let default_keypair = Arc::new(UserKeys::new(DEFULT_KEYPAIR).unwrap());
-        let default_pubkey = default_keypair.get_public_key();
-        let default_home_coordinates = extract_coordinates(&default_pubkey).unwrap_or((0, 0, 0));
-        let scaled_home_coordinates = scale_coordinates_to_world(
-            default_home_coordinates.0,
-            default_home_coordinates.1,
-            default_home_coordinates.2,
-        );
-        let home_vec3 = Vec3::new(
-            scaled_home_coordinates.0 as f32,
-            scaled_home_coordinates.1 as f32,
-            scaled_home_coordinates.2 as f32,
-        );
-        let default_keys = UserNostrKeys {
-            keypair: default_keypair,
-            home_coordinates: home_vec3,
-            public_key: default_pubkey,
-        };
-
-        let pem_file = std::fs::read(PEM_FILE_PATH);
-        if pem_file.is_err() {
-            return default_keys;
-        }
-        let pem_file = pem_file.unwrap();
-
-        let buffer = EcKey::private_key_from_pem(&pem_file);
-        if buffer.is_err() {
-            return default_keys;
-        }
-        let buffer = buffer.unwrap();
-
-        let keypair = UserKeys::new(&buffer.private_key().to_hex_str().unwrap());
-        if keypair.is_err() {
-            return default_keys;
-        }
-        let keypair = Arc::new(keypair.unwrap());
-
+impl UserNostrKeys {
+    // Hex secret key, none if it isn't a valid secp256k1 key
+    fn from_secret_key(secret_key: &str) -> Option<Self> {
+        let keypair = Arc::new(UserKeys::new(secret_key).ok()?);
         let public_key = keypair.get_public_key();
-        let home_coordinates = extract_coordinates(&public_key).unwrap_or((0, 0, 0));
-        let scaled_home_coordinates =
-            scale_coordinates_to_world(home_coordinates.0, home_coordinates.1, home_coordinates.2);
-        let home_coordinates = Vec3::new(
-            scaled_home_coordinates.0 as f32,
-            scaled_home_coordinates.1 as f32,
-            scaled_home_coordinates.2 as f32,
-        );
-
-        UserNostrKeys {
+        let home_coordinates = CyberspaceCoordinates::from_hex(&public_key).unwrap_or_default();
+        Some(UserNostrKeys {
             keypair,
             home_coordinates,
             public_key,
-        }
+            secret_key: secret_key.to_string(),
+        })
+    }
+
+    // Everyone without a pem file shares this key
+    fn is_default(&self) -> bool {
+        self.secret_key.eq_ignore_ascii_case(DEFULT_KEYPAIR)
+    }
+}
+
+// The key in the configured pem file if there is one, the menu can pick another before the
+// world spawns
+impl Default for UserNostrKeys {
+    fn default() -> Self {
+        std::fs::read(Settings::load().key_path)
+            .ok()
+            .and_then(|pem_file| EcKey::private_key_from_pem(&pem_file).ok())
+            .and_then(|buffer| Some(buffer.private_key().to_hex_str().ok()?.to_string()))
+            .and_then(|secret_key| UserNostrKeys::from_secret_key(&secret_key))
+            .unwrap_or_else(|| UserNostrKeys::from_secret_key(DEFULT_KEYPAIR).unwrap())
     }
 }
 
@@ -152,85 +374,93 @@ fn add_sample_blocks(
     nostr_signer: Res<UserNostrKeys>,
 ) {
     // spawn a block of each type of material at my coordinate location
-    let pubkey = nostr_signer.get_public_key();
-    let home_coordinates = extract_coordinates(&pubkey).unwrap();
-    let scale_coordinates =
-        scale_coordinates_to_world(home_coordinates.0, home_coordinates.1, home_coordinates.2);
-    let home_vec = Vec3::new(
-        scale_coordinates.0,
-        scale_coordinates.1,
-        scale_coordinates.2,
-    );
+    let home_coordinates = nostr_signer.get_home_coordinates();
 
     let _spawned_block = commands
-        .spawn((PbrBundle {
-            mesh: assets.cube_mesh.clone_weak(),
-            material: assets.mud_material.clone_weak(),
-            transform: Transform::from_translation(home_vec + Vec3::new(0.0, 1.0, 0.0)),
-            ..Default::default()
-        },))
+        .spawn((
+            PbrBundle {
+                mesh: assets.cube_mesh.clone_weak(),
+                material: assets.mud_material.clone_weak(),
+                ..Default::default()
+            },
+            CyberspacePosition(home_coordinates.offset(IVec3::new(0, 1, 0))),
+        ))
         .id();
 
     let _spawned_block = commands
-        .spawn((PbrBundle {
-            mesh: assets.cube_mesh.clone_weak(),
-            material: assets.bronze_material.clone_weak(),
-            transform: Transform::from_translation(home_vec + Vec3::new(1.0, 1.0, 0.0)),
-            ..Default::default()
-        },))
+        .spawn((
+            PbrBundle {
+                mesh: assets.cube_mesh.clone_weak(),
+                material: assets.bronze_material.clone_weak(),
+                ..Default::default()
+            },
+            CyberspacePosition(home_coordinates.offset(IVec3::new(1, 1, 0))),
+        ))
         .id();
 
     let _spawned_block = commands
-        .spawn((PbrBundle {
-            mesh: assets.cube_mesh.clone_weak(),
-            material: assets.iron_material.clone_weak(),
-            transform: Transform::from_translation(home_vec + Vec3::new(2.0, 1.0, 0.0)),
-            ..Default::default()
-        },))
+        .spawn((
+            PbrBundle {
+                mesh: assets.cube_mesh.clone_weak(),
+                material: assets.iron_material.clone_weak(),
+                ..Default::default()
+            },
+            CyberspacePosition(home_coordinates.offset(IVec3::new(2, 1, 0))),
+        ))
         .id();
 
     let _spawned_block = commands
-        .spawn((PbrBundle {
-            mesh: assets.cube_mesh.clone_weak(),
-            material: assets.steel_material.clone_weak(),
-            transform: Transform::from_translation(home_vec + Vec3::new(3.0, 1.0, 0.0)),
-            ..Default::default()
-        },))
+        .spawn((
+            PbrBundle {
+                mesh: assets.cube_mesh.clone_weak(),
+                material: assets.steel_material.clone_weak(),
+                ..Default::default()
+            },
+            CyberspacePosition(home_coordinates.offset(IVec3::new(3, 1, 0))),
+        ))
         .id();
 
     let _spawned_block = commands
-        .spawn((PbrBundle {
-            mesh: assets.cube_mesh.clone_weak(),
-            material: assets.mithril_material.clone_weak(),
-            transform: Transform::from_translation(home_vec + Vec3::new(4.0, 1.0, 0.0)),
-            ..Default::default()
-        },))
+        .spawn((
+            PbrBundle {
+                mesh: assets.cube_mesh.clone_weak(),
+                material: assets.mithril_material.clone_weak(),
+                ..Default::default()
+            },
+            CyberspacePosition(home_coordinates.offset(IVec3::new(4, 1, 0))),
+        ))
         .id();
 
     let _spawned_block = commands
-        .spawn((PbrBundle {
-            mesh: assets.cube_mesh.clone_weak(),
-            material: assets.adamant_material.clone_weak(),
-            transform: Transform::from_translation(home_vec + Vec3::new(5.0, 1.0, 0.0)),
-            ..Default::default()
-        },))
+        .spawn((
+            PbrBundle {
+                mesh: assets.cube_mesh.clone_weak(),
+                material: assets.adamant_material.clone_weak(),
+                ..Default::default()
+            },
+            CyberspacePosition(home_coordinates.offset(IVec3::new(5, 1, 0))),
+        ))
         .id();
 
     let _spawned_block = commands
-        .spawn((PbrBundle {
-            mesh: assets.cube_mesh.clone_weak(),
-            material: assets.rune_material.clone_weak(),
-            transform: Transform::from_translation(home_vec + Vec3::new(6.0, 1.0, 0.0)),
-            ..Default::default()
-        },))
+        .spawn((
+            PbrBundle {
+                mesh: assets.cube_mesh.clone_weak(),
+                material: assets.rune_material.clone_weak(),
+                ..Default::default()
+            },
+            CyberspacePosition(home_coordinates.offset(IVec3::new(6, 1, 0))),
+        ))
         .id();
 
     let _spawned_block = commands
-        .spawn((PbrBundle {
-            mesh: assets.cube_mesh.clone_weak(),
-            material: assets.gold_material.clone_weak(),
-            transform: Transform::from_translation(home_vec + Vec3::new(7.0, 1.0, 0.0)),
-            ..Default::default()
-        },))
+        .spawn((
+            PbrBundle {
+                mesh: assets.cube_mesh.clone_weak(),
+                material: assets.gold_material.clone_weak(),
+                ..Default::default()
+            },
+            CyberspacePosition(home_coordinates.offset(IVec3::new(7, 1, 0))),
+        ))
         .id();
 }
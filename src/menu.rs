@@ -0,0 +1,608 @@
+use std::{sync::atomic::Ordering, time::Duration};
+
+use bech32::FromBase32;
+use bevy::{
+    input::{
+        keyboard::{Key, KeyboardInput},
+        ButtonState,
+    },
+    prelude::*,
+    ui::UiSystem,
+};
+use openssl::{
+    bn::{BigNum, BigNumContext},
+    ec::{EcGroup, EcKey, EcPoint},
+    nid::Nid,
+};
+
+use crate::{
+    keybindings::Typing,
+    nostr::{is_relay_url, NoteCounters, RelayPool},
+    resources::{short_npub, MeshesAndMaterials},
+    settings::Settings,
+    ui_camera::{
+        edit_text_field, text_bundle_builder, TextFieldEdit, BORDER_WIDTH, FLEX_GAP, LIGHT_GRAY,
+        NORMAL_FONT, PADDING_UI, TITLE_FONT,
+    },
+    UserNostrKeys,
+};
+
+// Long enough for an nsec or a relay address
+const MAX_FIELD_CHARS: usize = 128;
+// The world opens after this even if no relay answered yet
+const LOADING_TIMEOUT: Duration = Duration::from_secs(8);
+
+// The menu picks the key and relays, loading waits for the textures and a relay, then the world is played
+#[derive(Clone, Copy, Default, Eq, PartialEq, Debug, Hash, States)]
+pub enum AppState {
+    #[default]
+    Menu,
+    Loading,
+    InGame,
+}
+
+pub fn menu_plugin(app: &mut App) {
+    app.init_state::<AppState>()
+        .init_resource::<MenuInput>()
+        .add_systems(OnEnter(AppState::Menu), (setup_menu, start_typing))
+        .add_systems(OnExit(AppState::Menu), despawn_menu_screen)
+        .add_systems(OnEnter(AppState::Loading), setup_loading_screen)
+        .add_systems(OnExit(AppState::Loading), despawn_menu_screen)
+        .add_systems(OnEnter(AppState::InGame), stop_typing)
+        // After the UI saw the clicks, before the game sees the input
+        .add_systems(
+            PreUpdate,
+            (
+                type_menu_field.run_if(in_state(AppState::Menu)),
+                hold_game_input.run_if(not(in_state(AppState::InGame))),
+            )
+                .chain()
+                .after(UiSystem::Focus),
+        )
+        .add_systems(
+            Update,
+            (menu_buttons, update_menu_relays, update_menu_text)
+                .chain()
+                .run_if(in_state(AppState::Menu)),
+        )
+        .add_systems(
+            Update,
+            (update_loading_progress, finish_loading).run_if(in_state(AppState::Loading)),
+        );
+}
+
+#[derive(Component, Clone, Copy, Default, PartialEq, Eq, Debug)]
+enum MenuField {
+    #[default]
+    SecretKey,
+    Relay,
+}
+
+// What has been typed in the menu fields, and the reply to the last thing done there
+#[derive(Resource, Default)]
+struct MenuInput {
+    focused: MenuField,
+    secret_key: String,
+    relay: String,
+    status: String,
+}
+
+impl MenuInput {
+    fn typed(&mut self) -> &mut String {
+        match self.focused {
+            MenuField::SecretKey => &mut self.secret_key,
+            MenuField::Relay => &mut self.relay,
+        }
+    }
+}
+
+#[derive(Component, Clone, PartialEq, Debug)]
+enum MenuButton {
+    NewKey,
+    SaveKey,
+    Start,
+    RemoveRelay(String),
+}
+
+// Everything spawned by the menu and the loading screen
+#[derive(Component)]
+struct MenuScreen;
+
+#[derive(Component)]
+struct MenuKeyText;
+
+#[derive(Component)]
+struct MenuFieldText(MenuField);
+
+#[derive(Component)]
+struct MenuRelays;
+
+#[derive(Component)]
+struct MenuStatusText;
+
+#[derive(Component)]
+struct LoadingText;
+
+#[derive(Resource, Deref, DerefMut)]
+struct LoadingTimer(Timer);
+
+#[derive(Component)]
+struct LoadingProgressText;
+
+// The filled part of the loading bar
+#[derive(Component)]
+struct LoadingBar;
+
+// Hex secret key from a pasted nsec or hex key
+fn parse_secret_key(input: &str) -> Option<String> {
+    let input = input.trim();
+    let secret_bytes = if input.starts_with("nsec1") {
+        let (hrp, data, _) = bech32::decode(input).ok()?;
+        if hrp != "nsec" {
+            return None;
+        }
+        Vec::<u8>::from_base32(&data).ok()?
+    } else {
+        hex::decode(input).ok()?
+    };
+    (secret_bytes.len() == 32).then(|| hex::encode(secret_bytes))
+}
+
+fn generate_secret_key() -> Option<String> {
+    let group = EcGroup::from_curve_name(Nid::SECP256K1).ok()?;
+    let key = EcKey::generate(&group).ok()?;
+    let secret_key = key.private_key().to_hex_str().ok()?;
+    Some(format!("{:0>64}", &*secret_key))
+}
+
+// The same format the pem file is read in at startup
+fn secret_key_pem(secret_key: &str) -> Option<Vec<u8>> {
+    let group = EcGroup::from_curve_name(Nid::SECP256K1).ok()?;
+    let private_key = BigNum::from_hex_str(secret_key).ok()?;
+    let context = BigNumContext::new().ok()?;
+    let mut public_key = EcPoint::new(&group).ok()?;
+    public_key
+        .mul_generator(&group, &private_key, &context)
+        .ok()?;
+    let key = EcKey::from_private_components(&group, &private_key, &public_key).ok()?;
+    key.private_key_to_pem().ok()
+}
+
+fn start_typing(mut typing: ResMut<Typing>) {
+    **typing = true;
+}
+
+fn stop_typing(mut typing: ResMut<Typing>) {
+    **typing = false;
+}
+
+// Keys and clicks meant for the menu don't trigger anything in the world behind it
+fn hold_game_input(
+    mut keyboard_input: ResMut<ButtonInput<KeyCode>>,
+    mut mouse_input: ResMut<ButtonInput<MouseButton>>,
+) {
+    keyboard_input.clear();
+    mouse_input.clear();
+}
+
+fn type_menu_field(
+    mut keyboard_events: EventReader<KeyboardInput>,
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    settings: Res<Settings>,
+    mut menu_input: ResMut<MenuInput>,
+    mut user_keys: ResMut<UserNostrKeys>,
+    mut relay_pool: ResMut<RelayPool>,
+    mut next_state: ResMut<NextState<AppState>>,
+) {
+    for event in keyboard_events.read() {
+        if event.state != ButtonState::Pressed {
+            continue;
+        }
+        match &event.logical_key {
+            Key::Tab => {
+                menu_input.focused = match menu_input.focused {
+                    MenuField::SecretKey => MenuField::Relay,
+                    MenuField::Relay => MenuField::SecretKey,
+                };
+            }
+            // An empty field starts the game
+            Key::Enter if menu_input.typed().trim().is_empty() => {
+                if relay_pool.is_empty() {
+                    menu_input.status = "Add a relay first".to_string();
+                } else {
+                    next_state.set(AppState::Loading);
+                }
+            }
+            Key::Enter => {
+                let typed = std::mem::take(menu_input.typed());
+                menu_input.status = match menu_input.focused {
+                    MenuField::SecretKey => match parse_secret_key(&typed)
+                        .and_then(|secret_key| UserNostrKeys::from_secret_key(&secret_key))
+                    {
+                        Some(keys) => {
+                            *user_keys = keys;
+                            format!("Using {}", short_npub(&user_keys.get_public_key()))
+                        }
+                        None => "Not an nsec or hex secret key".to_string(),
+                    },
+                    MenuField::Relay => {
+                        let relay_url = typed.trim();
+                        if !is_relay_url(relay_url) {
+                            format!("Not a relay address: {}", relay_url)
+                        } else if relay_pool.add(relay_url) {
+                            format!("Added {}", relay_url)
+                        } else {
+                            format!("{} is already in the pool", relay_url)
+                        }
+                    }
+                };
+            }
+            // Keys and relay addresses have no spaces
+            Key::Space => {}
+            key => {
+                let edit =
+                    edit_text_field(menu_input.typed(), key, MAX_FIELD_CHARS, &keyboard_input);
+                if edit == TextFieldEdit::NothingToPaste {
+                    menu_input.status = "Nothing to paste".to_string();
+                }
+            }
+        }
+    }
+}
+
+fn menu_buttons(
+    button_query: Query<(&Interaction, &MenuButton), Changed<Interaction>>,
+    field_query: Query<(&Interaction, &MenuField), Changed<Interaction>>,
+    mut menu_input: ResMut<MenuInput>,
+    mut user_keys: ResMut<UserNostrKeys>,
+    mut relay_pool: ResMut<RelayPool>,
+    mut next_state: ResMut<NextState<AppState>>,
+) {
+    for (interaction, field) in field_query.iter() {
+        if *interaction == Interaction::Pressed {
+            menu_input.focused = *field;
+        }
+    }
+    for (interaction, button) in button_query.iter() {
+        if *interaction != Interaction::Pressed {
+            continue;
+        }
+        match button {
+            MenuButton::NewKey => {
+                match generate_secret_key()
+                    .and_then(|secret_key| UserNostrKeys::from_secret_key(&secret_key))
+                {
+                    Some(keys) => {
+                        *user_keys = keys;
+                        menu_input.status =
+                            "New key made, save it to keep it after closing the game".to_string();
+                    }
+                    None => menu_input.status = "Could not make a new key".to_string(),
+                }
+            }
+            // Never overwrites a key that is already saved
+            MenuButton::SaveKey => {
+                let key_path = &settings.key_path;
+                menu_input.status = if std::path::Path::new(key_path).exists() {
+                    format!("{} already exists, move it away first", key_path)
+                } else {
+                    match secret_key_pem(&user_keys.secret_key)
+                        .map(|pem| std::fs::write(key_path, pem))
+                    {
+                        Some(Ok(())) => format!("Saved to {}", key_path),
+                        _ => format!("Could not write {}", key_path),
+                    }
+                };
+            }
+            MenuButton::Start if relay_pool.is_empty() => {
+                menu_input.status = "Add a relay first".to_string();
+            }
+            MenuButton::Start => next_state.set(AppState::Loading),
+            MenuButton::RemoveRelay(relay_url) => {
+                relay_pool.retain(|relay| &relay.url != relay_url);
+            }
+        }
+    }
+}
+
+fn setup_menu(mut commands: Commands) {
+    commands
+        .spawn((menu_screen(), MenuScreen))
+        .with_children(|menu_ui| {
+            menu_ui.spawn(text_bundle_builder("NostrCraft".to_string(), TITLE_FONT));
+            menu_ui.spawn((text_bundle_builder(String::new(), NORMAL_FONT), MenuKeyText));
+            menu_ui.spawn(text_bundle_builder(
+                "Secret key, nsec or hex, Enter to use it".to_string(),
+                NORMAL_FONT,
+            ));
+            spawn_menu_field(menu_ui, MenuField::SecretKey);
+            menu_ui.spawn(menu_row()).with_children(|row| {
+                spawn_menu_button(row, "[New key]", MenuButton::NewKey);
+                spawn_menu_button(row, "[Save key]", MenuButton::SaveKey);
+            });
+
+            menu_ui.spawn(text_bundle_builder("Relays".to_string(), TITLE_FONT));
+            menu_ui.spawn((
+                NodeBundle {
+                    style: Style {
+                        flex_direction: FlexDirection::Column,
+                        ..Default::default()
+                    },
+                    ..Default::default()
+                },
+                MenuRelays,
+            ));
+            menu_ui.spawn(text_bundle_builder(
+                "Relay address, Enter to add it".to_string(),
+                NORMAL_FONT,
+            ));
+            spawn_menu_field(menu_ui, MenuField::Relay);
+
+            menu_ui.spawn((
+                text_bundle_builder(String::new(), NORMAL_FONT),
+                MenuStatusText,
+            ));
+            spawn_menu_button(menu_ui, "[Enter Cyberspace]", MenuButton::Start);
+            menu_ui.spawn(text_bundle_builder(
+                "Tab switches fields, Ctrl+V pastes, Enter on an empty field starts".to_string(),
+                NORMAL_FONT,
+            ));
+        });
+}
+
+// Covers the world until the game starts
+fn menu_screen() -> NodeBundle {
+    NodeBundle {
+        style: Style {
+            position_type: PositionType::Absolute,
+            width: Val::Percent(100.0),
+            height: Val::Percent(100.0),
+            padding: PADDING_UI,
+            row_gap: FLEX_GAP,
+            flex_direction: FlexDirection::Column,
+            align_items: AlignItems::Center,
+            justify_content: JustifyContent::Center,
+            ..Default::default()
+        },
+        background_color: BackgroundColor(Color::rgb(0.02, 0.02, 0.04)),
+        z_index: ZIndex::Global(20),
+        ..Default::default()
+    }
+}
+
+fn menu_row() -> NodeBundle {
+    NodeBundle {
+        style: Style {
+            column_gap: FLEX_GAP,
+            align_items: AlignItems::Center,
+            ..Default::default()
+        },
+        ..Default::default()
+    }
+}
+
+fn spawn_menu_field(parent: &mut ChildBuilder, field: MenuField) {
+    let field_ui = NodeBundle {
+        style: Style {
+            width: Val::Percent(40.0),
+            padding: PADDING_UI,
+            border: BORDER_WIDTH,
+            ..Default::default()
+        },
+        border_color: BorderColor(LIGHT_GRAY),
+        ..Default::default()
+    };
+    parent
+        .spawn((field_ui, Interaction::default(), field))
+        .with_children(|field_ui| {
+            field_ui.spawn((
+                text_bundle_builder(String::new(), NORMAL_FONT),
+                MenuFieldText(field),
+            ));
+        });
+}
+
+fn spawn_menu_button(parent: &mut ChildBuilder, label: &str, button: MenuButton) {
+    parent.spawn((
+        text_bundle_builder(label.to_string(), NORMAL_FONT),
+        Interaction::default(),
+        button,
+    ));
+}
+
+fn update_menu_relays(
+    mut commands: Commands,
+    relay_pool: Res<RelayPool>,
+    relays_query: Query<Entity, With<MenuRelays>>,
+) {
+    if !relay_pool.is_changed() {
+        return;
+    }
+    for container in relays_query.iter() {
+        commands
+            .entity(container)
+            .despawn_descendants()
+            .with_children(|container| {
+                for relay in relay_pool.iter() {
+                    container.spawn(menu_row()).with_children(|row| {
+                        spawn_menu_button(row, "[x]", MenuButton::RemoveRelay(relay.url.clone()));
+                        row.spawn(text_bundle_builder(relay.url.clone(), NORMAL_FONT));
+                    });
+                }
+            });
+    }
+}
+
+fn update_menu_text(
+    menu_input: Res<MenuInput>,
+    user_keys: Res<UserNostrKeys>,
+    mut text_query: Query<(
+        &mut Text,
+        Option<&MenuKeyText>,
+        Option<&MenuFieldText>,
+        Option<&MenuStatusText>,
+    )>,
+) {
+    if !menu_input.is_changed() && !user_keys.is_changed() {
+        return;
+    }
+    for (mut text, key_text, field_text, status_text) in text_query.iter_mut() {
+        if key_text.is_some() {
+            let npub = short_npub(&user_keys.get_public_key());
+            text.sections[0].value = if user_keys.is_default() {
+                format!("Key: {} (shared by everyone without a key)", npub)
+            } else {
+                format!("Key: {}", npub)
+            };
+        } else if let Some(MenuFieldText(field)) = field_text {
+            // The secret key is never shown
+            let typed = match field {
+                MenuField::SecretKey => "*".repeat(menu_input.secret_key.chars().count()),
+                MenuField::Relay => menu_input.relay.clone(),
+            };
+            let cursor = if *field == menu_input.focused {
+                "_"
+            } else {
+                ""
+            };
+            text.sections[0].value = format!("> {}{}", typed, cursor);
+        } else if status_text.is_some() {
+            text.sections[0].value = menu_input.status.clone();
+        }
+    }
+}
+
+fn setup_loading_screen(mut commands: Commands, relay_pool: Res<RelayPool>) {
+    commands.insert_resource(LoadingTimer(Timer::new(LOADING_TIMEOUT, TimerMode::Once)));
+    commands
+        .spawn((menu_screen(), MenuScreen))
+        .with_children(|loading_ui| {
+            loading_ui.spawn((
+                text_bundle_builder(
+                    format!("Connecting to {} relays...", relay_pool.len()),
+                    TITLE_FONT,
+                ),
+                LoadingText,
+            ));
+            loading_ui.spawn((
+                text_bundle_builder("Loading textures...".to_string(), NORMAL_FONT),
+                LoadingProgressText,
+            ));
+            let bar_ui = NodeBundle {
+                style: Style {
+                    width: Val::Percent(40.0),
+                    height: Val::Px(24.0),
+                    border: BORDER_WIDTH,
+                    ..Default::default()
+                },
+                border_color: BorderColor(LIGHT_GRAY),
+                ..Default::default()
+            };
+            loading_ui.spawn(bar_ui).with_children(|bar_ui| {
+                bar_ui.spawn((
+                    NodeBundle {
+                        style: Style {
+                            width: Val::Percent(0.0),
+                            height: Val::Percent(100.0),
+                            ..Default::default()
+                        },
+                        background_color: BackgroundColor(LIGHT_GRAY),
+                        ..Default::default()
+                    },
+                    LoadingBar,
+                ));
+            });
+        });
+}
+
+// Share of the textures done, an empty list is nothing to wait for
+fn loading_progress(loaded: usize, total: usize) -> f32 {
+    if total == 0 {
+        return 1.0;
+    }
+    loaded.min(total) as f32 / total as f32
+}
+
+fn update_loading_progress(
+    asset_server: Res<AssetServer>,
+    meshes_and_materials: Res<MeshesAndMaterials>,
+    mut progress_text_query: Query<&mut Text, With<LoadingProgressText>>,
+    mut bar_query: Query<&mut Style, With<LoadingBar>>,
+) {
+    let loaded = meshes_and_materials.loaded_textures(&asset_server);
+    let total = meshes_and_materials.block_textures.len();
+    for mut text in progress_text_query.iter_mut() {
+        let progress = format!("Loading textures {}/{}", loaded, total);
+        if text.sections[0].value != progress {
+            text.sections[0].value = progress;
+        }
+    }
+    for mut style in bar_query.iter_mut() {
+        let width = Val::Percent(loading_progress(loaded, total) * 100.0);
+        if style.width != width {
+            style.width = width;
+        }
+    }
+}
+
+// The world opens once every block texture is in, so blocks never show up untextured, and a
+// relay answered or the timeout passed, so a dead relay can't hold it
+fn finish_loading(
+    time: Res<Time>,
+    mut loading_timer: ResMut<LoadingTimer>,
+    relay_pool: Res<RelayPool>,
+    note_counters: Res<NoteCounters>,
+    asset_server: Res<AssetServer>,
+    meshes_and_materials: Res<MeshesAndMaterials>,
+    mut next_state: ResMut<NextState<AppState>>,
+) {
+    let relay_answered = relay_pool.iter().any(|relay| relay.information.is_some())
+        || note_counters.incoming.load(Ordering::Relaxed) > 0;
+    let relay_timed_out = loading_timer.tick(time.delta()).finished();
+    let textures_loaded = meshes_and_materials.loaded_textures(&asset_server)
+        >= meshes_and_materials.block_textures.len();
+    if textures_loaded && (relay_timed_out || relay_answered) {
+        next_state.set(AppState::InGame);
+    }
+}
+
+fn despawn_menu_screen(mut commands: Commands, screen_query: Query<Entity, With<MenuScreen>>) {
+    for screen in screen_query.iter() {
+        commands.entity(screen).despawn_recursive();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bech32::{ToBase32, Variant};
+
+    use super::*;
+
+    #[test]
+    fn secret_keys_parse_from_nsec_or_hex() {
+        let secret_key = "55be2a31916e238a5d21f44deaf7fa2579d11eeeb98d022842a15a2c7af2f106";
+        let secret_bytes = hex::decode(secret_key).unwrap();
+        let nsec = bech32::encode("nsec", secret_bytes.to_base32(), Variant::Bech32).unwrap();
+        let npub = bech32::encode("npub", secret_bytes.to_base32(), Variant::Bech32).unwrap();
+        assert_eq!(parse_secret_key(&nsec).as_deref(), Some(secret_key));
+        assert_eq!(
+            parse_secret_key(&format!(" {} ", secret_key.to_uppercase())).as_deref(),
+            Some(secret_key)
+        );
+        assert_eq!(parse_secret_key(&npub), None);
+        assert_eq!(parse_secret_key(&secret_key[2..]), None);
+    }
+
+    #[test]
+    fn generated_keys_are_full_length_hex() {
+        let secret_key = generate_secret_key().unwrap();
+        assert_eq!(parse_secret_key(&secret_key), Some(secret_key));
+    }
+
+    #[test]
+    fn loading_progress_is_the_share_of_textures_done() {
+        assert_eq!(loading_progress(2, 8), 0.25);
+        assert_eq!(loading_progress(8, 8), 1.0);
+        assert_eq!(loading_progress(0, 0), 1.0);
+    }
+}
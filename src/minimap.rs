@@ -0,0 +1,337 @@
+use bevy::{
+    prelude::*,
+    render::{
+        camera::{RenderTarget, ScalingMode},
+        render_resource::{
+            Extent3d, TextureDescriptor, TextureDimension, TextureFormat, TextureUsages,
+        },
+        view::RenderLayers,
+    },
+    ui::RelativeCursorPosition,
+};
+
+use crate::{
+    cameras::{BlockIndicator, Teleporting},
+    chunks::WorldChunks,
+    cyberspace::CyberspaceCoordinates,
+    keybindings::{Action, KeyBindings},
+    menu::AppState,
+    origin::{CyberspacePosition, FloatingOrigin},
+    resources::MeshesAndMaterials,
+    ui_camera::{
+        text_bundle_builder, BORDER_WIDTH, FLEX_GAP, LIGHT_GRAY, MARGIN_UI, NORMAL_FONT, PADDING_UI,
+    },
+    UserNostrKeys,
+};
+
+const MINIMAP_PIXELS: u32 = 256;
+const MINIMAP_UI_SIZE: Val = Val::Px(200.0);
+// World units shown across the minimap, centered on the indicator
+const MINIMAP_SPAN: f32 = 256.0;
+// The minimap camera looks straight down from this far above the indicator
+const MINIMAP_HEIGHT: f32 = 512.0;
+// Things only the minimap camera draws, like the home marker
+const MINIMAP_LAYER: u8 = 1;
+const HOME_MARKER_SIZE: f32 = 4.0;
+const HOME_MARKER_COLOR: Color = Color::rgba_linear(0.0, 40.0, 10.0, 1.0);
+// The radar counts blocks this far from the indicator, out to the sides of the minimap
+const RADAR_RANGE: i128 = MINIMAP_SPAN as i128 / 2;
+
+pub fn minimap_plugin(app: &mut App) {
+    app.init_resource::<TeleportDestination>()
+        .add_systems(PostStartup, setup_minimap)
+        .add_systems(OnEnter(AppState::Loading), move_home_marker)
+        .add_systems(
+            Update,
+            (
+                follow_indicator,
+                pick_teleport_destination,
+                teleport_to_destination,
+                update_destination_text,
+                update_radar_text,
+            ),
+        );
+}
+
+// Where the indicator goes when holding Enter, picked on the minimap
+#[derive(Resource, Default, Deref, DerefMut)]
+pub struct TeleportDestination(pub Option<CyberspaceCoordinates>);
+
+#[derive(Component)]
+struct MinimapCamera;
+
+#[derive(Component)]
+struct MinimapImage;
+
+#[derive(Component)]
+struct DestinationText;
+
+#[derive(Component)]
+struct HomeMarker;
+
+#[derive(Component)]
+struct RadarText;
+
+fn setup_minimap(
+    mut commands: Commands,
+    mut images: ResMut<Assets<Image>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    stuff: Res<MeshesAndMaterials>,
+    nostr_signer: Res<UserNostrKeys>,
+) {
+    // The minimap camera renders into this image, which the UI then shows
+    let size = Extent3d {
+        width: MINIMAP_PIXELS,
+        height: MINIMAP_PIXELS,
+        depth_or_array_layers: 1,
+    };
+    let mut image = Image {
+        texture_descriptor: TextureDescriptor {
+            label: Some("minimap"),
+            size,
+            dimension: TextureDimension::D2,
+            format: TextureFormat::Bgra8UnormSrgb,
+            mip_level_count: 1,
+            sample_count: 1,
+            usage: TextureUsages::TEXTURE_BINDING
+                | TextureUsages::COPY_DST
+                | TextureUsages::RENDER_ATTACHMENT,
+            view_formats: &[],
+        },
+        ..Default::default()
+    };
+    image.resize(size);
+    let image = images.add(image);
+
+    commands.spawn((
+        Camera3dBundle {
+            camera: Camera {
+                // Rendered before the explorer camera
+                order: -1,
+                target: RenderTarget::Image(image.clone()),
+                clear_color: ClearColorConfig::Custom(Color::BLACK),
+                ..Default::default()
+            },
+            projection: OrthographicProjection {
+                scaling_mode: ScalingMode::Fixed {
+                    width: MINIMAP_SPAN,
+                    height: MINIMAP_SPAN,
+                },
+                far: MINIMAP_HEIGHT * 2.0,
+                ..Default::default()
+            }
+            .into(),
+            // North, -Z, is up on the minimap
+            transform: Transform::from_xyz(0.0, MINIMAP_HEIGHT, 0.0)
+                .looking_at(Vec3::ZERO, Vec3::NEG_Z),
+            ..Default::default()
+        },
+        RenderLayers::layer(0).with(MINIMAP_LAYER),
+        MinimapCamera,
+    ));
+
+    // Home stays findable on the minimap even when it is empty
+    let home_marker = materials.add(StandardMaterial {
+        emissive: HOME_MARKER_COLOR,
+        alpha_mode: AlphaMode::Add,
+        ..Default::default()
+    });
+    commands.spawn((
+        PbrBundle {
+            mesh: stuff.pubkey_mesh.clone_weak(),
+            material: home_marker,
+            transform: Transform::from_scale(Vec3::splat(HOME_MARKER_SIZE)),
+            ..Default::default()
+        },
+        CyberspacePosition(nostr_signer.get_home_coordinates()),
+        RenderLayers::layer(MINIMAP_LAYER),
+        HomeMarker,
+    ));
+
+    let minimap_ui = NodeBundle {
+        style: Style {
+            position_type: PositionType::Absolute,
+            top: Val::Px(0.0),
+            left: Val::Percent(42.0),
+            margin: MARGIN_UI,
+            padding: PADDING_UI,
+            row_gap: FLEX_GAP,
+            flex_direction: FlexDirection::Column,
+            border: BORDER_WIDTH,
+            ..Default::default()
+        },
+        border_color: BorderColor(LIGHT_GRAY),
+        ..Default::default()
+    };
+    let minimap_image = ImageBundle {
+        style: Style {
+            width: MINIMAP_UI_SIZE,
+            height: MINIMAP_UI_SIZE,
+            ..Default::default()
+        },
+        image: UiImage::new(image),
+        ..Default::default()
+    };
+    commands.spawn(minimap_ui).with_children(|minimap_ui| {
+        minimap_ui.spawn((
+            minimap_image,
+            MinimapImage,
+            Interaction::default(),
+            RelativeCursorPosition::default(),
+        ));
+        minimap_ui.spawn((
+            text_bundle_builder(String::new(), NORMAL_FONT),
+            DestinationText,
+        ));
+        minimap_ui.spawn((text_bundle_builder(String::new(), NORMAL_FONT), RadarText));
+    });
+}
+
+// The key may have changed in the menu since the marker was placed
+fn move_home_marker(
+    nostr_signer: Res<UserNostrKeys>,
+    mut marker_query: Query<&mut CyberspacePosition, With<HomeMarker>>,
+) {
+    for mut position in marker_query.iter_mut() {
+        **position = nostr_signer.get_home_coordinates();
+    }
+}
+
+fn follow_indicator(
+    block_indicator: Query<&Transform, (With<BlockIndicator>, Without<MinimapCamera>)>,
+    mut camera_query: Query<&mut Transform, With<MinimapCamera>>,
+) {
+    let Ok(indicator_transform) = block_indicator.get_single() else {
+        return;
+    };
+    for mut camera_transform in camera_query.iter_mut() {
+        camera_transform.translation = indicator_transform.translation + Vec3::Y * MINIMAP_HEIGHT;
+    }
+}
+
+// Maps a click on the minimap, in 0..1 from the top left, to the world around the indicator
+fn minimap_to_world(position: Vec2, indicator: Vec3) -> Vec3 {
+    let offset = (position - Vec2::splat(0.5)) * MINIMAP_SPAN;
+    Vec3::new(indicator.x + offset.x, indicator.y, indicator.z + offset.y).round()
+}
+
+fn pick_teleport_destination(
+    block_indicator: Query<&Transform, With<BlockIndicator>>,
+    minimap_query: Query<
+        (&Interaction, &RelativeCursorPosition),
+        (Changed<Interaction>, With<MinimapImage>),
+    >,
+    origin: Res<FloatingOrigin>,
+    mut teleport_destination: ResMut<TeleportDestination>,
+) {
+    let Ok(indicator_transform) = block_indicator.get_single() else {
+        return;
+    };
+    for (interaction, cursor) in minimap_query.iter() {
+        if *interaction != Interaction::Pressed {
+            continue;
+        }
+        if let Some(position) = cursor.normalized {
+            let destination = minimap_to_world(position, indicator_transform.translation);
+            **teleport_destination = Some(origin.coordinates(destination));
+        }
+    }
+}
+
+// Enter flies to the destination like going home or to an avatar
+fn teleport_to_destination(
+    mut commands: Commands,
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    key_bindings: Res<KeyBindings>,
+    mut teleport_destination: ResMut<TeleportDestination>,
+    origin: Res<FloatingOrigin>,
+    block_indicator: Query<(Entity, &Transform), (With<BlockIndicator>, Without<Teleporting>)>,
+) {
+    let Some(destination) = **teleport_destination else {
+        return;
+    };
+    if !keyboard_input.just_pressed(key_bindings.key(Action::TeleportToDestination)) {
+        return;
+    }
+    let Ok((indicator, block_transform)) = block_indicator.get_single() else {
+        return;
+    };
+    commands.entity(indicator).insert(Teleporting::new(
+        origin.coordinates(block_transform.translation),
+        destination,
+        "Teleporting",
+    ));
+    **teleport_destination = None;
+}
+
+fn update_destination_text(
+    teleport_destination: Res<TeleportDestination>,
+    mut text_query: Query<&mut Text, With<DestinationText>>,
+) {
+    if !teleport_destination.is_changed() {
+        return;
+    }
+    let destination_text = match **teleport_destination {
+        Some(destination) => format!(
+            "Destination X: {} Z: {}\nPress Enter to teleport",
+            destination.x, destination.z
+        ),
+        None => "Click to pick a destination".to_string(),
+    };
+    for mut text in text_query.iter_mut() {
+        text.sections[0].value = destination_text.clone();
+    }
+}
+
+fn radar_text(blocks: usize, nearest_steps: Option<i128>) -> String {
+    match nearest_steps {
+        Some(steps) => format!(
+            "Radar: {} blocks within {}, nearest {} steps away",
+            blocks, RADAR_RANGE, steps
+        ),
+        None => format!("Radar: no blocks within {}", RADAR_RANGE),
+    }
+}
+
+// Counts every known block around the indicator, even in sectors too far to be loaded.
+// The nearest is told in steps since the indicator moves along one axis at a time
+fn update_radar_text(
+    block_indicator: Query<Ref<Transform>, With<BlockIndicator>>,
+    origin: Res<FloatingOrigin>,
+    world_chunks: Res<WorldChunks>,
+    mut text_query: Query<&mut Text, With<RadarText>>,
+) {
+    let Ok(indicator_transform) = block_indicator.get_single() else {
+        return;
+    };
+    if !indicator_transform.is_changed() && !world_chunks.is_changed() {
+        return;
+    }
+    let indicator = origin.coordinates(indicator_transform.translation);
+    let mut blocks = 0;
+    let mut nearest_steps = None;
+    for (coordinates, _) in world_chunks.blocks_within(indicator, RADAR_RANGE) {
+        blocks += 1;
+        let steps = coordinates.manhattan_distance(indicator);
+        nearest_steps = Some(nearest_steps.map_or(steps, |nearest: i128| nearest.min(steps)));
+    }
+    for mut text in text_query.iter_mut() {
+        text.sections[0].value = radar_text(blocks, nearest_steps);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn minimap_clicks_map_around_the_indicator() {
+        let indicator = Vec3::new(10.0, 5.0, -20.0);
+        assert_eq!(minimap_to_world(Vec2::splat(0.5), indicator), indicator);
+        // Top left is north west
+        assert_eq!(
+            minimap_to_world(Vec2::ZERO, indicator),
+            Vec3::new(10.0 - MINIMAP_SPAN / 2.0, 5.0, -20.0 - MINIMAP_SPAN / 2.0)
+        );
+    }
+}
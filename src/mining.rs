@@ -1,18 +1,36 @@
-use std::sync::Arc;
+use std::{
+    sync::{
+        atomic::{AtomicU64, AtomicU8, AtomicUsize, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
 
 use bevy::{prelude::*, utils::HashMap};
 
-use rand::Rng;
-
+#[cfg(not(target_arch = "wasm32"))]
+use crate::supervisor::{RestartPolicy, TaskSupervisor};
+#[cfg(target_arch = "wasm32")]
+use crate::web_mining::{web_mining_plugin, WebMiners};
 use crate::{
     cameras::BlockIndicator,
-    cyberspace::encode_coordinates,
-    nostr::POWBlockDetails,
-    resources::MeshesAndMaterials,
+    channels::{send_when_ready, MINED_NOTES_CAPACITY},
+    chunks::WorldChunks,
+    console::{ConsoleCommand, ConsoleOutput, RegisterConsoleCommand},
+    cyberspace::{extract_coordinates, CyberspaceCoordinates, Plane},
+    keybindings::{Action, KeyBindings},
+    logging::MINING_LOG,
+    origin::{CyberspacePosition, FloatingOrigin},
+    picking::PICK_MODIFIERS,
+    protocol::{sector_cell_tags, DerezzDetails, POWBlockDetails, DEREZZ_KIND, POW_BLOCK_KIND},
+    resources::{block_tier, tier_material, CoordinatesMap, MeshesAndMaterials, TOP_MATERIAL_TIER},
+    settings::{available_threads, Settings, MAX_MINING_INTENSITY},
+    ui_camera::PowEvent,
     UserNostrKeys,
 };
+#[cfg(not(target_arch = "wasm32"))]
 use bevy_tokio_tasks::TokioTasksRuntime;
-use crossbeam_channel::{unbounded, Receiver, Sender};
+use crossbeam_channel::{bounded, Receiver, Sender};
 use cryptoxide::digest::Digest;
 use cryptoxide::sha2::Sha256;
 use nostro2::{
@@ -20,16 +38,72 @@ use nostro2::{
     userkeys::UserKeys,
 };
 use serde_json::json;
-use tokio::task::JoinHandle;
 use tokio_util::sync::CancellationToken;
 
+pub const MINER_MAX_RESTARTS: u32 = 3;
+// In-progress blocks are drawn slightly smaller so they don't z-fight with published ones
+const MINING_BLOCK_SCALE: f32 = 0.9;
+// Blocks queued for removal are wrapped in a slightly larger glow
+const DEREZZ_MARKER_SCALE: f32 = 1.1;
+// Keeps a stray corner from queueing millions of blocks
+const MAX_AREA_FILL_BLOCKS: usize = 4096;
+// Miners count hashes locally and add them to the shared counter in batches
+const HASH_COUNTER_BATCH: u64 = 1024;
+const HASHRATE_SAMPLE_INTERVAL: Duration = Duration::from_secs(1);
+// Weight of the newest sample in the smoothed hashrate
+const HASHRATE_SMOOTHING: f64 = 0.3;
+
 pub fn mining_plugin(app: &mut App) {
+    // This channel is used to send the mined blocks to the websocket thread
+    // for broadcasting to the relay network
+    let (pow_notes_writer, pow_notes_reader) = bounded::<SignedNote>(MINED_NOTES_CAPACITY);
+
     app.init_state::<MiningState>()
-        .init_resource::<MiningChannel>()
         .init_resource::<UnminedBlockMap>()
-        .init_resource::<POWNotes>()
-        .add_systems(Update, (add_unmined_blocks, mining_trigger))
-        .add_systems(OnEnter(MiningState::Mining), mining_system);
+        .init_resource::<MiningQueue>()
+        .init_resource::<HashCounter>()
+        .init_resource::<Hashrate>()
+        .init_resource::<MiningIntensity>()
+        .init_resource::<DefensePolicy>()
+        .init_resource::<AreaSelection>()
+        .init_resource::<ChosenMaterial>()
+        .add_event::<MiningProgress>()
+        .add_event::<BlockOutbid>()
+        .add_event::<DerezzEvent>()
+        .insert_resource(POWNotes(pow_notes_reader))
+        .insert_resource(POWNotesWriter(Arc::new(pow_notes_writer)))
+        .add_systems(
+            Update,
+            (
+                add_unmined_blocks,
+                area_fill,
+                mining_trigger,
+                material_controls,
+                mining_queue_controls,
+                track_mining_progress,
+                harden_mining_blocks,
+                defense_controls,
+                defend_outbid_blocks,
+                derezz_controls,
+                finish_outbidding_blocks,
+                measure_hashrate,
+                apply_mining_intensity,
+                apply_mining_threads,
+                apply_defense_limits,
+                schedule_miners.run_if(in_state(MiningState::Mining)),
+            ),
+        )
+        .add_systems(Update, measure_worker_hashrates.after(measure_hashrate))
+        .register_console_command(
+            "mine",
+            "/mine radius <blocks>",
+            "Places blocks around the indicator and mines them",
+        )
+        .add_systems(Update, mine_command)
+        .add_systems(OnExit(MiningState::Mining), stop_all_miners);
+
+    #[cfg(target_arch = "wasm32")]
+    app.add_plugins(web_mining_plugin);
 }
 
 #[derive(Clone, Copy, Default, Eq, PartialEq, Debug, Hash, States)]
@@ -39,162 +113,1078 @@ pub enum MiningState {
     Mining,
 }
 
-struct MiningEvent;
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub enum QueuedBlockStatus {
+    Queued,
+    Mining,
+    Paused,
+}
 
-#[derive(Resource, Debug)]
-struct MiningChannel(pub Sender<MiningEvent>);
+// What a mined note does at its coordinate
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub enum MiningAction {
+    Place {
+        material: Option<usize>,
+        label: Option<String>,
+    },
+    Derezz,
+}
 
-impl Default for MiningChannel {
+impl MiningAction {
+    // The unsigned note committing to `target` bits, still without its nonce tag
+    pub fn note(&self, pubkey: String, coordinates: &str, target: usize) -> Note {
+        let mut note = match self {
+            MiningAction::Place { material, label } => {
+                let block_details = POWBlockDetails {
+                    pow_amount: target,
+                    coordinates: coordinates.to_string(),
+                    miner_pubkey: pubkey.clone(),
+                    material: *material,
+                    label: label.clone(),
+                    owner: None,
+                };
+                Note::new(pubkey, POW_BLOCK_KIND, &json!(block_details).to_string())
+            }
+            MiningAction::Derezz => {
+                let derezz_details = DerezzDetails {
+                    pow_amount: target,
+                    derezz: coordinates.to_string(),
+                    miner_pubkey: pubkey.clone(),
+                };
+                Note::new(pubkey, DEREZZ_KIND, &json!(derezz_details).to_string())
+            }
+        };
+        note.tags.extend(sector_cell_tags(coordinates));
+        note
+    }
+}
+
+pub struct QueuedBlock {
+    pub coordinates: String,
+    pub action: MiningAction,
+    pub status: QueuedBlockStatus,
+    pub best_pow: usize,
+    // Defended blocks only publish notes that beat the POW that outbid us
+    pub min_pow: usize,
+    pub defending: bool,
+    pub entity: Entity,
+    pub workers: Vec<MiningWorker>,
+    token: Option<CancellationToken>,
+}
+
+// One task grinding nonces for a queued block, listed in the mining dashboard
+pub struct MiningWorker {
+    pub index: u64,
+    pub started_at: Instant,
+    pub hashes_per_second: f64,
+    hashes: Arc<AtomicU64>,
+    last_count: u64,
+    token: CancellationToken,
+}
+
+impl QueuedBlock {
+    fn new(coordinates: String, entity: Entity) -> Self {
+        QueuedBlock {
+            coordinates,
+            action: MiningAction::Place {
+                material: None,
+                label: None,
+            },
+            status: QueuedBlockStatus::Queued,
+            best_pow: 0,
+            min_pow: 0,
+            defending: false,
+            entity,
+            workers: Vec::new(),
+            token: None,
+        }
+    }
+
+    pub fn display_coordinates(&self) -> String {
+        let coordinates = extract_coordinates(&self.coordinates).unwrap_or((0, 0, 0));
+        format!(
+            "X:{}, Y: {}, Z: {}",
+            coordinates.0, coordinates.1, coordinates.2
+        )
+    }
+
+    // The difficulty the miners are currently trying to reach
+    pub fn target(&self) -> usize {
+        self.best_pow.max(self.min_pow) + 1
+    }
+
+    // Defenses and derezzes are done once they beat the block they are up against
+    fn outbid_done(&self) -> bool {
+        (self.defending || self.action == MiningAction::Derezz) && self.best_pow > self.min_pow
+    }
+
+    fn stop(&mut self, status: QueuedBlockStatus) {
+        if let Some(token) = self.token.take() {
+            token.cancel();
+        }
+        self.workers.clear();
+        self.status = status;
+    }
+}
+
+// Blocks waiting to be mined, in priority order.
+// Only the first `max_concurrent` blocks that aren't paused are mined at once.
+#[derive(Resource)]
+pub struct MiningQueue {
+    pub blocks: Vec<QueuedBlock>,
+    pub selected: usize,
+    pub max_concurrent: usize,
+}
+
+impl Default for MiningQueue {
     fn default() -> Self {
-        let (sender, _) = unbounded();
-        MiningChannel(sender)
+        MiningQueue {
+            blocks: Vec::new(),
+            selected: 0,
+            max_concurrent: available_threads(),
+        }
+    }
+}
+
+impl MiningQueue {
+    // Returns false if the coordinate is already queued
+    pub fn enqueue(&mut self, coordinates: String, entity: Entity) -> bool {
+        if self
+            .blocks
+            .iter()
+            .any(|block| block.coordinates == coordinates)
+        {
+            return false;
+        }
+        self.blocks.push(QueuedBlock::new(coordinates, entity));
+        true
+    }
+
+    // Stops and takes a block out of the queue, its entity is left to the caller
+    pub fn cancel(&mut self, coordinates: &str) -> Option<QueuedBlock> {
+        let index = self
+            .blocks
+            .iter()
+            .position(|block| block.coordinates == coordinates)?;
+        let mut block = self.blocks.remove(index);
+        block.stop(QueuedBlockStatus::Queued);
+        self.selected = self.selected.min(self.blocks.len().saturating_sub(1));
+        Some(block)
+    }
+
+    pub fn active_defenses(&self) -> usize {
+        self.blocks.iter().filter(|block| block.defending).count()
+    }
+
+    // Stops a single worker, the block is paused once none are left so it isn't restarted
+    pub fn cancel_worker(&mut self, coordinates: &str, index: u64) {
+        let Some(block) = self
+            .blocks
+            .iter_mut()
+            .find(|block| block.coordinates == coordinates)
+        else {
+            return;
+        };
+        block.workers.retain(|worker| {
+            if worker.index != index {
+                return true;
+            }
+            worker.token.cancel();
+            false
+        });
+        if block.workers.is_empty() && block.status == QueuedBlockStatus::Mining {
+            block.stop(QueuedBlockStatus::Paused);
+        }
+    }
+
+    pub fn active_miners(&self) -> usize {
+        self.blocks
+            .iter()
+            .filter(|block| block.status == QueuedBlockStatus::Mining)
+            .count()
+    }
+}
+
+// Material tier newly queued blocks ask for, `None` leaves it to the POW
+#[derive(Resource, Default, Deref, DerefMut)]
+pub struct ChosenMaterial(pub Option<usize>);
+
+// C cycles through the materials, then back to POW based tiers
+fn material_controls(
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    key_bindings: Res<KeyBindings>,
+    mut chosen_material: ResMut<ChosenMaterial>,
+) {
+    if keyboard_input.just_pressed(key_bindings.key(Action::CycleMaterial)) {
+        **chosen_material = next_material(**chosen_material);
+    }
+}
+
+// Tier 0 looks like tier 1, so the cycle starts at 1
+fn next_material(chosen: Option<usize>) -> Option<usize> {
+    match chosen {
+        None => Some(1),
+        Some(tier) if tier >= TOP_MATERIAL_TIER => None,
+        Some(tier) => Some(tier + 1),
     }
 }
 
 fn mining_trigger(
+    mut commands: Commands,
     keyboard_input: Res<ButtonInput<KeyCode>>,
-    mining_channel: ResMut<MiningChannel>,
+    key_bindings: Res<KeyBindings>,
+    chosen_material: Res<ChosenMaterial>,
+    settings: Res<Settings>,
+    mut unmined_block_map: ResMut<UnminedBlockMap>,
+    mut mining_queue: ResMut<MiningQueue>,
     mut state: ResMut<NextState<MiningState>>,
 ) {
-    if keyboard_input.just_pressed(KeyCode::KeyM) {
+    if keyboard_input.just_pressed(key_bindings.key(Action::Mine)) {
+        queue_placed_blocks(
+            &mut commands,
+            &mut unmined_block_map,
+            &mut mining_queue,
+            **chosen_material,
+            settings.block_label(),
+        );
         state.set(MiningState::Mining);
     }
-    if keyboard_input.just_pressed(KeyCode::KeyN) {
+    if keyboard_input.just_pressed(key_bindings.key(Action::StopMining)) {
         state.set(MiningState::Idle);
-        let _ = mining_channel.0.send(MiningEvent);
     }
 }
 
+// Moves the placed blocks into the mining queue, they stay in the scene while mining
+fn queue_placed_blocks(
+    commands: &mut Commands,
+    unmined_block_map: &mut UnminedBlockMap,
+    mining_queue: &mut MiningQueue,
+    material: Option<usize>,
+    label: Option<String>,
+) {
+    for (key, entity) in unmined_block_map.iter() {
+        if mining_queue.enqueue(key.clone(), *entity) {
+            if let Some(block) = mining_queue.blocks.last_mut() {
+                block.action = MiningAction::Place {
+                    material,
+                    label: label.clone(),
+                };
+            }
+            commands
+                .entity(*entity)
+                .remove::<UnminedBlock>()
+                .insert(MiningBlock);
+        } else {
+            commands.entity(*entity).despawn();
+        }
+    }
+    unmined_block_map.clear();
+}
 
-#[derive(Resource, Deref, DerefMut)]
-pub struct POWNotes(pub Receiver<SignedNote>);
+// Same as placing a cube around the indicator and pressing M
+fn mine_command(
+    mut commands: Commands,
+    stuff: Res<MeshesAndMaterials>,
+    chosen_material: Res<ChosenMaterial>,
+    settings: Res<Settings>,
+    block_indicator: Query<&Transform, With<BlockIndicator>>,
+    origin: Res<FloatingOrigin>,
+    world_chunks: Res<WorldChunks>,
+    mut console_commands: EventReader<ConsoleCommand>,
+    mut console_output: EventWriter<ConsoleOutput>,
+    mut unmined_block_map: ResMut<UnminedBlockMap>,
+    mut mining_queue: ResMut<MiningQueue>,
+    mut state: ResMut<NextState<MiningState>>,
+) {
+    for command in console_commands.read() {
+        if command.name != "mine" {
+            continue;
+        }
+        let radius = match command.args.iter().map(String::as_str).collect::<Vec<_>>()[..] {
+            ["radius", radius] => radius.parse::<u32>().ok(),
+            _ => None,
+        };
+        let (Some(radius), Ok(indicator_transform)) = (radius, block_indicator.get_single()) else {
+            console_output.send(ConsoleOutput("Usage: /mine radius <blocks>".to_string()));
+            continue;
+        };
+        // Checked before building the cube so a huge radius can't overflow it
+        let side = 2 * radius as usize + 1;
+        if side.saturating_pow(3) > MAX_AREA_FILL_BLOCKS {
+            console_output.send(ConsoleOutput(format!(
+                "Radius {} is larger than {} blocks",
+                radius, MAX_AREA_FILL_BLOCKS
+            )));
+            continue;
+        }
+        let indicator = origin.coordinates(indicator_transform.translation);
+        let offset = IVec3::splat(radius as i32);
+        let (corner, opposite) = (indicator.offset(-offset), indicator.offset(offset));
+        for (coordinates, coordinate_string) in
+            area_coordinates(corner, opposite, world_chunks.plane())
+        {
+            if unmined_block_map.contains_key(&coordinate_string) {
+                continue;
+            }
+            let block_entity = spawn_unmined_block(
+                &mut commands,
+                &stuff,
+                coordinates,
+                coordinate_string.clone(),
+            );
+            unmined_block_map.insert(coordinate_string, block_entity);
+        }
+        let queued_before = mining_queue.blocks.len();
+        queue_placed_blocks(
+            &mut commands,
+            &mut unmined_block_map,
+            &mut mining_queue,
+            **chosen_material,
+            settings.block_label(),
+        );
+        state.set(MiningState::Mining);
+        console_output.send(ConsoleOutput(format!(
+            "Queued {} blocks for mining",
+            mining_queue.blocks.len() - queued_before
+        )));
+    }
+}
+
+fn mining_queue_controls(
+    mut commands: Commands,
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    key_bindings: Res<KeyBindings>,
+    mut mining_queue: ResMut<MiningQueue>,
+) {
+    let queue_length = mining_queue.blocks.len();
+    if queue_length == 0 {
+        return;
+    }
+    let selected = mining_queue.selected.min(queue_length - 1);
 
-impl Default for POWNotes {
+    if keyboard_input.just_pressed(key_bindings.key(Action::NextQueued)) {
+        mining_queue.selected = (selected + 1) % queue_length;
+    }
+    if keyboard_input.just_pressed(key_bindings.key(Action::PreviousQueued)) {
+        mining_queue.selected = (selected + queue_length - 1) % queue_length;
+    }
+
+    // Pause or resume the selected block
+    if keyboard_input.just_pressed(key_bindings.key(Action::PauseQueued)) {
+        let block = &mut mining_queue.blocks[selected];
+        match block.status {
+            QueuedBlockStatus::Paused => block.status = QueuedBlockStatus::Queued,
+            _ => block.stop(QueuedBlockStatus::Paused),
+        }
+    }
+
+    // Cancel the selected block entirely
+    if keyboard_input.just_pressed(key_bindings.key(Action::CancelQueued)) {
+        let mut block = mining_queue.blocks.remove(selected);
+        block.stop(QueuedBlockStatus::Queued);
+        commands.entity(block.entity).despawn();
+        mining_queue.selected = selected.min(queue_length.saturating_sub(2));
+        // The block to move is gone, and `queue_length` with it
+        return;
+    }
+
+    // Move the selected block up or down the queue
+    if keyboard_input.just_pressed(key_bindings.key(Action::RaiseQueued)) && selected > 0 {
+        mining_queue.blocks.swap(selected, selected - 1);
+        mining_queue.selected = selected - 1;
+    }
+    if keyboard_input.just_pressed(key_bindings.key(Action::LowerQueued))
+        && selected + 1 < queue_length
+    {
+        mining_queue.blocks.swap(selected, selected + 1);
+        mining_queue.selected = selected + 1;
+    }
+}
+
+// Sent whenever one of our miners beats its best POW for a coordinate
+#[derive(Event)]
+pub struct MiningProgress {
+    pub coordinates: String,
+    pub best_pow: usize,
+}
+
+// Sent by the websocket middleware when someone else replaces one of our blocks
+#[derive(Event)]
+pub struct BlockOutbid {
+    pub coordinates: String,
+    pub pow_amount: usize,
+}
+
+// Whether and how hard we fight back when our blocks get outbid
+#[derive(Resource)]
+pub struct DefensePolicy {
+    pub enabled: bool,
+    // Blocks outbid at this difficulty or more are given up
+    pub max_difficulty: usize,
+    pub max_concurrent_defenses: usize,
+}
+
+impl Default for DefensePolicy {
     fn default() -> Self {
-        let (_notes_writer, notes_reader) = unbounded::<SignedNote>();
-        POWNotes(notes_reader)
+        DefensePolicy {
+            enabled: false,
+            max_difficulty: 32,
+            max_concurrent_defenses: 4,
+        }
     }
 }
 
-fn mining_system(
-    runtime: ResMut<TokioTasksRuntime>,
+impl DefensePolicy {
+    fn should_defend(&self, pow_amount: usize, active_defenses: usize) -> bool {
+        self.enabled
+            && pow_amount < self.max_difficulty
+            && active_defenses < self.max_concurrent_defenses
+    }
+}
+
+fn defense_controls(
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    key_bindings: Res<KeyBindings>,
+    mut defense_policy: ResMut<DefensePolicy>,
+) {
+    if keyboard_input.just_pressed(key_bindings.key(Action::ToggleDefense)) {
+        defense_policy.enabled = !defense_policy.enabled;
+    }
+}
+
+// Queue outbid blocks for re-mining above the POW that beat them
+fn defend_outbid_blocks(
     mut commands: Commands,
-    mut unmined_block_map: ResMut<UnminedBlockMap>,
+    stuff: Res<MeshesAndMaterials>,
+    defense_policy: Res<DefensePolicy>,
+    mut outbid_events: EventReader<BlockOutbid>,
+    mut mining_queue: ResMut<MiningQueue>,
+    mut state: ResMut<NextState<MiningState>>,
+) {
+    for outbid in outbid_events.read() {
+        if let Some(block) = mining_queue
+            .blocks
+            .iter_mut()
+            .find(|block| block.coordinates == outbid.coordinates)
+        {
+            // Already queued, restart the miners with the higher floor
+            if block.min_pow < outbid.pow_amount {
+                block.min_pow = outbid.pow_amount;
+                if block.status == QueuedBlockStatus::Mining {
+                    block.stop(QueuedBlockStatus::Queued);
+                }
+            }
+            continue;
+        }
+
+        if !defense_policy.should_defend(outbid.pow_amount, mining_queue.active_defenses()) {
+            continue;
+        }
+
+        let Ok(coordinates) = CyberspaceCoordinates::from_hex(&outbid.coordinates) else {
+            continue;
+        };
+        let entity = commands
+            .spawn((
+                PbrBundle {
+                    mesh: stuff.cube_mesh.clone_weak(),
+                    material: stuff.mud_material.clone_weak(),
+                    transform: Transform::from_scale(Vec3::splat(MINING_BLOCK_SCALE)),
+                    ..Default::default()
+                },
+                CyberspacePosition(coordinates),
+                MiningBlock,
+            ))
+            .id();
+        mining_queue.enqueue(outbid.coordinates.clone(), entity);
+        if let Some(block) = mining_queue.blocks.last_mut() {
+            block.min_pow = outbid.pow_amount;
+            block.defending = true;
+        }
+        info!(
+            target: MINING_LOG,
+            "Defending block at {} against {} bits",
+            outbid.coordinates,
+            outbid.pow_amount
+        );
+        state.set(MiningState::Mining);
+    }
+}
+
+// Sent when we publish a derezz note, like `PowEvent` for blocks
+#[derive(Event)]
+pub struct DerezzEvent(pub DerezzDetails);
+
+// X queues a derezz of our own block at the indicator, mined above the block's POW
+fn derezz_controls(
+    mut commands: Commands,
+    stuff: Res<MeshesAndMaterials>,
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    key_bindings: Res<KeyBindings>,
+    camera_query: Query<&Transform, With<BlockIndicator>>,
+    origin: Res<FloatingOrigin>,
+    coordinates_map: Res<CoordinatesMap>,
+    world_chunks: Res<WorldChunks>,
     user_keys: Res<UserNostrKeys>,
+    mut mining_queue: ResMut<MiningQueue>,
+    mut state: ResMut<NextState<MiningState>>,
 ) {
-    // This channel is used to send the mined blocks to the websocket thread
-    // for broadcasting to the relay network
-    let (pow_notes_writer, pow_notes_reader) = unbounded::<SignedNote>();
-    commands.insert_resource(POWNotes(pow_notes_reader));
+    if !keyboard_input.just_pressed(key_bindings.key(Action::Derezz)) {
+        return;
+    }
+    let coordinates = origin.coordinates(camera_query.single().translation);
+    let coordinate_string = coordinates.to_hex(world_chunks.plane());
+    let Some((_, block_details)) = coordinates_map.get(&coordinate_string) else {
+        return;
+    };
+    if block_details.owner() != user_keys.get_public_key() {
+        info!(target: MINING_LOG, "Only your own blocks can be targeted for derezz");
+        return;
+    }
 
-    // This channel is used to send a cancellation signal to the mining threads
-    let (sender, receiver) = unbounded::<MiningEvent>();
-    commands.insert_resource(MiningChannel(sender));
+    let entity = commands
+        .spawn((
+            PbrBundle {
+                mesh: stuff.cube_mesh.clone_weak(),
+                material: stuff.clear_material.clone_weak(),
+                transform: Transform::from_scale(Vec3::splat(DEREZZ_MARKER_SCALE)),
+                ..Default::default()
+            },
+            CyberspacePosition(coordinates),
+            DerezzMarker,
+        ))
+        .id();
+    if !mining_queue.enqueue(coordinate_string, entity) {
+        commands.entity(entity).despawn();
+        return;
+    }
+    if let Some(block) = mining_queue.blocks.last_mut() {
+        block.action = MiningAction::Derezz;
+        block.min_pow = block_details.pow_amount;
+    }
+    state.set(MiningState::Mining);
+}
 
-    // Build a list of blocks to mine
-    let mut blocks = Vec::new();
-    for (key, entity) in unmined_block_map.iter() {
-        blocks.push(key.clone());
-        // Remove the block from the scene so it doesn't get mined again
-        commands.entity(*entity).despawn();
+// Marks a block queued for derezz
+#[derive(Component)]
+pub struct DerezzMarker;
+
+// Defenses and derezzes leave the queue once we publish a note beating the block they target
+fn finish_outbidding_blocks(mut commands: Commands, mut mining_queue: ResMut<MiningQueue>) {
+    if !mining_queue.blocks.iter().any(QueuedBlock::outbid_done) {
+        return;
     }
-    // Clear the hashmap
-    unmined_block_map.clear();
+    mining_queue.blocks.retain_mut(|block| {
+        if block.outbid_done() {
+            block.stop(QueuedBlockStatus::Queued);
+            commands.entity(block.entity).despawn();
+            return false;
+        }
+        true
+    });
+    let queue_length = mining_queue.blocks.len();
+    mining_queue.selected = mining_queue.selected.min(queue_length.saturating_sub(1));
+}
 
-    let user_keys = user_keys.get_keypair();
-    runtime.spawn_background_task(|_ctx| async move {
-        let writer_arc = Arc::new(pow_notes_writer);
-        let token = CancellationToken::new();
-        let mut thread_array: Vec<JoinHandle<()>> = Vec::new();
+// Marks the in-progress block of a queued coordinate
+#[derive(Component)]
+pub struct MiningBlock;
+
+fn track_mining_progress(
+    mut pow_events: EventReader<PowEvent>,
+    mut derezz_events: EventReader<DerezzEvent>,
+    mut mining_queue: ResMut<MiningQueue>,
+    mut mining_progress: EventWriter<MiningProgress>,
+) {
+    let published = pow_events
+        .read()
+        .map(|event| (&event.0.coordinates, event.0.pow_amount))
+        .chain(
+            derezz_events
+                .read()
+                .map(|event| (&event.0.derezz, event.0.pow_amount)),
+        );
+    for (coordinates, pow_amount) in published {
+        if let Some(block) = mining_queue
+            .blocks
+            .iter_mut()
+            .find(|block| &block.coordinates == coordinates)
+        {
+            if pow_amount > block.best_pow {
+                block.best_pow = pow_amount;
+                mining_progress.send(MiningProgress {
+                    coordinates: block.coordinates.clone(),
+                    best_pow: block.best_pow,
+                });
+            }
+        }
+    }
+}
 
-        // We spawn a mining thread for each block
-        for block in blocks {
-            let writer_arc_clone = writer_arc.clone();
-            let child_token = token.clone();
-            let key_ref = user_keys.clone();
+// Upgrade the material of in-progress blocks as their POW improves
+fn harden_mining_blocks(
+    stuff: Res<MeshesAndMaterials>,
+    mining_queue: Res<MiningQueue>,
+    mut mining_progress: EventReader<MiningProgress>,
+    mut block_query: Query<(&mut Handle<StandardMaterial>, &mut Transform), With<MiningBlock>>,
+) {
+    for progress in mining_progress.read() {
+        let Some(block) = mining_queue
+            .blocks
+            .iter()
+            .find(|block| block.coordinates == progress.coordinates)
+        else {
+            continue;
+        };
+        if let Ok((mut material, mut transform)) = block_query.get_mut(block.entity) {
+            let chosen = match block.action {
+                MiningAction::Place {
+                    material: chosen, ..
+                } => chosen,
+                MiningAction::Derezz => None,
+            };
+            *material = tier_material(&stuff, block_tier(progress.best_pow, chosen));
+            transform.scale = Vec3::splat(MINING_BLOCK_SCALE);
+        }
+    }
+}
+
+// Total hashes computed by every miner since the game started
+#[derive(Resource, Clone, Default, Deref)]
+pub struct HashCounter(pub Arc<AtomicU64>);
 
-            let mining_thread = tokio::spawn(async move {
-                mine_pow_event(block, writer_arc_clone, child_token, key_ref).await;
-            });
-            thread_array.push(mining_thread);
+#[derive(Resource)]
+pub struct Hashrate {
+    pub hashes_per_second: f64,
+    last_count: u64,
+    last_sample: Instant,
+    // How long the latest sample took, worker rates are measured over the same span
+    sampled_over: Duration,
+}
+
+impl Default for Hashrate {
+    fn default() -> Self {
+        Hashrate {
+            hashes_per_second: 0.0,
+            last_count: 0,
+            last_sample: Instant::now(),
+            sampled_over: Duration::ZERO,
         }
+    }
+}
 
-        // We spawn a thread to listen for the cancellation signal
-        let _ = tokio::spawn(async move {
-            while let Ok(_) = receiver.recv() {
-                token.cancel();
+// Sampled together with the total so the dashboard rows add up to it
+pub fn measure_worker_hashrates(hashrate: Res<Hashrate>, mut mining_queue: ResMut<MiningQueue>) {
+    if !hashrate.is_changed() {
+        return;
+    }
+    for worker in mining_queue
+        .blocks
+        .iter_mut()
+        .flat_map(|block| block.workers.iter_mut())
+    {
+        let count = worker.hashes.load(Ordering::Relaxed);
+        let elapsed = worker.started_at.elapsed().min(hashrate.sampled_over);
+        if elapsed.is_zero() {
+            continue;
+        }
+        let sample = (count - worker.last_count) as f64 / elapsed.as_secs_f64();
+        worker.hashes_per_second =
+            HASHRATE_SMOOTHING * sample + (1.0 - HASHRATE_SMOOTHING) * worker.hashes_per_second;
+        worker.last_count = count;
+    }
+}
+
+pub fn measure_hashrate(hash_counter: Res<HashCounter>, mut hashrate: ResMut<Hashrate>) {
+    let elapsed = hashrate.last_sample.elapsed();
+    if elapsed < HASHRATE_SAMPLE_INTERVAL {
+        return;
+    }
+    let count = hash_counter.load(Ordering::Relaxed);
+    let sample = (count - hashrate.last_count) as f64 / elapsed.as_secs_f64();
+    hashrate.hashes_per_second =
+        HASHRATE_SMOOTHING * sample + (1.0 - HASHRATE_SMOOTHING) * hashrate.hashes_per_second;
+    hashrate.last_count = count;
+    hashrate.last_sample = Instant::now();
+    hashrate.sampled_over = elapsed;
+}
+
+// Percentage of the time miners spend hashing, shared with the miner tasks
+#[derive(Resource, Clone, Deref)]
+pub struct MiningIntensity(pub Arc<AtomicU8>);
+
+impl Default for MiningIntensity {
+    fn default() -> Self {
+        MiningIntensity(Arc::new(AtomicU8::new(MAX_MINING_INTENSITY)))
+    }
+}
+
+pub fn apply_mining_intensity(settings: Res<Settings>, mining_intensity: Res<MiningIntensity>) {
+    if settings.is_changed() {
+        mining_intensity.store(settings.mining_intensity, Ordering::Relaxed);
+    }
+}
+
+// Blocks already mining keep their workers, the new limit applies the next time the queue changes
+pub fn apply_mining_threads(settings: Res<Settings>, mut mining_queue: ResMut<MiningQueue>) {
+    if settings.is_changed() && mining_queue.max_concurrent != settings.mining_threads {
+        mining_queue.max_concurrent = settings.mining_threads;
+    }
+}
+
+// Whether defense is on stays a toggle, only its limits come from the config
+fn apply_defense_limits(settings: Res<Settings>, mut defense_policy: ResMut<DefensePolicy>) {
+    if settings.is_changed() {
+        defense_policy.max_difficulty = settings.defense_max_difficulty;
+        defense_policy.max_concurrent_defenses = settings.max_defenses;
+    }
+}
+
+// How long to sleep after `busy` of hashing so miners only hash `intensity` percent of the time
+pub fn throttle_delay(busy: Duration, intensity: u8) -> Duration {
+    let intensity = intensity.clamp(1, MAX_MINING_INTENSITY) as u32;
+    busy * (MAX_MINING_INTENSITY as u32 - intensity) / intensity
+}
+
+// Each hash has a 1 in 2^target chance of meeting the target difficulty
+pub fn expected_hashes(target: usize) -> f64 {
+    2_f64.powi(target as i32)
+}
+
+pub fn mining_eta(target: usize, hashes_per_second: f64) -> Option<Duration> {
+    if hashes_per_second <= 0.0 {
+        return None;
+    }
+    Duration::try_from_secs_f64(expected_hashes(target) / hashes_per_second).ok()
+}
+
+// Chance of finding at least one hash meeting the target within `time`
+pub fn mining_probability(target: usize, hashes_per_second: f64, time: Duration) -> f64 {
+    let hashes = hashes_per_second * time.as_secs_f64();
+    1.0 - (-hashes / expected_hashes(target)).exp()
+}
+
+#[derive(Resource, Deref, DerefMut)]
+pub struct POWNotes(pub Receiver<SignedNote>);
+
+#[derive(Resource, Deref)]
+pub struct POWNotesWriter(pub Arc<Sender<SignedNote>>);
+
+pub fn schedule_miners(
+    #[cfg(not(target_arch = "wasm32"))] runtime: ResMut<TokioTasksRuntime>,
+    #[cfg(target_arch = "wasm32")] mut web_miners: NonSendMut<WebMiners>,
+    mut mining_queue: ResMut<MiningQueue>,
+    user_keys: Res<UserNostrKeys>,
+    #[cfg(not(target_arch = "wasm32"))] supervisor: Res<TaskSupervisor>,
+    #[cfg(not(target_arch = "wasm32"))] pow_notes_writer: Res<POWNotesWriter>,
+    #[cfg(not(target_arch = "wasm32"))] hash_counter: Res<HashCounter>,
+    #[cfg(not(target_arch = "wasm32"))] mining_intensity: Res<MiningIntensity>,
+) {
+    // Work out which blocks should be mining given their priority
+    let mut free_slots = mining_queue.max_concurrent;
+    let should_mine: Vec<bool> = mining_queue
+        .blocks
+        .iter()
+        .map(|block| {
+            if block.status == QueuedBlockStatus::Paused || free_slots == 0 {
+                return false;
             }
+            free_slots -= 1;
+            true
         })
-        .await;
+        .collect();
+
+    let needs_update = mining_queue
+        .blocks
+        .iter()
+        .zip(should_mine.iter())
+        .any(|(block, should_mine)| (block.status == QueuedBlockStatus::Mining) != *should_mine);
+    if !needs_update {
+        return;
+    }
+
+    // Spare threads are shared out between the blocks so each one mines on several workers
+    let mining_blocks = should_mine
+        .iter()
+        .filter(|should_mine| **should_mine)
+        .count();
+    let workers_per_block = (mining_queue.max_concurrent / mining_blocks.max(1)).max(1) as u64;
 
-        // Wait for all the mining threads to finish
-        for thread in thread_array {
-            thread.await.unwrap();
+    for (block, should_mine) in mining_queue.blocks.iter_mut().zip(should_mine) {
+        match (block.status == QueuedBlockStatus::Mining, should_mine) {
+            (false, true) => {
+                let token = CancellationToken::new();
+                block.token = Some(token.clone());
+                block.status = QueuedBlockStatus::Mining;
+
+                // The workers share their best POW so only real improvements get published
+                let best_pow = Arc::new(AtomicUsize::new(block.target() - 1));
+                for worker in 0..workers_per_block {
+                    // Cancelling the block cancels every worker, a worker can also be stopped alone
+                    let token = token.child_token();
+                    let worker_hashes = Arc::new(AtomicU64::new(0));
+                    block.workers.push(MiningWorker {
+                        index: worker,
+                        started_at: Instant::now(),
+                        hashes_per_second: 0.0,
+                        hashes: worker_hashes.clone(),
+                        last_count: 0,
+                        token: token.clone(),
+                    });
+                    let best_pow = best_pow.clone();
+                    let nonces = NonceSplit {
+                        start: worker,
+                        stride: workers_per_block,
+                    };
+                    let key_ref = user_keys.get_keypair();
+                    // Wasm has no threads for the tokio runtime, its workers are Web Workers
+                    #[cfg(target_arch = "wasm32")]
+                    web_miners.spawn(block, best_pow, nonces, token, worker_hashes, &key_ref);
+                    #[cfg(not(target_arch = "wasm32"))]
+                    {
+                        let coordinates = block.coordinates.clone();
+                        let action = block.action.clone();
+                        let writer_arc = pow_notes_writer.0.clone();
+                        let supervisor = supervisor.clone();
+                        let hash_counter = hash_counter.0.clone();
+                        let intensity = mining_intensity.0.clone();
+                        runtime.spawn_background_task(move |_ctx| async move {
+                            supervisor.supervise(
+                                format!("miner {} #{}", coordinates, worker),
+                                RestartPolicy::OnFailure {
+                                    max_restarts: MINER_MAX_RESTARTS,
+                                },
+                                move || {
+                                    let coordinates = coordinates.clone();
+                                    let best_pow = best_pow.clone();
+                                    let writer_arc_clone = writer_arc.clone();
+                                    let child_token = token.clone();
+                                    let key_ref = key_ref.clone();
+                                    let hash_counter = hash_counter.clone();
+                                    let worker_hashes = worker_hashes.clone();
+                                    let intensity = intensity.clone();
+                                    let action = action.clone();
+                                    async move {
+                                        mine_block(
+                                            coordinates,
+                                            action,
+                                            best_pow,
+                                            nonces,
+                                            writer_arc_clone,
+                                            child_token,
+                                            key_ref,
+                                            hash_counter,
+                                            worker_hashes,
+                                            intensity,
+                                        )
+                                        .await;
+                                        Ok(())
+                                    }
+                                },
+                            );
+                        });
+                    }
+                }
+            }
+            (true, false) => block.stop(QueuedBlockStatus::Queued),
+            _ => {}
         }
-    });
+    }
+}
+
+fn stop_all_miners(mut mining_queue: ResMut<MiningQueue>) {
+    for block in mining_queue.blocks.iter_mut() {
+        if block.status == QueuedBlockStatus::Mining {
+            block.stop(QueuedBlockStatus::Queued);
+        }
+    }
+}
+
+// Workers mining the same block take every `stride`th nonce starting at `start`,
+// so their nonce ranges never overlap
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct NonceSplit {
+    pub start: u64,
+    pub stride: u64,
+}
+
+impl NonceSplit {
+    pub fn next(&self, nonce: u64) -> u64 {
+        nonce.wrapping_add(self.stride)
+    }
+
+    // Splits this range again between `workers`, keeping every nonce inside it
+    pub fn subdivide(&self, worker: u64, workers: u64) -> NonceSplit {
+        NonceSplit {
+            start: self.start + worker * self.stride,
+            stride: self.stride * workers,
+        }
+    }
+}
+
+// Uses the GPU miner when it is compiled in and an adapter is available
+async fn mine_block(
+    coordinate: String,
+    action: MiningAction,
+    best_pow: Arc<AtomicUsize>,
+    nonces: NonceSplit,
+    writer_arc_clone: Arc<Sender<SignedNote>>,
+    cancel_token: CancellationToken,
+    key_ref: Arc<UserKeys>,
+    hash_counter: Arc<AtomicU64>,
+    worker_hashes: Arc<AtomicU64>,
+    intensity: Arc<AtomicU8>,
+) {
+    // Only the first worker drives the GPU, the rest keep grinding on the CPU
+    #[cfg(feature = "gpu-mining")]
+    if nonces.start == 0
+        && crate::gpu_mining::mine_pow_event_gpu(
+            &coordinate,
+            &action,
+            &best_pow,
+            &writer_arc_clone,
+            &cancel_token,
+            &key_ref,
+            &hash_counter,
+            &worker_hashes,
+            &intensity,
+        )
+        .await
+    {
+        return;
+    }
+
+    mine_pow_event(
+        coordinate,
+        action,
+        best_pow,
+        nonces,
+        writer_arc_clone,
+        cancel_token,
+        key_ref,
+        hash_counter,
+        worker_hashes,
+        intensity,
+    )
+    .await;
 }
 
 async fn mine_pow_event(
     coordinate: String,
+    action: MiningAction,
+    best_pow: Arc<AtomicUsize>,
+    nonces: NonceSplit,
     writer_arc_clone: Arc<Sender<SignedNote>>,
     cancel_token: CancellationToken,
     key_ref: Arc<UserKeys>,
+    hash_counter: Arc<AtomicU64>,
+    worker_hashes: Arc<AtomicU64>,
+    intensity: Arc<AtomicU8>,
 ) {
-    let mut pow: usize = 0;
-    info!("Starting POW Miner");
-    let mut block_details = POWBlockDetails {
-        pow_amount: pow,
-        coordinates: coordinate.clone(),
-        miner_pubkey: key_ref.get_public_key(),
-    };
+    let mut nonce: u64 = nonces.start;
+    let mut hash_meter = HashMeter::new(hash_counter, intensity).with_worker_counter(worker_hashes);
+    info!(target: MINING_LOG, "Starting POW Miner");
 
     while !cancel_token.is_cancelled() {
-        let mut pow_note = Note::new(
-            key_ref.get_public_key(),
-            333,
-            &json!(block_details).to_string(),
-        );
-        let nonce = generate_nonce();
-        pow_note.tag_note("nonce", &hex::encode(nonce));
-        let json_str = pow_note.serialize_for_nostr();
-
-        // Compute the SHA256 hash of the serialized JSON string
-        let mut hasher = Sha256::new();
-        hasher.input_str(&json_str);
-        let mut result = [0u8; 32];
-        hasher.result(&mut result);
-
-        let pow_id = hex::encode(result);
-
-        let leading_zeroes_in_id = pow_id.chars().take_while(|c| c == &'0').count();
-        if leading_zeroes_in_id > pow {
-            pow = leading_zeroes_in_id;
-            block_details.pow_amount = pow;
+        // Each note commits to beating our current best, per NIP-13
+        let target = best_pow.load(Ordering::Relaxed) + 1;
+        let mut pow_note = action.note(key_ref.get_public_key(), &coordinate, target);
+        pow_note.tags.push(nonce_tag(&nonce.to_string(), target));
+        nonce = nonces.next(nonce);
+
+        let leading_zeroes_in_id = note_difficulty(&pow_note);
+        hash_meter.tick().await;
+        if leading_zeroes_in_id >= target
+            && best_pow.fetch_max(leading_zeroes_in_id, Ordering::Relaxed) < leading_zeroes_in_id
+        {
             let signed_note = key_ref.sign_nostr_event(pow_note);
-            let _sent = writer_arc_clone.send(signed_note);
+            let _sent = send_when_ready(&writer_arc_clone, signed_note).await;
+        }
+    }
+    info!(target: MINING_LOG, "Stopping POW Miner");
+}
+
+// Counts hashes into the shared counter in batches and sleeps off the throttled share of each batch
+pub struct HashMeter {
+    hash_counter: Arc<AtomicU64>,
+    // Hashes of this worker alone, for the mining dashboard
+    worker_counter: Option<Arc<AtomicU64>>,
+    intensity: Arc<AtomicU8>,
+    hashes: u64,
+    batch_started: Instant,
+}
+
+impl HashMeter {
+    pub fn new(hash_counter: Arc<AtomicU64>, intensity: Arc<AtomicU8>) -> Self {
+        HashMeter {
+            hash_counter,
+            worker_counter: None,
+            intensity,
+            hashes: 0,
+            batch_started: Instant::now(),
+        }
+    }
+
+    pub fn with_worker_counter(mut self, worker_counter: Arc<AtomicU64>) -> Self {
+        self.worker_counter = Some(worker_counter);
+        self
+    }
+
+    pub async fn tick(&mut self) {
+        self.hashes += 1;
+        if self.hashes < HASH_COUNTER_BATCH {
+            return;
+        }
+        self.hash_counter.fetch_add(self.hashes, Ordering::Relaxed);
+        if let Some(worker_counter) = &self.worker_counter {
+            worker_counter.fetch_add(self.hashes, Ordering::Relaxed);
         }
+        self.hashes = 0;
+        let delay = throttle_delay(
+            self.batch_started.elapsed(),
+            self.intensity.load(Ordering::Relaxed),
+        );
+        if !delay.is_zero() {
+            tokio::time::sleep(delay).await;
+        }
+        self.batch_started = Instant::now();
     }
-    info!("Stopping POW Miner");
 }
 
-fn generate_nonce() -> [u8; 16] {
-    // Define the symbols allowed in the nonce
-    let symbols: [u8; 16] = [
-        b'!', b'"', b'#', b'$', b'%', b'&', b'\'', b'(', b')', b'*', b'+', b',', b'-', b'.', b'/',
-        b'0',
-    ];
+// Leading zero bits of the id the note would get once signed
+pub fn note_difficulty(note: &Note) -> usize {
+    let mut hasher = Sha256::new();
+    hasher.input_str(&note.serialize_for_nostr());
+    let mut result = [0u8; 32];
+    hasher.result(&mut result);
+    count_leading_zero_bits(&result)
+}
 
-    let mut rng = rand::thread_rng();
-    let mut nonce: [u8; 16] = [0; 16];
+// NIP-13 nonce tag: ["nonce", "<nonce>", "<target difficulty>"]
+pub fn nonce_tag(nonce: &str, target: usize) -> Vec<String> {
+    vec!["nonce".to_string(), nonce.to_string(), target.to_string()]
+}
 
-    for i in 0..16 {
-        // Generate a random index to select a symbol from the array
-        let index = rng.gen_range(0..16);
-        // Assign the selected symbol to the nonce buffer
-        nonce[i] = symbols[index];
+// NIP-13 difficulty is the number of leading zero bits in the event id
+pub fn count_leading_zero_bits(id: &[u8]) -> usize {
+    let mut count = 0;
+    for byte in id {
+        if *byte == 0 {
+            count += 8;
+        } else {
+            count += byte.leading_zeros() as usize;
+            break;
+        }
     }
+    count
+}
 
-    nonce
+// Blocks published before we counted bits carry a hex character count in their content,
+// so the difficulty is always recomputed from the event id itself
+pub fn note_id_difficulty(id: &str) -> usize {
+    hex::decode(id)
+        .map(|id_bytes| count_leading_zero_bits(&id_bytes))
+        .unwrap_or(0)
 }
 
 #[derive(Resource, Debug, Deref, DerefMut)]
@@ -213,50 +1203,367 @@ fn add_unmined_blocks(
     mut commands: Commands,
     stuff: Res<MeshesAndMaterials>,
     mouse_input: Res<ButtonInput<MouseButton>>,
+    keyboard_input: Res<ButtonInput<KeyCode>>,
     camera_query: Query<&Transform, With<BlockIndicator>>,
+    origin: Res<FloatingOrigin>,
+    world_chunks: Res<WorldChunks>,
     mut unmined_block_map: ResMut<UnminedBlockMap>,
+    interaction_query: Query<&Interaction>,
 ) {
+    // Clicks on UI widgets shouldn't place blocks behind them
+    if interaction_query
+        .iter()
+        .any(|interaction| *interaction != Interaction::None)
+    {
+        return;
+    }
+    // Modified clicks go to the block under the cursor instead
+    if keyboard_input.any_pressed(PICK_MODIFIERS) {
+        return;
+    }
     let camera_transform = camera_query.single();
     if mouse_input.just_pressed(MouseButton::Left) {
-        // Calculate the coordinates of the block and encode them
-        let x = camera_transform.translation.x;
-        let y = camera_transform.translation.y;
-        let z = camera_transform.translation.z;
-        let rounded_x = x.round();
-        let rounded_y = y.round();
-        let rounded_z = z.round();
-        let x_128 = rounded_x as i128;
-        let y_128 = rounded_y as i128;
-        let z_128 = rounded_z as i128;
-        let coordinate_string = encode_coordinates(x_128, y_128, z_128);
-
-        // Check if the block already exists
-        if let Some(entity) = unmined_block_map.get(&coordinate_string) {
-            // Remove the block
-            commands.entity(*entity).despawn();
-            unmined_block_map.0.remove(&coordinate_string);
-            return;
+        toggle_unmined_block(
+            &mut commands,
+            &stuff,
+            &mut unmined_block_map,
+            origin.coordinates(camera_transform.translation),
+            world_chunks.plane(),
+        );
+    }
+}
+
+// Places an unmined block, or removes the one already placed there
+pub fn toggle_unmined_block(
+    commands: &mut Commands,
+    stuff: &MeshesAndMaterials,
+    unmined_block_map: &mut UnminedBlockMap,
+    coordinates: CyberspaceCoordinates,
+    plane: Plane,
+) {
+    let coordinate_string = coordinates.to_hex(plane);
+    if let Some(entity) = unmined_block_map.remove(&coordinate_string) {
+        commands.entity(entity).despawn();
+        return;
+    }
+    let block_entity = spawn_unmined_block(commands, stuff, coordinates, coordinate_string.clone());
+    unmined_block_map.insert(coordinate_string, block_entity);
+}
+
+fn spawn_unmined_block(
+    commands: &mut Commands,
+    stuff: &MeshesAndMaterials,
+    coordinates: CyberspaceCoordinates,
+    coordinate_string: String,
+) -> Entity {
+    commands
+        .spawn((
+            PbrBundle {
+                mesh: stuff.cube_mesh.clone_weak(),
+                material: stuff.mud_material.clone_weak(),
+                ..Default::default()
+            },
+            CyberspacePosition(coordinates),
+            UnminedBlock(coordinate_string),
+        ))
+        .id()
+}
+
+// Spawns a block straight into the mining queue, false if the coordinate is already queued
+pub fn queue_block(
+    commands: &mut Commands,
+    stuff: &MeshesAndMaterials,
+    mining_queue: &mut MiningQueue,
+    coordinates: CyberspaceCoordinates,
+    plane: Plane,
+    action: MiningAction,
+) -> bool {
+    let coordinate_string = coordinates.to_hex(plane);
+    if mining_queue
+        .blocks
+        .iter()
+        .any(|block| block.coordinates == coordinate_string)
+    {
+        return false;
+    }
+    let entity = commands
+        .spawn((
+            PbrBundle {
+                mesh: stuff.cube_mesh.clone_weak(),
+                material: stuff.mud_material.clone_weak(),
+                ..Default::default()
+            },
+            CyberspacePosition(coordinates),
+            MiningBlock,
+        ))
+        .id();
+    mining_queue.enqueue(coordinate_string, entity);
+    if let Some(block) = mining_queue.blocks.last_mut() {
+        block.action = action;
+    }
+    true
+}
+
+// First corner of an area being selected, the indicator is the other one
+#[derive(Resource, Default, Deref, DerefMut)]
+pub struct AreaSelection(pub Option<CyberspaceCoordinates>);
+
+// Every coordinate inside the cuboid spanned by two corners, both included
+pub fn area_coordinates(
+    corner: CyberspaceCoordinates,
+    opposite: CyberspaceCoordinates,
+    plane: Plane,
+) -> Vec<(CyberspaceCoordinates, String)> {
+    let span = opposite.relative_to(corner).as_ivec3();
+    let min = span.min(IVec3::ZERO);
+    let max = span.max(IVec3::ZERO);
+    let mut coordinates = Vec::new();
+    for x in min.x..=max.x {
+        for y in min.y..=max.y {
+            for z in min.z..=max.z {
+                let position = corner.offset(IVec3::new(x, y, z));
+                coordinates.push((position, position.to_hex(plane)));
+            }
         }
+    }
+    coordinates
+}
 
-        // Add block at the calculated coordinates
-        let block_entity = commands
-            .spawn((
-                PbrBundle {
-                    mesh: stuff.cube_mesh.clone_weak(),
-                    material: stuff.mud_material.clone_weak(),
-                    transform: Transform::from_translation(Vec3::new(
-                        rounded_x, rounded_y, rounded_z,
-                    ))
-                    .with_rotation(Quat::IDENTITY),
-                    ..Default::default()
-                },
-                UnminedBlock(coordinate_string.clone()),
-            ))
-            .id();
+// In floats so corners across cyberspace saturate instead of overflowing
+fn area_size(corner: CyberspaceCoordinates, opposite: CyberspaceCoordinates) -> usize {
+    let size = opposite.relative_to(corner).abs() + Vec3::ONE;
+    (size.x * size.y * size.z) as usize
+}
+
+// B marks a corner, B again fills the cuboid up to the indicator with unmined blocks
+fn area_fill(
+    mut commands: Commands,
+    stuff: Res<MeshesAndMaterials>,
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    key_bindings: Res<KeyBindings>,
+    camera_query: Query<&Transform, With<BlockIndicator>>,
+    origin: Res<FloatingOrigin>,
+    world_chunks: Res<WorldChunks>,
+    mut area_selection: ResMut<AreaSelection>,
+    mut unmined_block_map: ResMut<UnminedBlockMap>,
+    mut gizmos: Gizmos,
+) {
+    let indicator = origin.coordinates(camera_query.single().translation);
+
+    if let Some(corner) = **area_selection {
+        // Preview the selection
+        let (corner_position, indicator_position) =
+            (origin.position(corner), origin.position(indicator));
+        let min = corner_position.min(indicator_position) - Vec3::splat(0.5);
+        let max = corner_position.max(indicator_position) + Vec3::splat(0.5);
+        let color = if area_size(corner, indicator) > MAX_AREA_FILL_BLOCKS {
+            Color::RED
+        } else {
+            Color::WHITE
+        };
+        gizmos.cuboid(
+            Transform::from_translation((min + max) / 2.0).with_scale(max - min),
+            color,
+        );
+    }
 
-        // Update the hashmap with the new block
+    if keyboard_input.just_pressed(key_bindings.key(Action::CancelArea)) {
+        **area_selection = None;
+    }
+    if !keyboard_input.just_pressed(key_bindings.key(Action::SelectArea)) {
+        return;
+    }
+
+    let Some(corner) = area_selection.take() else {
+        **area_selection = Some(indicator);
+        return;
+    };
+    if area_size(corner, indicator) > MAX_AREA_FILL_BLOCKS {
+        warn!(
+            target: MINING_LOG,
+            "Area is larger than {} blocks, not filling it",
+            MAX_AREA_FILL_BLOCKS
+        );
+        return;
+    }
+    for (coordinates, coordinate_string) in
+        area_coordinates(corner, indicator, world_chunks.plane())
+    {
+        if unmined_block_map.contains_key(&coordinate_string) {
+            continue;
+        }
+        let block_entity = spawn_unmined_block(
+            &mut commands,
+            &stuff,
+            coordinates,
+            coordinate_string.clone(),
+        );
         unmined_block_map.insert(coordinate_string, block_entity);
     }
 }
 
 // KEY 55BE2A31916E238A5D21F44DEAF7FA2579D11EEEB98D022842A15A2C7AF2F106
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn worker(index: u64, token: &CancellationToken) -> MiningWorker {
+        MiningWorker {
+            index,
+            started_at: Instant::now(),
+            hashes_per_second: 0.0,
+            hashes: Arc::new(AtomicU64::new(0)),
+            last_count: 0,
+            token: token.child_token(),
+        }
+    }
+
+    #[test]
+    fn cancelling_the_last_worker_pauses_its_block() {
+        let mut mining_queue = MiningQueue::default();
+        mining_queue.enqueue("block".to_string(), Entity::PLACEHOLDER);
+        let token = CancellationToken::new();
+        let block = &mut mining_queue.blocks[0];
+        block.status = QueuedBlockStatus::Mining;
+        block.workers = vec![worker(0, &token), worker(1, &token)];
+        block.token = Some(token.clone());
+        let first_worker = block.workers[0].token.clone();
+
+        mining_queue.cancel_worker("block", 0);
+        assert!(first_worker.is_cancelled());
+        assert!(!token.is_cancelled());
+        assert_eq!(mining_queue.blocks[0].status, QueuedBlockStatus::Mining);
+
+        mining_queue.cancel_worker("block", 1);
+        assert!(token.is_cancelled());
+        assert_eq!(mining_queue.blocks[0].status, QueuedBlockStatus::Paused);
+    }
+
+    #[test]
+    fn material_cycle_returns_to_pow_tiers() {
+        let mut chosen = None;
+        for _ in 0..TOP_MATERIAL_TIER {
+            chosen = next_material(chosen);
+            assert!(chosen.is_some());
+        }
+        assert_eq!(chosen, Some(TOP_MATERIAL_TIER));
+        assert_eq!(next_material(chosen), None);
+    }
+
+    #[test]
+    fn derezz_notes_are_not_read_as_blocks() {
+        let note = MiningAction::Derezz.note("ab".repeat(32), &"01".repeat(32), 12);
+        assert!(serde_json::from_str::<POWBlockDetails>(&note.content).is_err());
+        let derezz_details: DerezzDetails = serde_json::from_str(&note.content).unwrap();
+        assert_eq!(derezz_details.derezz, "01".repeat(32));
+        assert_eq!(derezz_details.pow_amount, 12);
+        let cell_tags = note.tags.iter().filter(|tag| tag[0] == "m").count();
+        assert_eq!(cell_tags, crate::cyberspace::MORTON_LEVELS.len());
+    }
+
+    #[test]
+    fn leading_zero_bits_counts_partial_bytes() {
+        let id = hex::decode("000f00ff").unwrap();
+        assert_eq!(count_leading_zero_bits(&id), 12);
+    }
+
+    #[test]
+    fn leading_zero_bits_matches_nip13_example() {
+        // Example id from NIP-13 with a difficulty of 36
+        let id = hex::decode("000000000e9d97a1ab09fc381030b346cdd7a142ad57e6df0b46dc9bef6c7e2d")
+            .unwrap();
+        assert_eq!(count_leading_zero_bits(&id), 36);
+    }
+
+    #[test]
+    fn note_id_difficulty_ignores_invalid_ids() {
+        assert_eq!(note_id_difficulty("00000fff"), 20);
+        assert_eq!(note_id_difficulty("not hex"), 0);
+    }
+
+    #[test]
+    fn nonce_splits_do_not_overlap() {
+        let workers: Vec<NonceSplit> = (0..3)
+            .map(|start| NonceSplit { start, stride: 3 })
+            .collect();
+        let mut seen = std::collections::HashSet::new();
+        for split in workers {
+            let mut nonce = split.start;
+            for _ in 0..100 {
+                assert!(seen.insert(nonce));
+                nonce = split.next(nonce);
+            }
+        }
+        assert_eq!(seen.len(), 300);
+    }
+
+    #[test]
+    fn subdivided_splits_stay_inside_their_slot() {
+        let slot = NonceSplit {
+            start: 1,
+            stride: 3,
+        };
+        let mut seen = std::collections::HashSet::new();
+        for worker in 0..4 {
+            let split = slot.subdivide(worker, 4);
+            let mut nonce = split.start;
+            for _ in 0..25 {
+                assert_eq!(nonce % 3, 1);
+                assert!(seen.insert(nonce));
+                nonce = split.next(nonce);
+            }
+        }
+        assert_eq!(seen.len(), 100);
+    }
+
+    #[test]
+    fn area_covers_the_cuboid_in_any_corner_order() {
+        let origin = CyberspaceCoordinates::new(1 << 84, 7, 0);
+        let far_corner = origin.offset(IVec3::new(4, 0, 4));
+        let area = area_coordinates(far_corner, origin, Plane::DSpace);
+        assert_eq!(area.len(), 25);
+        assert_eq!(area_size(far_corner, origin), 25);
+        assert_eq!(area[0].0, origin);
+        assert_eq!(area[0].1, origin.to_hex(Plane::DSpace));
+        // Across the edge of cyberspace the cuboid still takes the short way
+        let edge = CyberspaceCoordinates::new(0, 0, 0);
+        assert_eq!(area_size(edge, edge.offset(IVec3::new(-1, 0, 0))), 2);
+    }
+
+    #[test]
+    fn nonce_tag_commits_target() {
+        assert_eq!(nonce_tag("776797", 20), vec!["nonce", "776797", "20"]);
+    }
+
+    #[test]
+    fn defense_policy_respects_limits() {
+        let mut policy = DefensePolicy::default();
+        assert!(!policy.should_defend(10, 0));
+        policy.enabled = true;
+        assert!(policy.should_defend(10, 0));
+        assert!(!policy.should_defend(policy.max_difficulty, 0));
+        assert!(!policy.should_defend(10, policy.max_concurrent_defenses));
+    }
+
+    #[test]
+    fn throttle_sleeps_for_the_idle_share() {
+        let busy = Duration::from_millis(100);
+        assert_eq!(throttle_delay(busy, 100), Duration::ZERO);
+        assert_eq!(throttle_delay(busy, 50), busy);
+        assert_eq!(throttle_delay(busy, 10), Duration::from_millis(900));
+    }
+
+    #[test]
+    fn eta_is_expected_hashes_over_hashrate() {
+        assert_eq!(mining_eta(10, 1024.0), Some(Duration::from_secs(1)));
+        assert_eq!(mining_eta(10, 0.0), None);
+    }
+
+    #[test]
+    fn probability_at_eta_is_one_minus_inverse_e() {
+        let probability = mining_probability(20, 1000.0, mining_eta(20, 1000.0).unwrap());
+        assert!((probability - (1.0 - (-1.0_f64).exp())).abs() < 1e-6);
+        assert_eq!(mining_probability(20, 1000.0, Duration::ZERO), 0.0);
+    }
+}
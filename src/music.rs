@@ -0,0 +1,104 @@
+use bevy::{
+    audio::{AudioSinkPlayback, Volume},
+    prelude::*,
+};
+
+use crate::{
+    cameras::BlockIndicator, chunks::SectorSummaries, menu::AppState, origin::FloatingOrigin,
+    settings::Settings,
+};
+
+// Both eight seconds long and looped, they always play and only their volumes change
+const VOID_TRACK: &str = "sounds/ambient_void.wav";
+const CITY_TRACK: &str = "sounds/ambient_city.wav";
+// A sector with this many blocks is all city, sparse sectors already lean towards it
+const CITY_BLOCKS: f32 = 256.0;
+// Share of the mix that moves towards the new sector's every second
+const CROSSFADE_SPEED: f32 = 0.25;
+
+pub fn music_plugin(app: &mut App) {
+    app.add_systems(OnEnter(AppState::InGame), start_music)
+        .add_systems(Update, crossfade_music.run_if(in_state(AppState::InGame)));
+}
+
+#[derive(Component, Clone, Copy, PartialEq, Debug)]
+enum MusicTrack {
+    Void,
+    City,
+}
+
+fn start_music(mut commands: Commands, asset_server: Res<AssetServer>) {
+    for (track, path) in [
+        (MusicTrack::Void, VOID_TRACK),
+        (MusicTrack::City, CITY_TRACK),
+    ] {
+        commands.spawn((
+            AudioBundle {
+                source: asset_server.load(path),
+                // Faded in by `crossfade_music`
+                settings: PlaybackSettings::LOOP.with_volume(Volume::new(0.0)),
+            },
+            track,
+        ));
+    }
+}
+
+// How much of the mix the city track gets in a sector holding this many blocks
+fn city_share(blocks: usize) -> f32 {
+    (blocks as f32 / CITY_BLOCKS).sqrt().min(1.0)
+}
+
+// Moves the mix towards the indicator's sector, at most `CROSSFADE_SPEED` a second
+fn crossfade_music(
+    time: Res<Time>,
+    settings: Res<Settings>,
+    origin: Res<FloatingOrigin>,
+    sector_summaries: Res<SectorSummaries>,
+    block_indicator: Query<&Transform, With<BlockIndicator>>,
+    track_query: Query<(&MusicTrack, &AudioSink)>,
+    mut mix: Local<Option<f32>>,
+) {
+    let Ok(indicator_transform) = block_indicator.get_single() else {
+        return;
+    };
+    let sector = origin.coordinates(indicator_transform.translation).sector();
+    let blocks = sector_summaries
+        .get(&sector)
+        .map(|summary| summary.blocks)
+        .unwrap_or(0);
+    let target = city_share(blocks);
+    // The first sector plays its own mix right away instead of fading from the void track
+    let current = mix.unwrap_or(target);
+    let step = CROSSFADE_SPEED * time.delta_seconds();
+    let next = current + (target - current).clamp(-step, step);
+    if *mix == Some(next) && !settings.is_changed() {
+        return;
+    }
+
+    let volume = settings.music_volume();
+    let mut playing = false;
+    for (track, sink) in track_query.iter() {
+        let share = match track {
+            MusicTrack::Void => 1.0 - next,
+            MusicTrack::City => next,
+        };
+        sink.set_volume(volume * share);
+        playing = true;
+    }
+    // The sinks show up a frame after the tracks are spawned, the mix waits for them
+    if playing {
+        *mix = Some(next);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn denser_sectors_lean_towards_the_city_track() {
+        assert_eq!(city_share(0), 0.0);
+        assert_eq!(city_share(64), 0.5);
+        assert_eq!(city_share(10_000), 1.0);
+    }
+}
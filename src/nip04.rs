@@ -0,0 +1,96 @@
+use anyhow::anyhow;
+use openssl::{
+    base64,
+    bn::{BigNum, BigNumContext},
+    ec::{EcGroup, EcPoint},
+    nid::Nid,
+    rand::rand_bytes,
+    symm::{self, Cipher},
+};
+
+// NIP-04 content is the base64 ciphertext followed by this and the base64 iv
+const IV_SEPARATOR: &str = "?iv=";
+
+// ECDH between our secret key and their x only public key, the x coordinate is the AES key
+fn shared_secret(secret_key: &str, public_key: &str) -> anyhow::Result<Vec<u8>> {
+    let group = EcGroup::from_curve_name(Nid::SECP256K1)?;
+    let mut context = BigNumContext::new()?;
+    let secret = BigNum::from_hex_str(secret_key)?;
+    // Nostr keys drop the y coordinate, the even one is meant
+    let mut compressed = vec![0x02];
+    compressed.extend(hex::decode(public_key)?);
+    let their_point = EcPoint::from_bytes(&group, &compressed, &mut context)?;
+    let mut shared_point = EcPoint::new(&group)?;
+    shared_point.mul(&group, &their_point, &secret, &context)?;
+    let mut x = BigNum::new()?;
+    let mut y = BigNum::new()?;
+    shared_point.affine_coordinates(&group, &mut x, &mut y, &mut context)?;
+    Ok(x.to_vec_padded(32)?)
+}
+
+pub fn encrypt(secret_key: &str, public_key: &str, plaintext: &str) -> anyhow::Result<String> {
+    let key = shared_secret(secret_key, public_key)?;
+    let mut iv = [0; 16];
+    rand_bytes(&mut iv)?;
+    let ciphertext = symm::encrypt(Cipher::aes_256_cbc(), &key, Some(&iv), plaintext.as_bytes())?;
+    Ok(format!(
+        "{}{}{}",
+        base64::encode_block(&ciphertext),
+        IV_SEPARATOR,
+        base64::encode_block(&iv)
+    ))
+}
+
+pub fn decrypt(secret_key: &str, public_key: &str, content: &str) -> anyhow::Result<String> {
+    let (ciphertext, iv) = content
+        .split_once(IV_SEPARATOR)
+        .ok_or_else(|| anyhow!("Direct message has no iv"))?;
+    let key = shared_secret(secret_key, public_key)?;
+    let plaintext = symm::decrypt(
+        Cipher::aes_256_cbc(),
+        &key,
+        Some(&base64::decode_block(iv)?),
+        &base64::decode_block(ciphertext)?,
+    )?;
+    Ok(String::from_utf8(plaintext)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ALICE_SECRET: &str = "55BE2A31916E238A5D21F44DEAF7FA2579D11EEEB98D022842A15A2C7AF2F106";
+    const BOB_SECRET: &str = "0000000000000000000000000000000000000000000000000000000000000003";
+
+    fn public_key(secret_key: &str) -> String {
+        let group = EcGroup::from_curve_name(Nid::SECP256K1).unwrap();
+        let mut context = BigNumContext::new().unwrap();
+        let secret = BigNum::from_hex_str(secret_key).unwrap();
+        let mut point = EcPoint::new(&group).unwrap();
+        point.mul_generator(&group, &secret, &context).unwrap();
+        let mut x = BigNum::new().unwrap();
+        let mut y = BigNum::new().unwrap();
+        point
+            .affine_coordinates(&group, &mut x, &mut y, &mut context)
+            .unwrap();
+        hex::encode(x.to_vec_padded(32).unwrap())
+    }
+
+    #[test]
+    fn both_sides_decrypt_the_message() {
+        let alice = public_key(ALICE_SECRET);
+        let bob = public_key(BOB_SECRET);
+        let content = encrypt(ALICE_SECRET, &bob, "meet me at the obelisk").unwrap();
+        assert!(content.contains(IV_SEPARATOR));
+        assert_eq!(
+            decrypt(BOB_SECRET, &alice, &content).unwrap(),
+            "meet me at the obelisk"
+        );
+        // The sender reads their own copy with the recipient's key
+        assert_eq!(
+            decrypt(ALICE_SECRET, &bob, &content).unwrap(),
+            "meet me at the obelisk"
+        );
+        assert!(decrypt(BOB_SECRET, &alice, "not encrypted").is_err());
+    }
+}
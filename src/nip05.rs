@@ -0,0 +1,219 @@
+use std::{collections::HashMap as StdHashMap, time::Duration};
+
+use bevy::{prelude::*, utils::HashMap};
+use bevy_tokio_tasks::TokioTasksRuntime;
+use crossbeam_channel::{unbounded, Receiver, Sender};
+use serde::Deserialize;
+
+use crate::{resources::Profiles, sandbox::offline};
+
+const NIP05_TIMEOUT: Duration = Duration::from_secs(5);
+// Lookups in flight at once, the rest wait for a free slot
+const MAX_PENDING_LOOKUPS: usize = 8;
+// The default font has no check mark
+const VERIFIED_BADGE: &str = "[v]";
+
+pub fn nip05_plugin(app: &mut App) {
+    app.init_resource::<Nip05Lookups>()
+        .init_resource::<VerifiedNames>()
+        .add_systems(Startup, setup_nip05_channel)
+        .add_systems(
+            Update,
+            (
+                // The sandbox asks no domain, names stay unverified
+                request_nip05_lookups.run_if(not(offline)),
+                receive_nip05_lookups,
+                update_verified_names,
+            )
+                .chain(),
+        );
+}
+
+enum Nip05Lookup {
+    Pending,
+    // The key the domain lists for the name, none if it lists none or couldn't be reached
+    Resolved(Option<String>),
+}
+
+// Every identifier looked up this session, failures included so they aren't asked for again
+#[derive(Resource, Default, Deref, DerefMut)]
+struct Nip05Lookups(HashMap<String, Nip05Lookup>);
+
+#[derive(Resource, Deref)]
+struct Nip05Results(Receiver<(String, Option<String>)>);
+
+#[derive(Resource, Deref)]
+struct Nip05Writer(Sender<(String, Option<String>)>);
+
+// Keys whose profile's NIP-05 identifier points back at them, with that identifier
+#[derive(Resource, Default, Deref, PartialEq)]
+pub struct VerifiedNames(HashMap<String, String>);
+
+impl VerifiedNames {
+    // The verified identifier with a badge, the profile name or short npub otherwise
+    pub fn display_name(&self, profiles: &Profiles, pubkey: &str) -> String {
+        match self.get(pubkey) {
+            Some(identifier) => format!("{} {}", short_identifier(identifier), VERIFIED_BADGE),
+            None => profiles.display_name(pubkey),
+        }
+    }
+}
+
+// `_@domain` is the domain's own key and goes by the domain alone
+fn short_identifier(identifier: &str) -> &str {
+    identifier.strip_prefix("_@").unwrap_or(identifier)
+}
+
+// Splits `name@domain` into its parts, names are lowercased as NIP-05 asks
+pub fn parse_nip05(identifier: &str) -> Option<(String, String)> {
+    let (name, domain) = identifier
+        .trim()
+        .to_lowercase()
+        .split_once('@')
+        .map(|(name, domain)| (name.to_string(), domain.to_string()))?;
+    let valid_name = !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-_.".contains(c));
+    let valid_domain = !domain.is_empty() && !domain.contains(['/', '?', '#', '@']);
+    (valid_name && valid_domain).then_some((name, domain))
+}
+
+fn nip05_url(name: &str, domain: &str) -> String {
+    format!("https://{}/.well-known/nostr.json?name={}", domain, name)
+}
+
+#[derive(Deserialize)]
+struct Nip05Document {
+    #[serde(default)]
+    names: StdHashMap<String, String>,
+}
+
+fn listed_pubkey(document: &str, name: &str) -> Option<String> {
+    let document: Nip05Document = serde_json::from_str(document).ok()?;
+    document.names.get(name).map(|pubkey| pubkey.to_lowercase())
+}
+
+async fn fetch_nip05(name: &str, domain: &str) -> Option<String> {
+    // NIP-05 forbids following redirects
+    let client = reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .timeout(NIP05_TIMEOUT)
+        .build()
+        .ok()?;
+    let document = client
+        .get(nip05_url(name, domain))
+        .send()
+        .await
+        .ok()?
+        .text()
+        .await
+        .ok()?;
+    listed_pubkey(&document, name)
+}
+
+fn setup_nip05_channel(mut commands: Commands) {
+    let (nip05_writer, nip05_results) = unbounded();
+    commands.insert_resource(Nip05Results(nip05_results));
+    commands.insert_resource(Nip05Writer(nip05_writer));
+}
+
+// Looks up the identifiers of new profiles, a few at a time
+fn request_nip05_lookups(
+    runtime: Res<TokioTasksRuntime>,
+    profiles: Res<Profiles>,
+    nip05_writer: Res<Nip05Writer>,
+    mut lookups: ResMut<Nip05Lookups>,
+) {
+    if !profiles.is_changed() && !lookups.is_changed() {
+        return;
+    }
+    let pending = lookups
+        .values()
+        .filter(|lookup| matches!(lookup, Nip05Lookup::Pending))
+        .count();
+    let new_identifiers: Vec<(String, String, String)> = profiles
+        .values()
+        .filter_map(|profile| profile.nip05.as_deref())
+        .filter_map(|identifier| {
+            let (name, domain) = parse_nip05(identifier)?;
+            let identifier = format!("{}@{}", name, domain);
+            (!lookups.contains_key(&identifier)).then_some((identifier, name, domain))
+        })
+        .take(MAX_PENDING_LOOKUPS.saturating_sub(pending))
+        .collect();
+    for (identifier, name, domain) in new_identifiers {
+        lookups.insert(identifier.clone(), Nip05Lookup::Pending);
+        let nip05_writer = nip05_writer.0.clone();
+        runtime.spawn_background_task(move |_ctx| async move {
+            let pubkey = fetch_nip05(&name, &domain).await;
+            let _ = nip05_writer.send((identifier, pubkey));
+        });
+    }
+}
+
+fn receive_nip05_lookups(nip05_results: Res<Nip05Results>, mut lookups: ResMut<Nip05Lookups>) {
+    for (identifier, pubkey) in nip05_results.try_iter() {
+        lookups.insert(identifier, Nip05Lookup::Resolved(pubkey));
+    }
+}
+
+// A profile can change its identifier, so the names are worked out again from scratch
+fn update_verified_names(
+    profiles: Res<Profiles>,
+    lookups: Res<Nip05Lookups>,
+    mut verified_names: ResMut<VerifiedNames>,
+) {
+    if !profiles.is_changed() && !lookups.is_changed() {
+        return;
+    }
+    let verified = VerifiedNames(
+        profiles
+            .iter()
+            .filter_map(|(pubkey, profile)| {
+                let (name, domain) = parse_nip05(profile.nip05.as_deref()?)?;
+                let identifier = format!("{}@{}", name, domain);
+                match lookups.get(&identifier) {
+                    Some(Nip05Lookup::Resolved(Some(listed))) if listed == pubkey => {
+                        Some((pubkey.clone(), identifier))
+                    }
+                    _ => None,
+                }
+            })
+            .collect(),
+    );
+    // Only marked changed when a name is gained or lost, the nametags redraw on it
+    if *verified_names != verified {
+        *verified_names = verified;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn identifiers_parse_into_name_and_domain() {
+        assert_eq!(
+            parse_nip05(" Bob@Example.com "),
+            Some(("bob".to_string(), "example.com".to_string()))
+        );
+        assert_eq!(
+            nip05_url("bob", "example.com"),
+            "https://example.com/.well-known/nostr.json?name=bob"
+        );
+        assert_eq!(parse_nip05("example.com"), None);
+        assert_eq!(parse_nip05("bob smith@example.com"), None);
+        assert_eq!(parse_nip05("bob@example.com/evil"), None);
+        assert_eq!(short_identifier("_@example.com"), "example.com");
+    }
+
+    #[test]
+    fn only_the_listed_key_is_verified() {
+        let pubkey = "ab".repeat(32);
+        let document = format!(r#"{{"names":{{"bob":"{}"}}}}"#, pubkey.to_uppercase());
+        assert_eq!(listed_pubkey(&document, "bob"), Some(pubkey));
+        assert_eq!(listed_pubkey(&document, "alice"), None);
+        assert_eq!(listed_pubkey("not json", "bob"), None);
+    }
+}
@@ -1,154 +1,1769 @@
-use std::sync::Arc;
+use std::{
+    collections::VecDeque,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
 
-use bevy::prelude::*;
+use anyhow::anyhow;
+use bevy::{
+    ecs::system::SystemParam,
+    prelude::*,
+    utils::{HashMap, HashSet},
+};
 use bevy_tokio_tasks::TokioTasksRuntime;
-use crossbeam_channel::{unbounded, Receiver, Sender};
+use crossbeam_channel::{bounded, unbounded, Receiver, Sender};
+use futures_util::{SinkExt, StreamExt};
 use nostro2::{
-    notes::SignedNote,
+    notes::{Note, SignedNote},
     relays::{NostrRelay, RelayEvents},
 };
-use serde::{Deserialize, Serialize};
-use serde_json::json;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tokio::sync::{broadcast, watch};
+use tokio_tungstenite::tungstenite::Message;
 
 use crate::{
-    cyberspace::extract_coordinates,
-    mining::POWNotes,
+    block_effects::BlockReplaced,
+    cameras::BlockIndicator,
+    channels::{
+        drop_oldest, send_when_ready, DropOldestReceiver, DropOldestSender,
+        INCOMING_NOTES_CAPACITY, OUTGOING_NOTES_CAPACITY, RELAY_REPLIES_CAPACITY, TRAFFIC_CAPACITY,
+        UNVERIFIED_NOTES_CAPACITY,
+    },
+    chat::{ChatMessageReceived, ChatSector},
+    chunks::{SectorSummaries, WorldChunks},
+    console::{ConsoleCommand, ConsoleOutput, RegisterConsoleCommand},
+    constructs::ConstructReceived,
+    contacts::ContactListReceived,
+    cyberspace::{
+        encode_coordinates, morton_cover_within, CyberspaceCoordinates, Sector, SECTOR_SIZE,
+    },
+    diagnostics::{PipelineHop, PipelineLatency},
+    direct_messages::DirectMessageReceived,
+    home_bases::HomeBaseReceived,
+    keybindings::{Action, KeyBindings},
+    logging::{verbose_relays, NOSTR_LOG, RELAY_LOG},
+    menu::AppState,
+    mining::{note_id_difficulty, BlockOutbid, DerezzEvent, POWNotes},
+    note_glyphs::TextNoteReceived,
+    origin::FloatingOrigin,
+    picking::BlockNoteReceived,
+    pool::PoolMessageReceived,
+    protocol::{
+        chat_sector, construct_coordinates, construct_shape, reacted_event, sector_chat_tag,
+        tagged_pubkey, DerezzDetails, DriftDetails, NoteContent, POWBlockDetails, CHAT_KIND,
+        CONSTRUCT_KIND, CONTACTS_KIND, DEREZZ_KIND, DIRECT_MESSAGE_KIND, DRIFT_KIND,
+        HOME_BASE_KIND, METADATA_KIND, POOL_KIND, POW_BLOCK_KIND, REACTION_KIND, TRANSFER_KIND,
+        ZAP_RECEIPT_KIND,
+    },
+    reactions::ReactionReceived,
+    replay::replaying,
     resources::{
-        spawn_mined_block, spawn_pubkey_note, CoordinatesMap, MeshesAndMaterials, UniqueKeys,
+        block_tier, spawn_mined_block, unix_now, CoordinatesMap, DerezzTombstones, DriftPositions,
+        LastSeen, POWBlock, Profiles, UniqueKeys,
     },
+    sandbox::{offline, SANDBOX_RELAY_URL},
+    settings::Settings,
+    supervisor::{RestartPolicy, TaskSupervisor},
+    transfers::{BlockTransfer, BlockTransferred, BlockTransfers},
     ui_camera::PowEvent,
+    verification::{spawn_verifiers, UnverifiedNote, VerificationQueue},
+    zaps::ZapReceiptReceived,
+    UserNostrKeys,
 };
 
+// Relays in a new config file
+pub const DEFAULT_RELAY_URLS: [&str; 1] = ["wss://relay.arrakis.lat"];
+const RELAY_INFO_TIMEOUT: Duration = Duration::from_secs(5);
+pub const COUNT_NIP: u32 = 45;
+const OUTGOING_BROADCAST_CAPACITY: usize = 256;
+
+// Public relays offered to new players by the relay test
+const WELL_KNOWN_RELAYS: [&str; 7] = [
+    "wss://relay.arrakis.lat",
+    "wss://relay.damus.io",
+    "wss://nos.lol",
+    "wss://relay.nostr.band",
+    "wss://relay.primal.net",
+    "wss://relay.snort.social",
+    "wss://nostr.wine",
+];
+const RELAY_PROBE_TIMEOUT: Duration = Duration::from_secs(5);
+// Relays that haven't answered about a published block by then never will
+const PENDING_BLOCK_TIMEOUT: Duration = Duration::from_secs(60);
+const SUGGESTED_RELAYS: usize = 3;
+// How often our avatar position is broadcast while moving
+const DRIFT_INTERVAL: Duration = Duration::from_secs(2);
+// Earlier messages asked for when entering a sector
+const CHAT_HISTORY: usize = 20;
+// Subscriptions we replace or close later keep fixed ids
+const CHAT_SUBSCRIPTION: &str = "chat";
+const CONTACTS_SUBSCRIPTION: &str = "contacts";
+const FIREHOSE_SUBSCRIPTION: &str = "firehose";
+const HOME_BASES_SUBSCRIPTION: &str = "home_bases";
+const NEIGHBORHOOD_SUBSCRIPTION: &str = "neighborhood";
+const ZAP_SUBSCRIPTION: &str = "zaps";
+// Zap receipts are asked for the owners of the blocks on screen, at most this many of them
+const ZAPPED_KEYS_LIMIT: usize = 100;
+const ZAPPED_KEYS_INTERVAL: Duration = Duration::from_secs(5);
+// Recent reactions to blocks asked for on connecting, newer ones stream in as they're made
+const REACTION_HISTORY: usize = 500;
+// Recent text notes asked for when the note glyphs are turned on
+const FIREHOSE_HISTORY: usize = 100;
+// Relay messages kept for the traffic panel, older ones are dropped
+const TRAFFIC_LOG_CAPACITY: usize = 200;
+const TRAFFIC_CONTENT_CHARS: usize = 80;
+
+pub fn nostr_plugin(app: &mut App) {
+    app.init_resource::<RelayPool>()
+        .init_resource::<NoteCounters>()
+        .init_resource::<TrafficLog>()
+        .add_systems(Startup, websocket_thread)
+        .init_resource::<RelayProbe>()
+        .init_resource::<PendingBlocks>()
+        .add_event::<BlockAccepted>()
+        .add_event::<BlockRejected>()
+        .register_console_command(
+            "relay",
+            "/relay <add wss://... | list>",
+            "Connects to another relay or lists the pool",
+        )
+        .add_systems(Startup, setup_relay_probe)
+        .add_systems(OnEnter(AppState::Loading), use_chosen_key)
+        .add_systems(
+            Update,
+            (
+                websocket_middleware,
+                update_relay_pool,
+                // Relays are picked in the menu before any of them is connected
+                // and never while a replay or the offline sandbox stands in for them
+                connect_pending_relays.run_if(
+                    not(in_state(AppState::Menu))
+                        .and_then(not(replaying))
+                        .and_then(not(offline)),
+                ),
+                relay_probe_controls.run_if(not(offline)),
+                update_relay_probe,
+                confirm_published_blocks,
+                broadcast_drift,
+                follow_chat_sector,
+                follow_nearby_blocks,
+                follow_zapped_keys,
+                relay_command,
+                record_traffic,
+                add_configured_relays,
+            ),
+        );
+}
+
 #[derive(Resource, Deref, DerefMut)]
-pub struct IncomingNotes(pub Receiver<SignedNote>);
+pub struct IncomingNotes(pub Receiver<ReceivedNote>);
+
+// A note read from a relay, stamped on arrival so we can measure ingestion latency
+pub struct ReceivedNote {
+    pub note: SignedNote,
+    pub content: NoteContent,
+    pub received_at: Instant,
+    // Spent checking the id and signature, and parsing the content, before the note was queued
+    pub verify_time: Duration,
+    pub parse_time: Duration,
+}
+
+impl ReceivedNote {
+    // From a verifier thread, stamped with when the note came off the relay
+    pub fn verified(note: SignedNote, received_at: Instant, verify_time: Duration) -> Self {
+        let parse_started = Instant::now();
+        let content = NoteContent::parse(note.get_kind(), note.get_content());
+        ReceivedNote {
+            note,
+            content,
+            received_at,
+            verify_time,
+            parse_time: parse_started.elapsed(),
+        }
+    }
+}
 
 #[derive(Resource, Deref, DerefMut)]
 pub struct OutgoingNotes(pub Sender<SignedNote>);
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
-pub struct POWBlockDetails {
-    pub pow_amount: usize,
-    pub coordinates: String,
-    pub miner_pubkey: String,
+// Notes handled since startup, shared with the forwarder thread, for the diagnostics overlay
+#[derive(Resource, Clone, Default)]
+pub struct NoteCounters {
+    pub incoming: Arc<AtomicU64>,
+    pub outgoing: Arc<AtomicU64>,
+    // Dropped by the verifiers for a bad id or signature
+    pub rejected: Arc<AtomicU64>,
+    // Outgoing notes relay sessions fell too far behind to send, summed over the relays
+    pub missed_outgoing: Arc<AtomicU64>,
 }
 
-impl POWBlockDetails {
-    pub fn coordinates(&self) -> Vec3 {
-        if let Ok((x, y, z)) = extract_coordinates(&self.coordinates) {
-            Vec3::new(x as f32, y as f32, z as f32)
-        } else {
-            Vec3::new(0.0, 0.0, 0.0)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TrafficDirection {
+    Received,
+    Sent,
+}
+
+// A message a relay session read or wrote, with the content cut short
+#[derive(Debug, Clone, PartialEq)]
+pub struct TrafficEntry {
+    pub direction: TrafficDirection,
+    pub relay_url: String,
+    pub message: &'static str,
+    pub kind: Option<u32>,
+    pub pubkey: Option<String>,
+    pub content: String,
+}
+
+impl TrafficEntry {
+    fn note(direction: TrafficDirection, relay_url: &str, note: &SignedNote) -> Self {
+        TrafficEntry {
+            direction,
+            relay_url: relay_url.to_string(),
+            message: "EVENT",
+            kind: Some(note.get_kind()),
+            pubkey: Some(note.get_pubkey().to_string()),
+            content: note
+                .get_content()
+                .chars()
+                .take(TRAFFIC_CONTENT_CHARS)
+                .collect(),
         }
     }
 
-    pub fn display_coordinates(&self) -> String {
-        let coordinates = extract_coordinates(self.coordinates.as_str()).unwrap_or((0, 0, 0));
-        format!(
-            "X:{}, Y: {}, Z: {}",
-            coordinates.0, coordinates.1, coordinates.2
-        )
+    fn reply(relay_url: &str, message: &'static str, content: String) -> Self {
+        TrafficEntry {
+            direction: TrafficDirection::Received,
+            relay_url: relay_url.to_string(),
+            message,
+            kind: None,
+            pubkey: None,
+            content: content.chars().take(TRAFFIC_CONTENT_CHARS).collect(),
+        }
+    }
+}
+
+#[derive(Resource)]
+pub struct TrafficUpdates(pub DropOldestReceiver<TrafficEntry>);
+
+// The latest relay messages of every session, oldest first
+#[derive(Resource, Default, Deref)]
+pub struct TrafficLog(VecDeque<TrafficEntry>);
+
+impl TrafficLog {
+    fn record(&mut self, entry: TrafficEntry) {
+        self.0.push_back(entry);
+        if self.0.len() > TRAFFIC_LOG_CAPACITY {
+            self.0.pop_front();
+        }
+    }
+}
+
+pub fn record_traffic(traffic_updates: Res<TrafficUpdates>, mut traffic_log: ResMut<TrafficLog>) {
+    traffic_updates
+        .0
+        .receiver
+        .try_iter()
+        .for_each(|entry| traffic_log.record(entry));
+}
+
+// A relay's OK reply to a note we published
+pub struct RelayAck {
+    pub relay_url: String,
+    pub note_id: String,
+    pub accepted: bool,
+    pub message: String,
+}
+
+#[derive(Resource, Deref, DerefMut)]
+pub struct RelayAcks(pub Receiver<RelayAck>);
+
+// Whether a relay had our contact list, sent once it has sent everything it stores
+#[derive(Resource, Deref, DerefMut)]
+pub struct ContactListAnswers(pub Receiver<bool>);
+
+// One of our mined blocks waiting for a relay to answer
+pub struct PendingBlock {
+    pub note_id: String,
+    pub block_details: POWBlockDetails,
+    pub published_at: Instant,
+    // Another relay may still take it, so a rejected block waits for an accept until it expires
+    pub rejected: bool,
+}
+
+// Our mined blocks waiting for a relay to answer, by coordinate. Miners publish a note for every
+// improvement, each one replaces the note before it so only the newest is reported
+#[derive(Resource, Default, Deref, DerefMut)]
+pub struct PendingBlocks(pub HashMap<String, PendingBlock>);
+
+impl PendingBlocks {
+    pub fn publish(&mut self, note_id: &str, block_details: POWBlockDetails) {
+        self.insert(
+            block_details.coordinates.clone(),
+            PendingBlock {
+                note_id: note_id.to_string(),
+                block_details,
+                published_at: Instant::now(),
+                rejected: false,
+            },
+        );
+    }
+
+    // Gives back the block when the answer is worth reporting: the first accept, which takes it
+    // out, or the first reject. Answers to replaced notes are ignored
+    pub fn answer(&mut self, note_id: &str, accepted: bool) -> Option<POWBlockDetails> {
+        let (coordinates, pending) = self
+            .iter_mut()
+            .find(|(_, pending)| pending.note_id == note_id)?;
+        if accepted {
+            let coordinates = coordinates.clone();
+            return self
+                .remove(&coordinates)
+                .map(|pending| pending.block_details);
+        }
+        if pending.rejected {
+            return None;
+        }
+        pending.rejected = true;
+        Some(pending.block_details.clone())
+    }
+
+    // Drops blocks no relay accepted in time, returning how many no relay answered about at all
+    pub fn expire(&mut self, now: Instant) -> usize {
+        let mut unanswered = 0;
+        self.retain(|_, block| {
+            let waiting = now.duration_since(block.published_at) < PENDING_BLOCK_TIMEOUT;
+            if !waiting && !block.rejected {
+                unanswered += 1;
+            }
+            waiting
+        });
+        unanswered
+    }
+}
+
+// Sent the first time a relay accepts one of our mined blocks
+#[derive(Event)]
+pub struct BlockAccepted {
+    pub block_details: POWBlockDetails,
+    pub relay_url: String,
+}
+
+// Sent when a relay turns down one of our mined blocks
+#[derive(Event)]
+pub struct BlockRejected {
+    pub block_details: POWBlockDetails,
+    pub relay_url: String,
+    pub message: String,
+}
+
+// Hashtag of the sector chat every relay session subscribes to, none until the indicator spawns
+#[derive(Resource, Deref)]
+pub struct ChatSubscription(watch::Sender<Option<String>>);
+
+// Morton cells around the indicator every relay session asks for blocks in, none until it spawns
+#[derive(Resource, Deref)]
+pub struct NeighborhoodSubscription(watch::Sender<Vec<String>>);
+
+// Keys every relay session asks for the home bases of wherever they are, nearby ones come with
+// the blocks around the indicator
+#[derive(Resource, Deref)]
+pub struct HomeBaseSubscription(watch::Sender<Vec<String>>);
+
+// Public keys every relay session asks for zap receipts to, empty until the key is chosen
+#[derive(Resource, Deref)]
+pub struct ZapSubscription(watch::Sender<Vec<String>>);
+
+// Whether relay sessions should also stream every text note, for the note glyphs
+#[derive(Resource, Deref)]
+pub struct FirehoseSubscription(watch::Sender<bool>);
+
+#[derive(Resource, Deref, DerefMut)]
+pub struct RelayInfoUpdates(pub Receiver<(String, RelayInformation)>);
+
+// NIP-11 relay information document
+// Only the fields we display or adapt our behavior to are parsed
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct RelayInformation {
+    pub name: String,
+    pub description: String,
+    pub software: String,
+    pub version: String,
+    pub supported_nips: Vec<u32>,
+    pub limitation: RelayLimitation,
+    // Not in the document, counted with NIP-45 on relays that support it
+    #[serde(skip)]
+    pub stored_blocks: Option<u64>,
+}
+
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct RelayLimitation {
+    pub max_message_length: Option<u64>,
+    pub max_subscriptions: Option<u64>,
+    pub max_filters: Option<u64>,
+    pub min_pow_difficulty: Option<u64>,
+    pub auth_required: bool,
+    pub payment_required: bool,
+}
+
+impl RelayInformation {
+    pub fn supports_nip(&self, nip: u32) -> bool {
+        self.supported_nips.contains(&nip)
+    }
+
+    // Relays that require auth or payment will reject our notes, so we only read from them
+    pub fn accepts_writes(&self) -> bool {
+        !self.limitation.auth_required && !self.limitation.payment_required
+    }
+
+    pub fn display_nips(&self) -> String {
+        self.supported_nips
+            .iter()
+            .map(|nip| nip.to_string())
+            .collect::<Vec<String>>()
+            .join(", ")
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct RelayDetails {
+    pub url: String,
+    pub information: Option<RelayInformation>,
+    pub connected: bool,
+}
+
+// Filled from the config file, the menu and the console can add more for this session
+#[derive(Resource, Debug, Default, Deref, DerefMut)]
+pub struct RelayPool(pub Vec<RelayDetails>);
+
+impl RelayPool {
+    // Returns false if the relay is already in the pool
+    pub fn add(&mut self, url: &str) -> bool {
+        if self.iter().any(|relay| relay.url == url) {
+            return false;
+        }
+        self.push(RelayDetails {
+            url: url.to_string(),
+            information: None,
+            connected: false,
+        });
+        true
+    }
+}
+
+// Everything a relay session needs, so relays can be connected at any time
+#[derive(Resource, Clone)]
+pub struct RelayConnector {
+    unverified_writer: Sender<UnverifiedNote>,
+    outgoing_notes: broadcast::Sender<SignedNote>,
+    relay_info_writer: Sender<(String, RelayInformation)>,
+    acks_writer: Sender<RelayAck>,
+    contacts_writer: Sender<bool>,
+    traffic_writer: DropOldestSender<TrafficEntry>,
+    chat_tags: watch::Receiver<Option<String>>,
+    neighborhood_cells: watch::Receiver<Vec<String>>,
+    zapped_keys: watch::Receiver<Vec<String>>,
+    home_base_authors: watch::Receiver<Vec<String>>,
+    firehose: watch::Receiver<bool>,
+    missed_outgoing: Arc<AtomicU64>,
+    our_pubkey: String,
+}
+
+impl RelayConnector {
+    pub fn connect(
+        &self,
+        runtime: &TokioTasksRuntime,
+        supervisor: &TaskSupervisor,
+        relay_url: String,
+    ) {
+        let connector = self.clone();
+        let supervisor = supervisor.clone();
+        runtime.spawn_background_task(|_ctx| async move {
+            // Fetch the NIP-11 document before connecting so we know what the relay accepts
+            let information = fetch_relay_information(&relay_url).await;
+            let accepts_writes = information
+                .as_ref()
+                .map(|information| information.accepts_writes())
+                .unwrap_or(true);
+            if let Some(information) = information {
+                let _ = send_when_ready(
+                    &connector.relay_info_writer,
+                    (relay_url.clone(), information.clone()),
+                )
+                .await;
+                // Relays without COUNT would have to send every block to be counted
+                if information.supports_nip(COUNT_NIP) {
+                    let count_url = relay_url.clone();
+                    let relay_info_writer = connector.relay_info_writer.clone();
+                    tokio::spawn(async move {
+                        let stored_blocks = count_blocks(&count_url).await;
+                        let information = RelayInformation {
+                            stored_blocks,
+                            ..information
+                        };
+                        let _ = send_when_ready(&relay_info_writer, (count_url, information)).await;
+                    });
+                }
+            }
+            if !accepts_writes {
+                info!(
+                    target: NOSTR_LOG,
+                    "{} requires auth or payment, not publishing to it",
+                    relay_url
+                );
+            }
+
+            supervisor.supervise(
+                format!("relay {}", relay_url),
+                RestartPolicy::Always,
+                move || {
+                    relay_session(
+                        relay_url.clone(),
+                        connector.clone(),
+                        connector.outgoing_notes.subscribe(),
+                        accepts_writes,
+                    )
+                },
+            );
+        });
     }
 }
 
-pub fn websocket_thread(mut commands: Commands, runtime: ResMut<TokioTasksRuntime>) {
-    let (notes_writer, notes_reader) = unbounded::<SignedNote>();
+impl RelayConnector {
+    // Notes that don't come from a relay, like a replay's, are checked by the same verifiers.
+    // A full queue hands the note back to try again later
+    pub fn queue_unverified(&self, note: SignedNote) -> Result<(), SignedNote> {
+        let unverified_note = UnverifiedNote {
+            note,
+            received_at: Instant::now(),
+        };
+        self.unverified_writer
+            .try_send(unverified_note)
+            .map_err(|error| error.into_inner().note)
+    }
+
+    // The offline sandbox's relay, a task in this process rather than a connection
+    pub fn connect_sandbox(
+        &self,
+        runtime: &TokioTasksRuntime,
+        stored_notes: Vec<SignedNote>,
+        store: Sender<SignedNote>,
+    ) {
+        let connector = self.clone();
+        let outgoing_notes = self.outgoing_notes.subscribe();
+        runtime.spawn_background_task(|_ctx| async move {
+            sandbox_session(connector, outgoing_notes, stored_notes, store).await;
+        });
+    }
+}
+
+// Sends back everything stored so far, then stores and echoes every note we publish with an OK,
+// the way a relay answers a subscription that matches everything
+async fn sandbox_session(
+    connector: RelayConnector,
+    mut outgoing_notes: broadcast::Receiver<SignedNote>,
+    stored_notes: Vec<SignedNote>,
+    store: Sender<SignedNote>,
+) {
+    let RelayConnector {
+        unverified_writer,
+        acks_writer,
+        contacts_writer,
+        traffic_writer,
+        missed_outgoing,
+        our_pubkey,
+        ..
+    } = connector;
+    let has_contact_list = stored_notes
+        .iter()
+        .any(|note| note.get_kind() == CONTACTS_KIND && note.get_pubkey() == our_pubkey);
+    for note in stored_notes {
+        let unverified_note = UnverifiedNote {
+            note,
+            received_at: Instant::now(),
+        };
+        if send_when_ready(&unverified_writer, unverified_note)
+            .await
+            .is_err()
+        {
+            return;
+        }
+    }
+    let _ = contacts_writer.try_send(has_contact_list);
+    loop {
+        let note = match outgoing_notes.recv().await {
+            Ok(note) => note,
+            Err(broadcast::error::RecvError::Lagged(missed)) => {
+                missed_outgoing.fetch_add(missed, Ordering::Relaxed);
+                continue;
+            }
+            Err(broadcast::error::RecvError::Closed) => break,
+        };
+        let _ = store.send(note.clone());
+        traffic_writer.send(TrafficEntry::note(
+            TrafficDirection::Sent,
+            SANDBOX_RELAY_URL,
+            &note,
+        ));
+        let ack = RelayAck {
+            relay_url: SANDBOX_RELAY_URL.to_string(),
+            note_id: note.get_id().to_string(),
+            accepted: true,
+            message: String::new(),
+        };
+        let _ = send_when_ready(&acks_writer, ack).await;
+        let unverified_note = UnverifiedNote {
+            note,
+            received_at: Instant::now(),
+        };
+        let _ = send_when_ready(&unverified_writer, unverified_note).await;
+    }
+}
+
+pub fn websocket_thread(
+    mut commands: Commands,
+    runtime: ResMut<TokioTasksRuntime>,
+    user_keys: Res<UserNostrKeys>,
+    note_counters: Res<NoteCounters>,
+) {
+    let (notes_writer, notes_reader) = bounded::<ReceivedNote>(INCOMING_NOTES_CAPACITY);
     commands.insert_resource(IncomingNotes(notes_reader));
 
-    let (outgoing_notes_sender, outgoing_notes_receiver) = unbounded::<SignedNote>();
+    // Relay tasks only queue what they read, the verifiers pass it on once checked
+    let (unverified_writer, unverified_reader) =
+        bounded::<UnverifiedNote>(UNVERIFIED_NOTES_CAPACITY);
+    spawn_verifiers(
+        unverified_reader.clone(),
+        notes_writer,
+        note_counters.rejected.clone(),
+    );
+    commands.insert_resource(VerificationQueue(unverified_reader));
+
+    // Systems publishing a note wait for room, the forwarder below empties it right away
+    let (outgoing_notes_sender, outgoing_notes_receiver) =
+        bounded::<SignedNote>(OUTGOING_NOTES_CAPACITY);
     commands.insert_resource(OutgoingNotes(outgoing_notes_sender));
 
+    let (relay_info_writer, relay_info_reader) =
+        bounded::<(String, RelayInformation)>(RELAY_REPLIES_CAPACITY);
+    commands.insert_resource(RelayInfoUpdates(relay_info_reader));
+
+    let (acks_writer, acks_reader) = bounded::<RelayAck>(RELAY_REPLIES_CAPACITY);
+    commands.insert_resource(RelayAcks(acks_reader));
+
+    let (contacts_writer, contacts_reader) = bounded::<bool>(RELAY_REPLIES_CAPACITY);
+    commands.insert_resource(ContactListAnswers(contacts_reader));
+
+    let (traffic_writer, traffic_reader) = drop_oldest::<TrafficEntry>(TRAFFIC_CAPACITY);
+    commands.insert_resource(TrafficUpdates(traffic_reader));
+
+    let (chat_subscription, chat_tags) = watch::channel(None);
+    commands.insert_resource(ChatSubscription(chat_subscription));
+
+    let (neighborhood_subscription, neighborhood_cells) = watch::channel(Vec::new());
+    commands.insert_resource(NeighborhoodSubscription(neighborhood_subscription));
+
+    let (zap_subscription, zapped_keys) = watch::channel(Vec::new());
+    commands.insert_resource(ZapSubscription(zap_subscription));
+
+    let (home_base_subscription, home_base_authors) = watch::channel(Vec::new());
+    commands.insert_resource(HomeBaseSubscription(home_base_subscription));
+
+    let (firehose_subscription, firehose) = watch::channel(false);
+    commands.insert_resource(FirehoseSubscription(firehose_subscription));
+
+    // Every relay session gets its own copy of the outgoing notes,
+    // so a relay that reconnects doesn't steal notes from the others
+    let (broadcast_sender, _) = broadcast::channel::<SignedNote>(OUTGOING_BROADCAST_CAPACITY);
+    let forwarder = broadcast_sender.clone();
+    let outgoing_count = note_counters.outgoing.clone();
     runtime.spawn_background_task(|_ctx| async move {
-        if let Ok(relay) = NostrRelay::new("wss://relay.arrakis.lat").await {
-            let filter = json!({
-                "kinds": [0, 333],
-            });
+        tokio::task::spawn_blocking(move || {
+            while let Ok(note) = outgoing_notes_receiver.recv() {
+                outgoing_count.fetch_add(1, Ordering::Relaxed);
+                let _ = forwarder.send(note);
+            }
+        });
+    });
 
-            let relay_arc = Arc::new(relay);
-            let relay = relay_arc.clone();
+    commands.insert_resource(RelayConnector {
+        unverified_writer,
+        outgoing_notes: broadcast_sender,
+        relay_info_writer,
+        acks_writer,
+        contacts_writer,
+        traffic_writer,
+        chat_tags,
+        neighborhood_cells,
+        zapped_keys,
+        home_base_authors,
+        firehose,
+        missed_outgoing: note_counters.missed_outgoing.clone(),
+        our_pubkey: user_keys.get_public_key(),
+    });
+}
 
-            relay.subscribe(filter).await.unwrap();
+// The key may have changed in the menu since the connector was made
+fn use_chosen_key(user_keys: Res<UserNostrKeys>, mut connector: ResMut<RelayConnector>) {
+    connector.our_pubkey = user_keys.get_public_key();
+}
 
-            tokio::spawn(async move {
-                while let Ok(note) = outgoing_notes_receiver.recv() {
-                    let _sent = relay.send_note(note).await;
+// Connects every relay in the pool that doesn't have a session yet
+pub fn connect_pending_relays(
+    runtime: Res<TokioTasksRuntime>,
+    supervisor: Res<TaskSupervisor>,
+    connector: Res<RelayConnector>,
+    mut relay_pool: ResMut<RelayPool>,
+) {
+    if !relay_pool.is_changed() {
+        return;
+    }
+    for relay in relay_pool.iter_mut().filter(|relay| !relay.connected) {
+        connector.connect(&runtime, &supervisor, relay.url.clone());
+        relay.connected = true;
+    }
+}
+
+// Connects to a relay and pumps notes both ways until the connection drops
+async fn relay_session(
+    relay_url: String,
+    connector: RelayConnector,
+    mut outgoing_notes: broadcast::Receiver<SignedNote>,
+    accepts_writes: bool,
+) -> anyhow::Result<()> {
+    let RelayConnector {
+        unverified_writer,
+        acks_writer,
+        contacts_writer,
+        traffic_writer,
+        mut chat_tags,
+        mut neighborhood_cells,
+        mut zapped_keys,
+        mut home_base_authors,
+        mut firehose,
+        missed_outgoing,
+        our_pubkey,
+        ..
+    } = connector;
+    let relay = NostrRelay::new(&relay_url)
+        .await
+        .map_err(|_| anyhow!("Could not connect to {}", relay_url))?;
+    let relay = Arc::new(relay);
+    if verbose_relays() {
+        debug!(target: RELAY_LOG, "Connected to {}", relay_url);
+    }
+
+    let filter = json!({
+        "kinds": [
+            METADATA_KIND,
+            POW_BLOCK_KIND,
+            DEREZZ_KIND,
+            CONSTRUCT_KIND,
+            POOL_KIND,
+            DRIFT_KIND,
+            TRANSFER_KIND,
+        ],
+    });
+    relay
+        .subscribe(filter)
+        .await
+        .map_err(|_| anyhow!("Could not subscribe to {}", relay_url))?;
+
+    // Direct messages to us, and the ones we sent so both sides of a conversation load.
+    // Reactions to blocks, other notes' reactions aren't shown anywhere
+    for filter in [
+        json!({ "kinds": [DIRECT_MESSAGE_KIND], "#p": [our_pubkey] }),
+        json!({ "kinds": [DIRECT_MESSAGE_KIND], "authors": [our_pubkey] }),
+        json!({
+            "kinds": [REACTION_KIND],
+            "#k": [POW_BLOCK_KIND.to_string()],
+            "limit": REACTION_HISTORY,
+        }),
+    ] {
+        relay
+            .subscribe(filter)
+            .await
+            .map_err(|_| anyhow!("Could not subscribe to {}", relay_url))?;
+    }
+
+    // Our own contact list, only the newest one counts. Its end of stored notes tells whether the
+    // relay has one, so following someone can't replace a list that hasn't arrived yet
+    let contacts_filter = json!({ "kinds": [CONTACTS_KIND], "authors": [our_pubkey], "limit": 1 });
+    relay
+        .send_to_relay(json!(["REQ", CONTACTS_SUBSCRIPTION, contacts_filter]))
+        .await
+        .map_err(|_| anyhow!("Could not subscribe to {}", relay_url))?;
+    let mut has_contact_list = false;
+
+    let writer = relay.clone();
+    let sent_traffic = traffic_writer.clone();
+    let writer_url = relay_url.clone();
+    let writer_task = tokio::spawn(async move {
+        loop {
+            match outgoing_notes.recv().await {
+                Ok(note) if accepts_writes => {
+                    if verbose_relays() {
+                        debug!(
+                            target: RELAY_LOG,
+                            "Sent kind {} note {} to {}",
+                            note.get_kind(),
+                            note.get_id(),
+                            writer_url
+                        );
+                    }
+                    let entry = TrafficEntry::note(TrafficDirection::Sent, &writer_url, &note);
+                    let _sent = writer.send_note(note).await;
+                    sent_traffic.send(entry);
+                }
+                Ok(_) => {}
+                // The broadcast only keeps the newest notes, a relay this slow loses the rest
+                Err(broadcast::error::RecvError::Lagged(missed)) => {
+                    missed_outgoing.fetch_add(missed, Ordering::Relaxed);
+                    warn!(
+                        target: NOSTR_LOG,
+                        "{} fell behind and missed {} outgoing notes", writer_url, missed
+                    );
                 }
+                Err(broadcast::error::RecvError::Closed) => break,
+            }
+        }
+    });
+
+    // Chat follows the sector we're in, so it gets its own subscription that changes as we move
+    let subscriber = relay.clone();
+    let chat_task = tokio::spawn(async move {
+        let mut open = false;
+        loop {
+            let filter = chat_tags.borrow_and_update().clone().map(|chat_tag| {
+                json!({
+                    "kinds": [CHAT_KIND],
+                    "#t": [chat_tag],
+                    "limit": CHAT_HISTORY,
+                })
             });
+            replace_subscription(&subscriber, CHAT_SUBSCRIPTION, &mut open, filter).await;
+            if chat_tags.changed().await.is_err() {
+                break;
+            }
+        }
+    });
 
-            let relay = relay_arc.clone();
-            tokio::spawn(async move {
-                while let Some(Ok(relay_message)) = relay.read_from_relay().await {
-                    match relay_message {
-                        RelayEvents::EVENT(_, _, signed_note) => {
-                            let _ = notes_writer.send(signed_note);
-                        }
-                        RelayEvents::EOSE(_, _) => {
-                            info!("End of Stream Event");
-                        }
-                        _ => {}
-                    }
+    // Relays cut off the block subscription above after their own limit, so the blocks around us
+    // are asked for again by the Morton cells they are tagged with. Older blocks and other clients
+    // don't tag them, those still only come from the subscription above. Home bases are only
+    // asked for here and by author
+    let subscriber = relay.clone();
+    let neighborhood_task = tokio::spawn(async move {
+        let mut open = false;
+        loop {
+            let cells = neighborhood_cells.borrow_and_update().clone();
+            let filter = (!cells.is_empty()).then(|| {
+                json!({
+                    "kinds": [POW_BLOCK_KIND, DEREZZ_KIND, TRANSFER_KIND, HOME_BASE_KIND],
+                    "#m": cells,
+                })
+            });
+            replace_subscription(&subscriber, NEIGHBORHOOD_SUBSCRIPTION, &mut open, filter).await;
+            if neighborhood_cells.changed().await.is_err() {
+                break;
+            }
+        }
+    });
+
+    let subscriber = relay.clone();
+    let home_bases_task = tokio::spawn(async move {
+        let mut open = false;
+        loop {
+            let authors = home_base_authors.borrow_and_update().clone();
+            let filter = (!authors.is_empty())
+                .then(|| json!({ "kinds": [HOME_BASE_KIND], "authors": authors }));
+            replace_subscription(&subscriber, HOME_BASES_SUBSCRIPTION, &mut open, filter).await;
+            if home_base_authors.changed().await.is_err() {
+                break;
+            }
+        }
+    });
+
+    // Zap receipts only from now on, the glow is for zaps paid while we watch
+    let subscriber = relay.clone();
+    let zaps_task = tokio::spawn(async move {
+        let mut open = false;
+        loop {
+            let keys = zapped_keys.borrow_and_update().clone();
+            let filter = (!keys.is_empty()).then(|| {
+                json!({
+                    "kinds": [ZAP_RECEIPT_KIND],
+                    "#p": keys,
+                    "since": unix_now(),
+                })
+            });
+            replace_subscription(&subscriber, ZAP_SUBSCRIPTION, &mut open, filter).await;
+            if zapped_keys.changed().await.is_err() {
+                break;
+            }
+        }
+    });
+
+    // Text notes from everyone are only asked for while the glyphs are turned on
+    let subscriber = relay.clone();
+    let firehose_task = tokio::spawn(async move {
+        let mut open = false;
+        loop {
+            let filter = firehose
+                .borrow_and_update()
+                .then(|| json!({ "kinds": [CHAT_KIND], "limit": FIREHOSE_HISTORY }));
+            if filter.is_some() != open {
+                replace_subscription(&subscriber, FIREHOSE_SUBSCRIPTION, &mut open, filter).await;
+            }
+            if firehose.changed().await.is_err() {
+                break;
+            }
+        }
+    });
+
+    while let Some(Ok(relay_message)) = relay.read_from_relay().await {
+        match relay_message {
+            RelayEvents::EVENT(_, subscription_id, signed_note) => {
+                has_contact_list |= subscription_id == CONTACTS_SUBSCRIPTION;
+                if verbose_relays() {
+                    debug!(
+                        target: RELAY_LOG,
+                        "Received kind {} note {} from {}",
+                        signed_note.get_kind(),
+                        signed_note.get_id(),
+                        relay_url
+                    );
+                }
+                traffic_writer.send(TrafficEntry::note(
+                    TrafficDirection::Received,
+                    &relay_url,
+                    &signed_note,
+                ));
+                let unverified_note = UnverifiedNote {
+                    note: signed_note,
+                    received_at: Instant::now(),
+                };
+                if send_when_ready(&unverified_writer, unverified_note)
+                    .await
+                    .is_err()
+                {
+                    break;
+                }
+            }
+            RelayEvents::EOSE(_, subscription_id) => {
+                if subscription_id == CONTACTS_SUBSCRIPTION {
+                    let _ = contacts_writer.try_send(has_contact_list);
+                }
+                if verbose_relays() {
+                    debug!(target: RELAY_LOG, "End of stored notes from {}", relay_url);
+                }
+                traffic_writer.send(TrafficEntry::reply(&relay_url, "EOSE", String::new()));
+            }
+            RelayEvents::OK(_, note_id, accepted, message) => {
+                let reply = format!(
+                    "{} {} {}",
+                    if accepted { "accepted" } else { "rejected" },
+                    note_id,
+                    message
+                );
+                if verbose_relays() {
+                    debug!(target: RELAY_LOG, "{} replied {}", relay_url, reply);
                 }
+                traffic_writer.send(TrafficEntry::reply(&relay_url, "OK", reply));
+                let ack = RelayAck {
+                    relay_url: relay_url.clone(),
+                    note_id,
+                    accepted,
+                    message,
+                };
+                let _ = send_when_ready(&acks_writer, ack).await;
+            }
+            _ => {}
+        }
+    }
+
+    writer_task.abort();
+    chat_task.abort();
+    neighborhood_task.abort();
+    home_bases_task.abort();
+    zaps_task.abort();
+    firehose_task.abort();
+    Err(anyhow!("Lost connection to {}", relay_url))
+}
+
+// Closes the subscription's old filter before asking for the new one, so relays don't keep
+// sending what we moved away from. Without a filter it stays closed
+async fn replace_subscription(
+    relay: &NostrRelay,
+    subscription_id: &str,
+    open: &mut bool,
+    filter: Option<Value>,
+) {
+    if *open {
+        let _ = relay.send_to_relay(json!(["CLOSE", subscription_id])).await;
+        *open = false;
+    }
+    if let Some(filter) = filter {
+        *open = relay
+            .send_to_relay(json!(["REQ", subscription_id, filter]))
+            .await
+            .is_ok();
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct RelayProbeResult {
+    pub url: String,
+    pub latency: Option<Duration>,
+    pub has_blocks: bool,
+}
+
+#[derive(Resource, Default)]
+pub struct RelayProbe {
+    pub running: bool,
+    pub results: Vec<RelayProbeResult>,
+}
+
+impl RelayProbe {
+    // Fastest reachable relays carrying blocks, or just the fastest ones if none do
+    pub fn suggestions(&self) -> Vec<&RelayProbeResult> {
+        let mut reachable: Vec<&RelayProbeResult> = self
+            .results
+            .iter()
+            .filter(|result| result.latency.is_some())
+            .collect();
+        reachable.sort_by_key(|result| (!result.has_blocks, result.latency));
+        reachable.into_iter().take(SUGGESTED_RELAYS).collect()
+    }
+}
+
+#[derive(Resource, Deref)]
+struct RelayProbeResults(Receiver<RelayProbeResult>);
+
+#[derive(Resource, Deref)]
+struct RelayProbeWriter(Sender<RelayProbeResult>);
+
+fn setup_relay_probe(mut commands: Commands) {
+    let (probe_writer, probe_reader) = unbounded::<RelayProbeResult>();
+    commands.insert_resource(RelayProbeResults(probe_reader));
+    commands.insert_resource(RelayProbeWriter(probe_writer));
+}
+
+fn relay_probe_controls(
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    key_bindings: Res<KeyBindings>,
+    runtime: Res<TokioTasksRuntime>,
+    probe_writer: Res<RelayProbeWriter>,
+    mut relay_probe: ResMut<RelayProbe>,
+    mut relay_pool: ResMut<RelayPool>,
+) {
+    // Test every well known relay in parallel
+    if keyboard_input.just_pressed(key_bindings.key(Action::ProbeRelays)) && !relay_probe.running {
+        relay_probe.running = true;
+        relay_probe.results.clear();
+        for relay_url in WELL_KNOWN_RELAYS {
+            let probe_writer = probe_writer.0.clone();
+            runtime.spawn_background_task(move |_ctx| async move {
+                let _ = probe_writer.send(probe_relay(relay_url).await);
             });
         }
+    }
+
+    // Add the suggested relays to the pool
+    if keyboard_input.just_pressed(key_bindings.key(Action::AddSuggestedRelays)) {
+        for suggestion in relay_probe.suggestions() {
+            relay_pool.add(&suggestion.url);
+        }
+    }
+}
+
+fn relay_command(
+    mut console_commands: EventReader<ConsoleCommand>,
+    mut console_output: EventWriter<ConsoleOutput>,
+    mut relay_pool: ResMut<RelayPool>,
+) {
+    for command in console_commands.read() {
+        if command.name != "relay" {
+            continue;
+        }
+        let reply = match command.args.iter().map(String::as_str).collect::<Vec<_>>()[..] {
+            ["add", url] if is_relay_url(url) => match relay_pool.add(url) {
+                true => format!("Connecting to {}", url),
+                false => format!("{} is already in the pool", url),
+            },
+            ["list"] => relay_pool
+                .iter()
+                .map(|relay| {
+                    let status = if relay.connected {
+                        "connected"
+                    } else {
+                        "connecting"
+                    };
+                    format!("{} {}", relay.url, status)
+                })
+                .collect::<Vec<_>>()
+                .join("\n"),
+            _ => "Usage: /relay <add wss://... | list>".to_string(),
+        };
+        console_output.send(ConsoleOutput(reply));
+    }
+}
+
+// Every configured relay on startup, then only the ones added to the file, so relays removed
+// in the menu don't come back whenever another setting changes
+pub fn add_configured_relays(
+    settings: Res<Settings>,
+    mut configured: Local<Vec<String>>,
+    mut relay_pool: ResMut<RelayPool>,
+) {
+    if !settings.is_changed() {
+        return;
+    }
+    for url in settings.relays.iter() {
+        if configured.contains(url) {
+            continue;
+        }
+        if is_relay_url(url) {
+            relay_pool.add(url);
+        } else {
+            warn!(target: NOSTR_LOG, "{} is not a relay address", url);
+        }
+    }
+    *configured = settings.relays.clone();
+}
+
+pub fn is_relay_url(url: &str) -> bool {
+    ["wss://", "ws://"]
+        .iter()
+        .any(|scheme| url.len() > scheme.len() && url.starts_with(scheme))
+}
+
+fn update_relay_probe(probe_results: Res<RelayProbeResults>, mut relay_probe: ResMut<RelayProbe>) {
+    probe_results.try_iter().for_each(|result| {
+        relay_probe.results.push(result);
+        if relay_probe.results.len() == WELL_KNOWN_RELAYS.len() {
+            relay_probe.running = false;
+        }
     });
 }
 
+// Measures how long a relay takes to connect and whether it has any blocks for us
+async fn probe_relay(relay_url: &str) -> RelayProbeResult {
+    let started_at = Instant::now();
+    let connection = tokio::time::timeout(RELAY_PROBE_TIMEOUT, NostrRelay::new(relay_url)).await;
+    let Ok(Ok(relay)) = connection else {
+        return RelayProbeResult {
+            url: relay_url.to_string(),
+            latency: None,
+            has_blocks: false,
+        };
+    };
+    let latency = started_at.elapsed();
+
+    let filter = json!({
+        "kinds": [POW_BLOCK_KIND],
+        "limit": 1,
+    });
+    let mut has_blocks = false;
+    if relay.subscribe(filter).await.is_ok() {
+        // The first EVENT before EOSE means the relay stores blocks
+        let first_message =
+            tokio::time::timeout(RELAY_PROBE_TIMEOUT, relay.read_from_relay()).await;
+        has_blocks = matches!(first_message, Ok(Some(Ok(RelayEvents::EVENT(_, _, _)))));
+    }
+
+    RelayProbeResult {
+        url: relay_url.to_string(),
+        latency: Some(latency),
+        has_blocks,
+    }
+}
+
+async fn fetch_relay_information(relay_url: &str) -> Option<RelayInformation> {
+    // NIP-11 documents are served over HTTP(S) on the same host as the websocket
+    let http_url = relay_url
+        .replacen("wss://", "https://", 1)
+        .replacen("ws://", "http://", 1);
+    let response = reqwest::Client::new()
+        .get(http_url)
+        .header("Accept", "application/nostr+json")
+        .timeout(RELAY_INFO_TIMEOUT)
+        .send()
+        .await
+        .ok()?;
+    response.json::<RelayInformation>().await.ok()
+}
+
+// COUNT gets its own connection, nostro2 doesn't read COUNT replies
+async fn count_blocks(relay_url: &str) -> Option<u64> {
+    let count = async {
+        let (mut socket, _) = tokio_tungstenite::connect_async(relay_url).await.ok()?;
+        let request = json!(["COUNT", "blocks", { "kinds": [POW_BLOCK_KIND] }]);
+        socket.send(Message::Text(request.to_string())).await.ok()?;
+        while let Some(Ok(message)) = socket.next().await {
+            if let Message::Text(reply) = message {
+                if let Some(count) = parse_count_reply(&reply) {
+                    return Some(count);
+                }
+            }
+        }
+        None
+    };
+    tokio::time::timeout(RELAY_INFO_TIMEOUT, count)
+        .await
+        .ok()
+        .flatten()
+}
+
+fn parse_count_reply(reply: &str) -> Option<u64> {
+    let reply: Value = serde_json::from_str(reply).ok()?;
+    match reply.as_array()?.as_slice() {
+        [message, _, result] if message == "COUNT" => result.get("count")?.as_u64(),
+        _ => None,
+    }
+}
+
+pub fn update_relay_pool(
+    relay_info_updates: Res<RelayInfoUpdates>,
+    mut relay_pool: ResMut<RelayPool>,
+) {
+    relay_info_updates
+        .try_iter()
+        .for_each(|(url, information)| {
+            if let Some(relay) = relay_pool.iter_mut().find(|relay| relay.url == url) {
+                relay.information = Some(information);
+            }
+        });
+}
+
+// Everything the middleware tells the rest of the game about
+#[derive(SystemParam)]
+pub struct MiddlewareEvents<'w> {
+    pow_events: EventWriter<'w, PowEvent>,
+    outbid_events: EventWriter<'w, BlockOutbid>,
+    replaced_blocks: EventWriter<'w, BlockReplaced>,
+    derezz_events: EventWriter<'w, DerezzEvent>,
+    pool_messages: EventWriter<'w, PoolMessageReceived>,
+    chat_messages: EventWriter<'w, ChatMessageReceived>,
+    direct_messages: EventWriter<'w, DirectMessageReceived>,
+    block_notes: EventWriter<'w, BlockNoteReceived>,
+    constructs: EventWriter<'w, ConstructReceived>,
+    text_notes: EventWriter<'w, TextNoteReceived>,
+    zap_receipts: EventWriter<'w, ZapReceiptReceived>,
+    contact_lists: EventWriter<'w, ContactListReceived>,
+    reactions: EventWriter<'w, ReactionReceived>,
+    transfers: EventWriter<'w, BlockTransferred>,
+    home_bases: EventWriter<'w, HomeBaseReceived>,
+}
+
 pub fn websocket_middleware(
     mut commands: Commands,
-    stuff: Res<MeshesAndMaterials>,
     incoming_notes: Res<IncomingNotes>,
     outgoing_notes: Res<OutgoingNotes>,
     pow_notes: Res<POWNotes>,
-    mut pow_events: EventWriter<PowEvent>,
+    mut events: MiddlewareEvents,
+    mut pending_blocks: ResMut<PendingBlocks>,
+    user_keys: Res<UserNostrKeys>,
     mut unique_keys: ResMut<UniqueKeys>,
-    mut coordinates_map: ResMut<CoordinatesMap>,
+    // Grouped since systems take at most 16 parameters
+    (mut coordinates_map, mut block_transfers, mut derezz_tombstones): (
+        ResMut<CoordinatesMap>,
+        ResMut<BlockTransfers>,
+        ResMut<DerezzTombstones>,
+    ),
+    mut world_chunks: ResMut<WorldChunks>,
+    mut sector_summaries: ResMut<SectorSummaries>,
+    mut profiles: ResMut<Profiles>,
+    mut drift_positions: ResMut<DriftPositions>,
+    mut last_seen: ResMut<LastSeen>,
+    mut pipeline_latency: ResMut<PipelineLatency>,
+    note_counters: Res<NoteCounters>,
 ) {
-    incoming_notes.try_iter().for_each(|note| {
+    incoming_notes.try_iter().for_each(|received_note| {
+        note_counters.incoming.fetch_add(1, Ordering::Relaxed);
+        let ReceivedNote {
+            note,
+            content,
+            received_at,
+            verify_time,
+            parse_time,
+        } = received_note;
+        let mut hop_started_at = received_at;
+        pipeline_latency.record(PipelineHop::Received, hop_started_at.elapsed());
+        pipeline_latency.record(PipelineHop::Verified, verify_time);
+        hop_started_at = Instant::now();
+
+        // Receipts are signed by the recipient's lightning server, which isn't a player
+        if note.get_kind() == ZAP_RECEIPT_KIND {
+            events.zap_receipts.send(ZapReceiptReceived {
+                id: note.get_id().to_string(),
+                signer: note.get_pubkey().to_string(),
+                tags: note.get_tags().to_vec(),
+            });
+            return;
+        }
+        // Contact lists aren't JSON either, and only ours is asked for
+        if note.get_kind() == CONTACTS_KIND {
+            if note.get_pubkey() == user_keys.get_public_key() {
+                events.contact_lists.send(ContactListReceived {
+                    created_at: note.get_created_at(),
+                    tags: note.get_tags().to_vec(),
+                    content: note.get_content().to_string(),
+                });
+            }
+            return;
+        }
+
+        // Avatar entities are spawned by the world plugin within the avatar budget
         if !unique_keys.contains(note.get_pubkey()) {
-            spawn_pubkey_note(&mut commands, &stuff, note.get_pubkey().to_string());
             unique_keys.insert(note.get_pubkey().to_string());
         }
+        last_seen.record(note.get_pubkey(), note.get_created_at());
 
-        // Check if the note is a POW block with proper formatting
-        if let Ok(pow_block_details) = serde_json::from_str::<POWBlockDetails>(&note.get_content())
-        {
-            // Check if the coordinates aalready have a block
-            if !coordinates_map.contains_key(&pow_block_details.coordinates) {
-                // If not, spawn a new block
-                let spawned_block = spawn_mined_block(&mut commands, &stuff, &pow_block_details);
-                // And add it to the hashmap
+        // Chat and direct messages aren't JSON, so they're told apart by kind first
+        if note.get_kind() == CHAT_KIND {
+            if let Some(sector) = chat_sector(&note.get_tags()) {
+                events.chat_messages.send(ChatMessageReceived {
+                    id: note.get_id().to_string(),
+                    author: note.get_pubkey().to_string(),
+                    sector,
+                    content: note.get_content().to_string(),
+                    created_at: note.get_created_at(),
+                });
+            } else {
+                events.text_notes.send(TextNoteReceived {
+                    id: note.get_id().to_string(),
+                    content: note.get_content().to_string(),
+                });
+            }
+            return;
+        }
+        if note.get_kind() == CONSTRUCT_KIND {
+            let tags = note.get_tags();
+            if let Some(coordinates) = construct_coordinates(&tags) {
+                events.constructs.send(ConstructReceived {
+                    id: note.get_id().to_string(),
+                    coordinates,
+                    pow_amount: note_id_difficulty(note.get_id()),
+                    shape: construct_shape(&tags),
+                });
+            }
+            return;
+        }
+        if note.get_kind() == DIRECT_MESSAGE_KIND {
+            if let Some(recipient) = tagged_pubkey(&note.get_tags()) {
+                events.direct_messages.send(DirectMessageReceived {
+                    id: note.get_id().to_string(),
+                    author: note.get_pubkey().to_string(),
+                    recipient,
+                    content: note.get_content().to_string(),
+                    created_at: note.get_created_at(),
+                });
+            }
+            return;
+        }
+        if note.get_kind() == REACTION_KIND {
+            if let Some(note_id) = reacted_event(&note.get_tags()) {
+                events.reactions.send(ReactionReceived {
+                    author: note.get_pubkey().to_string(),
+                    note_id,
+                    content: note.get_content().to_string(),
+                });
+            }
+            return;
+        }
+        if note.get_kind() == HOME_BASE_KIND {
+            if let NoteContent::HomeBase(details) = content {
+                events.home_bases.send(HomeBaseReceived {
+                    author: note.get_pubkey().to_string(),
+                    created_at: note.get_created_at(),
+                    details,
+                });
+            }
+            return;
+        }
+
+        if note.get_kind() == TRANSFER_KIND {
+            let NoteContent::Transfer(transfer_details) = content else {
+                return;
+            };
+            let recorded = block_transfers.record(
+                &transfer_details.block,
+                BlockTransfer {
+                    id: note.get_id().to_string(),
+                    author: note.get_pubkey().to_string(),
+                    previous: transfer_details.previous.clone(),
+                    recipient: transfer_details.recipient.clone(),
+                    created_at: note.get_created_at(),
+                },
+            );
+            // Transfers of a block that was outbid since, or isn't known yet, wait in the chain
+            if !recorded
+                || block_transfers.block_id(&transfer_details.transfer)
+                    != Some(&transfer_details.block)
+            {
+                return;
+            }
+            let Some((entity, block_details)) = coordinates_map.get_mut(&transfer_details.transfer)
+            else {
+                return;
+            };
+            let owner = block_transfers.owner(&transfer_details.block, &block_details.miner_pubkey);
+            if owner == block_details.owner() {
+                return;
+            }
+            events.transfers.send(BlockTransferred {
+                coordinates: transfer_details.transfer.clone(),
+                from: block_details.owner().to_string(),
+                to: owner.clone(),
+            });
+            block_details.owner = Some(owner);
+            // Spawned again so the batches pick up the new owner's colors
+            if let Some(spawned_block) = entity.take() {
+                commands.entity(spawned_block).despawn();
+                *entity = Some(spawn_mined_block(&mut commands, block_details));
+            }
+            return;
+        }
+
+        match content {
+            // Check if the note is a POW block with proper formatting
+            NoteContent::Block(mut pow_block_details) => {
+                pipeline_latency.record(PipelineHop::Parsed, parse_time);
+
+                // The transfer chain and the outbid checks start from the miner, which only the
+                // signature vouches for
+                if pow_block_details.miner_pubkey != note.get_pubkey() {
+                    return;
+                }
+
+                // Never trust the claimed POW, count the leading zero bits of the id instead
+                pow_block_details.pow_amount = note_id_difficulty(note.get_id());
+                // Removed by a derezz that came in first
+                if derezz_tombstones
+                    .buries(&pow_block_details.coordinates, pow_block_details.pow_amount)
+                {
+                    return;
+                }
+                pipeline_latency.record(PipelineHop::PowCounted, hop_started_at.elapsed());
+                hop_started_at = Instant::now();
+
+                // Check if the coordinates aalready have a block
+                let sector = pow_block_details.sector();
+                let replacing = coordinates_map.contains_key(&pow_block_details.coordinates);
+                if let Some(existing_pow_block) =
+                    coordinates_map.get(&pow_block_details.coordinates)
+                {
+                    // Only replace the existing block if the new one has more POW
+                    if pow_block_details.pow_amount <= existing_pow_block.1.pow_amount {
+                        return;
+                    }
+                    // Let the defense policy know if it was one of ours
+                    let our_pubkey = user_keys.get_public_key();
+                    if existing_pow_block.1.owner() == our_pubkey && note.get_pubkey() != our_pubkey
+                    {
+                        events.outbid_events.send(BlockOutbid {
+                            coordinates: pow_block_details.coordinates.clone(),
+                            pow_amount: pow_block_details.pow_amount,
+                        });
+                    }
+                    // Despawn the old block
+                    if let Some(existing_entity) = existing_pow_block.0 {
+                        commands.entity(existing_entity).despawn();
+                    }
+                    sector_summaries.remove_block(sector, existing_pow_block.1.pow_amount);
+                    block_transfers.remove_block(&pow_block_details.coordinates);
+                }
+
+                // Transfers can arrive before the block they hand over
+                block_transfers.set_block(&pow_block_details.coordinates, note.get_id());
+                let owner = block_transfers.owner(note.get_id(), &pow_block_details.miner_pubkey);
+                if owner != pow_block_details.miner_pubkey {
+                    pow_block_details.owner = Some(owner);
+                }
+
+                // Blocks in unloaded sectors or the other plane are only cached until shown
+                let spawned_block = world_chunks
+                    .shows(sector, &pow_block_details.coordinates)
+                    .then(|| {
+                        let spawned_block = spawn_mined_block(&mut commands, &pow_block_details);
+                        pipeline_latency.record(PipelineHop::Spawned, hop_started_at.elapsed());
+                        spawned_block
+                    });
+                // An upgraded or outbid block bursts where it stands
+                if replacing && spawned_block.is_some() {
+                    events.replaced_blocks.send(BlockReplaced {
+                        coordinates: pow_block_details.coordinates(),
+                        tier: block_tier(pow_block_details.pow_amount, pow_block_details.material),
+                    });
+                }
+                world_chunks.index(sector, &pow_block_details.coordinates);
+                sector_summaries.add_block(sector, pow_block_details.pow_amount);
+                events.block_notes.send(BlockNoteReceived {
+                    coordinates: pow_block_details.coordinates.clone(),
+                    note: note.clone(),
+                });
                 coordinates_map.insert(
                     pow_block_details.coordinates.to_string(),
-                    (spawned_block, pow_block_details.clone()),
+                    (spawned_block, pow_block_details),
                 );
-            } else {
-                // Get the matching block from the hashmap
-                let existing_pow_block =
-                    coordinates_map.get(&pow_block_details.coordinates).unwrap();
-                // Get the amount of POW for the existing block
-                let existing_entity = existing_pow_block.0;
-
-                // If the new block has more POW, replace the existing block
-                if pow_block_details.pow_amount > existing_pow_block.1.pow_amount {
-                    // Spawn the new block
-                    let spawned_block =
-                        spawn_mined_block(&mut commands, &stuff, &pow_block_details);
-                    // Add it to the hashmap
-                    coordinates_map.insert(
-                        pow_block_details.coordinates.to_string(),
-                        (spawned_block, pow_block_details.clone()),
-                    );
-                    // Despawn the old block
-                    commands.entity(existing_entity).despawn();
+            }
+            NoteContent::Derezz(derezz_details) => {
+                // Only a derezz with more POW than the block removes it
+                let pow_amount = note_id_difficulty(note.get_id());
+                // Kept for blocks it beats that haven't arrived yet
+                derezz_tombstones.record(&derezz_details.derezz, pow_amount);
+                let Some((entity, block_details)) = coordinates_map.get(&derezz_details.derezz)
+                else {
+                    return;
+                };
+                if pow_amount <= block_details.pow_amount {
+                    return;
+                }
+                let our_pubkey = user_keys.get_public_key();
+                if block_details.owner() == our_pubkey && note.get_pubkey() != our_pubkey {
+                    events.outbid_events.send(BlockOutbid {
+                        coordinates: derezz_details.derezz.clone(),
+                        pow_amount,
+                    });
+                }
+                if let Some(entity) = entity {
+                    commands.entity(*entity).despawn();
+                }
+                let sector = block_details.sector();
+                world_chunks.remove(sector, &derezz_details.derezz);
+                sector_summaries.remove_block(sector, block_details.pow_amount);
+                block_transfers.remove_block(&derezz_details.derezz);
+                coordinates_map.remove(&derezz_details.derezz);
+            }
+            NoteContent::Drift(drift_details) => {
+                // Our own avatar is the block indicator
+                if note.get_pubkey() == user_keys.get_public_key() {
+                    return;
+                }
+                if let Some(position) = drift_details.coordinates() {
+                    drift_positions.insert(note.get_pubkey().to_string(), position);
+                }
+            }
+            NoteContent::Pool(message) => {
+                // Relays echo our own pool notes back, only others' matter
+                if note.get_pubkey() != user_keys.get_public_key() {
+                    events.pool_messages.send(PoolMessageReceived {
+                        author: note.get_pubkey().to_string(),
+                        message,
+                    });
+                }
+            }
+            NoteContent::Profile(profile) => {
+                if !profile.is_empty() && profiles.get(note.get_pubkey()) != Some(&profile) {
+                    profiles.insert(note.get_pubkey().to_string(), profile);
                 }
             }
+            NoteContent::HomeBase(_) | NoteContent::Transfer(_) | NoteContent::Unparsed => {}
         }
     });
 
     // Forward the mined POW notes to the websocket
     pow_notes.try_iter().for_each(|note| {
-        if let Ok(block_details) = serde_json::from_str::<POWBlockDetails>(note.get_content()) {
-            pow_events.send(PowEvent(block_details));
+        if let Ok(mut block_details) = serde_json::from_str::<POWBlockDetails>(note.get_content()) {
+            block_details.pow_amount = note_id_difficulty(note.get_id());
+            pending_blocks.publish(note.get_id(), block_details.clone());
+            events.pow_events.send(PowEvent(block_details));
+        } else if let Ok(mut derezz_details) =
+            serde_json::from_str::<DerezzDetails>(note.get_content())
+        {
+            derezz_details.pow_amount = note_id_difficulty(note.get_id());
+            events.derezz_events.send(DerezzEvent(derezz_details));
         }
         let _sent = outgoing_notes.send(note);
     });
 }
+
+// Lets other players see our avatar move, at most every `DRIFT_INTERVAL` and only after moving
+fn broadcast_drift(
+    time: Res<Time>,
+    user_keys: Res<UserNostrKeys>,
+    outgoing_notes: Res<OutgoingNotes>,
+    block_indicator: Query<&Transform, With<BlockIndicator>>,
+    origin: Res<FloatingOrigin>,
+    mut last_broadcast: Local<Option<(Duration, CyberspaceCoordinates)>>,
+) {
+    let Ok(indicator_transform) = block_indicator.get_single() else {
+        return;
+    };
+    let position = origin.coordinates(indicator_transform.translation);
+    if let Some((broadcast_at, broadcast_position)) = *last_broadcast {
+        if broadcast_position == position || time.elapsed() - broadcast_at < DRIFT_INTERVAL {
+            return;
+        }
+    }
+    *last_broadcast = Some((time.elapsed(), position));
+
+    let drift_details = DriftDetails {
+        drift: encode_coordinates(position.x, position.y, position.z),
+    };
+    let note = Note::new(
+        user_keys.get_public_key(),
+        DRIFT_KIND,
+        &json!(drift_details).to_string(),
+    );
+    let _sent = outgoing_notes.send(user_keys.get_keypair().sign_nostr_event(note));
+}
+
+fn follow_chat_sector(chat_sector: Res<ChatSector>, chat_subscription: Res<ChatSubscription>) {
+    if chat_sector.is_changed() {
+        chat_subscription.send_replace(chat_sector.map(sector_chat_tag));
+    }
+}
+
+// Relays are asked for the blocks within the load radius of the indicator
+fn follow_nearby_blocks(
+    settings: Res<Settings>,
+    origin: Res<FloatingOrigin>,
+    block_indicator: Query<&Transform, With<BlockIndicator>>,
+    neighborhood_subscription: Res<NeighborhoodSubscription>,
+    mut last_query: Local<Option<(Sector, i128)>>,
+) {
+    let Ok(indicator_transform) = block_indicator.get_single() else {
+        return;
+    };
+    let indicator = origin.coordinates(indicator_transform.translation);
+    let radius = settings.sector_load_radius as i128 * SECTOR_SIZE;
+    // The cells only change with the indicator's sector
+    if *last_query == Some((indicator.sector(), radius)) {
+        return;
+    }
+    *last_query = Some((indicator.sector(), radius));
+    let cells = morton_cover_within(indicator, radius);
+    neighborhood_subscription.send_if_modified(|current| {
+        if *current == cells {
+            return false;
+        }
+        *current = cells;
+        true
+    });
+}
+
+// Receipts are asked for zaps to us and to the owners of the blocks on screen, the ones
+// that can glow
+fn follow_zapped_keys(
+    time: Res<Time>,
+    user_keys: Res<UserNostrKeys>,
+    blocks: Query<&POWBlock>,
+    zap_subscription: Res<ZapSubscription>,
+    mut last_update: Local<Option<Duration>>,
+) {
+    if last_update.is_some_and(|updated_at| time.elapsed() - updated_at < ZAPPED_KEYS_INTERVAL) {
+        return;
+    }
+    *last_update = Some(time.elapsed());
+    let our_pubkey = user_keys.get_public_key();
+    let mut owners: Vec<String> = blocks
+        .iter()
+        .map(|block| block.owner_pubkey.clone())
+        .filter(|owner| *owner != our_pubkey)
+        .collect::<HashSet<String>>()
+        .into_iter()
+        .collect();
+    owners.sort();
+    owners.truncate(ZAPPED_KEYS_LIMIT - 1);
+    owners.insert(0, our_pubkey);
+    zap_subscription.send_if_modified(|current| {
+        if *current == owners {
+            return false;
+        }
+        *current = owners;
+        true
+    });
+}
+
+// Matches relay OK replies against the blocks we published
+fn confirm_published_blocks(
+    relay_acks: Res<RelayAcks>,
+    mut pending_blocks: ResMut<PendingBlocks>,
+    mut accepted_events: EventWriter<BlockAccepted>,
+    mut rejected_events: EventWriter<BlockRejected>,
+) {
+    relay_acks.try_iter().for_each(|ack| {
+        // Only the first accept and the first reject are reported
+        let Some(block_details) = pending_blocks.answer(&ack.note_id, ack.accepted) else {
+            return;
+        };
+        if ack.accepted {
+            accepted_events.send(BlockAccepted {
+                block_details,
+                relay_url: ack.relay_url,
+            });
+        } else {
+            warn!(target: NOSTR_LOG, "{} rejected block: {}", ack.relay_url, ack.message);
+            rejected_events.send(BlockRejected {
+                block_details,
+                relay_url: ack.relay_url,
+                message: ack.message,
+            });
+        }
+    });
+    let expired = pending_blocks.expire(Instant::now());
+    if expired > 0 {
+        warn!(target: NOSTR_LOG, "No relay answered about {} published blocks", expired);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn block_details(coordinates: &str, pow_amount: usize) -> POWBlockDetails {
+        POWBlockDetails {
+            pow_amount,
+            coordinates: coordinates.to_string(),
+            miner_pubkey: "ab".to_string(),
+            material: None,
+            label: None,
+            owner: None,
+        }
+    }
+
+    #[test]
+    fn pending_blocks_keep_the_newest_note_until_accepted() {
+        let mut pending_blocks = PendingBlocks::default();
+        pending_blocks.publish("first", block_details("aa", 8));
+        pending_blocks.publish("second", block_details("aa", 9));
+        pending_blocks.publish("other", block_details("bb", 8));
+        pending_blocks.publish("refused", block_details("cc", 8));
+        assert!(pending_blocks.answer("first", true).is_none());
+        // Rejects are reported once and wait for another relay to accept
+        assert_eq!(
+            pending_blocks.answer("second", false).unwrap().pow_amount,
+            9
+        );
+        assert!(pending_blocks.answer("second", false).is_none());
+        assert_eq!(pending_blocks.answer("second", true).unwrap().pow_amount, 9);
+        assert!(pending_blocks.answer("second", true).is_none());
+        assert!(pending_blocks.answer("refused", false).is_some());
+        assert_eq!(pending_blocks.expire(Instant::now()), 0);
+        assert_eq!(
+            pending_blocks.expire(Instant::now() + PENDING_BLOCK_TIMEOUT),
+            1
+        );
+        assert!(pending_blocks.is_empty());
+    }
+
+    #[test]
+    fn count_replies_give_the_number_of_blocks() {
+        assert_eq!(
+            parse_count_reply(r#"["COUNT","blocks",{"count":1234}]"#),
+            Some(1234)
+        );
+        assert_eq!(
+            parse_count_reply(r#"["CLOSED","blocks","unsupported"]"#),
+            None
+        );
+        assert_eq!(parse_count_reply("not json"), None);
+    }
+}
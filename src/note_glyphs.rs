@@ -0,0 +1,202 @@
+use std::collections::VecDeque;
+
+use bevy::prelude::*;
+
+use crate::{
+    cameras::BlockIndicator,
+    cyberspace::simhash_coordinates,
+    keybindings::{Action, KeyBindings},
+    nostr::FirehoseSubscription,
+    resources::MeshesAndMaterials,
+};
+
+// Older notes are dropped once this many are kept
+const MAX_NOTE_GLYPHS: usize = 256;
+const GLYPH_SCALE: f32 = 0.3;
+const GLYPH_COLOR: Color = Color::rgb_linear(1.0, 0.9, 0.6);
+// Units between glyphs whose simhashes differ by one bit on an axis
+const GLYPH_SPACING: f32 = 4.0;
+
+pub fn note_glyphs_plugin(app: &mut App) {
+    app.init_resource::<NoteGlyphs>()
+        .add_event::<TextNoteReceived>()
+        .add_systems(Startup, setup_glyph_material)
+        .add_systems(
+            Update,
+            (
+                toggle_note_glyphs,
+                receive_text_notes,
+                show_note_glyphs,
+                follow_indicator,
+            )
+                .chain(),
+        );
+}
+
+// A kind 1 note that isn't sector chat, from anyone on the relays
+#[derive(Event, Debug, Clone, PartialEq)]
+pub struct TextNoteReceived {
+    pub id: String,
+    pub content: String,
+}
+
+struct NoteGlyph {
+    id: String,
+    offset: Vec3,
+    entity: Option<Entity>,
+}
+
+// Where a glyph floats from the indicator
+#[derive(Component)]
+struct GlyphOffset(Vec3);
+
+// Recent text notes placed by the simhash of their content, oldest first
+#[derive(Resource, Default)]
+struct NoteGlyphs {
+    enabled: bool,
+    glyphs: VecDeque<NoteGlyph>,
+}
+
+#[derive(Resource)]
+struct GlyphMaterial(Handle<StandardMaterial>);
+
+fn setup_glyph_material(mut commands: Commands, mut materials: ResMut<Assets<StandardMaterial>>) {
+    let material = materials.add(StandardMaterial {
+        base_color: GLYPH_COLOR,
+        emissive: GLYPH_COLOR * 4.0,
+        ..Default::default()
+    });
+    commands.insert_resource(GlyphMaterial(material));
+}
+
+// The simhash is a coordinate somewhere in all of cyberspace, too far to ever see, so glyphs float
+// around the indicator instead. Each axis counts the set bits in its third of the simhash, notes
+// differing in a few bits get close counts and land close together
+fn glyph_offset(simhash: &str) -> Vec3 {
+    let mut axes = [0.0; 3];
+    for (index, byte) in hex::decode(simhash).unwrap_or_default().iter().enumerate() {
+        // Half of each byte's bits are set on average, the field is centered on that
+        axes[index % 3] += byte.count_ones() as f32 - 4.0;
+    }
+    Vec3::from(axes) * GLYPH_SPACING
+}
+
+fn toggle_note_glyphs(
+    mut commands: Commands,
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    key_bindings: Res<KeyBindings>,
+    firehose_subscription: Res<FirehoseSubscription>,
+    mut note_glyphs: ResMut<NoteGlyphs>,
+) {
+    if !keyboard_input.just_pressed(key_bindings.key(Action::ToggleNoteGlyphs)) {
+        return;
+    }
+    note_glyphs.enabled = !note_glyphs.enabled;
+    firehose_subscription.send_replace(note_glyphs.enabled);
+    if !note_glyphs.enabled {
+        for glyph in note_glyphs.glyphs.drain(..) {
+            if let Some(entity) = glyph.entity {
+                commands.entity(entity).despawn();
+            }
+        }
+    }
+}
+
+fn receive_text_notes(
+    mut commands: Commands,
+    mut text_notes: EventReader<TextNoteReceived>,
+    mut note_glyphs: ResMut<NoteGlyphs>,
+) {
+    if !note_glyphs.enabled {
+        text_notes.clear();
+        return;
+    }
+    for note in text_notes.read() {
+        // Several relays deliver the same note
+        if note_glyphs.glyphs.iter().any(|glyph| glyph.id == note.id) {
+            continue;
+        }
+        note_glyphs.glyphs.push_back(NoteGlyph {
+            id: note.id.clone(),
+            offset: glyph_offset(&simhash_coordinates(&note.content)),
+            entity: None,
+        });
+        if note_glyphs.glyphs.len() > MAX_NOTE_GLYPHS {
+            if let Some(entity) = note_glyphs
+                .glyphs
+                .pop_front()
+                .and_then(|glyph| glyph.entity)
+            {
+                commands.entity(entity).despawn();
+            }
+        }
+    }
+}
+
+fn show_note_glyphs(
+    mut commands: Commands,
+    stuff: Res<MeshesAndMaterials>,
+    glyph_material: Res<GlyphMaterial>,
+    mut note_glyphs: ResMut<NoteGlyphs>,
+) {
+    if !note_glyphs.is_changed() {
+        return;
+    }
+    for glyph in note_glyphs
+        .glyphs
+        .iter_mut()
+        .filter(|glyph| glyph.entity.is_none())
+    {
+        let entity = commands
+            .spawn((
+                PbrBundle {
+                    mesh: stuff.pubkey_mesh.clone_weak(),
+                    material: glyph_material.0.clone_weak(),
+                    transform: Transform::from_scale(Vec3::splat(GLYPH_SCALE)),
+                    ..Default::default()
+                },
+                GlyphOffset(glyph.offset),
+            ))
+            .id();
+        glyph.entity = Some(entity);
+    }
+}
+
+fn follow_indicator(
+    indicator_query: Query<&Transform, (With<BlockIndicator>, Without<GlyphOffset>)>,
+    mut glyph_query: Query<(&mut Transform, &GlyphOffset)>,
+) {
+    let Ok(indicator) = indicator_query.get_single() else {
+        return;
+    };
+    for (mut transform, offset) in glyph_query.iter_mut() {
+        transform.translation = indicator.translation + offset.0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn similar_notes_float_close_together() {
+        let note = glyph_offset(&simhash_coordinates(
+            "gm nostr, building a castle in cyberspace today",
+        ));
+        let similar = glyph_offset(&simhash_coordinates(
+            "gm nostr, building a tower in cyberspace today",
+        ));
+        let unrelated = glyph_offset(&simhash_coordinates(
+            "bitcoin fees are low, time to consolidate utxos",
+        ));
+        assert!(note.distance(similar) < note.distance(unrelated));
+    }
+
+    #[test]
+    fn glyphs_are_centered_on_the_indicator() {
+        // Every axis has half of its bits set
+        assert_eq!(glyph_offset(&"0f".repeat(32)), Vec3::ZERO);
+        let empty = glyph_offset(&"00".repeat(32));
+        assert_eq!(empty, -Vec3::new(44.0, 44.0, 40.0) * GLYPH_SPACING);
+    }
+}
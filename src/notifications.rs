@@ -0,0 +1,328 @@
+use std::{collections::VecDeque, time::Duration};
+
+use bevy::{prelude::*, utils::HashMap};
+
+use crate::{
+    cyberspace::extract_coordinates,
+    mining::BlockOutbid,
+    nostr::{BlockAccepted, BlockRejected},
+    resources::{block_tier, tier_name},
+    settings::Settings,
+    sounds::sound_effect,
+    supervisor::{TaskHealth, TaskStatus, TaskSupervisor},
+    ui_camera::{
+        text_bundle_builder, BORDER_WIDTH, FLEX_GAP, LIGHT_GRAY, MARGIN_UI, NORMAL_FONT, PADDING_UI,
+    },
+};
+
+const TOAST_DURATION: Duration = Duration::from_secs(6);
+// More toasts than this wait in the queue until one expires
+const MAX_TOASTS: usize = 5;
+// A flood of notifications drops the oldest waiting ones past this
+const MAX_QUEUED_TOASTS: usize = 20;
+const NOTIFICATION_SOUND: &str = "sounds/block_mined.wav";
+// Relay sessions report to the supervisor, which is checked this often for lost connections
+const RELAY_HEALTH_INTERVAL: Duration = Duration::from_secs(1);
+const RELAY_TASK_PREFIX: &str = "relay ";
+
+pub fn notifications_plugin(app: &mut App) {
+    app.add_event::<Notification>()
+        .init_resource::<ToastQueue>()
+        .add_systems(Startup, load_notification_sound)
+        .add_systems(PostStartup, setup_toast_container)
+        .add_systems(
+            Update,
+            (
+                notify_block_results,
+                notify_outbid_blocks,
+                notify_relay_health,
+                (queue_notifications, expire_toasts, show_toasts).chain(),
+            ),
+        );
+}
+
+// Anything worth telling the player about without them watching the log
+#[derive(Event, Debug, Clone, PartialEq)]
+pub struct Notification {
+    pub message: String,
+    pub severity: Severity,
+    pub play_sound: bool,
+}
+
+// Picks the toast's border color
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Severity {
+    Info,
+    Success,
+    Warning,
+    Error,
+}
+
+impl Severity {
+    fn color(self) -> Color {
+        match self {
+            Severity::Info => LIGHT_GRAY,
+            Severity::Success => Color::GREEN,
+            Severity::Warning => Color::ORANGE,
+            Severity::Error => Color::RED,
+        }
+    }
+}
+
+// Notifications waiting for room on screen, oldest first
+#[derive(Resource, Default, Deref, DerefMut)]
+struct ToastQueue(VecDeque<Notification>);
+
+impl ToastQueue {
+    fn push(&mut self, notification: Notification) {
+        self.push_back(notification);
+        if self.len() > MAX_QUEUED_TOASTS {
+            self.pop_front();
+        }
+    }
+}
+
+#[derive(Resource, Deref)]
+struct NotificationSound(Handle<AudioSource>);
+
+fn load_notification_sound(mut commands: Commands, asset_server: Res<AssetServer>) {
+    commands.insert_resource(NotificationSound(asset_server.load(NOTIFICATION_SOUND)));
+}
+
+fn notify_block_results(
+    mut accepted_events: EventReader<BlockAccepted>,
+    mut rejected_events: EventReader<BlockRejected>,
+    mut notifications: EventWriter<Notification>,
+) {
+    for accepted in accepted_events.read() {
+        let block = &accepted.block_details;
+        notifications.send(Notification {
+            message: format!(
+                "{} block mined at {}\n{} bits, accepted by {}",
+                tier_name(block_tier(block.pow_amount, block.material)),
+                block.display_coordinates(),
+                block.pow_amount,
+                accepted.relay_url
+            ),
+            severity: Severity::Success,
+            // Chimed by the sounds plugin
+            play_sound: false,
+        });
+    }
+    for rejected in rejected_events.read() {
+        notifications.send(Notification {
+            message: format!(
+                "{} rejected block at {}\n{}",
+                rejected.relay_url,
+                rejected.block_details.display_coordinates(),
+                rejected.message
+            ),
+            severity: Severity::Error,
+            play_sound: false,
+        });
+    }
+}
+
+fn notify_outbid_blocks(
+    mut outbid_events: EventReader<BlockOutbid>,
+    mut notifications: EventWriter<Notification>,
+) {
+    for outbid in outbid_events.read() {
+        let (x, y, z) = extract_coordinates(&outbid.coordinates).unwrap_or((0, 0, 0));
+        notifications.send(Notification {
+            message: format!(
+                "Your block at X:{}, Y: {}, Z: {} was outbid with {} bits",
+                x, y, z, outbid.pow_amount
+            ),
+            severity: Severity::Warning,
+            play_sound: false,
+        });
+    }
+}
+
+// What changed for each relay session since the last check
+fn relay_health_notifications(
+    previous: &HashMap<String, TaskStatus>,
+    tasks: &[(String, TaskHealth)],
+) -> Vec<Notification> {
+    tasks
+        .iter()
+        .filter_map(|(name, health)| {
+            let relay_url = name.strip_prefix(RELAY_TASK_PREFIX)?;
+            let (message, severity) = match (previous.get(name)?, &health.status) {
+                (TaskStatus::Running, TaskStatus::Restarting) => (
+                    format!("Lost connection to {}, reconnecting", relay_url),
+                    Severity::Warning,
+                ),
+                (TaskStatus::Restarting, TaskStatus::Running) => {
+                    (format!("Reconnected to {}", relay_url), Severity::Success)
+                }
+                _ => return None,
+            };
+            Some(Notification {
+                message,
+                severity,
+                play_sound: false,
+            })
+        })
+        .collect()
+}
+
+fn notify_relay_health(
+    time: Res<Time>,
+    supervisor: Res<TaskSupervisor>,
+    mut notifications: EventWriter<Notification>,
+    mut since_check: Local<Duration>,
+    mut previous: Local<HashMap<String, TaskStatus>>,
+) {
+    *since_check += time.delta();
+    if *since_check < RELAY_HEALTH_INTERVAL {
+        return;
+    }
+    *since_check = Duration::ZERO;
+    let tasks = supervisor.snapshot();
+    for notification in relay_health_notifications(&previous, &tasks) {
+        notifications.send(notification);
+    }
+    *previous = tasks
+        .into_iter()
+        .map(|(name, health)| (name, health.status))
+        .collect();
+}
+
+#[derive(Component)]
+struct ToastContainer;
+
+#[derive(Component, Deref, DerefMut)]
+struct Toast(Timer);
+
+fn setup_toast_container(mut commands: Commands) {
+    let toast_container = NodeBundle {
+        style: Style {
+            position_type: PositionType::Absolute,
+            bottom: Val::Px(0.0),
+            right: Val::Px(0.0),
+            margin: MARGIN_UI,
+            row_gap: FLEX_GAP,
+            flex_direction: FlexDirection::ColumnReverse,
+            align_items: AlignItems::FlexEnd,
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    commands.spawn((toast_container, ToastContainer));
+}
+
+fn queue_notifications(
+    mut notifications: EventReader<Notification>,
+    mut toast_queue: ResMut<ToastQueue>,
+) {
+    for notification in notifications.read() {
+        toast_queue.push(notification.clone());
+    }
+}
+
+fn expire_toasts(
+    mut commands: Commands,
+    time: Res<Time>,
+    mut toast_query: Query<(Entity, &mut Toast)>,
+) {
+    for (entity, mut toast) in toast_query.iter_mut() {
+        if toast.tick(time.delta()).finished() {
+            commands.entity(entity).despawn_recursive();
+        }
+    }
+}
+
+// Stacks queued toasts above the older ones while there is room
+fn show_toasts(
+    mut commands: Commands,
+    notification_sound: Res<NotificationSound>,
+    settings: Res<Settings>,
+    mut toast_queue: ResMut<ToastQueue>,
+    container_query: Query<Entity, With<ToastContainer>>,
+    toast_query: Query<&Toast>,
+) {
+    let Ok(container) = container_query.get_single() else {
+        return;
+    };
+    for _ in toast_query.iter().count()..MAX_TOASTS {
+        let Some(notification) = toast_queue.pop_front() else {
+            return;
+        };
+        if notification.play_sound {
+            commands.spawn(AudioBundle {
+                source: notification_sound.clone(),
+                settings: sound_effect(&settings),
+            });
+        }
+        let toast = NodeBundle {
+            style: Style {
+                padding: PADDING_UI,
+                border: BORDER_WIDTH,
+                ..Default::default()
+            },
+            border_color: BorderColor(notification.severity.color()),
+            background_color: BackgroundColor(Color::rgba(0.0, 0.0, 0.0, 0.7)),
+            ..Default::default()
+        };
+        let toast_entity = commands
+            .spawn((toast, Toast(Timer::new(TOAST_DURATION, TimerMode::Once))))
+            .with_children(|toast| {
+                toast.spawn(text_bundle_builder(notification.message, NORMAL_FONT));
+            })
+            .id();
+        commands.entity(container).add_child(toast_entity);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn health(status: TaskStatus) -> TaskHealth {
+        TaskHealth {
+            status,
+            restarts: 0,
+            last_error: None,
+        }
+    }
+
+    #[test]
+    fn queue_drops_the_oldest_past_its_limit() {
+        let mut toast_queue = ToastQueue::default();
+        for index in 0..MAX_QUEUED_TOASTS + 2 {
+            toast_queue.push(Notification {
+                message: index.to_string(),
+                severity: Severity::Info,
+                play_sound: false,
+            });
+        }
+        assert_eq!(toast_queue.len(), MAX_QUEUED_TOASTS);
+        assert_eq!(toast_queue[0].message, "2");
+    }
+
+    #[test]
+    fn relay_sessions_report_lost_and_restored_connections() {
+        let mut previous = HashMap::new();
+        previous.insert("relay wss://a".to_string(), TaskStatus::Running);
+        previous.insert("relay wss://b".to_string(), TaskStatus::Restarting);
+        previous.insert("miner".to_string(), TaskStatus::Running);
+        let tasks = vec![
+            ("relay wss://a".to_string(), health(TaskStatus::Restarting)),
+            ("relay wss://b".to_string(), health(TaskStatus::Running)),
+            ("relay wss://c".to_string(), health(TaskStatus::Running)),
+            ("miner".to_string(), health(TaskStatus::Restarting)),
+        ];
+        let notifications = relay_health_notifications(&previous, &tasks);
+        let severities: Vec<_> = notifications
+            .iter()
+            .map(|notification| notification.severity)
+            .collect();
+        assert_eq!(severities, [Severity::Warning, Severity::Success]);
+        assert_eq!(
+            notifications[0].message,
+            "Lost connection to wss://a, reconnecting"
+        );
+    }
+}
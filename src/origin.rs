@@ -0,0 +1,133 @@
+use bevy::{prelude::*, transform::TransformSystem};
+
+use crate::{
+    cameras::{BlockIndicator, Teleporting},
+    cyberspace::{CyberspaceCoordinates, Plane, Sector},
+};
+
+// The indicator can get this far from the world origin before the origin moves to it
+const RECENTER_DISTANCE: f32 = 4096.0;
+// A multiple of the sector size and of the starfield sway, so neither jumps on a recenter
+const ORIGIN_ALIGNMENT: i128 = 2048;
+
+pub fn origin_plugin(app: &mut App) {
+    app.init_resource::<FloatingOrigin>().add_systems(
+        PostUpdate,
+        (recenter_on_indicator, shift_world)
+            .chain()
+            .before(TransformSystem::TransformPropagate),
+    );
+}
+
+// Coordinates are 85 bits per axis, far more than an f32 holds, so transforms are relative
+// to an origin near the indicator instead of to the middle of cyberspace
+#[derive(Resource, Default)]
+pub struct FloatingOrigin {
+    coordinates: CyberspaceCoordinates,
+    // Origin the transforms were last moved for
+    applied: CyberspaceCoordinates,
+}
+
+impl FloatingOrigin {
+    // World position of a coordinate
+    pub fn position(&self, coordinates: CyberspaceCoordinates) -> Vec3 {
+        coordinates.relative_to(self.coordinates)
+    }
+
+    // Coordinate of the block at a world position
+    pub fn coordinates(&self, position: Vec3) -> CyberspaceCoordinates {
+        self.coordinates.offset(position.round().as_ivec3())
+    }
+
+    // World space box around a sector's blocks
+    pub fn sector_bounds(&self, sector: Sector) -> (Vec3, Vec3) {
+        sector.world_bounds(self.coordinates)
+    }
+
+    pub fn encode(&self, position: Vec3, plane: Plane) -> String {
+        self.coordinates(position).to_hex(plane)
+    }
+
+    // World position of a hex coordinate, none if it isn't one
+    pub fn decode(&self, hex_str: &str) -> Option<Vec3> {
+        let coordinates = CyberspaceCoordinates::from_hex(hex_str).ok()?;
+        Some(self.position(coordinates))
+    }
+
+    // Moves the origin next to a coordinate, transforms follow at the end of the frame
+    pub fn recenter(&mut self, coordinates: CyberspaceCoordinates) {
+        self.coordinates = coordinates.aligned(ORIGIN_ALIGNMENT);
+    }
+
+    // Moves the origin without moving any transform, for when the world is placed from scratch
+    pub fn reset(&mut self, coordinates: CyberspaceCoordinates) {
+        self.recenter(coordinates);
+        self.applied = self.coordinates;
+    }
+}
+
+// Entities that sit on a coordinate, put back on it exactly whenever the origin moves
+#[derive(Component, Deref, DerefMut, Clone, Copy, Debug)]
+pub struct CyberspacePosition(pub CyberspaceCoordinates);
+
+fn recenter_on_indicator(
+    block_indicator: Query<&Transform, (With<BlockIndicator>, Without<Teleporting>)>,
+    mut origin: ResMut<FloatingOrigin>,
+) {
+    let Ok(indicator_transform) = block_indicator.get_single() else {
+        return;
+    };
+    if indicator_transform.translation.abs().max_element() > RECENTER_DISTANCE {
+        let indicator = origin.coordinates(indicator_transform.translation);
+        origin.recenter(indicator);
+    }
+}
+
+// Flights place the indicator themselves, relative to the origin they moved
+fn shift_world(
+    mut origin: ResMut<FloatingOrigin>,
+    mut free_query: Query<
+        &mut Transform,
+        (
+            Without<Parent>,
+            Without<Node>,
+            Without<CyberspacePosition>,
+            Without<Teleporting>,
+        ),
+    >,
+    mut placed_query: Query<(Ref<CyberspacePosition>, &mut Transform)>,
+) {
+    let moved = origin.coordinates != origin.applied;
+    if moved {
+        let shift = origin.coordinates.relative_to(origin.applied);
+        for mut transform in free_query.iter_mut() {
+            transform.translation -= shift;
+        }
+        origin.applied = origin.coordinates;
+    }
+    for (position, mut transform) in placed_query.iter_mut() {
+        if moved || position.is_changed() {
+            transform.translation = origin.position(**position);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn world_positions_round_trip_far_from_zero() {
+        let far = CyberspaceCoordinates::new(1 << 84, (1 << 84) + 5, 3);
+        let mut origin = FloatingOrigin::default();
+        origin.reset(far);
+        let next_to_far = far.offset(IVec3::new(2, -1, 0));
+        let position = origin.position(next_to_far);
+        assert_eq!(origin.coordinates(position), next_to_far);
+        assert_eq!(
+            origin.decode(&origin.encode(position, Plane::ISpace)),
+            Some(position)
+        );
+        assert!(position.length() < ORIGIN_ALIGNMENT as f32 * 2.0);
+    }
+}
@@ -0,0 +1,590 @@
+use bevy::{prelude::*, utils::HashMap, window::PrimaryWindow};
+use nostro2::notes::SignedNote;
+
+use crate::{
+    cameras::{BlockIndicator, ExplorerCamera},
+    chunks::{BlockSlice, WorldChunks},
+    cyberspace::Plane,
+    mining::{toggle_unmined_block, UnminedBlockMap},
+    notifications::{Notification, Severity},
+    origin::FloatingOrigin,
+    protocol::POWBlockDetails,
+    reactions::BlockReactions,
+    resources::{
+        block_tier, format_age, npub, tier_name, unix_now, CoordinatesMap, FollowList, LastSeen,
+        MeshesAndMaterials, Profiles, FRIEND_COLOR,
+    },
+    ui_camera::{
+        text_bundle_builder, BORDER_WIDTH, FLEX_GAP, LIGHT_GRAY, MARGIN_UI, NORMAL_FONT,
+        PADDING_UI, TITLE_FONT,
+    },
+    UserNostrKeys,
+};
+
+// Shift click places next to the block under the cursor, Ctrl click inspects it
+pub const PICK_MODIFIERS: [KeyCode; 4] = [
+    KeyCode::ShiftLeft,
+    KeyCode::ShiftRight,
+    KeyCode::ControlLeft,
+    KeyCode::ControlRight,
+];
+const PLACE_MODIFIERS: [KeyCode; 2] = [KeyCode::ShiftLeft, KeyCode::ShiftRight];
+const INSPECT_MODIFIERS: [KeyCode; 2] = [KeyCode::ControlLeft, KeyCode::ControlRight];
+// Blocks further than this from the camera can't be picked
+const PICK_DISTANCE: f32 = 128.0;
+// Slightly larger than a block so the outline isn't hidden inside its faces
+const OUTLINE_SCALE: f32 = 1.06;
+const OWN_OUTLINE_COLOR: Color = Color::GREEN;
+const FRIEND_OUTLINE_COLOR: Color = FRIEND_COLOR;
+const OTHER_OUTLINE_COLOR: Color = Color::WHITE;
+
+pub fn picking_plugin(app: &mut App) {
+    app.init_resource::<CursorPick>()
+        .init_resource::<InspectedBlock>()
+        .init_resource::<Selection>()
+        .init_resource::<BlockNotes>()
+        .add_event::<BlockNoteReceived>()
+        .add_systems(PostStartup, setup_inspector)
+        .add_systems(
+            Update,
+            (
+                record_block_notes,
+                pick_block_under_cursor,
+                update_selection,
+                (
+                    draw_selection_outline,
+                    pick_interactions,
+                    update_inspector,
+                    copy_inspector_field,
+                ),
+            )
+                .chain(),
+        );
+}
+
+// The signed note behind a block that was just placed in the coordinates map
+#[derive(Event, Debug, Clone)]
+pub struct BlockNoteReceived {
+    pub coordinates: String,
+    pub note: SignedNote,
+}
+
+// Note of the block currently at each coordinate string, for the inspector and zaps
+#[derive(Resource, Default, Deref, DerefMut)]
+pub struct BlockNotes(HashMap<String, SignedNote>);
+
+impl BlockNotes {
+    // Coordinate string of the block a note id claimed
+    pub fn coordinates_of(&self, note_id: &str) -> Option<&String> {
+        self.iter()
+            .find(|(_, note)| note.get_id() == note_id)
+            .map(|(coordinates, _)| coordinates)
+    }
+}
+
+// The mined block under the cursor and the empty cell in front of the face the cursor is on
+#[derive(Resource, Default, Debug, Clone, Copy, PartialEq)]
+pub struct CursorPick {
+    pub block: Option<IVec3>,
+    pub adjacent: Option<IVec3>,
+}
+
+// Coordinate string of the block shown in the inspector
+#[derive(Resource, Default, Deref, DerefMut)]
+pub struct InspectedBlock(pub Option<String>);
+
+// The mined block being pointed at, by the cursor or else by the indicator standing in it
+#[derive(Resource, Default, Debug, Clone, PartialEq)]
+pub struct Selection {
+    pub block: Option<IVec3>,
+    pub owner: Option<String>,
+}
+
+#[derive(Component)]
+struct InspectorPanel;
+
+#[derive(Component)]
+struct InspectorText;
+
+// Everything the inspector lists for a block, each with its own copy button
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum InspectorField {
+    Coordinates,
+    Plane,
+    Hex,
+    Pubkey,
+    Npub,
+    Pow,
+    NoteId,
+    CreatedAt,
+    RawEvent,
+}
+
+impl InspectorField {
+    const ALL: [InspectorField; 9] = [
+        InspectorField::Coordinates,
+        InspectorField::Plane,
+        InspectorField::Hex,
+        InspectorField::Pubkey,
+        InspectorField::Npub,
+        InspectorField::Pow,
+        InspectorField::NoteId,
+        InspectorField::CreatedAt,
+        InspectorField::RawEvent,
+    ];
+
+    fn label(&self) -> &'static str {
+        match self {
+            InspectorField::Coordinates => "Coordinates",
+            InspectorField::Plane => "Plane",
+            InspectorField::Hex => "Hex",
+            InspectorField::Pubkey => "Miner",
+            InspectorField::Npub => "Npub",
+            InspectorField::Pow => "POW",
+            InspectorField::NoteId => "Note id",
+            InspectorField::CreatedAt => "Created at",
+            InspectorField::RawEvent => "Event",
+        }
+    }
+
+    // The note is missing for blocks that never came from a relay, like the sample ones
+    fn value(&self, block_details: &POWBlockDetails, note: Option<&SignedNote>) -> String {
+        let unknown = || "unknown".to_string();
+        match self {
+            InspectorField::Coordinates => block_details.display_coordinates(),
+            InspectorField::Plane => block_details.plane().name().to_string(),
+            InspectorField::Hex => block_details.coordinates.clone(),
+            InspectorField::Pubkey => block_details.miner_pubkey.clone(),
+            InspectorField::Npub => npub(&block_details.miner_pubkey).unwrap_or_else(unknown),
+            InspectorField::Pow => format!("{} bits", block_details.pow_amount),
+            InspectorField::NoteId => note.map_or_else(unknown, |note| note.get_id().to_string()),
+            InspectorField::CreatedAt => note.map_or_else(unknown, |note| {
+                let created_at = note.get_created_at();
+                format!(
+                    "{} ({})",
+                    created_at,
+                    format_age(unix_now().saturating_sub(created_at))
+                )
+            }),
+            InspectorField::RawEvent => note
+                .and_then(|note| serde_json::to_string_pretty(note).ok())
+                .unwrap_or_else(unknown),
+        }
+    }
+}
+
+#[derive(Component)]
+struct InspectorValue(InspectorField);
+
+#[derive(Component)]
+struct CopyButton(InspectorField);
+
+pub fn voxel_coordinates(voxel: IVec3, origin: &FloatingOrigin, plane: Plane) -> String {
+    origin.encode(voxel.as_vec3(), plane)
+}
+
+// Walks the voxels along a ray, blocks are unit cubes centered on integer coordinates.
+// Returns the first occupied voxel and the one the ray was in just before it.
+pub fn raycast_voxels(
+    origin: Vec3,
+    direction: Vec3,
+    max_distance: f32,
+    occupied: impl Fn(IVec3) -> bool,
+) -> Option<(IVec3, IVec3)> {
+    let direction = direction.normalize_or_zero();
+    if direction == Vec3::ZERO {
+        return None;
+    }
+    let start = origin + Vec3::splat(0.5);
+    let mut voxel = start.floor().as_ivec3();
+    let step = direction.signum().as_ivec3();
+    // Distance along the ray to cross one voxel on each axis, infinite along axes it doesn't move on
+    let delta = (Vec3::ONE / direction).abs();
+    // Distance along the ray to the first voxel boundary on each axis
+    let mut next = Vec3::select(
+        direction.cmpge(Vec3::ZERO),
+        (voxel.as_vec3() + Vec3::ONE - start) * delta,
+        (start - voxel.as_vec3()) * delta,
+    );
+    let mut previous = voxel;
+    let mut travelled = 0.0;
+    while travelled <= max_distance {
+        if occupied(voxel) {
+            return Some((voxel, previous));
+        }
+        previous = voxel;
+        if next.x <= next.y && next.x <= next.z {
+            travelled = next.x;
+            next.x += delta.x;
+            voxel.x += step.x;
+        } else if next.y <= next.z {
+            travelled = next.y;
+            next.y += delta.y;
+            voxel.y += step.y;
+        } else {
+            travelled = next.z;
+            next.z += delta.z;
+            voxel.z += step.z;
+        }
+    }
+    None
+}
+
+fn record_block_notes(
+    mut block_notes_received: EventReader<BlockNoteReceived>,
+    mut block_notes: ResMut<BlockNotes>,
+) {
+    for received in block_notes_received.read() {
+        block_notes.insert(received.coordinates.clone(), received.note.clone());
+    }
+}
+
+fn pick_block_under_cursor(
+    window_query: Query<&Window, With<PrimaryWindow>>,
+    camera_query: Query<(&Camera, &GlobalTransform), With<ExplorerCamera>>,
+    origin: Res<FloatingOrigin>,
+    world_chunks: Res<WorldChunks>,
+    block_slice: Res<BlockSlice>,
+    mut cursor_pick: ResMut<CursorPick>,
+) {
+    let ray = window_query
+        .get_single()
+        .ok()
+        .and_then(|window| window.cursor_position())
+        .zip(camera_query.get_single().ok())
+        .and_then(|(cursor, (camera, camera_transform))| {
+            camera.viewport_to_world(camera_transform, cursor)
+        });
+    let hit = ray.and_then(|ray| {
+        // Blocks sliced away are clicked through
+        raycast_voxels(ray.origin, *ray.direction, PICK_DISTANCE, |voxel| {
+            let coordinates = origin.coordinates(voxel.as_vec3());
+            block_slice.shows(coordinates) && world_chunks.block_at(coordinates).is_some()
+        })
+    });
+    let pick = CursorPick {
+        block: hit.map(|(block, _)| block),
+        adjacent: hit.map(|(_, adjacent)| adjacent),
+    };
+    if *cursor_pick != pick {
+        *cursor_pick = pick;
+    }
+}
+
+fn update_selection(
+    cursor_pick: Res<CursorPick>,
+    coordinates_map: Res<CoordinatesMap>,
+    origin: Res<FloatingOrigin>,
+    world_chunks: Res<WorldChunks>,
+    block_indicator: Query<&Transform, With<BlockIndicator>>,
+    mut selection: ResMut<Selection>,
+) {
+    let indicator_block = block_indicator
+        .get_single()
+        .ok()
+        .map(|transform| transform.translation.round().as_ivec3());
+    let owned_block = |block: IVec3| {
+        coordinates_map
+            .get(&voxel_coordinates(block, &origin, world_chunks.plane()))
+            .map(|(_, block_details)| (block, block_details.owner().to_string()))
+    };
+    let selected = cursor_pick
+        .block
+        .and_then(owned_block)
+        .or_else(|| indicator_block.and_then(owned_block));
+    let wanted = Selection {
+        block: selected.as_ref().map(|(block, _)| *block),
+        owner: selected.map(|(_, owner)| owner),
+    };
+    if *selection != wanted {
+        *selection = wanted;
+    }
+}
+
+fn ownership_color(owner: &str, our_pubkey: &str, follow_list: &FollowList) -> Color {
+    if owner == our_pubkey {
+        OWN_OUTLINE_COLOR
+    } else if follow_list.contains(owner) {
+        FRIEND_OUTLINE_COLOR
+    } else {
+        OTHER_OUTLINE_COLOR
+    }
+}
+
+// Gizmos are drawn in their own pass over the scene, every frame
+fn draw_selection_outline(
+    selection: Res<Selection>,
+    user_keys: Res<UserNostrKeys>,
+    follow_list: Res<FollowList>,
+    mut gizmos: Gizmos,
+) {
+    let (Some(block), Some(owner)) = (selection.block, selection.owner.as_ref()) else {
+        return;
+    };
+    gizmos.cuboid(
+        Transform::from_translation(block.as_vec3()).with_scale(Vec3::splat(OUTLINE_SCALE)),
+        ownership_color(owner, &user_keys.get_public_key(), &follow_list),
+    );
+}
+
+fn pick_interactions(
+    mut commands: Commands,
+    stuff: Res<MeshesAndMaterials>,
+    mouse_input: Res<ButtonInput<MouseButton>>,
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    cursor_pick: Res<CursorPick>,
+    origin: Res<FloatingOrigin>,
+    world_chunks: Res<WorldChunks>,
+    interaction_query: Query<&Interaction>,
+    mut unmined_block_map: ResMut<UnminedBlockMap>,
+    mut inspected_block: ResMut<InspectedBlock>,
+) {
+    if !mouse_input.just_pressed(MouseButton::Left)
+        || interaction_query
+            .iter()
+            .any(|interaction| *interaction != Interaction::None)
+    {
+        return;
+    }
+    if keyboard_input.any_pressed(PLACE_MODIFIERS) {
+        if let Some(adjacent) = cursor_pick.adjacent {
+            toggle_unmined_block(
+                &mut commands,
+                &stuff,
+                &mut unmined_block_map,
+                origin.coordinates(adjacent.as_vec3()),
+                world_chunks.plane(),
+            );
+        }
+    } else if keyboard_input.any_pressed(INSPECT_MODIFIERS) {
+        // Clicking empty space closes the inspector
+        **inspected_block = cursor_pick
+            .block
+            .map(|block| voxel_coordinates(block, &origin, world_chunks.plane()));
+    }
+}
+
+fn setup_inspector(mut commands: Commands) {
+    let inspector_ui = NodeBundle {
+        style: Style {
+            position_type: PositionType::Absolute,
+            top: Val::Percent(25.0),
+            right: Val::Px(0.0),
+            max_width: Val::Percent(30.0),
+            margin: MARGIN_UI,
+            padding: PADDING_UI,
+            row_gap: FLEX_GAP,
+            flex_direction: FlexDirection::Column,
+            border: BORDER_WIDTH,
+            ..Default::default()
+        },
+        border_color: BorderColor(LIGHT_GRAY),
+        background_color: BackgroundColor(Color::rgba(0.0, 0.0, 0.0, 0.7)),
+        visibility: Visibility::Hidden,
+        ..Default::default()
+    };
+    commands
+        .spawn((inspector_ui, InspectorPanel))
+        .with_children(|inspector_ui| {
+            inspector_ui.spawn(text_bundle_builder("Inspector".to_string(), TITLE_FONT));
+            inspector_ui.spawn((
+                text_bundle_builder(String::new(), NORMAL_FONT),
+                InspectorText,
+            ));
+            for field in InspectorField::ALL {
+                inspector_ui.spawn(field_row()).with_children(|row| {
+                    let copy = text_bundle_builder("[copy]".to_string(), NORMAL_FONT);
+                    row.spawn((copy, Interaction::default(), CopyButton(field)));
+                    let value = text_bundle_builder(String::new(), NORMAL_FONT);
+                    row.spawn((value, InspectorValue(field)));
+                });
+            }
+        });
+}
+
+fn field_row() -> NodeBundle {
+    NodeBundle {
+        style: Style {
+            column_gap: FLEX_GAP,
+            align_items: AlignItems::FlexStart,
+            ..Default::default()
+        },
+        ..Default::default()
+    }
+}
+
+fn update_inspector(
+    inspected_block: Res<InspectedBlock>,
+    coordinates_map: Res<CoordinatesMap>,
+    block_notes: Res<BlockNotes>,
+    block_reactions: Res<BlockReactions>,
+    profiles: Res<Profiles>,
+    last_seen: Res<LastSeen>,
+    mut panel_query: Query<&mut Visibility, With<InspectorPanel>>,
+    mut text_query: Query<&mut Text, (With<InspectorText>, Without<InspectorValue>)>,
+    mut value_query: Query<(&mut Text, &InspectorValue), Without<InspectorText>>,
+) {
+    let block_details = inspected_block
+        .as_ref()
+        .and_then(|coordinates| coordinates_map.get(coordinates))
+        .map(|(_, block_details)| block_details);
+    let wanted = match block_details {
+        Some(_) => Visibility::Inherited,
+        None => Visibility::Hidden,
+    };
+    for mut visibility in panel_query.iter_mut() {
+        if *visibility != wanted {
+            *visibility = wanted;
+        }
+    }
+    let Some(block_details) = block_details else {
+        return;
+    };
+    let mut inspector_text = format!(
+        "Owner: {}, {}\n{} block",
+        profiles.display_name(block_details.owner()),
+        last_seen.describe(block_details.owner()),
+        tier_name(block_tier(block_details.pow_amount, block_details.material))
+    );
+    if let Some(label) = block_details.display_label() {
+        inspector_text.push_str(&format!("\nLabel: {}", label));
+    }
+    let note = block_details_note(&block_notes, block_details);
+    if let Some(note) = note {
+        let (likes, dislikes) = block_reactions.counts(note.get_id());
+        inspector_text.push_str(&format!("\nReactions: {} likes", likes));
+        if dislikes > 0 {
+            inspector_text.push_str(&format!(", {} dislikes", dislikes));
+        }
+    }
+    for mut text in text_query.iter_mut() {
+        if text.sections[0].value != inspector_text {
+            text.sections[0].value = inspector_text.clone();
+        }
+    }
+    for (mut text, field) in value_query.iter_mut() {
+        let value = format!(
+            "{}: {}",
+            field.0.label(),
+            field.0.value(block_details, note)
+        );
+        if text.sections[0].value != value {
+            text.sections[0].value = value;
+        }
+    }
+}
+
+// A note left behind by a derezzed block is only used if it still claims the same block
+pub fn block_details_note<'a>(
+    block_notes: &'a BlockNotes,
+    block_details: &POWBlockDetails,
+) -> Option<&'a SignedNote> {
+    block_notes
+        .get(&block_details.coordinates)
+        .filter(|note| note.get_pubkey() == block_details.miner_pubkey)
+}
+
+fn copy_inspector_field(
+    inspected_block: Res<InspectedBlock>,
+    coordinates_map: Res<CoordinatesMap>,
+    block_notes: Res<BlockNotes>,
+    copy_query: Query<(&Interaction, &CopyButton), Changed<Interaction>>,
+    mut notifications: EventWriter<Notification>,
+) {
+    let Some((_, block_details)) = inspected_block
+        .as_ref()
+        .and_then(|coordinates| coordinates_map.get(coordinates))
+    else {
+        return;
+    };
+    for (interaction, copy_button) in copy_query.iter() {
+        if *interaction != Interaction::Pressed {
+            continue;
+        }
+        let field = copy_button.0;
+        let value = field.value(
+            block_details,
+            block_details_note(&block_notes, block_details),
+        );
+        let copied = arboard::Clipboard::new().and_then(|mut clipboard| clipboard.set_text(value));
+        let (message, severity) = match copied {
+            Ok(()) => (format!("Copied {}", field.label()), Severity::Info),
+            Err(error) => (
+                format!("Could not copy {}: {}", field.label(), error),
+                Severity::Error,
+            ),
+        };
+        notifications.send(Notification {
+            message,
+            severity,
+            play_sound: false,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ray_stops_at_the_first_block_and_keeps_the_face_in_front() {
+        let block = IVec3::new(5, 0, 0);
+        let hit = raycast_voxels(Vec3::ZERO, Vec3::X, 32.0, |voxel| voxel == block);
+        assert_eq!(hit, Some((block, IVec3::new(4, 0, 0))));
+        // Coming from above lands on the top face
+        let hit = raycast_voxels(Vec3::new(5.2, 10.0, 0.1), Vec3::NEG_Y, 32.0, |voxel| {
+            voxel == block
+        });
+        assert_eq!(hit, Some((block, IVec3::new(5, 1, 0))));
+    }
+
+    #[test]
+    fn outlines_are_tinted_by_ownership() {
+        let mut follow_list = FollowList::default();
+        follow_list.insert("friend".to_string());
+        assert_eq!(ownership_color("me", "me", &follow_list), OWN_OUTLINE_COLOR);
+        assert_eq!(
+            ownership_color("friend", "me", &follow_list),
+            FRIEND_OUTLINE_COLOR
+        );
+        assert_eq!(
+            ownership_color("stranger", "me", &follow_list),
+            OTHER_OUTLINE_COLOR
+        );
+    }
+
+    #[test]
+    fn inspector_fields_without_a_note() {
+        let block_details = POWBlockDetails {
+            pow_amount: 21,
+            coordinates: "ab".repeat(32),
+            miner_pubkey: "0".repeat(64),
+            material: None,
+            label: None,
+            owner: None,
+        };
+        assert_eq!(
+            InspectorField::Hex.value(&block_details, None),
+            "ab".repeat(32)
+        );
+        assert_eq!(InspectorField::Pow.value(&block_details, None), "21 bits");
+        assert_eq!(InspectorField::Plane.value(&block_details, None), "i-space");
+        assert!(InspectorField::Npub
+            .value(&block_details, None)
+            .starts_with("npub1"));
+        assert_eq!(
+            InspectorField::NoteId.value(&block_details, None),
+            "unknown"
+        );
+        assert_eq!(
+            InspectorField::RawEvent.value(&block_details, None),
+            "unknown"
+        );
+    }
+
+    #[test]
+    fn ray_misses_beyond_its_range() {
+        let hit = raycast_voxels(Vec3::ZERO, Vec3::Z, 4.0, |voxel| voxel.z == 10);
+        assert_eq!(hit, None);
+    }
+}
@@ -0,0 +1,226 @@
+use bevy::prelude::*;
+
+use crate::{
+    cameras::{BlockIndicator, ExplorerCamera},
+    chunks::WorldChunks,
+    mining::{MiningQueue, UnminedBlockMap},
+    origin::FloatingOrigin,
+    resources::{CoordinatesMap, MeshesAndMaterials},
+    UserNostrKeys,
+};
+
+// A bit larger than a block so it shows around one already there
+const GHOST_SCALE: f32 = 1.06;
+const EMPTY_GHOST_COLOR: Color = Color::rgba(0.3, 0.9, 1.0, 0.25);
+const PLACED_GHOST_COLOR: Color = Color::rgba(1.0, 0.85, 0.1, 0.3);
+// Same green as the outline of our blocks
+const OURS_GHOST_COLOR: Color = Color::rgba(0.2, 1.0, 0.3, 0.3);
+const OTHERS_GHOST_COLOR: Color = Color::rgba(1.0, 0.15, 0.1, 0.3);
+const CROSSHAIR_SIZE: f32 = 12.0;
+
+pub fn placement_plugin(app: &mut App) {
+    app.add_systems(PostStartup, (setup_placement_ghost, setup_crosshair))
+        .add_systems(Update, (update_placement_ghost, position_crosshair).chain());
+}
+
+// What a click would do at the coordinate under the indicator
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum PlacementState {
+    // Places a new block
+    Empty,
+    // Removes the placed block, or it's already waiting in the mining queue
+    Placed,
+    // One of our mined blocks, a new one has to beat its POW
+    Ours,
+    // Someone else's mined block
+    Others,
+}
+
+fn placement_state(
+    placed: bool,
+    queued: bool,
+    owner: Option<&str>,
+    our_pubkey: &str,
+) -> PlacementState {
+    if placed || queued {
+        return PlacementState::Placed;
+    }
+    match owner {
+        Some(owner) if owner == our_pubkey => PlacementState::Ours,
+        Some(_) => PlacementState::Others,
+        None => PlacementState::Empty,
+    }
+}
+
+#[derive(Resource)]
+struct GhostMaterials {
+    empty: Handle<StandardMaterial>,
+    placed: Handle<StandardMaterial>,
+    ours: Handle<StandardMaterial>,
+    others: Handle<StandardMaterial>,
+}
+
+impl GhostMaterials {
+    fn get(&self, state: PlacementState) -> &Handle<StandardMaterial> {
+        match state {
+            PlacementState::Empty => &self.empty,
+            PlacementState::Placed => &self.placed,
+            PlacementState::Ours => &self.ours,
+            PlacementState::Others => &self.others,
+        }
+    }
+}
+
+#[derive(Component)]
+struct PlacementGhost;
+
+#[derive(Component)]
+struct Crosshair;
+
+fn setup_placement_ghost(
+    mut commands: Commands,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    stuff: Res<MeshesAndMaterials>,
+) {
+    let mut ghost_material = |color: Color| {
+        materials.add(StandardMaterial {
+            base_color: color,
+            alpha_mode: AlphaMode::Blend,
+            unlit: true,
+            ..Default::default()
+        })
+    };
+    let ghost_materials = GhostMaterials {
+        empty: ghost_material(EMPTY_GHOST_COLOR),
+        placed: ghost_material(PLACED_GHOST_COLOR),
+        ours: ghost_material(OURS_GHOST_COLOR),
+        others: ghost_material(OTHERS_GHOST_COLOR),
+    };
+    commands.spawn((
+        PbrBundle {
+            mesh: stuff.cube_mesh.clone_weak(),
+            material: ghost_materials.empty.clone(),
+            transform: Transform::from_scale(Vec3::splat(GHOST_SCALE)),
+            ..Default::default()
+        },
+        PlacementGhost,
+    ));
+    commands.insert_resource(ghost_materials);
+}
+
+fn setup_crosshair(mut commands: Commands) {
+    commands.spawn((
+        NodeBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                width: Val::Px(CROSSHAIR_SIZE),
+                height: Val::Px(CROSSHAIR_SIZE),
+                border: UiRect::all(Val::Px(2.0)),
+                ..Default::default()
+            },
+            border_color: BorderColor(Color::WHITE),
+            visibility: Visibility::Hidden,
+            ..Default::default()
+        },
+        Crosshair,
+    ));
+}
+
+// Snaps to the coordinate a click would place on, like the block it would make
+fn update_placement_ghost(
+    indicator_query: Query<&Transform, (With<BlockIndicator>, Without<PlacementGhost>)>,
+    mut ghost_query: Query<(&mut Transform, &mut Handle<StandardMaterial>), With<PlacementGhost>>,
+    ghost_materials: Res<GhostMaterials>,
+    unmined_block_map: Res<UnminedBlockMap>,
+    mining_queue: Res<MiningQueue>,
+    coordinates_map: Res<CoordinatesMap>,
+    origin: Res<FloatingOrigin>,
+    world_chunks: Res<WorldChunks>,
+    user_keys: Res<UserNostrKeys>,
+) {
+    let Ok(indicator) = indicator_query.get_single() else {
+        return;
+    };
+    let position = indicator.translation.round();
+    let coordinates = origin.encode(position, world_chunks.plane());
+    let state = placement_state(
+        unmined_block_map.contains_key(&coordinates),
+        mining_queue
+            .blocks
+            .iter()
+            .any(|block| block.coordinates == coordinates),
+        coordinates_map
+            .get(&coordinates)
+            .map(|(_, block_details)| block_details.owner()),
+        &user_keys.get_public_key(),
+    );
+    let material = ghost_materials.get(state);
+    for (mut transform, mut handle) in ghost_query.iter_mut() {
+        if transform.translation != position {
+            transform.translation = position;
+        }
+        if *handle != *material {
+            *handle = material.clone();
+        }
+    }
+}
+
+fn position_crosshair(
+    camera_query: Query<(&Camera, &GlobalTransform), With<ExplorerCamera>>,
+    ghost_query: Query<&Transform, With<PlacementGhost>>,
+    ui_scale: Res<UiScale>,
+    mut crosshair_query: Query<(&mut Style, &mut Visibility), With<Crosshair>>,
+) {
+    let (Ok((camera, camera_transform)), Ok(ghost)) =
+        (camera_query.get_single(), ghost_query.get_single())
+    else {
+        return;
+    };
+    let viewport_position = camera.world_to_viewport(camera_transform, ghost.translation);
+    for (mut style, mut visibility) in crosshair_query.iter_mut() {
+        let wanted = match viewport_position {
+            Some(viewport_position) => {
+                // UI pixels are multiplied by the UI scale, viewport ones aren't
+                let center = viewport_position / ui_scale.0;
+                style.left = Val::Px(center.x - CROSSHAIR_SIZE / 2.0);
+                style.top = Val::Px(center.y - CROSSHAIR_SIZE / 2.0);
+                Visibility::Inherited
+            }
+            None => Visibility::Hidden,
+        };
+        if *visibility != wanted {
+            *visibility = wanted;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ghost_state_prefers_what_a_click_would_touch() {
+        let ours = "a".repeat(64);
+        let theirs = "b".repeat(64);
+        assert_eq!(
+            placement_state(false, false, None, &ours),
+            PlacementState::Empty
+        );
+        assert_eq!(
+            placement_state(true, false, Some(&theirs), &ours),
+            PlacementState::Placed
+        );
+        assert_eq!(
+            placement_state(false, true, Some(&ours), &ours),
+            PlacementState::Placed
+        );
+        assert_eq!(
+            placement_state(false, false, Some(&ours), &ours),
+            PlacementState::Ours
+        );
+        assert_eq!(
+            placement_state(false, false, Some(&theirs), &ours),
+            PlacementState::Others
+        );
+    }
+}
@@ -0,0 +1,626 @@
+use std::sync::Arc;
+
+use bevy::{ecs::system::SystemParam, prelude::*};
+use bevy_tokio_tasks::TokioTasksRuntime;
+use crossbeam_channel::{bounded, Receiver, Sender};
+use nostro2::notes::{Note, SignedNote};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+    channels::{send_when_ready, MINED_NOTES_CAPACITY},
+    keybindings::{Action, KeyBindings},
+    logging::MINING_LOG,
+    mining::{
+        nonce_tag, note_difficulty, HashCounter, HashMeter, MiningIntensity, MiningQueue,
+        NonceSplit, POWNotesWriter, MINER_MAX_RESTARTS,
+    },
+    nostr::OutgoingNotes,
+    protocol::{sector_cell_tags, POWBlockDetails, POOL_KIND, POW_BLOCK_KIND},
+    supervisor::{RestartPolicy, TaskSupervisor},
+    ui_camera::{
+        text_bundle_builder, BORDER_WIDTH, FLEX_GAP, LIGHT_GRAY, MARGIN_UI, NORMAL_FONT,
+        PADDING_UI, TITLE_FONT,
+    },
+    UserNostrKeys,
+};
+
+// Only the most recent announcements are offered to join
+const MAX_ANNOUNCEMENTS: usize = 8;
+
+pub fn pool_plugin(app: &mut App) {
+    // Local pool miners report winning nonces through this channel
+    let (solutions_writer, solutions_reader) = bounded::<String>(MINED_NOTES_CAPACITY);
+
+    app.init_resource::<MiningPool>()
+        .add_event::<PoolMessageReceived>()
+        .insert_resource(PoolSolutions(solutions_reader))
+        .insert_resource(PoolSolutionsWriter(solutions_writer))
+        .add_systems(PostStartup, setup_pool_ui)
+        .add_systems(
+            Update,
+            (
+                pool_controls,
+                handle_pool_messages,
+                collect_pool_solutions,
+                update_pool_ui,
+            ),
+        );
+}
+
+// Content of an ephemeral pool note. Everyone in a pool grinds the host's block note,
+// each member on its own slice of the nonce space, and the host signs the winner.
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum PoolMessage {
+    /// The host offers a coordinate and target for others to join
+    Announce {
+        pool_id: String,
+        coordinates: String,
+        target: usize,
+    },
+    /// A member asks the host to be included
+    Join { pool_id: String },
+    /// The host fixes the block note and the member order, member `i` takes nonces `i mod n`
+    Start {
+        pool_id: String,
+        template: PoolTemplate,
+        members: Vec<String>,
+    },
+    /// A member found a nonce meeting the target
+    Solution { pool_id: String, nonce: String },
+    /// The host published the block or closed the pool
+    Finish { pool_id: String },
+}
+
+impl PoolMessage {
+    pub fn pool_id(&self) -> &str {
+        match self {
+            PoolMessage::Announce { pool_id, .. }
+            | PoolMessage::Join { pool_id }
+            | PoolMessage::Start { pool_id, .. }
+            | PoolMessage::Solution { pool_id, .. }
+            | PoolMessage::Finish { pool_id } => pool_id,
+        }
+    }
+}
+
+/// The parts of the host's kind 333 note every member must hash identically
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, PartialEq)]
+pub struct PoolTemplate {
+    pub created_at: u64,
+    pub content: String,
+    /// Contributor `p` tags and the sector cell tags, the nonce tag is appended while mining
+    pub tags: Vec<Vec<String>>,
+}
+
+impl PoolTemplate {
+    fn note(&self, host: &str) -> Note {
+        let mut note = Note::new(host.to_string(), POW_BLOCK_KIND, &self.content);
+        note.created_at = self.created_at;
+        note.tags = self.tags.clone();
+        note
+    }
+
+    // The host's block note with a nonce, if it meets the target
+    fn solved_note(&self, host: &str, nonce: &str, target: usize) -> Option<Note> {
+        let mut note = self.note(host);
+        note.tags.push(nonce_tag(nonce, target));
+        (note_difficulty(&note) >= target).then_some(note)
+    }
+}
+
+// Sent by the websocket middleware for every pool note from someone else
+#[derive(Event)]
+pub struct PoolMessageReceived {
+    pub author: String,
+    pub message: PoolMessage,
+}
+
+#[derive(Clone, Debug)]
+pub struct PoolAnnouncement {
+    pub pool_id: String,
+    pub host: String,
+    pub coordinates: String,
+    pub target: usize,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PoolRole {
+    Host,
+    Member,
+}
+
+pub struct ActivePool {
+    pub pool_id: String,
+    pub role: PoolRole,
+    pub host: String,
+    pub coordinates: String,
+    pub target: usize,
+    pub members: Vec<String>,
+    pub template: Option<PoolTemplate>,
+    token: Option<CancellationToken>,
+}
+
+impl ActivePool {
+    fn stop(&mut self) {
+        if let Some(token) = self.token.take() {
+            token.cancel();
+        }
+    }
+}
+
+#[derive(Resource, Default)]
+pub struct MiningPool {
+    pub active: Option<ActivePool>,
+    pub announcements: Vec<PoolAnnouncement>,
+}
+
+#[derive(Resource, Deref)]
+struct PoolSolutions(Receiver<String>);
+
+#[derive(Resource, Deref)]
+struct PoolSolutionsWriter(Sender<String>);
+
+// The queue is emptied by a system too, so a system waiting for room would never get it
+fn queue_solved_block(pow_notes_writer: &POWNotesWriter, note: SignedNote) {
+    if pow_notes_writer.try_send(note).is_err() {
+        warn!(target: MINING_LOG, "Mined notes queue is full, dropped the pool's block");
+    }
+}
+
+fn send_pool_message(
+    user_keys: &UserNostrKeys,
+    outgoing_notes: &OutgoingNotes,
+    message: &PoolMessage,
+) {
+    let note = Note::new(
+        user_keys.get_public_key(),
+        POOL_KIND,
+        &json!(message).to_string(),
+    );
+    let _sent = outgoing_notes.send(user_keys.get_keypair().sign_nostr_event(note));
+}
+
+fn pool_controls(
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    key_bindings: Res<KeyBindings>,
+    mut mining_pool: ResMut<MiningPool>,
+    mining_queue: Res<MiningQueue>,
+    user_keys: Res<UserNostrKeys>,
+    outgoing_notes: Res<OutgoingNotes>,
+    pool_miners: PoolMiners,
+) {
+    let our_pubkey = user_keys.get_public_key();
+
+    if keyboard_input.just_pressed(key_bindings.key(Action::HostPool)) {
+        match mining_pool.active.as_mut() {
+            // Host a pool for the selected block in the mining queue
+            None => {
+                let Some(block) = mining_queue.blocks.get(mining_queue.selected) else {
+                    return;
+                };
+                let pool_id = format!("{:016x}", rand::random::<u64>());
+                send_pool_message(
+                    &user_keys,
+                    &outgoing_notes,
+                    &PoolMessage::Announce {
+                        pool_id: pool_id.clone(),
+                        coordinates: block.coordinates.clone(),
+                        target: block.target(),
+                    },
+                );
+                mining_pool.active = Some(ActivePool {
+                    pool_id,
+                    role: PoolRole::Host,
+                    host: our_pubkey.clone(),
+                    coordinates: block.coordinates.clone(),
+                    target: block.target(),
+                    members: vec![our_pubkey],
+                    template: None,
+                    token: None,
+                });
+            }
+            // Pressing it again fixes the members and starts mining
+            Some(pool) if pool.role == PoolRole::Host && pool.template.is_none() => {
+                let block_details = POWBlockDetails {
+                    pow_amount: pool.target,
+                    coordinates: pool.coordinates.clone(),
+                    miner_pubkey: pool.host.clone(),
+                    material: None,
+                    label: None,
+                    owner: None,
+                };
+                let mut note = Note::new(
+                    pool.host.clone(),
+                    POW_BLOCK_KIND,
+                    &json!(block_details).to_string(),
+                );
+                note.tags = pool
+                    .members
+                    .iter()
+                    .map(|member| vec!["p".to_string(), member.clone(), "pool".to_string()])
+                    .chain(sector_cell_tags(&pool.coordinates))
+                    .collect();
+                let template = PoolTemplate {
+                    created_at: note.created_at,
+                    content: note.content.clone(),
+                    tags: note.tags.clone(),
+                };
+                send_pool_message(
+                    &user_keys,
+                    &outgoing_notes,
+                    &PoolMessage::Start {
+                        pool_id: pool.pool_id.clone(),
+                        template: template.clone(),
+                        members: pool.members.clone(),
+                    },
+                );
+                pool.token = Some(pool_miners.start(&template, 0, pool));
+                pool.template = Some(template);
+            }
+            Some(_) => {}
+        }
+    }
+
+    // Join the most recent pool someone else announced
+    if keyboard_input.just_pressed(key_bindings.key(Action::JoinPool))
+        && mining_pool.active.is_none()
+    {
+        if let Some(announcement) = mining_pool.announcements.last().cloned() {
+            send_pool_message(
+                &user_keys,
+                &outgoing_notes,
+                &PoolMessage::Join {
+                    pool_id: announcement.pool_id.clone(),
+                },
+            );
+            mining_pool.active = Some(ActivePool {
+                pool_id: announcement.pool_id,
+                role: PoolRole::Member,
+                host: announcement.host.clone(),
+                coordinates: announcement.coordinates,
+                target: announcement.target,
+                members: vec![announcement.host, our_pubkey],
+                template: None,
+                token: None,
+            });
+        }
+    }
+
+    // Leave the pool, closing it for everyone if we host it
+    if keyboard_input.just_pressed(key_bindings.key(Action::LeavePool)) {
+        if let Some(mut pool) = mining_pool.active.take() {
+            pool.stop();
+            if pool.role == PoolRole::Host {
+                send_pool_message(
+                    &user_keys,
+                    &outgoing_notes,
+                    &PoolMessage::Finish {
+                        pool_id: pool.pool_id,
+                    },
+                );
+            }
+        }
+    }
+}
+
+fn handle_pool_messages(
+    mut pool_messages: EventReader<PoolMessageReceived>,
+    mut mining_pool: ResMut<MiningPool>,
+    user_keys: Res<UserNostrKeys>,
+    outgoing_notes: Res<OutgoingNotes>,
+    pow_notes_writer: Res<POWNotesWriter>,
+    pool_miners: PoolMiners,
+) {
+    let our_pubkey = user_keys.get_public_key();
+    for PoolMessageReceived { author, message } in pool_messages.read() {
+        if let PoolMessage::Announce {
+            pool_id,
+            coordinates,
+            target,
+        } = message
+        {
+            mining_pool
+                .announcements
+                .retain(|announcement| announcement.pool_id != *pool_id);
+            mining_pool.announcements.push(PoolAnnouncement {
+                pool_id: pool_id.clone(),
+                host: author.clone(),
+                coordinates: coordinates.clone(),
+                target: *target,
+            });
+            if mining_pool.announcements.len() > MAX_ANNOUNCEMENTS {
+                mining_pool.announcements.remove(0);
+            }
+            continue;
+        }
+        if let PoolMessage::Finish { pool_id } = message {
+            mining_pool
+                .announcements
+                .retain(|announcement| announcement.pool_id != *pool_id);
+        }
+
+        let Some(pool) = mining_pool
+            .active
+            .as_mut()
+            .filter(|pool| pool.pool_id == message.pool_id())
+        else {
+            continue;
+        };
+
+        match (pool.role, message) {
+            (PoolRole::Host, PoolMessage::Join { .. })
+                if pool.template.is_none() && !pool.members.contains(author) =>
+            {
+                pool.members.push(author.clone());
+            }
+            (PoolRole::Host, PoolMessage::Solution { nonce, .. }) => {
+                let Some(template) = pool.template.as_ref() else {
+                    continue;
+                };
+                let Some(note) = template.solved_note(&pool.host, nonce, pool.target) else {
+                    warn!(target: MINING_LOG, "Pool member {} sent an invalid nonce", author);
+                    continue;
+                };
+                queue_solved_block(
+                    &pow_notes_writer,
+                    user_keys.get_keypair().sign_nostr_event(note),
+                );
+                send_pool_message(
+                    &user_keys,
+                    &outgoing_notes,
+                    &PoolMessage::Finish {
+                        pool_id: pool.pool_id.clone(),
+                    },
+                );
+                pool.stop();
+                mining_pool.active = None;
+            }
+            (
+                PoolRole::Member,
+                PoolMessage::Start {
+                    template, members, ..
+                },
+            ) if *author == pool.host => {
+                // A host that didn't include us started without us
+                let Some(slot) = members.iter().position(|member| *member == our_pubkey) else {
+                    mining_pool.active = None;
+                    continue;
+                };
+                pool.members = members.clone();
+                pool.token = Some(pool_miners.start(template, slot as u64, pool));
+                pool.template = Some(template.clone());
+            }
+            (PoolRole::Member, PoolMessage::Finish { .. }) if *author == pool.host => {
+                pool.stop();
+                mining_pool.active = None;
+            }
+            _ => {}
+        }
+    }
+}
+
+// Winning nonces found by our own pool miners
+fn collect_pool_solutions(
+    pool_solutions: Res<PoolSolutions>,
+    mut mining_pool: ResMut<MiningPool>,
+    user_keys: Res<UserNostrKeys>,
+    outgoing_notes: Res<OutgoingNotes>,
+    pow_notes_writer: Res<POWNotesWriter>,
+) {
+    for nonce in pool_solutions.try_iter() {
+        let Some(pool) = mining_pool.active.as_mut() else {
+            continue;
+        };
+        match pool.role {
+            PoolRole::Host => {
+                let Some(note) = pool
+                    .template
+                    .as_ref()
+                    .and_then(|template| template.solved_note(&pool.host, &nonce, pool.target))
+                else {
+                    continue;
+                };
+                queue_solved_block(
+                    &pow_notes_writer,
+                    user_keys.get_keypair().sign_nostr_event(note),
+                );
+                send_pool_message(
+                    &user_keys,
+                    &outgoing_notes,
+                    &PoolMessage::Finish {
+                        pool_id: pool.pool_id.clone(),
+                    },
+                );
+                pool.stop();
+                mining_pool.active = None;
+            }
+            PoolRole::Member => send_pool_message(
+                &user_keys,
+                &outgoing_notes,
+                &PoolMessage::Solution {
+                    pool_id: pool.pool_id.clone(),
+                    nonce,
+                },
+            ),
+        }
+    }
+}
+
+// Everything needed to spawn the local workers for our slot of a pool
+#[derive(SystemParam)]
+struct PoolMiners<'w> {
+    runtime: Res<'w, TokioTasksRuntime>,
+    supervisor: Res<'w, TaskSupervisor>,
+    hash_counter: Res<'w, HashCounter>,
+    mining_intensity: Res<'w, MiningIntensity>,
+    mining_queue: Res<'w, MiningQueue>,
+    solutions_writer: Res<'w, PoolSolutionsWriter>,
+}
+
+impl PoolMiners<'_> {
+    fn start(&self, template: &PoolTemplate, slot: u64, pool: &ActivePool) -> CancellationToken {
+        let token = CancellationToken::new();
+        let slot = NonceSplit {
+            start: slot,
+            stride: pool.members.len() as u64,
+        };
+        let workers = self.mining_queue.max_concurrent as u64;
+        let note = Arc::new(template.note(&pool.host));
+        for worker in 0..workers {
+            let nonces = slot.subdivide(worker, workers);
+            let note = note.clone();
+            let target = pool.target;
+            let token = token.clone();
+            let solutions_writer = self.solutions_writer.0.clone();
+            let hash_counter = self.hash_counter.0.clone();
+            let intensity = self.mining_intensity.0.clone();
+            let supervisor = self.supervisor.clone();
+            let task_name = format!("pool {} #{}", pool.pool_id, worker);
+            self.runtime.spawn_background_task(move |_ctx| async move {
+                supervisor.supervise(
+                    task_name,
+                    RestartPolicy::OnFailure {
+                        max_restarts: MINER_MAX_RESTARTS,
+                    },
+                    move || {
+                        let note = note.clone();
+                        let token = token.clone();
+                        let solutions_writer = solutions_writer.clone();
+                        let hash_meter = HashMeter::new(hash_counter.clone(), intensity.clone());
+                        async move {
+                            mine_pool_share(
+                                note,
+                                target,
+                                nonces,
+                                solutions_writer,
+                                token,
+                                hash_meter,
+                            )
+                            .await;
+                            Ok(())
+                        }
+                    },
+                );
+            });
+        }
+        token
+    }
+}
+
+// Grinds our slice of the pool's nonce space until someone finds the block
+async fn mine_pool_share(
+    note: Arc<Note>,
+    target: usize,
+    nonces: NonceSplit,
+    solutions_writer: Sender<String>,
+    cancel_token: CancellationToken,
+    mut hash_meter: HashMeter,
+) {
+    let mut nonce = nonces.start;
+    info!(target: MINING_LOG, "Starting pool miner");
+    while !cancel_token.is_cancelled() {
+        let mut pool_note = note.as_ref().clone();
+        pool_note.tags.push(nonce_tag(&nonce.to_string(), target));
+        if note_difficulty(&pool_note) >= target {
+            let _sent = send_when_ready(&solutions_writer, nonce.to_string()).await;
+            break;
+        }
+        nonce = nonces.next(nonce);
+        hash_meter.tick().await;
+    }
+    info!(target: MINING_LOG, "Stopping pool miner");
+}
+
+#[derive(Component)]
+struct PoolText;
+
+fn setup_pool_ui(mut commands: Commands) {
+    let pool_ui = NodeBundle {
+        style: Style {
+            position_type: PositionType::Absolute,
+            bottom: Val::Percent(15.0),
+            left: Val::Px(0.0),
+            margin: MARGIN_UI,
+            padding: PADDING_UI,
+            row_gap: FLEX_GAP,
+            column_gap: FLEX_GAP,
+            flex_direction: FlexDirection::Column,
+            border: BORDER_WIDTH,
+            ..Default::default()
+        },
+        border_color: BorderColor(LIGHT_GRAY),
+        ..Default::default()
+    };
+
+    commands.spawn(pool_ui).with_children(|pool_ui| {
+        let pool_title = text_bundle_builder("Mining Pool".to_string(), TITLE_FONT);
+        pool_ui.spawn(pool_title);
+
+        let pool_details = text_bundle_builder(String::new(), NORMAL_FONT);
+        pool_ui.spawn((pool_details, PoolText));
+    });
+}
+
+fn update_pool_ui(mining_pool: Res<MiningPool>, mut text_query: Query<&mut Text, With<PoolText>>) {
+    if !mining_pool.is_changed() {
+        return;
+    }
+
+    let pool_details = match &mining_pool.active {
+        Some(pool) => {
+            let role = match pool.role {
+                PoolRole::Host => "Hosting",
+                PoolRole::Member => "Joined",
+            };
+            let status = match (&pool.template, pool.role) {
+                (Some(_), _) => "mining",
+                (None, PoolRole::Host) => "waiting, H to start",
+                (None, PoolRole::Member) => "waiting for host",
+            };
+            format!(
+                "{} pool {} ({})\n{} members, target {} bits\nL to leave",
+                role,
+                pool.pool_id,
+                status,
+                pool.members.len(),
+                pool.target
+            )
+        }
+        None => format!(
+            "{} pools announced\nH to host the selected block, J to join",
+            mining_pool.announcements.len()
+        ),
+    };
+
+    for mut text in text_query.iter_mut() {
+        text.sections[0].value = pool_details.clone();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pool_messages_are_tagged_by_type() {
+        let message = PoolMessage::Join {
+            pool_id: "abc".to_string(),
+        };
+        let content = json!(message).to_string();
+        assert!(content.contains(r#""type":"join""#));
+        assert_eq!(
+            serde_json::from_str::<PoolMessage>(&content).unwrap(),
+            message
+        );
+    }
+
+    #[test]
+    fn block_content_is_not_a_pool_message() {
+        let content = r#"{"pow_amount":1,"coordinates":"00","miner_pubkey":"00"}"#;
+        assert!(serde_json::from_str::<PoolMessage>(content).is_err());
+    }
+}
@@ -0,0 +1,414 @@
+use bevy::{
+    input::{
+        keyboard::{Key, KeyboardInput},
+        ButtonState,
+    },
+    prelude::*,
+};
+use nostro2::notes::Note;
+use serde_json::json;
+
+use crate::{
+    keybindings::{Action, KeyBindings, Typing},
+    nostr::OutgoingNotes,
+    notifications::{Notification, Severity},
+    protocol::{ProfileMetadata, METADATA_KIND},
+    resources::Profiles,
+    ui_camera::{
+        edit_text_field, text_bundle_builder, TextFieldEdit, TextInput, BORDER_WIDTH, FLEX_GAP,
+        LIGHT_GRAY, MARGIN_UI, NORMAL_FONT, PADDING_UI, TITLE_FONT,
+    },
+    UserNostrKeys,
+};
+
+const MAX_NAME_CHARS: usize = 64;
+const MAX_ABOUT_CHARS: usize = 280;
+const MAX_PICTURE_CHARS: usize = 512;
+
+pub fn profile_editor_plugin(app: &mut App) {
+    app.init_resource::<ProfileEditor>()
+        .add_systems(PostStartup, setup_profile_panel)
+        .add_systems(PreUpdate, type_profile_field.in_set(TextInput))
+        .add_systems(
+            Update,
+            (profile_panel_buttons, update_profile_panel).chain(),
+        );
+}
+
+#[derive(Component, Clone, Copy, Default, PartialEq, Eq, Debug)]
+enum ProfileField {
+    #[default]
+    Name,
+    About,
+    Picture,
+}
+
+impl ProfileField {
+    const ALL: [ProfileField; 3] = [
+        ProfileField::Name,
+        ProfileField::About,
+        ProfileField::Picture,
+    ];
+
+    fn label(self) -> &'static str {
+        match self {
+            ProfileField::Name => "Name",
+            ProfileField::About => "About",
+            ProfileField::Picture => "Picture URL",
+        }
+    }
+
+    fn max_chars(self) -> usize {
+        match self {
+            ProfileField::Name => MAX_NAME_CHARS,
+            ProfileField::About => MAX_ABOUT_CHARS,
+            ProfileField::Picture => MAX_PICTURE_CHARS,
+        }
+    }
+
+    fn next(self) -> Self {
+        match self {
+            ProfileField::Name => ProfileField::About,
+            ProfileField::About => ProfileField::Picture,
+            ProfileField::Picture => ProfileField::Name,
+        }
+    }
+}
+
+// The fields being edited, filled from our latest kind 0 when the editor opens
+struct EditedProfile {
+    focused: ProfileField,
+    name: String,
+    about: String,
+    picture: String,
+    status: String,
+}
+
+impl EditedProfile {
+    fn field(&mut self, field: ProfileField) -> &mut String {
+        match field {
+            ProfileField::Name => &mut self.name,
+            ProfileField::About => &mut self.about,
+            ProfileField::Picture => &mut self.picture,
+        }
+    }
+
+    fn typed(&mut self) -> &mut String {
+        self.field(self.focused)
+    }
+}
+
+// None while the editor is closed
+#[derive(Resource, Default, Deref, DerefMut)]
+struct ProfileEditor(Option<EditedProfile>);
+
+#[derive(Component, Clone, Copy, PartialEq)]
+enum ProfileButton {
+    Publish,
+    Close,
+}
+
+#[derive(Component)]
+struct ProfilePanel;
+
+#[derive(Component)]
+struct ProfileFieldText(ProfileField);
+
+#[derive(Component)]
+struct ProfileStatusText;
+
+fn is_picture_url(url: &str) -> bool {
+    ["https://", "http://"]
+        .iter()
+        .any(|scheme| url.len() > scheme.len() && url.starts_with(scheme))
+}
+
+// Our current profile with the edited fields swapped in, blank ones are left out
+fn edited_metadata(
+    current: Option<&ProfileMetadata>,
+    name: &str,
+    about: &str,
+    picture: &str,
+) -> ProfileMetadata {
+    let filled = |value: &str| {
+        let value = value.trim();
+        (!value.is_empty()).then(|| value.to_string())
+    };
+    ProfileMetadata {
+        name: filled(name),
+        about: filled(about),
+        picture: filled(picture),
+        ..current.cloned().unwrap_or_default()
+    }
+}
+
+fn open_profile_editor(profiles: &Profiles, pubkey: &str) -> EditedProfile {
+    let current = profiles.get(pubkey);
+    let value = |field: Option<&Option<String>>| field.cloned().flatten().unwrap_or_default();
+    EditedProfile {
+        focused: ProfileField::Name,
+        name: value(current.map(|profile| &profile.name)),
+        about: value(current.map(|profile| &profile.about)),
+        picture: value(current.map(|profile| &profile.picture)),
+        status: match current {
+            Some(_) => "Editing your profile from the relays".to_string(),
+            None => "No profile of yours on the relays yet".to_string(),
+        },
+    }
+}
+
+// Signs and sends the kind 0 note, and shows it right away instead of waiting for a relay
+fn publish_profile(
+    edited: &mut EditedProfile,
+    user_keys: &UserNostrKeys,
+    outgoing_notes: &OutgoingNotes,
+    profiles: &mut Profiles,
+    notifications: &mut EventWriter<Notification>,
+) -> bool {
+    if !edited.picture.trim().is_empty() && !is_picture_url(edited.picture.trim()) {
+        edited.status = "The picture has to be an http or https link".to_string();
+        return false;
+    }
+    let pubkey = user_keys.get_public_key();
+    let metadata = edited_metadata(
+        profiles.get(&pubkey),
+        &edited.name,
+        &edited.about,
+        &edited.picture,
+    );
+    let note = Note::new(pubkey.clone(), METADATA_KIND, &json!(metadata).to_string());
+    let _sent = outgoing_notes.send(user_keys.get_keypair().sign_nostr_event(note));
+    profiles.insert(pubkey, metadata);
+    notifications.send(Notification {
+        message: "Profile published".to_string(),
+        severity: Severity::Success,
+        play_sound: false,
+    });
+    true
+}
+
+fn type_profile_field(
+    mut keyboard_events: EventReader<KeyboardInput>,
+    key_bindings: Res<KeyBindings>,
+    mut keyboard_input: ResMut<ButtonInput<KeyCode>>,
+    mut typing: ResMut<Typing>,
+    user_keys: Res<UserNostrKeys>,
+    outgoing_notes: Res<OutgoingNotes>,
+    mut profiles: ResMut<Profiles>,
+    mut editor: ResMut<ProfileEditor>,
+    mut notifications: EventWriter<Notification>,
+) {
+    let Some(edited) = editor.0.as_mut() else {
+        keyboard_events.clear();
+        if !**typing && keyboard_input.just_pressed(key_bindings.key(Action::EditProfile)) {
+            **editor = Some(open_profile_editor(&profiles, &user_keys.get_public_key()));
+            **typing = true;
+            keyboard_input.reset_all();
+        }
+        return;
+    };
+    let mut closed = false;
+    for event in keyboard_events.read() {
+        if event.state != ButtonState::Pressed {
+            continue;
+        }
+        match &event.logical_key {
+            Key::Tab => edited.focused = edited.focused.next(),
+            Key::Enter => {
+                closed = publish_profile(
+                    edited,
+                    &user_keys,
+                    &outgoing_notes,
+                    &mut profiles,
+                    &mut notifications,
+                );
+            }
+            Key::Escape => closed = true,
+            key => {
+                let max_chars = edited.focused.max_chars();
+                let edit = edit_text_field(edited.typed(), key, max_chars, &keyboard_input);
+                if edit == TextFieldEdit::NothingToPaste {
+                    edited.status = "Nothing to paste".to_string();
+                }
+            }
+        }
+    }
+    // Keys typed into the editor don't reach the rest of the game
+    keyboard_input.reset_all();
+    if closed {
+        **editor = None;
+        **typing = false;
+    }
+}
+
+fn profile_panel_buttons(
+    button_query: Query<(&Interaction, &ProfileButton), Changed<Interaction>>,
+    field_query: Query<(&Interaction, &ProfileField), Changed<Interaction>>,
+    user_keys: Res<UserNostrKeys>,
+    outgoing_notes: Res<OutgoingNotes>,
+    mut profiles: ResMut<Profiles>,
+    mut typing: ResMut<Typing>,
+    mut editor: ResMut<ProfileEditor>,
+    mut notifications: EventWriter<Notification>,
+) {
+    let Some(edited) = editor.0.as_mut() else {
+        return;
+    };
+    for (interaction, field) in field_query.iter() {
+        if *interaction == Interaction::Pressed {
+            edited.focused = *field;
+        }
+    }
+    let mut closed = false;
+    for (interaction, button) in button_query.iter() {
+        if *interaction != Interaction::Pressed {
+            continue;
+        }
+        closed = match button {
+            ProfileButton::Publish => publish_profile(
+                edited,
+                &user_keys,
+                &outgoing_notes,
+                &mut profiles,
+                &mut notifications,
+            ),
+            ProfileButton::Close => true,
+        };
+    }
+    if closed {
+        **editor = None;
+        **typing = false;
+    }
+}
+
+fn setup_profile_panel(mut commands: Commands) {
+    let profile_ui = NodeBundle {
+        style: Style {
+            position_type: PositionType::Absolute,
+            top: Val::Percent(25.0),
+            left: Val::Percent(30.0),
+            width: Val::Percent(40.0),
+            margin: MARGIN_UI,
+            padding: PADDING_UI,
+            row_gap: FLEX_GAP,
+            flex_direction: FlexDirection::Column,
+            border: BORDER_WIDTH,
+            ..Default::default()
+        },
+        border_color: BorderColor(LIGHT_GRAY),
+        background_color: BackgroundColor(Color::rgba(0.0, 0.0, 0.0, 0.85)),
+        visibility: Visibility::Hidden,
+        ..Default::default()
+    };
+    let field_ui = || NodeBundle {
+        style: Style {
+            padding: PADDING_UI,
+            border: BORDER_WIDTH,
+            ..Default::default()
+        },
+        border_color: BorderColor(LIGHT_GRAY),
+        ..Default::default()
+    };
+    let buttons = NodeBundle {
+        style: Style {
+            column_gap: FLEX_GAP,
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    commands
+        .spawn((profile_ui, ProfilePanel))
+        .with_children(|profile_ui| {
+            profile_ui.spawn(text_bundle_builder("Profile".to_string(), TITLE_FONT));
+            for field in ProfileField::ALL {
+                profile_ui.spawn(text_bundle_builder(field.label().to_string(), NORMAL_FONT));
+                profile_ui
+                    .spawn((field_ui(), Interaction::default(), field))
+                    .with_children(|field_ui| {
+                        field_ui.spawn((
+                            text_bundle_builder(String::new(), NORMAL_FONT),
+                            ProfileFieldText(field),
+                        ));
+                    });
+            }
+            profile_ui.spawn((
+                text_bundle_builder(String::new(), NORMAL_FONT),
+                ProfileStatusText,
+            ));
+            profile_ui.spawn(buttons).with_children(|buttons| {
+                for (label, button) in [
+                    ("[Publish]", ProfileButton::Publish),
+                    ("[Close]", ProfileButton::Close),
+                ] {
+                    let text = text_bundle_builder(label.to_string(), NORMAL_FONT);
+                    buttons.spawn((text, Interaction::default(), button));
+                }
+            });
+            profile_ui.spawn(text_bundle_builder(
+                "Tab switches fields, Ctrl+V pastes, Enter publishes, Esc closes".to_string(),
+                NORMAL_FONT,
+            ));
+        });
+}
+
+fn update_profile_panel(
+    editor: Res<ProfileEditor>,
+    mut panel_query: Query<&mut Visibility, With<ProfilePanel>>,
+    mut text_query: Query<
+        (&mut Text, Option<&ProfileFieldText>, Has<ProfileStatusText>),
+        Or<(With<ProfileFieldText>, With<ProfileStatusText>)>,
+    >,
+) {
+    if !editor.is_changed() {
+        return;
+    }
+    for mut visibility in panel_query.iter_mut() {
+        *visibility = match editor.0.as_ref() {
+            Some(_) => Visibility::Visible,
+            None => Visibility::Hidden,
+        };
+    }
+    let Some(edited) = editor.0.as_ref() else {
+        return;
+    };
+    for (mut text, field_text, status) in text_query.iter_mut() {
+        if let Some(ProfileFieldText(field)) = field_text {
+            let typed = match field {
+                ProfileField::Name => &edited.name,
+                ProfileField::About => &edited.about,
+                ProfileField::Picture => &edited.picture,
+            };
+            let cursor = if *field == edited.focused { "_" } else { "" };
+            text.sections[0].value = format!("> {}{}", typed, cursor);
+        } else if status {
+            text.sections[0].value = edited.status.clone();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn edited_profiles_keep_the_fields_the_editor_doesnt_show() {
+        let current: ProfileMetadata = serde_json::from_str(
+            r#"{"name":"old","nip05":"bob@example.com","banner":"https://example.com/b.png"}"#,
+        )
+        .unwrap();
+        let edited = edited_metadata(Some(&current), " bob ", "", "https://example.com/p.png");
+        assert_eq!(edited.name.as_deref(), Some("bob"));
+        assert_eq!(edited.about, None);
+        assert_eq!(edited.nip05.as_deref(), Some("bob@example.com"));
+        let published = json!(edited).to_string();
+        assert!(published.contains(r#""banner":"https://example.com/b.png""#));
+        assert!(!published.contains("null"));
+    }
+
+    #[test]
+    fn pictures_are_web_links() {
+        assert!(is_picture_url("https://example.com/me.png"));
+        assert!(!is_picture_url("https://"));
+        assert!(!is_picture_url("file:///home/me.png"));
+    }
+}
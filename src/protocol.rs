@@ -0,0 +1,663 @@
+use schemars::{schema::RootSchema, schema_for, JsonSchema};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    cyberspace::{
+        extract_coordinates, extract_plane, morton_cell, CyberspaceCoordinates, Plane, Sector,
+        MORTON_LEVELS,
+    },
+    pool::PoolMessage,
+};
+
+// Note kinds NostrCraft publishes or reads from relays
+pub const METADATA_KIND: u32 = 0;
+// Plain text notes, sector chat is told apart by its `t` tag
+pub const CHAT_KIND: u32 = 1;
+// NIP-04 encrypted direct messages, the recipient is the `p` tag
+pub const DIRECT_MESSAGE_KIND: u32 = 4;
+// NIP-25 reactions, the last `e` tag is the note reacted to
+pub const REACTION_KIND: u32 = 7;
+// NIP-02 contact list, replaceable, only the newest one per author counts
+pub const CONTACTS_KIND: u32 = 3;
+pub const POW_BLOCK_KIND: u32 = 333;
+pub const DEREZZ_KIND: u32 = 334;
+// Hands a block to another key, signed by whoever owns it at the end of the chain so far
+pub const TRANSFER_KIND: u32 = 335;
+// Replaceable, where a player invites others to visit, only the newest one per author counts
+pub const HOME_BASE_KIND: u32 = 10333;
+// Structures from the cyberspace spec, published by other cyberspace clients
+pub const CONSTRUCT_KIND: u32 = 331;
+// Ephemeral, relays forward pool coordination without storing it
+pub const POOL_KIND: u32 = 23333;
+// Ephemeral too, avatar positions are only interesting while they are fresh
+pub const DRIFT_KIND: u32 = 20333;
+// NIP-57 zaps, the request is signed by us and sent to the recipient's LNURL server,
+// which publishes the receipt once the invoice is paid
+pub const ZAP_REQUEST_KIND: u32 = 9734;
+pub const ZAP_RECEIPT_KIND: u32 = 9735;
+// Blossom upload authorization, shown to the media server instead of a relay
+pub const BLOSSOM_AUTH_KIND: u32 = 24242;
+// Hashtag of a sector's chat, the sector's x, y and z joined by underscores
+const SECTOR_CHAT_TAG_PREFIX: &str = "nostrcraft-sector-";
+// Longer labels are cut when read, they are meant for a short message or a link
+pub const MAX_LABEL_CHARS: usize = 140;
+
+// Content of a kind 333 note, a block claimed at a cyberspace coordinate
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone)]
+pub struct POWBlockDetails {
+    /// Leading zero bits of the note id, always recomputed by readers
+    pub pow_amount: usize,
+    /// 64 character hex cyberspace coordinate of the block
+    pub coordinates: String,
+    /// Hex public key of the miner
+    pub miner_pubkey: String,
+    /// Material tier picked by the miner, capped at the tier the POW reaches
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub material: Option<usize>,
+    /// Short message or link left by the miner
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
+    /// Key the block was last transferred to, only ever set by readers from the transfer chain
+    #[serde(skip)]
+    pub owner: Option<String>,
+}
+
+impl POWBlockDetails {
+    pub fn coordinates(&self) -> CyberspaceCoordinates {
+        CyberspaceCoordinates::from_hex(&self.coordinates).unwrap_or_default()
+    }
+
+    pub fn sector(&self) -> Sector {
+        self.coordinates().sector()
+    }
+
+    pub fn plane(&self) -> Plane {
+        extract_plane(&self.coordinates).unwrap_or_default()
+    }
+
+    pub fn display_label(&self) -> Option<String> {
+        trim_label(self.label.as_deref()?)
+    }
+
+    // The miner, until the block is transferred
+    pub fn owner(&self) -> &str {
+        self.owner.as_deref().unwrap_or(&self.miner_pubkey)
+    }
+
+    pub fn display_coordinates(&self) -> String {
+        let coordinates = extract_coordinates(self.coordinates.as_str()).unwrap_or((0, 0, 0));
+        format!(
+            "X:{}, Y: {}, Z: {}",
+            coordinates.0, coordinates.1, coordinates.2
+        )
+    }
+}
+
+// Content of a kind 334 note, removes the block at a coordinate if it beats the block's POW
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone)]
+pub struct DerezzDetails {
+    /// Leading zero bits of the note id, always recomputed by readers
+    pub pow_amount: usize,
+    /// 64 character hex cyberspace coordinate of the block to remove
+    pub derezz: String,
+    /// Hex public key of the miner
+    pub miner_pubkey: String,
+}
+
+// Content of a kind 335 note, one link of a block's chain of owners
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, PartialEq)]
+pub struct TransferDetails {
+    /// 64 character hex cyberspace coordinate of the block
+    pub transfer: String,
+    /// Id of the kind 333 note that claimed the block
+    pub block: String,
+    /// Id of the transfer this one follows, or the block's note id for the first one
+    pub previous: String,
+    /// Hex public key of the new owner
+    pub recipient: String,
+}
+
+// Content of a kind 10333 note, the author's home base
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, PartialEq)]
+pub struct HomeBaseDetails {
+    /// 64 character hex cyberspace coordinate of the home base
+    pub home: String,
+    /// Short description shown to visitors
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+}
+
+impl HomeBaseDetails {
+    pub fn coordinates(&self) -> Option<CyberspaceCoordinates> {
+        CyberspaceCoordinates::from_hex(&self.home).ok()
+    }
+}
+
+// Content of a kind 20333 note, where the author's avatar currently is
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, PartialEq)]
+pub struct DriftDetails {
+    /// 64 character hex cyberspace coordinate of the avatar
+    pub drift: String,
+}
+
+impl DriftDetails {
+    pub fn coordinates(&self) -> Option<CyberspaceCoordinates> {
+        CyberspaceCoordinates::from_hex(&self.drift).ok()
+    }
+}
+
+// NIP-01 kind 0 content, every field is optional and unknown ones are kept as they are,
+// so publishing our own profile from the game doesn't wipe what other clients set
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, Default, PartialEq)]
+pub struct ProfileMetadata {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub display_name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub picture: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub about: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub nip05: Option<String>,
+    /// Lightning address zaps are paid to
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub lud16: Option<String>,
+    #[serde(flatten)]
+    pub other: serde_json::Map<String, serde_json::Value>,
+}
+
+impl ProfileMetadata {
+    // Nothing worth showing, every field is optional
+    pub fn is_empty(&self) -> bool {
+        self.display_name().is_none() && self.picture.is_none() && self.about.is_none()
+    }
+
+    pub fn display_name(&self) -> Option<&str> {
+        [&self.display_name, &self.name, &self.nip05]
+            .into_iter()
+            .flatten()
+            .map(|name| name.trim())
+            .find(|name| !name.is_empty())
+    }
+}
+
+// JSON content of an incoming note, parsed by the relay task that read it so frames only match
+// on it. Kinds that carry their data in tags, or aren't JSON, are left to the middleware
+#[derive(Debug, Clone)]
+pub enum NoteContent {
+    Block(POWBlockDetails),
+    Derezz(DerezzDetails),
+    Transfer(TransferDetails),
+    HomeBase(HomeBaseDetails),
+    Drift(DriftDetails),
+    Pool(PoolMessage),
+    Profile(ProfileMetadata),
+    Unparsed,
+}
+
+impl NoteContent {
+    // Each kind has one shape, kinds without a JSON shape are left to the middleware
+    pub fn parse(kind: u32, content: &str) -> Self {
+        let parsed = match kind {
+            METADATA_KIND => serde_json::from_str(content).map(NoteContent::Profile),
+            POW_BLOCK_KIND => serde_json::from_str(content).map(NoteContent::Block),
+            DEREZZ_KIND => serde_json::from_str(content).map(NoteContent::Derezz),
+            TRANSFER_KIND => serde_json::from_str(content).map(NoteContent::Transfer),
+            HOME_BASE_KIND => serde_json::from_str(content).map(NoteContent::HomeBase),
+            DRIFT_KIND => serde_json::from_str(content).map(NoteContent::Drift),
+            POOL_KIND => serde_json::from_str(content).map(NoteContent::Pool),
+            _ => return NoteContent::Unparsed,
+        };
+        parsed.unwrap_or(NoteContent::Unparsed)
+    }
+}
+
+pub fn sector_chat_tag(sector: Sector) -> String {
+    format!(
+        "{}{}_{}_{}",
+        SECTOR_CHAT_TAG_PREFIX, sector.x, sector.y, sector.z
+    )
+}
+
+// The sector a note's `t` tags put it in, if any
+pub fn chat_sector(tags: &[Vec<String>]) -> Option<Sector> {
+    tags.iter().find_map(|tag| {
+        let [name, value, ..] = &tag[..] else {
+            return None;
+        };
+        let sector = value
+            .strip_prefix(SECTOR_CHAT_TAG_PREFIX)
+            .filter(|_| name == "t")?;
+        let parts: Vec<i128> = sector
+            .split('_')
+            .map(|part| part.parse())
+            .collect::<Result<_, _>>()
+            .ok()?;
+        match parts[..] {
+            [x, y, z] => Some(Sector { x, y, z }),
+            _ => None,
+        }
+    })
+}
+
+// The first `p` tag's public key
+pub fn tagged_pubkey(tags: &[Vec<String>]) -> Option<String> {
+    tags.iter().find_map(|tag| match &tag[..] {
+        [name, pubkey, ..] if name == "p" => Some(pubkey.clone()),
+        _ => None,
+    })
+}
+
+// Every `p` tag's public key, in the order they were listed
+pub fn tagged_pubkeys(tags: &[Vec<String>]) -> Vec<String> {
+    tags.iter()
+        .filter_map(|tag| match &tag[..] {
+            [name, pubkey, ..] if name == "p" => Some(pubkey.clone()),
+            _ => None,
+        })
+        .collect()
+}
+
+// The first `e` tag's note id
+pub fn tagged_event(tags: &[Vec<String>]) -> Option<String> {
+    tags.iter().find_map(|tag| match &tag[..] {
+        [name, note_id, ..] if name == "e" => Some(note_id.clone()),
+        _ => None,
+    })
+}
+
+// The last `e` tag's note id, earlier ones are the thread a reaction's note is in
+pub fn reacted_event(tags: &[Vec<String>]) -> Option<String> {
+    tags.iter().rev().find_map(|tag| match &tag[..] {
+        [name, note_id, ..] if name == "e" => Some(note_id.clone()),
+        _ => None,
+    })
+}
+
+// The coordinate a construct's `C` tag places it at, if it's a valid one
+pub fn construct_coordinates(tags: &[Vec<String>]) -> Option<String> {
+    tags.iter().find_map(|tag| match &tag[..] {
+        [name, coordinates, ..]
+            if name == "C"
+                && coordinates.len() == 64
+                && extract_coordinates(coordinates).is_ok() =>
+        {
+            Some(coordinates.to_lowercase())
+        }
+        _ => None,
+    })
+}
+
+// What a construct's `shape` tag describes, in blocks
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ConstructShape {
+    Box([f32; 3]),
+    Sphere(f32),
+}
+
+// `["shape", "box", width, height, depth]` or `["shape", "sphere", diameter]`,
+// constructs without one or with sizes we can't draw are cubes sized by their POW
+pub fn construct_shape(tags: &[Vec<String>]) -> Option<ConstructShape> {
+    tags.iter().find_map(|tag| {
+        let [name, shape, sizes @ ..] = &tag[..] else {
+            return None;
+        };
+        if name != "shape" {
+            return None;
+        }
+        let sizes = sizes
+            .iter()
+            .map(|size| {
+                size.parse::<f32>()
+                    .ok()
+                    .filter(|size| size.is_finite() && *size > 0.0)
+            })
+            .collect::<Option<Vec<f32>>>()?;
+        match (shape.as_str(), &sizes[..]) {
+            ("box", [width, height, depth]) => Some(ConstructShape::Box([*width, *height, *depth])),
+            ("sphere", [diameter]) => Some(ConstructShape::Sphere(*diameter)),
+            _ => None,
+        }
+    })
+}
+
+// One `m` tag per Morton level naming the cell the coordinate's sector is in,
+// so blocks can be asked for by neighborhood
+pub fn sector_cell_tags(coordinates: &str) -> Vec<Vec<String>> {
+    let Ok(coordinates) = CyberspaceCoordinates::from_hex(coordinates) else {
+        return Vec::new();
+    };
+    MORTON_LEVELS
+        .into_iter()
+        .map(|level| vec!["m".to_string(), morton_cell(coordinates.sector(), level)])
+        .collect()
+}
+
+// Blank labels count as none, long ones are cut
+pub fn trim_label(label: &str) -> Option<String> {
+    let label = label.trim();
+    (!label.is_empty()).then(|| label.chars().take(MAX_LABEL_CHARS).collect())
+}
+
+#[derive(Serialize)]
+pub struct TagSchema {
+    pub name: &'static str,
+    pub fields: Vec<&'static str>,
+    pub description: &'static str,
+}
+
+#[derive(Serialize)]
+pub struct NoteKindSchema {
+    pub kind: u32,
+    pub name: &'static str,
+    pub published: bool,
+    pub consumed: bool,
+    pub content: Option<RootSchema>,
+    pub tags: Vec<TagSchema>,
+}
+
+pub fn protocol_schema() -> Vec<NoteKindSchema> {
+    vec![
+        NoteKindSchema {
+            kind: METADATA_KIND,
+            name: "metadata",
+            published: true,
+            consumed: true,
+            content: Some(schema_for!(ProfileMetadata)),
+            tags: Vec::new(),
+        },
+        NoteKindSchema {
+            kind: POW_BLOCK_KIND,
+            name: "pow_block",
+            published: true,
+            consumed: true,
+            content: Some(schema_for!(POWBlockDetails)),
+            tags: vec![
+                TagSchema {
+                    name: "nonce",
+                    fields: vec!["nonce", "target difficulty"],
+                    description: "NIP-13 proof of work nonce and committed target",
+                },
+                TagSchema {
+                    name: "m",
+                    fields: vec!["<level>:<hex Morton code of the sector's cell>"],
+                    description: "One per level, clients near the block subscribe to its cells",
+                },
+            ],
+        },
+        NoteKindSchema {
+            kind: DEREZZ_KIND,
+            name: "derezz",
+            published: true,
+            consumed: true,
+            content: Some(schema_for!(DerezzDetails)),
+            tags: vec![
+                TagSchema {
+                    name: "nonce",
+                    fields: vec!["nonce", "target difficulty"],
+                    description: "NIP-13 proof of work nonce and committed target",
+                },
+                TagSchema {
+                    name: "m",
+                    fields: vec!["<level>:<hex Morton code of the sector's cell>"],
+                    description: "One per level, clients near the block subscribe to its cells",
+                },
+            ],
+        },
+        NoteKindSchema {
+            kind: TRANSFER_KIND,
+            name: "block_transfer",
+            published: true,
+            consumed: true,
+            content: Some(schema_for!(TransferDetails)),
+            tags: vec![
+                TagSchema {
+                    name: "p",
+                    fields: vec!["recipient public key"],
+                    description: "The new owner",
+                },
+                TagSchema {
+                    name: "m",
+                    fields: vec!["<level>:<hex Morton code of the sector's cell>"],
+                    description: "Same cells as the block, so clients near it see the transfer",
+                },
+            ],
+        },
+        NoteKindSchema {
+            kind: CONSTRUCT_KIND,
+            name: "construct",
+            published: false,
+            consumed: true,
+            content: None,
+            tags: vec![
+                TagSchema {
+                    name: "C",
+                    fields: vec!["64 character hex cyberspace coordinate"],
+                    description: "Where the construct stands",
+                },
+                TagSchema {
+                    name: "shape",
+                    fields: vec!["box", "width", "height", "depth"],
+                    description:
+                        "Optional, or `sphere` with a diameter. Cubes grown by POW without it",
+                },
+                TagSchema {
+                    name: "nonce",
+                    fields: vec!["nonce", "target difficulty"],
+                    description: "NIP-13 proof of work nonce and committed target",
+                },
+            ],
+        },
+        NoteKindSchema {
+            kind: POOL_KIND,
+            name: "mining_pool",
+            published: true,
+            consumed: true,
+            content: Some(schema_for!(PoolMessage)),
+            tags: Vec::new(),
+        },
+        NoteKindSchema {
+            kind: DRIFT_KIND,
+            name: "drift",
+            published: true,
+            consumed: true,
+            content: Some(schema_for!(DriftDetails)),
+            tags: Vec::new(),
+        },
+        NoteKindSchema {
+            kind: CHAT_KIND,
+            name: "sector_chat",
+            published: true,
+            consumed: true,
+            content: None,
+            tags: vec![TagSchema {
+                name: "t",
+                fields: vec!["nostrcraft-sector-<x>_<y>_<z>"],
+                description: "Sector the message was sent from, readers subscribe to their own",
+            }],
+        },
+        NoteKindSchema {
+            kind: DIRECT_MESSAGE_KIND,
+            name: "direct_message",
+            published: true,
+            consumed: true,
+            content: None,
+            tags: vec![TagSchema {
+                name: "p",
+                fields: vec!["recipient public key"],
+                description: "Who the NIP-04 encrypted message is for",
+            }],
+        },
+        NoteKindSchema {
+            kind: REACTION_KIND,
+            name: "reaction",
+            published: true,
+            consumed: true,
+            content: None,
+            tags: vec![
+                TagSchema {
+                    name: "e",
+                    fields: vec!["note id"],
+                    description: "The liked block's kind 333 note, counted in the inspector",
+                },
+                TagSchema {
+                    name: "p",
+                    fields: vec!["miner public key"],
+                    description: "The block's miner",
+                },
+                TagSchema {
+                    name: "k",
+                    fields: vec!["333"],
+                    description: "Kind of the reacted note, readers only ask for block reactions",
+                },
+            ],
+        },
+        NoteKindSchema {
+            // Replaced whenever we move our home base, relays keep only the newest
+            kind: HOME_BASE_KIND,
+            name: "home_base",
+            published: true,
+            consumed: true,
+            content: Some(schema_for!(HomeBaseDetails)),
+            tags: Vec::new(),
+        },
+        NoteKindSchema {
+            // Replaced as a whole on every follow, tags from other clients are kept
+            kind: CONTACTS_KIND,
+            name: "contacts",
+            published: true,
+            consumed: true,
+            content: None,
+            tags: vec![TagSchema {
+                name: "p",
+                fields: vec!["followed public key", "relay hint", "petname"],
+                description: "Followed players, whose avatars and blocks are tinted",
+            }],
+        },
+        NoteKindSchema {
+            // Handed to the recipient's LNURL server rather than to relays
+            kind: ZAP_REQUEST_KIND,
+            name: "zap_request",
+            published: true,
+            consumed: false,
+            content: None,
+            tags: vec![
+                TagSchema {
+                    name: "p",
+                    fields: vec!["recipient public key"],
+                    description: "The zapped miner or avatar",
+                },
+                TagSchema {
+                    name: "e",
+                    fields: vec!["note id"],
+                    description: "The zapped block's kind 333 note, left out for avatars",
+                },
+                TagSchema {
+                    name: "amount",
+                    fields: vec!["millisats"],
+                    description: "Amount of the invoice asked for",
+                },
+                TagSchema {
+                    name: "relays",
+                    fields: vec!["relay url", "..."],
+                    description: "Where the LNURL server publishes the receipt",
+                },
+            ],
+        },
+        NoteKindSchema {
+            kind: ZAP_RECEIPT_KIND,
+            name: "zap_receipt",
+            published: false,
+            consumed: true,
+            content: None,
+            tags: vec![
+                TagSchema {
+                    name: "e",
+                    fields: vec!["note id"],
+                    description: "The zapped block's note, which glows when the receipt arrives",
+                },
+                TagSchema {
+                    name: "description",
+                    fields: vec!["zap request JSON"],
+                    description: "The paid zap request, bigger zaps glow longer",
+                },
+            ],
+        },
+        NoteKindSchema {
+            // Sent in the upload's Authorization header
+            kind: BLOSSOM_AUTH_KIND,
+            name: "blossom_upload_auth",
+            published: true,
+            consumed: false,
+            content: None,
+            tags: vec![
+                TagSchema {
+                    name: "t",
+                    fields: vec!["upload"],
+                    description: "What the media server is allowed to do",
+                },
+                TagSchema {
+                    name: "x",
+                    fields: vec!["sha256"],
+                    description: "Hash of the screenshot being uploaded",
+                },
+                TagSchema {
+                    name: "expiration",
+                    fields: vec!["unix timestamp"],
+                    description: "When the authorization stops being accepted",
+                },
+            ],
+        },
+    ]
+}
+
+pub fn dump_protocol() -> String {
+    serde_json::to_string_pretty(&protocol_schema()).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn incoming_content_is_parsed_by_kind() {
+        let block = format!(
+            r#"{{"pow_amount": 3, "coordinates": "{:064x}", "miner_pubkey": "ab"}}"#,
+            7
+        );
+        assert!(matches!(
+            NoteContent::parse(POW_BLOCK_KIND, &block),
+            NoteContent::Block(details) if details.pow_amount == 3
+        ));
+        // Chat isn't JSON even when it looks like it, and unknown kinds are never blocks
+        assert!(matches!(
+            NoteContent::parse(CHAT_KIND, &block),
+            NoteContent::Unparsed
+        ));
+        assert!(matches!(
+            NoteContent::parse(30333, &block),
+            NoteContent::Unparsed
+        ));
+        assert!(matches!(
+            NoteContent::parse(POW_BLOCK_KIND, r#"{"name": "satoshi"}"#),
+            NoteContent::Unparsed
+        ));
+        assert!(matches!(
+            NoteContent::parse(METADATA_KIND, r#"{"name": "satoshi"}"#),
+            NoteContent::Profile(profile) if profile.display_name() == Some("satoshi")
+        ));
+    }
+
+    #[test]
+    fn broken_content_is_left_unparsed() {
+        assert!(matches!(
+            NoteContent::parse(TRANSFER_KIND, "{\"block\": "),
+            NoteContent::Unparsed
+        ));
+        assert!(matches!(
+            NoteContent::parse(POW_BLOCK_KIND, "not json"),
+            NoteContent::Unparsed
+        ));
+    }
+}
@@ -0,0 +1,190 @@
+use std::collections::VecDeque;
+
+use bevy::{prelude::*, utils::HashMap};
+use nostro2::notes::Note;
+
+use crate::{
+    chunks::WorldChunks,
+    keybindings::{Action, KeyBindings},
+    nostr::OutgoingNotes,
+    notifications::{Notification, Severity},
+    origin::FloatingOrigin,
+    picking::{block_details_note, voxel_coordinates, BlockNotes, InspectedBlock, Selection},
+    protocol::{POW_BLOCK_KIND, REACTION_KIND},
+    resources::{CoordinatesMap, Profiles},
+    UserNostrKeys,
+};
+
+// NIP-25 likes, a dislike is "-" and anything else, emoji included, counts as a like
+const LIKE: &str = "+";
+const DISLIKE: &str = "-";
+// Blocks whose reactions are kept, the ones reacted to longest ago are forgotten first
+const MAX_REACTED_BLOCKS: usize = 4096;
+// Authors counted per block, later reactions to a block past it are ignored
+const MAX_BLOCK_REACTIONS: usize = 1024;
+
+pub fn reactions_plugin(app: &mut App) {
+    app.init_resource::<BlockReactions>()
+        .add_event::<ReactionReceived>()
+        .add_systems(Update, (record_reactions, like_block).chain());
+}
+
+// A kind 7 reaction to a block's note, read from a relay
+#[derive(Event, Debug, Clone, PartialEq)]
+pub struct ReactionReceived {
+    pub author: String,
+    pub note_id: String,
+    pub content: String,
+}
+
+// Reactions per block note id, one per author so relays sending the same one don't add up
+#[derive(Resource, Default)]
+pub struct BlockReactions {
+    reactions: HashMap<String, HashMap<String, String>>,
+    // Note ids in the order they were first reacted to
+    order: VecDeque<String>,
+}
+
+impl BlockReactions {
+    fn record(&mut self, note_id: &str, author: &str, content: &str) {
+        if !self.reactions.contains_key(note_id) {
+            if self.order.len() >= MAX_REACTED_BLOCKS {
+                if let Some(oldest) = self.order.pop_front() {
+                    self.reactions.remove(&oldest);
+                }
+            }
+            self.order.push_back(note_id.to_string());
+        }
+        let reactions = self.reactions.entry(note_id.to_string()).or_default();
+        if reactions.len() < MAX_BLOCK_REACTIONS || reactions.contains_key(author) {
+            reactions.insert(author.to_string(), content.to_string());
+        }
+    }
+
+    // Likes and dislikes of a block's note
+    pub fn counts(&self, note_id: &str) -> (usize, usize) {
+        let Some(reactions) = self.reactions.get(note_id) else {
+            return (0, 0);
+        };
+        let dislikes = reactions
+            .values()
+            .filter(|content| content.as_str() == DISLIKE)
+            .count();
+        (reactions.len() - dislikes, dislikes)
+    }
+
+    pub fn reacted(&self, note_id: &str, pubkey: &str) -> bool {
+        self.reactions
+            .get(note_id)
+            .is_some_and(|reactions| reactions.contains_key(pubkey))
+    }
+}
+
+fn record_reactions(
+    mut received: EventReader<ReactionReceived>,
+    mut block_reactions: ResMut<BlockReactions>,
+) {
+    for reaction in received.read() {
+        block_reactions.record(&reaction.note_id, &reaction.author, &reaction.content);
+    }
+}
+
+fn reaction_tags(note_id: &str, miner_pubkey: &str) -> Vec<Vec<String>> {
+    vec![
+        vec!["e".to_string(), note_id.to_string()],
+        vec!["p".to_string(), miner_pubkey.to_string()],
+        vec!["k".to_string(), POW_BLOCK_KIND.to_string()],
+    ]
+}
+
+// Likes the inspected block, or the one pointed at when nothing is inspected
+fn like_block(
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    key_bindings: Res<KeyBindings>,
+    inspected_block: Res<InspectedBlock>,
+    selection: Res<Selection>,
+    origin: Res<FloatingOrigin>,
+    world_chunks: Res<WorldChunks>,
+    coordinates_map: Res<CoordinatesMap>,
+    block_notes: Res<BlockNotes>,
+    user_keys: Res<UserNostrKeys>,
+    outgoing_notes: Res<OutgoingNotes>,
+    profiles: Res<Profiles>,
+    mut block_reactions: ResMut<BlockReactions>,
+    mut notifications: EventWriter<Notification>,
+) {
+    if !keyboard_input.just_pressed(key_bindings.key(Action::LikeBlock)) {
+        return;
+    }
+    let coordinates = inspected_block.0.clone().or_else(|| {
+        selection
+            .block
+            .map(|block| voxel_coordinates(block, &origin, world_chunks.plane()))
+    });
+    let Some((_, block_details)) =
+        coordinates.and_then(|coordinates| coordinates_map.get(&coordinates))
+    else {
+        return;
+    };
+    // Blocks that never came from a relay have no note to react to
+    let Some(note_id) =
+        block_details_note(&block_notes, block_details).map(|note| note.get_id().to_string())
+    else {
+        notifications.send(Notification {
+            message: "This block has no note to like yet".to_string(),
+            severity: Severity::Warning,
+            play_sound: false,
+        });
+        return;
+    };
+    let our_pubkey = user_keys.get_public_key();
+    if block_reactions.reacted(&note_id, &our_pubkey) {
+        notifications.send(Notification {
+            message: "You already reacted to this block".to_string(),
+            severity: Severity::Info,
+            play_sound: false,
+        });
+        return;
+    }
+
+    let mut note = Note::new(our_pubkey.clone(), REACTION_KIND, LIKE);
+    note.tags = reaction_tags(&note_id, &block_details.miner_pubkey);
+    let _sent = outgoing_notes.send(user_keys.get_keypair().sign_nostr_event(note));
+    block_reactions.record(&note_id, &our_pubkey, LIKE);
+    notifications.send(Notification {
+        message: format!(
+            "Liked {}'s block",
+            profiles.display_name(block_details.owner())
+        ),
+        severity: Severity::Success,
+        play_sound: false,
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reactions_count_once_per_author() {
+        let mut block_reactions = BlockReactions::default();
+        for (author, content) in [("aa", "+"), ("aa", "+"), ("bb", "🔥"), ("cc", "-")] {
+            block_reactions.record("note", author, content);
+        }
+        assert_eq!(block_reactions.counts("note"), (2, 1));
+        assert_eq!(block_reactions.counts("other"), (0, 0));
+        assert!(block_reactions.reacted("note", "cc"));
+        assert!(!block_reactions.reacted("note", "dd"));
+    }
+
+    #[test]
+    fn the_longest_reacted_blocks_are_forgotten_first() {
+        let mut block_reactions = BlockReactions::default();
+        for block in 0..=MAX_REACTED_BLOCKS {
+            block_reactions.record(&block.to_string(), "aa", LIKE);
+        }
+        assert_eq!(block_reactions.counts("0"), (0, 0));
+        assert_eq!(block_reactions.counts("1"), (1, 0));
+        assert_eq!(block_reactions.reactions.len(), MAX_REACTED_BLOCKS);
+    }
+}
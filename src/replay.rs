@@ -0,0 +1,518 @@
+use std::{
+    collections::VecDeque,
+    fs::File,
+    io::{BufWriter, Write},
+    time::Duration,
+};
+
+use bevy::{
+    input::{
+        keyboard::KeyboardInput,
+        mouse::{MouseButtonInput, MouseMotion, MouseWheel},
+        InputSystem,
+    },
+    prelude::*,
+    time::{TimeSystem, TimeUpdateStrategy},
+    window::PrimaryWindow,
+};
+use crossbeam_channel::{unbounded, Receiver, Sender};
+use nostro2::notes::SignedNote;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    cameras::{BlockIndicator, CameraMode, ExplorerCamera, OrbitDistance},
+    chunks::WorldChunks,
+    cyberspace::CyberspaceCoordinates,
+    menu::AppState,
+    nostr::{websocket_middleware, IncomingNotes, ReceivedNote, RelayConnector},
+    notifications::{Notification, Severity},
+    origin::FloatingOrigin,
+};
+
+pub fn replay_plugin(app: &mut App) {
+    app.init_resource::<ReplayMode>()
+        .init_resource::<ReplayClock>()
+        .add_systems(PostStartup, tap_incoming_notes.run_if(replay_active))
+        .add_systems(OnEnter(AppState::Menu), skip_menu.run_if(replaying))
+        .add_systems(
+            OnEnter(AppState::Loading),
+            (
+                start_replay_clock,
+                start_recording.run_if(recording),
+                start_replay_time.run_if(replaying),
+            )
+                .chain(),
+        )
+        .add_systems(OnEnter(AppState::InGame), record_start.run_if(recording))
+        .add_systems(First, record_frame.after(TimeSystem).run_if(recording))
+        .add_systems(
+            PreUpdate,
+            (
+                record_inputs.after(InputSystem).run_if(recording),
+                play_replay
+                    .before(InputSystem)
+                    .run_if(replaying.and_then(not(in_state(AppState::Menu)))),
+            ),
+        )
+        // Replayed notes come back from the verifiers the same way
+        .add_systems(
+            Update,
+            record_incoming_notes
+                .before(websocket_middleware)
+                .run_if(replay_active),
+        )
+        .add_systems(Last, flush_recording.run_if(recording));
+}
+
+// `--record <file>` writes every relay note and input to a replay file, `--replay <file>` plays
+// one back without connecting to any relay
+#[derive(Resource, Debug, Clone, Default, PartialEq)]
+pub enum ReplayMode {
+    #[default]
+    Off,
+    Record(String),
+    Play(String),
+}
+
+impl ReplayMode {
+    pub fn from_args(args: &[String]) -> Self {
+        let value_of = |flag: &str| {
+            args.iter()
+                .position(|arg| arg == flag)
+                .and_then(|index| args.get(index + 1))
+                .cloned()
+        };
+        if let Some(path) = value_of("--replay") {
+            ReplayMode::Play(path)
+        } else if let Some(path) = value_of("--record") {
+            ReplayMode::Record(path)
+        } else {
+            ReplayMode::Off
+        }
+    }
+}
+
+pub fn replaying(mode: Res<ReplayMode>) -> bool {
+    matches!(*mode, ReplayMode::Play(_))
+}
+
+fn recording(mode: Res<ReplayMode>) -> bool {
+    matches!(*mode, ReplayMode::Record(_))
+}
+
+fn replay_active(mode: Res<ReplayMode>) -> bool {
+    *mode != ReplayMode::Off
+}
+
+// One line of a replay file, `at` is in seconds since loading started. Every frame starts with
+// its length and is followed by what happened in it, playback steps time by those lengths
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct ReplayEntry {
+    at: f64,
+    event: ReplayEvent,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "snake_case")]
+enum ReplayEvent {
+    // Where the indicator and the orbit camera were once the game started
+    Start {
+        indicator: String,
+        camera: Option<Transform>,
+    },
+    Frame(f64),
+    Note(SignedNote),
+    Key(KeyboardInput),
+    MouseButton(MouseButtonInput),
+    MouseMotion(MouseMotion),
+    MouseWheel(MouseWheel),
+    Cursor(Vec2),
+}
+
+// Seconds since loading started, for both recording and playback
+#[derive(Resource, Default)]
+struct ReplayClock(Option<f64>);
+
+impl ReplayClock {
+    fn now(&self, time: &Time) -> f64 {
+        self.0
+            .map(|started| time.elapsed_seconds_f64() - started)
+            .unwrap_or_default()
+    }
+}
+
+// Notes from the relays pass through here before the middleware sees them, so they can be
+// written down. When playing, the replay is the only source
+#[derive(Resource)]
+struct NoteTap {
+    relays: Receiver<ReceivedNote>,
+    middleware: Sender<ReceivedNote>,
+}
+
+#[derive(Resource)]
+struct ReplayRecorder(BufWriter<File>);
+
+impl ReplayRecorder {
+    fn write(&mut self, at: f64, event: ReplayEvent) {
+        let entry = ReplayEntry { at, event };
+        match serde_json::to_string(&entry) {
+            Ok(line) => {
+                let _ = writeln!(self.0, "{}", line);
+            }
+            Err(error) => warn!("Could not serialize a replay entry: {}", error),
+        }
+    }
+}
+
+#[derive(Resource, Default)]
+struct ReplayPlayer {
+    entries: VecDeque<ReplayEntry>,
+    // Notes due but not yet taken by the verifiers, their queue may be full
+    unverified: VecDeque<SignedNote>,
+    finished: bool,
+}
+
+// Lines that don't parse are skipped, a recording cut off by a crash still plays
+fn parse_replay(replay: &str) -> VecDeque<ReplayEntry> {
+    replay
+        .lines()
+        .filter_map(|line| serde_json::from_str(line).ok())
+        .collect()
+}
+
+fn frame_length(entry: &ReplayEntry) -> Option<Duration> {
+    match entry.event {
+        ReplayEvent::Frame(seconds) => {
+            Some(Duration::try_from_secs_f64(seconds).unwrap_or_default())
+        }
+        _ => None,
+    }
+}
+
+// Takes the frame at the front and everything that happened in it, with the length of the frame
+// after it. None once the recording ran out
+fn next_frame(entries: &mut VecDeque<ReplayEntry>) -> (Vec<ReplayEvent>, Option<Duration>) {
+    if entries.front().and_then(frame_length).is_some() {
+        entries.pop_front();
+    }
+    let mut events = Vec::new();
+    while let Some(entry) = entries.front() {
+        if let Some(length) = frame_length(entry) {
+            return (events, Some(length));
+        }
+        if let Some(entry) = entries.pop_front() {
+            events.push(entry.event);
+        }
+    }
+    (events, None)
+}
+
+fn tap_incoming_notes(
+    mut commands: Commands,
+    mode: Res<ReplayMode>,
+    incoming_notes: Res<IncomingNotes>,
+) {
+    // Both ends are systems, and what comes in is already held back by the relays' channel
+    let (middleware, notes) = unbounded();
+    commands.insert_resource(NoteTap {
+        relays: incoming_notes.0.clone(),
+        middleware,
+    });
+    commands.insert_resource(IncomingNotes(notes));
+    if let ReplayMode::Play(path) = &*mode {
+        match std::fs::read_to_string(path) {
+            Ok(replay) => commands.insert_resource(ReplayPlayer {
+                entries: parse_replay(&replay),
+                ..Default::default()
+            }),
+            Err(error) => warn!("Could not read {}: {}", path, error),
+        }
+    }
+}
+
+// Straight into the world, the replay starts from where the recording did
+fn skip_menu(mut next_state: ResMut<NextState<AppState>>) {
+    next_state.set(AppState::Loading);
+}
+
+fn start_replay_clock(time: Res<Time>, mut clock: ResMut<ReplayClock>) {
+    clock.0 = Some(time.elapsed_seconds_f64());
+}
+
+// The first recorded frame's length is used from the next frame on
+fn start_replay_time(
+    player: Option<Res<ReplayPlayer>>,
+    mut time_update: ResMut<TimeUpdateStrategy>,
+) {
+    if let Some(length) = player.and_then(|player| player.entries.front().and_then(frame_length)) {
+        *time_update = TimeUpdateStrategy::ManualDuration(length);
+    }
+}
+
+fn start_recording(mut commands: Commands, mode: Res<ReplayMode>) {
+    let ReplayMode::Record(path) = &*mode else {
+        return;
+    };
+    match File::create(path) {
+        Ok(file) => {
+            info!("Recording to {}", path);
+            commands.insert_resource(ReplayRecorder(BufWriter::new(file)));
+        }
+        Err(error) => warn!("Could not write {}: {}", path, error),
+    }
+}
+
+fn record_start(
+    time: Res<Time>,
+    clock: Res<ReplayClock>,
+    origin: Res<FloatingOrigin>,
+    world_chunks: Res<WorldChunks>,
+    camera_mode: Res<CameraMode>,
+    recorder: Option<ResMut<ReplayRecorder>>,
+    block_indicator: Query<&Transform, With<BlockIndicator>>,
+    camera_query: Query<&Transform, With<ExplorerCamera>>,
+) {
+    let (Some(mut recorder), Ok(indicator)) = (recorder, block_indicator.get_single()) else {
+        return;
+    };
+    recorder.write(
+        clock.now(&time),
+        ReplayEvent::Start {
+            indicator: origin.encode(indicator.translation, world_chunks.plane()),
+            camera: camera_query
+                .get_single()
+                .ok()
+                .map(|transform| camera_mode.orbit_transform(*transform)),
+        },
+    );
+}
+
+fn record_incoming_notes(
+    time: Res<Time>,
+    clock: Res<ReplayClock>,
+    note_tap: Option<Res<NoteTap>>,
+    mut recorder: Option<ResMut<ReplayRecorder>>,
+) {
+    let Some(note_tap) = note_tap else {
+        return;
+    };
+    let at = clock.now(&time);
+    for received_note in note_tap.relays.try_iter() {
+        if let Some(recorder) = recorder.as_mut() {
+            recorder.write(at, ReplayEvent::Note(received_note.note.clone()));
+        }
+        let _ = note_tap.middleware.send(received_note);
+    }
+}
+
+fn record_frame(
+    time: Res<Time>,
+    clock: Res<ReplayClock>,
+    recorder: Option<ResMut<ReplayRecorder>>,
+) {
+    if let Some(mut recorder) = recorder {
+        recorder.write(
+            clock.now(&time),
+            ReplayEvent::Frame(time.delta_seconds_f64()),
+        );
+    }
+}
+
+fn record_inputs(
+    time: Res<Time>,
+    clock: Res<ReplayClock>,
+    recorder: Option<ResMut<ReplayRecorder>>,
+    mut keys: EventReader<KeyboardInput>,
+    mut mouse_buttons: EventReader<MouseButtonInput>,
+    mut mouse_motion: EventReader<MouseMotion>,
+    mut mouse_wheel: EventReader<MouseWheel>,
+    mut cursor_moved: EventReader<CursorMoved>,
+) {
+    let Some(mut recorder) = recorder else {
+        return;
+    };
+    let at = clock.now(&time);
+    let events = keys
+        .read()
+        .cloned()
+        .map(ReplayEvent::Key)
+        .chain(mouse_buttons.read().cloned().map(ReplayEvent::MouseButton))
+        .chain(mouse_motion.read().cloned().map(ReplayEvent::MouseMotion))
+        .chain(mouse_wheel.read().cloned().map(ReplayEvent::MouseWheel))
+        .chain(
+            cursor_moved
+                .read()
+                .map(|moved| ReplayEvent::Cursor(moved.position)),
+        )
+        .collect::<Vec<_>>();
+    for event in events {
+        recorder.write(at, event);
+    }
+}
+
+fn flush_recording(recorder: Option<ResMut<ReplayRecorder>>) {
+    if let Some(mut recorder) = recorder {
+        if let Err(error) = recorder.0.flush() {
+            warn!("Could not write the replay: {}", error);
+        }
+    }
+}
+
+// Checked like notes from a relay, in the order they were recorded
+fn queue_replayed_notes(connector: &RelayConnector, unverified: &mut VecDeque<SignedNote>) {
+    while let Some(note) = unverified.pop_front() {
+        if let Err(note) = connector.queue_unverified(note) {
+            unverified.push_front(note);
+            break;
+        }
+    }
+}
+
+// Plays one recorded frame as if it came from the relays and the window, inputs go to the primary
+// window whatever window they were recorded in. Real input is dropped so it can't change the run
+fn play_replay(
+    connector: Res<RelayConnector>,
+    player: Option<ResMut<ReplayPlayer>>,
+    mut time_update: ResMut<TimeUpdateStrategy>,
+    mut origin: ResMut<FloatingOrigin>,
+    mut orbit_distance: ResMut<OrbitDistance>,
+    mut windows: Query<(Entity, &mut Window), With<PrimaryWindow>>,
+    mut block_indicator: Query<&mut Transform, (With<BlockIndicator>, Without<ExplorerCamera>)>,
+    mut camera_query: Query<&mut Transform, (With<ExplorerCamera>, Without<BlockIndicator>)>,
+    mut keys: ResMut<Events<KeyboardInput>>,
+    mut mouse_buttons: ResMut<Events<MouseButtonInput>>,
+    mut mouse_motion: ResMut<Events<MouseMotion>>,
+    mut mouse_wheel: ResMut<Events<MouseWheel>>,
+    mut cursor_moved: ResMut<Events<CursorMoved>>,
+    mut notifications: EventWriter<Notification>,
+    mut cursor: Local<Option<Vec2>>,
+) {
+    keys.clear();
+    mouse_buttons.clear();
+    mouse_motion.clear();
+    mouse_wheel.clear();
+    cursor_moved.clear();
+    let Some(mut player) = player else {
+        return;
+    };
+    let Ok((window_entity, mut window)) = windows.get_single_mut() else {
+        return;
+    };
+    if player.finished {
+        queue_replayed_notes(&connector, &mut player.unverified);
+        return;
+    }
+    let (events, next_frame_length) = next_frame(&mut player.entries);
+    for event in events {
+        match event {
+            ReplayEvent::Start { indicator, camera } => {
+                if let Ok(indicator) = CyberspaceCoordinates::from_hex(&indicator) {
+                    origin.reset(indicator);
+                    for mut transform in block_indicator.iter_mut() {
+                        transform.translation = origin.position(indicator);
+                    }
+                }
+                if let Some(camera) = camera {
+                    for mut transform in camera_query.iter_mut() {
+                        *transform = camera;
+                    }
+                    **orbit_distance = camera.translation.length();
+                }
+            }
+            ReplayEvent::Frame(_) => {}
+            ReplayEvent::Note(note) => player.unverified.push_back(note),
+            ReplayEvent::Key(key) => {
+                keys.send(KeyboardInput {
+                    window: window_entity,
+                    ..key
+                });
+            }
+            ReplayEvent::MouseButton(button) => {
+                mouse_buttons.send(MouseButtonInput {
+                    window: window_entity,
+                    ..button
+                });
+            }
+            ReplayEvent::MouseMotion(motion) => {
+                mouse_motion.send(motion);
+            }
+            ReplayEvent::MouseWheel(wheel) => {
+                mouse_wheel.send(MouseWheel {
+                    window: window_entity,
+                    ..wheel
+                });
+            }
+            ReplayEvent::Cursor(position) => *cursor = Some(position),
+        }
+    }
+    queue_replayed_notes(&connector, &mut player.unverified);
+    // The real mouse would move the cursor away from where the recording left it
+    if let Some(position) = *cursor {
+        if window.cursor_position() != Some(position) {
+            window.set_cursor_position(Some(position));
+        }
+    }
+    if let Some(length) = next_frame_length {
+        *time_update = TimeUpdateStrategy::ManualDuration(length);
+        return;
+    }
+    *time_update = TimeUpdateStrategy::Automatic;
+    player.finished = true;
+    notifications.send(Notification {
+        message: "Replay finished".to_string(),
+        severity: Severity::Info,
+        play_sound: false,
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(input: &str) -> Vec<String> {
+        input.split_whitespace().map(str::to_string).collect()
+    }
+
+    #[test]
+    fn replay_flags_pick_the_mode() {
+        assert_eq!(ReplayMode::from_args(&args("")), ReplayMode::Off);
+        assert_eq!(
+            ReplayMode::from_args(&args("--record demo.jsonl")),
+            ReplayMode::Record("demo.jsonl".to_string())
+        );
+        assert_eq!(
+            ReplayMode::from_args(&args("--record a.jsonl --replay b.jsonl")),
+            ReplayMode::Play("b.jsonl".to_string())
+        );
+        assert_eq!(ReplayMode::from_args(&args("--replay")), ReplayMode::Off);
+    }
+
+    #[test]
+    fn cut_off_lines_are_skipped() {
+        let replay = r#"{"at":0.5,"event":{"cursor":[10.0,20.0]}}
+{"at":1.0,"event":{"curs"#;
+        let entries = parse_replay(replay);
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].at, 0.5);
+        let ReplayEvent::Cursor(position) = entries[0].event else {
+            panic!("expected a cursor entry");
+        };
+        assert_eq!(position, Vec2::new(10.0, 20.0));
+    }
+
+    #[test]
+    fn frames_play_their_own_entries() {
+        let replay = r#"{"at":0.0,"event":{"frame":0.02}}
+{"at":0.0,"event":{"cursor":[1.0,2.0]}}
+{"at":0.02,"event":{"frame":0.05}}
+{"at":0.07,"event":{"frame":0.01}}"#;
+        let mut entries = parse_replay(replay);
+        let (events, length) = next_frame(&mut entries);
+        assert!(matches!(events[..], [ReplayEvent::Cursor(_)]));
+        assert_eq!(length, Some(Duration::from_secs_f64(0.05)));
+        let (events, length) = next_frame(&mut entries);
+        assert!(events.is_empty());
+        assert_eq!(length, Some(Duration::from_secs_f64(0.01)));
+        assert_eq!(next_frame(&mut entries).1, None);
+    }
+}
@@ -1,12 +1,22 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
 use bevy::{
+    asset::LoadState,
     pbr::CascadeShadowConfigBuilder,
     prelude::*,
     utils::{HashMap, HashSet},
 };
 
+use bech32::{FromBase32, ToBase32, Variant};
+
 use crate::{
-    cyberspace::{extract_coordinates, scale_coordinates_to_world},
-    nostr::POWBlockDetails,
+    block_effects::SpawnAnimation,
+    cameras::{BlockIndicator, ExplorerCamera},
+    cyberspace::{CyberspaceCoordinates, Sector},
+    keybindings::{Action, KeyBindings},
+    nip05::VerifiedNames,
+    origin::{CyberspacePosition, FloatingOrigin},
+    protocol::{POWBlockDetails, ProfileMetadata},
 };
 
 pub const BRONZE: Color = Color::rgba_linear(0.804, 0.498, 0.196, 1.0);
@@ -17,15 +27,73 @@ pub const ADAMANT: Color = Color::rgba_linear(0.443 * 10., 0.651 * 10., 0.475 *
 pub const RUNE: Color = Color::rgba_linear(0.416 * 10., 0.569 * 10., 0.824 * 10., 1.0);
 pub const GOLD: Color = Color::rgba_linear(0.855 * 10., 0.647 * 10., 0.125 * 10., 1.0);
 
+// Okabe-Ito palette, distinguishable under the common forms of color blindness
+pub const OWNER_PALETTE: [Color; 8] = [
+    Color::rgb(0.902, 0.624, 0.0),
+    Color::rgb(0.337, 0.706, 0.914),
+    Color::rgb(0.0, 0.620, 0.451),
+    Color::rgb(0.941, 0.894, 0.259),
+    Color::rgb(0.0, 0.447, 0.698),
+    Color::rgb(0.835, 0.369, 0.0),
+    Color::rgb(0.800, 0.475, 0.655),
+    Color::rgb(0.600, 0.600, 0.600),
+];
+
+// Followed keys stand out from the owner palette, their avatars and blocks are tinted with it
+pub const FRIEND_COLOR: Color = Color::rgb(0.2, 0.5, 1.0);
+
+// How far the ambient light is pulled from white towards the sector owner's color
+const SECTOR_TINT_STRENGTH: f32 = 0.35;
+
 const STAR_COLOR: Color = Color::rgba_linear(1000.0, 1000., 1000., 0.01);
 
+const AVATAR_RENDER_DISTANCE: f32 = 1024.0;
+const MAX_AVATAR_ENTITIES: usize = 512;
+const AVATAR_BUDGET_REFRESH_DISTANCE: f32 = 64.0;
+
 const BLOCK_SIZE: Vec3 = Vec3::splat(0.5);
 const PUBKEY_SIZE: f32 = 1.0;
+const AVATAR_RADIUS: f32 = 0.6;
+const AVATAR_LENGTH: f32 = 1.2;
+// Avatars glow in their owner color
+const AVATAR_EMISSIVE: f32 = 4.0;
+// Nametags float above the avatar and are only shown this close to the camera
+const NAMETAG_OFFSET: f32 = 2.0;
+const NAMETAG_DISTANCE: f32 = 256.0;
+const NAMETAG_FONT: f32 = 12.0;
+// Avatars close this fraction of the gap to their last drift position every second
+const DRIFT_SMOOTHING: f32 = 4.0;
+// Further jumps are teleports, the avatar snaps there instead of flying across the world
+const DRIFT_SNAP_DISTANCE: f32 = 256.0;
+// Avatar glow for keys active within the last 15 minutes, the last day, and before that
+const PRESENCE_BRIGHTNESS: [f32; 3] = [1.0, 0.35, 0.08];
+pub const ACTIVE_SECONDS: u64 = 15 * 60;
+const RECENT_SECONDS: u64 = 24 * 60 * 60;
+const PRESENCE_REFRESH: Duration = Duration::from_secs(1);
 
 pub fn world_plugin(app: &mut App) {
     app.init_resource::<UniqueKeys>()
         .init_resource::<CoordinatesMap>()
-        .add_systems(Startup, setup_world);
+        .init_resource::<DerezzTombstones>()
+        .init_resource::<SectorAmbience>()
+        .init_resource::<AvatarEntities>()
+        .init_resource::<FollowList>()
+        .init_resource::<Profiles>()
+        .init_resource::<DriftPositions>()
+        .init_resource::<LastSeen>()
+        .add_systems(Startup, setup_world)
+        .add_systems(
+            Update,
+            (
+                toggle_sector_ambience,
+                update_sector_ambience,
+                budget_avatar_entities,
+                position_nametags,
+                update_nametags,
+                drift_avatars,
+                update_avatar_presence,
+            ),
+        );
 }
 
 #[derive(Resource, Deref, DerefMut, Debug)]
@@ -37,8 +105,9 @@ impl Default for UniqueKeys {
     }
 }
 
+// Every mined block we know of, with its entity while its sector is loaded
 #[derive(Resource, Deref, DerefMut, Debug)]
-pub struct CoordinatesMap(pub HashMap<String, (Entity, POWBlockDetails)>);
+pub struct CoordinatesMap(pub HashMap<String, (Option<Entity>, POWBlockDetails)>);
 
 impl Default for CoordinatesMap {
     fn default() -> Self {
@@ -46,9 +115,27 @@ impl Default for CoordinatesMap {
     }
 }
 
+// Strongest derezz POW seen at each coordinate. Relays send the newest notes first, so a derezz
+// often arrives before the block it removed
+#[derive(Resource, Deref, DerefMut, Debug, Default)]
+pub struct DerezzTombstones(pub HashMap<String, usize>);
+
+impl DerezzTombstones {
+    pub fn record(&mut self, coordinates: &str, pow_amount: usize) {
+        let best = self.entry(coordinates.to_string()).or_insert(pow_amount);
+        *best = (*best).max(pow_amount);
+    }
+
+    // Whether a derezz seen so far removes a block with this much POW
+    pub fn buries(&self, coordinates: &str, pow_amount: usize) -> bool {
+        self.get(coordinates).is_some_and(|best| *best > pow_amount)
+    }
+}
+
 #[derive(Resource)]
 pub struct MeshesAndMaterials {
     pub pubkey_mesh: Handle<Mesh>,
+    pub avatar_mesh: Handle<Mesh>,
     pub cube_mesh: Handle<Mesh>,
     pub clear_material: Handle<StandardMaterial>,
     pub mud_material: Handle<StandardMaterial>,
@@ -59,6 +146,29 @@ pub struct MeshesAndMaterials {
     pub adamant_material: Handle<StandardMaterial>,
     pub rune_material: Handle<StandardMaterial>,
     pub gold_material: Handle<StandardMaterial>,
+    // In tier order. Kept so loading can wait for them, blocks spawned before they arrive show up
+    // untextured, and so the block atlas can stack them
+    pub block_textures: Vec<Handle<Image>>,
+    // One per owner color, each with a material per presence level
+    pub avatar_materials: Vec<Vec<Handle<StandardMaterial>>>,
+    // Followed avatars, one per presence level
+    pub friend_avatar_materials: Vec<Handle<StandardMaterial>>,
+}
+
+impl MeshesAndMaterials {
+    // Block textures the asset server is done with. One that failed to load counts as done, a
+    // missing file shows up as an untextured block rather than a loading screen that never ends
+    pub fn loaded_textures(&self, asset_server: &AssetServer) -> usize {
+        self.block_textures
+            .iter()
+            .filter(|texture| {
+                matches!(
+                    asset_server.get_load_state(texture.id()),
+                    Some(LoadState::Loaded | LoadState::Failed)
+                )
+            })
+            .count()
+    }
 }
 
 fn setup_world(
@@ -96,6 +206,8 @@ fn setup_world(
         ..Default::default()
     }));
 
+    let avatar_mesh = meshes.add(Mesh::from(Capsule3d::new(AVATAR_RADIUS, AVATAR_LENGTH)));
+
     let clear_material = materials.add(StandardMaterial {
         emissive: STAR_COLOR,
         alpha_mode: AlphaMode::Add,
@@ -104,7 +216,7 @@ fn setup_world(
 
     let clay_texture = asset_server.load("textures/clay.png");
     let mud_material = materials.add(StandardMaterial {
-        base_color_texture: Some(clay_texture),
+        base_color_texture: Some(clay_texture.clone()),
         metallic: 0.0,
         perceptual_roughness: 0.8,
         reflectance: 0.1,
@@ -113,7 +225,7 @@ fn setup_world(
 
     let bronze_texture = asset_server.load("textures/bronze.png");
     let bronze_material = materials.add(StandardMaterial {
-        base_color_texture: Some(bronze_texture),
+        base_color_texture: Some(bronze_texture.clone()),
         emissive: BRONZE,
         metallic: 0.8,
         perceptual_roughness: 0.4,
@@ -123,7 +235,7 @@ fn setup_world(
 
     let iron_texture = asset_server.load("textures/iron.png");
     let iron_material = materials.add(StandardMaterial {
-        base_color_texture: Some(iron_texture),
+        base_color_texture: Some(iron_texture.clone()),
         emissive: IRON,
         metallic: 0.8,
         perceptual_roughness: 0.3,
@@ -133,7 +245,7 @@ fn setup_world(
 
     let steel_texture = asset_server.load("textures/steel.png");
     let steel_material = materials.add(StandardMaterial {
-        base_color_texture: Some(steel_texture),
+        base_color_texture: Some(steel_texture.clone()),
         emissive: STEEL,
         metallic: 0.9,
         perceptual_roughness: 0.2,
@@ -143,7 +255,7 @@ fn setup_world(
 
     let mithril_texture = asset_server.load("textures/mithril.png");
     let mithril_material = materials.add(StandardMaterial {
-        base_color_texture: Some(mithril_texture),
+        base_color_texture: Some(mithril_texture.clone()),
         emissive: MITHRIL,
         metallic: 0.2,
         perceptual_roughness: 0.99,
@@ -156,7 +268,7 @@ fn setup_world(
 
     let adamant_texture = asset_server.load("textures/adamant.png");
     let adamant_material = materials.add(StandardMaterial {
-        base_color_texture: Some(adamant_texture),
+        base_color_texture: Some(adamant_texture.clone()),
         emissive: ADAMANT,
         metallic: 0.2,
         perceptual_roughness: 0.99,
@@ -169,7 +281,7 @@ fn setup_world(
 
     let rune_texture = asset_server.load("textures/rune.png");
     let rune_material = materials.add(StandardMaterial {
-        base_color_texture: Some(rune_texture),
+        base_color_texture: Some(rune_texture.clone()),
         emissive: RUNE,
         metallic: 0.2,
         perceptual_roughness: 0.99,
@@ -182,7 +294,7 @@ fn setup_world(
 
     let gold_texture = asset_server.load("textures/gold.png");
     let gold_material = materials.add(StandardMaterial {
-        base_color_texture: Some(gold_texture),
+        base_color_texture: Some(gold_texture.clone()),
         emissive: GOLD,
         metallic: 0.9,
         perceptual_roughness: 0.1,
@@ -190,8 +302,48 @@ fn setup_world(
         ..Default::default()
     });
 
+    let block_textures = vec![
+        clay_texture,
+        bronze_texture,
+        iron_texture,
+        steel_texture,
+        mithril_texture,
+        adamant_texture,
+        rune_texture,
+        gold_texture,
+    ];
+
+    let avatar_materials = OWNER_PALETTE
+        .iter()
+        .map(|color| {
+            PRESENCE_BRIGHTNESS
+                .iter()
+                .map(|brightness| {
+                    materials.add(StandardMaterial {
+                        base_color: *color * *brightness,
+                        emissive: *color * AVATAR_EMISSIVE * *brightness,
+                        perceptual_roughness: 0.3,
+                        ..Default::default()
+                    })
+                })
+                .collect()
+        })
+        .collect();
+    let friend_avatar_materials = PRESENCE_BRIGHTNESS
+        .iter()
+        .map(|brightness| {
+            materials.add(StandardMaterial {
+                base_color: FRIEND_COLOR * *brightness,
+                emissive: FRIEND_COLOR * AVATAR_EMISSIVE * *brightness,
+                perceptual_roughness: 0.3,
+                ..Default::default()
+            })
+        })
+        .collect();
+
     commands.insert_resource(MeshesAndMaterials {
         pubkey_mesh,
+        avatar_mesh,
         cube_mesh,
         clear_material,
         mud_material,
@@ -202,22 +354,38 @@ fn setup_world(
         adamant_material,
         rune_material,
         gold_material,
+        block_textures,
+        avatar_materials,
+        friend_avatar_materials,
     });
 }
 
 #[derive(Component, Clone)]
 pub struct POWBlock {
     pub pow_amount: usize,
+    pub material: Option<usize>,
     pub coordinate_string: String,
-    pub miner_pubkey: String,
+    // The miner, or whoever the block was transferred to
+    pub owner_pubkey: String,
 }
 
-pub fn spawn_mined_block(
-    commands: &mut Commands,
-    stuff: &Res<MeshesAndMaterials>,
-    block_details: &POWBlockDetails,
-) -> Entity {
-    let material = match block_details.pow_amount {
+// Gold, every tier past it looks the same
+pub const TOP_MATERIAL_TIER: usize = 8;
+
+// Each material tier takes another 4 bits of POW, one hex character of the note id
+pub fn material_tier(pow_bits: usize) -> usize {
+    pow_bits / 4
+}
+
+// Miners may pick any tier up to the one their POW reaches, otherwise the POW decides
+pub fn block_tier(pow_bits: usize, chosen: Option<usize>) -> usize {
+    let pow_tier = material_tier(pow_bits);
+    chosen.map_or(pow_tier, |chosen| chosen.min(pow_tier))
+}
+
+// For blocks drawn on their own, like the one being mined, sector batches use the block atlas
+pub fn tier_material(stuff: &MeshesAndMaterials, tier: usize) -> Handle<StandardMaterial> {
+    match tier {
         0 => stuff.mud_material.clone_weak(),
         1 => stuff.mud_material.clone_weak(),
         2 => stuff.bronze_material.clone_weak(),
@@ -227,38 +395,582 @@ pub fn spawn_mined_block(
         6 => stuff.adamant_material.clone_weak(),
         7 => stuff.rune_material.clone_weak(),
         _ => stuff.gold_material.clone_weak(),
-    };
+    }
+}
+
+pub fn material_name(pow_bits: usize) -> &'static str {
+    tier_name(material_tier(pow_bits))
+}
+
+pub fn tier_name(tier: usize) -> &'static str {
+    match tier {
+        0 | 1 => "Mud",
+        2 => "Bronze",
+        3 => "Iron",
+        4 => "Steel",
+        5 => "Mithril",
+        6 => "Adamant",
+        7 => "Rune",
+        _ => "Gold",
+    }
+}
 
+pub fn spawn_mined_block(commands: &mut Commands, block_details: &POWBlockDetails) -> Entity {
+    // Drawn as part of its sector's batch, see `chunks::rebuild_block_batches`, once it has grown
+    // in, see `block_effects::animate_spawning_blocks`
     let spawned_block = commands
         .spawn((
-            PbrBundle {
-                mesh: stuff.cube_mesh.clone_weak(),
-                material,
-                transform: Transform::from_translation(block_details.coordinates()),
-                ..Default::default()
-            },
+            TransformBundle::default(),
+            SpawnAnimation::default(),
+            CyberspacePosition(block_details.coordinates()),
             POWBlock {
                 pow_amount: block_details.pow_amount,
+                material: block_details.material,
                 coordinate_string: block_details.coordinates.clone(),
-                miner_pubkey: block_details.miner_pubkey.clone(),
+                owner_pubkey: block_details.owner().to_string(),
             },
         ))
         .id();
     spawned_block
 }
 
-pub fn spawn_pubkey_note(
+pub fn avatar_position(pubkey: &str) -> Option<CyberspaceCoordinates> {
+    CyberspaceCoordinates::from_hex(pubkey).ok()
+}
+
+// Last position each key broadcast in a drift note, keys without one stand at home
+#[derive(Resource, Deref, DerefMut, Debug, Default)]
+pub struct DriftPositions(pub HashMap<String, CyberspaceCoordinates>);
+
+impl DriftPositions {
+    pub fn position(&self, pubkey: &str) -> Option<CyberspaceCoordinates> {
+        self.get(pubkey)
+            .copied()
+            .or_else(|| avatar_position(pubkey))
+    }
+}
+
+// Unix time of the newest note seen from each key
+#[derive(Resource, Deref, DerefMut, Debug, Default)]
+pub struct LastSeen(pub HashMap<String, u64>);
+
+impl LastSeen {
+    pub fn record(&mut self, pubkey: &str, created_at: u64) {
+        let last_seen = self.entry(pubkey.to_string()).or_insert(created_at);
+        *last_seen = (*last_seen).max(created_at);
+    }
+
+    // Seconds since the key's newest note
+    pub fn age(&self, pubkey: &str) -> Option<u64> {
+        self.get(pubkey)
+            .map(|last_seen| unix_now().saturating_sub(*last_seen))
+    }
+
+    pub fn describe(&self, pubkey: &str) -> String {
+        match self.age(pubkey) {
+            Some(age) => format!("last seen {}", format_age(age)),
+            None => "never seen".to_string(),
+        }
+    }
+}
+
+pub fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0)
+}
+
+pub fn format_age(seconds: u64) -> String {
+    match seconds {
+        0..=59 => "just now".to_string(),
+        60..=3599 => format!("{}m ago", seconds / 60),
+        3600..=86399 => format!("{}h ago", seconds / 3600),
+        _ => format!("{}d ago", seconds / 86400),
+    }
+}
+
+fn presence_level(age: Option<u64>) -> usize {
+    match age {
+        Some(age) if age <= ACTIVE_SECONDS => 0,
+        Some(age) if age <= RECENT_SECONDS => 1,
+        _ => 2,
+    }
+}
+
+fn avatar_material(
+    stuff: &MeshesAndMaterials,
+    pubkey: &str,
+    followed: bool,
+    presence_level: usize,
+) -> Handle<StandardMaterial> {
+    if followed {
+        return stuff.friend_avatar_materials[presence_level].clone_weak();
+    }
+    stuff.avatar_materials[owner_palette_index(pubkey)][presence_level].clone_weak()
+}
+
+// Dims avatars as their keys go quiet, checked every second since time passes without new notes
+fn update_avatar_presence(
+    time: Res<Time>,
+    stuff: Res<MeshesAndMaterials>,
+    last_seen: Res<LastSeen>,
+    follow_list: Res<FollowList>,
+    added_avatars: Query<(), Added<Avatar>>,
+    mut avatar_query: Query<(&Avatar, &mut Handle<StandardMaterial>)>,
+    mut last_refresh: Local<Duration>,
+) {
+    if time.elapsed() - *last_refresh < PRESENCE_REFRESH
+        && added_avatars.is_empty()
+        && !follow_list.is_changed()
+    {
+        return;
+    }
+    *last_refresh = time.elapsed();
+    for (avatar, mut material) in avatar_query.iter_mut() {
+        let presence_level = presence_level(last_seen.age(&avatar.pubkey));
+        let followed = follow_list.contains(&avatar.pubkey);
+        let wanted = avatar_material(&stuff, &avatar.pubkey, followed, presence_level);
+        if *material != wanted {
+            *material = wanted;
+        }
+    }
+}
+
+// The pubkey an avatar entity stands for
+#[derive(Component, Clone)]
+pub struct Avatar {
+    pub pubkey: String,
+}
+
+// Screen space label following an avatar, despawned along with it
+#[derive(Component)]
+struct Nametag {
+    avatar: Entity,
+}
+
+pub fn npub(pubkey: &str) -> Option<String> {
+    let pubkey_bytes = hex::decode(pubkey).ok()?;
+    bech32::encode("npub", pubkey_bytes.to_base32(), Variant::Bech32).ok()
+}
+
+// Hex key from a pasted npub or hex key
+pub fn parse_pubkey(input: &str) -> Option<String> {
+    let input = input.trim();
+    if input.starts_with("npub1") {
+        let (hrp, data, _) = bech32::decode(input).ok()?;
+        let pubkey_bytes = Vec::<u8>::from_base32(&data).ok()?;
+        return (hrp == "npub" && pubkey_bytes.len() == 32).then(|| hex::encode(pubkey_bytes));
+    }
+    let pubkey_bytes = hex::decode(input).ok()?;
+    (pubkey_bytes.len() == 32).then(|| hex::encode(pubkey_bytes))
+}
+
+pub fn short_npub(pubkey: &str) -> String {
+    match npub(pubkey) {
+        Some(npub) if npub.len() > 16 => format!("{}...{}", &npub[..10], &npub[npub.len() - 6..]),
+        _ => pubkey.chars().take(16).collect(),
+    }
+}
+
+// Kind 0 profiles of the keys we have seen, the latest one received wins
+#[derive(Resource, Deref, DerefMut, Debug, Default)]
+pub struct Profiles(pub HashMap<String, ProfileMetadata>);
+
+impl Profiles {
+    // Profile name when we have one, the short npub otherwise
+    pub fn display_name(&self, pubkey: &str) -> String {
+        self.get(pubkey)
+            .and_then(|profile| profile.display_name())
+            .map(|name| name.to_string())
+            .unwrap_or_else(|| short_npub(pubkey))
+    }
+}
+
+pub fn spawn_avatar(
     commands: &mut Commands,
     stuff: &Res<MeshesAndMaterials>,
-    unique_key: String,
-) {
-    let (x, y, z) = extract_coordinates(&unique_key).unwrap();
-    let (scaled_x, scaled_y, scaled_z) = scale_coordinates_to_world(x, y, z);
+    name: String,
+    drift_positions: &DriftPositions,
+    origin: &FloatingOrigin,
+    pubkey: &str,
+) -> Entity {
+    let position = drift_positions
+        .position(pubkey)
+        .map(|position| origin.position(position))
+        .unwrap_or(Vec3::ZERO);
+    // Dimmest until the presence system knows better
+    let material = avatar_material(stuff, pubkey, false, PRESENCE_BRIGHTNESS.len() - 1);
+
+    let avatar = commands
+        .spawn((
+            PbrBundle {
+                mesh: stuff.avatar_mesh.clone_weak(),
+                material,
+                transform: Transform::from_translation(position),
+                ..Default::default()
+            },
+            Avatar {
+                pubkey: pubkey.to_string(),
+            },
+        ))
+        .id();
 
-    commands.spawn(PbrBundle {
-        mesh: stuff.pubkey_mesh.clone_weak(),
-        material: stuff.clear_material.clone_weak(),
-        transform: Transform::from_translation(Vec3::new(scaled_x, scaled_y, scaled_z)),
+    let nametag = TextBundle::from_section(
+        name,
+        TextStyle {
+            font_size: NAMETAG_FONT,
+            ..Default::default()
+        },
+    )
+    .with_style(Style {
+        position_type: PositionType::Absolute,
         ..Default::default()
     });
+    commands.spawn((nametag, Nametag { avatar }));
+    avatar
+}
+
+// Glides avatars toward the position they last broadcast
+fn drift_avatars(
+    time: Res<Time>,
+    drift_positions: Res<DriftPositions>,
+    origin: Res<FloatingOrigin>,
+    mut avatar_query: Query<(&Avatar, &mut Transform)>,
+) {
+    for (avatar, mut transform) in avatar_query.iter_mut() {
+        let Some(target) = drift_positions.get(&avatar.pubkey) else {
+            continue;
+        };
+        let target = origin.position(*target);
+        if transform.translation != target {
+            transform.translation = drift_step(transform.translation, target, time.delta_seconds());
+        }
+    }
+}
+
+// Eases toward the target independently of the frame rate
+fn drift_step(current: Vec3, target: Vec3, delta_seconds: f32) -> Vec3 {
+    if current.distance(target) > DRIFT_SNAP_DISTANCE {
+        return target;
+    }
+    let next = current.lerp(target, 1.0 - (-DRIFT_SMOOTHING * delta_seconds).exp());
+    // Close enough, stop moving the transform every frame
+    if next.distance(target) < 0.01 {
+        target
+    } else {
+        next
+    }
+}
+
+fn update_nametags(
+    profiles: Res<Profiles>,
+    verified_names: Res<VerifiedNames>,
+    avatar_query: Query<&Avatar>,
+    mut nametag_query: Query<(&Nametag, &mut Text)>,
+) {
+    if !profiles.is_changed() && !verified_names.is_changed() {
+        return;
+    }
+    for (nametag, mut text) in nametag_query.iter_mut() {
+        if let Ok(avatar) = avatar_query.get(nametag.avatar) {
+            let name = verified_names.display_name(&profiles, &avatar.pubkey);
+            if text.sections[0].value != name {
+                text.sections[0].value = name;
+            }
+        }
+    }
+}
+
+fn position_nametags(
+    mut commands: Commands,
+    camera_query: Query<(&Camera, &GlobalTransform), With<ExplorerCamera>>,
+    avatar_query: Query<&GlobalTransform, With<Avatar>>,
+    mut nametag_query: Query<(Entity, &Nametag, &mut Style, &mut Visibility)>,
+) {
+    let Ok((camera, camera_transform)) = camera_query.get_single() else {
+        return;
+    };
+    for (entity, nametag, mut style, mut visibility) in nametag_query.iter_mut() {
+        let Ok(avatar_transform) = avatar_query.get(nametag.avatar) else {
+            commands.entity(entity).despawn_recursive();
+            continue;
+        };
+        let position = avatar_transform.translation() + Vec3::Y * NAMETAG_OFFSET;
+        let viewport_position = camera
+            .world_to_viewport(camera_transform, position)
+            .filter(|_| camera_transform.translation().distance(position) <= NAMETAG_DISTANCE);
+        let wanted = match viewport_position {
+            Some(viewport_position) => {
+                style.left = Val::Px(viewport_position.x);
+                style.top = Val::Px(viewport_position.y);
+                Visibility::Inherited
+            }
+            None => Visibility::Hidden,
+        };
+        if *visibility != wanted {
+            *visibility = wanted;
+        }
+    }
+}
+
+// Avatars that currently have an entity in the world, every other key in `UniqueKeys` is data only
+#[derive(Resource, Deref, DerefMut, Debug, Default)]
+pub struct AvatarEntities(pub HashMap<String, Entity>);
+
+// Followed keys always get an avatar entity, regardless of distance.
+// Filled from our kind 3 contact list by the contacts plugin
+#[derive(Resource, Deref, DerefMut, Debug, Default)]
+pub struct FollowList(pub HashSet<String>);
+
+fn budget_avatar_entities(
+    mut commands: Commands,
+    stuff: Res<MeshesAndMaterials>,
+    unique_keys: Res<UniqueKeys>,
+    follow_list: Res<FollowList>,
+    profiles: Res<Profiles>,
+    verified_names: Res<VerifiedNames>,
+    drift_positions: Res<DriftPositions>,
+    origin: Res<FloatingOrigin>,
+    block_indicator: Query<&Transform, With<BlockIndicator>>,
+    mut avatar_entities: ResMut<AvatarEntities>,
+    mut last_position: Local<Option<CyberspaceCoordinates>>,
+) {
+    let Ok(indicator_transform) = block_indicator.get_single() else {
+        return;
+    };
+    let coordinates = origin.coordinates(indicator_transform.translation);
+    let position = indicator_transform.translation;
+
+    // Only rebuild the budget after moving a fair distance or when the keys change
+    let moved = last_position
+        .map(|last_position| {
+            coordinates.relative_to(last_position).length() > AVATAR_BUDGET_REFRESH_DISTANCE
+        })
+        .unwrap_or(true);
+    if !moved
+        && !unique_keys.is_changed()
+        && !follow_list.is_changed()
+        && !drift_positions.is_changed()
+    {
+        return;
+    }
+    *last_position = Some(coordinates);
+
+    // Followed avatars first, then the closest ones
+    let mut candidates: Vec<(bool, f32, &String)> = unique_keys
+        .iter()
+        .filter_map(|pubkey| {
+            let distance = origin
+                .position(drift_positions.position(pubkey)?)
+                .distance(position);
+            let followed = follow_list.contains(pubkey);
+            (followed || distance <= AVATAR_RENDER_DISTANCE).then_some((followed, distance, pubkey))
+        })
+        .collect();
+    candidates.sort_by(|a, b| b.0.cmp(&a.0).then(a.1.total_cmp(&b.1)));
+    let wanted: HashSet<&String> = candidates
+        .iter()
+        .take(MAX_AVATAR_ENTITIES)
+        .map(|(_, _, pubkey)| *pubkey)
+        .collect();
+
+    avatar_entities.retain(|pubkey, entity| {
+        if wanted.contains(pubkey) {
+            return true;
+        }
+        commands.entity(*entity).despawn();
+        false
+    });
+    for pubkey in wanted {
+        if !avatar_entities.contains_key(pubkey) {
+            let entity = spawn_avatar(
+                &mut commands,
+                &stuff,
+                verified_names.display_name(&profiles, pubkey),
+                &drift_positions,
+                &origin,
+                pubkey,
+            );
+            avatar_entities.insert(pubkey.clone(), entity);
+        }
+    }
+}
+
+pub fn owner_palette_index(pubkey: &str) -> usize {
+    let first_byte = hex::decode(pubkey)
+        .ok()
+        .and_then(|bytes| bytes.first().copied())
+        .unwrap_or(0);
+    first_byte as usize % OWNER_PALETTE.len()
+}
+
+pub fn pubkey_color(pubkey: &str) -> Color {
+    OWNER_PALETTE[owner_palette_index(pubkey)]
+}
+
+// Spreads keys over the whole hue circle, pubkeys are hashes so their first bytes are uniform
+pub fn owner_heat_color(pubkey: &str) -> Color {
+    let hue_bytes = hex::decode(pubkey.get(..4).unwrap_or_default()).unwrap_or_default();
+    let hue = match hue_bytes[..] {
+        [high, low] => u16::from_be_bytes([high, low]) as f32 / 65536.0 * 360.0,
+        _ => 0.0,
+    };
+    Color::hsl(hue, 0.9, 0.5)
+}
+
+#[derive(Resource)]
+pub struct SectorAmbience {
+    pub enabled: bool,
+    current_sector: Option<Sector>,
+}
+
+impl Default for SectorAmbience {
+    fn default() -> Self {
+        SectorAmbience {
+            enabled: true,
+            current_sector: None,
+        }
+    }
+}
+
+fn toggle_sector_ambience(
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    key_bindings: Res<KeyBindings>,
+    mut sector_ambience: ResMut<SectorAmbience>,
+) {
+    if keyboard_input.just_pressed(key_bindings.key(Action::ToggleAmbience)) {
+        sector_ambience.enabled = !sector_ambience.enabled;
+    }
+}
+
+fn update_sector_ambience(
+    block_indicator: Query<&Transform, With<BlockIndicator>>,
+    origin: Res<FloatingOrigin>,
+    coordinates_map: Res<CoordinatesMap>,
+    mut sector_ambience: ResMut<SectorAmbience>,
+    mut ambient_light: ResMut<AmbientLight>,
+) {
+    let Ok(indicator_transform) = block_indicator.get_single() else {
+        return;
+    };
+    let sector = origin.coordinates(indicator_transform.translation).sector();
+
+    // Only recompute when we move sectors, blocks change, or the toggle flips
+    if sector_ambience.current_sector == Some(sector)
+        && !coordinates_map.is_changed()
+        && !sector_ambience.is_changed()
+    {
+        return;
+    }
+    sector_ambience.bypass_change_detection().current_sector = Some(sector);
+
+    if !sector_ambience.enabled {
+        ambient_light.color = Color::WHITE;
+        return;
+    }
+
+    // Add up the POW each miner holds in this sector
+    let mut pow_by_miner: HashMap<&str, usize> = HashMap::new();
+    for (_, block_details) in coordinates_map.values() {
+        if block_details.sector() == sector {
+            *pow_by_miner.entry(block_details.owner()).or_insert(0) += block_details.pow_amount;
+        }
+    }
+
+    ambient_light.color = match pow_by_miner.iter().max_by_key(|(_, pow)| **pow) {
+        Some((miner, _)) => {
+            let [red, green, blue, _] = pubkey_color(miner).as_rgba_f32();
+            Color::rgb(
+                1.0 + (red - 1.0) * SECTOR_TINT_STRENGTH,
+                1.0 + (green - 1.0) * SECTOR_TINT_STRENGTH,
+                1.0 + (blue - 1.0) * SECTOR_TINT_STRENGTH,
+            )
+        }
+        None => Color::WHITE,
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn npub_encodes_the_hex_pubkey() {
+        let pubkey = "3bf0c63fcb93463407af97a5e5ee64fa883d107ef9e558472c4eb9aaaefa459d";
+        assert_eq!(
+            npub(pubkey).as_deref(),
+            Some("npub180cvv07tjdrrgpa0j7j7tmnyl2yr6yr7l8j4s3evf6u64th6gkwsyjh6w6")
+        );
+        assert_eq!(short_npub(pubkey), "npub180cvv...yjh6w6");
+    }
+
+    #[test]
+    fn pasted_keys_parse_to_hex() {
+        let pubkey = "3bf0c63fcb93463407af97a5e5ee64fa883d107ef9e558472c4eb9aaaefa459d";
+        let npub = "npub180cvv07tjdrrgpa0j7j7tmnyl2yr6yr7l8j4s3evf6u64th6gkwsyjh6w6";
+        assert_eq!(parse_pubkey(npub).as_deref(), Some(pubkey));
+        assert_eq!(
+            parse_pubkey(&format!(" {} ", pubkey.to_uppercase())).as_deref(),
+            Some(pubkey)
+        );
+        assert_eq!(parse_pubkey("npub1nope"), None);
+        assert_eq!(parse_pubkey("abcd"), None);
+    }
+
+    #[test]
+    fn heat_colors_follow_the_key() {
+        let pubkey = "3bf0c63fcb93463407af97a5e5ee64fa883d107ef9e558472c4eb9aaaefa459d";
+        assert_eq!(owner_heat_color(pubkey), owner_heat_color(pubkey));
+        assert_ne!(
+            owner_heat_color(pubkey),
+            owner_heat_color("8000000000000000000000000000000000000000000000000000000000000000")
+        );
+    }
+
+    #[test]
+    fn chosen_materials_are_capped_by_pow() {
+        assert_eq!(block_tier(20, None), 5);
+        assert_eq!(block_tier(20, Some(2)), 2);
+        assert_eq!(block_tier(20, Some(TOP_MATERIAL_TIER)), 5);
+    }
+
+    #[test]
+    fn presence_dims_with_age() {
+        assert_eq!(presence_level(Some(0)), 0);
+        assert_eq!(presence_level(Some(ACTIVE_SECONDS + 1)), 1);
+        assert_eq!(presence_level(Some(RECENT_SECONDS + 1)), 2);
+        assert_eq!(presence_level(None), 2);
+        assert_eq!(format_age(30), "just now");
+        assert_eq!(format_age(3 * 3600 + 20), "3h ago");
+        assert_eq!(format_age(2 * 86400), "2d ago");
+    }
+
+    #[test]
+    fn last_seen_keeps_the_newest_note() {
+        let mut last_seen = LastSeen::default();
+        last_seen.record("abc", 100);
+        last_seen.record("abc", 50);
+        assert_eq!(last_seen.get("abc"), Some(&100));
+    }
+
+    #[test]
+    fn tombstones_only_bury_weaker_blocks() {
+        let mut tombstones = DerezzTombstones::default();
+        tombstones.record("abc", 12);
+        tombstones.record("abc", 8);
+        assert!(tombstones.buries("abc", 11));
+        assert!(!tombstones.buries("abc", 12));
+        assert!(!tombstones.buries("def", 0));
+    }
+
+    #[test]
+    fn drifting_avatars_ease_in_and_snap_on_teleports() {
+        let target = Vec3::new(10.0, 0.0, 0.0);
+        let step = drift_step(Vec3::ZERO, target, 0.1);
+        assert!(step.x > 0.0 && step.x < target.x);
+        assert_eq!(drift_step(Vec3::ZERO, target, 10.0), target);
+        let far = Vec3::splat(DRIFT_SNAP_DISTANCE);
+        assert_eq!(drift_step(Vec3::ZERO, far, 0.1), far);
+    }
 }
@@ -0,0 +1,120 @@
+use std::{
+    fs::OpenOptions,
+    io::{BufWriter, Write},
+};
+
+use bevy::prelude::*;
+use bevy_tokio_tasks::TokioTasksRuntime;
+use crossbeam_channel::{unbounded, Sender};
+use nostro2::notes::SignedNote;
+
+use crate::{menu::AppState, nostr::RelayConnector};
+
+// Shown as the relay in the traffic panel and on accepted blocks
+pub const SANDBOX_RELAY_URL: &str = "sandbox";
+// Every note published in the sandbox, one JSON line each, sent back on the next launch
+const SANDBOX_FILE_PATH: &str = "./nostrcraft_sandbox.jsonl";
+
+pub fn sandbox_plugin(app: &mut App) {
+    app.init_resource::<SandboxMode>()
+        .add_systems(OnEnter(AppState::Loading), connect_sandbox.run_if(offline));
+}
+
+// `--offline` swaps the relays for a relay inside the game, so building and mining can be tried
+// without a connection and without publishing anything
+#[derive(Resource, Debug, Default, Deref, PartialEq)]
+pub struct SandboxMode(pub bool);
+
+impl SandboxMode {
+    pub fn from_args(args: &[String]) -> Self {
+        SandboxMode(args.iter().any(|arg| arg == "--offline"))
+    }
+}
+
+pub fn offline(sandbox_mode: Res<SandboxMode>) -> bool {
+    **sandbox_mode
+}
+
+fn load_sandbox_notes() -> Vec<SignedNote> {
+    let Ok(notes) = std::fs::read_to_string(SANDBOX_FILE_PATH) else {
+        return Vec::new();
+    };
+    parse_sandbox_notes(&notes)
+}
+
+// A line cut short when the game was killed is skipped
+fn parse_sandbox_notes(notes: &str) -> Vec<SignedNote> {
+    notes
+        .lines()
+        .filter_map(|line| serde_json::from_str(line).ok())
+        .collect()
+}
+
+// Every note the sandbox relay accepts is appended from a thread of its own, so the relay's task
+// never waits on the disk
+fn spawn_sandbox_store() -> Sender<SignedNote> {
+    let (store, notes) = unbounded::<SignedNote>();
+    std::thread::spawn(move || {
+        let file = match OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(SANDBOX_FILE_PATH)
+        {
+            Ok(file) => file,
+            Err(error) => {
+                warn!("Could not write {}: {}", SANDBOX_FILE_PATH, error);
+                return;
+            }
+        };
+        let mut file = BufWriter::new(file);
+        for note in notes {
+            let line = match serde_json::to_string(&note) {
+                Ok(line) => line,
+                Err(error) => {
+                    warn!("Could not serialize a sandbox note: {}", error);
+                    continue;
+                }
+            };
+            // Flushed right away, a killed game keeps what it published
+            if let Err(error) = writeln!(file, "{}", line).and_then(|_| file.flush()) {
+                warn!("Could not write {}: {}", SANDBOX_FILE_PATH, error);
+            }
+        }
+    });
+    store
+}
+
+fn connect_sandbox(runtime: Res<TokioTasksRuntime>, connector: Res<RelayConnector>) {
+    let stored_notes = load_sandbox_notes();
+    info!(
+        "Offline sandbox with {} notes from {}",
+        stored_notes.len(),
+        SANDBOX_FILE_PATH
+    );
+    connector.connect_sandbox(&runtime, stored_notes, spawn_sandbox_store());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn offline_flag_turns_the_sandbox_on() {
+        let args =
+            |input: &str| -> Vec<String> { input.split_whitespace().map(str::to_string).collect() };
+        assert_eq!(
+            SandboxMode::from_args(&args("--offline")),
+            SandboxMode(true)
+        );
+        assert_eq!(
+            SandboxMode::from_args(&args("coords 1 2 3")),
+            SandboxMode(false)
+        );
+    }
+
+    #[test]
+    fn broken_sandbox_lines_are_skipped() {
+        assert!(parse_sandbox_notes("{\"id\": \"cut off").is_empty());
+        assert!(parse_sandbox_notes("").is_empty());
+    }
+}
@@ -0,0 +1,317 @@
+use std::{io::Cursor, sync::Arc, time::Duration};
+
+use anyhow::anyhow;
+use bevy::{prelude::*, render::view::screenshot::ScreenshotManager, window::PrimaryWindow};
+use bevy_tokio_tasks::TokioTasksRuntime;
+use crossbeam_channel::{unbounded, Receiver, Sender};
+use cryptoxide::{digest::Digest, sha2::Sha256};
+use nostro2::{notes::Note, userkeys::UserKeys};
+use openssl::base64;
+use serde::Deserialize;
+
+use crate::{
+    cameras::BlockIndicator,
+    chunks::WorldChunks,
+    cyberspace::CyberspaceCoordinates,
+    keybindings::{Action, KeyBindings},
+    nostr::OutgoingNotes,
+    notifications::{Notification, Severity},
+    origin::FloatingOrigin,
+    protocol::{BLOSSOM_AUTH_KIND, CHAT_KIND},
+    resources::unix_now,
+    sandbox::SandboxMode,
+    settings::Settings,
+    UserNostrKeys,
+};
+
+const UPLOAD_TIMEOUT: Duration = Duration::from_secs(60);
+// Blossom servers refuse upload authorizations past their expiration
+const UPLOAD_AUTH_SECONDS: u64 = 5 * 60;
+
+pub fn screenshots_plugin(app: &mut App) {
+    app.init_resource::<PendingScreenshot>()
+        .add_systems(Startup, setup_screenshot_channels)
+        .add_systems(
+            Update,
+            (take_screenshot, upload_screenshots, publish_screenshots).chain(),
+        );
+}
+
+// Where the indicator was when the screenshot was taken, until its note is published
+#[derive(Resource, Default, Deref, DerefMut)]
+struct PendingScreenshot(Option<CyberspaceCoordinates>);
+
+// PNG encoded frames, sent from the render world once they are read back from the GPU
+#[derive(Resource, Deref)]
+struct ScreenshotCaptures(Receiver<Result<Vec<u8>, String>>);
+
+#[derive(Resource, Deref)]
+struct ScreenshotCaptureWriter(Sender<Result<Vec<u8>, String>>);
+
+#[derive(Resource, Deref)]
+struct ScreenshotUploads(Receiver<Result<UploadedBlob, String>>);
+
+#[derive(Resource, Deref)]
+struct ScreenshotUploadWriter(Sender<Result<UploadedBlob, String>>);
+
+// BUD-02 blob descriptor, the answer to an upload
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+struct UploadedBlob {
+    url: String,
+    sha256: String,
+}
+
+fn setup_screenshot_channels(mut commands: Commands) {
+    let (capture_writer, captures) = unbounded();
+    commands.insert_resource(ScreenshotCaptures(captures));
+    commands.insert_resource(ScreenshotCaptureWriter(capture_writer));
+    let (upload_writer, uploads) = unbounded();
+    commands.insert_resource(ScreenshotUploads(uploads));
+    commands.insert_resource(ScreenshotUploadWriter(upload_writer));
+}
+
+fn encode_png(screenshot: Image) -> anyhow::Result<Vec<u8>> {
+    // Swapchains have no meaningful alpha, it would make the picture see-through
+    let frame = screenshot.try_into_dynamic()?.to_rgb8();
+    let mut png = Cursor::new(Vec::new());
+    frame.write_to(&mut png, image::ImageOutputFormat::Png)?;
+    Ok(png.into_inner())
+}
+
+fn sha256_hex(data: &[u8]) -> String {
+    let mut hasher = Sha256::new();
+    hasher.input(data);
+    hasher.result_str()
+}
+
+fn upload_auth_tags(sha256: &str, expiration: u64) -> Vec<Vec<String>> {
+    vec![
+        vec!["t".to_string(), "upload".to_string()],
+        vec!["x".to_string(), sha256.to_string()],
+        vec!["expiration".to_string(), expiration.to_string()],
+    ]
+}
+
+fn upload_url(media_server: &str) -> String {
+    format!("{}/upload", media_server.trim().trim_end_matches('/'))
+}
+
+// BUD-02 upload, authorized by a kind 24242 note naming the blob's hash
+async fn upload_blob(
+    media_server: &str,
+    keypair: Arc<UserKeys>,
+    png: Vec<u8>,
+) -> anyhow::Result<UploadedBlob> {
+    let sha256 = sha256_hex(&png);
+    let mut auth = Note::new(
+        keypair.get_public_key(),
+        BLOSSOM_AUTH_KIND,
+        "Upload NostrCraft screenshot",
+    );
+    auth.tags = upload_auth_tags(&sha256, unix_now() + UPLOAD_AUTH_SECONDS);
+    let auth = keypair.sign_nostr_event(auth);
+    let authorization = format!(
+        "Nostr {}",
+        base64::encode_block(serde_json::to_string(&auth)?.as_bytes())
+    );
+
+    let response = reqwest::Client::builder()
+        .timeout(UPLOAD_TIMEOUT)
+        .build()?
+        .put(upload_url(media_server))
+        .header("Authorization", authorization)
+        .header("Content-Type", "image/png")
+        .body(png)
+        .send()
+        .await?;
+    if !response.status().is_success() {
+        // Servers explain refusals in this header, the body is often an HTML page
+        let reason = response
+            .headers()
+            .get("X-Reason")
+            .and_then(|reason| reason.to_str().ok())
+            .map(|reason| reason.to_string())
+            .unwrap_or_else(|| response.status().to_string());
+        return Err(anyhow!("{}", reason));
+    }
+    let blob: UploadedBlob = response.json().await?;
+    if blob.sha256 != sha256 {
+        return Err(anyhow!("The media server stored something else"));
+    }
+    Ok(blob)
+}
+
+fn screenshot_note_content(coordinates: CyberspaceCoordinates, url: &str) -> String {
+    format!(
+        "My view of cyberspace from X: {}, Y: {}, Z: {}\n{}",
+        coordinates.x, coordinates.y, coordinates.z, url
+    )
+}
+
+// `C` places the note like a construct, `imeta` lets clients show the image inline
+fn screenshot_note_tags(coordinates: &str, blob: &UploadedBlob) -> Vec<Vec<String>> {
+    vec![
+        vec!["C".to_string(), coordinates.to_string()],
+        vec![
+            "imeta".to_string(),
+            format!("url {}", blob.url),
+            "m image/png".to_string(),
+            format!("x {}", blob.sha256),
+        ],
+        vec!["t".to_string(), "nostrcraft".to_string()],
+    ]
+}
+
+fn take_screenshot(
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    key_bindings: Res<KeyBindings>,
+    window_query: Query<Entity, With<PrimaryWindow>>,
+    block_indicator: Query<&Transform, With<BlockIndicator>>,
+    origin: Res<FloatingOrigin>,
+    capture_writer: Res<ScreenshotCaptureWriter>,
+    mut screenshot_manager: ResMut<ScreenshotManager>,
+    sandbox_mode: Res<SandboxMode>,
+    mut pending_screenshot: ResMut<PendingScreenshot>,
+    mut notifications: EventWriter<Notification>,
+) {
+    if !keyboard_input.just_pressed(key_bindings.key(Action::Screenshot)) {
+        return;
+    }
+    // Screenshots are shared by uploading them, which the sandbox never does
+    if **sandbox_mode {
+        notifications.send(Notification {
+            message: "Screenshots can't be uploaded from the offline sandbox".to_string(),
+            severity: Severity::Warning,
+            play_sound: false,
+        });
+        return;
+    }
+    if pending_screenshot.is_some() {
+        notifications.send(Notification {
+            message: "The last screenshot is still uploading".to_string(),
+            severity: Severity::Warning,
+            play_sound: false,
+        });
+        return;
+    }
+    let (Ok(window), Ok(indicator_transform)) =
+        (window_query.get_single(), block_indicator.get_single())
+    else {
+        return;
+    };
+    let capture_writer = capture_writer.0.clone();
+    let requested = screenshot_manager.take_screenshot(window, move |screenshot| {
+        let png = encode_png(screenshot).map_err(|error| error.to_string());
+        let _ = capture_writer.send(png);
+    });
+    if requested.is_ok() {
+        **pending_screenshot = Some(origin.coordinates(indicator_transform.translation));
+    }
+}
+
+fn upload_screenshots(
+    runtime: Res<TokioTasksRuntime>,
+    captures: Res<ScreenshotCaptures>,
+    upload_writer: Res<ScreenshotUploadWriter>,
+    user_keys: Res<UserNostrKeys>,
+    settings: Res<Settings>,
+    mut pending_screenshot: ResMut<PendingScreenshot>,
+    mut notifications: EventWriter<Notification>,
+) {
+    for capture in captures.try_iter() {
+        let png = match capture {
+            Ok(png) => png,
+            Err(error) => {
+                **pending_screenshot = None;
+                notifications.send(Notification {
+                    message: format!("Could not capture the screenshot: {}", error),
+                    severity: Severity::Error,
+                    play_sound: false,
+                });
+                continue;
+            }
+        };
+        notifications.send(Notification {
+            message: format!("Uploading screenshot to {}", settings.media_server),
+            severity: Severity::Info,
+            play_sound: false,
+        });
+        let media_server = settings.media_server.clone();
+        let keypair = user_keys.get_keypair();
+        let upload_writer = upload_writer.0.clone();
+        runtime.spawn_background_task(move |_ctx| async move {
+            let uploaded = upload_blob(&media_server, keypair, png)
+                .await
+                .map_err(|error| error.to_string());
+            let _ = upload_writer.send(uploaded);
+        });
+    }
+}
+
+fn publish_screenshots(
+    uploads: Res<ScreenshotUploads>,
+    user_keys: Res<UserNostrKeys>,
+    outgoing_notes: Res<OutgoingNotes>,
+    world_chunks: Res<WorldChunks>,
+    mut pending_screenshot: ResMut<PendingScreenshot>,
+    mut notifications: EventWriter<Notification>,
+) {
+    for uploaded in uploads.try_iter() {
+        let Some(coordinates) = pending_screenshot.take() else {
+            continue;
+        };
+        let blob = match uploaded {
+            Ok(blob) => blob,
+            Err(error) => {
+                notifications.send(Notification {
+                    message: format!("Could not upload the screenshot: {}", error),
+                    severity: Severity::Error,
+                    play_sound: false,
+                });
+                continue;
+            }
+        };
+        let mut note = Note::new(
+            user_keys.get_public_key(),
+            CHAT_KIND,
+            &screenshot_note_content(coordinates, &blob.url),
+        );
+        note.tags = screenshot_note_tags(&coordinates.to_hex(world_chunks.plane()), &blob);
+        let _sent = outgoing_notes.send(user_keys.get_keypair().sign_nostr_event(note));
+        notifications.send(Notification {
+            message: "Screenshot shared".to_string(),
+            severity: Severity::Success,
+            play_sound: false,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn uploads_go_to_the_servers_upload_endpoint() {
+        assert_eq!(
+            upload_url("https://media.example.com/"),
+            "https://media.example.com/upload"
+        );
+        assert_eq!(
+            upload_url(" https://media.example.com"),
+            "https://media.example.com/upload"
+        );
+    }
+
+    #[test]
+    fn upload_auth_names_the_blob_hash() {
+        let sha256 = sha256_hex(b"");
+        assert_eq!(
+            sha256,
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+        let tags = upload_auth_tags(&sha256, 100);
+        assert!(tags.contains(&vec!["t".to_string(), "upload".to_string()]));
+        assert!(tags.contains(&vec!["x".to_string(), sha256]));
+        assert!(tags.contains(&vec!["expiration".to_string(), "100".to_string()]));
+    }
+}
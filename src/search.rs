@@ -0,0 +1,298 @@
+use bevy::prelude::*;
+
+use crate::{
+    console::{ConsoleCommand, ConsoleOutput, RegisterConsoleCommand},
+    cyberspace::CyberspaceCoordinates,
+    keybindings::{Action, KeyBindings},
+    minimap::TeleportDestination,
+    protocol::POWBlockDetails,
+    resources::{
+        avatar_position, block_tier, npub, parse_pubkey, tier_name, CoordinatesMap, LastSeen,
+        Profiles,
+    },
+    session::SessionPanel,
+    ui_camera::{
+        text_bundle_builder, BORDER_WIDTH, FLEX_GAP, LIGHT_GRAY, MARGIN_UI, NORMAL_FONT,
+        PADDING_UI, TITLE_FONT,
+    },
+};
+
+// Only the strongest blocks of a miner are listed
+const MAX_SEARCH_RESULTS: usize = 8;
+const RESULT_HOVER_COLOR: Color = Color::rgba(1.0, 1.0, 1.0, 0.15);
+
+pub fn search_plugin(app: &mut App) {
+    app.init_resource::<OwnerSearch>()
+        .register_console_command("who", "/who <npub>", "Describes a key and its blocks")
+        .add_systems(PostStartup, setup_search_panel)
+        .add_systems(
+            Update,
+            (
+                toggle_search_panel,
+                paste_search_key,
+                update_search_results,
+                pick_search_result,
+                who_command,
+            )
+                .chain(),
+        );
+}
+
+// The miner being searched for and their blocks, strongest first
+#[derive(Resource, Default)]
+pub struct OwnerSearch {
+    pub pubkey: Option<String>,
+    pub results: Vec<POWBlockDetails>,
+}
+
+#[derive(Component)]
+struct SearchPanel;
+
+#[derive(Component)]
+struct SearchText;
+
+#[derive(Component)]
+struct SearchResults;
+
+// Clicking a result makes its block the teleport destination
+#[derive(Component)]
+struct SearchResult(CyberspaceCoordinates);
+
+fn setup_search_panel(mut commands: Commands) {
+    let search_ui = NodeBundle {
+        style: Style {
+            position_type: PositionType::Absolute,
+            top: Val::Percent(30.0),
+            left: Val::Percent(30.0),
+            margin: MARGIN_UI,
+            padding: PADDING_UI,
+            row_gap: FLEX_GAP,
+            flex_direction: FlexDirection::Column,
+            border: BORDER_WIDTH,
+            ..Default::default()
+        },
+        border_color: BorderColor(LIGHT_GRAY),
+        background_color: BackgroundColor(Color::rgba(0.0, 0.0, 0.0, 0.7)),
+        visibility: Visibility::Hidden,
+        ..Default::default()
+    };
+    let results = NodeBundle {
+        style: Style {
+            row_gap: FLEX_GAP,
+            flex_direction: FlexDirection::Column,
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    commands
+        .spawn((search_ui, SearchPanel, SessionPanel("search")))
+        .with_children(|search_ui| {
+            search_ui.spawn(text_bundle_builder(
+                "Search by Owner".to_string(),
+                TITLE_FONT,
+            ));
+            search_ui.spawn((
+                text_bundle_builder(
+                    "Copy an npub or hex key and press Ctrl+V".to_string(),
+                    NORMAL_FONT,
+                ),
+                SearchText,
+            ));
+            search_ui.spawn((results, SearchResults));
+        });
+}
+
+fn toggle_search_panel(
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    key_bindings: Res<KeyBindings>,
+    mut panel_query: Query<&mut Visibility, With<SearchPanel>>,
+) {
+    if keyboard_input.just_pressed(key_bindings.key(Action::ToggleSearch)) {
+        for mut visibility in panel_query.iter_mut() {
+            *visibility = match *visibility {
+                Visibility::Hidden => Visibility::Visible,
+                _ => Visibility::Hidden,
+            };
+        }
+    }
+}
+
+// Bevy has no text fields, so the key comes from the clipboard
+fn paste_search_key(
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    panel_query: Query<&Visibility, With<SearchPanel>>,
+    profiles: Res<Profiles>,
+    mut owner_search: ResMut<OwnerSearch>,
+    mut text_query: Query<&mut Text, With<SearchText>>,
+) {
+    let panel_open = panel_query
+        .iter()
+        .any(|visibility| *visibility == Visibility::Visible);
+    let pasting = keyboard_input.any_pressed([KeyCode::ControlLeft, KeyCode::ControlRight])
+        && keyboard_input.just_pressed(KeyCode::KeyV);
+    if !panel_open || !pasting {
+        return;
+    }
+    let pasted = arboard::Clipboard::new().and_then(|mut clipboard| clipboard.get_text());
+    let search_text = match pasted.ok().as_deref().and_then(parse_pubkey) {
+        Some(pubkey) => {
+            let search_text = format!("Blocks of {}", profiles.display_name(&pubkey));
+            owner_search.pubkey = Some(pubkey);
+            search_text
+        }
+        None => "The clipboard doesn't hold an npub or hex key".to_string(),
+    };
+    for mut text in text_query.iter_mut() {
+        text.sections[0].value = search_text.clone();
+    }
+}
+
+fn owner_blocks(coordinates_map: &CoordinatesMap, pubkey: &str) -> Vec<POWBlockDetails> {
+    let mut blocks: Vec<POWBlockDetails> = coordinates_map
+        .values()
+        .filter(|(_, block_details)| block_details.owner() == pubkey)
+        .map(|(_, block_details)| block_details.clone())
+        .collect();
+    blocks.sort_by(|a, b| b.pow_amount.cmp(&a.pow_amount));
+    blocks.truncate(MAX_SEARCH_RESULTS);
+    blocks
+}
+
+fn who_command(
+    coordinates_map: Res<CoordinatesMap>,
+    profiles: Res<Profiles>,
+    last_seen: Res<LastSeen>,
+    mut console_commands: EventReader<ConsoleCommand>,
+    mut console_output: EventWriter<ConsoleOutput>,
+) {
+    for command in console_commands.read() {
+        if command.name != "who" {
+            continue;
+        }
+        let Some(pubkey) = command.args.first().and_then(|key| parse_pubkey(key)) else {
+            console_output.send(ConsoleOutput("Usage: /who <npub>".to_string()));
+            continue;
+        };
+        let block_count = coordinates_map
+            .values()
+            .filter(|(_, block_details)| block_details.owner() == pubkey)
+            .count();
+        let mut who = format!(
+            "{}, {}\n{}\n{} known blocks",
+            profiles.display_name(&pubkey),
+            last_seen.describe(&pubkey),
+            npub(&pubkey).unwrap_or_else(|| pubkey.clone()),
+            block_count
+        );
+        if let Some(strongest) = owner_blocks(&coordinates_map, &pubkey).first() {
+            who.push_str(&format!(
+                ", strongest {} bits at {}",
+                strongest.pow_amount,
+                strongest.display_coordinates()
+            ));
+        }
+        if let Some(home) = avatar_position(&pubkey) {
+            who.push_str(&format!("\nHome X: {} Y: {} Z: {}", home.x, home.y, home.z));
+        }
+        console_output.send(ConsoleOutput(who));
+    }
+}
+
+fn update_search_results(
+    mut commands: Commands,
+    coordinates_map: Res<CoordinatesMap>,
+    mut owner_search: ResMut<OwnerSearch>,
+    results_query: Query<Entity, With<SearchResults>>,
+) {
+    if !owner_search.is_changed() && !coordinates_map.is_changed() {
+        return;
+    }
+    let Some(pubkey) = owner_search.pubkey.clone() else {
+        return;
+    };
+    let results = owner_blocks(&coordinates_map, &pubkey);
+    let listed = |blocks: &[POWBlockDetails]| {
+        blocks
+            .iter()
+            .map(|block| (block.coordinates.clone(), block.pow_amount))
+            .collect::<Vec<_>>()
+    };
+    if owner_search.is_changed() || listed(&results) != listed(&owner_search.results) {
+        for results_entity in results_query.iter() {
+            commands
+                .entity(results_entity)
+                .despawn_descendants()
+                .with_children(|results_ui| {
+                    if results.is_empty() {
+                        results_ui.spawn(text_bundle_builder(
+                            "No known blocks".to_string(),
+                            NORMAL_FONT,
+                        ));
+                    }
+                    for block in results.iter() {
+                        let result = text_bundle_builder(
+                            format!(
+                                "{} - {} block, {} bits",
+                                block.display_coordinates(),
+                                tier_name(block_tier(block.pow_amount, block.material)),
+                                block.pow_amount
+                            ),
+                            NORMAL_FONT,
+                        );
+                        results_ui.spawn((
+                            result,
+                            Interaction::default(),
+                            SearchResult(block.coordinates()),
+                        ));
+                    }
+                });
+        }
+    }
+    owner_search.bypass_change_detection().results = results;
+}
+
+fn pick_search_result(
+    mut result_query: Query<
+        (&Interaction, &SearchResult, &mut BackgroundColor),
+        Changed<Interaction>,
+    >,
+    mut teleport_destination: ResMut<TeleportDestination>,
+) {
+    for (interaction, result, mut background) in result_query.iter_mut() {
+        *background = match interaction {
+            Interaction::Pressed => {
+                **teleport_destination = Some(result.0);
+                RESULT_HOVER_COLOR.into()
+            }
+            Interaction::Hovered => RESULT_HOVER_COLOR.into(),
+            Interaction::None => Color::NONE.into(),
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn block(pow_amount: usize, miner_pubkey: &str) -> POWBlockDetails {
+        POWBlockDetails {
+            pow_amount,
+            coordinates: format!("{:064x}", pow_amount),
+            miner_pubkey: miner_pubkey.to_string(),
+            material: None,
+            label: None,
+            owner: None,
+        }
+    }
+
+    #[test]
+    fn owner_blocks_are_strongest_first() {
+        let mut coordinates_map = CoordinatesMap::default();
+        for block in [block(3, "me"), block(9, "me"), block(12, "other")] {
+            coordinates_map.insert(block.coordinates.clone(), (None, block));
+        }
+        let blocks = owner_blocks(&coordinates_map, "me");
+        let pows: Vec<usize> = blocks.iter().map(|block| block.pow_amount).collect();
+        assert_eq!(pows, vec![9, 3]);
+    }
+}
@@ -0,0 +1,436 @@
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+};
+
+use bevy::{app::AppExit, prelude::*, utils::HashSet};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    cameras::{move_indicator_home, BlockIndicator, CameraMode, ExplorerCamera, OrbitDistance},
+    chunks::WorldChunks,
+    console::{ConsoleCommand, ConsoleOutput, RegisterConsoleCommand},
+    cyberspace::{extract_plane, CyberspaceCoordinates},
+    logging::WORLD_LOG,
+    menu::AppState,
+    mining::{queue_block, toggle_unmined_block, MiningAction, MiningQueue, UnminedBlockMap},
+    notifications::{Notification, Severity},
+    origin::FloatingOrigin,
+    replay::replaying,
+    resources::MeshesAndMaterials,
+    UserNostrKeys,
+};
+
+const SESSION_FILE_PATH: &str = "./nostrcraft_session.json";
+// Every selection and deselection of an unmined block, appended as it happens so a crash
+// doesn't lose them. Removed when the session is saved on a clean exit
+const JOURNAL_FILE_PATH: &str = "./nostrcraft_unmined.journal";
+
+pub fn session_plugin(app: &mut App) {
+    app.insert_resource(RecoveredBlocks(read_journal()))
+        .register_console_command(
+            "restore",
+            "/restore",
+            "Selects the unmined blocks recovered from a crashed session again",
+        )
+        .add_systems(
+            OnEnter(AppState::Loading),
+            restore_world_session.after(move_indicator_home),
+        )
+        .add_systems(
+            OnEnter(AppState::InGame),
+            (restore_open_panels, offer_recovered_blocks),
+        )
+        .add_systems(
+            Update,
+            (journal_unmined_blocks, restore_command).run_if(in_state(AppState::InGame)),
+        )
+        // A replay starts wherever the recording did, the player's own session is kept
+        .add_systems(
+            Last,
+            save_session.run_if(in_state(AppState::InGame).and_then(not(replaying))),
+        );
+}
+
+// Panels whose visibility is kept between sessions, named in the session file
+#[derive(Component)]
+pub struct SessionPanel(pub &'static str);
+
+// Where the last game was left, stored in `nostrcraft_session.json` when the game closes
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq)]
+#[serde(default)]
+struct Session {
+    // Another key starts at its own home with an empty queue
+    pubkey: String,
+    indicator: Option<String>,
+    // The orbit camera, relative to the indicator
+    camera: Option<Transform>,
+    unmined_blocks: Vec<String>,
+    queued_blocks: Vec<SessionBlock>,
+    open_panels: Vec<String>,
+}
+
+// A block waiting in the mining queue, defenses and derezzes aren't kept since the blocks
+// they answer may be gone by the next launch
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+struct SessionBlock {
+    coordinates: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    material: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    label: Option<String>,
+}
+
+impl Session {
+    fn load() -> Option<Self> {
+        let session = std::fs::read_to_string(SESSION_FILE_PATH).ok()?;
+        match serde_json::from_str(&session) {
+            Ok(session) => Some(session),
+            Err(error) => {
+                warn!(target: WORLD_LOG, "Could not read {}: {}", SESSION_FILE_PATH, error);
+                None
+            }
+        }
+    }
+
+    fn save(&self) {
+        let session = match serde_json::to_string_pretty(self) {
+            Ok(session) => session,
+            Err(error) => {
+                warn!(target: WORLD_LOG, "Could not serialize the session: {}", error);
+                return;
+            }
+        };
+        if let Err(error) = std::fs::write(SESSION_FILE_PATH, session) {
+            warn!(target: WORLD_LOG, "Could not write {}: {}", SESSION_FILE_PATH, error);
+        }
+    }
+
+    // None for a session of another key, or when there is no session at all
+    fn load_for(pubkey: &str) -> Option<Self> {
+        Session::load().filter(|session| session.pubkey == pubkey)
+    }
+}
+
+fn queued_session_blocks(mining_queue: &MiningQueue) -> Vec<SessionBlock> {
+    mining_queue
+        .blocks
+        .iter()
+        .filter(|block| !block.defending)
+        .filter_map(|block| match &block.action {
+            MiningAction::Place { material, label } => Some(SessionBlock {
+                coordinates: block.coordinates.clone(),
+                material: *material,
+                label: label.clone(),
+            }),
+            MiningAction::Derezz => None,
+        })
+        .collect()
+}
+
+fn save_session(
+    mut app_exit: EventReader<AppExit>,
+    user_keys: Res<UserNostrKeys>,
+    origin: Res<FloatingOrigin>,
+    world_chunks: Res<WorldChunks>,
+    camera_mode: Res<CameraMode>,
+    unmined_block_map: Res<UnminedBlockMap>,
+    mining_queue: Res<MiningQueue>,
+    block_indicator: Query<&Transform, With<BlockIndicator>>,
+    camera_query: Query<&Transform, With<ExplorerCamera>>,
+    panel_query: Query<(&SessionPanel, &Visibility)>,
+) {
+    if app_exit.read().next().is_none() {
+        return;
+    }
+    let indicator = block_indicator
+        .get_single()
+        .ok()
+        .map(|transform| origin.encode(transform.translation, world_chunks.plane()));
+    let session = Session {
+        pubkey: user_keys.get_public_key(),
+        indicator,
+        camera: camera_query
+            .get_single()
+            .ok()
+            .map(|transform| camera_mode.orbit_transform(*transform)),
+        unmined_blocks: unmined_block_map.keys().cloned().collect(),
+        queued_blocks: queued_session_blocks(&mining_queue),
+        open_panels: panel_query
+            .iter()
+            .filter(|(_, visibility)| **visibility != Visibility::Hidden)
+            .map(|(panel, _)| panel.0.to_string())
+            .collect(),
+    };
+    session.save();
+    // The session holds the selection now
+    let _ = std::fs::remove_file(JOURNAL_FILE_PATH);
+}
+
+// Runs after the indicator was moved home, before anything is placed around it
+fn restore_world_session(
+    mut commands: Commands,
+    stuff: Res<MeshesAndMaterials>,
+    user_keys: Res<UserNostrKeys>,
+    mut origin: ResMut<FloatingOrigin>,
+    mut orbit_distance: ResMut<OrbitDistance>,
+    mut unmined_block_map: ResMut<UnminedBlockMap>,
+    mut mining_queue: ResMut<MiningQueue>,
+    mut block_indicator: Query<&mut Transform, (With<BlockIndicator>, Without<ExplorerCamera>)>,
+    mut camera_query: Query<&mut Transform, (With<ExplorerCamera>, Without<BlockIndicator>)>,
+) {
+    let Some(session) = Session::load_for(&user_keys.get_public_key()) else {
+        return;
+    };
+    if let Some(indicator) = session
+        .indicator
+        .as_deref()
+        .and_then(|indicator| CyberspaceCoordinates::from_hex(indicator).ok())
+    {
+        origin.reset(indicator);
+        for mut transform in block_indicator.iter_mut() {
+            transform.translation = origin.position(indicator);
+        }
+    }
+    if let Some(camera) = session.camera {
+        for mut transform in camera_query.iter_mut() {
+            *transform = camera;
+        }
+        **orbit_distance = camera.translation.length();
+    }
+
+    for coordinates in session.unmined_blocks.iter() {
+        let (Ok(position), Ok(plane)) = (
+            CyberspaceCoordinates::from_hex(coordinates),
+            extract_plane(coordinates),
+        ) else {
+            continue;
+        };
+        if !unmined_block_map.contains_key(coordinates) {
+            toggle_unmined_block(
+                &mut commands,
+                &stuff,
+                &mut unmined_block_map,
+                position,
+                plane,
+            );
+        }
+    }
+    for block in session.queued_blocks {
+        let (Ok(position), Ok(plane)) = (
+            CyberspaceCoordinates::from_hex(&block.coordinates),
+            extract_plane(&block.coordinates),
+        ) else {
+            continue;
+        };
+        queue_block(
+            &mut commands,
+            &stuff,
+            &mut mining_queue,
+            position,
+            plane,
+            MiningAction::Place {
+                material: block.material,
+                label: block.label,
+            },
+        );
+    }
+}
+
+// Panels are spawned hidden, the menu would be covered if they were shown any earlier
+fn restore_open_panels(
+    user_keys: Res<UserNostrKeys>,
+    mut panel_query: Query<(&SessionPanel, &mut Visibility)>,
+) {
+    let Some(session) = Session::load_for(&user_keys.get_public_key()) else {
+        return;
+    };
+    for (panel, mut visibility) in panel_query.iter_mut() {
+        if session.open_panels.iter().any(|open| open == panel.0) {
+            *visibility = Visibility::Visible;
+        }
+    }
+}
+
+// Unmined blocks left in the journal by a game that didn't exit cleanly
+#[derive(Resource, Default, Deref, DerefMut)]
+struct RecoveredBlocks(Vec<String>);
+
+fn read_journal() -> Vec<String> {
+    std::fs::read_to_string(JOURNAL_FILE_PATH)
+        .map(|journal| replay_journal(&journal))
+        .unwrap_or_default()
+}
+
+// `+` lines select a block and `-` lines deselect it, a line cut short by the crash is skipped
+fn replay_journal(journal: &str) -> Vec<String> {
+    let mut selected: Vec<String> = Vec::new();
+    for line in journal.lines() {
+        if let Some(coordinates) = line.strip_prefix('+') {
+            if !selected.iter().any(|known| known == coordinates) {
+                selected.push(coordinates.to_string());
+            }
+        } else if let Some(coordinates) = line.strip_prefix('-') {
+            selected.retain(|known| known != coordinates);
+        }
+    }
+    selected
+        .into_iter()
+        .filter(|coordinates| {
+            coordinates.len() == 64 && CyberspaceCoordinates::from_hex(coordinates).is_ok()
+        })
+        .collect()
+}
+
+// The first run starts a new journal with the whole selection, later runs append the difference.
+// Blocks recovered from the last journal stay in it until they're restored, so crashing again
+// before `/restore` doesn't lose them
+fn journal_unmined_blocks(
+    unmined_block_map: Res<UnminedBlockMap>,
+    recovered_blocks: Res<RecoveredBlocks>,
+    mut journal: Local<Option<BufWriter<File>>>,
+    mut journaled: Local<HashSet<String>>,
+) {
+    if !unmined_block_map.is_changed() && !recovered_blocks.is_changed() {
+        return;
+    }
+    if journal.is_none() {
+        match File::create(JOURNAL_FILE_PATH) {
+            Ok(file) => *journal = Some(BufWriter::new(file)),
+            Err(error) => {
+                warn!(target: WORLD_LOG, "Could not write {}: {}", JOURNAL_FILE_PATH, error);
+                return;
+            }
+        }
+    }
+    let Some(journal) = journal.as_mut() else {
+        return;
+    };
+    let mut lines = String::new();
+    for coordinates in unmined_block_map.keys().chain(recovered_blocks.iter()) {
+        if journaled.insert(coordinates.clone()) {
+            lines.push_str(&format!("+{}\n", coordinates));
+        }
+    }
+    journaled.retain(|coordinates| {
+        let selected =
+            unmined_block_map.contains_key(coordinates) || recovered_blocks.contains(coordinates);
+        if !selected {
+            lines.push_str(&format!("-{}\n", coordinates));
+        }
+        selected
+    });
+    if lines.is_empty() {
+        return;
+    }
+    // Flushed right away, the point is surviving a crash
+    if let Err(error) = journal
+        .write_all(lines.as_bytes())
+        .and_then(|_| journal.flush())
+    {
+        warn!(target: WORLD_LOG, "Could not write {}: {}", JOURNAL_FILE_PATH, error);
+    }
+}
+
+// Blocks the saved session already brought back aren't offered again
+fn offer_recovered_blocks(
+    unmined_block_map: Res<UnminedBlockMap>,
+    mut recovered_blocks: ResMut<RecoveredBlocks>,
+    mut notifications: EventWriter<Notification>,
+) {
+    recovered_blocks.retain(|coordinates| !unmined_block_map.contains_key(coordinates));
+    if recovered_blocks.is_empty() {
+        return;
+    }
+    notifications.send(Notification {
+        message: format!(
+            "The last game closed with {} unmined blocks selected, /restore brings them back",
+            recovered_blocks.len()
+        ),
+        severity: Severity::Info,
+        play_sound: false,
+    });
+}
+
+fn restore_command(
+    mut commands: Commands,
+    stuff: Res<MeshesAndMaterials>,
+    mut recovered_blocks: ResMut<RecoveredBlocks>,
+    mut unmined_block_map: ResMut<UnminedBlockMap>,
+    mut console_commands: EventReader<ConsoleCommand>,
+    mut console_output: EventWriter<ConsoleOutput>,
+) {
+    for command in console_commands.read() {
+        if command.name != "restore" {
+            continue;
+        }
+        let mut restored = 0;
+        for coordinates in recovered_blocks.drain(..) {
+            let (Ok(position), Ok(plane)) = (
+                CyberspaceCoordinates::from_hex(&coordinates),
+                extract_plane(&coordinates),
+            ) else {
+                continue;
+            };
+            if !unmined_block_map.contains_key(&coordinates) {
+                toggle_unmined_block(
+                    &mut commands,
+                    &stuff,
+                    &mut unmined_block_map,
+                    position,
+                    plane,
+                );
+                restored += 1;
+            }
+        }
+        console_output.send(ConsoleOutput(if restored == 0 {
+            "No unmined blocks to restore".to_string()
+        } else {
+            format!("Restored {} unmined blocks", restored)
+        }));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cyberspace::Plane;
+
+    #[test]
+    fn sessions_fill_missing_fields() {
+        let session: Session = serde_json::from_str(r#"{"pubkey": "aa"}"#).unwrap();
+        assert_eq!(session.pubkey, "aa");
+        assert_eq!(session.indicator, None);
+        assert!(session.queued_blocks.is_empty());
+    }
+
+    #[test]
+    fn journals_replay_to_the_last_selection() {
+        let kept = CyberspaceCoordinates::new(1, 2, 3).to_hex(Plane::DSpace);
+        let dropped = CyberspaceCoordinates::new(4, 5, 6).to_hex(Plane::DSpace);
+        let journal = format!("+{kept}\n+{dropped}\n+{kept}\n-{dropped}\n+{}", &kept[..10]);
+        assert_eq!(replay_journal(&journal), vec![kept]);
+    }
+
+    #[test]
+    fn only_placed_blocks_are_kept_from_the_queue() {
+        let mut mining_queue = MiningQueue::default();
+        mining_queue.enqueue("placed".to_string(), Entity::PLACEHOLDER);
+        mining_queue.enqueue("derezzed".to_string(), Entity::PLACEHOLDER);
+        mining_queue.enqueue("defended".to_string(), Entity::PLACEHOLDER);
+        mining_queue.blocks[0].action = MiningAction::Place {
+            material: Some(3),
+            label: None,
+        };
+        mining_queue.blocks[1].action = MiningAction::Derezz;
+        mining_queue.blocks[2].defending = true;
+        assert_eq!(
+            queued_session_blocks(&mining_queue),
+            vec![SessionBlock {
+                coordinates: "placed".to_string(),
+                material: Some(3),
+                label: None,
+            }]
+        );
+    }
+}
@@ -0,0 +1,732 @@
+use bevy::{prelude::*, ui::RelativeCursorPosition, window::PrimaryWindow};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::{read_config, write_config, ConfigReloaded, CONFIG_FILE_PATH},
+    console::{ConsoleCommand, ConsoleOutput, RegisterConsoleCommand},
+    keybindings::{key_name, Action, KeyBindings},
+    nostr::DEFAULT_RELAY_URLS,
+    protocol::trim_label,
+    session::SessionPanel,
+    ui_camera::{
+        text_bundle_builder, BORDER_WIDTH, FLEX_GAP, LIGHT_GRAY, MARGIN_UI, NORMAL_FONT,
+        PADDING_UI, TITLE_FONT,
+    },
+};
+
+pub const MIN_MINING_INTENSITY: u8 = 10;
+pub const MAX_MINING_INTENSITY: u8 = 100;
+const DEFAULT_SECTOR_LOAD_RADIUS: i32 = 4;
+const DEFAULT_BLOCK_VIEW_DISTANCE: f32 = 128.0;
+pub const DEFAULT_BLOOM_INTENSITY: f32 = 0.21;
+const MAX_SECTOR_LOAD_RADIUS: i32 = 16;
+const MIN_BLOCK_VIEW_DISTANCE: f32 = 16.0;
+const MAX_BLOCK_VIEW_DISTANCE: f32 = 1024.0;
+const MAX_BLOOM_INTENSITY: f32 = 1.0;
+const MAX_MINING_THREADS: usize = 256;
+const MIN_UI_SCALE: f32 = 0.5;
+const MAX_UI_SCALE: f32 = 2.0;
+const DEFAULT_SOUND_VOLUME: f32 = 0.8;
+const DEFAULT_MUSIC_VOLUME: f32 = 0.5;
+const DEFAULT_MEDIA_SERVER: &str = "https://blossom.primal.net";
+const DEFAULT_KEY_PATH: &str = "./nostr.pem";
+const DEFAULT_DEFENSE_MAX_DIFFICULTY: usize = 32;
+const DEFAULT_MAX_DEFENSES: usize = 4;
+// Panels and fonts were sized for a window this many physical pixels tall
+const UI_REFERENCE_HEIGHT: f32 = 1080.0;
+// The slider snaps to steps of this many percent
+const MINING_INTENSITY_STEP: u8 = 5;
+const SLIDER_WIDTH: Val = Val::Px(200.0);
+const SLIDER_HEIGHT: Val = Val::Px(12.0);
+
+pub fn settings_plugin(app: &mut App) {
+    app.insert_resource(Settings::load())
+        .add_systems(PostStartup, setup_settings_panel)
+        .add_systems(
+            Update,
+            (
+                toggle_settings_panel,
+                drag_intensity_slider,
+                step_settings,
+                update_settings_panel,
+                detect_ui_scale,
+                apply_ui_scale,
+                reload_settings,
+                save_settings,
+            ),
+        )
+        .register_console_command(
+            "label",
+            "/label [text]",
+            "Sets the label of the blocks you queue, clears it without text",
+        )
+        .add_systems(Update, label_command);
+}
+
+// Everything the player can configure, stored at the top of `nostrcraft.toml`
+#[derive(Resource, Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct Settings {
+    // Percentage of the time the miners spend hashing, the rest they sleep
+    pub mining_intensity: u8,
+    // Blocks are only spawned for sectors this many sectors away from the indicator
+    pub sector_load_radius: i32,
+    // Loaded blocks further than this from the camera are hidden
+    pub block_view_distance: f32,
+    // Message or link attached to every block we queue, empty for none
+    pub block_label: String,
+    // Strength of the glow around bright blocks and the starfield
+    pub bloom_intensity: f32,
+    // Worker threads shared by the blocks being mined, also the most blocks mined at once
+    pub mining_threads: usize,
+    // Multiplies the size of every panel and its text
+    pub ui_scale: f32,
+    // Picks the UI scale from the window's resolution, until the scale is stepped by hand
+    pub auto_ui_scale: bool,
+    // Loudness of the sound effects, 0 mutes them
+    pub sound_volume: f32,
+    // Loudness of the ambient music, kept while it is muted
+    pub music_volume: f32,
+    pub music_muted: bool,
+    // Light beams over our home and over the other waypoints, to find them from far away
+    pub home_beacon: bool,
+    pub waypoint_beacons: bool,
+    // Whether the relay list panel is shown
+    pub show_relays: bool,
+    // Blossom server screenshots are uploaded to before they are shared
+    pub media_server: String,
+    // Relays connected on startup, relays added to the file later are connected right away
+    pub relays: Vec<String>,
+    // PEM file holding the secret key, read when the game starts
+    pub key_path: String,
+    // Outbid blocks are only defended while the difficulty to beat is under this
+    pub defense_max_difficulty: usize,
+    // Defenses mined at the same time, later outbids are given up
+    pub max_defenses: usize,
+}
+
+// Every core by default, like before the setting existed
+pub fn available_threads() -> usize {
+    std::thread::available_parallelism()
+        .map(|threads| threads.get())
+        .unwrap_or(4)
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            mining_intensity: MAX_MINING_INTENSITY,
+            sector_load_radius: DEFAULT_SECTOR_LOAD_RADIUS,
+            block_view_distance: DEFAULT_BLOCK_VIEW_DISTANCE,
+            block_label: String::new(),
+            bloom_intensity: DEFAULT_BLOOM_INTENSITY,
+            mining_threads: available_threads(),
+            ui_scale: 1.0,
+            auto_ui_scale: true,
+            sound_volume: DEFAULT_SOUND_VOLUME,
+            music_volume: DEFAULT_MUSIC_VOLUME,
+            music_muted: false,
+            home_beacon: true,
+            waypoint_beacons: false,
+            show_relays: true,
+            media_server: DEFAULT_MEDIA_SERVER.to_string(),
+            relays: DEFAULT_RELAY_URLS.map(|url| url.to_string()).to_vec(),
+            key_path: DEFAULT_KEY_PATH.to_string(),
+            defense_max_difficulty: DEFAULT_DEFENSE_MAX_DIFFICULTY,
+            max_defenses: DEFAULT_MAX_DEFENSES,
+        }
+    }
+}
+
+impl Settings {
+    pub fn load() -> Self {
+        read_config()
+            .and_then(Settings::from_config)
+            .unwrap_or_default()
+    }
+
+    // Keys that aren't settings, like the key bindings table, are left to their own readers
+    fn from_config(config: toml::Table) -> Option<Self> {
+        match config.try_into::<Settings>() {
+            Ok(settings) => Some(settings.clamped()),
+            Err(error) => {
+                warn!("Could not read {}: {}", CONFIG_FILE_PATH, error);
+                None
+            }
+        }
+    }
+
+    pub fn block_label(&self) -> Option<String> {
+        trim_label(&self.block_label)
+    }
+
+    // Skipped when the file already holds these settings, so a reload isn't written back
+    fn save(&self) {
+        if Settings::load() == *self {
+            return;
+        }
+        let settings = match toml::Table::try_from(self) {
+            Ok(settings) => settings,
+            Err(error) => {
+                warn!("Could not serialize settings: {}", error);
+                return;
+            }
+        };
+        write_config(|config| config.extend(settings));
+    }
+
+    fn clamped(mut self) -> Self {
+        self.mining_intensity = self
+            .mining_intensity
+            .clamp(MIN_MINING_INTENSITY, MAX_MINING_INTENSITY);
+        self.sector_load_radius = self.sector_load_radius.clamp(0, MAX_SECTOR_LOAD_RADIUS);
+        self.block_view_distance = self
+            .block_view_distance
+            .clamp(MIN_BLOCK_VIEW_DISTANCE, MAX_BLOCK_VIEW_DISTANCE);
+        self.bloom_intensity = self.bloom_intensity.clamp(0.0, MAX_BLOOM_INTENSITY);
+        self.mining_threads = self.mining_threads.clamp(1, MAX_MINING_THREADS);
+        self.ui_scale = self.ui_scale.clamp(MIN_UI_SCALE, MAX_UI_SCALE);
+        self.sound_volume = self.sound_volume.clamp(0.0, 1.0);
+        self.music_volume = self.music_volume.clamp(0.0, 1.0);
+        self.max_defenses = self.max_defenses.max(1);
+        self
+    }
+
+    // Moves an option one step up or down, rounding away float drift from repeated steps
+    fn step(&mut self, option: SettingOption, up: bool) {
+        let sign = if up { 1.0 } else { -1.0 };
+        match option {
+            SettingOption::BlockViewDistance => self.block_view_distance += sign * 16.0,
+            SettingOption::SectorLoadRadius => self.sector_load_radius += sign as i32,
+            SettingOption::BloomIntensity => {
+                self.bloom_intensity =
+                    ((self.bloom_intensity + sign * 0.05) * 100.0).round() / 100.0
+            }
+            SettingOption::MiningThreads if up => self.mining_threads += 1,
+            SettingOption::MiningThreads => {
+                self.mining_threads = self.mining_threads.saturating_sub(1)
+            }
+            SettingOption::UiScale => {
+                self.auto_ui_scale = false;
+                self.ui_scale = ((self.ui_scale + sign * 0.1) * 10.0).round() / 10.0
+            }
+            SettingOption::SoundVolume => {
+                self.sound_volume = ((self.sound_volume + sign * 0.1) * 10.0).round() / 10.0
+            }
+            SettingOption::MusicVolume => {
+                self.music_volume = ((self.music_volume + sign * 0.1) * 10.0).round() / 10.0
+            }
+        }
+        *self = self.clone().clamped();
+    }
+
+    fn describe(&self, option: SettingOption) -> String {
+        match option {
+            SettingOption::BlockViewDistance => {
+                format!("View distance: {}", self.block_view_distance)
+            }
+            SettingOption::SectorLoadRadius => {
+                format!("Sector load radius: {}", self.sector_load_radius)
+            }
+            SettingOption::BloomIntensity => format!("Bloom: {:.2}", self.bloom_intensity),
+            SettingOption::MiningThreads => format!("Mining threads: {}", self.mining_threads),
+            SettingOption::UiScale if self.auto_ui_scale => {
+                format!("UI scale: {:.1}x (auto)", self.ui_scale)
+            }
+            SettingOption::UiScale => format!("UI scale: {:.1}x", self.ui_scale),
+            SettingOption::SoundVolume => {
+                format!("Sound volume: {:.0}%", self.sound_volume * 100.0)
+            }
+            SettingOption::MusicVolume if self.music_muted => {
+                format!("Music volume: {:.0}% (muted)", self.music_volume * 100.0)
+            }
+            SettingOption::MusicVolume => {
+                format!("Music volume: {:.0}%", self.music_volume * 100.0)
+            }
+        }
+    }
+
+    // What the music plays at, nothing while muted
+    pub fn music_volume(&self) -> f32 {
+        if self.music_muted {
+            0.0
+        } else {
+            self.music_volume
+        }
+    }
+}
+
+// Options changed with the - and + buttons on their row
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum SettingOption {
+    BlockViewDistance,
+    SectorLoadRadius,
+    BloomIntensity,
+    MiningThreads,
+    UiScale,
+    SoundVolume,
+    MusicVolume,
+}
+
+impl SettingOption {
+    const ALL: [SettingOption; 7] = [
+        SettingOption::BlockViewDistance,
+        SettingOption::SectorLoadRadius,
+        SettingOption::BloomIntensity,
+        SettingOption::MiningThreads,
+        SettingOption::UiScale,
+        SettingOption::SoundVolume,
+        SettingOption::MusicVolume,
+    ];
+}
+
+#[derive(Component)]
+struct SettingsPanel;
+
+#[derive(Component)]
+struct IntensityText;
+
+#[derive(Component)]
+struct IntensitySlider;
+
+#[derive(Component)]
+struct IntensitySliderFill;
+
+#[derive(Component)]
+struct SettingText(SettingOption);
+
+// A - or + button, true for +
+#[derive(Component)]
+struct SettingStep(SettingOption, bool);
+
+// Goes back to the UI scale picked from the window
+#[derive(Component)]
+struct AutoUiScaleButton;
+
+// Mutes or unmutes the music, its volume is kept
+#[derive(Component)]
+struct MusicMuteButton;
+
+// Shows or hides the beam over home or over the other waypoints
+#[derive(Component, Clone, Copy, PartialEq)]
+enum BeaconToggle {
+    Home,
+    Waypoints,
+}
+
+// Shows or hides the relay list, which also has the relay test shortcut
+#[derive(Component)]
+struct RelaysToggle;
+
+fn setup_settings_panel(mut commands: Commands) {
+    let panel = NodeBundle {
+        style: Style {
+            position_type: PositionType::Absolute,
+            bottom: Val::Percent(30.0),
+            right: Val::Px(0.0),
+            margin: MARGIN_UI,
+            padding: PADDING_UI,
+            row_gap: FLEX_GAP,
+            column_gap: FLEX_GAP,
+            flex_direction: FlexDirection::Column,
+            border: BORDER_WIDTH,
+            ..Default::default()
+        },
+        border_color: BorderColor(LIGHT_GRAY),
+        background_color: BackgroundColor(Color::rgba(0.0, 0.0, 0.0, 0.7)),
+        visibility: Visibility::Hidden,
+        ..Default::default()
+    };
+    let slider = NodeBundle {
+        style: Style {
+            width: SLIDER_WIDTH,
+            height: SLIDER_HEIGHT,
+            ..Default::default()
+        },
+        background_color: BackgroundColor(Color::DARK_GRAY),
+        ..Default::default()
+    };
+    let slider_fill = NodeBundle {
+        style: Style {
+            width: Val::Percent(100.0),
+            height: Val::Percent(100.0),
+            ..Default::default()
+        },
+        background_color: BackgroundColor(LIGHT_GRAY),
+        ..Default::default()
+    };
+
+    commands
+        .spawn((panel, SettingsPanel, SessionPanel("settings")))
+        .with_children(|panel| {
+            let title = text_bundle_builder("Settings".to_string(), TITLE_FONT);
+            panel.spawn(title);
+
+            let intensity = text_bundle_builder(String::new(), NORMAL_FONT);
+            panel.spawn((intensity, IntensityText));
+
+            panel
+                .spawn((
+                    slider,
+                    IntensitySlider,
+                    Interaction::default(),
+                    RelativeCursorPosition::default(),
+                ))
+                .with_children(|slider| {
+                    slider.spawn((slider_fill, IntensitySliderFill));
+                });
+
+            for option in SettingOption::ALL {
+                panel.spawn(setting_row()).with_children(|row| {
+                    let decrease = text_bundle_builder("[-]".to_string(), NORMAL_FONT);
+                    row.spawn((decrease, Interaction::default(), SettingStep(option, false)));
+                    let increase = text_bundle_builder("[+]".to_string(), NORMAL_FONT);
+                    row.spawn((increase, Interaction::default(), SettingStep(option, true)));
+                    let value = text_bundle_builder(String::new(), NORMAL_FONT);
+                    row.spawn((value, SettingText(option)));
+                    if option == SettingOption::UiScale {
+                        let auto = text_bundle_builder("[auto]".to_string(), NORMAL_FONT);
+                        row.spawn((auto, Interaction::default(), AutoUiScaleButton));
+                    }
+                    if option == SettingOption::MusicVolume {
+                        let mute = text_bundle_builder("[mute]".to_string(), NORMAL_FONT);
+                        row.spawn((mute, Interaction::default(), MusicMuteButton));
+                    }
+                });
+            }
+
+            for toggle in [BeaconToggle::Home, BeaconToggle::Waypoints] {
+                let beacons = text_bundle_builder(String::new(), NORMAL_FONT);
+                panel.spawn((beacons, Interaction::default(), toggle));
+            }
+
+            let relays = text_bundle_builder(String::new(), NORMAL_FONT);
+            panel.spawn((relays, Interaction::default(), RelaysToggle));
+        });
+}
+
+fn setting_row() -> NodeBundle {
+    NodeBundle {
+        style: Style {
+            column_gap: FLEX_GAP,
+            align_items: AlignItems::Center,
+            ..Default::default()
+        },
+        ..Default::default()
+    }
+}
+
+fn toggle_settings_panel(
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    key_bindings: Res<KeyBindings>,
+    mut panel_query: Query<&mut Visibility, With<SettingsPanel>>,
+) {
+    if keyboard_input.just_pressed(key_bindings.key(Action::ToggleSettings)) {
+        for mut visibility in panel_query.iter_mut() {
+            *visibility = match *visibility {
+                Visibility::Hidden => Visibility::Visible,
+                _ => Visibility::Hidden,
+            };
+        }
+    }
+}
+
+fn drag_intensity_slider(
+    mut settings: ResMut<Settings>,
+    slider_query: Query<(&Interaction, &RelativeCursorPosition), With<IntensitySlider>>,
+) {
+    for (interaction, cursor) in slider_query.iter() {
+        if *interaction != Interaction::Pressed {
+            continue;
+        }
+        let Some(position) = cursor.normalized else {
+            continue;
+        };
+        let intensity = slider_intensity(position.x);
+        if settings.mining_intensity != intensity {
+            settings.mining_intensity = intensity;
+        }
+    }
+}
+
+fn step_settings(
+    mut settings: ResMut<Settings>,
+    step_query: Query<(&Interaction, &SettingStep), Changed<Interaction>>,
+    relays_query: Query<&Interaction, (Changed<Interaction>, With<RelaysToggle>)>,
+    auto_ui_scale_query: Query<&Interaction, (Changed<Interaction>, With<AutoUiScaleButton>)>,
+    mute_query: Query<&Interaction, (Changed<Interaction>, With<MusicMuteButton>)>,
+    beacon_query: Query<(&Interaction, &BeaconToggle), Changed<Interaction>>,
+) {
+    for (interaction, step) in step_query.iter() {
+        if *interaction == Interaction::Pressed {
+            settings.step(step.0, step.1);
+        }
+    }
+    for interaction in relays_query.iter() {
+        if *interaction == Interaction::Pressed {
+            settings.show_relays = !settings.show_relays;
+        }
+    }
+    for interaction in auto_ui_scale_query.iter() {
+        if *interaction == Interaction::Pressed && !settings.auto_ui_scale {
+            settings.auto_ui_scale = true;
+        }
+    }
+    for interaction in mute_query.iter() {
+        if *interaction == Interaction::Pressed {
+            settings.music_muted = !settings.music_muted;
+        }
+    }
+    for (interaction, toggle) in beacon_query.iter() {
+        if *interaction != Interaction::Pressed {
+            continue;
+        }
+        match toggle {
+            BeaconToggle::Home => settings.home_beacon = !settings.home_beacon,
+            BeaconToggle::Waypoints => settings.waypoint_beacons = !settings.waypoint_beacons,
+        }
+    }
+}
+
+// Maps the cursor position along the slider to a snapped intensity
+fn slider_intensity(position: f32) -> u8 {
+    let percent = (position.clamp(0.0, 1.0) * 100.0).round() as u8;
+    let snapped =
+        (percent + MINING_INTENSITY_STEP / 2) / MINING_INTENSITY_STEP * MINING_INTENSITY_STEP;
+    snapped.clamp(MIN_MINING_INTENSITY, MAX_MINING_INTENSITY)
+}
+
+fn update_settings_panel(
+    settings: Res<Settings>,
+    key_bindings: Res<KeyBindings>,
+    mut text_query: Query<
+        (
+            &mut Text,
+            Option<&SettingText>,
+            Has<RelaysToggle>,
+            Has<MusicMuteButton>,
+            Option<&BeaconToggle>,
+        ),
+        Or<(
+            With<IntensityText>,
+            With<SettingText>,
+            With<RelaysToggle>,
+            With<MusicMuteButton>,
+            With<BeaconToggle>,
+        )>,
+    >,
+    mut fill_query: Query<&mut Style, With<IntensitySliderFill>>,
+) {
+    // The relay row names the key that tests them
+    if !settings.is_changed() && !key_bindings.is_changed() {
+        return;
+    }
+    for (mut text, setting, relays, mute, beacon) in text_query.iter_mut() {
+        text.sections[0].value = match setting {
+            Some(setting) => settings.describe(setting.0),
+            None if beacon == Some(&BeaconToggle::Home) => {
+                format!("[Home beacon: {}]", on_off(settings.home_beacon))
+            }
+            None if beacon.is_some() => {
+                format!("[Waypoint beacons: {}]", on_off(settings.waypoint_beacons))
+            }
+            None if mute && settings.music_muted => "[unmute]".to_string(),
+            None if mute => "[mute]".to_string(),
+            None if relays => format!(
+                "[Relays: {}] {} tests them",
+                if settings.show_relays {
+                    "shown"
+                } else {
+                    "hidden"
+                },
+                key_name(key_bindings.key(Action::ProbeRelays))
+            ),
+            None => format!("Mining intensity: {}%", settings.mining_intensity),
+        };
+    }
+    for mut style in fill_query.iter_mut() {
+        style.width = Val::Percent(settings.mining_intensity as f32);
+    }
+}
+
+fn on_off(enabled: bool) -> &'static str {
+    if enabled {
+        "on"
+    } else {
+        "off"
+    }
+}
+
+// Bevy already scales the UI by the OS scale factor, this makes up for a large screen
+// left at 100% so the panels stay as big as they are on a 1080p one. Never shrinks them
+fn detected_ui_scale(physical_height: u32, scale_factor: f32) -> f32 {
+    let scale = physical_height as f32 / UI_REFERENCE_HEIGHT / scale_factor.max(1.0);
+    ((scale * 10.0).round() / 10.0).clamp(1.0, MAX_UI_SCALE)
+}
+
+fn detect_ui_scale(
+    mut settings: ResMut<Settings>,
+    window_query: Query<Ref<Window>, With<PrimaryWindow>>,
+) {
+    let Ok(window) = window_query.get_single() else {
+        return;
+    };
+    if !settings.auto_ui_scale || (!window.is_changed() && !settings.is_changed()) {
+        return;
+    }
+    let detected = detected_ui_scale(
+        window.resolution.physical_height(),
+        window.resolution.scale_factor(),
+    );
+    if settings.ui_scale != detected {
+        settings.ui_scale = detected;
+    }
+}
+
+fn apply_ui_scale(settings: Res<Settings>, mut ui_scale: ResMut<UiScale>) {
+    if settings.is_changed() && ui_scale.0 != settings.ui_scale {
+        ui_scale.0 = settings.ui_scale;
+    }
+}
+
+// Edits to the file replace the settings as a whole, a broken file changes nothing
+fn reload_settings(mut reloaded: EventReader<ConfigReloaded>, mut settings: ResMut<Settings>) {
+    for config in reloaded.read() {
+        let Some(loaded) = Settings::from_config(config.0.clone()) else {
+            continue;
+        };
+        if *settings != loaded {
+            *settings = loaded;
+        }
+    }
+}
+
+fn label_command(
+    mut settings: ResMut<Settings>,
+    mut console_commands: EventReader<ConsoleCommand>,
+    mut console_output: EventWriter<ConsoleOutput>,
+) {
+    for command in console_commands.read() {
+        if command.name != "label" {
+            continue;
+        }
+        let label = trim_label(&command.args.join(" "));
+        settings.block_label = label.clone().unwrap_or_default();
+        console_output.send(ConsoleOutput(match label {
+            Some(label) => format!("Blocks you queue are labelled \"{}\"", label),
+            None => "Blocks you queue have no label".to_string(),
+        }));
+    }
+}
+
+// Waits for the slider to be released so dragging doesn't write the file every frame
+fn save_settings(
+    settings: Res<Settings>,
+    mouse_input: Res<ButtonInput<MouseButton>>,
+    mut pending_save: Local<bool>,
+) {
+    if settings.is_changed() && !settings.is_added() {
+        *pending_save = true;
+    }
+    if *pending_save && !mouse_input.pressed(MouseButton::Left) {
+        settings.save();
+        *pending_save = false;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::MAX_LABEL_CHARS;
+
+    #[test]
+    fn slider_snaps_and_clamps_intensity() {
+        assert_eq!(slider_intensity(0.0), MIN_MINING_INTENSITY);
+        assert_eq!(slider_intensity(0.52), 50);
+        assert_eq!(slider_intensity(0.53), 55);
+        assert_eq!(slider_intensity(1.5), MAX_MINING_INTENSITY);
+    }
+
+    #[test]
+    fn config_fills_missing_fields_and_clamps() {
+        let settings: Settings = toml::from_str("").unwrap();
+        assert_eq!(settings, Settings::default());
+        let settings = toml::from_str::<Settings>("mining_intensity = 0")
+            .unwrap()
+            .clamped();
+        assert_eq!(settings.mining_intensity, MIN_MINING_INTENSITY);
+        let settings = toml::from_str::<Settings>("mining_threads = 0\nui_scale = 9.0")
+            .unwrap()
+            .clamped();
+        assert_eq!(settings.mining_threads, 1);
+        assert_eq!(settings.ui_scale, MAX_UI_SCALE);
+    }
+
+    #[test]
+    fn settings_ignore_the_key_bindings_table() {
+        let config: toml::Table = r#"
+            bloom_intensity = 0.5
+            relays = ["wss://relay.example.com"]
+
+            [keybindings]
+            mine = "KeyZ"
+        "#
+        .parse()
+        .unwrap();
+        let settings = Settings::from_config(config).unwrap();
+        assert_eq!(settings.bloom_intensity, 0.5);
+        assert_eq!(settings.relays, vec!["wss://relay.example.com".to_string()]);
+        assert_eq!(settings.key_path, DEFAULT_KEY_PATH);
+        let config: toml::Table = "mining_threads = \"many\"".parse().unwrap();
+        assert_eq!(Settings::from_config(config), None);
+    }
+
+    #[test]
+    fn steps_stay_in_range_without_drift() {
+        let mut settings = Settings::default();
+        for _ in 0..3 {
+            settings.step(SettingOption::BloomIntensity, true);
+        }
+        assert_eq!(settings.bloom_intensity, 0.36);
+        settings.ui_scale = MIN_UI_SCALE;
+        settings.step(SettingOption::UiScale, false);
+        assert_eq!(settings.ui_scale, MIN_UI_SCALE);
+        assert!(!settings.auto_ui_scale);
+        settings.step(SettingOption::UiScale, true);
+        assert_eq!(settings.ui_scale, 0.6);
+        settings.sector_load_radius = 0;
+        settings.step(SettingOption::SectorLoadRadius, false);
+        assert_eq!(settings.sector_load_radius, 0);
+        settings.mining_threads = 1;
+        settings.step(SettingOption::MiningThreads, false);
+        assert_eq!(settings.mining_threads, 1);
+        settings.music_muted = true;
+        assert_eq!(settings.music_volume(), 0.0);
+        settings.step(SettingOption::MusicVolume, true);
+        assert_eq!(settings.music_volume, 0.6);
+        settings.sound_volume = 0.1;
+        settings.step(SettingOption::SoundVolume, false);
+        settings.step(SettingOption::SoundVolume, false);
+        assert_eq!(settings.sound_volume, 0.0);
+    }
+
+    #[test]
+    fn block_label_is_trimmed_and_capped() {
+        let mut settings = Settings::default();
+        assert_eq!(settings.block_label(), None);
+        settings.block_label = "  home base  ".to_string();
+        assert_eq!(settings.block_label().as_deref(), Some("home base"));
+        settings.block_label = "x".repeat(MAX_LABEL_CHARS + 10);
+        assert_eq!(settings.block_label().unwrap().len(), MAX_LABEL_CHARS);
+    }
+
+    #[test]
+    fn ui_scale_follows_the_window_resolution() {
+        assert_eq!(detected_ui_scale(1080, 1.0), 1.0);
+        assert_eq!(detected_ui_scale(1440, 1.0), 1.3);
+        assert_eq!(detected_ui_scale(2160, 1.0), 2.0);
+        // The OS already doubled everything
+        assert_eq!(detected_ui_scale(2160, 2.0), 1.0);
+        assert_eq!(detected_ui_scale(720, 1.0), 1.0);
+    }
+}
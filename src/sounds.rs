@@ -0,0 +1,224 @@
+use std::time::Duration;
+
+use bevy::{
+    audio::{AudioSinkPlayback, Volume},
+    prelude::*,
+};
+
+use crate::{
+    cameras::ExplorerCamera,
+    cyberspace::CyberspaceCoordinates,
+    mining::{MiningBlock, MiningQueue, QueuedBlockStatus},
+    nostr::BlockAccepted,
+    origin::{CyberspacePosition, FloatingOrigin},
+    picking::BlockNoteReceived,
+    resources::unix_now,
+    settings::Settings,
+    UserNostrKeys,
+};
+
+const PLACE_SOUND: &str = "sounds/place.wav";
+// One second long, it loops without a seam
+const MINING_HUM_SOUND: &str = "sounds/mining_hum.wav";
+const BLOCK_ACCEPTED_SOUND: &str = "sounds/block_accepted.wav";
+const WHOOSH_SOUND: &str = "sounds/whoosh.wav";
+// The hum sits under everything else
+const HUM_VOLUME: f32 = 0.3;
+// The hum plays an octave higher at this POW
+const HUM_TOP_POW: f32 = 32.0;
+// Other players' blocks further than this make no sound
+const WHOOSH_DISTANCE: f32 = 96.0;
+// Blocks older than this are backfill from the relays, not someone building right now
+const FRESH_BLOCK_SECONDS: u64 = 60;
+// A burst of blocks whooshes once
+const WHOOSH_INTERVAL: Duration = Duration::from_millis(250);
+// Distance between the listener's ears, in blocks
+const EAR_GAP: f32 = 4.0;
+
+pub fn sounds_plugin(app: &mut App) {
+    app.add_systems(Startup, load_sound_effects).add_systems(
+        Update,
+        (
+            add_spatial_listener,
+            play_place_sound,
+            update_mining_hum,
+            play_accepted_chime,
+            play_nearby_blocks,
+        ),
+    );
+}
+
+#[derive(Resource)]
+struct SoundEffects {
+    place: Handle<AudioSource>,
+    mining_hum: Handle<AudioSource>,
+    block_accepted: Handle<AudioSource>,
+    whoosh: Handle<AudioSource>,
+}
+
+fn load_sound_effects(mut commands: Commands, asset_server: Res<AssetServer>) {
+    commands.insert_resource(SoundEffects {
+        place: asset_server.load(PLACE_SOUND),
+        mining_hum: asset_server.load(MINING_HUM_SOUND),
+        block_accepted: asset_server.load(BLOCK_ACCEPTED_SOUND),
+        whoosh: asset_server.load(WHOOSH_SOUND),
+    });
+}
+
+// Plays once at the sound volume and despawns, every effect and notification goes through this
+pub fn sound_effect(settings: &Settings) -> PlaybackSettings {
+    PlaybackSettings::DESPAWN.with_volume(Volume::new(settings.sound_volume))
+}
+
+// Speed of the hum, which raises its pitch along with it
+fn hum_pitch(best_pow: usize) -> f32 {
+    1.0 + (best_pow as f32 / HUM_TOP_POW).min(1.0)
+}
+
+#[derive(Component)]
+struct MiningHum;
+
+// Positional sounds are heard from the camera
+fn add_spatial_listener(
+    mut commands: Commands,
+    camera_query: Query<Entity, (With<ExplorerCamera>, Without<SpatialListener>)>,
+) {
+    for camera in camera_query.iter() {
+        commands
+            .entity(camera)
+            .insert(SpatialListener::new(EAR_GAP));
+    }
+}
+
+fn play_place_sound(
+    mut commands: Commands,
+    sound_effects: Res<SoundEffects>,
+    settings: Res<Settings>,
+    placed_blocks: Query<(), Added<MiningBlock>>,
+) {
+    if placed_blocks.is_empty() {
+        return;
+    }
+    commands.spawn(AudioBundle {
+        source: sound_effects.place.clone(),
+        settings: sound_effect(&settings),
+    });
+}
+
+// Hums while anything is being mined, higher the better the best POW found so far
+fn update_mining_hum(
+    mut commands: Commands,
+    sound_effects: Res<SoundEffects>,
+    settings: Res<Settings>,
+    mining_queue: Res<MiningQueue>,
+    hum_query: Query<(Entity, Option<&AudioSink>), With<MiningHum>>,
+) {
+    let best_pow = mining_queue
+        .blocks
+        .iter()
+        .filter(|block| block.status == QueuedBlockStatus::Mining)
+        .map(|block| block.best_pow)
+        .max();
+    let volume = settings.sound_volume * HUM_VOLUME;
+    match (best_pow, hum_query.get_single()) {
+        (Some(best_pow), Err(_)) => {
+            commands.spawn((
+                AudioBundle {
+                    source: sound_effects.mining_hum.clone(),
+                    settings: PlaybackSettings::LOOP
+                        .with_volume(Volume::new(volume))
+                        .with_speed(hum_pitch(best_pow)),
+                },
+                MiningHum,
+            ));
+        }
+        (None, Ok((hum, _))) => commands.entity(hum).despawn(),
+        // The sink shows up a frame after the hum is spawned
+        (Some(best_pow), Ok((_, Some(sink)))) => {
+            if mining_queue.is_changed() {
+                sink.set_speed(hum_pitch(best_pow));
+            }
+            if settings.is_changed() {
+                sink.set_volume(volume);
+            }
+        }
+        _ => {}
+    }
+}
+
+// The notification for an accepted block is silent, this is its sound
+fn play_accepted_chime(
+    mut commands: Commands,
+    sound_effects: Res<SoundEffects>,
+    settings: Res<Settings>,
+    mut accepted_events: EventReader<BlockAccepted>,
+) {
+    if accepted_events.read().count() == 0 {
+        return;
+    }
+    commands.spawn(AudioBundle {
+        source: sound_effects.block_accepted.clone(),
+        settings: sound_effect(&settings),
+    });
+}
+
+// Another player's new block whooshes in from where it landed
+fn play_nearby_blocks(
+    mut commands: Commands,
+    time: Res<Time>,
+    sound_effects: Res<SoundEffects>,
+    settings: Res<Settings>,
+    user_keys: Res<UserNostrKeys>,
+    origin: Res<FloatingOrigin>,
+    camera_query: Query<&GlobalTransform, With<ExplorerCamera>>,
+    mut block_notes: EventReader<BlockNoteReceived>,
+    mut last_whoosh: Local<Option<Duration>>,
+) {
+    let Ok(camera_transform) = camera_query.get_single() else {
+        block_notes.clear();
+        return;
+    };
+    let camera_position = camera_transform.translation();
+    let our_pubkey = user_keys.get_public_key();
+    let now = unix_now();
+    let nearest = block_notes
+        .read()
+        .filter(|block_note| {
+            block_note.note.get_pubkey() != our_pubkey
+                && block_note.note.get_created_at() >= now.saturating_sub(FRESH_BLOCK_SECONDS)
+        })
+        .filter_map(|block_note| {
+            let coordinates = CyberspaceCoordinates::from_hex(&block_note.coordinates).ok()?;
+            let distance = origin.position(coordinates).distance(camera_position);
+            (distance <= WHOOSH_DISTANCE).then_some((coordinates, distance))
+        })
+        .min_by(|(_, first), (_, second)| first.total_cmp(second));
+    let Some((coordinates, _)) = nearest else {
+        return;
+    };
+    let elapsed = time.elapsed();
+    if last_whoosh.is_some_and(|last| elapsed < last + WHOOSH_INTERVAL) {
+        return;
+    }
+    *last_whoosh = Some(elapsed);
+    commands.spawn((
+        AudioBundle {
+            source: sound_effects.whoosh.clone(),
+            settings: sound_effect(&settings).with_spatial(true),
+        },
+        TransformBundle::from_transform(Transform::from_translation(origin.position(coordinates))),
+        CyberspacePosition(coordinates),
+    ));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hum_rises_an_octave_with_the_best_pow() {
+        assert_eq!(hum_pitch(0), 1.0);
+        assert_eq!(hum_pitch(16), 1.5);
+        assert_eq!(hum_pitch(80), 2.0);
+    }
+}
@@ -0,0 +1,114 @@
+use bevy::{pbr::NotShadowCaster, prelude::*};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+use crate::{cameras::BlockIndicator, chunks::batch_mesh};
+
+// Same sky for everyone
+const STARFIELD_SEED: u64 = 333;
+const STARS_PER_LAYER: usize = 1500;
+// Inside the camera's far plane
+const STARFIELD_RADIUS: f32 = 800.0;
+const STAR_SIZE: f32 = 1.5;
+// The sky follows the indicator but sways up to this far as it moves, for a bit of parallax
+const PARALLAX_AMPLITUDE: f32 = 40.0;
+// World units of movement per full sway
+const PARALLAX_PERIOD: f32 = 2048.0;
+// Dim, normal and bright stars, one mesh each
+const STAR_LAYERS: [f32; 3] = [2.0, 8.0, 40.0];
+
+pub fn starfield_plugin(app: &mut App) {
+    app.add_systems(Startup, setup_starfield)
+        .add_systems(Update, follow_indicator);
+}
+
+#[derive(Component)]
+struct Starfield;
+
+// Points spread evenly over a sphere of `radius`
+fn star_positions(count: usize, rng: &mut StdRng, radius: f32) -> Vec<Vec3> {
+    let mut positions = Vec::with_capacity(count);
+    while positions.len() < count {
+        let point = Vec3::new(
+            rng.gen_range(-1.0..1.0),
+            rng.gen_range(-1.0..1.0),
+            rng.gen_range(-1.0..1.0),
+        );
+        // Only points inside the unit ball, so the directions aren't bunched at the corners
+        let length = point.length();
+        if length > 0.01 && length <= 1.0 {
+            positions.push(point / length * radius);
+        }
+    }
+    positions
+}
+
+fn setup_starfield(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+    let star_mesh = Mesh::from(Cuboid {
+        half_size: Vec3::splat(STAR_SIZE / 2.0),
+    });
+    let mut rng = StdRng::seed_from_u64(STARFIELD_SEED);
+
+    commands
+        .spawn((SpatialBundle::default(), Starfield))
+        .with_children(|starfield| {
+            for brightness in STAR_LAYERS {
+                let positions = star_positions(STARS_PER_LAYER, &mut rng, STARFIELD_RADIUS);
+                let Some(mesh) = batch_mesh(&star_mesh, &positions) else {
+                    continue;
+                };
+                let material = materials.add(StandardMaterial {
+                    base_color: Color::BLACK,
+                    emissive: Color::rgb_linear(brightness, brightness, brightness),
+                    unlit: true,
+                    ..Default::default()
+                });
+                starfield.spawn((
+                    PbrBundle {
+                        mesh: meshes.add(mesh),
+                        material,
+                        ..Default::default()
+                    },
+                    NotShadowCaster,
+                ));
+            }
+        });
+}
+
+fn follow_indicator(
+    block_indicator: Query<&Transform, (With<BlockIndicator>, Without<Starfield>)>,
+    mut starfield_query: Query<&mut Transform, With<Starfield>>,
+) {
+    let Ok(indicator_transform) = block_indicator.get_single() else {
+        return;
+    };
+    for mut starfield_transform in starfield_query.iter_mut() {
+        starfield_transform.translation =
+            indicator_transform.translation + parallax_offset(indicator_transform.translation);
+    }
+}
+
+// Stays bounded however far out the indicator goes, unlike lagging behind it
+fn parallax_offset(position: Vec3) -> Vec3 {
+    let phase = position / PARALLAX_PERIOD * std::f32::consts::TAU;
+    -Vec3::new(phase.x.sin(), phase.y.sin(), phase.z.sin()) * PARALLAX_AMPLITUDE
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stars_sit_on_the_sphere_and_repeat_with_the_seed() {
+        let stars = star_positions(100, &mut StdRng::seed_from_u64(STARFIELD_SEED), 10.0);
+        assert_eq!(stars.len(), 100);
+        assert!(stars
+            .iter()
+            .all(|star| (star.length() - 10.0).abs() < 0.001));
+        let again = star_positions(100, &mut StdRng::seed_from_u64(STARFIELD_SEED), 10.0);
+        assert_eq!(stars, again);
+    }
+}
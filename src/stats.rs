@@ -0,0 +1,267 @@
+use std::{collections::BTreeMap, sync::atomic::Ordering, time::Duration};
+
+use bevy::{app::AppExit, prelude::*, utils::HashMap};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    keybindings::{Action, KeyBindings},
+    logging::MINING_LOG,
+    mining::HashCounter,
+    nostr::BlockAccepted,
+    resources::material_name,
+    session::SessionPanel,
+    ui_camera::{
+        text_bundle_builder, BORDER_WIDTH, FLEX_GAP, LIGHT_GRAY, MARGIN_UI, NORMAL_FONT,
+        PADDING_UI, TITLE_FONT,
+    },
+};
+
+const STATS_FILE_PATH: &str = "./nostrcraft_stats.toml";
+const STATS_SAVE_INTERVAL: Duration = Duration::from_secs(30);
+
+pub fn stats_plugin(app: &mut App) {
+    app.insert_resource(MiningStats::load())
+        .insert_resource(StatsSaveTimer(Timer::new(
+            STATS_SAVE_INTERVAL,
+            TimerMode::Repeating,
+        )))
+        .add_systems(PostStartup, setup_stats_screen)
+        .add_systems(
+            Update,
+            (
+                count_hashes,
+                count_mined_blocks,
+                toggle_stats_screen,
+                update_stats_screen,
+            ),
+        )
+        .add_systems(Last, save_stats);
+}
+
+// Lifetime mining career, stored in `nostrcraft_stats.toml`
+#[derive(Resource, Serialize, Deserialize, Debug, Default, Clone, PartialEq)]
+#[serde(default)]
+pub struct MiningStats {
+    pub total_hashes: u64,
+    pub blocks_mined: u64,
+    pub best_pow: usize,
+    pub materials: BTreeMap<String, u64>,
+}
+
+impl MiningStats {
+    fn load() -> Self {
+        let Ok(stats) = std::fs::read_to_string(STATS_FILE_PATH) else {
+            return MiningStats::default();
+        };
+        toml::from_str(&stats).unwrap_or_else(|error| {
+            warn!(target: MINING_LOG, "Could not read {}: {}", STATS_FILE_PATH, error);
+            MiningStats::default()
+        })
+    }
+
+    fn save(&self) {
+        let stats = match toml::to_string_pretty(self) {
+            Ok(stats) => stats,
+            Err(error) => {
+                warn!(target: MINING_LOG, "Could not serialize mining stats: {}", error);
+                return;
+            }
+        };
+        if let Err(error) = std::fs::write(STATS_FILE_PATH, stats) {
+            warn!(target: MINING_LOG, "Could not write {}: {}", STATS_FILE_PATH, error);
+        }
+    }
+
+    pub fn record_block(&mut self, pow_bits: usize) {
+        self.blocks_mined += 1;
+        self.best_pow = self.best_pow.max(pow_bits);
+        *self
+            .materials
+            .entry(material_name(pow_bits).to_string())
+            .or_insert(0) += 1;
+    }
+
+    // A block counted before got more POW, it only moves to the material that reaches
+    pub fn record_improvement(&mut self, previous_bits: usize, pow_bits: usize) {
+        self.best_pow = self.best_pow.max(pow_bits);
+        let (previous, material) = (material_name(previous_bits), material_name(pow_bits));
+        if previous == material {
+            return;
+        }
+        if let Some(count) = self.materials.get_mut(previous) {
+            *count = count.saturating_sub(1);
+        }
+        self.materials.retain(|_, count| *count > 0);
+        *self.materials.entry(material.to_string()).or_insert(0) += 1;
+    }
+}
+
+#[derive(Resource, Deref, DerefMut)]
+struct StatsSaveTimer(Timer);
+
+// The hash counter only counts this session, so we add what changed since the last frame
+fn count_hashes(
+    hash_counter: Res<HashCounter>,
+    mut stats: ResMut<MiningStats>,
+    mut last_count: Local<u64>,
+) {
+    let count = hash_counter.load(Ordering::Relaxed);
+    if count > *last_count {
+        stats.total_hashes += count - *last_count;
+        *last_count = count;
+    }
+}
+
+// Miners publish a note for every improvement of the same block, so a coordinate is only counted
+// the first time a relay accepts one of its notes
+fn count_mined_blocks(
+    mut accepted_events: EventReader<BlockAccepted>,
+    mut stats: ResMut<MiningStats>,
+    mut accepted_pow: Local<HashMap<String, usize>>,
+) {
+    for event in accepted_events.read() {
+        let block_details = &event.block_details;
+        let pow_bits = block_details.pow_amount;
+        match accepted_pow.get(&block_details.coordinates).copied() {
+            None => stats.record_block(pow_bits),
+            Some(previous_bits) if pow_bits > previous_bits => {
+                stats.record_improvement(previous_bits, pow_bits)
+            }
+            // Acks for older notes can come in after newer ones
+            Some(_) => continue,
+        }
+        accepted_pow.insert(block_details.coordinates.clone(), pow_bits);
+    }
+}
+
+fn save_stats(
+    time: Res<Time>,
+    stats: Res<MiningStats>,
+    mut save_timer: ResMut<StatsSaveTimer>,
+    mut app_exit: EventReader<AppExit>,
+) {
+    let exiting = app_exit.read().next().is_some();
+    if save_timer.tick(time.delta()).just_finished() || exiting {
+        stats.save();
+    }
+}
+
+#[derive(Component)]
+struct StatsScreen;
+
+#[derive(Component)]
+struct StatsText;
+
+fn setup_stats_screen(mut commands: Commands) {
+    let stats_screen = NodeBundle {
+        style: Style {
+            position_type: PositionType::Absolute,
+            top: Val::Percent(30.0),
+            left: Val::Percent(40.0),
+            margin: MARGIN_UI,
+            padding: PADDING_UI,
+            row_gap: FLEX_GAP,
+            column_gap: FLEX_GAP,
+            flex_direction: FlexDirection::Column,
+            border: BORDER_WIDTH,
+            ..Default::default()
+        },
+        border_color: BorderColor(LIGHT_GRAY),
+        background_color: BackgroundColor(Color::rgba(0.0, 0.0, 0.0, 0.7)),
+        visibility: Visibility::Hidden,
+        ..Default::default()
+    };
+
+    commands
+        .spawn((stats_screen, StatsScreen, SessionPanel("stats")))
+        .with_children(|stats_screen| {
+            let title = text_bundle_builder("Mining Career".to_string(), TITLE_FONT);
+            stats_screen.spawn(title);
+
+            let stats = text_bundle_builder(String::new(), NORMAL_FONT);
+            stats_screen.spawn((stats, StatsText));
+        });
+}
+
+fn toggle_stats_screen(
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    key_bindings: Res<KeyBindings>,
+    mut screen_query: Query<&mut Visibility, With<StatsScreen>>,
+) {
+    if keyboard_input.just_pressed(key_bindings.key(Action::ToggleStats)) {
+        for mut visibility in screen_query.iter_mut() {
+            *visibility = match *visibility {
+                Visibility::Hidden => Visibility::Visible,
+                _ => Visibility::Hidden,
+            };
+        }
+    }
+}
+
+fn update_stats_screen(
+    stats: Res<MiningStats>,
+    screen_query: Query<&Visibility, With<StatsScreen>>,
+    mut text_query: Query<&mut Text, With<StatsText>>,
+) {
+    // Skip the work while the screen is closed
+    if !screen_query
+        .iter()
+        .any(|visibility| *visibility == Visibility::Visible)
+    {
+        return;
+    }
+
+    let mut stats_details = format!(
+        "Total hashes: {}\nBlocks mined: {}\nBest POW: {} bits\n",
+        stats.total_hashes, stats.blocks_mined, stats.best_pow
+    );
+    if !stats.materials.is_empty() {
+        stats_details.push_str("Blocks by material\n");
+        for (material, count) in stats.materials.iter() {
+            stats_details.push_str(&format!("  {}: {}\n", material, count));
+        }
+    }
+
+    for mut text in text_query.iter_mut() {
+        text.sections[0].value = stats_details.clone();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn record_block_tracks_best_and_materials() {
+        let mut stats = MiningStats::default();
+        stats.record_block(9);
+        stats.record_block(21);
+        stats.record_block(10);
+        assert_eq!(stats.blocks_mined, 3);
+        assert_eq!(stats.best_pow, 21);
+        assert_eq!(stats.materials.get("Bronze"), Some(&2));
+        assert_eq!(stats.materials.get("Mithril"), Some(&1));
+    }
+
+    #[test]
+    fn improvements_move_a_block_to_its_new_material() {
+        let mut stats = MiningStats::default();
+        stats.record_block(9);
+        stats.record_improvement(9, 10);
+        assert_eq!(stats.materials.get("Bronze"), Some(&1));
+        stats.record_improvement(10, 21);
+        assert_eq!(stats.blocks_mined, 1);
+        assert_eq!(stats.best_pow, 21);
+        assert_eq!(stats.materials.get("Bronze"), None);
+        assert_eq!(stats.materials.get("Mithril"), Some(&1));
+    }
+
+    #[test]
+    fn stats_survive_a_round_trip() {
+        let mut stats = MiningStats::default();
+        stats.total_hashes = 123_456;
+        stats.record_block(16);
+        let saved = toml::to_string_pretty(&stats).unwrap();
+        assert_eq!(toml::from_str::<MiningStats>(&saved).unwrap(), stats);
+    }
+}
@@ -0,0 +1,181 @@
+use std::{
+    collections::VecDeque,
+    future::Future,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use anyhow::anyhow;
+use bevy::{prelude::*, utils::HashMap};
+
+use crate::logging::NOSTR_LOG;
+
+const INITIAL_BACKOFF: Duration = Duration::from_millis(500);
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+// Failed tasks stay listed for the diagnostics overlay, the oldest go past this
+const MAX_FAILED_TASKS: usize = 16;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RestartPolicy {
+    Never,
+    OnFailure { max_restarts: u32 },
+    Always,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum TaskStatus {
+    Running,
+    Restarting,
+    Failed,
+}
+
+#[derive(Clone, Debug)]
+pub struct TaskHealth {
+    pub status: TaskStatus,
+    pub restarts: u32,
+    pub last_error: Option<String>,
+}
+
+#[derive(Default)]
+struct SupervisedTasks {
+    health: HashMap<String, TaskHealth>,
+    // Oldest first
+    failed: VecDeque<String>,
+}
+
+// Shared between the ECS and the tokio tasks, each background task reports its own health here.
+// Tasks that finish are forgotten
+#[derive(Resource, Clone, Default)]
+pub struct TaskSupervisor(Arc<Mutex<SupervisedTasks>>);
+
+impl TaskSupervisor {
+    // Must be called from within the tokio runtime
+    pub fn supervise<F, Fut>(&self, name: String, policy: RestartPolicy, make_task: F)
+    where
+        F: Fn() -> Fut + Send + 'static,
+        Fut: Future<Output = anyhow::Result<()>> + Send + 'static,
+    {
+        let supervisor = self.clone();
+        tokio::spawn(async move {
+            let mut restarts = 0;
+            let mut failures_in_a_row = 0;
+            loop {
+                supervisor.report(&name, TaskStatus::Running, restarts, None);
+                let started_at = Instant::now();
+
+                // Run the task in its own tokio task so panics are caught as failures
+                let result = match tokio::spawn(make_task()).await {
+                    Ok(result) => result,
+                    Err(join_error) => Err(anyhow!("Task panicked: {}", join_error)),
+                };
+
+                let error = match result {
+                    Ok(()) if policy != RestartPolicy::Always => {
+                        supervisor.forget(&name);
+                        return;
+                    }
+                    Ok(()) => None,
+                    Err(error) => {
+                        warn!(target: NOSTR_LOG, "Task {} failed: {}", name, error);
+                        Some(error.to_string())
+                    }
+                };
+
+                let may_restart = match policy {
+                    RestartPolicy::Never => false,
+                    RestartPolicy::OnFailure { max_restarts } => restarts < max_restarts,
+                    RestartPolicy::Always => true,
+                };
+                if !may_restart {
+                    supervisor.report(&name, TaskStatus::Failed, restarts, error);
+                    return;
+                }
+
+                // A task that stayed up for a while gets its backoff reset
+                if started_at.elapsed() > MAX_BACKOFF {
+                    failures_in_a_row = 0;
+                }
+                let backoff = restart_backoff(failures_in_a_row);
+                failures_in_a_row += 1;
+                restarts += 1;
+
+                supervisor.report(&name, TaskStatus::Restarting, restarts, error);
+                tokio::time::sleep(backoff).await;
+            }
+        });
+    }
+
+    pub fn snapshot(&self) -> Vec<(String, TaskHealth)> {
+        let tasks = self.0.lock().unwrap();
+        let mut snapshot: Vec<(String, TaskHealth)> = tasks
+            .health
+            .iter()
+            .map(|(name, health)| (name.clone(), health.clone()))
+            .collect();
+        snapshot.sort_by(|a, b| a.0.cmp(&b.0));
+        snapshot
+    }
+
+    fn report(&self, name: &str, status: TaskStatus, restarts: u32, last_error: Option<String>) {
+        let mut tasks = self.0.lock().unwrap();
+        // A name can be reused once its task failed, the new task starts a fresh record
+        tasks.failed.retain(|failed| failed != name);
+        if status == TaskStatus::Failed {
+            tasks.failed.push_back(name.to_string());
+            while tasks.failed.len() > MAX_FAILED_TASKS {
+                if let Some(oldest) = tasks.failed.pop_front() {
+                    tasks.health.remove(&oldest);
+                }
+            }
+        }
+        let health = tasks.health.entry(name.to_string()).or_insert(TaskHealth {
+            status: TaskStatus::Running,
+            restarts: 0,
+            last_error: None,
+        });
+        health.status = status;
+        health.restarts = restarts;
+        if last_error.is_some() {
+            health.last_error = last_error;
+        }
+    }
+
+    fn forget(&self, name: &str) {
+        let mut tasks = self.0.lock().unwrap();
+        tasks.health.remove(name);
+        tasks.failed.retain(|failed| failed != name);
+    }
+}
+
+fn restart_backoff(failures_in_a_row: u32) -> Duration {
+    INITIAL_BACKOFF
+        .saturating_mul(2_u32.saturating_pow(failures_in_a_row))
+        .min(MAX_BACKOFF)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn backoff_doubles_until_capped() {
+        assert_eq!(restart_backoff(0), Duration::from_millis(500));
+        assert_eq!(restart_backoff(3), Duration::from_secs(4));
+        assert_eq!(restart_backoff(40), MAX_BACKOFF);
+    }
+
+    #[test]
+    fn only_running_and_recently_failed_tasks_are_kept() {
+        let supervisor = TaskSupervisor::default();
+        supervisor.report("relay", TaskStatus::Running, 0, None);
+        supervisor.report("miner", TaskStatus::Running, 0, None);
+        supervisor.forget("miner");
+        for index in 0..MAX_FAILED_TASKS + 2 {
+            supervisor.report(&format!("failed {:02}", index), TaskStatus::Failed, 0, None);
+        }
+        let snapshot = supervisor.snapshot();
+        assert_eq!(snapshot.len(), MAX_FAILED_TASKS + 1);
+        assert_eq!(snapshot[0].0, "failed 02");
+        assert!(snapshot.iter().all(|(name, _)| name != "miner"));
+    }
+}
@@ -0,0 +1,273 @@
+use bevy::{
+    input::touch::{TouchInput, TouchPhase},
+    prelude::*,
+    ui::UiSystem,
+};
+
+use crate::{
+    cameras::BlockIndicator,
+    chunks::WorldChunks,
+    keybindings::{Action, KeyBindings},
+    mining::{toggle_unmined_block, UnminedBlockMap},
+    origin::FloatingOrigin,
+    resources::MeshesAndMaterials,
+    ui_camera::{text_bundle_builder, LIGHT_GRAY, MARGIN_UI, NORMAL_FONT},
+};
+
+const TOUCH_BUTTON_SIZE: Val = Val::Px(56.0);
+const TOUCH_BUTTON_GAP: Val = Val::Px(6.0);
+const TOUCH_BUTTON_COLOR: Color = Color::rgba(1.0, 1.0, 1.0, 0.15);
+const TOUCH_BUTTON_PRESSED_COLOR: Color = Color::rgba(1.0, 1.0, 1.0, 0.4);
+// Pinching the fingers this many pixels closer or apart is one notch of the mouse wheel
+const PINCH_PIXELS_PER_NOTCH: f32 = 40.0;
+// Rows of the movement pad, empty slots keep the cross shape
+const MOVEMENT_PAD: [[Option<(&str, Action)>; 3]; 3] = [
+    [
+        Some(("Up", Action::MoveUp)),
+        Some(("W", Action::MoveForward)),
+        Some(("Down", Action::MoveDown)),
+    ],
+    [
+        Some(("A", Action::MoveLeft)),
+        None,
+        Some(("D", Action::MoveRight)),
+    ],
+    [None, Some(("S", Action::MoveBack)), None],
+];
+const MINING_BUTTONS: [(&str, TouchButton); 3] = [
+    ("Place", TouchButton::Place),
+    ("Mine", TouchButton::Hold(Action::Mine)),
+    ("Stop", TouchButton::Hold(Action::StopMining)),
+];
+
+pub fn touch_plugin(app: &mut App) {
+    app.init_resource::<TouchControls>()
+        .init_resource::<TouchGestures>()
+        .add_systems(PostStartup, setup_touch_buttons)
+        // Buttons press keys before the Update systems read them, like the gamepad does
+        .add_systems(PreUpdate, press_touch_buttons.after(UiSystem::Focus))
+        .add_systems(
+            Update,
+            (detect_touch, show_touch_buttons, read_touch_gestures).chain(),
+        );
+}
+
+// Set once a touchscreen is used, until then desktops never see the touch controls
+#[derive(Resource, Default, Deref, DerefMut)]
+struct TouchControls(bool);
+
+// What the fingers did this frame, read by the camera like mouse motion and wheel
+#[derive(Resource, Default, Debug, Clone, Copy, PartialEq)]
+pub struct TouchGestures {
+    // One finger drag, in pixels
+    pub orbit: Vec2,
+    // Two finger pinch, in wheel notches, positive zooms in
+    pub zoom: f32,
+}
+
+#[derive(Component, Debug, Clone, Copy, PartialEq)]
+enum TouchButton {
+    // Holds the key bound to the action down while touched
+    Hold(Action),
+    // Places or removes an unmined block at the indicator, like a left click
+    Place,
+}
+
+#[derive(Component)]
+struct TouchButtons;
+
+fn detect_touch(
+    mut touch_events: EventReader<TouchInput>,
+    mut touch_controls: ResMut<TouchControls>,
+) {
+    if touch_events.read().next().is_some() && !**touch_controls {
+        **touch_controls = true;
+    }
+}
+
+fn touch_button_bundle(label: &str) -> (ButtonBundle, TextBundle) {
+    let button = ButtonBundle {
+        style: Style {
+            width: TOUCH_BUTTON_SIZE,
+            height: TOUCH_BUTTON_SIZE,
+            justify_content: JustifyContent::Center,
+            align_items: AlignItems::Center,
+            ..Default::default()
+        },
+        background_color: BackgroundColor(TOUCH_BUTTON_COLOR),
+        border_color: BorderColor(LIGHT_GRAY),
+        ..Default::default()
+    };
+    (button, text_bundle_builder(label.to_string(), NORMAL_FONT))
+}
+
+fn setup_touch_buttons(mut commands: Commands) {
+    let side_panel = |left: bool| NodeBundle {
+        style: Style {
+            position_type: PositionType::Absolute,
+            top: Val::Percent(40.0),
+            left: if left { Val::Px(0.0) } else { Val::Auto },
+            right: if left { Val::Auto } else { Val::Px(0.0) },
+            margin: MARGIN_UI,
+            row_gap: TOUCH_BUTTON_GAP,
+            column_gap: TOUCH_BUTTON_GAP,
+            flex_direction: FlexDirection::Column,
+            ..Default::default()
+        },
+        visibility: Visibility::Hidden,
+        ..Default::default()
+    };
+    let pad_row = || NodeBundle {
+        style: Style {
+            column_gap: TOUCH_BUTTON_GAP,
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let empty_slot = || NodeBundle {
+        style: Style {
+            width: TOUCH_BUTTON_SIZE,
+            height: TOUCH_BUTTON_SIZE,
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+
+    commands
+        .spawn((side_panel(true), TouchButtons))
+        .with_children(|movement_pad| {
+            for row in MOVEMENT_PAD {
+                movement_pad.spawn(pad_row()).with_children(|pad_row| {
+                    for slot in row {
+                        let Some((label, action)) = slot else {
+                            pad_row.spawn(empty_slot());
+                            continue;
+                        };
+                        let (button, text) = touch_button_bundle(label);
+                        pad_row
+                            .spawn((button, TouchButton::Hold(action)))
+                            .with_children(|button| {
+                                button.spawn(text);
+                            });
+                    }
+                });
+            }
+        });
+    commands
+        .spawn((side_panel(false), TouchButtons))
+        .with_children(|mining_buttons| {
+            for (label, touch_button) in MINING_BUTTONS {
+                let (button, text) = touch_button_bundle(label);
+                mining_buttons
+                    .spawn((button, touch_button))
+                    .with_children(|button| {
+                        button.spawn(text);
+                    });
+            }
+        });
+}
+
+fn show_touch_buttons(
+    touch_controls: Res<TouchControls>,
+    mut panel_query: Query<&mut Visibility, With<TouchButtons>>,
+) {
+    if !touch_controls.is_changed() || !**touch_controls {
+        return;
+    }
+    for mut visibility in panel_query.iter_mut() {
+        *visibility = Visibility::Visible;
+    }
+}
+
+fn press_touch_buttons(
+    mut commands: Commands,
+    stuff: Res<MeshesAndMaterials>,
+    key_bindings: Res<KeyBindings>,
+    mut keyboard_input: ResMut<ButtonInput<KeyCode>>,
+    block_indicator: Query<&Transform, With<BlockIndicator>>,
+    origin: Res<FloatingOrigin>,
+    world_chunks: Res<WorldChunks>,
+    mut unmined_block_map: ResMut<UnminedBlockMap>,
+    mut button_query: Query<
+        (&Interaction, &TouchButton, &mut BackgroundColor),
+        Changed<Interaction>,
+    >,
+) {
+    for (interaction, touch_button, mut background) in button_query.iter_mut() {
+        let pressed = *interaction == Interaction::Pressed;
+        *background = if pressed {
+            TOUCH_BUTTON_PRESSED_COLOR.into()
+        } else {
+            TOUCH_BUTTON_COLOR.into()
+        };
+        match *touch_button {
+            TouchButton::Hold(action) if pressed => keyboard_input.press(key_bindings.key(action)),
+            TouchButton::Hold(action) => keyboard_input.release(key_bindings.key(action)),
+            TouchButton::Place if pressed => {
+                if let Ok(indicator_transform) = block_indicator.get_single() {
+                    toggle_unmined_block(
+                        &mut commands,
+                        &stuff,
+                        &mut unmined_block_map,
+                        origin.coordinates(indicator_transform.translation),
+                        world_chunks.plane(),
+                    );
+                }
+            }
+            TouchButton::Place => {}
+        }
+    }
+}
+
+// Change in distance between two fingers, in wheel notches
+fn pinch_notches(previous: [Vec2; 2], current: [Vec2; 2]) -> f32 {
+    (current[0].distance(current[1]) - previous[0].distance(previous[1])) / PINCH_PIXELS_PER_NOTCH
+}
+
+fn read_touch_gestures(
+    touches: Res<Touches>,
+    mut touch_events: EventReader<TouchInput>,
+    button_query: Query<&Interaction, With<TouchButton>>,
+    mut touch_gestures: ResMut<TouchGestures>,
+) {
+    // Only moves matter, the rest already went into `Touches`
+    let moved = touch_events
+        .read()
+        .any(|touch| touch.phase == TouchPhase::Moved);
+    // Fingers on the buttons don't turn the camera
+    let on_button = button_query
+        .iter()
+        .any(|interaction| *interaction == Interaction::Pressed);
+    let fingers: Vec<_> = touches.iter().collect();
+    let gestures = match fingers[..] {
+        _ if !moved || on_button => TouchGestures::default(),
+        [finger] => TouchGestures {
+            orbit: finger.delta(),
+            zoom: 0.0,
+        },
+        [first, second] => TouchGestures {
+            orbit: Vec2::ZERO,
+            zoom: pinch_notches(
+                [first.previous_position(), second.previous_position()],
+                [first.position(), second.position()],
+            ),
+        },
+        _ => TouchGestures::default(),
+    };
+    if *touch_gestures != gestures {
+        *touch_gestures = gestures;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn spreading_fingers_zooms_in() {
+        let previous = [Vec2::new(100.0, 100.0), Vec2::new(200.0, 100.0)];
+        let spread = [Vec2::new(80.0, 100.0), Vec2::new(220.0, 100.0)];
+        assert_eq!(pinch_notches(previous, spread), 1.0);
+        assert_eq!(pinch_notches(spread, previous), -1.0);
+    }
+}
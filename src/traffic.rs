@@ -0,0 +1,205 @@
+use bevy::prelude::*;
+
+use crate::{
+    keybindings::{Action, KeyBindings},
+    nostr::{TrafficDirection, TrafficEntry, TrafficLog},
+    session::SessionPanel,
+    ui_camera::{
+        text_bundle_builder, BORDER_WIDTH, FLEX_GAP, LIGHT_GRAY, MARGIN_UI, NORMAL_FONT,
+        PADDING_UI, TITLE_FONT,
+    },
+};
+
+// Only the latest few fit in the panel
+const SHOWN_TRAFFIC_LINES: usize = 24;
+const SHORT_PUBKEY_CHARS: usize = 8;
+
+pub fn traffic_plugin(app: &mut App) {
+    app.init_resource::<TrafficFilter>()
+        .add_systems(PostStartup, setup_traffic_panel)
+        .add_systems(
+            Update,
+            (
+                toggle_traffic_panel,
+                pick_traffic_filter,
+                update_traffic_panel,
+            )
+                .chain(),
+        );
+}
+
+// Kind of the notes shown, every message when none
+#[derive(Resource, Default, Deref, DerefMut)]
+struct TrafficFilter(Option<u32>);
+
+#[derive(Component)]
+struct TrafficPanel;
+
+#[derive(Component)]
+struct TrafficFilterButton;
+
+#[derive(Component)]
+struct TrafficLines;
+
+fn traffic_line(entry: &TrafficEntry) -> String {
+    let arrow = match entry.direction {
+        TrafficDirection::Received => "<-",
+        TrafficDirection::Sent => "->",
+    };
+    let relay = entry
+        .relay_url
+        .trim_start_matches("wss://")
+        .trim_start_matches("ws://");
+    let mut line = format!("{} {} {}", arrow, relay, entry.message);
+    if let Some(kind) = entry.kind {
+        line.push_str(&format!(" {}", kind));
+    }
+    if let Some(pubkey) = &entry.pubkey {
+        line.push_str(&format!(
+            " {}",
+            &pubkey[..pubkey.len().min(SHORT_PUBKEY_CHARS)]
+        ));
+    }
+    if !entry.content.is_empty() {
+        line.push_str(&format!(" {}", entry.content.replace('\n', " ")));
+    }
+    line
+}
+
+// Every message, then each kind seen in the log in turn
+fn next_kind_filter(current: Option<u32>, kinds: &[u32]) -> Option<u32> {
+    match current {
+        None => kinds.first().copied(),
+        Some(current) => kinds.iter().copied().find(|kind| *kind > current),
+    }
+}
+
+fn setup_traffic_panel(mut commands: Commands) {
+    let panel = NodeBundle {
+        style: Style {
+            position_type: PositionType::Absolute,
+            bottom: Val::Percent(10.0),
+            left: Val::Percent(20.0),
+            max_width: Val::Percent(60.0),
+            margin: MARGIN_UI,
+            padding: PADDING_UI,
+            row_gap: FLEX_GAP,
+            flex_direction: FlexDirection::Column,
+            border: BORDER_WIDTH,
+            ..Default::default()
+        },
+        border_color: BorderColor(LIGHT_GRAY),
+        background_color: BackgroundColor(Color::rgba(0.0, 0.0, 0.0, 0.85)),
+        visibility: Visibility::Hidden,
+        ..Default::default()
+    };
+    commands
+        .spawn((panel, TrafficPanel, SessionPanel("traffic")))
+        .with_children(|panel| {
+            panel.spawn(text_bundle_builder("Relay Traffic".to_string(), TITLE_FONT));
+            panel.spawn((
+                text_bundle_builder(String::new(), NORMAL_FONT),
+                Interaction::default(),
+                TrafficFilterButton,
+            ));
+            panel.spawn((
+                text_bundle_builder(String::new(), NORMAL_FONT),
+                TrafficLines,
+            ));
+        });
+}
+
+fn toggle_traffic_panel(
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    key_bindings: Res<KeyBindings>,
+    mut panel_query: Query<&mut Visibility, With<TrafficPanel>>,
+) {
+    if !keyboard_input.just_pressed(key_bindings.key(Action::ToggleTraffic)) {
+        return;
+    }
+    for mut visibility in panel_query.iter_mut() {
+        *visibility = match *visibility {
+            Visibility::Hidden => Visibility::Visible,
+            _ => Visibility::Hidden,
+        };
+    }
+}
+
+fn pick_traffic_filter(
+    button_query: Query<&Interaction, (Changed<Interaction>, With<TrafficFilterButton>)>,
+    traffic_log: Res<TrafficLog>,
+    mut traffic_filter: ResMut<TrafficFilter>,
+) {
+    for interaction in button_query.iter() {
+        if *interaction != Interaction::Pressed {
+            continue;
+        }
+        let mut kinds: Vec<u32> = traffic_log.iter().filter_map(|entry| entry.kind).collect();
+        kinds.sort_unstable();
+        kinds.dedup();
+        **traffic_filter = next_kind_filter(**traffic_filter, &kinds);
+    }
+}
+
+fn update_traffic_panel(
+    traffic_log: Res<TrafficLog>,
+    traffic_filter: Res<TrafficFilter>,
+    mut button_query: Query<&mut Text, (With<TrafficFilterButton>, Without<TrafficLines>)>,
+    mut lines_query: Query<&mut Text, (With<TrafficLines>, Without<TrafficFilterButton>)>,
+) {
+    if traffic_filter.is_changed() {
+        let label = match **traffic_filter {
+            Some(kind) => format!("[Kind: {}] click for the next kind", kind),
+            None => "[Kind: all] click to show one kind".to_string(),
+        };
+        for mut text in button_query.iter_mut() {
+            text.sections[0].value = label.clone();
+        }
+    }
+    if !traffic_log.is_changed() && !traffic_filter.is_changed() {
+        return;
+    }
+    let shown: Vec<String> = traffic_log
+        .iter()
+        .filter(|entry| traffic_filter.is_none() || entry.kind == **traffic_filter)
+        .map(traffic_line)
+        .collect();
+    let lines = shown[shown.len().saturating_sub(SHOWN_TRAFFIC_LINES)..].join("\n");
+    for mut text in lines_query.iter_mut() {
+        text.sections[0].value = if lines.is_empty() {
+            "Nothing yet".to_string()
+        } else {
+            lines.clone()
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lines_show_direction_kind_and_short_pubkey() {
+        let entry = TrafficEntry {
+            direction: TrafficDirection::Received,
+            relay_url: "wss://relay.arrakis.lat".to_string(),
+            message: "EVENT",
+            kind: Some(333),
+            pubkey: Some("a".repeat(64)),
+            content: "{\"x\":1,\n\"y\":2}".to_string(),
+        };
+        assert_eq!(
+            traffic_line(&entry),
+            "<- relay.arrakis.lat EVENT 333 aaaaaaaa {\"x\":1, \"y\":2}"
+        );
+    }
+
+    #[test]
+    fn kind_filter_cycles_back_to_everything() {
+        let kinds = [0, 333, 20333];
+        assert_eq!(next_kind_filter(None, &kinds), Some(0));
+        assert_eq!(next_kind_filter(Some(0), &kinds), Some(333));
+        assert_eq!(next_kind_filter(Some(20333), &kinds), None);
+        assert_eq!(next_kind_filter(None, &[]), None);
+    }
+}
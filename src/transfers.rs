@@ -0,0 +1,299 @@
+use std::collections::VecDeque;
+
+use bevy::{
+    prelude::*,
+    utils::{HashMap, HashSet},
+};
+use nostro2::notes::Note;
+use serde_json::json;
+
+use crate::{
+    console::{ConsoleCommand, ConsoleOutput, RegisterConsoleCommand},
+    nostr::OutgoingNotes,
+    notifications::{Notification, Severity},
+    picking::InspectedBlock,
+    protocol::{sector_cell_tags, TransferDetails, TRANSFER_KIND},
+    resources::{parse_pubkey, CoordinatesMap, Profiles},
+    UserNostrKeys,
+};
+
+// Blocks we haven't seen whose transfers are kept, the oldest are dropped past it
+const MAX_WAITING_CHAINS: usize = 1024;
+
+pub fn transfers_plugin(app: &mut App) {
+    app.init_resource::<BlockTransfers>()
+        .add_event::<BlockTransferred>()
+        .register_console_command(
+            "give",
+            "/give <npub or hex key>",
+            "Transfers the inspected block, if it's yours, to another key",
+        )
+        .add_systems(Update, (give_command, notify_received_blocks));
+}
+
+// A block that changed hands, sent by the middleware once the chain says so
+#[derive(Event, Debug, Clone, PartialEq)]
+pub struct BlockTransferred {
+    pub coordinates: String,
+    pub from: String,
+    pub to: String,
+}
+
+// One signed link of a block's chain of owners
+#[derive(Debug, Clone, PartialEq)]
+pub struct BlockTransfer {
+    pub id: String,
+    pub author: String,
+    pub previous: String,
+    pub recipient: String,
+    pub created_at: u64,
+}
+
+// Transfers are kept per block note id, in whatever order relays sent them, so a chain can be
+// followed even when its links arrive before the block
+#[derive(Resource, Default)]
+pub struct BlockTransfers {
+    transfers: HashMap<String, Vec<BlockTransfer>>,
+    // Note id of the block currently at each coordinate string
+    block_ids: HashMap<String, String>,
+    known_ids: HashSet<String>,
+    // Ids of blocks not seen yet that transfers were recorded for, oldest first
+    waiting: VecDeque<String>,
+}
+
+impl BlockTransfers {
+    // False for a transfer that was already known
+    pub fn record(&mut self, block_id: &str, transfer: BlockTransfer) -> bool {
+        if !self.transfers.contains_key(block_id) && !self.known_ids.contains(block_id) {
+            self.wait_for_block(block_id);
+        }
+        let transfers = self.transfers.entry(block_id.to_string()).or_default();
+        if transfers.iter().any(|known| known.id == transfer.id) {
+            return false;
+        }
+        transfers.push(transfer);
+        true
+    }
+
+    // Anyone can sign transfers of blocks that don't exist, so only the newest few are kept
+    fn wait_for_block(&mut self, block_id: &str) {
+        self.waiting.push_back(block_id.to_string());
+        while self.waiting.len() > MAX_WAITING_CHAINS {
+            let Some(oldest) = self.waiting.pop_front() else {
+                break;
+            };
+            if !self.known_ids.contains(&oldest) {
+                self.transfers.remove(&oldest);
+            }
+        }
+    }
+
+    pub fn set_block(&mut self, coordinates: &str, block_id: &str) {
+        if let Some(replaced) = self
+            .block_ids
+            .insert(coordinates.to_string(), block_id.to_string())
+        {
+            self.known_ids.remove(&replaced);
+        }
+        self.known_ids.insert(block_id.to_string());
+    }
+
+    pub fn remove_block(&mut self, coordinates: &str) {
+        if let Some(block_id) = self.block_ids.remove(coordinates) {
+            self.known_ids.remove(&block_id);
+            self.transfers.remove(&block_id);
+        }
+    }
+
+    pub fn block_id(&self, coordinates: &str) -> Option<&String> {
+        self.block_ids.get(coordinates)
+    }
+
+    // Follows the chain from the miner, every link must follow the last one and be signed by its
+    // recipient. When an owner signed two transfers of the same link, the older one counts
+    pub fn chain_head(&self, block_id: &str, miner_pubkey: &str) -> (String, String) {
+        let transfers = self
+            .transfers
+            .get(block_id)
+            .map(Vec::as_slice)
+            .unwrap_or_default();
+        let mut owner = miner_pubkey;
+        let mut head = block_id;
+        // Every link is used at most once, ids can't point back at later ones
+        for _ in 0..transfers.len() {
+            let Some(next) = transfers
+                .iter()
+                .filter(|transfer| transfer.previous == head && transfer.author == owner)
+                .min_by(|a, b| a.created_at.cmp(&b.created_at).then(a.id.cmp(&b.id)))
+            else {
+                break;
+            };
+            owner = &next.recipient;
+            head = &next.id;
+        }
+        (owner.to_string(), head.to_string())
+    }
+
+    pub fn owner(&self, block_id: &str, miner_pubkey: &str) -> String {
+        self.chain_head(block_id, miner_pubkey).0
+    }
+}
+
+fn give_command(
+    inspected_block: Res<InspectedBlock>,
+    coordinates_map: Res<CoordinatesMap>,
+    block_transfers: Res<BlockTransfers>,
+    user_keys: Res<UserNostrKeys>,
+    outgoing_notes: Res<OutgoingNotes>,
+    profiles: Res<Profiles>,
+    mut console_commands: EventReader<ConsoleCommand>,
+    mut console_output: EventWriter<ConsoleOutput>,
+) {
+    for command in console_commands.read() {
+        if command.name != "give" {
+            continue;
+        }
+        let Some(recipient) = command.args.first().and_then(|key| parse_pubkey(key)) else {
+            console_output.send(ConsoleOutput("Usage: /give <npub or hex key>".to_string()));
+            continue;
+        };
+        let Some((coordinates, block_details)) = inspected_block
+            .0
+            .as_ref()
+            .and_then(|coordinates| Some((coordinates, &coordinates_map.get(coordinates)?.1)))
+        else {
+            console_output.send(ConsoleOutput("Inspect a block to give it away".to_string()));
+            continue;
+        };
+        let Some(block_id) = block_transfers.block_id(coordinates) else {
+            console_output.send(ConsoleOutput(
+                "This block has no note to transfer yet".to_string(),
+            ));
+            continue;
+        };
+        let our_pubkey = user_keys.get_public_key();
+        let (owner, previous) = block_transfers.chain_head(block_id, &block_details.miner_pubkey);
+        if owner != our_pubkey {
+            console_output.send(ConsoleOutput(
+                "Only your own blocks can be given".to_string(),
+            ));
+            continue;
+        }
+        if recipient == our_pubkey {
+            console_output.send(ConsoleOutput("The block is already yours".to_string()));
+            continue;
+        }
+
+        let transfer_details = TransferDetails {
+            transfer: coordinates.clone(),
+            block: block_id.clone(),
+            previous,
+            recipient: recipient.clone(),
+        };
+        let mut note = Note::new(
+            our_pubkey,
+            TRANSFER_KIND,
+            &json!(transfer_details).to_string(),
+        );
+        note.tags.push(vec!["p".to_string(), recipient.clone()]);
+        note.tags.extend(sector_cell_tags(coordinates));
+        // The middleware applies it like anyone else's once a relay echoes it back
+        let _sent = outgoing_notes.send(user_keys.get_keypair().sign_nostr_event(note));
+        console_output.send(ConsoleOutput(format!(
+            "Gave the block at {} to {}",
+            block_details.display_coordinates(),
+            profiles.display_name(&recipient)
+        )));
+    }
+}
+
+fn notify_received_blocks(
+    user_keys: Res<UserNostrKeys>,
+    profiles: Res<Profiles>,
+    coordinates_map: Res<CoordinatesMap>,
+    mut transferred: EventReader<BlockTransferred>,
+    mut notifications: EventWriter<Notification>,
+) {
+    let our_pubkey = user_keys.get_public_key();
+    for transfer in transferred.read() {
+        if transfer.to != our_pubkey {
+            continue;
+        }
+        let Some((_, block_details)) = coordinates_map.get(&transfer.coordinates) else {
+            continue;
+        };
+        notifications.send(Notification {
+            message: format!(
+                "{} gave you the block at {}",
+                profiles.display_name(&transfer.from),
+                block_details.display_coordinates()
+            ),
+            severity: Severity::Success,
+            play_sound: true,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn transfer(id: &str, author: &str, previous: &str, recipient: &str) -> BlockTransfer {
+        BlockTransfer {
+            id: id.to_string(),
+            author: author.to_string(),
+            previous: previous.to_string(),
+            recipient: recipient.to_string(),
+            created_at: 0,
+        }
+    }
+
+    #[test]
+    fn chains_are_followed_whatever_order_they_arrive_in() {
+        let mut block_transfers = BlockTransfers::default();
+        assert!(block_transfers.record("block", transfer("t2", "bob", "t1", "carol")));
+        assert!(block_transfers.record("block", transfer("t1", "alice", "block", "bob")));
+        assert!(!block_transfers.record("block", transfer("t1", "alice", "block", "bob")));
+        assert_eq!(
+            block_transfers.chain_head("block", "alice"),
+            ("carol".to_string(), "t2".to_string())
+        );
+    }
+
+    #[test]
+    fn only_the_current_owner_can_transfer() {
+        let mut block_transfers = BlockTransfers::default();
+        // Mallory doesn't own the block, and Alice can't take it back after giving it away
+        block_transfers.record("block", transfer("t1", "mallory", "block", "mallory"));
+        block_transfers.record("block", transfer("t2", "alice", "block", "bob"));
+        block_transfers.record("block", transfer("t3", "alice", "t2", "carol"));
+        assert_eq!(block_transfers.owner("block", "alice"), "bob");
+        assert_eq!(block_transfers.owner("other", "alice"), "alice");
+    }
+
+    #[test]
+    fn double_transfers_keep_the_older_one() {
+        let mut block_transfers = BlockTransfers::default();
+        let mut later = transfer("t1", "alice", "block", "carol");
+        later.created_at = 20;
+        let mut earlier = transfer("t2", "alice", "block", "bob");
+        earlier.created_at = 10;
+        block_transfers.record("block", later);
+        block_transfers.record("block", earlier);
+        assert_eq!(block_transfers.owner("block", "alice"), "bob");
+    }
+
+    #[test]
+    fn transfers_of_unseen_blocks_are_dropped_oldest_first() {
+        let mut block_transfers = BlockTransfers::default();
+        block_transfers.set_block("here", "block");
+        block_transfers.record("block", transfer("t1", "alice", "block", "bob"));
+        for unseen in 0..=MAX_WAITING_CHAINS {
+            let block_id = unseen.to_string();
+            block_transfers.record(&block_id, transfer("t", "alice", &block_id, "bob"));
+        }
+        assert_eq!(block_transfers.owner("0", "alice"), "alice");
+        assert_eq!(block_transfers.owner("1", "alice"), "bob");
+        assert_eq!(block_transfers.owner("block", "alice"), "bob");
+    }
+}
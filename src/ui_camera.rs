@@ -1,43 +1,110 @@
-use bevy::prelude::*;
+use std::time::Duration;
+
+use bevy::{
+    input::{
+        keyboard::Key,
+        mouse::{MouseScrollUnit, MouseWheel},
+        InputSystem,
+    },
+    prelude::*,
+};
 
 use crate::{
-    cameras::BlockIndicator,
-    cyberspace::{encode_coordinates, extract_coordinates, scale_coordinates_to_world},
-    mining::{MiningState, UnminedBlockMap},
-    nostr::POWBlockDetails,
-    resources::{CoordinatesMap, UniqueKeys},
+    cameras::{BlockIndicator, IndicatorMoved},
+    chunks::{BlockSlice, WorldChunks},
+    cyberspace::{CyberspaceCoordinates, Plane},
+    home_bases::RosterHomeBase,
+    keybindings::{key_name, Action, KeyBindings},
+    menu::AppState,
+    mining::{
+        mining_eta, mining_probability, ChosenMaterial, DefensePolicy, Hashrate, MiningAction,
+        MiningQueue, MiningState, MiningWorker, QueuedBlock, QueuedBlockStatus, UnminedBlockMap,
+    },
+    nip05::VerifiedNames,
+    nostr::{RelayPool, RelayProbe, COUNT_NIP},
+    origin::FloatingOrigin,
+    protocol::POWBlockDetails,
+    resources::{
+        avatar_position, block_tier, tier_name, unix_now, CoordinatesMap, DriftPositions,
+        FollowList, LastSeen, Profiles, UniqueKeys, ACTIVE_SECONDS,
+    },
+    settings::Settings,
     UserNostrKeys,
 };
 
 pub fn ui_camera_plugin(app: &mut App) {
     app.init_resource::<AvatarListDetails>()
         .add_event::<PowEvent>()
+        .configure_sets(PreUpdate, TextInput.after(InputSystem))
         .add_systems(
             PostStartup,
-            (setup_coordinate_ui, setup_avatar_list, setup_mining_ui),
+            (
+                setup_coordinate_ui,
+                setup_avatar_list,
+                setup_mining_ui,
+                setup_relay_ui,
+            ),
         )
         .add_systems(
             Update,
-            (update_coordinate_ui, update_avatar_list, update_mining_ui),
-        );
+            (
+                update_coordinate_ui,
+                (
+                    order_avatar_roster,
+                    navigate_avatar_roster,
+                    update_avatar_roster_ui,
+                )
+                    .chain(),
+                update_mining_ui,
+                update_mining_queue_ui,
+                update_mining_estimate_ui,
+                (cancel_mining_workers, update_mining_workers_ui).chain(),
+                update_relay_ui,
+                update_relay_probe_ui,
+                show_relay_ui,
+            ),
+        )
+        .add_systems(OnEnter(AppState::Loading), show_mining_key);
 }
 
 #[derive(Component)]
 pub enum UiElement {
     CurrentCoordinates,
-    AvatarList(usize),
     TeleportingNotice(f32),
     MiningKey,
     MiningNotice,
+    MiningQueue,
+    MiningEstimate,
+    RelayList,
+    RelayProbe,
 }
 
-const FLEX_GAP: Val = Val::Px(8.4);
-const MARGIN_UI: UiRect = UiRect::all(Val::Percent(2.1));
-const PADDING_UI: UiRect = UiRect::all(Val::Percent(0.7));
-const BORDER_WIDTH: UiRect = UiRect::all(Val::Px(4.2));
-const LIGHT_GRAY: Color = Color::rgb(0.7, 0.7, 0.7);
-const TITLE_FONT: f32 = 18.0;
-const NORMAL_FONT: f32 = 12.0;
+#[derive(Component)]
+struct RelayPanel;
+
+// Holds a row per active mining worker
+#[derive(Component)]
+struct MiningWorkers;
+
+// Coordinate string and index of the worker a dashboard row shows
+#[derive(Component)]
+struct WorkerText(String, u64);
+
+#[derive(Component)]
+struct CancelWorker(String, u64);
+
+pub const FLEX_GAP: Val = Val::Px(8.4);
+pub const MARGIN_UI: UiRect = UiRect::all(Val::Percent(2.1));
+pub const PADDING_UI: UiRect = UiRect::all(Val::Percent(0.7));
+pub const BORDER_WIDTH: UiRect = UiRect::all(Val::Px(4.2));
+pub const LIGHT_GRAY: Color = Color::rgb(0.7, 0.7, 0.7);
+pub const TITLE_FONT: f32 = 18.0;
+pub const NORMAL_FONT: f32 = 12.0;
+// Keys shown at once in the avatar roster, the rest is scrolled to
+const ROSTER_ROWS: usize = 8;
+// The roster is reordered by distance after the indicator moves this far
+const ROSTER_REFRESH_DISTANCE: f32 = 16.0;
+const ROSTER_PIXELS_PER_ROW: f32 = 16.0;
 
 fn setup_coordinate_ui(mut commands: Commands) {
     let coordinates_ui = NodeBundle {
@@ -64,7 +131,7 @@ fn setup_coordinate_ui(mut commands: Commands) {
                 text_bundle_builder("Current Coordinates".to_string(), TITLE_FONT);
             coordinates_ui.spawn(current_coordinate_title);
 
-            let current_coordinates = multi_section_text_builder(3);
+            let current_coordinates = multi_section_text_builder(4);
             coordinates_ui.spawn((current_coordinates, UiElement::CurrentCoordinates));
         });
 }
@@ -87,144 +154,428 @@ fn setup_avatar_list(mut commands: Commands) {
         ..Default::default()
     };
 
-    commands.spawn(avatars_ui).with_children(|avatars_ui| {
-        let avatar_title = text_bundle_builder("Avatars".to_string(), TITLE_FONT);
-        avatars_ui.spawn(avatar_title);
+    commands
+        .spawn((avatars_ui, AvatarRoster, Interaction::default()))
+        .with_children(|avatars_ui| {
+            let avatar_title = text_bundle_builder("Avatars".to_string(), TITLE_FONT);
+            avatars_ui.spawn(avatar_title);
+
+            let roster_header = text_bundle_builder(String::new(), NORMAL_FONT);
+            avatars_ui.spawn((roster_header, RosterHeader));
+
+            for row in 0..ROSTER_ROWS {
+                let roster_row = text_bundle_builder(String::new(), NORMAL_FONT);
+                avatars_ui.spawn((roster_row, Interaction::default(), RosterRow(row)));
+            }
+            let home_base = text_bundle_builder(String::new(), NORMAL_FONT);
+            avatars_ui.spawn((home_base, RosterHomeBase));
+            let teleporting_notice = text_bundle_builder(String::new(), TITLE_FONT);
+            avatars_ui.spawn((teleporting_notice, UiElement::TeleportingNotice(0.0)));
+        });
+}
+
+// Scrolling over the roster moves the list instead of zooming the camera
+#[derive(Component)]
+pub struct AvatarRoster;
+
+#[derive(Component)]
+struct RosterHeader;
+
+#[derive(Component)]
+struct RosterRow(usize);
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum RosterSort {
+    #[default]
+    Distance,
+    Recency,
+    Name,
+}
+
+impl RosterSort {
+    fn next(self) -> Self {
+        match self {
+            RosterSort::Distance => RosterSort::Recency,
+            RosterSort::Recency => RosterSort::Name,
+            RosterSort::Name => RosterSort::Distance,
+        }
+    }
+
+    fn label(self) -> &'static str {
+        match self {
+            RosterSort::Distance => "distance",
+            RosterSort::Recency => "last seen",
+            RosterSort::Name => "name",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum RosterFilter {
+    #[default]
+    All,
+    Following,
+    Active,
+}
+
+impl RosterFilter {
+    fn next(self) -> Self {
+        match self {
+            RosterFilter::All => RosterFilter::Following,
+            RosterFilter::Following => RosterFilter::Active,
+            RosterFilter::Active => RosterFilter::All,
+        }
+    }
 
-        for i in 0..5 {
-            let avatar_list = text_bundle_builder(String::new(), NORMAL_FONT);
-            avatars_ui.spawn((avatar_list, UiElement::AvatarList(i)));
+    fn label(self) -> &'static str {
+        match self {
+            RosterFilter::All => "everyone",
+            RosterFilter::Following => "followed keys",
+            RosterFilter::Active => "active keys",
         }
-        let teleporting_notice = text_bundle_builder(String::new(), TITLE_FONT);
-        avatars_ui.spawn((teleporting_notice, UiElement::TeleportingNotice(0.0)));
+    }
+}
+
+// What the roster knows about a key when ordering it
+#[derive(Debug, Clone, PartialEq)]
+struct RosterEntry {
+    pubkey: String,
+    name: String,
+    distance: f32,
+    last_seen: Option<u64>,
+    followed: bool,
+}
+
+// Keys kept by the filter in the order of the sort, ties broken by key so the list doesn't jump
+fn order_roster(
+    mut entries: Vec<RosterEntry>,
+    sort: RosterSort,
+    filter: RosterFilter,
+    now: u64,
+) -> Vec<String> {
+    entries.retain(|entry| match filter {
+        RosterFilter::All => true,
+        RosterFilter::Following => entry.followed,
+        RosterFilter::Active => entry
+            .last_seen
+            .is_some_and(|last_seen| now.saturating_sub(last_seen) <= ACTIVE_SECONDS),
+    });
+    entries.sort_by(|a, b| {
+        let order = match sort {
+            RosterSort::Distance => a.distance.total_cmp(&b.distance),
+            RosterSort::Recency => b.last_seen.cmp(&a.last_seen),
+            RosterSort::Name => a.name.to_lowercase().cmp(&b.name.to_lowercase()),
+        };
+        order.then_with(|| a.pubkey.cmp(&b.pubkey))
     });
+    entries.into_iter().map(|entry| entry.pubkey).collect()
 }
 
-#[derive(Resource)]
+// First shown row after scrolling just enough for `index` to be visible
+fn scroll_to(scroll: usize, index: usize, rows: usize) -> usize {
+    if index < scroll {
+        index
+    } else if index >= scroll + rows {
+        index + 1 - rows
+    } else {
+        scroll
+    }
+}
+
+#[derive(Resource, Default)]
 pub struct AvatarListDetails {
-    selected: usize,
-    coordinate_string: String,
+    selected: Option<String>,
+    sort: RosterSort,
+    filter: RosterFilter,
+    // Keys kept by the filter, in sorted order
+    roster: Vec<String>,
+    // Index of the first key shown
+    scroll: usize,
 }
 
 impl AvatarListDetails {
-    pub fn get_coordinates(&self) -> Vec3 {
-        let i128_coordinates = extract_coordinates(&self.coordinate_string).unwrap_or((0, 0, 0));
-        let world_coordinates =
-            scale_coordinates_to_world(i128_coordinates.0, i128_coordinates.1, i128_coordinates.2);
-        Vec3::new(
-            world_coordinates.0 as f32,
-            world_coordinates.1 as f32,
-            world_coordinates.2 as f32,
-        )
+    // Public key of the highlighted avatar, none until the roster has one
+    pub fn selected_pubkey(&self) -> Option<&str> {
+        self.selected.as_deref()
     }
-}
 
-impl Default for AvatarListDetails {
-    fn default() -> Self {
-        AvatarListDetails {
-            selected: 0,
-            coordinate_string: String::new(),
-        }
+    pub fn get_coordinates(&self) -> Option<CyberspaceCoordinates> {
+        avatar_position(self.selected_pubkey()?)
+    }
+
+    fn selected_index(&self) -> Option<usize> {
+        let selected = self.selected.as_ref()?;
+        self.roster.iter().position(|pubkey| pubkey == selected)
+    }
+
+    fn max_scroll(&self) -> usize {
+        self.roster.len().saturating_sub(ROSTER_ROWS)
+    }
+
+    fn select(&mut self, index: usize) {
+        self.selected = self.roster.get(index).cloned();
+        self.scroll = scroll_to(self.scroll, index, ROSTER_ROWS);
     }
 }
 
-fn update_avatar_list(
+// Only reordered when the keys, what is known about them, or the sort and filter change
+fn order_avatar_roster(
     unique_keys: Res<UniqueKeys>,
-    mut text_query: Query<(&mut Text, &UiElement)>,
+    profiles: Res<Profiles>,
+    last_seen: Res<LastSeen>,
+    follow_list: Res<FollowList>,
+    drift_positions: Res<DriftPositions>,
+    block_indicator: Query<&Transform, With<BlockIndicator>>,
+    origin: Res<FloatingOrigin>,
     mut avatar_list: ResMut<AvatarListDetails>,
-    keyboard_input: Res<ButtonInput<KeyCode>>,
+    mut last_position: Local<Option<CyberspaceCoordinates>>,
 ) {
-    if unique_keys.len() == 0 {
+    let position = block_indicator
+        .get_single()
+        .map(|transform| transform.translation)
+        .unwrap_or_default();
+    let coordinates = origin.coordinates(position);
+    // Distances only matter to the order after moving a fair distance
+    let moved = avatar_list.sort == RosterSort::Distance
+        && last_position
+            .map(|last_position| {
+                coordinates.relative_to(last_position).length() > ROSTER_REFRESH_DISTANCE
+            })
+            .unwrap_or(true);
+    if !moved
+        && !unique_keys.is_changed()
+        && !profiles.is_changed()
+        && !last_seen.is_changed()
+        && !follow_list.is_changed()
+        && !drift_positions.is_changed()
+        && !avatar_list.is_changed()
+    {
         return;
     }
+    *last_position = Some(coordinates);
 
-    let keys_vec: Vec<&String> = unique_keys.iter().collect(); // Convert HashSet to Vec
-
-    let list_len = keys_vec.len();
-    let middle_index = 2; // Middle index for a list of 5 items
-    let selected_index = (avatar_list.selected + list_len / 2) % list_len; // Calculate selected index based on list length and ensure it's in the middle
-
-    for (i, _key) in (0..5).enumerate() {
-        let index = (selected_index + i + list_len - middle_index) % list_len;
-
-        // Get the corresponding Text component and UiElement tag
-        for (mut text, ui_entity) in text_query.iter_mut() {
-            if let UiElement::AvatarList(j) = ui_entity {
-                if j == &i {
-                    let avatar_key = keys_vec[index];
-                    text.sections[0].value = format!(
-                        "{}...{}",
-                        &avatar_key[..8],
-                        &avatar_key[avatar_key.len() - 8..]
-                    );
-                    // Set text color based on whether the current index matches the selected index
-                    if index == selected_index {
-                        text.sections[0].style.color = Color::GREEN;
-                        avatar_list.coordinate_string = avatar_key.to_string();
-                    } else {
-                        text.sections[0].style.color = Color::WHITE;
-                    }
-                }
+    let entries = unique_keys
+        .iter()
+        .map(|pubkey| RosterEntry {
+            pubkey: pubkey.clone(),
+            name: profiles.display_name(pubkey),
+            distance: drift_positions.position(pubkey).map_or(f32::MAX, |avatar| {
+                origin.position(avatar).distance(position)
+            }),
+            last_seen: last_seen.get(pubkey).copied(),
+            followed: follow_list.contains(pubkey),
+        })
+        .collect();
+    let roster = order_roster(entries, avatar_list.sort, avatar_list.filter, unix_now());
+    // Writing only what differs keeps the change detection of the list meaningful
+    if avatar_list.roster != roster {
+        avatar_list.roster = roster;
+    }
+    let selected_index = avatar_list.selected_index();
+    match selected_index {
+        Some(index) => {
+            let scroll = scroll_to(avatar_list.scroll, index, ROSTER_ROWS);
+            if avatar_list.scroll != scroll {
+                avatar_list.scroll = scroll;
             }
         }
+        // Filtered out or nothing picked yet, the top of the list takes over
+        None if avatar_list.selected.is_some() || !avatar_list.roster.is_empty() => {
+            avatar_list.scroll = 0;
+            avatar_list.select(0);
+        }
+        None => {}
+    }
+}
+
+fn navigate_avatar_roster(
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    key_bindings: Res<KeyBindings>,
+    mut mouse_wheel_events: EventReader<MouseWheel>,
+    roster_query: Query<&Interaction, With<AvatarRoster>>,
+    row_query: Query<(&Interaction, &RosterRow), Changed<Interaction>>,
+    mut avatar_list: ResMut<AvatarListDetails>,
+) {
+    if keyboard_input.just_pressed(key_bindings.key(Action::SortAvatars)) {
+        avatar_list.sort = avatar_list.sort.next();
+    }
+    if keyboard_input.just_pressed(key_bindings.key(Action::FilterAvatars)) {
+        avatar_list.filter = avatar_list.filter.next();
+    }
+
+    let list_len = avatar_list.roster.len();
+    if list_len > 0 {
+        let selected_index = avatar_list.selected_index().unwrap_or(0);
+        if keyboard_input.just_pressed(key_bindings.key(Action::NextAvatar)) {
+            // Wrap around when reaching the end
+            avatar_list.select((selected_index + 1) % list_len);
+        }
+        if keyboard_input.just_pressed(key_bindings.key(Action::PreviousAvatar)) {
+            avatar_list.select((selected_index + list_len - 1) % list_len);
+        }
     }
 
-    if keyboard_input.just_pressed(KeyCode::Delete) {
-        avatar_list.selected = (avatar_list.selected + 1) % list_len; // Wrap around when reaching the end
+    for (interaction, row) in row_query.iter() {
+        let index = avatar_list.scroll + row.0;
+        if *interaction == Interaction::Pressed && index < list_len {
+            avatar_list.select(index);
+        }
     }
 
-    if keyboard_input.just_pressed(KeyCode::Insert) {
-        avatar_list.selected = (avatar_list.selected + list_len - 1) % list_len;
-        // Wrap around when reaching the beginning
+    let hovered = roster_query
+        .iter()
+        .any(|interaction| *interaction != Interaction::None);
+    let notches: f32 = mouse_wheel_events
+        .read()
+        .map(|wheel| match wheel.unit {
+            MouseScrollUnit::Line => wheel.y,
+            MouseScrollUnit::Pixel => wheel.y / ROSTER_PIXELS_PER_ROW,
+        })
+        .sum();
+    if hovered && notches != 0.0 {
+        // Scrolling up shows the keys above
+        let scroll = (avatar_list.scroll as f32 - notches.round()).max(0.0) as usize;
+        let scroll = scroll.min(avatar_list.max_scroll());
+        if avatar_list.scroll != scroll {
+            avatar_list.scroll = scroll;
+        }
+    }
+}
+
+fn update_avatar_roster_ui(
+    avatar_list: Res<AvatarListDetails>,
+    unique_keys: Res<UniqueKeys>,
+    profiles: Res<Profiles>,
+    verified_names: Res<VerifiedNames>,
+    last_seen: Res<LastSeen>,
+    key_bindings: Res<KeyBindings>,
+    mut header_query: Query<&mut Text, (With<RosterHeader>, Without<RosterRow>)>,
+    mut row_query: Query<(&mut Text, &RosterRow), Without<RosterHeader>>,
+) {
+    if !avatar_list.is_changed()
+        && !profiles.is_changed()
+        && !verified_names.is_changed()
+        && !last_seen.is_changed()
+        && !key_bindings.is_changed()
+    {
+        return;
+    }
+    let header = format!(
+        "{} of {} keys by {}, showing {}\n{} sorts, {} filters",
+        avatar_list.roster.len(),
+        unique_keys.len(),
+        avatar_list.sort.label(),
+        avatar_list.filter.label(),
+        key_name(key_bindings.key(Action::SortAvatars)),
+        key_name(key_bindings.key(Action::FilterAvatars))
+    );
+    for mut text in header_query.iter_mut() {
+        text.sections[0].value = header.clone();
+    }
+    for (mut text, row) in row_query.iter_mut() {
+        let pubkey = avatar_list.roster.get(avatar_list.scroll + row.0);
+        text.sections[0].value = match pubkey {
+            Some(pubkey) => format!(
+                "{}, {}",
+                verified_names.display_name(&profiles, pubkey),
+                last_seen.describe(pubkey)
+            ),
+            None => String::new(),
+        };
+        text.sections[0].style.color = match pubkey {
+            Some(pubkey) if avatar_list.selected.as_ref() == Some(pubkey) => Color::GREEN,
+            _ => Color::WHITE,
+        };
     }
 }
 
+// What the coordinates panel was last written for
+#[derive(PartialEq)]
+struct ShownCoordinates {
+    coordinates: CyberspaceCoordinates,
+    plane: Plane,
+    // Owner and POW of the block there, blocks arriving anywhere else leave the panel alone
+    block: Option<(String, usize)>,
+}
+
 fn update_coordinate_ui(
-    query: Query<&Transform, With<BlockIndicator>>,
+    mut moves: EventReader<IndicatorMoved>,
     mut text_query: Query<(&mut Text, &UiElement)>,
     mined_blocks: Res<CoordinatesMap>,
+    world_chunks: Res<WorldChunks>,
+    block_slice: Res<BlockSlice>,
+    profiles: Res<Profiles>,
+    verified_names: Res<VerifiedNames>,
+    last_seen: Res<LastSeen>,
+    mut shown: Local<Option<ShownCoordinates>>,
 ) {
-    if let Ok(transform) = query.get_single() {
-        let x = transform.translation.x;
-        let y = transform.translation.y;
-        let z = transform.translation.z;
-
-        let rounded_x = x.round();
-        let rounded_y = y.round();
-        let rounded_z = z.round();
-
-        let x_i128 = rounded_x as i128;
-        let y_i128 = rounded_y as i128;
-        let z_i128 = rounded_z as i128;
-
-        let coordinate_string = encode_coordinates(x_i128, y_i128, z_i128);
-
-        for (mut text, ui_entity) in text_query.iter_mut() {
-            match ui_entity {
-                UiElement::CurrentCoordinates => {
-                    let current_coordinates =
-                        format!("X: {} Y: {} Z: {}\n", rounded_x, rounded_y, rounded_z);
-                    text.sections[0].value = current_coordinates;
-                    text.sections[1].value = format!(
-                        "i-Space: {}...{}\n",
-                        &coordinate_string[..8],
-                        &coordinate_string[coordinate_string.len() - 8..]
-                    );
-                    if let Some(owner) = mined_blocks.get(&coordinate_string) {
-                        text.sections[2].value = format!(
-                            "Owner: {}...{}",
-                            &owner.1.miner_pubkey[..8],
-                            &owner.1.miner_pubkey[owner.1.miner_pubkey.len() - 8..]
-                        );
-                    } else {
-                        text.sections[2].value = String::new();
-                    }
-                }
+    let moved_to = moves.read().last().map(|moved| moved.0);
+    let Some(coordinates) = moved_to.or(shown.as_ref().map(|shown| shown.coordinates)) else {
+        return;
+    };
+    let plane = world_chunks.plane();
+    let block = world_chunks
+        .block_at(coordinates)
+        .and_then(|hex| mined_blocks.get(hex))
+        .map(|(_, block_details)| block_details);
+    let block_key = block.map(|block_details| (block_details.owner(), block_details.pow_amount));
+    let unchanged = shown.as_ref().is_some_and(|shown| {
+        shown.coordinates == coordinates
+            && shown.plane == plane
+            && shown
+                .block
+                .as_ref()
+                .map(|(owner, pow_amount)| (owner.as_str(), *pow_amount))
+                == block_key
+    });
+    // Names and last seen times only show while on a block
+    let owner_changed = block.is_some()
+        && (profiles.is_changed() || verified_names.is_changed() || last_seen.is_changed());
+    if unchanged && !block_slice.is_changed() && !owner_changed {
+        return;
+    }
 
-                _ => {}
-            }
+    let coordinate_string = coordinates.to_hex(plane);
+    for (mut text, ui_entity) in text_query.iter_mut() {
+        let UiElement::CurrentCoordinates = ui_entity else {
+            continue;
+        };
+        text.sections[0].value = format!(
+            "X: {} Y: {} Z: {}\n",
+            coordinates.x, coordinates.y, coordinates.z
+        );
+        text.sections[1].value = format!(
+            "{}: {}...{}\n{}",
+            plane.name(),
+            &coordinate_string[..8],
+            &coordinate_string[coordinate_string.len() - 8..],
+            block_slice
+                .describe()
+                .map(|slice| format!("{}\n", slice))
+                .unwrap_or_default()
+        );
+        if let Some(block_details) = block {
+            text.sections[2].value = format!(
+                "Owner: {}, {}\n{} block, {} bits\n",
+                verified_names.display_name(&profiles, block_details.owner()),
+                last_seen.describe(block_details.owner()),
+                tier_name(block_tier(block_details.pow_amount, block_details.material)),
+                block_details.pow_amount
+            );
+            text.sections[3].value = block_details
+                .display_label()
+                .map(|label| format!("Label: {}", label))
+                .unwrap_or_default();
+        } else {
+            text.sections[2].value = String::new();
+            text.sections[3].value = String::new();
         }
     }
+    *shown = Some(ShownCoordinates {
+        coordinates,
+        plane,
+        block: block_key.map(|(owner, pow_amount)| (owner.to_string(), pow_amount)),
+    });
 }
 
 fn setup_mining_ui(mut commands: Commands, nostr_signer: Res<UserNostrKeys>) {
@@ -253,12 +604,40 @@ fn setup_mining_ui(mut commands: Commands, nostr_signer: Res<UserNostrKeys>) {
 
         let mining_notices = multi_section_text_builder(3);
         mining_ui.spawn((mining_notices, UiElement::MiningNotice));
+
+        let mining_queue = text_bundle_builder(String::new(), NORMAL_FONT);
+        mining_ui.spawn((mining_queue, UiElement::MiningQueue));
+
+        let mining_estimate = text_bundle_builder(String::new(), NORMAL_FONT);
+        mining_ui.spawn((mining_estimate, UiElement::MiningEstimate));
+
+        let mining_workers = NodeBundle {
+            style: Style {
+                flex_direction: FlexDirection::Column,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        mining_ui.spawn((mining_workers, MiningWorkers));
     });
 }
 
 #[derive(Event)]
 pub struct PowEvent(pub POWBlockDetails);
 
+// The key may have changed in the menu since the mining panel was spawned
+fn show_mining_key(
+    nostr_signer: Res<UserNostrKeys>,
+    mut text_query: Query<(&mut Text, &UiElement)>,
+) {
+    for (mut text, ui_entity) in text_query.iter_mut() {
+        if let UiElement::MiningKey = ui_entity {
+            text.sections[0].value = nostr_signer.get_display_key();
+        }
+    }
+}
+
+// Idle shows block counts, written when they change. Mining shows the latest mined block
 fn update_mining_ui(
     mut text_query: Query<(&mut Text, &UiElement)>,
     mining_state: Res<State<MiningState>>,
@@ -266,37 +645,445 @@ fn update_mining_ui(
     unmined_blocks: Res<UnminedBlockMap>,
     mut pow_events: EventReader<PowEvent>,
 ) {
-    let blocks_in_world = mined_blocks.len();
-    let blocks_in_memory = unmined_blocks.len();
+    let switched = mining_state.is_changed();
+    match mining_state.get() {
+        MiningState::Idle => {
+            if !switched && !mined_blocks.is_changed() && !unmined_blocks.is_changed() {
+                return;
+            }
+            let blocks_in_memory = unmined_blocks.len();
+            let notices = [
+                format!("Blocks in world: {}\n", mined_blocks.len()),
+                format!("Unmined Blocks: {}\n", blocks_in_memory),
+                if blocks_in_memory > 0 {
+                    "Press M to mine".to_string()
+                } else {
+                    "No blocks to mine".to_string()
+                },
+            ];
+            write_mining_notices(&mut text_query, notices);
+        }
+        MiningState::Mining => {
+            let mined = pow_events.read().last().map(|event| {
+                [
+                    "Mining... Press N to stop\n".to_string(),
+                    format!("Mined block at: {}\n", event.0.display_coordinates()),
+                    format!("With POW: {} bits\n", event.0.pow_amount),
+                ]
+            });
+            match mined {
+                Some(notices) => write_mining_notices(&mut text_query, notices),
+                // The counts from idle stay until the first block is mined
+                None if switched => {
+                    for (mut text, ui_entity) in text_query.iter_mut() {
+                        if let UiElement::MiningNotice = ui_entity {
+                            text.sections[0].value = "Mining... Press N to stop\n".to_string();
+                        }
+                    }
+                }
+                None => {}
+            }
+        }
+    }
+}
+
+fn write_mining_notices(text_query: &mut Query<(&mut Text, &UiElement)>, notices: [String; 3]) {
     for (mut text, ui_entity) in text_query.iter_mut() {
-        match ui_entity {
-            UiElement::MiningNotice => match mining_state.get() {
-                MiningState::Idle => {
-                    text.sections[0].value = format!("Blocks in world: {}\n", blocks_in_world);
-                    text.sections[1].value = format!("Unmined Blocks: {}\n", blocks_in_memory);
-                    text.sections[2].value = if blocks_in_memory > 0 {
-                        "Press M to mine".to_string()
-                    } else {
-                        "No blocks to mine".to_string()
+        if let UiElement::MiningNotice = ui_entity {
+            for (section, notice) in text.sections.iter_mut().zip(notices.iter()) {
+                section.value.clone_from(notice);
+            }
+        }
+    }
+}
+
+fn update_mining_queue_ui(
+    mining_queue: Res<MiningQueue>,
+    defense_policy: Res<DefensePolicy>,
+    chosen_material: Res<ChosenMaterial>,
+    mut text_query: Query<(&mut Text, &UiElement)>,
+) {
+    if !mining_queue.is_changed() && !defense_policy.is_changed() && !chosen_material.is_changed() {
+        return;
+    }
+
+    let mut queue_details = if defense_policy.enabled {
+        format!(
+            "Defense on: {}/{} blocks, up to {} bits\n",
+            mining_queue.active_defenses(),
+            defense_policy.max_concurrent_defenses,
+            defense_policy.max_difficulty
+        )
+    } else {
+        "Defense off, G to defend outbid blocks\n".to_string()
+    };
+    queue_details.push_str(&match **chosen_material {
+        Some(tier) => format!(
+            "Material: {} (capped by POW), C to change\n",
+            tier_name(tier)
+        ),
+        None => "Material: by POW, C to choose\n".to_string(),
+    });
+    if !mining_queue.blocks.is_empty() {
+        queue_details.push_str(&format!(
+            "Queue ({} mining)\n",
+            mining_queue.active_miners()
+        ));
+    }
+    for (index, block) in mining_queue.blocks.iter().enumerate() {
+        let status = match block.status {
+            QueuedBlockStatus::Queued => "queued",
+            QueuedBlockStatus::Mining => "mining",
+            QueuedBlockStatus::Paused => "paused",
+        };
+        let marker = if index == mining_queue.selected {
+            ">"
+        } else {
+            " "
+        };
+        queue_details.push_str(&format!(
+            "{} {} [{}] best {} bits{}\n",
+            marker,
+            block.display_coordinates(),
+            status,
+            block.best_pow,
+            if block.action == MiningAction::Derezz {
+                format!(", derezz over {}", block.min_pow)
+            } else if block.defending {
+                format!(", defending over {}", block.min_pow)
+            } else {
+                String::new()
+            }
+        ));
+    }
+
+    for (mut text, ui_entity) in text_query.iter_mut() {
+        if let UiElement::MiningQueue = ui_entity {
+            text.sections[0].value = queue_details.clone();
+        }
+    }
+}
+
+fn cancel_mining_workers(
+    cancel_query: Query<(&Interaction, &CancelWorker), Changed<Interaction>>,
+    mut mining_queue: ResMut<MiningQueue>,
+) {
+    for (interaction, cancel) in cancel_query.iter() {
+        if *interaction == Interaction::Pressed {
+            mining_queue.cancel_worker(&cancel.0, cancel.1);
+        }
+    }
+}
+
+// Rows are only respawned when workers start or stop, their text follows the hashrate samples
+fn update_mining_workers_ui(
+    mut commands: Commands,
+    mining_queue: Res<MiningQueue>,
+    workers_query: Query<Entity, With<MiningWorkers>>,
+    mut text_query: Query<(&mut Text, &WorkerText)>,
+    mut shown_workers: Local<Vec<(String, u64)>>,
+) {
+    if !mining_queue.is_changed() {
+        return;
+    }
+    let workers: Vec<(&QueuedBlock, &MiningWorker)> = mining_queue
+        .blocks
+        .iter()
+        .flat_map(|block| block.workers.iter().map(move |worker| (block, worker)))
+        .collect();
+    let worker_keys: Vec<(String, u64)> = workers
+        .iter()
+        .map(|(block, worker)| (block.coordinates.clone(), worker.index))
+        .collect();
+
+    if *shown_workers != worker_keys {
+        for container in workers_query.iter() {
+            commands
+                .entity(container)
+                .despawn_descendants()
+                .with_children(|container| {
+                    if !workers.is_empty() {
+                        container.spawn(text_bundle_builder(
+                            format!("Workers ({})", workers.len()),
+                            NORMAL_FONT,
+                        ));
+                    }
+                    for (block, worker) in &workers {
+                        container.spawn(worker_row()).with_children(|row| {
+                            let cancel = text_bundle_builder("[x]".to_string(), NORMAL_FONT);
+                            row.spawn((
+                                cancel,
+                                Interaction::default(),
+                                CancelWorker(block.coordinates.clone(), worker.index),
+                            ));
+                            let text = text_bundle_builder(worker_line(block, worker), NORMAL_FONT);
+                            row.spawn((text, WorkerText(block.coordinates.clone(), worker.index)));
+                        });
+                    }
+                });
+        }
+        *shown_workers = worker_keys;
+        return;
+    }
+
+    for (mut text, worker_text) in text_query.iter_mut() {
+        if let Some((block, worker)) = workers.iter().find(|(block, worker)| {
+            block.coordinates == worker_text.0 && worker.index == worker_text.1
+        }) {
+            text.sections[0].value = worker_line(block, worker);
+        }
+    }
+}
+
+fn worker_row() -> NodeBundle {
+    NodeBundle {
+        style: Style {
+            column_gap: FLEX_GAP,
+            align_items: AlignItems::Center,
+            ..Default::default()
+        },
+        ..Default::default()
+    }
+}
+
+fn worker_line(block: &QueuedBlock, worker: &MiningWorker) -> String {
+    format!(
+        "{} #{}: best {} bits, {}, {}",
+        block.display_coordinates(),
+        worker.index,
+        block.best_pow,
+        display_hashrate(worker.hashes_per_second),
+        display_duration(worker.started_at.elapsed())
+    )
+}
+
+// Points on the probability curve, as multiples of the ETA
+const ESTIMATE_CURVE: [f64; 5] = [0.25, 0.5, 1.0, 2.0, 4.0];
+const ESTIMATE_BAR_WIDTH: usize = 20;
+
+fn update_mining_estimate_ui(
+    hashrate: Res<Hashrate>,
+    mining_queue: Res<MiningQueue>,
+    mut text_query: Query<(&mut Text, &UiElement)>,
+) {
+    if !hashrate.is_changed() && !mining_queue.is_changed() {
+        return;
+    }
+
+    // Estimate the selected block, or the first one mining if the selection is idle
+    let active_miners = mining_queue.active_miners();
+    let block = mining_queue
+        .blocks
+        .get(mining_queue.selected)
+        .filter(|block| block.status == QueuedBlockStatus::Mining)
+        .or_else(|| {
+            mining_queue
+                .blocks
+                .iter()
+                .find(|block| block.status == QueuedBlockStatus::Mining)
+        });
+
+    let mut estimate_details = String::new();
+    if let Some(block) = block {
+        // Workers are shared evenly between the blocks being mined
+        let block_hashrate = hashrate.hashes_per_second / active_miners as f64;
+        let target = block.target();
+        estimate_details.push_str(&format!(
+            "Hashrate: {}\n",
+            display_hashrate(hashrate.hashes_per_second)
+        ));
+        match mining_eta(target, block_hashrate) {
+            Some(eta) => {
+                estimate_details.push_str(&format!(
+                    "{} bits expected in {}\n",
+                    target,
+                    display_duration(eta)
+                ));
+                for multiple in ESTIMATE_CURVE {
+                    // Past the longest duration for very high targets
+                    let Ok(time) = Duration::try_from_secs_f64(eta.as_secs_f64() * multiple) else {
+                        continue;
                     };
+                    let probability = mining_probability(target, block_hashrate, time);
+                    let filled = (probability * ESTIMATE_BAR_WIDTH as f64).round() as usize;
+                    estimate_details.push_str(&format!(
+                        "{:>8} {}{} {:>3.0}%\n",
+                        display_duration(time),
+                        "#".repeat(filled),
+                        "-".repeat(ESTIMATE_BAR_WIDTH - filled),
+                        probability * 100.0
+                    ));
                 }
-                MiningState::Mining => {
-                    text.sections[0].value = "Mining... Press N to stop\n".to_string();
-                    for event in pow_events.read() {
-                        let block = &event.0;
-                        text.sections[1].value =
-                            format!("Mined block at: {}\n", block.display_coordinates());
-                        text.sections[2].value = format!("With POW: {}\n", block.pow_amount);
+            }
+            None => estimate_details.push_str("Measuring hashrate...\n"),
+        }
+    }
+
+    for (mut text, ui_entity) in text_query.iter_mut() {
+        if let UiElement::MiningEstimate = ui_entity {
+            text.sections[0].value = estimate_details.clone();
+        }
+    }
+}
+
+pub fn display_hashrate(hashes_per_second: f64) -> String {
+    match hashes_per_second {
+        rate if rate >= 1e9 => format!("{:.2} GH/s", rate / 1e9),
+        rate if rate >= 1e6 => format!("{:.2} MH/s", rate / 1e6),
+        rate if rate >= 1e3 => format!("{:.2} kH/s", rate / 1e3),
+        rate => format!("{:.0} H/s", rate),
+    }
+}
+
+fn display_duration(duration: Duration) -> String {
+    let seconds = duration.as_secs();
+    match seconds {
+        0..=59 => format!("{}s", seconds),
+        60..=3599 => format!("{}m {}s", seconds / 60, seconds % 60),
+        3600..=86399 => format!("{}h {}m", seconds / 3600, seconds % 3600 / 60),
+        _ => format!("{}d {}h", seconds / 86400, seconds % 86400 / 3600),
+    }
+}
+
+fn setup_relay_ui(mut commands: Commands) {
+    let relay_ui = NodeBundle {
+        style: Style {
+            position_type: PositionType::Absolute,
+            top: Val::Px(0.0),
+            right: Val::Px(0.0),
+            margin: MARGIN_UI,
+            padding: PADDING_UI,
+            row_gap: FLEX_GAP,
+            column_gap: FLEX_GAP,
+            flex_direction: FlexDirection::Column,
+            border: BORDER_WIDTH,
+            ..Default::default()
+        },
+        border_color: BorderColor(LIGHT_GRAY),
+        ..Default::default()
+    };
+
+    commands
+        .spawn((relay_ui, RelayPanel))
+        .with_children(|relay_ui| {
+            let relay_title = text_bundle_builder("Relays".to_string(), TITLE_FONT);
+            relay_ui.spawn(relay_title);
+
+            let relay_list = text_bundle_builder(String::new(), NORMAL_FONT);
+            relay_ui.spawn((relay_list, UiElement::RelayList));
+
+            let relay_probe = text_bundle_builder("R to test relays".to_string(), NORMAL_FONT);
+            relay_ui.spawn((relay_probe, UiElement::RelayProbe));
+        });
+}
+
+// Hidden or shown from the settings panel
+fn show_relay_ui(
+    settings: Res<Settings>,
+    mut panel_query: Query<&mut Visibility, With<RelayPanel>>,
+) {
+    if !settings.is_changed() {
+        return;
+    }
+    for mut visibility in panel_query.iter_mut() {
+        *visibility = if settings.show_relays {
+            Visibility::Visible
+        } else {
+            Visibility::Hidden
+        };
+    }
+}
+
+fn update_relay_probe_ui(
+    relay_probe: Res<RelayProbe>,
+    mut text_query: Query<(&mut Text, &UiElement)>,
+) {
+    if !relay_probe.is_changed() {
+        return;
+    }
+
+    let mut probe_details = String::new();
+    if relay_probe.running {
+        probe_details.push_str("Testing relays...\n");
+    }
+    for result in relay_probe.results.iter() {
+        match result.latency {
+            Some(latency) => probe_details.push_str(&format!(
+                "{} {}ms{}\n",
+                result.url,
+                latency.as_millis(),
+                if result.has_blocks { " blocks" } else { "" }
+            )),
+            None => probe_details.push_str(&format!("{} unreachable\n", result.url)),
+        }
+    }
+    let suggestions = relay_probe.suggestions();
+    if !relay_probe.running && !suggestions.is_empty() {
+        probe_details.push_str("Suggested:\n");
+        for suggestion in suggestions {
+            probe_details.push_str(&format!("  {}\n", suggestion.url));
+        }
+        probe_details.push_str("Y to add suggested relays\n");
+    }
+    if probe_details.is_empty() {
+        probe_details.push_str("R to test relays");
+    }
+
+    for (mut text, ui_entity) in text_query.iter_mut() {
+        if let UiElement::RelayProbe = ui_entity {
+            text.sections[0].value = probe_details.clone();
+        }
+    }
+}
+
+fn update_relay_ui(relay_pool: Res<RelayPool>, mut text_query: Query<(&mut Text, &UiElement)>) {
+    if !relay_pool.is_changed() {
+        return;
+    }
+
+    let mut relay_details = String::new();
+    for relay in relay_pool.iter() {
+        relay_details.push_str(&format!("{}\n", relay.url));
+        match &relay.information {
+            Some(information) => {
+                relay_details.push_str(&format!(
+                    "  {} {} {}\n",
+                    information.name, information.software, information.version
+                ));
+                relay_details.push_str(&format!("  NIPs: {}\n", information.display_nips()));
+                let limitation = &information.limitation;
+                if let Some(max_subscriptions) = limitation.max_subscriptions {
+                    relay_details
+                        .push_str(&format!("  Max subscriptions: {}\n", max_subscriptions));
+                }
+                if let Some(min_pow) = limitation.min_pow_difficulty {
+                    relay_details.push_str(&format!("  Min POW: {}\n", min_pow));
+                }
+                if !information.accepts_writes() {
+                    relay_details.push_str("  Read only (auth or payment required)\n");
+                }
+                match information.stored_blocks {
+                    Some(stored_blocks) => {
+                        relay_details.push_str(&format!("  Blocks: {}\n", stored_blocks))
+                    }
+                    None if !information.supports_nip(COUNT_NIP) => {
+                        relay_details.push_str("  Blocks: not counted without NIP-45\n")
                     }
+                    None => {}
                 }
-            },
+            }
+            None => relay_details.push_str("  No relay information\n"),
+        }
+    }
 
-            _ => {}
+    for (mut text, ui_entity) in text_query.iter_mut() {
+        if let UiElement::RelayList = ui_entity {
+            text.sections[0].value = relay_details.clone();
         }
     }
 }
 
-fn text_bundle_builder(content: String, font_size: f32) -> TextBundle {
+pub fn text_bundle_builder(content: String, font_size: f32) -> TextBundle {
     TextBundle::from_section(
         content,
         TextStyle {
@@ -313,7 +1100,62 @@ fn text_bundle_builder(content: String, font_size: f32) -> TextBundle {
     })
 }
 
-fn multi_section_text_builder(sections: usize) -> TextBundle {
+// Prompts read the keyboard in here, before the Update systems, so typing doesn't also trigger
+// key bindings
+#[derive(SystemSet, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct TextInput;
+
+// What a key pressed in a text field did
+#[derive(Debug, PartialEq)]
+pub enum TextFieldEdit {
+    Edited,
+    // Ctrl + V with nothing on the clipboard
+    NothingToPaste,
+    // Enter, Escape and the like are up to the prompt
+    Unhandled,
+}
+
+// Typing, deleting and pasting work the same in every prompt. `max_chars` counts characters,
+// not bytes, so a name in any script gets as much room
+pub fn edit_text_field(
+    typed: &mut String,
+    key: &Key,
+    max_chars: usize,
+    keyboard_input: &ButtonInput<KeyCode>,
+) -> TextFieldEdit {
+    let room = max_chars.saturating_sub(typed.chars().count());
+    let control_held = keyboard_input.any_pressed([
+        KeyCode::ControlLeft,
+        KeyCode::ControlRight,
+        KeyCode::SuperLeft,
+        KeyCode::SuperRight,
+    ]);
+    match key {
+        Key::Backspace => {
+            typed.pop();
+        }
+        Key::Character(characters) if control_held && characters.eq_ignore_ascii_case("v") => {
+            let pasted = arboard::Clipboard::new().and_then(|mut clipboard| clipboard.get_text());
+            let Ok(pasted) = pasted else {
+                return TextFieldEdit::NothingToPaste;
+            };
+            // Pasted line breaks would end up in a one line field
+            typed.extend(
+                pasted
+                    .trim()
+                    .chars()
+                    .map(|c| if c.is_control() { ' ' } else { c })
+                    .take(room),
+            );
+        }
+        Key::Character(characters) => typed.extend(characters.chars().take(room)),
+        Key::Space => typed.extend([' '].into_iter().take(room)),
+        _ => return TextFieldEdit::Unhandled,
+    }
+    TextFieldEdit::Edited
+}
+
+pub fn multi_section_text_builder(sections: usize) -> TextBundle {
     let mut text_sections = Vec::new();
     for _ in 0..sections {
         text_sections.push(TextSection {
@@ -328,3 +1170,81 @@ fn multi_section_text_builder(sections: usize) -> TextBundle {
 
     TextBundle::from_sections(text_sections)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn text_fields_cap_characters_not_bytes() {
+        let keyboard_input = ButtonInput::<KeyCode>::default();
+        let mut typed = "ab".to_string();
+        let edit = edit_text_field(&mut typed, &Key::Character("éé".into()), 3, &keyboard_input);
+        assert_eq!(edit, TextFieldEdit::Edited);
+        assert_eq!(typed, "abé");
+        edit_text_field(&mut typed, &Key::Space, 3, &keyboard_input);
+        assert_eq!(typed, "abé");
+        edit_text_field(&mut typed, &Key::Backspace, 3, &keyboard_input);
+        assert_eq!(typed, "ab");
+        assert_eq!(
+            edit_text_field(&mut typed, &Key::Enter, 3, &keyboard_input),
+            TextFieldEdit::Unhandled
+        );
+    }
+
+    fn entry(pubkey: &str, name: &str, distance: f32, last_seen: Option<u64>) -> RosterEntry {
+        RosterEntry {
+            pubkey: pubkey.to_string(),
+            name: name.to_string(),
+            distance,
+            last_seen,
+            followed: pubkey == "b",
+        }
+    }
+
+    fn entries() -> Vec<RosterEntry> {
+        vec![
+            entry("a", "zed", 30.0, Some(1_000)),
+            entry("b", "Alice", 10.0, None),
+            entry("c", "bob", 20.0, Some(5_000)),
+        ]
+    }
+
+    #[test]
+    fn roster_sorts_by_distance_recency_and_name() {
+        let now = 5_000;
+        assert_eq!(
+            order_roster(entries(), RosterSort::Distance, RosterFilter::All, now),
+            ["b", "c", "a"]
+        );
+        // Keys never seen go last
+        assert_eq!(
+            order_roster(entries(), RosterSort::Recency, RosterFilter::All, now),
+            ["c", "a", "b"]
+        );
+        assert_eq!(
+            order_roster(entries(), RosterSort::Name, RosterFilter::All, now),
+            ["b", "c", "a"]
+        );
+    }
+
+    #[test]
+    fn roster_filters_followed_and_active_keys() {
+        let now = 5_000;
+        assert_eq!(
+            order_roster(entries(), RosterSort::Name, RosterFilter::Following, now),
+            ["b"]
+        );
+        assert_eq!(
+            order_roster(entries(), RosterSort::Name, RosterFilter::Active, now),
+            ["c"]
+        );
+    }
+
+    #[test]
+    fn scrolling_keeps_the_selection_visible() {
+        assert_eq!(scroll_to(0, 3, 8), 0);
+        assert_eq!(scroll_to(0, 10, 8), 3);
+        assert_eq!(scroll_to(5, 2, 8), 2);
+    }
+}
@@ -0,0 +1,97 @@
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Instant,
+};
+
+use bevy::prelude::*;
+use crossbeam_channel::{Receiver, Sender};
+use nostro2::notes::SignedNote;
+
+use crate::{logging::RELAY_LOG, nostr::ReceivedNote};
+
+// More threads than this only wait on the same queue
+const MAX_VERIFIERS: usize = 8;
+
+// A note straight off a relay, its id and signature not checked yet
+pub struct UnverifiedNote {
+    pub note: SignedNote,
+    pub received_at: Instant,
+}
+
+// Waiting to be checked, kept for the diagnostics overlay
+#[derive(Resource, Deref)]
+pub struct VerificationQueue(pub Receiver<UnverifiedNote>);
+
+// One core is left to the frame loop
+pub fn verifier_count(cores: usize) -> usize {
+    cores.saturating_sub(1).clamp(1, MAX_VERIFIERS)
+}
+
+// Checking the id and signature of thousands of backfilled notes would hold up a relay task or a
+// frame, so relay tasks hand notes to these threads, which check and parse them and pass the good
+// ones on to the ECS as they finish
+pub fn spawn_verifiers(
+    unverified_notes: Receiver<UnverifiedNote>,
+    notes_writer: Sender<ReceivedNote>,
+    rejected: Arc<AtomicU64>,
+) {
+    let cores = std::thread::available_parallelism()
+        .map(|cores| cores.get())
+        .unwrap_or(1);
+    for verifier in 0..verifier_count(cores) {
+        let unverified_notes = unverified_notes.clone();
+        let notes_writer = notes_writer.clone();
+        let rejected = rejected.clone();
+        let spawned = std::thread::Builder::new()
+            .name(format!("verifier {}", verifier))
+            .spawn(move || verify_notes(unverified_notes, notes_writer, rejected));
+        if let Err(error) = spawned {
+            error!(target: RELAY_LOG, "Could not start a verifier thread: {}", error);
+        }
+    }
+}
+
+// Runs until the relays or the ECS end of the queue is gone
+fn verify_notes(
+    unverified_notes: Receiver<UnverifiedNote>,
+    notes_writer: Sender<ReceivedNote>,
+    rejected: Arc<AtomicU64>,
+) {
+    while let Ok(UnverifiedNote { note, received_at }) = unverified_notes.recv() {
+        let verify_started = Instant::now();
+        if !note_is_authentic(&note) {
+            rejected.fetch_add(1, Ordering::Relaxed);
+            debug!(
+                target: RELAY_LOG,
+                "Dropped kind {} note {} with a bad id or signature",
+                note.get_kind(),
+                note.get_id()
+            );
+            continue;
+        }
+        let received_note = ReceivedNote::verified(note, received_at, verify_started.elapsed());
+        if notes_writer.send(received_note).is_err() {
+            return;
+        }
+    }
+}
+
+// The id has to be the hash of the note and the signature the author's
+fn note_is_authentic(note: &SignedNote) -> bool {
+    note.verify_content() && note.verify_signature()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn verifiers_leave_a_core_to_the_frame_loop() {
+        assert_eq!(verifier_count(1), 1);
+        assert_eq!(verifier_count(4), 3);
+        assert_eq!(verifier_count(64), MAX_VERIFIERS);
+    }
+}
@@ -0,0 +1,278 @@
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    cameras::BlockIndicator,
+    cyberspace::{CyberspaceCoordinates, Plane},
+    keybindings::{Action, KeyBindings},
+    logging::WORLD_LOG,
+    menu::AppState,
+    minimap::TeleportDestination,
+    notifications::{Notification, Severity},
+    origin::FloatingOrigin,
+    session::SessionPanel,
+    ui_camera::{
+        text_bundle_builder, BORDER_WIDTH, FLEX_GAP, LIGHT_GRAY, MARGIN_UI, NORMAL_FONT,
+        PADDING_UI, TITLE_FONT,
+    },
+    UserNostrKeys,
+};
+
+const WAYPOINTS_FILE_PATH: &str = "./waypoints.json";
+const HOME_WAYPOINT_NAME: &str = "Home";
+const WAYPOINT_HOVER_COLOR: Color = Color::rgba(1.0, 1.0, 1.0, 0.15);
+
+pub fn waypoints_plugin(app: &mut App) {
+    app.insert_resource(Waypoints::load())
+        // Home depends on the key picked in the menu
+        .add_systems(OnEnter(AppState::InGame), add_home_waypoint)
+        .add_systems(PostStartup, setup_waypoints_panel)
+        .add_systems(
+            Update,
+            (
+                toggle_waypoints_panel,
+                add_waypoint,
+                pick_waypoint,
+                update_waypoints_panel,
+            )
+                .chain(),
+        );
+}
+
+// A bookmarked location, the coordinates are a cyberspace coordinate string like block ones
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Waypoint {
+    pub name: String,
+    pub coordinates: String,
+}
+
+impl Waypoint {
+    fn new(name: String, coordinates: CyberspaceCoordinates) -> Self {
+        Waypoint {
+            name,
+            coordinates: coordinates.to_hex(Plane::ISpace),
+        }
+    }
+
+    pub fn position(&self) -> Option<CyberspaceCoordinates> {
+        CyberspaceCoordinates::from_hex(&self.coordinates).ok()
+    }
+}
+
+// Bookmarks in the order they were added, the first one is where Home goes
+#[derive(Resource, Default, Deref, DerefMut)]
+pub struct Waypoints(pub Vec<Waypoint>);
+
+impl Waypoints {
+    fn load() -> Self {
+        let Ok(waypoints) = std::fs::read_to_string(WAYPOINTS_FILE_PATH) else {
+            return Waypoints::default();
+        };
+        match serde_json::from_str(&waypoints) {
+            Ok(waypoints) => Waypoints(waypoints),
+            Err(error) => {
+                warn!(target: WORLD_LOG, "Could not read {}: {}", WAYPOINTS_FILE_PATH, error);
+                Waypoints::default()
+            }
+        }
+    }
+
+    fn save(&self) {
+        let waypoints = match serde_json::to_string_pretty(&self.0) {
+            Ok(waypoints) => waypoints,
+            Err(error) => {
+                warn!(target: WORLD_LOG, "Could not serialize waypoints: {}", error);
+                return;
+            }
+        };
+        if let Err(error) = std::fs::write(WAYPOINTS_FILE_PATH, waypoints) {
+            warn!(target: WORLD_LOG, "Could not write {}: {}", WAYPOINTS_FILE_PATH, error);
+        }
+    }
+
+    // Names can be changed by editing the file, new ones are just numbered
+    fn next_name(&self) -> String {
+        (1..)
+            .map(|number| format!("Waypoint {}", number))
+            .find(|name| self.iter().all(|waypoint| waypoint.name != *name))
+            .unwrap_or_default()
+    }
+
+    pub fn home(&self) -> Option<CyberspaceCoordinates> {
+        self.first().and_then(Waypoint::position)
+    }
+}
+
+// Without a saved list the key's home is the first bookmark
+fn add_home_waypoint(user_keys: Res<UserNostrKeys>, mut waypoints: ResMut<Waypoints>) {
+    if waypoints.is_empty() {
+        waypoints.push(Waypoint::new(
+            HOME_WAYPOINT_NAME.to_string(),
+            user_keys.get_home_coordinates(),
+        ));
+    }
+}
+
+#[derive(Component)]
+struct WaypointsPanel;
+
+#[derive(Component)]
+struct WaypointRows;
+
+// Index into `Waypoints` of the row
+#[derive(Component)]
+struct WaypointRow(usize);
+
+fn setup_waypoints_panel(mut commands: Commands) {
+    let waypoints_ui = NodeBundle {
+        style: Style {
+            position_type: PositionType::Absolute,
+            top: Val::Percent(30.0),
+            right: Val::Percent(20.0),
+            margin: MARGIN_UI,
+            padding: PADDING_UI,
+            row_gap: FLEX_GAP,
+            flex_direction: FlexDirection::Column,
+            border: BORDER_WIDTH,
+            ..Default::default()
+        },
+        border_color: BorderColor(LIGHT_GRAY),
+        background_color: BackgroundColor(Color::rgba(0.0, 0.0, 0.0, 0.7)),
+        visibility: Visibility::Hidden,
+        ..Default::default()
+    };
+    let rows = NodeBundle {
+        style: Style {
+            row_gap: FLEX_GAP,
+            flex_direction: FlexDirection::Column,
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    commands
+        .spawn((waypoints_ui, WaypointsPanel, SessionPanel("waypoints")))
+        .with_children(|waypoints_ui| {
+            waypoints_ui.spawn(text_bundle_builder("Waypoints".to_string(), TITLE_FONT));
+            waypoints_ui.spawn(text_bundle_builder(
+                "Click to set the destination, right click to remove".to_string(),
+                NORMAL_FONT,
+            ));
+            waypoints_ui.spawn((rows, WaypointRows));
+        });
+}
+
+fn toggle_waypoints_panel(
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    key_bindings: Res<KeyBindings>,
+    mut panel_query: Query<&mut Visibility, With<WaypointsPanel>>,
+) {
+    if keyboard_input.just_pressed(key_bindings.key(Action::ToggleWaypoints)) {
+        for mut visibility in panel_query.iter_mut() {
+            *visibility = match *visibility {
+                Visibility::Hidden => Visibility::Visible,
+                _ => Visibility::Hidden,
+            };
+        }
+    }
+}
+
+// Bookmarks the block the indicator is in
+fn add_waypoint(
+    keyboard_input: Res<ButtonInput<KeyCode>>,
+    key_bindings: Res<KeyBindings>,
+    block_indicator: Query<&Transform, With<BlockIndicator>>,
+    origin: Res<FloatingOrigin>,
+    mut waypoints: ResMut<Waypoints>,
+    mut notifications: EventWriter<Notification>,
+) {
+    if !keyboard_input.just_pressed(key_bindings.key(Action::AddWaypoint)) {
+        return;
+    }
+    let Ok(indicator_transform) = block_indicator.get_single() else {
+        return;
+    };
+    let waypoint = Waypoint::new(
+        waypoints.next_name(),
+        origin.coordinates(indicator_transform.translation),
+    );
+    notifications.send(Notification {
+        message: format!("Saved {}", waypoint.name),
+        severity: Severity::Success,
+        play_sound: false,
+    });
+    waypoints.push(waypoint);
+    waypoints.save();
+}
+
+fn pick_waypoint(
+    mouse_input: Res<ButtonInput<MouseButton>>,
+    mut row_query: Query<(Ref<Interaction>, &WaypointRow, &mut BackgroundColor)>,
+    mut waypoints: ResMut<Waypoints>,
+    mut teleport_destination: ResMut<TeleportDestination>,
+) {
+    for (interaction, row, mut background) in row_query.iter_mut() {
+        if *interaction == Interaction::Hovered && mouse_input.just_pressed(MouseButton::Right) {
+            if row.0 < waypoints.len() {
+                waypoints.remove(row.0);
+                waypoints.save();
+            }
+            return;
+        }
+        if !interaction.is_changed() {
+            continue;
+        }
+        *background = match *interaction {
+            Interaction::Pressed => {
+                **teleport_destination = waypoints.get(row.0).and_then(Waypoint::position);
+                WAYPOINT_HOVER_COLOR.into()
+            }
+            Interaction::Hovered => WAYPOINT_HOVER_COLOR.into(),
+            Interaction::None => Color::NONE.into(),
+        };
+    }
+}
+
+fn update_waypoints_panel(
+    mut commands: Commands,
+    waypoints: Res<Waypoints>,
+    rows_query: Query<Entity, With<WaypointRows>>,
+) {
+    if !waypoints.is_changed() {
+        return;
+    }
+    for rows_entity in rows_query.iter() {
+        commands
+            .entity(rows_entity)
+            .despawn_descendants()
+            .with_children(|rows| {
+                for (index, waypoint) in waypoints.iter().enumerate() {
+                    let position = waypoint.position().unwrap_or_default();
+                    let row = text_bundle_builder(
+                        format!(
+                            "{} - X: {} Y: {} Z: {}",
+                            waypoint.name, position.x, position.y, position.z
+                        ),
+                        NORMAL_FONT,
+                    );
+                    rows.spawn((row, Interaction::default(), WaypointRow(index)));
+                }
+            });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn waypoints_round_trip_their_position() {
+        let base = CyberspaceCoordinates::new(12, 4, (1 << 84) + 302);
+        let waypoint = Waypoint::new("Base".to_string(), base);
+        assert_eq!(waypoint.position(), Some(base));
+        let mut waypoints = Waypoints::default();
+        assert_eq!(waypoints.home(), None);
+        waypoints.push(waypoint);
+        assert_eq!(waypoints.home(), Some(base));
+        assert_eq!(waypoints.next_name(), "Waypoint 1");
+    }
+}
@@ -0,0 +1,492 @@
+use std::{
+    cell::{Cell, RefCell},
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use bevy::prelude::*;
+use crossbeam_channel::{unbounded, Receiver, Sender};
+use cryptoxide::digest::Digest;
+use cryptoxide::sha2::Sha256;
+use nostro2::{
+    notes::{Note, SignedNote},
+    userkeys::UserKeys,
+};
+use serde::{Deserialize, Serialize};
+use tokio_util::sync::CancellationToken;
+use wasm_bindgen::{prelude::*, JsCast};
+use wasm_bindgen_futures::JsFuture;
+use web_sys::{DedicatedWorkerGlobalScope, MessageEvent, Worker};
+
+use crate::{
+    logging::MINING_LOG,
+    mining::{
+        count_leading_zero_bits, nonce_tag, note_difficulty, throttle_delay, HashCounter,
+        MiningAction, NonceSplit, POWNotesWriter, QueuedBlock,
+    },
+    settings::{Settings, MAX_MINING_INTENSITY},
+    UserNostrKeys,
+};
+
+const WORKER_SCRIPT: &str = "assets/mining_worker.js";
+// The nonce is written into the template zero padded, which fits any u64
+const NONCE_DIGITS: usize = 20;
+// Hashes between yields, so the worker still reads the page's messages while it mines
+const HASHES_PER_BATCH: u64 = 20_000;
+
+pub fn web_mining_plugin(app: &mut App) {
+    let (responses_writer, responses_reader) = unbounded::<(u64, WorkerResponse)>();
+
+    app.insert_non_send_resource(WebMiners {
+        next_id: 0,
+        miners: Vec::new(),
+        responses_writer,
+        intensity: MAX_MINING_INTENSITY,
+    })
+    .insert_resource(WorkerResponses(responses_reader))
+    .add_systems(
+        Update,
+        (
+            stop_cancelled_web_miners,
+            handle_worker_responses,
+            throttle_web_miners,
+        ),
+    );
+}
+
+// Messages from the page to a mining worker, posted as JSON
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[serde(tag = "type")]
+pub enum WorkerRequest {
+    // Grind the digits at `nonce_offset` of the serialized note until its id reaches `target`,
+    // trying every `stride`th nonce from `start`. Replaces the job the worker had.
+    Mine {
+        template: String,
+        nonce_offset: usize,
+        target: usize,
+        start: u64,
+        stride: u64,
+    },
+    // Percentage of the time the worker spends hashing, it sleeps the rest
+    Throttle {
+        intensity: u8,
+    },
+    Cancel,
+}
+
+// Messages from a mining worker back to the page
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[serde(tag = "type")]
+pub enum WorkerResponse {
+    // The template with this nonce reached the target, the worker waits for its next job
+    Found { nonce: u64 },
+    // Hashes computed since the last message, for the hashrate
+    Hashed { hashes: u64 },
+}
+
+// Wasm has no threads for the tokio miners, so every worker of a queued block is a Web Worker
+pub struct WebMiners {
+    next_id: u64,
+    miners: Vec<WebMiner>,
+    responses_writer: Sender<(u64, WorkerResponse)>,
+    // The mining intensity setting, sent to every new worker
+    intensity: u8,
+}
+
+struct WebMiner {
+    id: u64,
+    coordinates: String,
+    action: MiningAction,
+    worker: Worker,
+    token: CancellationToken,
+    // Shared by the workers of a block, like the native miners
+    best_pow: Arc<AtomicUsize>,
+    nonces: NonceSplit,
+    // Hashes of this worker alone, for the mining dashboard
+    hashes: Arc<AtomicU64>,
+    // The note being ground and the target it commits to, its nonce tag comes last
+    template: Note,
+    target: usize,
+    // Called by the worker for as long as it runs
+    _on_message: Closure<dyn FnMut(MessageEvent)>,
+}
+
+impl WebMiners {
+    pub fn spawn(
+        &mut self,
+        block: &QueuedBlock,
+        best_pow: Arc<AtomicUsize>,
+        nonces: NonceSplit,
+        token: CancellationToken,
+        hashes: Arc<AtomicU64>,
+        key_ref: &UserKeys,
+    ) {
+        let worker = match Worker::new(WORKER_SCRIPT) {
+            Ok(worker) => worker,
+            Err(error) => {
+                warn!(target: MINING_LOG, "Could not start a mining worker: {:?}", error);
+                return;
+            }
+        };
+
+        let id = self.next_id;
+        self.next_id += 1;
+        let responses_writer = self.responses_writer.clone();
+        let on_message = Closure::<dyn FnMut(MessageEvent)>::new(move |event: MessageEvent| {
+            let Some(message) = event.data().as_string() else {
+                return;
+            };
+            match serde_json::from_str::<WorkerResponse>(&message) {
+                Ok(response) => {
+                    let _ = responses_writer.send((id, response));
+                }
+                Err(error) => {
+                    warn!(target: MINING_LOG, "Bad message from a mining worker: {}", error)
+                }
+            }
+        });
+        worker.set_onmessage(Some(on_message.as_ref().unchecked_ref()));
+
+        let mut miner = WebMiner {
+            id,
+            template: block_template(&block.action, &block.coordinates, key_ref, 0),
+            coordinates: block.coordinates.clone(),
+            action: block.action.clone(),
+            worker,
+            token,
+            best_pow,
+            nonces,
+            hashes,
+            target: 0,
+            _on_message: on_message,
+        };
+        miner.mine(key_ref);
+        miner.post(&WorkerRequest::Throttle {
+            intensity: self.intensity,
+        });
+        self.miners.push(miner);
+    }
+}
+
+impl WebMiner {
+    // Sends the worker a note committing to one more bit than the block's best POW
+    fn mine(&mut self, key_ref: &UserKeys) {
+        let target = self.best_pow.load(Ordering::Relaxed) + 1;
+        let template = block_template(&self.action, &self.coordinates, key_ref, target);
+        let Some(request) = mine_request(&template, target, self.nonces) else {
+            warn!(target: MINING_LOG, "Could not find the nonce in the note template");
+            return;
+        };
+        self.template = template;
+        self.target = target;
+        self.post(&request);
+    }
+
+    fn post(&self, request: &WorkerRequest) {
+        if let Ok(message) = serde_json::to_string(request) {
+            let _ = self.worker.post_message(&JsValue::from_str(&message));
+        }
+    }
+
+    // Rebuilds the winning note and checks its id before signing, the key never leaves the page
+    fn publish(&self, nonce: u64, key_ref: &UserKeys, writer: &Sender<SignedNote>) {
+        let mut pow_note = self.template.clone();
+        pow_note.tags.pop();
+        pow_note
+            .tags
+            .push(nonce_tag(&nonce_digits(nonce), self.target));
+
+        let leading_zeroes_in_id = note_difficulty(&pow_note);
+        if leading_zeroes_in_id < self.target {
+            warn!(target: MINING_LOG, "Worker nonce {} did not verify", nonce);
+            return;
+        }
+        if self.best_pow.load(Ordering::Relaxed) >= leading_zeroes_in_id {
+            return;
+        }
+        // Waiting for room would freeze the page, a full queue leaves the workers on this target
+        let signed_note = key_ref.sign_nostr_event(pow_note);
+        if writer.try_send(signed_note).is_ok() {
+            self.best_pow
+                .fetch_max(leading_zeroes_in_id, Ordering::Relaxed);
+        }
+    }
+}
+
+fn block_template(
+    action: &MiningAction,
+    coordinates: &str,
+    key_ref: &UserKeys,
+    target: usize,
+) -> Note {
+    let mut template = action.note(key_ref.get_public_key(), coordinates, target);
+    template
+        .tags
+        .push(nonce_tag(&"0".repeat(NONCE_DIGITS), target));
+    template
+}
+
+fn mine_request(template: &Note, target: usize, nonces: NonceSplit) -> Option<WorkerRequest> {
+    let serialized = template.serialize_for_nostr();
+    let nonce_tag_start = format!("\"nonce\",\"{}", "0".repeat(NONCE_DIGITS));
+    let tag_offset = serialized.find(&nonce_tag_start)?;
+    Some(WorkerRequest::Mine {
+        template: serialized,
+        nonce_offset: tag_offset + nonce_tag_start.len() - NONCE_DIGITS,
+        target,
+        start: nonces.start,
+        stride: nonces.stride,
+    })
+}
+
+fn nonce_digits(nonce: u64) -> String {
+    format!("{:0width$}", nonce, width = NONCE_DIGITS)
+}
+
+#[derive(Resource, Deref)]
+struct WorkerResponses(Receiver<(u64, WorkerResponse)>);
+
+// A find raises the block's best POW, so every worker of the block moves on to the next target
+fn handle_worker_responses(
+    mut web_miners: NonSendMut<WebMiners>,
+    worker_responses: Res<WorkerResponses>,
+    user_keys: Res<UserNostrKeys>,
+    pow_notes_writer: Res<POWNotesWriter>,
+    hash_counter: Res<HashCounter>,
+) {
+    let key_ref = user_keys.get_keypair();
+    for (id, response) in worker_responses.try_iter() {
+        let nonce = match response {
+            WorkerResponse::Found { nonce } => nonce,
+            WorkerResponse::Hashed { hashes } => {
+                hash_counter.fetch_add(hashes, Ordering::Relaxed);
+                if let Some(miner) = web_miners.miners.iter().find(|miner| miner.id == id) {
+                    miner.hashes.fetch_add(hashes, Ordering::Relaxed);
+                }
+                continue;
+            }
+        };
+        let Some(miner) = web_miners.miners.iter().find(|miner| miner.id == id) else {
+            continue;
+        };
+        if miner.token.is_cancelled() {
+            continue;
+        }
+        miner.publish(nonce, &key_ref, &pow_notes_writer);
+
+        let coordinates = miner.coordinates.clone();
+        for miner in web_miners
+            .miners
+            .iter_mut()
+            .filter(|miner| miner.coordinates == coordinates)
+        {
+            miner.mine(&key_ref);
+        }
+    }
+}
+
+fn throttle_web_miners(settings: Res<Settings>, mut web_miners: NonSendMut<WebMiners>) {
+    if !settings.is_changed() {
+        return;
+    }
+    web_miners.intensity = settings.mining_intensity;
+    let request = WorkerRequest::Throttle {
+        intensity: settings.mining_intensity,
+    };
+    for miner in web_miners.miners.iter() {
+        miner.post(&request);
+    }
+}
+
+// `N`, pausing a block and the dashboard's cancel buttons cancel a miner's token, just like they
+// stop the native miners
+fn stop_cancelled_web_miners(mut web_miners: NonSendMut<WebMiners>) {
+    web_miners.miners.retain(|miner| {
+        if !miner.token.is_cancelled() {
+            return true;
+        }
+        // The worker closes itself, a nonce it finds in the meantime is dropped
+        miner.post(&WorkerRequest::Cancel);
+        miner.worker.set_onmessage(None);
+        false
+    });
+}
+
+// Inside a worker, which grinds one job at a time
+struct WorkerJob {
+    template: Vec<u8>,
+    nonce_offset: usize,
+    target: usize,
+    nonces: NonceSplit,
+    nonce: u64,
+    // Since the last `Hashed` message
+    hashes: u64,
+}
+
+impl WorkerJob {
+    // Returns the nonce reaching the target if one of the next `hashes` nonces does
+    fn grind(&mut self, hashes: u64) -> Option<u64> {
+        for _ in 0..hashes {
+            let nonce = self.nonce;
+            self.nonce = self.nonces.next(nonce);
+            self.hashes += 1;
+            self.template[self.nonce_offset..self.nonce_offset + NONCE_DIGITS]
+                .copy_from_slice(nonce_digits(nonce).as_bytes());
+
+            let mut hasher = Sha256::new();
+            hasher.input(&self.template);
+            let mut result = [0u8; 32];
+            hasher.result(&mut result);
+            if count_leading_zero_bits(&result) >= self.target {
+                return Some(nonce);
+            }
+        }
+        None
+    }
+}
+
+thread_local! {
+    static WORKER_JOB: RefCell<Option<WorkerJob>> = const { RefCell::new(None) };
+    static WORKER_INTENSITY: Cell<u8> = const { Cell::new(MAX_MINING_INTENSITY) };
+}
+
+// Mining workers load this same module, but only to run `mining_worker_message`
+pub fn is_worker() -> bool {
+    js_sys::global().is_instance_of::<DedicatedWorkerGlobalScope>()
+}
+
+fn worker_scope() -> DedicatedWorkerGlobalScope {
+    js_sys::global().unchecked_into()
+}
+
+// Called by `assets/mining_worker.js` with every message the page posts to the worker
+#[wasm_bindgen]
+pub fn mining_worker_message(message: String) {
+    match serde_json::from_str::<WorkerRequest>(&message) {
+        Ok(WorkerRequest::Mine {
+            template,
+            nonce_offset,
+            target,
+            start,
+            stride,
+        }) => {
+            if template.len() < nonce_offset + NONCE_DIGITS {
+                return;
+            }
+            let job = WorkerJob {
+                template: template.into_bytes(),
+                nonce_offset,
+                target,
+                nonces: NonceSplit { start, stride },
+                nonce: start,
+                hashes: 0,
+            };
+            let grinding = WORKER_JOB.with(|current| current.borrow_mut().replace(job).is_some());
+            if !grinding {
+                wasm_bindgen_futures::spawn_local(grind_jobs());
+            }
+        }
+        Ok(WorkerRequest::Throttle { intensity }) => {
+            WORKER_INTENSITY.with(|current| current.set(intensity));
+        }
+        Ok(WorkerRequest::Cancel) => {
+            WORKER_JOB.with(|current| current.borrow_mut().take());
+            worker_scope().close();
+        }
+        Err(error) => warn!(target: MINING_LOG, "Bad message for a mining worker: {}", error),
+    }
+}
+
+// Grinds in batches and yields in between, so a new job or a cancel is read while mining
+async fn grind_jobs() {
+    loop {
+        let batch_started = js_sys::Date::now();
+        let found = WORKER_JOB.with(|current| {
+            let mut current = current.borrow_mut();
+            let job = current.as_mut()?;
+            let nonce = job.grind(HASHES_PER_BATCH);
+            post_response(&WorkerResponse::Hashed {
+                hashes: std::mem::take(&mut job.hashes),
+            });
+            if nonce.is_some() {
+                // The page answers with the job for the next target
+                *current = None;
+            }
+            Some(nonce)
+        });
+        match found {
+            // Cancelled
+            None => return,
+            Some(Some(nonce)) => {
+                post_response(&WorkerResponse::Found { nonce });
+                return;
+            }
+            Some(None) => {
+                // Sleep off the rest of the batch when throttled
+                let busy = Duration::from_secs_f64((js_sys::Date::now() - batch_started) / 1000.0);
+                let intensity = WORKER_INTENSITY.with(Cell::get);
+                yield_to_messages(throttle_delay(busy, intensity)).await;
+            }
+        }
+    }
+}
+
+fn post_response(response: &WorkerResponse) {
+    if let Ok(message) = serde_json::to_string(response) {
+        let _ = worker_scope().post_message(&JsValue::from_str(&message));
+    }
+}
+
+// Lets the worker's event loop deliver the page's messages before the next batch
+async fn yield_to_messages(delay: Duration) {
+    let promise = js_sys::Promise::new(&mut |resolve, _| {
+        let _ = worker_scope().set_timeout_with_callback_and_timeout_and_arguments_0(
+            &resolve,
+            delay.as_millis() as i32,
+        );
+    });
+    let _ = JsFuture::from(promise).await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn requests_are_tagged_by_type() {
+        let message = serde_json::to_string(&WorkerRequest::Cancel).unwrap();
+        assert_eq!(message, r#"{"type":"Cancel"}"#);
+        assert_eq!(
+            serde_json::from_str::<WorkerRequest>(&message).unwrap(),
+            WorkerRequest::Cancel
+        );
+    }
+
+    #[test]
+    fn jobs_find_a_nonce_reaching_the_target() {
+        let template = format!("[0,\"{}\"]", nonce_digits(0));
+        let mut job = WorkerJob {
+            template: template.into_bytes(),
+            nonce_offset: 4,
+            target: 4,
+            nonces: NonceSplit {
+                start: 1,
+                stride: 2,
+            },
+            nonce: 1,
+            hashes: 0,
+        };
+        let nonce = job.grind(10_000).unwrap();
+        assert_eq!(nonce % 2, 1);
+        assert_eq!(job.hashes, nonce / 2 + 1);
+
+        let mut hasher = Sha256::new();
+        hasher.input_str(&format!("[0,\"{}\"]", nonce_digits(nonce)));
+        let mut result = [0u8; 32];
+        hasher.result(&mut result);
+        assert!(count_leading_zero_bits(&result) >= 4);
+    }
+}
@@ -0,0 +1,843 @@
+use std::{collections::VecDeque, time::Duration};
+
+use anyhow::anyhow;
+use bech32::{ToBase32, Variant};
+use bevy::{
+    prelude::*,
+    render::{
+        render_asset::RenderAssetUsages,
+        render_resource::{Extent3d, TextureDimension, TextureFormat},
+        texture::ImageSampler,
+    },
+    utils::{HashMap, HashSet},
+};
+use bevy_tokio_tasks::TokioTasksRuntime;
+use crossbeam_channel::{unbounded, Receiver, Sender};
+use nostro2::notes::{Note, SignedNote};
+use qrcode::QrCode;
+use serde::{de::DeserializeOwned, Deserialize};
+
+use crate::{
+    console::{ConsoleCommand, ConsoleOutput, RegisterConsoleCommand},
+    nip05::parse_nip05,
+    nostr::RelayPool,
+    notifications::{Notification, Severity},
+    origin::CyberspacePosition,
+    picking::{block_details_note, BlockNotes, InspectedBlock},
+    protocol::{tagged_event, tagged_pubkey, ZAP_REQUEST_KIND},
+    resources::{CoordinatesMap, MeshesAndMaterials, Profiles},
+    sandbox::{offline, SandboxMode},
+    ui_camera::{
+        text_bundle_builder, AvatarListDetails, BORDER_WIDTH, FLEX_GAP, LIGHT_GRAY, MARGIN_UI,
+        NORMAL_FONT, PADDING_UI, TITLE_FONT,
+    },
+    UserNostrKeys,
+};
+
+const LNURL_TIMEOUT: Duration = Duration::from_secs(10);
+// Side of the invoice QR code on screen, the code itself is scaled up without smoothing
+const QR_SIZE: f32 = 256.0;
+// Modules of white around the code, scanners need it to find the edges
+const QR_QUIET_ZONE: usize = 4;
+// Bright enough to bloom
+const ZAP_GLOW_COLOR: Color = Color::rgba_linear(6.0, 4.5, 0.5, 0.35);
+// The glow starts this much larger than the block and shrinks back into it
+const ZAP_GLOW_SCALE: f32 = 1.6;
+// A 1 sat zap glows this long, every tenfold more sats adds as much again
+const ZAP_GLOW_SECONDS: f32 = 3.0;
+const MAX_ZAP_GLOW_SECONDS: f32 = 30.0;
+// Receipts waiting for their recipient's lightning server key, older ones are dropped
+const MAX_WAITING_RECEIPTS: usize = 64;
+
+pub fn zaps_plugin(app: &mut App) {
+    app.init_resource::<ZapInvoice>()
+        .init_resource::<ZapperKeys>()
+        .add_event::<ZapReceiptReceived>()
+        .add_event::<ZapReceived>()
+        .register_console_command(
+            "zap",
+            "/zap <sats> [message]",
+            "Zaps the inspected block's miner, or the avatar picked in the roster",
+        )
+        .add_systems(Startup, (setup_zap_channel, setup_zap_glow_material))
+        .add_systems(PostStartup, setup_invoice_panel)
+        .add_systems(
+            Update,
+            (
+                (
+                    zap_command,
+                    receive_zap_invoices,
+                    update_invoice_panel,
+                    invoice_panel_buttons,
+                )
+                    .chain(),
+                (
+                    check_zap_receipts.run_if(not(offline)),
+                    glow_zapped_blocks,
+                    fade_zap_glows,
+                )
+                    .chain(),
+            ),
+        );
+}
+
+// A kind 9735 receipt read from a relay, nothing glows before it's checked
+#[derive(Event, Debug, Clone, PartialEq)]
+pub struct ZapReceiptReceived {
+    pub id: String,
+    pub signer: String,
+    pub tags: Vec<Vec<String>>,
+}
+
+// A receipt that checked out, for a zapped note
+#[derive(Event, Debug, Clone, PartialEq)]
+pub struct ZapReceived {
+    pub id: String,
+    pub note_id: String,
+    pub amount_msats: u64,
+}
+
+// What a receipt's tags claim, once they agree with the zap request it carries
+#[derive(Debug, Clone, PartialEq)]
+struct ZapReceipt {
+    note_id: String,
+    recipient: String,
+    amount_msats: u64,
+}
+
+enum ZapperKey {
+    Asking,
+    Known(String),
+    Missing,
+}
+
+// The key each recipient's lightning server signs receipts with, from its LNURL pay parameters,
+// and the receipts waiting for one
+#[derive(Resource, Default)]
+struct ZapperKeys {
+    keys: HashMap<String, ZapperKey>,
+    waiting: VecDeque<(ZapReceiptReceived, ZapReceipt)>,
+}
+
+#[derive(Resource, Deref)]
+struct ZapperKeyResults(Receiver<(String, Option<String>)>);
+
+#[derive(Resource, Deref)]
+struct ZapperKeyWriter(Sender<(String, Option<String>)>);
+
+struct Invoice {
+    recipient: String,
+    sats: u64,
+    bolt11: String,
+}
+
+// The invoice of the last zap asked for, shown as a QR code until closed
+#[derive(Resource, Default, Deref, DerefMut)]
+struct ZapInvoice(Option<Invoice>);
+
+#[derive(Resource, Deref)]
+struct ZapInvoiceResults(Receiver<Result<Invoice, String>>);
+
+#[derive(Resource, Deref)]
+struct ZapInvoiceWriter(Sender<Result<Invoice, String>>);
+
+#[derive(Resource)]
+struct ZapGlowMaterial(Handle<StandardMaterial>);
+
+// Fades out and despawns when its timer runs out
+#[derive(Component)]
+struct ZapGlow(Timer);
+
+#[derive(Component)]
+struct InvoicePanel;
+
+#[derive(Component)]
+struct InvoiceText;
+
+#[derive(Component)]
+struct InvoiceQrCode;
+
+#[derive(Component, Clone, Copy, PartialEq)]
+enum InvoiceButton {
+    Copy,
+    Close,
+}
+
+// LUD-06 pay parameters, with the LUD-16 and NIP-57 additions we need
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct LnurlPayParams {
+    callback: String,
+    min_sendable: u64,
+    max_sendable: u64,
+    #[serde(default)]
+    allows_nostr: bool,
+    #[serde(default)]
+    nostr_pubkey: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct LnurlInvoice {
+    pr: String,
+}
+
+#[derive(Deserialize)]
+struct LnurlError {
+    status: String,
+    #[serde(default)]
+    reason: String,
+}
+
+// Lightning addresses are written like NIP-05 identifiers and resolved the same way
+fn lnurlp_url(lud16: &str) -> Option<String> {
+    let (name, domain) = parse_nip05(lud16)?;
+    Some(format!("https://{}/.well-known/lnurlp/{}", domain, name))
+}
+
+fn encode_lnurl(url: &str) -> Option<String> {
+    bech32::encode("lnurl", url.as_bytes().to_base32(), Variant::Bech32).ok()
+}
+
+fn zap_request_tags(
+    relays: &[String],
+    amount_msats: u64,
+    lnurl: &str,
+    recipient: &str,
+    note_id: Option<&str>,
+) -> Vec<Vec<String>> {
+    let mut relays_tag = vec!["relays".to_string()];
+    relays_tag.extend(relays.iter().cloned());
+    let mut tags = vec![
+        relays_tag,
+        vec!["amount".to_string(), amount_msats.to_string()],
+        vec!["lnurl".to_string(), lnurl.to_string()],
+        vec!["p".to_string(), recipient.to_string()],
+    ];
+    if let Some(note_id) = note_id {
+        tags.push(vec!["e".to_string(), note_id.to_string()]);
+    }
+    tags
+}
+
+fn tag_value<'a>(tags: &'a [Vec<String>], name: &str) -> Option<&'a str> {
+    tags.iter().find_map(|tag| match &tag[..] {
+        [tag_name, value, ..] if tag_name == name => Some(value.as_str()),
+        _ => None,
+    })
+}
+
+// Millisats a BOLT11 invoice asks for, from the amount in its human readable part
+fn bolt11_msats(invoice: &str) -> Option<u64> {
+    let invoice = invoice.to_lowercase();
+    // The data part after the last `1` never holds one
+    let human_readable = &invoice[..invoice.rfind('1')?];
+    let amount = ["lnbcrt", "lnbc", "lntbs", "lntb", "lnsb"]
+        .iter()
+        .find_map(|prefix| human_readable.strip_prefix(prefix))?;
+    let (digits, multiplier) = match amount.chars().last()? {
+        multiplier @ ('m' | 'u' | 'n' | 'p') => (&amount[..amount.len() - 1], Some(multiplier)),
+        _ => (amount, None),
+    };
+    let amount: u64 = digits.parse().ok()?;
+    match multiplier {
+        None => amount.checked_mul(100_000_000_000),
+        Some('m') => amount.checked_mul(100_000_000),
+        Some('u') => amount.checked_mul(100_000),
+        Some('n') => amount.checked_mul(100),
+        // Tenths of a millisat can't be paid
+        _ => (amount % 10 == 0).then_some(amount / 10),
+    }
+}
+
+// NIP-57: the receipt's `description` is the zap request that was paid, signed by the payer for the
+// same note and recipient, and the invoice asks for the amount the request did
+fn check_zap_receipt(tags: &[Vec<String>]) -> Option<ZapReceipt> {
+    let note_id = tagged_event(tags)?;
+    let recipient = tagged_pubkey(tags)?;
+    let amount_msats = bolt11_msats(tag_value(tags, "bolt11")?)?;
+    let zap_request: SignedNote = serde_json::from_str(tag_value(tags, "description")?).ok()?;
+    let request_tags = zap_request.get_tags();
+    let requested_amount = tag_value(&request_tags, "amount").map(str::parse::<u64>);
+    let matches = zap_request.get_kind() == ZAP_REQUEST_KIND
+        && tagged_event(&request_tags).as_ref() == Some(&note_id)
+        && tagged_pubkey(&request_tags).as_ref() == Some(&recipient)
+        && requested_amount.map_or(true, |requested| requested == Ok(amount_msats))
+        && zap_request.verify_content()
+        && zap_request.verify_signature();
+    matches.then_some(ZapReceipt {
+        note_id,
+        recipient,
+        amount_msats,
+    })
+}
+
+fn glow_seconds(amount_msats: u64) -> f32 {
+    let sats = (amount_msats / 1000).max(1);
+    (ZAP_GLOW_SECONDS * (1.0 + (sats as f32).log10())).min(MAX_ZAP_GLOW_SECONDS)
+}
+
+// LNURL servers answer errors with a status instead of an HTTP error code
+fn lnurl_response<T: DeserializeOwned>(body: &str) -> anyhow::Result<T> {
+    if let Ok(error) = serde_json::from_str::<LnurlError>(body) {
+        if error.status.eq_ignore_ascii_case("ERROR") {
+            return Err(anyhow!("{}", error.reason));
+        }
+    }
+    serde_json::from_str(body).map_err(|_| anyhow!("Unexpected answer from the lightning server"))
+}
+
+async fn request_invoice(
+    lnurlp_url: &str,
+    lnurl: &str,
+    amount_msats: u64,
+    zap_request: &SignedNote,
+) -> anyhow::Result<String> {
+    let client = reqwest::Client::builder().timeout(LNURL_TIMEOUT).build()?;
+    let body = client.get(lnurlp_url).send().await?.text().await?;
+    let pay_params: LnurlPayParams = lnurl_response(&body)?;
+    if !pay_params.allows_nostr {
+        return Err(anyhow!("The lightning address doesn't accept zaps"));
+    }
+    if !(pay_params.min_sendable..=pay_params.max_sendable).contains(&amount_msats) {
+        return Err(anyhow!(
+            "The lightning address takes {} to {} sats",
+            pay_params.min_sendable.div_ceil(1000),
+            pay_params.max_sendable / 1000
+        ));
+    }
+    let body = client
+        .get(&pay_params.callback)
+        .query(&[
+            ("amount", amount_msats.to_string()),
+            ("nostr", serde_json::to_string(zap_request)?),
+            ("lnurl", lnurl.to_string()),
+        ])
+        .send()
+        .await?
+        .text()
+        .await?;
+    let invoice: LnurlInvoice = lnurl_response(&body)?;
+    Ok(invoice.pr)
+}
+
+// The key receipts for zaps through this lightning address are signed with
+async fn fetch_zapper_key(lnurlp_url: &str) -> Option<String> {
+    let client = reqwest::Client::builder()
+        .timeout(LNURL_TIMEOUT)
+        .build()
+        .ok()?;
+    let body = client
+        .get(lnurlp_url)
+        .send()
+        .await
+        .ok()?
+        .text()
+        .await
+        .ok()?;
+    let pay_params: LnurlPayParams = lnurl_response(&body).ok()?;
+    pay_params.nostr_pubkey.filter(|_| pay_params.allows_nostr)
+}
+
+// Black modules on white, one pixel each
+fn qr_image(data: &str) -> Option<Image> {
+    let code = QrCode::new(data.as_bytes()).ok()?;
+    let width = code.width();
+    let side = width + QR_QUIET_ZONE * 2;
+    let mut pixels = vec![255; side * side * 4];
+    for (index, color) in code.to_colors().into_iter().enumerate() {
+        if color == qrcode::Color::Dark {
+            let x = index % width + QR_QUIET_ZONE;
+            let y = index / width + QR_QUIET_ZONE;
+            let pixel = (y * side + x) * 4;
+            pixels[pixel..pixel + 3].fill(0);
+        }
+    }
+    let mut image = Image::new(
+        Extent3d {
+            width: side as u32,
+            height: side as u32,
+            depth_or_array_layers: 1,
+        },
+        TextureDimension::D2,
+        pixels,
+        TextureFormat::Rgba8UnormSrgb,
+        RenderAssetUsages::RENDER_WORLD,
+    );
+    image.sampler = ImageSampler::nearest();
+    Some(image)
+}
+
+fn setup_zap_channel(mut commands: Commands) {
+    let (zap_writer, zap_results) = unbounded();
+    commands.insert_resource(ZapInvoiceResults(zap_results));
+    commands.insert_resource(ZapInvoiceWriter(zap_writer));
+    let (key_writer, key_results) = unbounded();
+    commands.insert_resource(ZapperKeyResults(key_results));
+    commands.insert_resource(ZapperKeyWriter(key_writer));
+}
+
+fn setup_zap_glow_material(
+    mut commands: Commands,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+    let material = materials.add(StandardMaterial {
+        base_color: ZAP_GLOW_COLOR,
+        alpha_mode: AlphaMode::Add,
+        unlit: true,
+        ..Default::default()
+    });
+    commands.insert_resource(ZapGlowMaterial(material));
+}
+
+fn setup_invoice_panel(mut commands: Commands) {
+    let invoice_ui = NodeBundle {
+        style: Style {
+            position_type: PositionType::Absolute,
+            top: Val::Percent(20.0),
+            left: Val::Percent(40.0),
+            margin: MARGIN_UI,
+            padding: PADDING_UI,
+            row_gap: FLEX_GAP,
+            flex_direction: FlexDirection::Column,
+            align_items: AlignItems::Center,
+            border: BORDER_WIDTH,
+            ..Default::default()
+        },
+        border_color: BorderColor(LIGHT_GRAY),
+        background_color: BackgroundColor(Color::rgba(0.0, 0.0, 0.0, 0.7)),
+        visibility: Visibility::Hidden,
+        ..Default::default()
+    };
+    let qr_code = ImageBundle {
+        style: Style {
+            width: Val::Px(QR_SIZE),
+            height: Val::Px(QR_SIZE),
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let buttons = NodeBundle {
+        style: Style {
+            column_gap: FLEX_GAP,
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    commands
+        .spawn((invoice_ui, InvoicePanel))
+        .with_children(|invoice_ui| {
+            invoice_ui.spawn(text_bundle_builder("Zap".to_string(), TITLE_FONT));
+            invoice_ui.spawn((text_bundle_builder(String::new(), NORMAL_FONT), InvoiceText));
+            invoice_ui.spawn((qr_code, InvoiceQrCode));
+            invoice_ui.spawn(buttons).with_children(|buttons| {
+                for (label, button) in [
+                    ("[copy invoice]", InvoiceButton::Copy),
+                    ("[close]", InvoiceButton::Close),
+                ] {
+                    let text = text_bundle_builder(label.to_string(), NORMAL_FONT);
+                    buttons.spawn((text, Interaction::default(), button));
+                }
+            });
+        });
+}
+
+// Signs a zap request for the target and asks their lightning server for an invoice
+fn zap_command(
+    runtime: Res<TokioTasksRuntime>,
+    user_keys: Res<UserNostrKeys>,
+    relay_pool: Res<RelayPool>,
+    profiles: Res<Profiles>,
+    inspected_block: Res<InspectedBlock>,
+    coordinates_map: Res<CoordinatesMap>,
+    block_notes: Res<BlockNotes>,
+    avatar_list: Res<AvatarListDetails>,
+    zap_writer: Res<ZapInvoiceWriter>,
+    sandbox_mode: Res<SandboxMode>,
+    mut console_commands: EventReader<ConsoleCommand>,
+    mut console_output: EventWriter<ConsoleOutput>,
+) {
+    for command in console_commands.read() {
+        if command.name != "zap" {
+            continue;
+        }
+        // Nothing leaves the game in the sandbox, lightning servers included
+        if **sandbox_mode {
+            console_output.send(ConsoleOutput(
+                "Zaps need a connection, the offline sandbox has none".to_string(),
+            ));
+            continue;
+        }
+        let Some(sats) = command
+            .args
+            .first()
+            .and_then(|sats| sats.parse::<u64>().ok())
+            .filter(|sats| *sats > 0)
+        else {
+            console_output.send(ConsoleOutput("Usage: /zap <sats> [message]".to_string()));
+            continue;
+        };
+        let message = command.args[1..].join(" ");
+
+        // The block's note is zapped along with its miner, so its receipt makes it glow
+        let inspected = inspected_block
+            .0
+            .as_ref()
+            .and_then(|coordinates| coordinates_map.get(coordinates))
+            .map(|(_, block_details)| {
+                let note_id = block_details_note(&block_notes, block_details)
+                    .map(|note| note.get_id().to_string());
+                (block_details.owner().to_string(), note_id)
+            });
+        let Some((recipient, note_id)) = inspected.or_else(|| {
+            avatar_list
+                .selected_pubkey()
+                .map(|pubkey| (pubkey.to_string(), None))
+        }) else {
+            console_output.send(ConsoleOutput(
+                "Inspect a block or pick an avatar in the roster to zap".to_string(),
+            ));
+            continue;
+        };
+        let name = profiles.display_name(&recipient);
+        let Some((lnurlp_url, lnurl)) = profiles
+            .get(&recipient)
+            .and_then(|profile| profile.lud16.as_deref())
+            .and_then(lnurlp_url)
+            .and_then(|url| Some((url.clone(), encode_lnurl(&url)?)))
+        else {
+            console_output.send(ConsoleOutput(format!(
+                "{} has no lightning address in their profile",
+                name
+            )));
+            continue;
+        };
+
+        let Some(amount_msats) = sats.checked_mul(1000) else {
+            console_output.send(ConsoleOutput(format!("{} sats is more than exist", sats)));
+            continue;
+        };
+        let relays: Vec<String> = relay_pool.iter().map(|relay| relay.url.clone()).collect();
+        let mut note = Note::new(user_keys.get_public_key(), ZAP_REQUEST_KIND, &message);
+        note.tags = zap_request_tags(
+            &relays,
+            amount_msats,
+            &lnurl,
+            &recipient,
+            note_id.as_deref(),
+        );
+        let zap_request = user_keys.get_keypair().sign_nostr_event(note);
+
+        let zap_writer = zap_writer.0.clone();
+        let recipient_name = name.clone();
+        runtime.spawn_background_task(move |_ctx| async move {
+            let invoice = request_invoice(&lnurlp_url, &lnurl, amount_msats, &zap_request)
+                .await
+                .map(|bolt11| Invoice {
+                    recipient: recipient_name.clone(),
+                    sats,
+                    bolt11,
+                })
+                .map_err(|error| format!("Could not zap {}: {}", recipient_name, error));
+            let _ = zap_writer.send(invoice);
+        });
+        console_output.send(ConsoleOutput(format!(
+            "Asking {}'s lightning server for a {} sat invoice",
+            name, sats
+        )));
+    }
+}
+
+fn receive_zap_invoices(
+    zap_results: Res<ZapInvoiceResults>,
+    mut zap_invoice: ResMut<ZapInvoice>,
+    mut notifications: EventWriter<Notification>,
+) {
+    for result in zap_results.try_iter() {
+        match result {
+            Ok(invoice) => **zap_invoice = Some(invoice),
+            Err(message) => {
+                notifications.send(Notification {
+                    message,
+                    severity: Severity::Error,
+                    play_sound: false,
+                });
+            }
+        }
+    }
+}
+
+fn update_invoice_panel(
+    zap_invoice: Res<ZapInvoice>,
+    mut images: ResMut<Assets<Image>>,
+    mut panel_query: Query<&mut Visibility, With<InvoicePanel>>,
+    mut text_query: Query<&mut Text, With<InvoiceText>>,
+    mut qr_query: Query<&mut UiImage, With<InvoiceQrCode>>,
+) {
+    if !zap_invoice.is_changed() {
+        return;
+    }
+    let wanted = match zap_invoice.0.as_ref() {
+        Some(_) => Visibility::Inherited,
+        None => Visibility::Hidden,
+    };
+    for mut visibility in panel_query.iter_mut() {
+        *visibility = wanted;
+    }
+    let Some(invoice) = zap_invoice.0.as_ref() else {
+        return;
+    };
+    for mut text in text_query.iter_mut() {
+        text.sections[0].value = format!(
+            "{} sats to {}\nScan with a lightning wallet",
+            invoice.sats, invoice.recipient
+        );
+    }
+    // Uppercase fits the QR alphanumeric mode, which makes a smaller code
+    let qr_data = format!("lightning:{}", invoice.bolt11).to_uppercase();
+    if let Some(image) = qr_image(&qr_data) {
+        let image = images.add(image);
+        for mut qr_code in qr_query.iter_mut() {
+            qr_code.texture = image.clone();
+        }
+    }
+}
+
+fn invoice_panel_buttons(
+    button_query: Query<(&Interaction, &InvoiceButton), Changed<Interaction>>,
+    mut zap_invoice: ResMut<ZapInvoice>,
+    mut notifications: EventWriter<Notification>,
+) {
+    for (interaction, button) in button_query.iter() {
+        if *interaction != Interaction::Pressed {
+            continue;
+        }
+        match button {
+            InvoiceButton::Copy => {
+                let Some(invoice) = zap_invoice.0.as_ref() else {
+                    continue;
+                };
+                let copied = arboard::Clipboard::new()
+                    .and_then(|mut clipboard| clipboard.set_text(invoice.bolt11.clone()));
+                let (message, severity) = match copied {
+                    Ok(()) => ("Copied the invoice".to_string(), Severity::Info),
+                    Err(error) => (
+                        format!("Could not copy the invoice: {}", error),
+                        Severity::Error,
+                    ),
+                };
+                notifications.send(Notification {
+                    message,
+                    severity,
+                    play_sound: false,
+                });
+            }
+            InvoiceButton::Close => **zap_invoice = None,
+        }
+    }
+}
+
+// Anyone can publish a receipt, only the recipient's lightning server signs real ones. Its key is
+// asked for the first time a receipt for that recipient arrives, their receipts wait until then
+fn check_zap_receipts(
+    runtime: Res<TokioTasksRuntime>,
+    profiles: Res<Profiles>,
+    key_results: Res<ZapperKeyResults>,
+    key_writer: Res<ZapperKeyWriter>,
+    mut zapper_keys: ResMut<ZapperKeys>,
+    mut receipts: EventReader<ZapReceiptReceived>,
+    mut zaps: EventWriter<ZapReceived>,
+) {
+    for (recipient, key) in key_results.try_iter() {
+        let key = key.map_or(ZapperKey::Missing, ZapperKey::Known);
+        zapper_keys.keys.insert(recipient, key);
+    }
+    for received in receipts.read() {
+        if let Some(receipt) = check_zap_receipt(&received.tags) {
+            zapper_keys.waiting.push_back((received.clone(), receipt));
+        }
+    }
+    while zapper_keys.waiting.len() > MAX_WAITING_RECEIPTS {
+        zapper_keys.waiting.pop_front();
+    }
+
+    let ZapperKeys { keys, waiting } = zapper_keys.as_mut();
+    waiting.retain(|(received, receipt)| {
+        match keys.get(&receipt.recipient) {
+            Some(ZapperKey::Known(key)) => {
+                if *key == received.signer {
+                    zaps.send(ZapReceived {
+                        id: received.id.clone(),
+                        note_id: receipt.note_id.clone(),
+                        amount_msats: receipt.amount_msats,
+                    });
+                }
+                false
+            }
+            Some(ZapperKey::Asking) => true,
+            Some(ZapperKey::Missing) => false,
+            // Waits for the recipient's profile to know where to ask
+            None => {
+                let Some(profile) = profiles.get(&receipt.recipient) else {
+                    return true;
+                };
+                let Some(url) = profile.lud16.as_deref().and_then(lnurlp_url) else {
+                    keys.insert(receipt.recipient.clone(), ZapperKey::Missing);
+                    return false;
+                };
+                keys.insert(receipt.recipient.clone(), ZapperKey::Asking);
+                let recipient = receipt.recipient.clone();
+                let key_writer = key_writer.0.clone();
+                runtime.spawn_background_task(move |_ctx| async move {
+                    let key = fetch_zapper_key(&url).await;
+                    let _ = key_writer.send((recipient, key));
+                });
+                true
+            }
+        }
+    });
+}
+
+// Receipts only name the note, so the block is found through the note it was claimed with
+fn glow_zapped_blocks(
+    mut commands: Commands,
+    stuff: Res<MeshesAndMaterials>,
+    glow_material: Res<ZapGlowMaterial>,
+    block_notes: Res<BlockNotes>,
+    coordinates_map: Res<CoordinatesMap>,
+    mut zap_receipts: EventReader<ZapReceived>,
+    mut seen_receipts: Local<HashSet<String>>,
+) {
+    for receipt in zap_receipts.read() {
+        // Several relays deliver the same receipt
+        if !seen_receipts.insert(receipt.id.clone()) {
+            continue;
+        }
+        // Blocks in unloaded sectors or the other plane have no entity to glow around
+        let Some((Some(_), block_details)) = block_notes
+            .coordinates_of(&receipt.note_id)
+            .and_then(|coordinates| coordinates_map.get(coordinates))
+        else {
+            continue;
+        };
+        commands.spawn((
+            PbrBundle {
+                mesh: stuff.cube_mesh.clone_weak(),
+                material: glow_material.0.clone_weak(),
+                transform: Transform::from_scale(Vec3::splat(ZAP_GLOW_SCALE)),
+                ..Default::default()
+            },
+            CyberspacePosition(block_details.coordinates()),
+            ZapGlow(Timer::from_seconds(
+                glow_seconds(receipt.amount_msats),
+                TimerMode::Once,
+            )),
+        ));
+    }
+}
+
+fn fade_zap_glows(
+    mut commands: Commands,
+    time: Res<Time>,
+    mut glow_query: Query<(Entity, &mut ZapGlow, &mut Transform)>,
+) {
+    for (entity, mut glow, mut transform) in glow_query.iter_mut() {
+        glow.0.tick(time.delta());
+        if glow.0.finished() {
+            commands.entity(entity).despawn();
+            continue;
+        }
+        let remaining = 1.0 - glow.0.fraction();
+        transform.scale = Vec3::splat(1.0 + (ZAP_GLOW_SCALE - 1.0) * remaining);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use nostro2::userkeys::UserKeys;
+
+    use super::*;
+
+    #[test]
+    fn zap_requests_carry_the_nip57_tags() {
+        let relays = vec!["wss://relay.arrakis.lat".to_string()];
+        let tags = zap_request_tags(&relays, 21_000, "lnurl1abc", "bob", Some("note"));
+        assert_eq!(
+            tags,
+            vec![
+                vec!["relays", "wss://relay.arrakis.lat"],
+                vec!["amount", "21000"],
+                vec!["lnurl", "lnurl1abc"],
+                vec!["p", "bob"],
+                vec!["e", "note"],
+            ]
+        );
+        assert_eq!(
+            lnurlp_url("Bob@Example.com").as_deref(),
+            Some("https://example.com/.well-known/lnurlp/bob")
+        );
+        assert!(encode_lnurl("https://example.com/.well-known/lnurlp/bob")
+            .is_some_and(|lnurl| lnurl.starts_with("lnurl1")));
+    }
+
+    #[test]
+    fn receipts_must_match_the_signed_request() {
+        let payer = UserKeys::new(&"01".repeat(32)).unwrap();
+        let mut request = Note::new(payer.get_public_key(), ZAP_REQUEST_KIND, "");
+        request.tags = zap_request_tags(&[], 2_500_000, "lnurl1abc", "bob", Some("note"));
+        let description = serde_json::to_string(&payer.sign_nostr_event(request)).unwrap();
+        let receipt = |bolt11: &str, note_id: &str| {
+            vec![
+                vec!["p".to_string(), "bob".to_string()],
+                vec!["e".to_string(), note_id.to_string()],
+                vec!["bolt11".to_string(), bolt11.to_string()],
+                vec!["description".to_string(), description.clone()],
+            ]
+        };
+        assert_eq!(
+            check_zap_receipt(&receipt("lnbc25u1pabc", "note")).map(|zap| zap.amount_msats),
+            Some(2_500_000)
+        );
+        // Paid less than was asked, or claimed for another note
+        assert_eq!(check_zap_receipt(&receipt("lnbc20u1pabc", "note")), None);
+        assert_eq!(check_zap_receipt(&receipt("lnbc25u1pabc", "other")), None);
+        assert!(glow_seconds(1_000_000) > glow_seconds(1_000));
+        assert_eq!(glow_seconds(0), ZAP_GLOW_SECONDS);
+        assert_eq!(glow_seconds(u64::MAX), MAX_ZAP_GLOW_SECONDS);
+    }
+
+    #[test]
+    fn invoices_give_their_amount_in_millisats() {
+        assert_eq!(bolt11_msats("lnbc2500u1pvjluez"), Some(250_000_000));
+        assert_eq!(bolt11_msats("lnbcrt1m1pabc"), Some(100_000_000));
+        assert_eq!(bolt11_msats("lntb10p1pabc"), Some(1));
+        // Tenths of a millisat, no amount, and amounts past u64
+        assert_eq!(bolt11_msats("lnbc15p1pabc"), None);
+        assert_eq!(bolt11_msats("lnbc1pabc"), None);
+        assert_eq!(bolt11_msats("lnbc999999999999999999m1pabc"), None);
+    }
+
+    #[test]
+    fn lnurl_errors_carry_their_reason() {
+        let error = lnurl_response::<LnurlInvoice>(r#"{"status":"ERROR","reason":"Too much"}"#);
+        assert_eq!(
+            error.err().map(|error| error.to_string()).as_deref(),
+            Some("Too much")
+        );
+        let invoice = lnurl_response::<LnurlInvoice>(r#"{"pr":"lnbc1","routes":[]}"#);
+        assert_eq!(
+            invoice.ok().map(|invoice| invoice.pr).as_deref(),
+            Some("lnbc1")
+        );
+    }
+
+    #[test]
+    fn qr_codes_have_a_quiet_zone() {
+        let image = qr_image("LIGHTNING:LNBC1").unwrap();
+        let side = image.width() as usize;
+        assert_eq!(image.height() as usize, side);
+        // White in the corner, then the dark edge of the top left finder pattern
+        assert_eq!(&image.data[..4], &[255, 255, 255, 255]);
+        let finder = (QR_QUIET_ZONE * side + QR_QUIET_ZONE) * 4;
+        assert_eq!(&image.data[finder..finder + 4], &[0, 0, 0, 255]);
+    }
+}